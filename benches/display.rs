@@ -0,0 +1,43 @@
+#![feature(test)]
+
+extern crate test;
+extern crate gnunet;
+
+use std::fmt::Write;
+use std::io::Cursor;
+use test::Bencher;
+
+use gnunet::{EcdsaPrivateKey, HashCode, PeerIdentity};
+
+#[bench]
+fn hashcode_to_string_alloc_free(b: &mut Bencher) {
+  let hash = HashCode::from_buffer(b"gnunet");
+  let mut buf = String::with_capacity(128);
+  b.iter(|| {
+    buf.clear();
+    write!(buf, "{}", hash).unwrap();
+    test::black_box(&buf);
+  });
+}
+
+#[bench]
+fn ecdsa_public_key_to_string_alloc_free(b: &mut Bencher) {
+  let pk = EcdsaPrivateKey::anonymous().get_public();
+  let mut buf = String::with_capacity(64);
+  b.iter(|| {
+    buf.clear();
+    write!(buf, "{}", pk).unwrap();
+    test::black_box(&buf);
+  });
+}
+
+#[bench]
+fn peer_identity_to_string_alloc_free(b: &mut Bencher) {
+  let id = PeerIdentity::deserialize(&mut Cursor::new(&[7u8; 32][..])).unwrap();
+  let mut buf = String::with_capacity(64);
+  b.iter(|| {
+    buf.clear();
+    write!(buf, "{}", id).unwrap();
+    test::black_box(&buf);
+  });
+}
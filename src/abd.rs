@@ -0,0 +1,351 @@
+//! Module for attribute-based delegation (ABD), GNUnet's decentralised authorization system.
+//!
+//! An issuer grants attributes to subjects with signed delegates of the form
+//! `issuer.attribute <- subject` or `issuer.attribute <- subject.attribute`. The second form
+//! delegates to everyone who holds `subject.attribute`. To check whether a subject holds an
+//! attribute, the ABD service searches for a chain of delegates from the issuer's attribute to
+//! the subject.
+
+use std::io::{self, Read, Write, Cursor};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use EcdsaSignature;
+use service::{self, ReadMessageError};
+use util::io::ReadUtil;
+
+/// A signed delegation of an attribute from its issuer to a subject.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delegate {
+  /// The public key of the ego whose attribute is delegated.
+  pub issuer: EcdsaPublicKey,
+  /// The public key of the ego the attribute is delegated to.
+  pub subject: EcdsaPublicKey,
+  /// The delegated attribute.
+  pub issuer_attribute: String,
+  /// If set, the attribute is delegated to every holder of this attribute of `subject` rather
+  /// than to `subject` itself.
+  pub subject_attribute: Option<String>,
+  /// When the delegation expires, in microseconds since the epoch.
+  pub expiration: u64,
+  signature: EcdsaSignature,
+}
+
+/// Errors returned when reading a delegate.
+error_def! DelegateError {
+  Malformed
+    => "The delegate is malformed",
+  InvalidSignature
+    => "The delegate's signature is not valid",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error reading the delegate" ("Specifically: {}", cause),
+  Disconnected
+    => "The delegate ended unexpectedly",
+}
+byteorder_error_chain! {DelegateError}
+
+/// The length of the fixed part of the signed data of a delegate, which is followed by the
+/// attributes.
+const DELEGATE_FIXED_LEN: usize = 8 + 32 + 32 + 8 + 4 + 4;
+
+/// The longest delegate accepted, so that one fits in a message to the service.
+const MAX_DELEGATE_LEN: usize = 0xffff;
+
+/// Read an attribute of `len` bytes including its terminating NUL. A length of zero means there is
+/// no attribute.
+fn read_attribute<R>(r: &mut R, len: u32) -> Result<Option<String>, DelegateError> where R: Read {
+  if len == 0 {
+    return Ok(None);
+  }
+  let mut bytes = try!(r.read_exact_alloc(len as usize));
+  if bytes.pop() != Some(0) {
+    return Err(DelegateError::Malformed);
+  }
+  match String::from_utf8(bytes) {
+    Ok(s)   => Ok(Some(s)),
+    Err(_)  => Err(DelegateError::Malformed),
+  }
+}
+
+/// The length of an attribute on the wire, including its terminating NUL.
+fn attribute_len(attribute: Option<&str>) -> usize {
+  attribute.map(|a| a.len() + 1).unwrap_or(0)
+}
+
+/// The data signed by the issuer of a delegate, starting with the signature purpose header. This
+/// is laid out like `struct DelegateEntry` in GNUnet's `abd.h`, without the signature.
+fn delegate_signed_data(issuer: &EcdsaPublicKey,
+                        subject: &EcdsaPublicKey,
+                        issuer_attribute: &str,
+                        subject_attribute: Option<&str>,
+                        expiration: u64) -> Vec<u8> {
+  let issuer_attribute_len = attribute_len(Some(issuer_attribute));
+  let subject_attribute_len = attribute_len(subject_attribute);
+  let len = DELEGATE_FIXED_LEN + issuer_attribute_len + subject_attribute_len;
+  let mut ret = Vec::with_capacity(len);
+  ret.write_u32::<BigEndian>(len as u32).unwrap();
+  ret.write_u32::<BigEndian>(ll::GNUNET_SIGNATURE_PURPOSE_DELEGATE).unwrap();
+  issuer.serialize(&mut ret).unwrap();
+  subject.serialize(&mut ret).unwrap();
+  ret.write_u64::<BigEndian>(expiration).unwrap();
+  ret.write_u32::<BigEndian>(issuer_attribute_len as u32).unwrap();
+  ret.write_u32::<BigEndian>(subject_attribute_len as u32).unwrap();
+  ret.extend_from_slice(issuer_attribute.as_bytes());
+  ret.push(0);
+  if let Some(a) = subject_attribute {
+    ret.extend_from_slice(a.as_bytes());
+    ret.push(0);
+  }
+  ret
+}
+
+impl Delegate {
+  /// Delegate `issuer_attribute` of the ego `issuer` to `subject`, or to holders of
+  /// `subject_attribute` of `subject` if it is given.
+  ///
+  /// The delegate is given to the subject, which presents it when its attributes are verified.
+  pub fn issue(issuer: &EcdsaPrivateKey,
+               issuer_attribute: &str,
+               subject: &EcdsaPublicKey,
+               subject_attribute: Option<&str>,
+               expiration: u64) -> Delegate {
+    let issuer_pub = issuer.get_public();
+    let signed = delegate_signed_data(&issuer_pub, subject, issuer_attribute, subject_attribute, expiration);
+    Delegate {
+      issuer: issuer_pub,
+      subject: *subject,
+      issuer_attribute: issuer_attribute.to_string(),
+      subject_attribute: subject_attribute.map(|s| s.to_string()),
+      expiration: expiration,
+      signature: issuer.sign(&signed[..]),
+    }
+  }
+
+  fn signed_data(&self) -> Vec<u8> {
+    delegate_signed_data(&self.issuer,
+                         &self.subject,
+                         &self.issuer_attribute,
+                         self.subject_attribute.as_ref().map(|s| &s[..]),
+                         self.expiration)
+  }
+
+  /// Check that the delegate was signed by its issuer.
+  pub fn verify(&self) -> bool {
+    let signed = self.signed_data();
+    self.issuer.verify(ll::GNUNET_SIGNATURE_PURPOSE_DELEGATE, &signed[..], &self.signature)
+  }
+
+  /// The number of bytes this delegate takes up when serialized.
+  pub fn serialized_len(&self) -> usize {
+    64 + DELEGATE_FIXED_LEN
+       + attribute_len(Some(&self.issuer_attribute))
+       + attribute_len(self.subject_attribute.as_ref().map(|s| &s[..]))
+  }
+
+  /// Serialize a delegate to a byte stream, the way `GNUNET_ABD_delegate_serialize` does.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    try!(self.signature.serialize(w));
+    w.write_all(&self.signed_data()[..])
+  }
+
+  /// Deserialize a delegate from a byte stream and check its signature.
+  pub fn deserialize<T>(r: &mut T) -> Result<Delegate, DelegateError> where T: Read {
+    let signature = try!(EcdsaSignature::deserialize(r));
+    let size = try!(r.read_u32::<BigEndian>()) as usize;
+    let purpose = try!(r.read_u32::<BigEndian>());
+    if purpose != ll::GNUNET_SIGNATURE_PURPOSE_DELEGATE || size < DELEGATE_FIXED_LEN || size > MAX_DELEGATE_LEN {
+      return Err(DelegateError::Malformed);
+    }
+    let issuer = try!(EcdsaPublicKey::deserialize(r));
+    let subject = try!(EcdsaPublicKey::deserialize(r));
+    let expiration = try!(r.read_u64::<BigEndian>());
+    let issuer_attribute_len = try!(r.read_u32::<BigEndian>());
+    let subject_attribute_len = try!(r.read_u32::<BigEndian>());
+    if issuer_attribute_len as usize + subject_attribute_len as usize != size - DELEGATE_FIXED_LEN {
+      return Err(DelegateError::Malformed);
+    }
+    let issuer_attribute = match try!(read_attribute(r, issuer_attribute_len)) {
+      Some(a) => a,
+      None    => return Err(DelegateError::Malformed),
+    };
+    let subject_attribute = try!(read_attribute(r, subject_attribute_len));
+    let ret = Delegate {
+      issuer: issuer,
+      subject: subject,
+      issuer_attribute: issuer_attribute,
+      subject_attribute: subject_attribute,
+      expiration: expiration,
+      signature: signature,
+    };
+    match ret.verify() {
+      true  => Ok(ret),
+      false => Err(DelegateError::InvalidSignature),
+    }
+  }
+}
+
+/// A link in a delegation chain found by `verify`: `issuer.issuer_attribute` is delegated to
+/// `subject` or `subject.subject_attribute`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegationLink {
+  pub issuer: EcdsaPublicKey,
+  pub issuer_attribute: String,
+  pub subject: EcdsaPublicKey,
+  pub subject_attribute: Option<String>,
+}
+
+/// Errors returned by `verify`.
+error_def! VerifyError {
+  TooLong
+    => "The request was too large to send to the service",
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the ABD service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the ABD service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the ABD service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The ABD service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  InvalidChain { #[from] cause: DelegateError }
+    => "The ABD service sent a malformed delegation chain" ("Reason: {}", cause),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {VerifyError}
+
+/// Check whether `subject` holds `issuer.issuer_attribute`, using the delegates the subject
+/// holds along with those published by issuers in GNS.
+///
+/// Returns the delegation chain from the issuer to the subject, or `None` if there is none.
+pub fn verify(cfg: &Cfg,
+              issuer: &EcdsaPublicKey,
+              issuer_attribute: &str,
+              subject: &EcdsaPublicKey,
+              delegates: &[Delegate]) -> Result<Option<Vec<DelegationLink>>, VerifyError> {
+  let delegates_len = delegates.iter().fold(0, |acc, d| acc + d.serialized_len());
+  let msg_len = 4 + 4 + 32 + 32 + 2 + 2 + 4 + issuer_attribute.len() + 1 + delegates_len;
+  if msg_len > 0xffff || delegates.len() > 0xffff {
+    return Err(VerifyError::TooLong);
+  }
+  let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "abd"));
+  {
+    let mut mw = service_writer.write_message(msg_len as u16, ll::GNUNET_MESSAGE_TYPE_ABD_VERIFY);
+    mw.write_u32::<BigEndian>(0).unwrap(); // request id
+    subject.serialize(&mut mw).unwrap();
+    issuer.serialize(&mut mw).unwrap();
+    mw.write_u16::<BigEndian>((issuer_attribute.len() + 1) as u16).unwrap();
+    mw.write_u16::<BigEndian>(delegates.len() as u16).unwrap();
+    mw.write_u32::<BigEndian>(0).unwrap(); // resolution algorithm: default
+    mw.write_all(issuer_attribute.as_bytes()).unwrap();
+    mw.write_u8(0).unwrap();
+    for delegate in delegates.iter() {
+      delegate.serialize(&mut mw).unwrap();
+    }
+    try!(mw.send());
+  }
+
+  let (tpe, mut mr) = try!(service_reader.read_message());
+  if tpe != ll::GNUNET_MESSAGE_TYPE_ABD_VERIFY_RESULT {
+    return Err(VerifyError::UnexpectedMessageType { ty: tpe });
+  }
+  let _id = try!(mr.read_u32::<BigEndian>());
+  let found = try!(mr.read_u32::<BigEndian>());
+  let chain_len = try!(mr.read_u32::<BigEndian>());
+  if found == 0 {
+    return Ok(None);
+  }
+  // each link takes at least two keys and two lengths
+  let remaining = mr.get_ref().len() as u64 - mr.position();
+  if chain_len as u64 * (32 + 32 + 4 + 4) > remaining {
+    return Err(VerifyError::InvalidChain { cause: DelegateError::Malformed });
+  }
+  let mut chain = Vec::with_capacity(chain_len as usize);
+  for _ in 0..chain_len {
+    chain.push(try!(read_link(&mut mr)));
+  }
+  Ok(Some(chain))
+}
+
+fn read_link(r: &mut Cursor<Vec<u8>>) -> Result<DelegationLink, DelegateError> {
+  let issuer = try!(EcdsaPublicKey::deserialize(r));
+  let subject = try!(EcdsaPublicKey::deserialize(r));
+  let issuer_attribute_len = try!(r.read_u32::<BigEndian>());
+  let subject_attribute_len = try!(r.read_u32::<BigEndian>());
+  let remaining = r.get_ref().len() as u64 - r.position();
+  if issuer_attribute_len as u64 + subject_attribute_len as u64 > remaining {
+    return Err(DelegateError::Malformed);
+  }
+  let issuer_attribute = match try!(read_attribute(r, issuer_attribute_len)) {
+    Some(a) => a,
+    None    => return Err(DelegateError::Malformed),
+  };
+  let subject_attribute = try!(read_attribute(r, subject_attribute_len));
+  Ok(DelegationLink {
+    issuer: issuer,
+    issuer_attribute: issuer_attribute,
+    subject: subject,
+    subject_attribute: subject_attribute,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use rand;
+  use EcdsaPrivateKey;
+  use super::*;
+
+  #[test]
+  fn test_delegate_round_trip() {
+    let issuer = EcdsaPrivateKey::anonymous();
+    let subject_key: [u8; 32] = rand::random();
+    let subject = EcdsaPrivateKey::deserialize(&mut Cursor::new(&subject_key[..])).unwrap();
+    let delegate = Delegate::issue(&issuer, "member", &subject.get_public(), Some("student"), 1234);
+    assert!(delegate.verify());
+
+    let mut buf = Vec::new();
+    delegate.serialize(&mut buf).unwrap();
+    assert_eq!(buf.len(), delegate.serialized_len());
+    assert_eq!(Delegate::deserialize(&mut Cursor::new(&buf[..])).unwrap(), delegate);
+
+    // Tampering with the attribute breaks the signature.
+    let last = buf.len() - 1;
+    buf[last] ^= 1;
+    match Delegate::deserialize(&mut Cursor::new(&buf[..])) {
+      Err(DelegateError::InvalidSignature) => (),
+      x => panic!("unexpected result: {:?}", x),
+    };
+  }
+
+  #[test]
+  fn test_delegate_layout() {
+    // The layout of `struct DelegateEntry` as written by `GNUNET_ABD_delegate_serialize`.
+    let issuer = EcdsaPrivateKey::anonymous();
+    let subject_key: [u8; 32] = rand::random();
+    let subject = EcdsaPrivateKey::deserialize(&mut Cursor::new(&subject_key[..])).unwrap().get_public();
+    let delegate = Delegate::issue(&issuer, "member", &subject, Some("student"), 1234);
+    let mut buf = Vec::new();
+    delegate.serialize(&mut buf).unwrap();
+    assert_eq!(buf.len(), 64 + 103);
+    let purpose: &[u8] = &[0, 0, 0, 103, 0, 0, 0, 28];
+    assert_eq!(&buf[64..72], purpose);
+    let mut keys = Vec::new();
+    issuer.get_public().serialize(&mut keys).unwrap();
+    subject.serialize(&mut keys).unwrap();
+    assert_eq!(&buf[72..136], &keys[..]);
+    let fields: &[u8] = &[0, 0, 0, 0, 0, 0, 0x04, 0xd2, 0, 0, 0, 7, 0, 0, 0, 8];
+    assert_eq!(&buf[136..152], fields);
+    assert_eq!(&buf[152..], &b"member\0student\0"[..]);
+
+    // Without a subject attribute its length is zero and nothing follows the issuer's.
+    let delegate = Delegate::issue(&issuer, "member", &subject, None, 1234);
+    let mut buf = Vec::new();
+    delegate.serialize(&mut buf).unwrap();
+    assert_eq!(buf.len(), delegate.serialized_len());
+    assert_eq!(&buf[144..], &[0, 0, 0, 7, 0, 0, 0, 0, b'm', b'e', b'm', b'b', b'e', b'r', 0][..]);
+    assert_eq!(Delegate::deserialize(&mut Cursor::new(&buf[..])).unwrap(), delegate);
+  }
+}
@@ -0,0 +1,154 @@
+//! An audit log of the operations performed through this crate.
+//!
+//! Operators who let programs act on their peer can ask for a record of what those programs did.
+//! When the config names a log file, operations which change the peer's state, such as storing
+//! records or creating egos, are appended to it as they succeed:
+//!
+//! ```text
+//! [activity]
+//! FILENAME = $GNUNET_HOME/activity.log
+//! ```
+//!
+//! Each line of the log is a JSON object with the time of the operation in microseconds since the
+//! epoch, the kind of operation and the keys and names involved, eg.
+//!
+//! ```text
+//! {"label":"www","operation":"record_stored","record_types":["A"],"timestamp":1461234567123456,"zone":"JK55…"}
+//! ```
+//!
+//! Several processes can share a log, since each line is appended with a single write. Failing to
+//! write the log does not make the operation fail.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use rustc_serialize::json::Json;
+
+use Cfg;
+use EcdsaPublicKey;
+use gns::RecordType;
+use time::now_micros;
+
+/// An operation recorded in the audit log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Activity {
+  /// The records under `label` in `zone` were replaced with records of these types.
+  RecordStored { zone: EcdsaPublicKey, label: String, record_types: Vec<RecordType> },
+  /// The ego `name` with the zone `zone` was created.
+  EgoCreated { name: String, zone: EcdsaPublicKey },
+  /// The key `key` was revoked.
+  KeyRevoked { key: EcdsaPublicKey },
+  /// A file was published with the URI `uri` under the keyword URI `keywords`, if any.
+  FilePublished { uri: String, keywords: Option<String> },
+}
+
+impl Activity {
+  /// The JSON object logged for this operation, performed at `timestamp`.
+  pub fn to_json(&self, timestamp: u64) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("timestamp".to_string(), Json::U64(timestamp));
+    let operation = match *self {
+      Activity::RecordStored { ref zone, ref label, ref record_types } => {
+        obj.insert("zone".to_string(), Json::String(zone.to_string()));
+        obj.insert("label".to_string(), Json::String(label.clone()));
+        let types = record_types.iter().map(|t| Json::String(t.to_string())).collect();
+        obj.insert("record_types".to_string(), Json::Array(types));
+        "record_stored"
+      },
+      Activity::EgoCreated { ref name, ref zone } => {
+        obj.insert("name".to_string(), Json::String(name.clone()));
+        obj.insert("zone".to_string(), Json::String(zone.to_string()));
+        "ego_created"
+      },
+      Activity::KeyRevoked { ref key } => {
+        obj.insert("key".to_string(), Json::String(key.to_string()));
+        "key_revoked"
+      },
+      Activity::FilePublished { ref uri, ref keywords } => {
+        obj.insert("uri".to_string(), Json::String(uri.clone()));
+        if let Some(ref keywords) = *keywords {
+          obj.insert("keywords".to_string(), Json::String(keywords.clone()));
+        }
+        "file_published"
+      },
+    };
+    obj.insert("operation".to_string(), Json::String(operation.to_string()));
+    Json::Object(obj)
+  }
+}
+
+/// An open audit log.
+pub struct ActivityLog {
+  file: File,
+}
+
+impl ActivityLog {
+  /// Open the log at `path` for appending, creating it if it doesn't exist.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<ActivityLog, io::Error> {
+    let file = try!(OpenOptions::new().append(true).create(true).open(path));
+    Ok(ActivityLog {
+      file: file,
+    })
+  }
+
+  /// Open the log named by `[activity] FILENAME` in `cfg`. Returns `None` if there is no log
+  /// configured or it can't be opened.
+  pub fn from_cfg(cfg: &Cfg) -> Option<ActivityLog> {
+    match cfg.get_filename("activity", "FILENAME") {
+      Ok(path)  => ActivityLog::open(path).ok(),
+      Err(_)    => None,
+    }
+  }
+
+  /// Append `activity` to the log, timestamped with the current time.
+  pub fn log(&self, activity: &Activity) -> Result<(), io::Error> {
+    let mut line = activity.to_json(now_micros()).to_string();
+    line.push('\n');
+    (&self.file).write_all(line.as_bytes())
+  }
+}
+
+/// Append `activity` to the log configured in `cfg`, if there is one. Errors are ignored.
+pub fn log(cfg: &Cfg, activity: &Activity) {
+  if let Some(log) = ActivityLog::from_cfg(cfg) {
+    let _ = log.log(activity);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::{self, File};
+  use std::io::{BufRead, BufReader};
+  use rustc_serialize::json::Json;
+  use EcdsaPrivateKey;
+  use gns::RecordType;
+  use super::*;
+
+  #[test]
+  fn test_activity_log() {
+    let mut path = ::std::env::temp_dir();
+    path.push(format!("gnunet-rs-activity-test-{}.log", ::rand::random::<u32>()));
+    let zone = EcdsaPrivateKey::anonymous().get_public();
+
+    let log = ActivityLog::open(&path).unwrap();
+    log.log(&Activity::RecordStored {
+      zone: zone,
+      label: "www".to_string(),
+      record_types: vec![RecordType::A, RecordType::AAAA],
+    }).unwrap();
+    // A second handle appends rather than truncating.
+    ActivityLog::open(&path).unwrap().log(&Activity::KeyRevoked { key: zone }).unwrap();
+
+    let lines: Vec<String> = BufReader::new(File::open(&path).unwrap()).lines().map(|l| l.unwrap()).collect();
+    let _ = fs::remove_file(&path);
+    assert_eq!(lines.len(), 2);
+    let first = Json::from_str(&lines[0][..]).unwrap();
+    assert_eq!(first.find("operation").unwrap().as_string(), Some("record_stored"));
+    assert_eq!(first.find("zone").unwrap().as_string(), Some(&zone.to_string()[..]));
+    assert_eq!(first.find("record_types").unwrap().as_array().unwrap().len(), 2);
+    assert!(first.find("timestamp").unwrap().as_u64().unwrap() > 0);
+    let second = Json::from_str(&lines[1][..]).unwrap();
+    assert_eq!(second.find("operation").unwrap().as_string(), Some("key_revoked"));
+  }
+}
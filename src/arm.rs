@@ -0,0 +1,433 @@
+//! Module for communicating with GNUnet's Automatic Restart Manager (ARM).
+//!
+//! ARM is the service which starts, stops and monitors all other GNUnet services. Programs can
+//! ask it to start the services they depend on.
+//!
+//! # Example
+//!
+//! Make sure the GNS service is running.
+//!
+//! ```rust
+//! use gnunet::{Cfg, arm};
+//!
+//! let config = Cfg::default().unwrap();
+//! let mut arm = arm::Arm::connect(&config).unwrap();
+//! let result = arm.start_service("gns").unwrap();
+//! assert!(result.is_running());
+//! ```
+
+use std::fmt;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num::ToPrimitive;
+
+use ll;
+use Cfg;
+use service::{self, ServiceReader, ServiceWriter};
+use util::{ReadCString, ReadCStringError};
+
+/// The result of a request to ARM.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArmResult {
+  /// The service was stopped.
+  Stopped,
+  /// The service is being started.
+  Starting,
+  /// The service is being stopped.
+  Stopping,
+  /// The service was already being started.
+  IsStartingAlready,
+  /// The service was already being stopped.
+  IsStoppingAlready,
+  /// The service was already running.
+  IsStartedAlready,
+  /// The service was already stopped.
+  IsStoppedAlready,
+  /// ARM has no service with this name.
+  IsNotKnown,
+  /// ARM failed to start the service.
+  StartFailed,
+  /// ARM is shutting down and will not start any services.
+  InShutdown,
+}
+
+impl ArmResult {
+  /// Create an `ArmResult` from the code sent by the service.
+  pub fn from_u32(x: u32) -> Option<ArmResult> {
+    Some(match x {
+      0 => ArmResult::Stopped,
+      1 => ArmResult::Starting,
+      2 => ArmResult::Stopping,
+      3 => ArmResult::IsStartingAlready,
+      4 => ArmResult::IsStoppingAlready,
+      5 => ArmResult::IsStartedAlready,
+      6 => ArmResult::IsStoppedAlready,
+      7 => ArmResult::IsNotKnown,
+      8 => ArmResult::StartFailed,
+      9 => ArmResult::InShutdown,
+      _ => return None,
+    })
+  }
+
+  /// Returns `true` if the service is running or about to be running.
+  pub fn is_running(&self) -> bool {
+    match *self {
+      ArmResult::Starting | ArmResult::IsStartingAlready | ArmResult::IsStartedAlready => true,
+      _ => false,
+    }
+  }
+
+  /// Returns `true` if the service is stopped or about to be stopped.
+  pub fn is_stopped(&self) -> bool {
+    match *self {
+      ArmResult::Stopped | ArmResult::Stopping |
+      ArmResult::IsStoppingAlready | ArmResult::IsStoppedAlready => true,
+      _ => false,
+    }
+  }
+}
+
+impl fmt::Display for ArmResult {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let s = match *self {
+      ArmResult::Stopped            => "stopped",
+      ArmResult::Starting           => "starting",
+      ArmResult::Stopping           => "stopping",
+      ArmResult::IsStartingAlready  => "already starting",
+      ArmResult::IsStoppingAlready  => "already stopping",
+      ArmResult::IsStartedAlready   => "already running",
+      ArmResult::IsStoppedAlready   => "already stopped",
+      ArmResult::IsNotKnown         => "unknown service",
+      ArmResult::StartFailed        => "failed to start",
+      ArmResult::InShutdown         => "ARM is shutting down",
+    };
+    write!(f, "{}", s)
+  }
+}
+
+/// A handle to the ARM service.
+pub struct Arm {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  next_request_id: u64,
+}
+
+/// Errors returned by `Arm::start_service` and `Arm::stop_service`.
+error_def! RequestError {
+  NameTooLong { name: String }
+    => "The name of the service was too long" ("\"{}\" is too long to be the name of a service.", name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the ARM service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  InvalidResult { result: u32 }
+    => "The service sent an invalid result code" ("Result code: {}", result),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {RequestError}
+
+impl Arm {
+  /// Connect to the ARM service.
+  pub fn connect(cfg: &Cfg) -> Result<Arm, service::ConnectError> {
+    let (service_reader, service_writer) = try!(service::connect(cfg, "arm"));
+    Ok(Arm {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      next_request_id: 0,
+    })
+  }
+
+  /// Ask ARM to start the service `name`, eg. `"gns"`.
+  ///
+  /// Returns once ARM has replied. A result of `Starting` means the service has been launched but
+  /// may not be accepting connections yet.
+  pub fn start_service(&mut self, name: &str) -> Result<ArmResult, RequestError> {
+    self.request(ll::GNUNET_MESSAGE_TYPE_ARM_START, name)
+  }
+
+  /// Ask ARM to stop the service `name`.
+  pub fn stop_service(&mut self, name: &str) -> Result<ArmResult, RequestError> {
+    self.request(ll::GNUNET_MESSAGE_TYPE_ARM_STOP, name)
+  }
+
+  /// Ask ARM to stop every service and then exit, shutting down the peer.
+  ///
+  /// Returns `Stopping` if the shutdown has begun. ARM closes the connection when it exits, so
+  /// the handle is consumed. Use `wait_for_shutdown` to wait for the peer to finish stopping.
+  pub fn request_shutdown(mut self) -> Result<ArmResult, RequestError> {
+    self.request(ll::GNUNET_MESSAGE_TYPE_ARM_STOP, "arm")
+  }
+
+  fn request(&mut self, tpe: u16, name: &str) -> Result<ArmResult, RequestError> {
+    let msg_length = match (16 + name.len() + 1).to_u16() {
+      Some(l) => l,
+      None    => return Err(RequestError::NameTooLong { name: name.to_string() }),
+    };
+    let request_id = self.next_request_id;
+    self.next_request_id += 1;
+    {
+      let mut mw = self.service_writer.write_message(msg_length, tpe);
+      mw.write_u32::<BigEndian>(0).unwrap();
+      mw.write_u64::<BigEndian>(request_id).unwrap();
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    loop {
+      let (tpe, mut mr) = try!(self.service_reader.read_message());
+      if tpe != ll::GNUNET_MESSAGE_TYPE_ARM_RESULT {
+        return Err(RequestError::UnexpectedMessageType { ty: tpe });
+      }
+      let _reserved = try!(mr.read_u32::<BigEndian>());
+      let id = try!(mr.read_u64::<BigEndian>());
+      let result = try!(mr.read_u32::<BigEndian>());
+      // Results for requests which were abandoned due to an error are skipped.
+      if id != request_id {
+        continue;
+      }
+      return match ArmResult::from_u32(result) {
+        Some(r) => Ok(r),
+        None    => Err(RequestError::InvalidResult { result: result }),
+      };
+    }
+  }
+}
+
+/// Errors returned by `arm::start_service` and `arm::stop_service`.
+error_def! ConnectRequestError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the ARM service" ("Reason: {}", cause),
+  Request { #[from] cause: RequestError }
+    => "The request to the ARM service failed" ("Reason: {}", cause),
+}
+
+/// Ask ARM to start the service `name`.
+///
+/// This a convenience function that connects to ARM, sends the request, then disconnects.
+pub fn start_service(cfg: &Cfg, name: &str) -> Result<ArmResult, ConnectRequestError> {
+  let mut arm = try!(Arm::connect(cfg));
+  let ret = try!(arm.start_service(name));
+  Ok(ret)
+}
+
+/// Ask ARM to stop the service `name`.
+///
+/// This a convenience function that connects to ARM, sends the request, then disconnects.
+pub fn stop_service(cfg: &Cfg, name: &str) -> Result<ArmResult, ConnectRequestError> {
+  let mut arm = try!(Arm::connect(cfg));
+  let ret = try!(arm.stop_service(name));
+  Ok(ret)
+}
+
+/// Stop each of the services in `names`, leaving the rest of the peer running.
+///
+/// Returns ARM's result for each service, in the same order as `names`.
+pub fn stop_services(cfg: &Cfg, names: &[&str]) -> Result<Vec<ArmResult>, ConnectRequestError> {
+  let mut arm = try!(Arm::connect(cfg));
+  let mut ret = Vec::with_capacity(names.len());
+  for name in names.iter() {
+    ret.push(try!(arm.stop_service(name)));
+  }
+  Ok(ret)
+}
+
+/// Ask ARM to shut down the whole peer. See `Arm::request_shutdown`.
+///
+/// # Example
+///
+/// Stop a peer started by a test harness and wait for it to exit.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use gnunet::{Cfg, arm};
+///
+/// let config = Cfg::default().unwrap();
+/// arm::request_shutdown(&config).unwrap();
+/// assert!(arm::wait_for_shutdown(&config, Duration::from_secs(30)));
+/// ```
+pub fn request_shutdown(cfg: &Cfg) -> Result<ArmResult, ConnectRequestError> {
+  let arm = try!(Arm::connect(cfg));
+  let ret = try!(arm.request_shutdown());
+  Ok(ret)
+}
+
+/// Returns `true` if ARM is accepting connections, ie. the peer is running.
+pub fn is_running(cfg: &Cfg) -> bool {
+  Arm::connect(cfg).is_ok()
+}
+
+/// Wait up to `timeout` for ARM to exit after a shutdown request. Returns `true` once ARM is no
+/// longer accepting connections.
+pub fn wait_for_shutdown(cfg: &Cfg, timeout: Duration) -> bool {
+  let start = Instant::now();
+  loop {
+    if !is_running(cfg) {
+      return true;
+    }
+    if start.elapsed() >= timeout {
+      return false;
+    }
+    thread::sleep(Duration::from_millis(100));
+  }
+}
+
+/// The status of a service, as reported by an `ArmMonitor`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ServiceStatus {
+  /// Sent once when the monitor is registered. The service name is empty.
+  MonitoringStarted,
+  /// The service has stopped, either on request or because it exited or crashed.
+  Stopped,
+  /// The service is being started.
+  Starting,
+  /// The service is being stopped.
+  Stopping,
+}
+
+impl ServiceStatus {
+  /// Create a `ServiceStatus` from the code sent by the service.
+  pub fn from_u32(x: u32) -> Option<ServiceStatus> {
+    Some(match x {
+      0 => ServiceStatus::MonitoringStarted,
+      1 => ServiceStatus::Stopped,
+      2 => ServiceStatus::Starting,
+      3 => ServiceStatus::Stopping,
+      _ => return None,
+    })
+  }
+}
+
+/// A change in the status of a service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusEvent {
+  /// The name of the service, eg. `"gns"`.
+  pub service: String,
+  /// Its new status.
+  pub status: ServiceStatus,
+}
+
+/// Errors returned by `ArmMonitor::start`.
+error_def! MonitorStartError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the ARM service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the ARM service" ("Specifically: {}", cause),
+}
+
+/// Errors returned when iterating over an `ArmMonitor`.
+error_def! MonitorError {
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the ARM service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  InvalidStatus { status: u32 }
+    => "The service sent an invalid status code" ("Status code: {}", status),
+  InvalidName { #[from] cause: ReadCStringError }
+    => "Failed to read the name of the service" ("Reason: {}", cause),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {MonitorError}
+
+/// A subscription to the status changes of the services managed by ARM.
+///
+/// The monitor uses its own connection to ARM. Events are received by iterating over the monitor,
+/// which blocks until the next change arrives. If the connection to ARM is lost, eg. because ARM
+/// itself was stopped, a `Disconnected` error is returned and the iteration ends.
+///
+/// # Example
+///
+/// A watchdog which restarts the GNS service whenever it stops.
+///
+/// ```rust,no_run
+/// use gnunet::{Cfg, arm};
+///
+/// let config = Cfg::default().unwrap();
+/// let monitor = arm::ArmMonitor::start(&config).unwrap();
+/// for event in monitor {
+///   let event = event.unwrap();
+///   if event.service == "gns" && event.status == arm::ServiceStatus::Stopped {
+///     arm::start_service(&config, "gns").unwrap();
+///   }
+/// }
+/// ```
+pub struct ArmMonitor {
+  service_reader: ServiceReader,
+  _service_writer: ServiceWriter,
+  disconnected: bool,
+}
+
+impl ArmMonitor {
+  /// Start monitoring the services managed by ARM.
+  pub fn start(cfg: &Cfg) -> Result<ArmMonitor, MonitorStartError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "arm"));
+    {
+      let mw = service_writer.write_message(4, ll::GNUNET_MESSAGE_TYPE_ARM_MONITOR);
+      try!(mw.send());
+    };
+    Ok(ArmMonitor {
+      service_reader: service_reader,
+      _service_writer: service_writer,
+      disconnected: false,
+    })
+  }
+
+  fn read_event(&mut self) -> Result<StatusEvent, MonitorError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_ARM_STATUS {
+      return Err(MonitorError::UnexpectedMessageType { ty: tpe });
+    }
+    let status = try!(mr.read_u32::<BigEndian>());
+    let status = match ServiceStatus::from_u32(status) {
+      Some(s) => s,
+      None    => return Err(MonitorError::InvalidStatus { status: status }),
+    };
+    let service = try!(mr.read_c_string());
+    Ok(StatusEvent {
+      service: service,
+      status: status,
+    })
+  }
+}
+
+impl Iterator for ArmMonitor {
+  type Item = Result<StatusEvent, MonitorError>;
+
+  fn next(&mut self) -> Option<Result<StatusEvent, MonitorError>> {
+    if self.disconnected {
+      return None;
+    }
+    match self.read_event() {
+      Err(MonitorError::ReadMessage { .. })
+      | Err(MonitorError::Disconnected)   => {
+        self.disconnected = true;
+        Some(Err(MonitorError::Disconnected))
+      },
+      res                                 => Some(res),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_arm_result_codes() {
+    for x in 0..10 {
+      assert!(ArmResult::from_u32(x).is_some());
+    }
+    assert_eq!(ArmResult::from_u32(10), None);
+    assert!(ArmResult::from_u32(5).unwrap().is_running());
+    assert!(ArmResult::from_u32(2).unwrap().is_stopped());
+    assert!(!ArmResult::from_u32(7).unwrap().is_running());
+  }
+}
@@ -0,0 +1,327 @@
+//! Module for reading bandwidth and address information from the ATS (automatic transport
+//! selection) service.
+//!
+//! ATS decides which address is used to talk to each peer and how much bandwidth each connection
+//! gets. A `Performance` handle reports these decisions as they change, which lets clients prefer
+//! peers with low latency or plenty of bandwidth.
+//!
+//! # Example
+//!
+//! ```rust
+//! use gnunet::{Cfg, ats};
+//!
+//! let config = Cfg::default().unwrap();
+//! for info in ats::list_addresses(&config, None, false).unwrap() {
+//!   println!("{} via {}: {} bytes/s in, {:?} latency", info.peer, info.plugin, info.bandwidth_in, info.latency());
+//! }
+//! ```
+
+use std::io::{self, Cursor, Read, Write};
+use std::str::from_utf8;
+use std::time::Duration;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use PeerIdentity;
+use service::{self, ServiceReader, ServiceWriter};
+
+const START_FLAG_PERFORMANCE_WITH_PIC: u32 = 1;
+const START_FLAG_PERFORMANCE_NO_PIC: u32 = 2;
+
+/// The ATS property giving the kind of network an address is on.
+pub const PROPERTY_NETWORK_TYPE: u32 = 5;
+/// The ATS property giving the latency of an address in microseconds.
+pub const PROPERTY_DELAY: u32 = 6;
+/// The ATS property giving the distance to a peer in hops. Directly connected peers are at
+/// distance 1.
+pub const PROPERTY_DISTANCE: u32 = 7;
+
+/// The kind of network an address is on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NetworkType {
+  /// Unknown.
+  Unspecified,
+  /// The loopback interface.
+  Loopback,
+  /// A local area network.
+  Lan,
+  /// A wide area network, ie. the internet.
+  Wan,
+  /// A wireless local area network.
+  Wlan,
+  /// Bluetooth.
+  Bluetooth,
+}
+
+impl NetworkType {
+  /// Create a `NetworkType` from the code sent by the service.
+  pub fn from_u32(x: u32) -> Option<NetworkType> {
+    Some(match x {
+      0 => NetworkType::Unspecified,
+      1 => NetworkType::Loopback,
+      2 => NetworkType::Lan,
+      3 => NetworkType::Wan,
+      4 => NetworkType::Wlan,
+      5 => NetworkType::Bluetooth,
+      _ => return None,
+    })
+  }
+}
+
+/// Information about one address of a peer.
+#[derive(Clone, Debug)]
+pub struct AddressInfo {
+  /// The peer.
+  pub peer: PeerIdentity,
+  /// The name of the transport plugin the address belongs to, eg. `"tcp"`.
+  pub plugin: String,
+  /// The address, in the plugin's own format.
+  pub address: Vec<u8>,
+  /// Whether ATS has chosen this address for talking to the peer.
+  pub active: bool,
+  /// The inbound bandwidth assigned to the address in bytes per second.
+  pub bandwidth_in: u32,
+  /// The outbound bandwidth assigned to the address in bytes per second.
+  pub bandwidth_out: u32,
+  /// The properties of the address as `(type, value)` pairs. See `latency`, `distance` and
+  /// `network_type` for the common ones.
+  pub properties: Vec<(u32, u32)>,
+}
+
+impl AddressInfo {
+  /// The value of the property `property_type`, if the service sent it.
+  pub fn property(&self, property_type: u32) -> Option<u32> {
+    self.properties.iter()
+                   .find(|&&(t, _)| t == property_type)
+                   .map(|&(_, v)| v)
+  }
+
+  /// The latency of the address.
+  pub fn latency(&self) -> Option<Duration> {
+    self.property(PROPERTY_DELAY).map(|us| Duration::new((us / 1000000) as u64, (us % 1000000) * 1000))
+  }
+
+  /// The distance to the peer in hops.
+  pub fn distance(&self) -> Option<u32> {
+    self.property(PROPERTY_DISTANCE)
+  }
+
+  /// The kind of network the address is on.
+  pub fn network_type(&self) -> Option<NetworkType> {
+    self.property(PROPERTY_NETWORK_TYPE).and_then(NetworkType::from_u32)
+  }
+}
+
+/// Errors returned by `Performance::connect`.
+error_def! ConnectError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the ATS service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the ATS service" ("Specifically: {}", cause),
+}
+
+/// Errors returned when reading address information from the service.
+error_def! ReadInfoError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the ATS service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the service" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  InvalidPluginName
+    => "The service sent a plugin name which is not valid utf-8",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {ReadInfoError}
+
+/// Errors returned by `list_addresses`.
+error_def! ListAddressesError {
+  Connect { #[from] cause: ConnectError }
+    => "Failed to connect to the ATS service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the ATS service" ("Specifically: {}", cause),
+  ReadInfo { #[from] cause: ReadInfoError }
+    => "Failed to read the address list" ("Reason: {}", cause),
+}
+
+/// Read the body of a `PEER_INFORMATION` or `ADDRESSLIST_RESPONSE` message. Returns `None` for
+/// the empty response which ends an address list.
+fn read_address_info(mr: &mut Cursor<Vec<u8>>) -> Result<Option<AddressInfo>, ReadInfoError> {
+  let ats_count = try!(mr.read_u32::<BigEndian>());
+  let active = try!(mr.read_u32::<BigEndian>());
+  let _id = try!(mr.read_u32::<BigEndian>());
+  let peer = try!(PeerIdentity::deserialize(mr));
+  let address_length = try!(mr.read_u16::<BigEndian>());
+  let plugin_name_length = try!(mr.read_u16::<BigEndian>());
+  let bandwidth_out = try!(mr.read_u32::<BigEndian>());
+  let bandwidth_in = try!(mr.read_u32::<BigEndian>());
+  let remaining = mr.get_ref().len() as u64 - mr.position();
+  if ats_count as u64 * 8 > remaining {
+    return Err(From::from(io::Error::new(io::ErrorKind::InvalidData, "the properties are longer than the message")));
+  }
+  let mut properties = Vec::with_capacity(ats_count as usize);
+  for _ in 0..ats_count {
+    let t = try!(mr.read_u32::<BigEndian>());
+    let v = try!(mr.read_u32::<BigEndian>());
+    properties.push((t, v));
+  }
+  if address_length == 0 && plugin_name_length == 0 {
+    return Ok(None);
+  }
+  let mut address = vec![0u8; address_length as usize];
+  try!(mr.read_exact(&mut address[..]));
+  let mut plugin = vec![0u8; plugin_name_length as usize];
+  try!(mr.read_exact(&mut plugin[..]));
+  if plugin.last() == Some(&0) {
+    plugin.pop();
+  }
+  let plugin = match from_utf8(&plugin[..]) {
+    Ok(s)   => s.to_string(),
+    Err(_)  => return Err(ReadInfoError::InvalidPluginName),
+  };
+  Ok(Some(AddressInfo {
+    peer: peer,
+    plugin: plugin,
+    address: address,
+    active: active != 0,
+    bandwidth_in: bandwidth_in,
+    bandwidth_out: bandwidth_out,
+    properties: properties,
+  }))
+}
+
+fn start(cfg: &Cfg, flag: u32) -> Result<(ServiceReader, ServiceWriter), ConnectError> {
+  let (service_reader, mut service_writer) = try!(service::connect(cfg, "ats"));
+  {
+    let mut mw = service_writer.write_message(8, ll::GNUNET_MESSAGE_TYPE_ATS_START);
+    mw.write_u32::<BigEndian>(flag).unwrap();
+    try!(mw.send());
+  };
+  Ok((service_reader, service_writer))
+}
+
+/// A subscription to changes in the addresses and bandwidth assigned by ATS.
+///
+/// The service reports each address it knows about, then each change to an address's bandwidth
+/// or properties. Changes are received by iterating over the handle, which blocks until the next
+/// change arrives. If the connection to the service is lost a `Disconnected` error is returned and
+/// the iteration ends.
+pub struct Performance {
+  service_reader: ServiceReader,
+  _service_writer: ServiceWriter,
+  disconnected: bool,
+}
+
+impl Performance {
+  /// Connect to the ATS service and subscribe to changes.
+  pub fn connect(cfg: &Cfg) -> Result<Performance, ConnectError> {
+    let (service_reader, service_writer) = try!(start(cfg, START_FLAG_PERFORMANCE_WITH_PIC));
+    Ok(Performance {
+      service_reader: service_reader,
+      _service_writer: service_writer,
+      disconnected: false,
+    })
+  }
+
+  /// Wait for the next change.
+  pub fn next_change(&mut self) -> Result<AddressInfo, ReadInfoError> {
+    loop {
+      let (tpe, mut mr) = try!(self.service_reader.read_message());
+      if tpe != ll::GNUNET_MESSAGE_TYPE_ATS_PEER_INFORMATION {
+        return Err(ReadInfoError::UnexpectedMessageType { ty: tpe });
+      }
+      if let Some(info) = try!(read_address_info(&mut mr)) {
+        return Ok(info);
+      }
+    }
+  }
+}
+
+impl Iterator for Performance {
+  type Item = Result<AddressInfo, ReadInfoError>;
+
+  fn next(&mut self) -> Option<Result<AddressInfo, ReadInfoError>> {
+    if self.disconnected {
+      return None;
+    }
+    match self.next_change() {
+      Err(ReadInfoError::ReadMessage { .. })
+      | Err(ReadInfoError::Disconnected)  => {
+        self.disconnected = true;
+        Some(Err(ReadInfoError::Disconnected))
+      },
+      res                                 => Some(res),
+    }
+  }
+}
+
+/// Get the addresses ATS knows about for `peer`, or for every peer if `peer` is `None`. If `all`
+/// is `false` only the addresses in use are returned.
+pub fn list_addresses(cfg: &Cfg, peer: Option<&PeerIdentity>, all: bool) -> Result<Vec<AddressInfo>, ListAddressesError> {
+  let (mut service_reader, mut service_writer) = try!(start(cfg, START_FLAG_PERFORMANCE_NO_PIC));
+  {
+    let mut mw = service_writer.write_message(4 + 4 + 4 + 32, ll::GNUNET_MESSAGE_TYPE_ATS_ADDRESSLIST_REQUEST);
+    mw.write_u32::<BigEndian>(0).unwrap(); // request id
+    mw.write_u32::<BigEndian>(if all { 1 } else { 0 }).unwrap();
+    match peer {
+      Some(peer)  => peer.serialize(&mut mw).unwrap(),
+      None        => mw.write_all(&[0u8; 32][..]).unwrap(),
+    };
+    try!(mw.send());
+  };
+  let mut ret = Vec::new();
+  loop {
+    let (tpe, mut mr) = try!(service_reader.read_message().map_err(ReadInfoError::from));
+    if tpe != ll::GNUNET_MESSAGE_TYPE_ATS_ADDRESSLIST_RESPONSE {
+      return Err(From::from(ReadInfoError::UnexpectedMessageType { ty: tpe }));
+    }
+    match try!(read_address_info(&mut mr)) {
+      Some(info)  => ret.push(info),
+      None        => return Ok(ret),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use std::time::Duration;
+  use byteorder::{BigEndian, WriteBytesExt};
+  use super::*;
+  use super::read_address_info;
+
+  #[test]
+  fn test_read_address_info() {
+    let mut buf = Vec::new();
+    buf.write_u32::<BigEndian>(2).unwrap(); // ats count
+    buf.write_u32::<BigEndian>(1).unwrap(); // active
+    buf.write_u32::<BigEndian>(0).unwrap(); // id
+    buf.extend_from_slice(&[7u8; 32][..]);
+    buf.write_u16::<BigEndian>(3).unwrap();
+    buf.write_u16::<BigEndian>(4).unwrap();
+    buf.write_u32::<BigEndian>(1000).unwrap();
+    buf.write_u32::<BigEndian>(2000).unwrap();
+    buf.write_u32::<BigEndian>(PROPERTY_DELAY).unwrap();
+    buf.write_u32::<BigEndian>(1500).unwrap();
+    buf.write_u32::<BigEndian>(PROPERTY_NETWORK_TYPE).unwrap();
+    buf.write_u32::<BigEndian>(3).unwrap();
+    buf.extend_from_slice(b"abctcp\0");
+
+    let info = read_address_info(&mut Cursor::new(buf)).unwrap().unwrap();
+    assert!(info.active);
+    assert_eq!(info.plugin, "tcp");
+    assert_eq!(info.address, b"abc".to_vec());
+    assert_eq!(info.bandwidth_out, 1000);
+    assert_eq!(info.bandwidth_in, 2000);
+    assert_eq!(info.latency(), Some(Duration::new(0, 1500000)));
+    assert_eq!(info.network_type(), Some(NetworkType::Wan));
+    assert_eq!(info.distance(), None);
+
+    let mut buf = Vec::new();
+    buf.write_u32::<BigEndian>(0xffffffff).unwrap(); // ats count
+    buf.extend_from_slice(&[0u8; 56][..]);
+    assert!(read_address_info(&mut Cursor::new(buf)).is_err());
+  }
+}
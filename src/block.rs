@@ -0,0 +1,336 @@
+//! Client-side validation of the blocks stored in the DHT. Mirrors GNUnet's block library so that
+//! results can be checked before they are used.
+
+use std::io::{self, Cursor};
+use byteorder::{BigEndian, ReadBytesExt};
+
+use ll;
+use EcdsaPublicKey;
+use EcdsaSignature;
+use HashCode;
+use Hello;
+
+/// The types of block that can be stored in the DHT.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BlockType {
+  /// Any type of block, used as a wildcard when searching. Should never be attached to a specific
+  /// block.
+  Any           = 0,
+  /// Data block (leaf) in the CHK tree.
+  FsDBlock      = 1,
+  /// Inner block in the CHK tree.
+  FsIBlock      = 2,
+  /// Type of a block representing a block to be encoded on demand from disk. Should never appear
+  /// on the network directly.
+  FsOnDemand    = 6,
+  /// Type of a block that contains a HELLO for a peer.
+  DhtHello      = 7,
+  /// Block for testing.
+  Test          = 8,
+  /// Type of a block representing any type of file-sharing search result.
+  FsUBlock      = 9,
+  /// Block for storing DNS exit service advertisements.
+  Dns           = 10,
+  /// Block for storing GNS record data.
+  GnsNameRecord = 11,
+  /// Block to store a cadet regex state.
+  Regex         = 22,
+  /// Block to store a cadet regex accepting state.
+  RegexAccept   = 23,
+}
+
+impl BlockType {
+  /// Creates a `BlockType` from it's block type number.
+  pub fn from_u32(x: u32) -> Option<BlockType> {
+    use self::BlockType::*;
+
+    Some(match x {
+      0   => Any,
+      1   => FsDBlock,
+      2   => FsIBlock,
+      6   => FsOnDemand,
+      7   => DhtHello,
+      8   => Test,
+      9   => FsUBlock,
+      10  => Dns,
+      11  => GnsNameRecord,
+      22  => Regex,
+      23  => RegexAccept,
+      _   => return None,
+    })
+  }
+}
+
+/// The outcome of evaluating a block against a query.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlockEvaluation {
+  /// The block is valid and matches the query. More results may follow.
+  OkMore,
+  /// The block is valid and matches the query. It is the only possible result for the query.
+  OkLast,
+  /// The block is malformed, carries a bad signature or does not match the query.
+  ResultInvalid,
+  /// Blocks of this type cannot be evaluated client-side.
+  TypeNotSupported,
+}
+
+/// Errors returned by `block::get_key`.
+error_def! BlockError {
+  TooShort { len: usize }
+    => "The block is too short" ("A block of {} bytes is too short for its type", len),
+  Malformed
+    => "The block is malformed",
+  BadSignature
+    => "The block's signature is invalid",
+  TypeNotSupported { block_type: BlockType }
+    => "Blocks of this type cannot be validated" ("Block type {:?} is not supported", block_type),
+}
+
+impl From<io::Error> for BlockError {
+  fn from(_: io::Error) -> BlockError {
+    BlockError::Malformed
+  }
+}
+
+/// A validator for one or more types of block. Mirrors GNUnet's block plugins.
+///
+/// Implement this trait to teach a `BlockContext` how to validate an application-specific block
+/// type.
+pub trait BlockPlugin: Send {
+  /// The block types handled by this plugin.
+  fn block_types(&self) -> Vec<BlockType>;
+
+  /// Validate a block and compute the key it should be stored under.
+  fn get_key(&self, block_type: BlockType, block: &[u8]) -> Result<HashCode, BlockError>;
+
+  /// Evaluate a block returned for the query `query`.
+  fn evaluate(&self, block_type: BlockType, query: &HashCode, block: &[u8]) -> BlockEvaluation {
+    match self.get_key(block_type, block) {
+      Ok(ref key) if key == query => BlockEvaluation::OkMore,
+      Ok(_)                       => BlockEvaluation::ResultInvalid,
+      Err(_)                      => BlockEvaluation::ResultInvalid,
+    }
+  }
+}
+
+/// Validates `DhtHello` blocks.
+pub struct HelloPlugin;
+
+impl BlockPlugin for HelloPlugin {
+  fn block_types(&self) -> Vec<BlockType> {
+    vec![BlockType::DhtHello]
+  }
+
+  fn get_key(&self, _block_type: BlockType, block: &[u8]) -> Result<HashCode, BlockError> {
+    hello_key(block)
+  }
+}
+
+/// Validates `GnsNameRecord` blocks.
+pub struct GnsPlugin;
+
+impl BlockPlugin for GnsPlugin {
+  fn block_types(&self) -> Vec<BlockType> {
+    vec![BlockType::GnsNameRecord]
+  }
+
+  fn get_key(&self, _block_type: BlockType, block: &[u8]) -> Result<HashCode, BlockError> {
+    gns_key(block)
+  }
+}
+
+/// Validates the file-sharing block types `FsDBlock`, `FsIBlock` and `FsUBlock`.
+pub struct FsPlugin;
+
+impl BlockPlugin for FsPlugin {
+  fn block_types(&self) -> Vec<BlockType> {
+    vec![BlockType::FsDBlock, BlockType::FsIBlock, BlockType::FsUBlock]
+  }
+
+  fn get_key(&self, block_type: BlockType, block: &[u8]) -> Result<HashCode, BlockError> {
+    match block_type {
+      BlockType::FsUBlock => ublock_key(block),
+      _                   => Ok(HashCode::from_buffer(block)),
+    }
+  }
+
+  fn evaluate(&self, block_type: BlockType, query: &HashCode, block: &[u8]) -> BlockEvaluation {
+    match self.get_key(block_type, block) {
+      Ok(ref key) if key == query => match block_type {
+        BlockType::FsUBlock => BlockEvaluation::OkMore,
+        _                   => BlockEvaluation::OkLast,
+      },
+      _ => BlockEvaluation::ResultInvalid,
+    }
+  }
+}
+
+/// A set of block plugins used to validate blocks. Mirrors GNUnet's block context.
+pub struct BlockContext {
+  plugins: Vec<Box<BlockPlugin>>,
+}
+
+impl BlockContext {
+  /// Create a context without any plugins. Every block evaluated with it is unsupported.
+  pub fn empty() -> BlockContext {
+    BlockContext {
+      plugins: Vec::new(),
+    }
+  }
+
+  /// Create a context with the plugins for HELLO, GNS and file-sharing blocks.
+  pub fn default() -> BlockContext {
+    let mut ret = BlockContext::empty();
+    ret.add_plugin(Box::new(HelloPlugin));
+    ret.add_plugin(Box::new(GnsPlugin));
+    ret.add_plugin(Box::new(FsPlugin));
+    ret
+  }
+
+  /// Add a plugin to the context. Plugins added later take precedence over earlier plugins for
+  /// the same block type.
+  pub fn add_plugin(&mut self, plugin: Box<BlockPlugin>) {
+    self.plugins.insert(0, plugin);
+  }
+
+  fn plugin_for(&self, block_type: BlockType) -> Option<&BlockPlugin> {
+    self.plugins.iter()
+                .find(|p| p.block_types().contains(&block_type))
+                .map(|p| &**p)
+  }
+
+  /// Validate a block and compute the key it should be stored under.
+  pub fn get_key(&self, block_type: BlockType, block: &[u8]) -> Result<HashCode, BlockError> {
+    match self.plugin_for(block_type) {
+      Some(p) => p.get_key(block_type, block),
+      None    => Err(BlockError::TypeNotSupported { block_type: block_type }),
+    }
+  }
+
+  /// Evaluate a block returned for the query `query`.
+  pub fn evaluate(&self, block_type: BlockType, query: &HashCode, block: &[u8]) -> BlockEvaluation {
+    match self.plugin_for(block_type) {
+      Some(p) => p.evaluate(block_type, query, block),
+      None    => BlockEvaluation::TypeNotSupported,
+    }
+  }
+}
+
+/// Validate a block and compute the key it should be stored under.
+///
+/// Checks the structure of the block and, for signed block types, the signature. Uses the
+/// plugins of `BlockContext::default()`.
+pub fn get_key(block_type: BlockType, block: &[u8]) -> Result<HashCode, BlockError> {
+  BlockContext::default().get_key(block_type, block)
+}
+
+/// Evaluate a block returned for the query `query`.
+///
+/// The block must be well-formed, correctly signed and stored under `query` to be accepted. Uses
+/// the plugins of `BlockContext::default()`.
+pub fn evaluate(block_type: BlockType, query: &HashCode, block: &[u8]) -> BlockEvaluation {
+  BlockContext::default().evaluate(block_type, query, block)
+}
+
+/// A HELLO block is a complete HELLO message, stored under the hash of the peer's identity.
+fn hello_key(block: &[u8]) -> Result<HashCode, BlockError> {
+  if block.len() < 40 {
+    return Err(BlockError::TooShort { len: block.len() });
+  }
+  let mut mr = Cursor::new(block);
+  let size = try!(mr.read_u16::<BigEndian>());
+  let tpe = try!(mr.read_u16::<BigEndian>());
+  if size as usize != block.len() || tpe != ll::GNUNET_MESSAGE_TYPE_HELLO {
+    return Err(BlockError::Malformed);
+  }
+  let hello = match Hello::deserialize(&mut mr) {
+    Ok(h)   => h,
+    Err(_)  => return Err(BlockError::Malformed),
+  };
+  let mut id = Vec::with_capacity(32);
+  try!(hello.id.serialize(&mut id));
+  Ok(HashCode::from_buffer(&id[..]))
+}
+
+/// A GNS block is signed with a key derived from the zone key and label and stored under the hash
+/// of that derived key.
+fn gns_key(block: &[u8]) -> Result<HashCode, BlockError> {
+  // signature, derived key, purpose and expiration time
+  const HEADER_LEN: usize = 64 + 32 + 8 + 8;
+
+  if block.len() < HEADER_LEN {
+    return Err(BlockError::TooShort { len: block.len() });
+  }
+  let mut mr = Cursor::new(block);
+  let sig = try!(EcdsaSignature::deserialize(&mut mr));
+  let derived_key = try!(EcdsaPublicKey::deserialize(&mut mr));
+  if !derived_key.verify(ll::GNUNET_SIGNATURE_PURPOSE_GNS_RECORD_SIGN, &block[96..], &sig) {
+    return Err(BlockError::BadSignature);
+  }
+  Ok(derived_key.hash())
+}
+
+/// A UBlock is signed with a key derived from the keyword or namespace and stored under the hash
+/// of that derived key.
+fn ublock_key(block: &[u8]) -> Result<HashCode, BlockError> {
+  // signature, purpose and verification key
+  const HEADER_LEN: usize = 64 + 8 + 32;
+
+  if block.len() < HEADER_LEN {
+    return Err(BlockError::TooShort { len: block.len() });
+  }
+  let mut mr = Cursor::new(block);
+  let sig = try!(EcdsaSignature::deserialize(&mut mr));
+  mr.set_position(64 + 8);
+  let verification_key = try!(EcdsaPublicKey::deserialize(&mut mr));
+  if !verification_key.verify(ll::GNUNET_SIGNATURE_PURPOSE_FS_UBLOCK, &block[64..], &sig) {
+    return Err(BlockError::BadSignature);
+  }
+  Ok(verification_key.hash())
+}
+
+#[cfg(test)]
+mod tests {
+  use HashCode;
+  use super::*;
+
+  #[test]
+  fn test_evaluate_dblock() {
+    let data = b"some content-addressed data";
+    let key = HashCode::from_buffer(&data[..]);
+    assert_eq!(evaluate(BlockType::FsDBlock, &key, &data[..]), BlockEvaluation::OkLast);
+
+    let other = HashCode::from_buffer(b"something else");
+    assert_eq!(evaluate(BlockType::FsDBlock, &other, &data[..]), BlockEvaluation::ResultInvalid);
+  }
+
+  #[test]
+  fn test_evaluate_short_hello() {
+    let key = HashCode::from_buffer(b"");
+    assert_eq!(evaluate(BlockType::DhtHello, &key, &[0u8; 12][..]), BlockEvaluation::ResultInvalid);
+    assert_eq!(evaluate(BlockType::Regex, &key, &[][..]), BlockEvaluation::TypeNotSupported);
+  }
+
+  struct AcceptAll;
+
+  impl BlockPlugin for AcceptAll {
+    fn block_types(&self) -> Vec<BlockType> {
+      vec![BlockType::Test, BlockType::FsDBlock]
+    }
+
+    fn get_key(&self, _block_type: BlockType, _block: &[u8]) -> Result<HashCode, BlockError> {
+      Ok(HashCode::from_buffer(b""))
+    }
+  }
+
+  #[test]
+  fn test_custom_plugin() {
+    let key = HashCode::from_buffer(b"");
+    let mut ctx = BlockContext::empty();
+    assert_eq!(ctx.evaluate(BlockType::Test, &key, b"x"), BlockEvaluation::TypeNotSupported);
+    ctx.add_plugin(Box::new(FsPlugin));
+    ctx.add_plugin(Box::new(AcceptAll));
+    assert_eq!(ctx.evaluate(BlockType::Test, &key, b"x"), BlockEvaluation::OkMore);
+    assert_eq!(ctx.evaluate(BlockType::FsDBlock, &key, b"x"), BlockEvaluation::OkMore);
+  }
+}
@@ -0,0 +1,349 @@
+//! A cache of answers from the services kept in a file.
+//!
+//! Command-line tools are started afresh for every invocation, so anything they learn from the
+//! services is lost when they exit. A `PersistentCache` keeps GNS lookup results, HELLOs and the
+//! revocation status of keys in a file, each with the time it expires at, so that the next run
+//! can use them instead of asking again. Expired entries are never returned and are dropped when
+//! the cache is saved.
+//!
+//! ```rust
+//! use gnunet::{gns, Cfg, RecordType};
+//! use gnunet::cache::PersistentCache;
+//!
+//! let config = Cfg::default().unwrap();
+//! let mut cache = PersistentCache::from_cfg(&config).unwrap();
+//! let records = match cache.records("www.gnu", RecordType::A) {
+//!   Some(records) => records,
+//!   None          => {
+//!     let record = gns::lookup_in_master(&config, "www.gnu", RecordType::A, None).unwrap();
+//!     cache.insert_records("www.gnu", RecordType::A, vec![record.clone()]);
+//!     vec![record]
+//!   },
+//! };
+//! cache.save().unwrap();
+//! ```
+
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::{u16, u32};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use Cfg;
+use EcdsaPublicKey;
+use configuration::CfgExpandDollarError;
+use gns::{self, Record, RecordType};
+use peerinfo::PeerIdentity;
+use time::now_micros;
+use util::io::ReadUtil;
+
+/// Where the cache is kept if the config doesn't say.
+pub const DEFAULT_CACHE_FILE: &'static str = "$GNUNET_CACHE_HOME/gnunet-rs/cache";
+
+/// The bytes a cache file starts with.
+const MAGIC: &'static [u8; 8] = b"GNRSCCH1";
+
+const KIND_RECORDS: u8 = 1;
+const KIND_HELLO: u8 = 2;
+const KIND_REVOCATION: u8 = 3;
+
+/// Errors returned when opening or saving a `PersistentCache`.
+error_def! CacheError {
+  CacheFile { #[from] cause: CfgExpandDollarError }
+    => "The location of the cache file could not be worked out from the config" ("Reason: {}", cause),
+  Corrupt
+    => "The cache file is not in the expected format",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error accessing the cache file" ("Specifically: {}", cause),
+}
+
+struct Entry {
+  expiration: u64,
+  data: Vec<u8>,
+}
+
+/// Answers from the services kept in a file. Created with `PersistentCache::open` or
+/// `PersistentCache::from_cfg`.
+///
+/// Changes are only written to the file by `save`.
+pub struct PersistentCache {
+  path: PathBuf,
+  entries: HashMap<(u8, Vec<u8>), Entry>,
+}
+
+fn records_key(name: &str, record_type: RecordType) -> (u8, Vec<u8>) {
+  let mut key = Vec::new();
+  key.write_u32::<BigEndian>(record_type.to_u32()).unwrap();
+  key.extend(name.to_ascii_lowercase().bytes());
+  (KIND_RECORDS, key)
+}
+
+fn hello_key(peer: &PeerIdentity) -> (u8, Vec<u8>) {
+  let mut key = Vec::new();
+  peer.serialize(&mut key).unwrap();
+  (KIND_HELLO, key)
+}
+
+fn revocation_key(key: &EcdsaPublicKey) -> (u8, Vec<u8>) {
+  let mut ret = Vec::new();
+  key.serialize(&mut ret).unwrap();
+  (KIND_REVOCATION, ret)
+}
+
+/// Parse the contents of a cache file.
+fn read_entries(contents: Vec<u8>) -> Result<HashMap<(u8, Vec<u8>), Entry>, io::Error> {
+  let len = contents.len() as u64;
+  let mut r = Cursor::new(contents);
+  let mut magic = [0u8; 8];
+  try!(r.read_exact(&mut magic));
+  if &magic != MAGIC {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "bad magic"));
+  }
+  let mut ret = HashMap::new();
+  while r.position() < len {
+    let kind = try!(r.read_u8());
+    let key_len = try!(r.read_u16::<BigEndian>()) as u64;
+    if key_len > len - r.position() {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "key runs past the end of the file"));
+    }
+    let key = try!(r.read_exact_alloc(key_len as usize));
+    let expiration = try!(r.read_u64::<BigEndian>());
+    let data_len = try!(r.read_u32::<BigEndian>()) as u64;
+    if data_len > len - r.position() {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "data runs past the end of the file"));
+    }
+    let data = try!(r.read_exact_alloc(data_len as usize));
+    ret.insert((kind, key), Entry {
+      expiration: expiration,
+      data: data,
+    });
+  }
+  Ok(ret)
+}
+
+impl PersistentCache {
+  /// Open the cache kept in the file at `path`. The cache is empty if the file doesn't exist yet.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<PersistentCache, CacheError> {
+    let path = path.as_ref();
+    let mut contents = Vec::new();
+    match File::open(path) {
+      Ok(mut f)                                         => try!(f.read_to_end(&mut contents)),
+      Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+      Err(e)                                            => return Err(From::from(e)),
+    };
+    let entries = match contents.is_empty() {
+      true  => HashMap::new(),
+      false => try!(read_entries(contents).map_err(|_| CacheError::Corrupt)),
+    };
+    Ok(PersistentCache {
+      path: path.to_path_buf(),
+      entries: entries,
+    })
+  }
+
+  /// Open the cache kept in the file named by `[cache] FILENAME` in `cfg`, or by
+  /// `DEFAULT_CACHE_FILE` if it isn't set.
+  pub fn from_cfg(cfg: &Cfg) -> Result<PersistentCache, CacheError> {
+    let path = match cfg.get_filename("cache", "FILENAME") {
+      Ok(path)  => path,
+      Err(_)    => PathBuf::from(try!(cfg.expand_dollar(DEFAULT_CACHE_FILE))),
+    };
+    PersistentCache::open(path)
+  }
+
+  /// The file the cache is kept in.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Write the cache to its file, leaving out expired entries. The file and its directory are
+  /// created if needed. The file is replaced in one step, so other processes reading it see either
+  /// the old or the new contents.
+  pub fn save(&mut self) -> Result<(), CacheError> {
+    self.purge_expired();
+    if let Some(dir) = self.path.parent() {
+      try!(fs::create_dir_all(dir));
+    }
+    let mut buf = Vec::new();
+    buf.extend(MAGIC.iter().cloned());
+    for (&(kind, ref key), entry) in self.entries.iter() {
+      buf.write_u8(kind).unwrap();
+      buf.write_u16::<BigEndian>(key.len() as u16).unwrap();
+      buf.extend(key.iter().cloned());
+      buf.write_u64::<BigEndian>(entry.expiration).unwrap();
+      buf.write_u32::<BigEndian>(entry.data.len() as u32).unwrap();
+      buf.extend(entry.data.iter().cloned());
+    }
+    let mut tmp = self.path.clone().into_os_string();
+    tmp.push(".tmp");
+    try!(try!(File::create(&tmp)).write_all(&buf[..]));
+    try!(fs::rename(&tmp, &self.path));
+    Ok(())
+  }
+
+  /// Drop the entries which have expired.
+  pub fn purge_expired(&mut self) {
+    let now = now_micros();
+    let expired: Vec<(u8, Vec<u8>)> = self.entries.iter()
+                                        .filter(|&(_, entry)| entry.expiration <= now)
+                                        .map(|(key, _)| key.clone())
+                                        .collect();
+    for key in expired {
+      self.entries.remove(&key);
+    }
+  }
+
+  /// Drop every entry.
+  pub fn clear(&mut self) {
+    self.entries.clear();
+  }
+
+  /// The number of entries, including any which have expired but not yet been purged.
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  fn get(&self, key: &(u8, Vec<u8>)) -> Option<&[u8]> {
+    match self.entries.get(key) {
+      Some(entry) if entry.expiration > now_micros()  => Some(&entry.data[..]),
+      _                                               => None,
+    }
+  }
+
+  /// Remember `data` under `key`. Entries too large to be written to the file, eg. the records of
+  /// an overlong name, are not remembered.
+  fn insert(&mut self, key: (u8, Vec<u8>), expiration: u64, data: Vec<u8>) {
+    if key.1.len() > u16::MAX as usize || data.len() > u32::MAX as usize {
+      return;
+    }
+    self.entries.insert(key, Entry {
+      expiration: expiration,
+      data: data,
+    });
+  }
+
+  /// The records of type `record_type` found for `name`, if they are cached.
+  pub fn records(&self, name: &str, record_type: RecordType) -> Option<Vec<Record>> {
+    let data = match self.get(&records_key(name, record_type)) {
+      Some(data)  => data,
+      None        => return None,
+    };
+    let mut r = Cursor::new(data);
+    let mut ret = Vec::new();
+    while (r.position() as usize) < data.len() {
+      match Record::deserialize(&mut r) {
+        Ok(record)  => ret.push(record),
+        Err(_)      => return None,
+      }
+    }
+    Some(ret)
+  }
+
+  /// Remember `records` as the records of type `record_type` found for `name`. They expire when
+  /// the first of the records does, so nothing is remembered if there are no records.
+  pub fn insert_records(&mut self, name: &str, record_type: RecordType, records: Vec<Record>) {
+    if records.is_empty() {
+      return;
+    }
+    let expiration = gns::block_expiration(&records[..], now_micros());
+    let mut data = Vec::new();
+    for record in records.iter() {
+      record.serialize(&mut data).unwrap();
+    }
+    self.insert(records_key(name, record_type), expiration, data);
+  }
+
+  /// The HELLO message of `peer`, if it is cached.
+  pub fn hello(&self, peer: &PeerIdentity) -> Option<Vec<u8>> {
+    self.get(&hello_key(peer)).map(|data| data.to_vec())
+  }
+
+  /// Remember `hello_message`, including its header, as the HELLO of `peer` until `expiration`,
+  /// in microseconds since the epoch.
+  pub fn insert_hello(&mut self, peer: &PeerIdentity, hello_message: &[u8], expiration: u64) {
+    self.insert(hello_key(peer), expiration, hello_message.to_vec());
+  }
+
+  /// Whether `key` has been revoked, if it is cached.
+  pub fn revoked(&self, key: &EcdsaPublicKey) -> Option<bool> {
+    self.get(&revocation_key(key)).map(|data| data == &[1u8][..])
+  }
+
+  /// Remember whether `key` has been revoked. A revocation is permanent and is remembered for
+  /// good. That a key hasn't been revoked is remembered until `expiration`, in microseconds since
+  /// the epoch.
+  pub fn insert_revoked(&mut self, key: &EcdsaPublicKey, revoked: bool, expiration: u64) {
+    let expiration = if revoked { ::std::u64::MAX } else { expiration };
+    self.insert(revocation_key(key), expiration, vec![revoked as u8]);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use std::io::Write;
+  use EcdsaPrivateKey;
+  use gns::{Record, RecordType};
+  use ll;
+  use super::*;
+
+  #[test]
+  fn test_persistent_cache() {
+    let mut path = env::temp_dir();
+    path.push(format!("gnunet-rs-cache-{}", ::rand::random::<u32>()));
+    path.push("cache");
+
+    let mut cache = PersistentCache::open(&path).unwrap();
+    assert_eq!(cache.len(), 0);
+    let hour = 60 * 60 * 1000000;
+    let www = Record::new(RecordType::A, vec![10, 0, 0, 1], hour, ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION);
+    let old = Record::new(RecordType::A, vec![10, 0, 0, 2], 1, 0);
+    cache.insert_records("WWW.gnu", RecordType::A, vec![www.clone()]);
+    cache.insert_records("old.gnu", RecordType::A, vec![old]);
+    let fresh = EcdsaPrivateKey::generate().get_public();
+    let revoked = EcdsaPrivateKey::generate().get_public();
+    cache.insert_revoked(&fresh, false, 1);
+    cache.insert_revoked(&revoked, true, 1);
+    cache.save().unwrap();
+
+    let cache = PersistentCache::open(&path);
+    let corrupt = {
+      fs::File::create(&path).unwrap().write_all(b"not a cache").unwrap();
+      PersistentCache::open(&path).is_err()
+    };
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let cache = cache.unwrap();
+    assert!(corrupt);
+    // The expired entries were dropped when saving.
+    assert_eq!(cache.len(), 2);
+    assert!(cache.records("www.gnu", RecordType::A) == Some(vec![www]));
+    assert!(cache.records("www.gnu", RecordType::AAAA).is_none());
+    assert!(cache.records("old.gnu", RecordType::A).is_none());
+    assert_eq!(cache.revoked(&fresh), None);
+    assert_eq!(cache.revoked(&revoked), Some(true));
+  }
+
+  #[test]
+  fn test_cache_bounds() {
+    let mut path = env::temp_dir();
+    path.push(format!("gnunet-rs-cache-{}", ::rand::random::<u32>()));
+
+    let mut cache = PersistentCache::open(&path).unwrap();
+    let long_name: String = ::std::iter::repeat('a').take(70000).collect();
+    let www = Record::new(RecordType::A, vec![10, 0, 0, 1], ::std::u64::MAX, 0);
+    cache.insert_records(&long_name, RecordType::A, vec![www]);
+    assert_eq!(cache.len(), 0);
+
+    // An entry claiming more data than the file holds.
+    let mut contents = b"GNRSCCH1".to_vec();
+    contents.extend_from_slice(&[1, 0, 1, b'k', 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 1, 2]);
+    fs::File::create(&path).unwrap().write_all(&contents[..]).unwrap();
+    let corrupt = PersistentCache::open(&path).is_err();
+    fs::remove_file(&path).unwrap();
+    assert!(corrupt);
+  }
+}
@@ -1,16 +1,37 @@
-use std::io::{self, Cursor};
-use byteorder::{BigEndian, WriteBytesExt};
+//! Module for peer-to-peer communication over CADET channels.
+//!
+//! A `Cadet` handle is used to open channels to other peers and to accept channels opened by
+//! other peers. Each message sent over a channel carries a GNUnet message type and a payload.
+//!
+//! The service tells us how many messages it is ready to accept on each channel. `Channel::send`
+//! blocks until the service is ready while `Channel::try_send` fails instead.
+
+use std::collections::HashMap;
+use std::io::{self, Write, Cursor};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num::ToPrimitive;
 
 use ll;
 use Cfg;
 use PeerIdentity;
-use service::{self, ServiceReadLoop, ServiceWriter, ProcessMessageResult};
+use util::io::ReadUtil;
+use service::{self, CatchAll, ServiceReadLoop, ServiceWriter, ProcessMessageResult};
+use shutdown::{Component, ReadLoop};
 
-pub struct ChannelId(u32);
+/// The first channel id allocated by the client for channels it creates.
+const LOCAL_CHANNEL_ID_CLI: u32 = 0x80000000;
 
+/// Options for a CADET channel.
+#[derive(Copy, Clone, Debug, Default)]
 pub struct ChannelOptions {
+  /// Disable buffering on intermediate nodes (for minimum latency).
   pub no_buffer:    bool,
+  /// Enable channel reliability, lost messages will be retransmitted.
   pub reliable:     bool,
+  /// Enable out of order delivery of messages.
   pub out_of_order: bool,
 }
 
@@ -25,51 +46,704 @@ impl ChannelOptions {
   }
 }
 
+/// Events delivered from the callback loop to a `Channel`.
+enum ChannelEvent {
+  Data(u16, Vec<u8>),
+  Destroyed,
+}
+
+/// Receivers registered with the callback loop.
+enum Registration {
+  Channel(u32, Sender<ChannelEvent>, Arc<SendWindow>),
+  Port(u32, Sender<Channel>),
+  ClosePorts,
+}
+
+/// Counters describing the traffic on a channel. Returned by `Channel::stats`.
+///
+/// The CADET service does not tell clients about retransmissions, so `send_stalls` is the best
+/// available hint that a channel is congested: it counts the sends which found the service not
+/// ready for another message.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+  /// The number of messages sent.
+  pub messages_sent: u64,
+  /// The number of payload bytes sent.
+  pub bytes_sent: u64,
+  /// The number of messages received from the service, including ones not yet read with `recv`.
+  pub messages_received: u64,
+  /// The number of payload bytes received from the service.
+  pub bytes_received: u64,
+  /// The number of ACKs received from the service, each allowing one more message to be sent.
+  pub acks_received: u64,
+  /// The number of ACKs sent to the service, each allowing it to deliver one more message.
+  pub acks_sent: u64,
+  /// The number of ACKs received but not yet used up by sending a message.
+  pub acks_outstanding: u32,
+  /// The number of times a send had to wait, or failed, because no ACK was outstanding.
+  pub send_stalls: u64,
+}
+
+struct WindowState {
+  credit: u32,
+  destroyed: bool,
+  disconnected: bool,
+  stats: ChannelStats,
+}
+
+/// The number of messages the service will currently accept on a channel.
+///
+/// The service sends a `LOCAL_ACK` for every message it is ready to accept. Each ACK grants one
+/// unit of credit and each message sent uses one up.
+struct SendWindow {
+  state: Mutex<WindowState>,
+  cond: Condvar,
+}
+
+impl SendWindow {
+  fn new() -> SendWindow {
+    SendWindow {
+      state: Mutex::new(WindowState {
+        credit: 0,
+        destroyed: false,
+        disconnected: false,
+        stats: ChannelStats::default(),
+      }),
+      cond: Condvar::new(),
+    }
+  }
+
+  fn grant(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.credit += 1;
+    state.stats.acks_received += 1;
+    self.cond.notify_all();
+  }
+
+  fn destroy(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.destroyed = true;
+    self.cond.notify_all();
+  }
+
+  fn disconnect(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.disconnected = true;
+    self.cond.notify_all();
+  }
+
+  /// Wait until there is credit or the channel is closed. Returns `false` if `timeout` expires.
+  fn wait(&self, timeout: Option<Duration>) -> bool {
+    let start = Instant::now();
+    let mut state = self.state.lock().unwrap();
+    if state.credit == 0 && !state.destroyed && !state.disconnected {
+      state.stats.send_stalls += 1;
+    }
+    while state.credit == 0 && !state.destroyed && !state.disconnected {
+      state = match timeout {
+        None    => self.cond.wait(state).unwrap(),
+        Some(t) => {
+          let elapsed = start.elapsed();
+          if elapsed >= t {
+            return false;
+          }
+          self.cond.wait_timeout(state, t - elapsed).unwrap().0
+        },
+      };
+    }
+    true
+  }
+
+  /// Use up one unit of credit.
+  fn take(&self) -> Result<(), ChannelSendError> {
+    let mut state = self.state.lock().unwrap();
+    if state.destroyed {
+      return Err(ChannelSendError::Destroyed);
+    }
+    if state.disconnected {
+      return Err(ChannelSendError::Disconnected);
+    }
+    if state.credit == 0 {
+      state.stats.send_stalls += 1;
+      return Err(ChannelSendError::WindowExhausted);
+    }
+    state.credit -= 1;
+    Ok(())
+  }
+
+  fn credit(&self) -> u32 {
+    self.state.lock().unwrap().credit
+  }
+
+  fn record_sent(&self, len: usize) {
+    let mut state = self.state.lock().unwrap();
+    state.stats.messages_sent += 1;
+    state.stats.bytes_sent += len as u64;
+  }
+
+  fn record_received(&self, len: usize) {
+    let mut state = self.state.lock().unwrap();
+    state.stats.messages_received += 1;
+    state.stats.bytes_received += len as u64;
+  }
+
+  fn record_ack_sent(&self) {
+    self.state.lock().unwrap().stats.acks_sent += 1;
+  }
+
+  fn stats(&self) -> ChannelStats {
+    let state = self.state.lock().unwrap();
+    ChannelStats {
+      acks_outstanding: state.credit,
+      ..state.stats
+    }
+  }
+}
+
+/// The send windows of all open channels. Closes them when the callback loop exits so that
+/// blocked senders wake up.
+struct SendWindows(HashMap<u32, Arc<SendWindow>>);
+
+impl Drop for SendWindows {
+  fn drop(&mut self) {
+    for window in self.0.values() {
+      window.disconnect();
+    }
+  }
+}
+
+/// A handle to a locally-running instance of the CADET service.
 pub struct Cadet {
-  service_writer: ServiceWriter,
-  _callback_loop: ServiceReadLoop,
+  service_writer: Arc<Mutex<ServiceWriter>>,
+  callback_loop: ServiceReadLoop,
   next_channel_id: u32,
+  registration_tx: Sender<Registration>,
+  listen_ports: Vec<u32>,
+  dropped: Arc<Mutex<Vec<u32>>>,
 }
 
+/// A channel to another peer. Created by `Cadet::create_channel` or accepted from a `Port`.
+///
+/// Dropping a channel destroys it.
 pub struct Channel {
   id: u32,
+  peer: PeerIdentity,
+  port: u32,
+  service_writer: Arc<Mutex<ServiceWriter>>,
+  receiver: Receiver<ChannelEvent>,
+  window: Arc<SendWindow>,
+  destroyed: bool,
+  dropped: Arc<Mutex<Vec<u32>>>,
+}
+
+/// Errors returned by `Channel::send`.
+error_def! ChannelSendError {
+  PayloadTooLong { len: usize }
+    => "The payload was too long to send in a single message" ("{} bytes is too long", len),
+  Destroyed
+    => "The channel has been destroyed",
+  WindowExhausted
+    => "The service is not ready to accept another message on the channel",
+  Disconnected
+    => "The connection to the CADET service was lost",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the service" ("Specifically: {}", cause),
+}
+
+/// Errors returned by `Cadet::open_port`.
+error_def! OpenPortError {
+  NotDeclared { port: u32 }
+    => "The port was not declared when connecting to the service" ("Port {} must be passed to Cadet::connect_with_ports", port),
+  Disconnected
+    => "The connection to the CADET service was lost",
+}
+
+/// Errors returned by `Port::accept`.
+error_def! AcceptError {
+  Disconnected
+    => "The connection to the CADET service was lost",
+}
+
+/// Errors returned by `Channel::recv`.
+error_def! ChannelRecvError {
+  Destroyed
+    => "The channel has been destroyed",
+  Disconnected
+    => "The connection to the CADET service was lost",
+}
+
+/// Read the body of a `LOCAL_DATA` message. Returns the channel id and the message it carries.
+fn read_data(reader: &mut Cursor<Vec<u8>>) -> Result<(u32, u16, Vec<u8>), io::Error> {
+  let id = try!(reader.read_u32::<BigEndian>());
+  let len = try!(reader.read_u16::<BigEndian>());
+  let tpe = try!(reader.read_u16::<BigEndian>());
+  if len < 4 {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid message length"));
+  }
+  let payload = try!(reader.read_exact_alloc(len as usize - 4));
+  try!(service::check_end(reader));
+  Ok((id, tpe, payload))
+}
+
+/// Send a message consisting of a header and a channel id.
+fn send_channel_id(service_writer: &Mutex<ServiceWriter>, tpe: u16, id: u32) -> Result<(), io::Error> {
+  let mut sw = service_writer.lock().unwrap();
+  let mut mw = sw.write_message(8, tpe);
+  mw.write_u32::<BigEndian>(id).unwrap();
+  mw.send()
+}
+
+/// Send a `LOCAL_CHANNEL_DESTROY` message for the channel `id`.
+fn send_channel_destroy(service_writer: &Mutex<ServiceWriter>, id: u32) -> Result<(), io::Error> {
+  let mut sw = service_writer.lock().unwrap();
+  let mut mw = sw.write_message(48, ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_CHANNEL_DESTROY);
+  mw.write_u32::<BigEndian>(id).unwrap();
+  mw.write_all(&[0u8; 40]).unwrap(); // peer, port and options are unused
+  mw.send()
+}
+
+/// Read the body of an incoming `LOCAL_CHANNEL_CREATE` message. Returns the channel id, the
+/// remote peer and the port.
+fn read_channel_create(reader: &mut Cursor<Vec<u8>>) -> Result<(u32, PeerIdentity, u32), io::Error> {
+  let id = try!(reader.read_u32::<BigEndian>());
+  let peer = try!(PeerIdentity::deserialize(reader));
+  let port = try!(reader.read_u32::<BigEndian>());
+  let _opt = try!(reader.read_u32::<BigEndian>());
+  try!(service::check_end(reader));
+  Ok((id, peer, port))
 }
 
 impl Cadet {
-  pub fn init(cfg: &Cfg, listen_ports: Vec<u32>) -> Result<Cadet, service::ConnectError> {
+  /// Connect to the CADET service.
+  ///
+  /// A handle created this way can only create channels. Use `connect_with_ports` to be able to
+  /// accept incoming channels.
+  pub fn connect(cfg: &Cfg) -> Result<Cadet, service::ConnectError> {
+    Cadet::connect_with_ports(cfg, &[])
+  }
+
+  /// Connect to the CADET service, declaring the ports on which we will accept incoming channels.
+  ///
+  /// The service only delivers channels for ports declared when connecting. Each of the ports
+  /// can then be listened on with `open_port`.
+  pub fn connect_with_ports(cfg: &Cfg, listen_ports: &[u32]) -> Result<Cadet, service::ConnectError> {
+    let (registration_tx, registration_rx) = channel::<Registration>();
+    let mut channels: HashMap<u32, Sender<ChannelEvent>> = HashMap::new();
+    let mut ports: HashMap<u32, Sender<Channel>> = HashMap::new();
+    let mut windows = SendWindows(HashMap::new());
+    // The ids of dropped channels, whose entries the callback loop removes.
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let loop_dropped = dropped.clone();
+
     let (service_reader, mut service_writer) = try!(service::connect(cfg, "cadet"));
-    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
-      println!("Got message!: tpe == {}", tpe);
-      ProcessMessageResult::Continue
-    }));
     {
-      let msg_length: u16 = 4 + 4 * listen_ports.len() as u16; // TODO: check for overflow
+      let msg_length = match (4 + 4 * listen_ports.len()).to_u16() {
+        Some(l) => l,
+        None    => return Err(service::ConnectError::Io {
+          cause: io::Error::new(io::ErrorKind::InvalidInput, "too many ports"),
+        }),
+      };
       let mut mw = service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_CONNECT);
       for port in listen_ports.iter() {
         mw.write_u32::<BigEndian>(*port).unwrap();
       }
       try!(mw.send());
     };
+    let service_writer = Arc::new(Mutex::new(service_writer));
+    let loop_writer = service_writer.clone();
+    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
+      loop {
+        match registration_rx.try_recv() {
+          Ok(Registration::Channel(id, sender, window)) => {
+            channels.insert(id, sender);
+            windows.0.insert(id, window);
+          },
+          Ok(Registration::Port(port, sender)) => {
+            ports.insert(port, sender);
+          },
+          Ok(Registration::ClosePorts) => ports.clear(),
+          Err(e)  => match e {
+            TryRecvError::Empty         => break,
+            TryRecvError::Disconnected  => return ProcessMessageResult::Shutdown,
+          },
+        }
+      }
+      for id in loop_dropped.lock().unwrap().drain(..) {
+        channels.remove(&id);
+        windows.0.remove(&id);
+      }
+
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_CHANNEL_CREATE => {
+          let (id, peer, port) = match read_channel_create(&mut reader) {
+            Ok(x)   => x,
+            Err(_)  => return ProcessMessageResult::Reconnect,
+          };
+          let (tx, rx) = channel::<ChannelEvent>();
+          let window = Arc::new(SendWindow::new());
+          let incoming = Channel {
+            id: id,
+            peer: peer,
+            port: port,
+            service_writer: loop_writer.clone(),
+            receiver: rx,
+            window: window.clone(),
+            destroyed: false,
+            dropped: loop_dropped.clone(),
+          };
+          // If nobody is listening on the port the channel is dropped, which destroys it.
+          let accepted = match ports.get(&port) {
+            Some(sender)  => sender.send(incoming).is_ok(),
+            None          => false,
+          };
+          match accepted {
+            true  => {
+              channels.insert(id, tx);
+              windows.0.insert(id, window.clone());
+              // allow the service to send us data on the new channel
+              if send_channel_id(&loop_writer, ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_ACK, id).is_err() {
+                return ProcessMessageResult::Reconnect;
+              }
+              window.record_ack_sent();
+            },
+            false => {
+              ports.remove(&port);
+            },
+          }
+        },
+        ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_DATA => {
+          let (id, msg_tpe, payload) = match read_data(&mut reader) {
+            Ok(x)   => x,
+            Err(_)  => return ProcessMessageResult::Reconnect,
+          };
+          let len = payload.len();
+          let delivered = match channels.get(&id) {
+            Some(sender)  => sender.send(ChannelEvent::Data(msg_tpe, payload)).is_ok(),
+            None          => false,
+          };
+          match delivered {
+            // tell the service we are ready for more data on this channel
+            true  => {
+              if send_channel_id(&loop_writer, ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_ACK, id).is_err() {
+                return ProcessMessageResult::Reconnect;
+              }
+              if let Some(window) = windows.0.get(&id) {
+                window.record_received(len);
+                window.record_ack_sent();
+              }
+            },
+            false => {
+              channels.remove(&id);
+              windows.0.remove(&id);
+            },
+          }
+        },
+        ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_CHANNEL_DESTROY => {
+          let id = match reader.read_u32::<BigEndian>() {
+            Ok(id)  => id,
+            Err(_)  => return ProcessMessageResult::Reconnect,
+          };
+          if let Some(sender) = channels.remove(&id) {
+            let _ = sender.send(ChannelEvent::Destroyed);
+          }
+          if let Some(window) = windows.0.remove(&id) {
+            window.destroy();
+          }
+        },
+        ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_ACK => {
+          let id = match reader.read_u32::<BigEndian>() {
+            Ok(id)  => id,
+            Err(_)  => return ProcessMessageResult::Reconnect,
+          };
+          if let Some(window) = windows.0.get(&id) {
+            window.grant();
+          }
+        },
+        _ => return ProcessMessageResult::Unhandled,
+      };
+      ProcessMessageResult::Continue
+    }));
     Ok(Cadet {
       service_writer: service_writer,
-      _callback_loop: callback_loop,
-      next_channel_id: 0x80000000,
+      callback_loop: callback_loop,
+      next_channel_id: LOCAL_CHANNEL_ID_CLI,
+      registration_tx: registration_tx,
+      listen_ports: listen_ports.to_vec(),
+      dropped: dropped,
+    })
+  }
+
+  /// Send a message the typed API doesn't cover to the CADET service. See
+  /// `ServiceWriter::send_raw`.
+  pub fn send_raw(&self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    self.service_writer.lock().unwrap().send_raw(tpe, body)
+  }
+
+  /// The handler for messages from the CADET service which this handle doesn't understand.
+  /// Without a handler such messages make the handle stop receiving.
+  pub fn catch_all(&self) -> CatchAll {
+    self.callback_loop.catch_all()
+  }
+
+  /// Start accepting incoming channels on `port`.
+  ///
+  /// The port must have been declared with `connect_with_ports`. Returns a `Port` which yields
+  /// the incoming channels. Opening the same port again replaces the previous `Port`. Channels
+  /// arriving on a port which is not open are destroyed.
+  pub fn open_port(&mut self, port: u32) -> Result<Port, OpenPortError> {
+    if !self.listen_ports.contains(&port) {
+      return Err(OpenPortError::NotDeclared { port: port });
+    }
+    let (tx, rx) = channel::<Channel>();
+    // This fails once the callback loop has exited, eg. because the service restarted.
+    if self.registration_tx.send(Registration::Port(port, tx)).is_err() {
+      return Err(OpenPortError::Disconnected);
+    }
+    Ok(Port {
+      port: port,
+      receiver: rx,
     })
   }
 
-  pub fn connect(&mut self, peer: &PeerIdentity, port: u32, opt: ChannelOptions) -> Result<Channel, io::Error> {
-    let msg_length = 4 + 4 + 32 + 4 + 4;
-    let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_CHANNEL_CREATE);
+  /// Create a channel to the port `port` of the peer `peer`.
+  ///
+  /// Fails with `io::ErrorKind::NotConnected` if the connection to the service has been lost.
+  pub fn create_channel(&mut self, peer: &PeerIdentity, port: u32, options: ChannelOptions) -> Result<Channel, io::Error> {
     let id = self.next_channel_id;
     self.next_channel_id += 1;
-    mw.write_u32::<BigEndian>(id).unwrap();
-    peer.serialize(&mut mw).unwrap();
-    mw.write_u32::<BigEndian>(port).unwrap();
-    mw.write_u32::<BigEndian>(opt.as_u32()).unwrap();
-    try!(mw.send());
+
+    let (tx, rx) = channel::<ChannelEvent>();
+    let window = Arc::new(SendWindow::new());
+    // This fails once the callback loop has exited, eg. because the service restarted.
+    if self.registration_tx.send(Registration::Channel(id, tx, window.clone())).is_err() {
+      return Err(io::Error::new(io::ErrorKind::NotConnected, "the connection to the CADET service was lost"));
+    }
+    {
+      let mut sw = self.service_writer.lock().unwrap();
+      let mut mw = sw.write_message(48, ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_CHANNEL_CREATE);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      peer.serialize(&mut mw).unwrap();
+      mw.write_u32::<BigEndian>(port).unwrap();
+      mw.write_u32::<BigEndian>(options.as_u32()).unwrap();
+      try!(mw.send());
+    };
     Ok(Channel {
       id: id,
+      peer: *peer,
+      port: port,
+      service_writer: self.service_writer.clone(),
+      receiver: rx,
+      window: window,
+      destroyed: false,
+      dropped: self.dropped.clone(),
     })
   }
 }
 
+/// A port on which incoming channels are accepted. Created by `Cadet::open_port`.
+pub struct Port {
+  port: u32,
+  receiver: Receiver<Channel>,
+}
+
+impl Port {
+  /// The port number.
+  pub fn port(&self) -> u32 {
+    self.port
+  }
+
+  /// Accept an incoming channel.
+  ///
+  /// Blocks until another peer opens a channel to this port.
+  pub fn accept(&mut self) -> Result<Channel, AcceptError> {
+    match self.receiver.recv() {
+      Ok(channel) => Ok(channel),
+      Err(_)      => Err(AcceptError::Disconnected),
+    }
+  }
+}
+
+impl Iterator for Port {
+  type Item = Channel;
+
+  fn next(&mut self) -> Option<Channel> {
+    self.accept().ok()
+  }
+}
+
+impl Channel {
+  /// The peer at the other end of the channel.
+  pub fn peer(&self) -> &PeerIdentity {
+    &self.peer
+  }
+
+  /// The port the channel is connected to.
+  pub fn port(&self) -> u32 {
+    self.port
+  }
+
+  /// The number of messages the service is currently ready to accept on this channel.
+  pub fn window(&self) -> u32 {
+    self.window.credit()
+  }
+
+  /// The traffic counters for this channel.
+  pub fn stats(&self) -> ChannelStats {
+    self.window.stats()
+  }
+
+  /// Returns `true` if a message can be sent without blocking.
+  pub fn send_ready(&self) -> bool {
+    self.window() > 0
+  }
+
+  /// Block until a message can be sent without blocking or the channel is closed.
+  ///
+  /// Returns `false` if `timeout` expires first. A `timeout` of `None` waits forever.
+  pub fn wait_send_ready(&self, timeout: Option<Duration>) -> bool {
+    self.window.wait(timeout)
+  }
+
+  /// Send a message of type `tpe` over the channel.
+  ///
+  /// Blocks until the service is ready to accept the message.
+  pub fn send(&mut self, tpe: u16, payload: &[u8]) -> Result<(), ChannelSendError> {
+    // Without a timeout this only returns once there is credit or the channel is closed, which
+    // `try_send` reports as an error.
+    let ready = self.window.wait(None);
+    debug_assert!(ready);
+    self.try_send(tpe, payload)
+  }
+
+  /// Send a message of type `tpe` over the channel without blocking.
+  ///
+  /// Fails with `WindowExhausted` if the service is not ready to accept another message.
+  pub fn try_send(&mut self, tpe: u16, payload: &[u8]) -> Result<(), ChannelSendError> {
+    if self.destroyed {
+      return Err(ChannelSendError::Destroyed);
+    }
+    let msg_length = match (12 + payload.len()).to_u16() {
+      Some(l) => l,
+      None    => return Err(ChannelSendError::PayloadTooLong { len: payload.len() }),
+    };
+    try!(self.window.take());
+    let mut sw = self.service_writer.lock().unwrap();
+    let mut mw = sw.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_CADET_LOCAL_DATA);
+    mw.write_u32::<BigEndian>(self.id).unwrap();
+    mw.write_u16::<BigEndian>((4 + payload.len()) as u16).unwrap();
+    mw.write_u16::<BigEndian>(tpe).unwrap();
+    mw.write_all(payload).unwrap();
+    try!(mw.send());
+    self.window.record_sent(payload.len());
+    Ok(())
+  }
+
+  /// Receive a message from the channel.
+  ///
+  /// Blocks until a message arrives. Returns the message type and payload.
+  pub fn recv(&mut self) -> Result<(u16, Vec<u8>), ChannelRecvError> {
+    if self.destroyed {
+      return Err(ChannelRecvError::Destroyed);
+    }
+    match self.receiver.recv() {
+      Ok(ChannelEvent::Data(tpe, payload))  => Ok((tpe, payload)),
+      Ok(ChannelEvent::Destroyed)           => {
+        self.destroyed = true;
+        Err(ChannelRecvError::Destroyed)
+      },
+      Err(_)                                => Err(ChannelRecvError::Disconnected),
+    }
+  }
+
+  /// Receive a message from the channel, waiting at most `timeout`.
+  ///
+  /// Returns `Ok(None)` if no message arrived before the timeout expired.
+  pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<(u16, Vec<u8>)>, ChannelRecvError> {
+    if self.destroyed {
+      return Err(ChannelRecvError::Destroyed);
+    }
+    match self.receiver.recv_timeout(timeout) {
+      Ok(ChannelEvent::Data(tpe, payload))    => Ok(Some((tpe, payload))),
+      Ok(ChannelEvent::Destroyed)             => {
+        self.destroyed = true;
+        Err(ChannelRecvError::Destroyed)
+      },
+      Err(RecvTimeoutError::Timeout)          => Ok(None),
+      Err(RecvTimeoutError::Disconnected)     => Err(ChannelRecvError::Disconnected),
+    }
+  }
+}
+
+impl Drop for Channel {
+  fn drop(&mut self) {
+    if !self.destroyed {
+      let _ = send_channel_destroy(&self.service_writer, self.id);
+    }
+    self.dropped.lock().unwrap().push(self.id);
+  }
+}
+
+impl Component for Cadet {
+  /// Stop accepting incoming channels. Ports being listened on are closed, which wakes up
+  /// anything waiting on them once the handle is closed.
+  fn stop_accepting(&mut self) {
+    let _ = self.registration_tx.send(Registration::ClosePorts);
+  }
+
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    Some(&mut self.callback_loop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+  use std::thread;
+  use super::{SendWindow, ChannelSendError, ChannelStats};
+
+  #[test]
+  fn test_send_window() {
+    let window = Arc::new(SendWindow::new());
+    match window.take() {
+      Err(ChannelSendError::WindowExhausted) => (),
+      _ => panic!("expected the window to be exhausted"),
+    };
+    window.grant();
+    window.grant();
+    assert_eq!(window.credit(), 2);
+    assert!(window.take().is_ok());
+    assert!(window.take().is_ok());
+    assert_eq!(window.credit(), 0);
+
+    let w = window.clone();
+    let t = thread::spawn(move || w.wait(None));
+    window.destroy();
+    assert!(t.join().unwrap());
+    match window.take() {
+      Err(ChannelSendError::Destroyed) => (),
+      _ => panic!("expected the channel to be destroyed"),
+    };
+  }
+
+  #[test]
+  fn test_channel_stats() {
+    let window = SendWindow::new();
+    assert!(window.take().is_err());
+    window.grant();
+    window.grant();
+    assert!(window.take().is_ok());
+    window.record_sent(10);
+    window.record_received(20);
+    window.record_ack_sent();
+    assert_eq!(window.stats(), ChannelStats {
+      messages_sent: 1,
+      bytes_sent: 10,
+      messages_received: 1,
+      bytes_received: 20,
+      acks_received: 2,
+      acks_sent: 1,
+      acks_outstanding: 1,
+      send_stalls: 1,
+    });
+  }
+}
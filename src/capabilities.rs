@@ -0,0 +1,88 @@
+//! Finding out which optional features the local daemons support.
+//!
+//! GNUnet daemons don't announce a protocol version and the layout of some messages has changed
+//! between releases. A daemon which receives a message it doesn't understand drops the
+//! connection, so the features can instead be detected by sending each daemon a harmless request
+//! in the newer layout and seeing whether the connection survives. See `service::probe`.
+//!
+//! `Namestore::connect` probes for single label lookups and `TransportService::try_connect`
+//! probes for connection requests the first time it is called. GNS lookups are laid out for
+//! current daemons unless the handle is given the result of `Capabilities::probe`:
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use gnunet::{Cfg, GNS};
+//! use gnunet::capabilities::Capabilities;
+//!
+//! let config = Cfg::default().unwrap();
+//! let caps = Capabilities::probe(&config, Duration::from_secs(1));
+//! let mut gns = GNS::connect(&config).unwrap();
+//! gns.set_capabilities(&caps);
+//! ```
+
+use std::time::Duration;
+
+use Cfg;
+use gns::LookupProtocol;
+use namestore;
+use transport;
+
+/// How long the namestore and transport handles wait for their daemon to react to a probe.
+pub const PROBE_TIMEOUT_MILLIS: u64 = 250;
+
+/// The optional features supported by the local daemons.
+///
+/// The GNS, namestore and transport handles choose how to lay out their requests from these.
+/// Their `set_capabilities` methods replace what the handles found out themselves. The default
+/// describes current daemons.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+  /// The layout of lookup requests the GNS daemon expects.
+  pub gns_lookup: LookupProtocol,
+  /// Whether the namestore daemon can look up the records under a single label. Without it
+  /// `Namestore::lookup` has to iterate over the whole zone.
+  pub namestore_record_lookup: bool,
+  /// Whether the transport daemon accepts requests to connect to a peer.
+  pub transport_request_connect: bool,
+}
+
+impl Default for Capabilities {
+  fn default() -> Capabilities {
+    Capabilities {
+      gns_lookup: LookupProtocol::NoShorten,
+      namestore_record_lookup: true,
+      transport_request_connect: true,
+    }
+  }
+}
+
+impl Capabilities {
+  /// Ask each daemon what it supports, waiting up to `timeout` for each of them to react.
+  ///
+  /// Every probe uses its own connection. If a daemon can't be reached the default is kept for
+  /// its features.
+  pub fn probe(cfg: &Cfg, timeout: Duration) -> Capabilities {
+    let configured = Capabilities::default();
+    Capabilities {
+      gns_lookup: LookupProtocol::probe(cfg, timeout).unwrap_or(configured.gns_lookup),
+      namestore_record_lookup: namestore::probe_record_lookup(cfg, timeout)
+                                 .unwrap_or(configured.namestore_record_lookup),
+      transport_request_connect: transport::probe_request_connect(cfg, timeout)
+                                   .unwrap_or(configured.transport_request_connect),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use gns::LookupProtocol;
+  use super::*;
+
+  #[test]
+  fn test_capabilities_default() {
+    let caps = Capabilities::default();
+    assert_eq!(caps.gns_lookup, LookupProtocol::NoShorten);
+    assert!(caps.namestore_record_lookup);
+    assert!(caps.transport_request_connect);
+  }
+}
@@ -1,16 +1,18 @@
 use std;
 use std::collections::{hash_map, HashMap};
 use std::borrow::{Borrow, IntoCow};
-use std::io::{self, Read, BufRead, BufReader};
+use std::io::{self, Read, Write, BufRead, BufReader};
 use std::num::{ParseIntError, ParseFloatError};
 use std::path::{Path, PathBuf};
-use std::fs::File;
+use std::fs::{self, File};
 use std::ffi::OsStr;
+use std::os::unix::fs::PermissionsExt;
 use std::str::FromStr;
 use util;
 use paths;
 use time;
 
+#[derive(Clone)]
 pub struct Cfg {
     data: HashMap<String, HashMap<String, String>>,
 }
@@ -27,6 +29,8 @@ error_def! CfgDefaultError {
 error_def! CfgLoadRawError {
     FileOpen { #[from] cause: io::Error }
         => "Failed to open file" ("Reason: {}", cause),
+    WorldReadable { mode: u32 }
+        => "Refusing to load a secret file which is readable by other users" ("File mode is {:o}", mode),
     Deserialize { #[from] cause: CfgDeserializeError }
         => "Failed to deserialize config" ("Reason: {}", cause),
 }
@@ -110,6 +114,52 @@ impl Cfg {
         Ok(try!(Cfg::deserialize(f, true)))
     }
 
+    /// Load a config file, or every `.conf` file in a directory, for an `@INLINE@` directive.
+    ///
+    /// The files in a directory are loaded in order of their names so that later files override
+    /// earlier ones consistently.
+    pub fn load_inline<P: AsRef<Path>>(path: P) -> Result<Cfg, CfgLoadRawError> {
+        let path = path.as_ref();
+        if !try!(fs::metadata(path)).is_dir() {
+            return Cfg::load_raw(path);
+        }
+
+        let mut paths = Vec::new();
+        for res_dirent in try!(fs::read_dir(path)) {
+            let dirent = try!(res_dirent);
+            let path = dirent.path();
+            if path.extension() == Some(OsStr::new("conf")) && try!(dirent.file_type()).is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut cfg = Cfg::empty();
+        for path in paths {
+            cfg.merge(try!(Cfg::load_raw(path)));
+        }
+        Ok(cfg)
+    }
+
+    /// Load a single section from a file containing secrets, for an `@INLINE-SECRET@` directive.
+    ///
+    /// Refuses to load the file if it is readable by users other than its owner and group, since
+    /// the secrets would not be secret. Other sections of the file are ignored.
+    pub fn load_secret<P: AsRef<Path>>(path: P, section: &str) -> Result<Cfg, CfgLoadRawError> {
+        let path = path.as_ref();
+        let mode = try!(fs::metadata(path)).permissions().mode();
+        if mode & 0o004 != 0 {
+            return Err(CfgLoadRawError::WorldReadable { mode: mode & 0o7777 });
+        }
+
+        let mut raw = try!(Cfg::load_raw(path));
+        let mut cfg = Cfg::empty();
+        if let Some(map) = raw.data.remove(section) {
+            cfg.data.insert(section.to_string(), map);
+        }
+        Ok(cfg)
+    }
+
     pub fn deserialize<R: Read>(read: R, allow_inline: bool) -> Result<Cfg, CfgDeserializeError> {
         use self::CfgDeserializeError::*;
 
@@ -134,11 +184,35 @@ impl Cfg {
                     continue;
                 }
 
+                let re_inline_secret = regex!(r"^(?i)@inline-secret@ \[?([^\]\s]+)\]? (.+)$");
+                if let Some(caps) = re_inline_secret.captures(line) {
+                    let secret_section = caps.at(1).unwrap(); // panic is logically impossible
+                    let filename = caps.at(2).unwrap().trim(); // panic is logically impossible
+                    if allow_inline {
+                        let cfg_raw = match Cfg::load_secret(filename, secret_section) {
+                            Ok(cfg_raw) => cfg_raw,
+                            Err(e)      => return Err(LoadInline {
+                                cause: Box::new(e),
+                                line_number: line_num,
+                                filename: filename.to_string(),
+                            })
+                        };
+                        cfg.merge(cfg_raw);
+                    }
+                    else {
+                        return Err(InlineDisabled {
+                            line_number: line_num,
+                            filename: filename.to_string(),
+                        })
+                    }
+                    continue;
+                }
+
                 let re_inline = regex!(r"^(?i)@inline@ (.+)$");
                 if let Some(caps) = re_inline.captures(line) {
                     let filename = caps.at(1).unwrap().trim(); // panic is logically impossible
                     if allow_inline {
-                        let cfg_raw = match Cfg::load_raw(filename) {
+                        let cfg_raw = match Cfg::load_inline(filename) {
                             Ok(cfg_raw) => cfg_raw,
                             Err(e)      => return Err(LoadInline {
                                 cause: Box::new(e),
@@ -316,7 +390,7 @@ impl Cfg {
                 std::mem::swap(val, &mut value);
                 return Some(value);
             }
-            map.insert(section.into_owned(), value);
+            map.insert(key.into_owned(), value);
             return None;
         }
 
@@ -326,6 +400,48 @@ impl Cfg {
         None
     }
 
+    /// The names of the sections in the config, sorted.
+    pub fn sections(&self) -> Vec<&str> {
+        let mut ret: Vec<&str> = self.data.keys().map(|s| &s[..]).collect();
+        ret.sort();
+        ret
+    }
+
+    /// Get the entries of a section, if it exists.
+    pub fn get_section(&self, section: &str) -> Option<&HashMap<String, String>> {
+        self.data.get(section)
+    }
+
+    /// Remove a section and all of its entries. Returns `true` if the section existed.
+    pub fn remove_section(&mut self, section: &str) -> bool {
+        self.data.remove(section).is_some()
+    }
+
+    /// Write the config in the format read by `deserialize`.
+    ///
+    /// Sections and keys are written in sorted order so that the output is stable.
+    pub fn serialize<W: Write>(&self, mut w: W) -> Result<(), io::Error> {
+        let mut sections: Vec<&String> = self.data.keys().collect();
+        sections.sort();
+        for section in sections {
+            let map = &self.data[section];
+            try!(writeln!(w, "[{}]", section));
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            for key in keys {
+                try!(writeln!(w, "{} = {}", key, map[key]));
+            }
+            try!(writeln!(w, ""));
+        }
+        Ok(())
+    }
+
+    /// Write the config to the file at `path`, replacing its contents.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let f = try!(File::create(path));
+        self.serialize(f)
+    }
+
     pub fn expand_dollar<'o>(&self, orig: &'o str) -> Result<String, CfgExpandDollarError> {
         use self::CfgExpandDollarError::*;
 
@@ -488,5 +604,56 @@ mod tests {
         let expanded = unwrap_result!(cfg.expand_dollar(unexpanded));
         assert_eq!(expanded, "foo in_paths in_env in_env_wub_blah");
     }
+
+    #[test]
+    fn test_set_string_new_key() {
+        let mut cfg = Cfg::empty();
+
+        assert!(cfg.set_string("foo", "A", String::from("1")).is_none());
+        assert!(cfg.set_string("foo", "B", String::from("2")).is_none());
+        assert_eq!(unwrap_result!(cfg.get_int("foo", "A")), 1);
+        assert_eq!(unwrap_result!(cfg.get_int("foo", "B")), 2);
+        assert_eq!(cfg.set_string("foo", "B", String::from("3")), Some(String::from("2")));
+        assert_eq!(unwrap_result!(cfg.get_int("foo", "B")), 3);
+    }
+
+    #[test]
+    fn test_inline_dir_and_secret() {
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("gnunet-rs-cfg-test-{}", ::rand::random::<u32>()));
+        let _ = fs::remove_dir_all(&dir);
+        unwrap_result!(fs::create_dir_all(dir.join("conf.d")));
+
+        let write = |name: &str, contents: &str, mode: u32| {
+            let path = dir.join(name);
+            let mut f = File::create(&path).unwrap();
+            f.write_all(contents.as_bytes()).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        write("conf.d/a.conf", "[foo]\nX = 1\nY = 1\n", 0o644);
+        write("conf.d/b.conf", "[foo]\nY = 2\n", 0o644);
+        write("conf.d/ignored.txt", "not a config", 0o644);
+        let secret = write("secret.conf", "[bar]\nKEY = 3\n[other]\nZ = 4\n", 0o600);
+
+        let text = format!("@INLINE@ {}\n@INLINE-SECRET@ [bar] {}\n", dir.join("conf.d").to_str().unwrap(), secret);
+        let cfg = unwrap_result!(Cfg::deserialize(text.as_bytes(), true));
+        assert_eq!(unwrap_result!(cfg.get_int("foo", "X")), 1);
+        assert_eq!(unwrap_result!(cfg.get_int("foo", "Y")), 2);
+        assert_eq!(unwrap_result!(cfg.get_int("bar", "KEY")), 3);
+        assert!(cfg.get_int("other", "Z").is_err());
+
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o644)).unwrap();
+        match Cfg::load_secret(&secret, "bar") {
+            Err(CfgLoadRawError::WorldReadable { .. }) => (),
+            _ => panic!("loaded a world-readable secret file"),
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
 
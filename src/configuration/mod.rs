@@ -1,4 +1,5 @@
 pub use self::configuration::*;
+pub use self::template::*;
 
 pub mod configuration;
-
+mod template;
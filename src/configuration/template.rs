@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use configuration::{Cfg, CfgExpandDollarError};
+
+/// Errors returned by `Template::render` and `Template::deploy`.
+error_def! RenderError {
+    ExpandDollar { section: String, key: String, cause: CfgExpandDollarError }
+        => "Failed to '$'-expand a config entry" ("[{}] {}: {}", section, key, cause),
+    Io { #[from] cause: io::Error }
+        => "There was an I/O error creating the peer's directories or writing its config"
+            ("Specifically: {}", cause),
+}
+
+/// A base config from which the configs of many peers are made.
+///
+/// Each peer's config is the base with that peer's overrides applied and every `$`-expansion
+/// resolved, so the rendered file means the same thing whatever environment it is later loaded
+/// in. Overrides are given as a map of section names to the entries to set in that section, eg.
+/// a different `[PATHS] GNUNET_HOME` and set of ports for each peer.
+pub struct Template {
+    base: Cfg,
+}
+
+impl Template {
+    /// Create a template from the config `base`.
+    pub fn new(base: Cfg) -> Template {
+        Template {
+            base: base,
+        }
+    }
+
+    /// The base config.
+    pub fn base(&self) -> &Cfg {
+        &self.base
+    }
+
+    /// Make the config for a peer by applying `overrides` to the base and expanding every entry.
+    pub fn render(&self, overrides: &HashMap<String, HashMap<String, String>>) -> Result<Cfg, RenderError> {
+        let mut cfg = self.base.clone();
+        for (section, entries) in overrides.iter() {
+            for (key, value) in entries.iter() {
+                cfg.set_string(&section[..], &key[..], value.clone());
+            }
+        }
+
+        // Expand against the merged config rather than the partly expanded one so that the
+        // result doesn't depend on the order entries are visited in.
+        let mut ret = Cfg::empty();
+        for section in cfg.sections() {
+            for (key, value) in cfg.get_section(section).unwrap().iter() {
+                let expanded = match cfg.expand_dollar(value) {
+                    Ok(e)   => e,
+                    Err(e)  => return Err(RenderError::ExpandDollar {
+                        section: section.to_string(),
+                        key: key.clone(),
+                        cause: e,
+                    }),
+                };
+                ret.set_string(section, &key[..], expanded);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Render the config for a peer, create the directories it refers to and save it to `path`.
+    ///
+    /// See `directories` for which directories are created. Returns the rendered config.
+    pub fn deploy<P: AsRef<Path>>(&self,
+                                  overrides: &HashMap<String, HashMap<String, String>>,
+                                  path: P) -> Result<Cfg, RenderError> {
+        let cfg = try!(self.render(overrides));
+        for dir in directories(&cfg) {
+            try!(fs::create_dir_all(dir));
+        }
+        if let Some(parent) = path.as_ref().parent() {
+            try!(fs::create_dir_all(parent));
+        }
+        try!(cfg.save(path));
+        Ok(cfg)
+    }
+}
+
+/// The directories a peer running with the rendered config `cfg` expects to exist.
+///
+/// These are the absolute paths in the `[PATHS]` section and in entries whose names end in `DIR`
+/// or `HOME`, plus the directories holding the files named by `UNIXPATH` entries and by entries
+/// whose names end in `FILE`, `FILENAME` or `KEY`. Relative paths are left alone since they
+/// depend on where the peer is started.
+pub fn directories(cfg: &Cfg) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    for section in cfg.sections() {
+        for (key, value) in cfg.get_section(section).unwrap().iter() {
+            let path = Path::new(value);
+            if !path.is_absolute() {
+                continue;
+            }
+            let dir = if section == "PATHS" || key.ends_with("DIR") || key.ends_with("HOME") {
+                Some(path)
+            }
+            else if key == "UNIXPATH" || key.ends_with("FILE") || key.ends_with("FILENAME") || key.ends_with("KEY") {
+                path.parent()
+            }
+            else {
+                None
+            };
+            if let Some(dir) = dir {
+                ret.push(dir.to_path_buf());
+            }
+        }
+    }
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use configuration::Cfg;
+    use super::*;
+
+    #[test]
+    fn test_render_and_deploy() {
+        let mut root = ::std::env::temp_dir();
+        root.push(format!("gnunet-rs-template-test-{}", ::rand::random::<u32>()));
+        let root = root.to_str().unwrap().to_string();
+
+        let mut base = Cfg::empty();
+        base.set_string("PATHS", "GNUNET_HOME", "/nonexistent".to_string());
+        base.set_string("PATHS", "SERVICEHOME", "$GNUNET_HOME/svc".to_string());
+        base.set_string("gns", "UNIXPATH", "${SERVICEHOME}/gns.sock".to_string());
+        base.set_string("gns", "PORT", "2100".to_string());
+        base.set_string("gns", "BINARY", "gnunet-service-gns".to_string());
+        let template = Template::new(base);
+
+        let mut paths = HashMap::new();
+        paths.insert("GNUNET_HOME".to_string(), format!("{}/peer1", root));
+        let mut gns = HashMap::new();
+        gns.insert("PORT".to_string(), "2101".to_string());
+        let mut overrides = HashMap::new();
+        overrides.insert("PATHS".to_string(), paths);
+        overrides.insert("gns".to_string(), gns);
+
+        let cfg = template.render(&overrides).unwrap();
+        assert_eq!(cfg.get_section("gns").unwrap()["UNIXPATH"], format!("{}/peer1/svc/gns.sock", root));
+        assert_eq!(cfg.get_int("gns", "PORT").unwrap(), 2101);
+        assert_eq!(directories(&cfg), vec![PathBuf::from(format!("{}/peer1", root)),
+                                           PathBuf::from(format!("{}/peer1/svc", root))]);
+
+        let conf = PathBuf::from(format!("{}/peer1.conf", root));
+        template.deploy(&overrides, &conf).unwrap();
+        assert!(fs::metadata(format!("{}/peer1/svc", root)).unwrap().is_dir());
+        let loaded = Cfg::load_raw(&conf).unwrap();
+        assert_eq!(loaded.get_section("PATHS").unwrap()["SERVICEHOME"], format!("{}/peer1/svc", root));
+        let _ = fs::remove_dir_all(&root);
+
+        let mut bad = HashMap::new();
+        bad.insert("NOT_SET_ANYWHERE_XYZ".to_string(), "$NOT_SET_ANYWHERE_XYZ_2".to_string());
+        overrides.insert("foo".to_string(), bad);
+        assert!(template.render(&overrides).is_err());
+    }
+}
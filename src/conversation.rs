@@ -0,0 +1,314 @@
+//! Module for making and receiving voice calls through the conversation service.
+//!
+//! A `Phone` registers a line with the local conversation service and receives calls made to it.
+//! Callers find the phone through a `PHONE` record in GNS naming the peer and line, and reach it
+//! with `call`. Once a call is established both sides exchange audio as opaque payloads; encoding
+//! and playing the audio is left to the application.
+
+use std::io::{self, Cursor, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use PeerIdentity;
+use gns::{self, ConnectLookupError, LocalOptions, Record, RecordType};
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+
+/// The contents of a `PHONE` record: where to reach a phone.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PhoneRecord {
+  /// The line the phone is registered on.
+  pub line: u32,
+  /// The peer the phone is registered with.
+  pub peer: PeerIdentity,
+}
+
+/// Errors returned by `PhoneRecord::from_record`.
+error_def! PhoneRecordError {
+  WrongType { record_type: RecordType }
+    => "The record is not a PHONE record" ("Record type: {}", record_type),
+  WrongSize { len: usize }
+    => "The record has the wrong size for a PHONE record" ("{} bytes", len),
+  UnsupportedVersion { version: u32 }
+    => "The PHONE record has an unsupported version" ("Version: {}", version),
+}
+
+impl PhoneRecord {
+  /// Make the record which tells callers how to reach this phone. `expiration_time` and `flags`
+  /// are as for `Record::new`.
+  pub fn to_record(&self, expiration_time: u64, flags: u32) -> Record {
+    let mut data = Vec::with_capacity(40);
+    data.write_u32::<BigEndian>(0).unwrap(); // version
+    data.write_u32::<BigEndian>(self.line).unwrap();
+    self.peer.serialize(&mut data).unwrap();
+    Record::new(RecordType::PHONE, data, expiration_time, flags)
+  }
+
+  /// Read a `PHONE` record.
+  pub fn from_record(record: &Record) -> Result<PhoneRecord, PhoneRecordError> {
+    if record.record_type() != RecordType::PHONE {
+      return Err(PhoneRecordError::WrongType { record_type: record.record_type() });
+    }
+    let data = record.data();
+    if data.len() != 40 {
+      return Err(PhoneRecordError::WrongSize { len: data.len() });
+    }
+    let mut r = Cursor::new(data);
+    let version = r.read_u32::<BigEndian>().unwrap();
+    if version != 0 {
+      return Err(PhoneRecordError::UnsupportedVersion { version: version });
+    }
+    let line = r.read_u32::<BigEndian>().unwrap();
+    let peer = PeerIdentity::deserialize(&mut r).unwrap();
+    Ok(PhoneRecord {
+      line: line,
+      peer: peer,
+    })
+  }
+}
+
+/// Errors returned when receiving events from the conversation service.
+error_def! ConversationError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the conversation service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the conversation service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The conversation service sent an unexpected message type" ("Message type {} was not expected", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {ConversationError}
+
+/// Errors returned when sending audio.
+error_def! SendAudioError {
+  TooLong { len: usize }
+    => "The audio payload is too long to send in one message" ("{} bytes is too long", len),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the conversation service" ("Specifically: {}", cause),
+}
+
+/// Send a message carrying only a call id.
+fn send_cid(w: &mut ServiceWriter, tpe: u16, cid: u32) -> Result<(), io::Error> {
+  let mut mw = w.write_message(8, tpe);
+  mw.write_u32::<BigEndian>(cid).unwrap();
+  mw.send()
+}
+
+fn send_audio(w: &mut ServiceWriter, cid: u32, data: &[u8]) -> Result<(), SendAudioError> {
+  if data.len() > 0xffff - 8 {
+    return Err(SendAudioError::TooLong { len: data.len() });
+  }
+  let mut mw = w.write_message((8 + data.len()) as u16, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_AUDIO);
+  mw.write_u32::<BigEndian>(cid).unwrap();
+  mw.write_all(data).unwrap();
+  Ok(try!(mw.send()))
+}
+
+/// Something which happened to a call to a `Phone`.
+#[derive(Debug)]
+pub enum PhoneEvent {
+  /// Someone is calling. `pick_up` answers the call and `hang_up` rejects it.
+  Ring {
+    /// Identifies the call in further events and requests.
+    cid: u32,
+    /// The ego key of the caller.
+    caller: EcdsaPublicKey,
+  },
+  /// The caller hung up.
+  HangUp { cid: u32 },
+  /// The caller put the call on hold.
+  Suspend { cid: u32 },
+  /// The caller resumed the call.
+  Resume { cid: u32 },
+  /// Audio from the caller.
+  Audio { cid: u32, data: Vec<u8> },
+}
+
+/// A phone registered with the conversation service, which can receive calls.
+pub struct Phone {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  line: u32,
+}
+
+impl Phone {
+  /// Register a phone on `line`. Callers reach it through a `PHONE` record giving this peer and
+  /// line, see `PhoneRecord`.
+  pub fn register(cfg: &Cfg, line: u32) -> Result<Phone, service::ConnectError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "conversation"));
+    try!(send_cid(&mut service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_REGISTER, line));
+    Ok(Phone {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      line: line,
+    })
+  }
+
+  /// The line the phone is registered on.
+  pub fn line(&self) -> u32 {
+    self.line
+  }
+
+  /// Wait for the next event.
+  pub fn next_event(&mut self) -> Result<PhoneEvent, ConversationError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    let cid = try!(mr.read_u32::<BigEndian>());
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_RING => {
+        let caller = try!(EcdsaPublicKey::deserialize(&mut mr));
+        Ok(PhoneEvent::Ring { cid: cid, caller: caller })
+      },
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_HANG_UP => Ok(PhoneEvent::HangUp { cid: cid }),
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_SUSPEND => Ok(PhoneEvent::Suspend { cid: cid }),
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_RESUME  => Ok(PhoneEvent::Resume { cid: cid }),
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_AUDIO => {
+        let mut data = Vec::new();
+        try!(mr.read_to_end(&mut data));
+        Ok(PhoneEvent::Audio { cid: cid, data: data })
+      },
+      x => Err(ConversationError::UnexpectedMessageType { ty: x }),
+    }
+  }
+
+  /// Answer the ringing call `cid`.
+  pub fn pick_up(&mut self, cid: u32) -> Result<(), io::Error> {
+    send_cid(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_PICK_UP, cid)
+  }
+
+  /// End or reject the call `cid`.
+  pub fn hang_up(&mut self, cid: u32) -> Result<(), io::Error> {
+    send_cid(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_HANG_UP, cid)
+  }
+
+  /// Put the call `cid` on hold.
+  pub fn suspend(&mut self, cid: u32) -> Result<(), io::Error> {
+    send_cid(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_SUSPEND, cid)
+  }
+
+  /// Take the call `cid` off hold.
+  pub fn resume(&mut self, cid: u32) -> Result<(), io::Error> {
+    send_cid(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_RESUME, cid)
+  }
+
+  /// Send audio to the caller of `cid`.
+  pub fn send_audio(&mut self, cid: u32, data: &[u8]) -> Result<(), SendAudioError> {
+    send_audio(&mut self.service_writer, cid, data)
+  }
+}
+
+/// Something which happened to an outgoing `Call`.
+#[derive(Debug)]
+pub enum CallEvent {
+  /// The callee answered.
+  PickedUp,
+  /// The callee hung up or rejected the call.
+  HangUp,
+  /// The callee put the call on hold.
+  Suspend,
+  /// The callee resumed the call.
+  Resume,
+  /// Audio from the callee.
+  Audio(Vec<u8>),
+}
+
+/// Errors returned by `call`.
+error_def! CallError {
+  Lookup { #[from] cause: ConnectLookupError }
+    => "Failed to look up the callee's PHONE record" ("Reason: {}", cause),
+  InvalidPhoneRecord { #[from] cause: PhoneRecordError }
+    => "The callee's PHONE record is invalid" ("Reason: {}", cause),
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the conversation service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the conversation service" ("Specifically: {}", cause),
+}
+
+/// An outgoing call. Dropping it hangs up.
+pub struct Call {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+}
+
+// Each connection carries a single outgoing call, so the service ignores the call id.
+const CALLER_CID: u32 = 0;
+
+/// Call the phone published under the GNS name `callee` in `zone`, identifying ourselves with the
+/// ego key `caller`.
+///
+/// Returns once the call has been placed; the callee's answer arrives as `CallEvent::PickedUp`.
+pub fn call(cfg: &Cfg, caller: &EcdsaPrivateKey, callee: &str, zone: &EcdsaPublicKey) -> Result<Call, CallError> {
+  let record = try!(gns::lookup(cfg, callee, zone, RecordType::PHONE, LocalOptions::Default, None));
+  let phone = try!(PhoneRecord::from_record(&record));
+  let (service_reader, mut service_writer) = try!(service::connect(cfg, "conversation"));
+  {
+    let mut mw = service_writer.write_message(72, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_CALL);
+    mw.write_u32::<BigEndian>(phone.line).unwrap();
+    phone.peer.serialize(&mut mw).unwrap();
+    caller.serialize(&mut mw).unwrap();
+    try!(mw.send());
+  }
+  Ok(Call {
+    service_reader: service_reader,
+    service_writer: service_writer,
+  })
+}
+
+impl Call {
+  /// Wait for the next event.
+  pub fn next_event(&mut self) -> Result<CallEvent, ConversationError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    let _cid = try!(mr.read_u32::<BigEndian>());
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_PICKED_UP => Ok(CallEvent::PickedUp),
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_HANG_UP   => Ok(CallEvent::HangUp),
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_SUSPEND   => Ok(CallEvent::Suspend),
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_RESUME    => Ok(CallEvent::Resume),
+      ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_AUDIO => {
+        let mut data = Vec::new();
+        try!(mr.read_to_end(&mut data));
+        Ok(CallEvent::Audio(data))
+      },
+      x => Err(ConversationError::UnexpectedMessageType { ty: x }),
+    }
+  }
+
+  /// Send audio to the callee.
+  pub fn send_audio(&mut self, data: &[u8]) -> Result<(), SendAudioError> {
+    send_audio(&mut self.service_writer, CALLER_CID, data)
+  }
+
+  /// Put the call on hold.
+  pub fn suspend(&mut self) -> Result<(), io::Error> {
+    send_cid(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_SUSPEND, CALLER_CID)
+  }
+
+  /// Take the call off hold.
+  pub fn resume(&mut self) -> Result<(), io::Error> {
+    send_cid(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_RESUME, CALLER_CID)
+  }
+
+  /// End the call.
+  pub fn hang_up(mut self) -> Result<(), io::Error> {
+    send_cid(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_HANG_UP, CALLER_CID)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use PeerIdentity;
+  use gns::RecordType;
+  use super::*;
+
+  #[test]
+  fn test_phone_record() {
+    let peer = PeerIdentity::deserialize(&mut Cursor::new(&[3u8; 32][..])).unwrap();
+    let phone = PhoneRecord { line: 7, peer: peer };
+    let record = phone.to_record(0, 0);
+    assert_eq!(record.record_type(), RecordType::PHONE);
+    assert_eq!(PhoneRecord::from_record(&record).unwrap(), phone);
+  }
+}
@@ -0,0 +1,361 @@
+//! Module for talking to the core service. The core service maintains encrypted connections to
+//! other peers.
+//!
+//! Applications can use core to exchange their own message types with connected peers. Pass the
+//! types you want to receive to `Core::init_with_types`, then use `Core::send` to send messages
+//! and watch for `CoreEvent::Message` events to receive them.
+//!
+//! # Example
+//!
+//! ```rust
+//! use gnunet::{Cfg, core};
+//!
+//! let config = Cfg::default().unwrap();
+//! let mut core = core::Core::init(&config).unwrap();
+//! for event in core {
+//!   match event.unwrap() {
+//!     core::CoreEvent::Connected(peer)    => println!("{} connected", peer),
+//!     core::CoreEvent::Disconnected(peer) => println!("{} disconnected", peer),
+//!     core::CoreEvent::Message { .. }     => (),
+//!   }
+//! }
+//! ```
+
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Cursor, Read, Write};
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use PeerIdentity;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+use time;
+pub use self::monitor::*;
+pub use self::peers::*;
+
+mod monitor;
+mod peers;
+
+/// Something that happened on one of our connections to other peers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CoreEvent {
+  /// We are now connected to the peer.
+  Connected(PeerIdentity),
+  /// We are no longer connected to the peer.
+  Disconnected(PeerIdentity),
+  /// The peer sent us a message of one of the types passed to `Core::init_with_types`.
+  Message {
+    /// The peer which sent the message.
+    peer: PeerIdentity,
+    /// The type of the message.
+    message_type: u16,
+    /// The body of the message, not including its size and type header.
+    data: Vec<u8>,
+  },
+}
+
+/// How important a message is. The service uses this to decide which messages to send first, and
+/// which to drop when it has to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+  /// Lowest priority, for traffic that can wait.
+  Background,
+  /// Normal traffic.
+  BestEffort,
+  /// Traffic which should be sent as soon as possible.
+  Urgent,
+  /// Highest priority, for control messages which must not be delayed.
+  CriticalControl,
+}
+
+impl Priority {
+  fn to_u32(&self) -> u32 {
+    match *self {
+      Priority::Background      => 0,
+      Priority::BestEffort      => 1,
+      Priority::Urgent          => 2,
+      Priority::CriticalControl => 3,
+    }
+  }
+}
+
+/// A handle to the core service.
+///
+/// After connecting, the service reports every peer we are already connected to (including our
+/// own peer) as a `Connected` event, followed by events as peers connect and disconnect and as
+/// messages arrive. Events are received with `next_event` or by iterating over the handle, which
+/// blocks until the next event arrives. If the connection to the service is lost the iterator
+/// returns a `Disconnected` error and ends.
+pub struct Core {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  my_identity: PeerIdentity,
+  connected: HashSet<PeerIdentity>,
+  pending: VecDeque<CoreEvent>,
+  next_request_id: u16,
+  disconnected: bool,
+}
+
+/// Errors returned by `Core::init`.
+error_def! InitError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the core service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the core service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the core service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The core service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  TooManyTypes { count: usize }
+    => "Too many message types were given to fit in the request to the service" ("{} message types is too many", count),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {InitError}
+
+/// Errors returned by `Core::next_event`.
+error_def! NextEventError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the core service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the core service" ("Reason: {}", cause),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {NextEventError}
+
+/// Errors returned by `Core::send`.
+error_def! SendError {
+  NotConnected
+    => "We are not connected to the peer",
+  PeerDisconnected
+    => "The peer disconnected before the message could be sent",
+  Timeout
+    => "The service was not ready to take the message before its maximum delay expired",
+  MessageTooLarge { len: usize }
+    => "The message is too large to send through the core service" ("The message body is {} bytes long", len),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the core service" ("Specifically: {}", cause),
+  NextEvent { #[from] cause: NextEventError }
+    => "Failed to receive a message from the core service" ("Reason: {}", cause),
+}
+
+/// The size of a `SEND_REQUEST` message.
+const SEND_REQUEST_LEN: u16 = 4 + 4 + 8 + 32 + 4 + 2 + 2;
+
+/// The size of a `SEND` message, not including the message being sent.
+const SEND_HEADER_LEN: usize = 4 + 4 + 8 + 32 + 4 + 4;
+
+/// The time `delay` from now in microseconds since the epoch.
+fn deadline_micros(delay: Duration) -> u64 {
+  let delay = delay.as_secs() * 1000000 + (delay.subsec_nanos() / 1000) as u64;
+  time::now_micros().saturating_add(delay)
+}
+
+impl Core {
+  /// Connect to the core service without asking to receive any messages.
+  pub fn init(cfg: &Cfg) -> Result<Core, InitError> {
+    Core::init_with_types(cfg, &[])
+  }
+
+  /// Connect to the core service, asking it to pass on any messages of the types in
+  /// `message_types` that other peers send us.
+  pub fn init_with_types(cfg: &Cfg, message_types: &[u16]) -> Result<Core, InitError> {
+    let msg_length = 8 + 2 * message_types.len();
+    if msg_length > 0xffff {
+      return Err(InitError::TooManyTypes { count: message_types.len() });
+    }
+    let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "core"));
+    {
+      let mut mw = service_writer.write_message(msg_length as u16, ll::GNUNET_MESSAGE_TYPE_CORE_INIT);
+      mw.write_u32::<BigEndian>(0).unwrap(); // options
+      for &ty in message_types.iter() {
+        mw.write_u16::<BigEndian>(ty).unwrap();
+      }
+      try!(mw.send());
+    };
+    let (tpe, mut mr) = try!(service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_CORE_INIT_REPLY {
+      return Err(InitError::UnexpectedMessageType { ty: tpe });
+    }
+    let _reserved = try!(mr.read_u32::<BigEndian>());
+    let my_identity = try!(PeerIdentity::deserialize(&mut mr));
+    Ok(Core {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      my_identity: my_identity,
+      connected: HashSet::new(),
+      pending: VecDeque::new(),
+      next_request_id: 0,
+      disconnected: false,
+    })
+  }
+
+  /// The identity of our own peer, as reported by the service.
+  pub fn my_identity(&self) -> &PeerIdentity {
+    &self.my_identity
+  }
+
+  /// Returns `true` if we have been told that `peer` is connected.
+  pub fn is_connected(&self, peer: &PeerIdentity) -> bool {
+    self.connected.contains(peer)
+  }
+
+  /// Wait for the next event.
+  pub fn next_event(&mut self) -> Result<CoreEvent, NextEventError> {
+    match self.pending.pop_front() {
+      Some(event) => Ok(event),
+      None        => self.read_event(),
+    }
+  }
+
+  /// Send a message of type `message_type` with body `data` to `peer`.
+  ///
+  /// This asks the service for room to send the message, waits until it is ready to take it and
+  /// then hands the message over. If the service can't send the message within `max_delay` it
+  /// drops it, and this fails with `Timeout`. Any events which arrive while waiting are kept and
+  /// returned by later calls to `next_event`.
+  ///
+  /// Fails with `NotConnected` unless a `Connected` event for `peer` has been received.
+  pub fn send(&mut self, peer: &PeerIdentity, message_type: u16, data: &[u8], priority: Priority, max_delay: Duration) -> Result<(), SendError> {
+    if !self.connected.contains(peer) {
+      return Err(SendError::NotConnected);
+    }
+    let inner_len = 4 + data.len();
+    if SEND_HEADER_LEN + inner_len > 0xffff {
+      return Err(SendError::MessageTooLarge { len: data.len() });
+    }
+    let request_id = self.next_request_id;
+    self.next_request_id = self.next_request_id.wrapping_add(1);
+    let deadline = deadline_micros(max_delay);
+
+    {
+      let mut mw = self.service_writer.write_message(SEND_REQUEST_LEN, ll::GNUNET_MESSAGE_TYPE_CORE_SEND_REQUEST);
+      mw.write_u32::<BigEndian>(priority.to_u32()).unwrap();
+      mw.write_u64::<BigEndian>(deadline).unwrap();
+      peer.serialize(&mut mw).unwrap();
+      mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+      mw.write_u16::<BigEndian>(inner_len as u16).unwrap();
+      mw.write_u16::<BigEndian>(request_id).unwrap();
+      try!(mw.send());
+    };
+
+    try!(self.wait_for_ready(peer, request_id, Instant::now() + max_delay));
+
+    let mut mw = self.service_writer.write_message((SEND_HEADER_LEN + inner_len) as u16, ll::GNUNET_MESSAGE_TYPE_CORE_SEND);
+    mw.write_u32::<BigEndian>(priority.to_u32()).unwrap();
+    mw.write_u64::<BigEndian>(deadline).unwrap();
+    peer.serialize(&mut mw).unwrap();
+    mw.write_u32::<BigEndian>(0).unwrap(); // cork
+    mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+    mw.write_u16::<BigEndian>(inner_len as u16).unwrap();
+    mw.write_u16::<BigEndian>(message_type).unwrap();
+    mw.write_all(data).unwrap();
+    Ok(try!(mw.send()))
+  }
+
+  /// Read messages until the service says it is ready for the message we asked to send to `peer`,
+  /// queueing any events that arrive in the meantime. Gives up at `deadline`, after which the
+  /// service has dropped the request.
+  fn wait_for_ready(&mut self, peer: &PeerIdentity, request_id: u16, deadline: Instant) -> Result<(), SendError> {
+    loop {
+      let now = Instant::now();
+      if now >= deadline {
+        return Err(SendError::Timeout);
+      }
+      let (tpe, mut mr) = match try!(self.service_reader.read_message_timeout(deadline - now).map_err(NextEventError::from)) {
+        Some(msg) => msg,
+        None      => return Err(SendError::Timeout),
+      };
+      if tpe == ll::GNUNET_MESSAGE_TYPE_CORE_SEND_READY {
+        let _size = try!(mr.read_u16::<BigEndian>().map_err(NextEventError::from));
+        let smr_id = try!(mr.read_u16::<BigEndian>().map_err(NextEventError::from));
+        let ready_peer = try!(PeerIdentity::deserialize(&mut mr));
+        if smr_id == request_id && ready_peer == *peer {
+          return Ok(());
+        }
+        continue;
+      }
+      if let Some(event) = try!(self.parse_event(tpe, &mut mr)) {
+        let gone = event == CoreEvent::Disconnected(*peer);
+        self.pending.push_back(event);
+        if gone {
+          return Err(SendError::PeerDisconnected);
+        }
+      }
+    }
+  }
+
+  fn read_event(&mut self) -> Result<CoreEvent, NextEventError> {
+    loop {
+      let (tpe, mut mr) = try!(self.service_reader.read_message());
+      if let Some(event) = try!(self.parse_event(tpe, &mut mr)) {
+        return Ok(event);
+      }
+    }
+  }
+
+  /// Parse a message from the service into an event, keeping track of which peers are connected.
+  /// Returns `None` for messages which aren't events.
+  ///
+  /// In strict mode, nonzero reserved fields, trailing bytes and inbound messages whose length
+  /// doesn't match their header are rejected.
+  fn parse_event(&mut self, tpe: u16, mr: &mut Cursor<Vec<u8>>) -> Result<Option<CoreEvent>, NextEventError> {
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_CORE_NOTIFY_CONNECT => {
+        let reserved = try!(mr.read_u32::<BigEndian>());
+        try!(service::check_flags(reserved, 0));
+        let peer = try!(PeerIdentity::deserialize(mr));
+        try!(service::check_end(mr));
+        self.connected.insert(peer);
+        Ok(Some(CoreEvent::Connected(peer)))
+      },
+      ll::GNUNET_MESSAGE_TYPE_CORE_NOTIFY_DISCONNECT => {
+        let reserved = try!(mr.read_u32::<BigEndian>());
+        try!(service::check_flags(reserved, 0));
+        let peer = try!(PeerIdentity::deserialize(mr));
+        try!(service::check_end(mr));
+        self.connected.remove(&peer);
+        Ok(Some(CoreEvent::Disconnected(peer)))
+      },
+      ll::GNUNET_MESSAGE_TYPE_CORE_NOTIFY_INBOUND => {
+        let peer = try!(PeerIdentity::deserialize(mr));
+        let len = try!(mr.read_u16::<BigEndian>());
+        let message_type = try!(mr.read_u16::<BigEndian>());
+        let mut data = Vec::with_capacity((len as usize).saturating_sub(4));
+        try!(mr.read_to_end(&mut data));
+        if service::parse_mode() == service::ParseMode::Strict && len as usize != data.len() + 4 {
+          return Err(NextEventError::Io {
+            cause: io::Error::new(io::ErrorKind::InvalidData, "the inbound message length doesn't match its header"),
+          });
+        }
+        Ok(Some(CoreEvent::Message {
+          peer: peer,
+          message_type: message_type,
+          data: data,
+        }))
+      },
+      // Ignore anything else the service sends us.
+      _ => Ok(None),
+    }
+  }
+}
+
+impl Iterator for Core {
+  type Item = Result<CoreEvent, NextEventError>;
+
+  fn next(&mut self) -> Option<Result<CoreEvent, NextEventError>> {
+    if self.disconnected {
+      return None;
+    }
+    match self.next_event() {
+      Err(NextEventError::ReadMessage { .. })
+      | Err(NextEventError::Disconnected)   => {
+        self.disconnected = true;
+        Some(Err(NextEventError::Disconnected))
+      },
+      res                                   => Some(res),
+    }
+  }
+}
@@ -0,0 +1,203 @@
+use std::io;
+use byteorder::{BigEndian, ReadBytesExt};
+
+use ll;
+use Cfg;
+use PeerIdentity;
+use service::{self, ServiceReader, ServiceWriter};
+
+/// The state of the key exchange with a neighbour, as reported by a `CoreMonitor`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KxState {
+  /// No handshake yet.
+  Down,
+  /// We've sent our session key.
+  KeySent,
+  /// We've received the other peer's session key.
+  KeyReceived,
+  /// The session keys have been exchanged and the connection is up.
+  Up,
+  /// We're rekeying, or had a timeout.
+  RekeySent,
+  /// The last state of a neighbour before it is forgotten.
+  PeerDisconnect,
+}
+
+impl KxState {
+  /// Create a `KxState` from the code sent by the service.
+  pub fn from_u32(x: u32) -> Option<KxState> {
+    Some(match x {
+      0 => KxState::Down,
+      1 => KxState::KeySent,
+      2 => KxState::KeyReceived,
+      3 => KxState::Up,
+      4 => KxState::RekeySent,
+      5 => KxState::PeerDisconnect,
+      _ => return None,
+    })
+  }
+
+  /// Returns `true` if messages can be exchanged with the neighbour.
+  pub fn is_up(&self) -> bool {
+    *self == KxState::Up || *self == KxState::RekeySent
+  }
+}
+
+/// Sent by the service once it has reported every neighbour it knew about when the monitor started.
+const KX_ITERATION_FINISHED: u32 = 6;
+/// Sent by the service if it is shutting down.
+const KX_CORE_DISCONNECT: u32 = 7;
+
+/// The state of our connection to a neighbour.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NeighbourState {
+  /// The neighbour.
+  pub peer: PeerIdentity,
+  /// The state of the key exchange with the neighbour.
+  pub state: KxState,
+  /// When the current state times out, in microseconds since the epoch.
+  pub timeout: u64,
+}
+
+/// An event reported by a `CoreMonitor`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MonitorEvent {
+  /// The state of a neighbour has changed.
+  Neighbour(NeighbourState),
+  /// Every neighbour the service knew about when the monitor started has been reported. Later
+  /// events are changes.
+  IterationFinished,
+}
+
+/// Errors returned by `CoreMonitor::start`.
+error_def! MonitorStartError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the core service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the core service" ("Specifically: {}", cause),
+}
+
+/// Errors returned when iterating over a `CoreMonitor`.
+error_def! MonitorError {
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the core service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  InvalidState { state: u32 }
+    => "The service sent an invalid key exchange state" ("State code: {}", state),
+  ServiceShutdown
+    => "The core service is shutting down",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {MonitorError}
+
+/// A subscription to the key exchange state of each of our neighbours. This is what
+/// `gnunet-core -m` shows.
+///
+/// The service first reports every neighbour it knows about followed by `IterationFinished`, then
+/// reports each change. Events are received by iterating over the monitor, which blocks until the
+/// next event arrives. The iteration ends after a `ServiceShutdown` or `Disconnected` error.
+pub struct CoreMonitor {
+  service_reader: ServiceReader,
+  _service_writer: ServiceWriter,
+  finished: bool,
+}
+
+impl CoreMonitor {
+  /// Start monitoring our neighbours.
+  pub fn start(cfg: &Cfg) -> Result<CoreMonitor, MonitorStartError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "core"));
+    {
+      let mw = service_writer.write_message(4, ll::GNUNET_MESSAGE_TYPE_CORE_MONITOR_PEERS);
+      try!(mw.send());
+    };
+    Ok(CoreMonitor {
+      service_reader: service_reader,
+      _service_writer: service_writer,
+      finished: false,
+    })
+  }
+
+  fn read_event(&mut self) -> Result<MonitorEvent, MonitorError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_CORE_MONITOR_NOTIFY {
+      return Err(MonitorError::UnexpectedMessageType { ty: tpe });
+    }
+    let state = try!(mr.read_u32::<BigEndian>());
+    let peer = try!(PeerIdentity::deserialize(&mut mr));
+    let timeout = try!(mr.read_u64::<BigEndian>());
+    match state {
+      KX_ITERATION_FINISHED => return Ok(MonitorEvent::IterationFinished),
+      KX_CORE_DISCONNECT    => return Err(MonitorError::ServiceShutdown),
+      _                     => (),
+    };
+    let state = match KxState::from_u32(state) {
+      Some(s) => s,
+      None    => return Err(MonitorError::InvalidState { state: state }),
+    };
+    Ok(MonitorEvent::Neighbour(NeighbourState {
+      peer: peer,
+      state: state,
+      timeout: timeout,
+    }))
+  }
+}
+
+impl Iterator for CoreMonitor {
+  type Item = Result<MonitorEvent, MonitorError>;
+
+  fn next(&mut self) -> Option<Result<MonitorEvent, MonitorError>> {
+    if self.finished {
+      return None;
+    }
+    match self.read_event() {
+      Err(MonitorError::ReadMessage { .. })
+      | Err(MonitorError::Disconnected)     => {
+        self.finished = true;
+        Some(Err(MonitorError::Disconnected))
+      },
+      Err(MonitorError::ServiceShutdown)    => {
+        self.finished = true;
+        Some(Err(MonitorError::ServiceShutdown))
+      },
+      res                                   => Some(res),
+    }
+  }
+}
+
+/// Errors returned by `neighbours`.
+error_def! NeighboursError {
+  Start { #[from] cause: MonitorStartError }
+    => "Failed to start monitoring the core service" ("Reason: {}", cause),
+  Monitor { #[from] cause: MonitorError }
+    => "Failed to read the state of our neighbours" ("Reason: {}", cause),
+}
+
+/// Get the current state of each of our neighbours.
+pub fn neighbours(cfg: &Cfg) -> Result<Vec<NeighbourState>, NeighboursError> {
+  let mut monitor = try!(CoreMonitor::start(cfg));
+  let mut ret = Vec::new();
+  loop {
+    match try!(monitor.read_event()) {
+      MonitorEvent::Neighbour(n)      => ret.push(n),
+      MonitorEvent::IterationFinished => return Ok(ret),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_kx_state_codes() {
+    assert_eq!(KxState::from_u32(3), Some(KxState::Up));
+    assert_eq!(KxState::from_u32(5), Some(KxState::PeerDisconnect));
+    assert_eq!(KxState::from_u32(6), None);
+    assert!(KxState::RekeySent.is_up());
+    assert!(!KxState::KeySent.is_up());
+  }
+}
@@ -0,0 +1,292 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Weak, Mutex, Condvar};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use Cfg;
+use PeerIdentity;
+use core::{CoreMonitor, MonitorEvent, MonitorStartError};
+
+/// A change to the set of peers we are connected to, reported by `ConnectedPeers`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PeerEvent {
+  /// We have been connected to the peer for at least the debounce interval.
+  Joined(PeerIdentity),
+  /// We have been disconnected from the peer for at least the debounce interval.
+  Left(PeerIdentity),
+}
+
+/// The set of connected peers, shared between a `ConnectedPeers` and its monitor thread.
+struct PeersState {
+  debounce: Duration,
+  connected: HashSet<PeerIdentity>,
+  /// Peers whose connectivity differs from `connected`, with the state they are in now and when
+  /// they entered it.
+  pending: HashMap<PeerIdentity, (bool, Instant)>,
+  events: VecDeque<PeerEvent>,
+  synced: bool,
+  running: bool,
+}
+
+impl PeersState {
+  fn new(debounce: Duration) -> PeersState {
+    PeersState {
+      debounce: debounce,
+      connected: HashSet::new(),
+      pending: HashMap::new(),
+      events: VecDeque::new(),
+      synced: false,
+      running: true,
+    }
+  }
+
+  fn apply(&mut self, event: MonitorEvent, now: Instant) {
+    let n = match event {
+      MonitorEvent::Neighbour(n)      => n,
+      MonitorEvent::IterationFinished => {
+        self.synced = true;
+        return;
+      },
+    };
+    let up = n.state.is_up();
+    // Peers reported before the iteration finishes were already connected when we started, so
+    // there is nothing to debounce.
+    if !self.synced {
+      match up {
+        true  => self.connected.insert(n.peer),
+        false => self.connected.remove(&n.peer),
+      };
+      return;
+    }
+    if up == self.connected.contains(&n.peer) {
+      self.pending.remove(&n.peer);
+      return;
+    }
+    let unchanged = match self.pending.get(&n.peer) {
+      Some(&(pending_up, _)) => pending_up == up,
+      None                   => false,
+    };
+    if !unchanged {
+      self.pending.insert(n.peer, (up, now));
+    }
+  }
+
+  /// Move the peers which have been in their new state for the debounce interval into
+  /// `connected`, queueing an event for each.
+  fn settle(&mut self, now: Instant) {
+    let debounce = self.debounce;
+    let mut ready: Vec<(Instant, PeerIdentity, bool)> = self.pending.iter()
+      .filter(|&(_, &(_, since))| since + debounce <= now)
+      .map(|(peer, &(up, since))| (since, *peer, up))
+      .collect();
+    ready.sort_by(|a, b| a.0.cmp(&b.0));
+    for (_, peer, up) in ready {
+      self.pending.remove(&peer);
+      if up {
+        self.connected.insert(peer);
+        self.events.push_back(PeerEvent::Joined(peer));
+      }
+      else {
+        self.connected.remove(&peer);
+        self.events.push_back(PeerEvent::Left(peer));
+      }
+    }
+  }
+
+  /// When the next pending change will have lasted the debounce interval.
+  fn next_deadline(&self) -> Option<Instant> {
+    self.pending.values().map(|&(_, since)| since + self.debounce).min()
+  }
+}
+
+struct Shared {
+  state: Mutex<PeersState>,
+  cond: Condvar,
+}
+
+/// The set of peers we are connected to, kept up to date by a `CoreMonitor`.
+///
+/// A connection only counts as made or lost once it has stayed that way for the debounce
+/// interval, so a peer which briefly drops and reconnects produces no events and never leaves the
+/// set. Changes are reported as `PeerEvent`s by `next_event`, and the current set is available
+/// from `snapshot`.
+///
+/// The peers we were connected to when the watcher started are in the set from the beginning and
+/// get no `Joined` event. If the connection to the core service is lost the set stops updating
+/// and `is_running` returns `false`.
+///
+/// The background thread notices that the watcher has been dropped the next time a neighbour
+/// changes state, and exits then.
+pub struct ConnectedPeers {
+  shared: Arc<Shared>,
+}
+
+impl ConnectedPeers {
+  /// Start watching our connections, treating a change as real once it has lasted `debounce`.
+  pub fn start(cfg: &Cfg, debounce: Duration) -> Result<ConnectedPeers, MonitorStartError> {
+    let monitor = try!(CoreMonitor::start(cfg));
+    let shared = Arc::new(Shared {
+      state: Mutex::new(PeersState::new(debounce)),
+      cond: Condvar::new(),
+    });
+    let weak = Arc::downgrade(&shared);
+    try!(thread::Builder::new().name("gnunet connected peers".to_string()).spawn(move || {
+      run_monitor(monitor, weak)
+    }));
+    Ok(ConnectedPeers {
+      shared: shared,
+    })
+  }
+
+  /// Block until the peers we were connected to when the watcher started have all been reported.
+  /// Returns `false` if the monitor stopped before this happened.
+  pub fn wait_for_sync(&self) -> bool {
+    let mut state = self.shared.state.lock().unwrap();
+    while !state.synced && state.running {
+      state = self.shared.cond.wait(state).unwrap();
+    }
+    state.synced
+  }
+
+  /// Returns `true` if the watcher is still receiving changes from the core service.
+  pub fn is_running(&self) -> bool {
+    self.shared.state.lock().unwrap().running
+  }
+
+  /// Returns `true` if we are connected to `peer`.
+  pub fn is_connected(&self, peer: &PeerIdentity) -> bool {
+    let mut state = self.shared.state.lock().unwrap();
+    state.settle(Instant::now());
+    state.connected.contains(peer)
+  }
+
+  /// The number of peers we are connected to.
+  pub fn len(&self) -> usize {
+    let mut state = self.shared.state.lock().unwrap();
+    state.settle(Instant::now());
+    state.connected.len()
+  }
+
+  /// Returns `true` if we are not connected to any peers.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// A copy of the set of peers we are connected to.
+  pub fn snapshot(&self) -> HashSet<PeerIdentity> {
+    let mut state = self.shared.state.lock().unwrap();
+    state.settle(Instant::now());
+    state.connected.clone()
+  }
+
+  /// Wait for the next change to the set of connected peers.
+  ///
+  /// Waits for at most `timeout`, or indefinitely if it is `None`. Returns `None` if the timeout
+  /// passes, or if the monitor has stopped and there are no more changes to report.
+  pub fn next_event(&self, timeout: Option<Duration>) -> Option<PeerEvent> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut state = self.shared.state.lock().unwrap();
+    loop {
+      let now = Instant::now();
+      state.settle(now);
+      if let Some(event) = state.events.pop_front() {
+        return Some(event);
+      }
+      if !state.running && state.pending.is_empty() {
+        return None;
+      }
+      let wake = match (state.next_deadline(), deadline) {
+        (Some(a), Some(b))  => Some(cmp::min(a, b)),
+        (a, None)           => a,
+        (None, b)           => b,
+      };
+      state = match wake {
+        Some(wake) => {
+          if let Some(deadline) = deadline {
+            if deadline <= now {
+              return None;
+            }
+          }
+          let wait = match wake > now {
+            true  => wake - now,
+            false => Duration::from_millis(0),
+          };
+          self.shared.cond.wait_timeout(state, wait).unwrap().0
+        },
+        None => self.shared.cond.wait(state).unwrap(),
+      };
+    }
+  }
+}
+
+/// Apply the events from `monitor` to the watcher until either the monitor fails or the watcher
+/// is dropped.
+fn run_monitor(monitor: CoreMonitor, shared: Weak<Shared>) {
+  for res in monitor {
+    let shared = match shared.upgrade() {
+      Some(s) => s,
+      None    => return,
+    };
+    let mut state = shared.state.lock().unwrap();
+    match res {
+      Ok(event) => state.apply(event, Instant::now()),
+      Err(_)    => {
+        state.running = false;
+        shared.cond.notify_all();
+        return;
+      },
+    };
+    shared.cond.notify_all();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+  use std::time::{Duration, Instant};
+  use PeerIdentity;
+  use core::{KxState, MonitorEvent, NeighbourState};
+  use super::{PeerEvent, PeersState};
+
+  fn neighbour(peer: PeerIdentity, state: KxState) -> MonitorEvent {
+    MonitorEvent::Neighbour(NeighbourState {
+      peer: peer,
+      state: state,
+      timeout: 0,
+    })
+  }
+
+  #[test]
+  fn test_peers_state() {
+    let a = PeerIdentity::from_str("AK55QA8J1A164MB08VM209KE93M9JBB07M2VB8M3M03FKRFSV0MG").unwrap();
+    let b = PeerIdentity::from_str("BK55QA8J1A164MB08VM209KE93M9JBB07M2VB8M3M03FKRFSV0MG").unwrap();
+    let t0 = Instant::now();
+    let ms = |n| t0 + Duration::from_millis(n);
+    let mut state = PeersState::new(Duration::from_millis(100));
+
+    // Peers reported during the initial iteration are connected straight away.
+    state.apply(neighbour(a, KxState::Up), t0);
+    state.apply(MonitorEvent::IterationFinished, t0);
+    assert!(state.synced && state.connected.contains(&a));
+    assert!(state.events.is_empty());
+
+    // A flap shorter than the debounce interval goes unreported.
+    state.apply(neighbour(a, KxState::Down), ms(10));
+    state.apply(neighbour(a, KxState::Up), ms(50));
+    state.settle(ms(200));
+    assert!(state.events.is_empty() && state.pending.is_empty());
+
+    state.apply(neighbour(b, KxState::KeySent), ms(200));
+    state.apply(neighbour(b, KxState::Up), ms(210));
+    state.apply(neighbour(a, KxState::PeerDisconnect), ms(220));
+    assert_eq!(state.next_deadline(), Some(ms(310)));
+    state.settle(ms(309));
+    assert!(state.events.is_empty());
+    state.settle(ms(320));
+    assert_eq!(state.events.pop_front(), Some(PeerEvent::Joined(b)));
+    assert_eq!(state.events.pop_front(), Some(PeerEvent::Left(a)));
+    assert!(state.connected.contains(&b) && !state.connected.contains(&a));
+    assert_eq!(state.next_deadline(), None);
+  }
+}
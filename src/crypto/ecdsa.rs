@@ -1,13 +1,15 @@
 use std::str::FromStr;
 use std::mem;
 use std::fmt::{self, Debug, Formatter};
-use std::mem::{uninitialized, size_of, size_of_val};
-use std::str::from_utf8;
+use std::hash;
+use std::mem::{uninitialized, size_of};
 use std::slice::from_raw_parts;
 use std::io::{self, Read, Write};
-use libc::{c_void, size_t, c_char};
+use std::ffi::CString;
+use libc::{c_void, size_t};
 
 use ll;
+use data;
 use crypto::hashcode::HashCode;
 
 /// A 256bit ECDSA public key.
@@ -16,12 +18,76 @@ pub struct EcdsaPublicKey {
   data: ll::Struct_GNUNET_CRYPTO_EcdsaPublicKey,
 }
 
+impl PartialEq for EcdsaPublicKey {
+  fn eq(&self, other: &EcdsaPublicKey) -> bool {
+    self.data.q_y == other.data.q_y
+  }
+}
+
+impl Eq for EcdsaPublicKey {}
+
+impl hash::Hash for EcdsaPublicKey {
+  fn hash<H>(&self, state: &mut H)
+      where H: hash::Hasher
+  {
+    self.data.q_y.hash(state)
+  }
+}
+
 impl EcdsaPublicKey {
   /// Serialize key to a byte stream.
   pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
     w.write_all(&self.data.q_y)
   }
 
+  /// Deserialize a key from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<EcdsaPublicKey, io::Error> where T: Read {
+    let mut ret: EcdsaPublicKey = unsafe { uninitialized() };
+    try!(r.read_exact(&mut ret.data.q_y[..]));
+    Ok(ret)
+  }
+
+  /// Verify a signature made with the private key corresponding to this public key.
+  ///
+  /// `signed` is the signed data, starting with the 8 byte signature purpose header (size and
+  /// purpose, both big-endian). Returns `false` if the header doesn't match `purpose` and the
+  /// length of `signed` or if the signature is invalid.
+  pub fn verify(&self, purpose: u32, signed: &[u8], sig: &EcdsaSignature) -> bool {
+    if signed.len() < 8 {
+      return false;
+    }
+    let size = ((signed[0] as usize) << 24) | ((signed[1] as usize) << 16) | ((signed[2] as usize) << 8) | (signed[3] as usize);
+    if size != signed.len() {
+      return false;
+    }
+    unsafe {
+      let res = ll::GNUNET_CRYPTO_ecdsa_verify(purpose,
+                                               signed.as_ptr() as *const ll::Struct_GNUNET_CRYPTO_EccSignaturePurpose,
+                                               &sig.data,
+                                               &self.data);
+      res == ll::GNUNET_OK
+    }
+  }
+
+  /// Derive a public key from this key, a label and a context string.
+  ///
+  /// The derived key corresponds to the private key derived from the same label and context.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `label` or `context` contain a nul byte.
+  pub fn derive(&self, label: &str, context: &str) -> EcdsaPublicKey {
+    let label = CString::new(label).unwrap();
+    let context = CString::new(context).unwrap();
+    unsafe {
+      let mut ret: ll::Struct_GNUNET_CRYPTO_EcdsaPublicKey = uninitialized();
+      ll::GNUNET_CRYPTO_ecdsa_public_key_derive(&self.data, label.as_ptr(), context.as_ptr(), &mut ret);
+      EcdsaPublicKey {
+        data: ret,
+      }
+    }
+  }
+
   /// Compute the hash of this key.
   pub fn hash(&self) -> HashCode {
     unsafe {
@@ -59,17 +125,7 @@ impl FromStr for EcdsaPublicKey {
 
 impl Debug for EcdsaPublicKey {
   fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-    unsafe {
-      const LEN: usize = 52usize;
-      assert!(LEN == (size_of_val(&self.data.q_y) * 8 + 4) / 5);
-      let mut enc: [u8; LEN] = uninitialized();
-      let res = ll::GNUNET_STRINGS_data_to_string(self.data.q_y.as_ptr() as *const c_void,
-                                                  self.data.q_y.len() as size_t,
-                                                  enc.as_mut_ptr() as *mut c_char,
-                                                  enc.len() as size_t);
-      assert!(!res.is_null());
-      fmt::Display::fmt(from_utf8(&enc).unwrap(), f)
-    }
+    data::crockford_encode_fmt(f, &self.data.q_y[..])
   }
 }
 
@@ -79,6 +135,43 @@ impl fmt::Display for EcdsaPublicKey {
   }
 }
 
+/// An ECDSA signature.
+#[derive(Copy, Clone)]
+pub struct EcdsaSignature {
+  data: ll::Struct_GNUNET_CRYPTO_EcdsaSignature,
+}
+
+impl PartialEq for EcdsaSignature {
+  fn eq(&self, other: &EcdsaSignature) -> bool {
+    self.data.r == other.data.r && self.data.s == other.data.s
+  }
+}
+
+impl Eq for EcdsaSignature {}
+
+impl Debug for EcdsaSignature {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    try!(data::crockford_encode_fmt(f, &self.data.r[..]));
+    data::crockford_encode_fmt(f, &self.data.s[..])
+  }
+}
+
+impl EcdsaSignature {
+  /// Serialize a signature to a byte stream.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    try!(w.write_all(&self.data.r));
+    w.write_all(&self.data.s)
+  }
+
+  /// Deserialize a signature from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<EcdsaSignature, io::Error> where T: Read {
+    let mut ret: EcdsaSignature = unsafe { uninitialized() };
+    try!(r.read_exact(&mut ret.data.r[..]));
+    try!(r.read_exact(&mut ret.data.s[..]));
+    Ok(ret)
+  }
+}
+
 /// A 256bit ECDSA private key.
 #[derive(Copy)]
 pub struct EcdsaPrivateKey {
@@ -86,6 +179,18 @@ pub struct EcdsaPrivateKey {
 }
 
 impl EcdsaPrivateKey {
+  /// Generate a new random key.
+  pub fn generate() -> EcdsaPrivateKey {
+    unsafe {
+      let created = ll::GNUNET_CRYPTO_ecdsa_key_create();
+      let ret = EcdsaPrivateKey {
+        data: *created,
+      };
+      ll::GNUNET_xfree_(created as *mut c_void, b"ecdsa.rs\0".as_ptr() as *const i8, line!() as i32);
+      ret
+    }
+  }
+
   /// Serialize this key to a byte stream.
   pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
     w.write_all(&self.data.d)
@@ -109,6 +214,51 @@ impl EcdsaPrivateKey {
     }
   }
 
+  /// Sign data with this key.
+  ///
+  /// `signed` is the data to sign, starting with the 8 byte signature purpose header (size and
+  /// purpose, both big-endian). The signature can be checked with `EcdsaPublicKey::verify`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the size in the header doesn't match the length of `signed`.
+  pub fn sign(&self, signed: &[u8]) -> EcdsaSignature {
+    assert!(signed.len() >= 8);
+    let size = ((signed[0] as usize) << 24) | ((signed[1] as usize) << 16) | ((signed[2] as usize) << 8) | (signed[3] as usize);
+    assert_eq!(size, signed.len());
+    unsafe {
+      let mut ret: ll::Struct_GNUNET_CRYPTO_EcdsaSignature = uninitialized();
+      let res = ll::GNUNET_CRYPTO_ecdsa_sign(&self.data,
+                                             signed.as_ptr() as *const ll::Struct_GNUNET_CRYPTO_EccSignaturePurpose,
+                                             &mut ret);
+      assert_eq!(res, ll::GNUNET_OK);
+      EcdsaSignature {
+        data: ret,
+      }
+    }
+  }
+
+  /// Derive a private key from this key, a label and a context.
+  ///
+  /// The public key of the result is the key `EcdsaPublicKey::derive` derives from this key's
+  /// public key with the same label and context.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `label` or `context` contain a nul byte.
+  pub fn derive(&self, label: &str, context: &str) -> EcdsaPrivateKey {
+    let label = CString::new(label).unwrap();
+    let context = CString::new(context).unwrap();
+    unsafe {
+      let derived = ll::GNUNET_CRYPTO_ecdsa_private_key_derive(&self.data, label.as_ptr(), context.as_ptr());
+      let ret = EcdsaPrivateKey {
+        data: *derived,
+      };
+      ll::GNUNET_xfree_(derived as *mut c_void, b"ecdsa.rs\0".as_ptr() as *const i8, line!() as i32);
+      ret
+    }
+  }
+
   /// Return the private key of the global, anonymous user.
   pub fn anonymous() -> EcdsaPrivateKey {
     //let anon = ll::GNUNET_CRYPTO_ecdsa_key_get_anonymous();
@@ -162,3 +312,10 @@ fn test_ecdsa_to_from_string() {
   assert!(s0 == &s1[..]);
 }
 
+#[test]
+fn test_ecdsa_derive() {
+  let key = EcdsaPrivateKey::anonymous();
+  let derived = key.derive("keyword", "fs-ublock");
+  assert!(derived.get_public() == key.get_public().derive("keyword", "fs-ublock"));
+  assert!(derived.get_public() != key.get_public());
+}
@@ -0,0 +1,76 @@
+use std::mem::uninitialized;
+use std::io::{self, Cursor, Read, Write};
+use libc::c_void;
+
+use ll;
+use crypto::hashcode::HashCode;
+use PeerIdentity;
+
+/// A 256bit EdDSA private key, as used for peer identities.
+#[derive(Copy)]
+pub struct EddsaPrivateKey {
+  data: ll::Struct_GNUNET_CRYPTO_EddsaPrivateKey,
+}
+
+impl EddsaPrivateKey {
+  /// Generate a new random key.
+  pub fn generate() -> EddsaPrivateKey {
+    unsafe {
+      let created = ll::GNUNET_CRYPTO_eddsa_key_create();
+      let ret = EddsaPrivateKey {
+        data: *created,
+      };
+      ll::GNUNET_xfree_(created as *mut c_void, b"eddsa.rs\0".as_ptr() as *const i8, line!() as i32);
+      ret
+    }
+  }
+
+  /// Derive a key from `seed`. The same seed always gives the same key, which lets tests create
+  /// peers with known identities. Keys made this way are only as secret as the seed.
+  pub fn from_seed(seed: &[u8]) -> EddsaPrivateKey {
+    let hash = HashCode::from_buffer(seed);
+    let mut ret: EddsaPrivateKey = unsafe { uninitialized() };
+    ret.data.d.clone_from_slice(&hash.as_slice()[..32]);
+    ret
+  }
+
+  /// Serialize this key to a byte stream.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    w.write_all(&self.data.d)
+  }
+
+  /// Deserialize a key from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<EddsaPrivateKey, io::Error> where T: Read {
+    let mut ret: EddsaPrivateKey = unsafe { uninitialized() };
+    try!(r.read_exact(&mut ret.data.d[..]));
+    Ok(ret)
+  }
+
+  /// The identity of a peer using this key as its hostkey.
+  pub fn peer_identity(&self) -> PeerIdentity {
+    unsafe {
+      let mut public: ll::Struct_GNUNET_CRYPTO_EddsaPublicKey = uninitialized();
+      ll::GNUNET_CRYPTO_eddsa_key_get_public(&self.data, &mut public);
+      PeerIdentity::deserialize(&mut Cursor::new(&public.q_y[..])).unwrap()
+    }
+  }
+}
+
+impl Clone for EddsaPrivateKey {
+  fn clone(&self) -> EddsaPrivateKey {
+    EddsaPrivateKey {
+      data: ll::Struct_GNUNET_CRYPTO_EddsaPrivateKey {
+        d: self.data.d,
+      },
+    }
+  }
+}
+
+#[test]
+fn test_eddsa_from_seed() {
+  let a = EddsaPrivateKey::from_seed(b"peer 1");
+  let b = EddsaPrivateKey::from_seed(b"peer 1");
+  let c = EddsaPrivateKey::from_seed(b"peer 2");
+  assert!(a.peer_identity() == b.peer_identity());
+  assert!(a.peer_identity() != c.peer_identity());
+}
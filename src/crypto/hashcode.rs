@@ -4,6 +4,7 @@ use std::fmt;
 use std::slice;
 use std::mem;
 use std::hash;
+use std::io::{self, Read, Write};
 use std::str::FromStr;
 use std::ops::{Add, Sub, BitXor};
 use rcrypto::sha2::Sha512;
@@ -33,6 +34,20 @@ impl HashCode {
     }
   }
 
+  /// Serialize a HashCode to a byte stream.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    w.write_all(self.as_slice())
+  }
+
+  /// Deserialize a HashCode from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<HashCode, io::Error> where T: Read {
+    let mut ret = HashCode {
+      data: unsafe { mem::uninitialized() },
+    };
+    try!(r.read_exact(ret.as_mut_slice()));
+    Ok(ret)
+  }
+
   /// Create a HashCode by computing the sha512 hash of a buffer.
   pub fn from_buffer(buf: &[u8]) -> HashCode {
     let mut ret = HashCode {
@@ -44,6 +59,24 @@ impl HashCode {
     ret
   }
 
+  /// Create a HashCode by computing the sha512 hash of everything read from `r`.
+  pub fn from_reader<T>(r: &mut T) -> Result<HashCode, io::Error> where T: Read {
+    let mut ret = HashCode {
+      data: unsafe { mem::uninitialized() },
+    };
+    let mut hasher = Sha512::new();
+    let mut buf = [0u8; 4096];
+    loop {
+      let n = try!(r.read(&mut buf));
+      if n == 0 {
+        break;
+      }
+      hasher.input(&buf[..n]);
+    }
+    hasher.result(ret.as_mut_slice());
+    Ok(ret)
+  }
+
   /// Compute the distance between two hashes.
   pub fn distance(&self, other: &HashCode) -> u32 {
     let a1 = Wrapping(self.data[1]);
@@ -217,3 +250,9 @@ fn test_hashcode_rand_add_sub() {
   assert!(sum == h1);
 }
 
+#[test]
+fn test_hashcode_from_reader() {
+  let data = vec![7u8; 10000];
+  let hc = HashCode::from_reader(&mut io::Cursor::new(&data[..])).unwrap();
+  assert!(hc == HashCode::from_buffer(&data[..]));
+}
@@ -1,7 +1,10 @@
 pub use self::ecdsa::EcdsaPublicKey;
 pub use self::ecdsa::EcdsaPrivateKey;
+pub use self::ecdsa::EcdsaSignature;
+pub use self::eddsa::EddsaPrivateKey;
 pub use self::hashcode::HashCode;
 
 pub mod ecdsa;
+pub mod eddsa;
 pub mod hashcode;
 
@@ -1,9 +1,11 @@
 use std::fmt::{self, Write};
+use std::str::from_utf8;
 
-static ENCODE_CHARS: [char; 32] = ['0', '1', '2', '3', '4', '5', '6', '7',
-                                   '8', '9', 'A', 'B', 'C', 'D', 'E', 'F', 
-                                   'G', 'H', 'J', 'K', 'M', 'N', 'P', 'Q',
-                                   'R', 'S', 'T', 'V', 'W', 'X', 'Y', 'Z'];
+static ENCODE_CHARS: &'static [u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The number of bytes encoded at a time by `crockford_encode_fmt`. This is a multiple of 5 so
+/// each block encodes to a whole number of characters.
+const ENCODE_BLOCK_LEN: usize = 80;
 
 /// Used to wrap a byte slice which can then be crockford base32 encoded using `std::fmt::Display`.
 pub struct CrockfordEncode<'a>(pub &'a [u8]);
@@ -27,14 +29,32 @@ pub fn crockford_encode(buf: &[u8]) -> String {
 
 /// Encodes a byte slice to printable ascii using crockford base32 encoding and writes the encoded
 /// data to a `std::fmt::Formatter`.
+///
+/// This doesn't allocate. Data of up to 80 bytes, such as keys and hashes, is encoded on the stack
+/// in one go so the formatter's width, fill and alignment are respected.
 pub fn crockford_encode_fmt(f: &mut fmt::Formatter, buf: &[u8]) -> fmt::Result {
+  let mut enc = [0u8; ENCODE_BLOCK_LEN * 8 / 5];
+  if buf.len() <= ENCODE_BLOCK_LEN {
+    let n = encode_block(buf, &mut enc);
+    return f.pad(from_utf8(&enc[..n]).unwrap());
+  }
+  for block in buf.chunks(ENCODE_BLOCK_LEN) {
+    let n = encode_block(block, &mut enc);
+    try!(f.write_str(from_utf8(&enc[..n]).unwrap()));
+  }
+  Ok(())
+}
+
+/// Encode `buf` into `enc`, returning the number of characters written.
+fn encode_block(buf: &[u8], enc: &mut [u8]) -> usize {
+  let mut n = 0;
   let mut shift: i32 = 3;
   let mut next_char: u8 = 0;
   for b in buf.iter() {
     while shift >= 0 {
       next_char |= (*b >> shift) & 0x1f;
-      let c = ENCODE_CHARS[next_char as usize];
-      try!(fmt::Display::fmt(&c, f));
+      enc[n] = ENCODE_CHARS[next_char as usize];
+      n += 1;
       next_char = 0;
       shift -= 5;
     };
@@ -42,11 +62,10 @@ pub fn crockford_encode_fmt(f: &mut fmt::Formatter, buf: &[u8]) -> fmt::Result {
     shift += 8;
   }
   if shift > 3 {
-    let c = ENCODE_CHARS[next_char as usize];
-    try!(fmt::Display::fmt(&c, f));
+    enc[n] = ENCODE_CHARS[next_char as usize];
+    n += 1;
   }
-
-  Ok(())
+  n
 }
 
 /// Errors that occur trying to decode Crockford base32 encoded data.
@@ -151,5 +170,17 @@ mod tests {
     decode_encode("ABCDEFGH", &mut buf[..5]);
     decode_encode("ABCDEFGHJ4", &mut buf[..6]);
   }
+
+  #[test]
+  fn test_encode_long_and_padded() {
+    let buf: Vec<u8> = (0..200).map(|i| i as u8).collect();
+    let s = crockford_encode(&buf[..]);
+    assert_eq!(s.len(), 320);
+    let mut dec = [0u8; 200];
+    crockford_decode(&s[..], &mut dec[..]).unwrap();
+    assert!(&dec[..] == &buf[..]);
+
+    assert_eq!(format!("{:>10}", CrockfordEncode(&buf[..4])), "   000G40R");
+  }
 }
 
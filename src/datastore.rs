@@ -0,0 +1,139 @@
+//! Module for storing blocks in the local datastore.
+//!
+//! The datastore is where a peer keeps the blocks it makes available to others, such as the
+//! blocks of the files it publishes with file-sharing.
+
+use std::io::{self, Read, Write};
+use std::u64;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num::ToPrimitive;
+
+use ll;
+use Cfg;
+use HashCode;
+use block::BlockType;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+
+/// How a block is stored. Used by `Datastore::put`.
+#[derive(Copy, Clone, Debug)]
+pub struct StoreOptions {
+  /// How important the block is. Blocks with low priority are discarded first when the
+  /// datastore is full.
+  pub priority: u32,
+  /// The anonymity level required to hand the block to other peers.
+  pub anonymity: u32,
+  /// How many peers the block should be pushed to proactively.
+  pub replication: u32,
+  /// When the block expires, in microseconds since the epoch.
+  pub expiration: u64,
+}
+
+impl Default for StoreOptions {
+  /// Priority 365, anonymity 1, replication 1 and never expiring, as `gnunet-publish` uses.
+  fn default() -> StoreOptions {
+    StoreOptions {
+      priority: 365,
+      anonymity: 1,
+      replication: 1,
+      expiration: u64::MAX,
+    }
+  }
+}
+
+/// A handle to a locally-running instance of the datastore service.
+pub struct Datastore {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+}
+
+/// Errors returned by `Datastore::put` and `Datastore::remove`.
+error_def! DatastoreError {
+  TooLong { len: usize }
+    => "The block was too large to send to the service" ("{} bytes is too long", len),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the datastore service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the datastore service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The datastore service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  Failed { message: String }
+    => "The datastore service failed to carry out the request" ("Reason: {}", message),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {DatastoreError}
+
+impl Datastore {
+  /// Connect to the datastore service.
+  pub fn connect(cfg: &Cfg) -> Result<Datastore, service::ConnectError> {
+    let (service_reader, service_writer) = try!(service::connect(cfg, "datastore"));
+    Ok(Datastore {
+      service_reader: service_reader,
+      service_writer: service_writer,
+    })
+  }
+
+  /// Send a `DataMessage` of type `tpe`.
+  fn send_data(&mut self,
+               tpe: u16,
+               key: &HashCode,
+               data: &[u8],
+               block_type: BlockType,
+               options: &StoreOptions) -> Result<(), DatastoreError> {
+    let msg_length = match (112 + data.len()).to_u16() {
+      Some(l) => l,
+      None    => return Err(DatastoreError::TooLong { len: data.len() }),
+    };
+    let mut mw = self.service_writer.write_message(msg_length, tpe);
+    mw.write_u32::<BigEndian>(0).unwrap(); // no reservation
+    mw.write_u32::<BigEndian>(data.len() as u32).unwrap();
+    mw.write_u32::<BigEndian>(block_type as u32).unwrap();
+    mw.write_u32::<BigEndian>(options.priority).unwrap();
+    mw.write_u32::<BigEndian>(options.anonymity).unwrap();
+    mw.write_u32::<BigEndian>(options.replication).unwrap();
+    mw.write_u32::<BigEndian>(0).unwrap();
+    mw.write_u64::<BigEndian>(0).unwrap(); // uid
+    mw.write_u64::<BigEndian>(options.expiration).unwrap();
+    key.serialize(&mut mw).unwrap();
+    mw.write_all(data).unwrap();
+    Ok(try!(mw.send()))
+  }
+
+  /// Wait for a `STATUS` response. Returns the status code.
+  fn read_status(&mut self) -> Result<i32, DatastoreError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_DATASTORE_STATUS {
+      return Err(DatastoreError::UnexpectedMessageType { ty: tpe });
+    }
+    let status = try!(mr.read_i32::<BigEndian>());
+    let _min_expiration = try!(mr.read_u64::<BigEndian>());
+    if status < 0 {
+      let mut msg = Vec::new();
+      try!(mr.read_to_end(&mut msg));
+      while msg.last() == Some(&0) {
+        msg.pop();
+      }
+      return Err(DatastoreError::Failed { message: String::from_utf8_lossy(&msg[..]).into_owned() });
+    }
+    Ok(status)
+  }
+
+  /// Store `data` under `key`.
+  pub fn put(&mut self, key: &HashCode, data: &[u8], block_type: BlockType, options: &StoreOptions) -> Result<(), DatastoreError> {
+    try!(self.send_data(ll::GNUNET_MESSAGE_TYPE_DATASTORE_PUT, key, data, block_type, options));
+    try!(self.read_status());
+    Ok(())
+  }
+
+  /// Remove the block `data` stored under `key`. Returns `false` if there was no such block.
+  pub fn remove(&mut self, key: &HashCode, data: &[u8]) -> Result<bool, DatastoreError> {
+    let options = StoreOptions {
+      priority: 0,
+      anonymity: 0,
+      replication: 0,
+      expiration: 0,
+    };
+    try!(self.send_data(ll::GNUNET_MESSAGE_TYPE_DATASTORE_REMOVE, key, data, BlockType::Any, &options));
+    Ok(try!(self.read_status()) == ll::GNUNET_OK)
+  }
+}
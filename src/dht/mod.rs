@@ -0,0 +1,517 @@
+//! Module for storing and retrieving data in the GNUnet distributed hash table.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver, RecvError, TryRecvError};
+use std::io::{self, Write, Cursor};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num::ToPrimitive;
+
+use ll;
+use Cfg;
+use HashCode;
+use PeerIdentity;
+use block::{BlockType, BlockContext, BlockEvaluation};
+use service::{self, CatchAll, DedupStats, Inflight, ServiceReadLoop, ServiceWriter, ProcessMessageResult, Ticket};
+use shutdown::{Component, ReadLoop};
+pub use self::monitor::*;
+pub use self::publisher::*;
+pub use self::routing::*;
+
+mod monitor;
+mod publisher;
+mod routing;
+
+/// Options for routing DHT requests.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RouteOptions {
+  /// Each peer along the way should look at the request, not only the peer closest to the key.
+  pub demultiplex_everywhere: bool,
+  /// Record the path taken by the request.
+  pub record_route: bool,
+  /// The request is looking for a peer rather than for data.
+  pub find_peer: bool,
+  /// Possible message from a bart peer.
+  pub bart: bool,
+  /// Flag given to monitors if this was the last hop of a request.
+  pub last_hop: bool,
+}
+
+impl RouteOptions {
+  /// Encode a `RouteOptions` as a u32 for transmission to the service.
+  pub fn as_u32(&self) -> u32 {
+    let mut opt_code = 0;
+    if self.demultiplex_everywhere { opt_code |= 1 };
+    if self.record_route           { opt_code |= 2 };
+    if self.find_peer              { opt_code |= 4 };
+    if self.bart                   { opt_code |= 8 };
+    if self.last_hop               { opt_code |= 16 };
+    opt_code
+  }
+}
+
+/// A result returned by a DHT GET request.
+#[derive(Clone)]
+pub struct GetResult {
+  /// When the data expires, in microseconds since the epoch.
+  pub expiration: u64,
+  /// The key the data was stored under.
+  pub key: HashCode,
+  /// The path the GET request took, if route recording was enabled.
+  pub get_path: Vec<PeerIdentity>,
+  /// The path the data took when it was stored, if route recording was enabled.
+  pub put_path: Vec<PeerIdentity>,
+  /// The type of the data. This is `BlockType::Any` if the service sent a type this library
+  /// doesn't know, see `type_number`.
+  pub block_type: BlockType,
+  /// The number of the type of the data, as sent by the service.
+  pub type_number: u32,
+  /// The data itself.
+  pub data: Vec<u8>,
+}
+
+/// What the callback loop needs to know to validate the results of a GET request.
+struct GetRequest {
+  key: HashCode,
+  block_type: BlockType,
+}
+
+/// Requests registered with the callback loop.
+enum Registration {
+  Get(u64, GetRequest),
+  Put(u64, Sender<()>),
+  /// Forget the GET request or PUT with this id, eg. because it could not be sent or nobody is
+  /// waiting for its results any more.
+  Cancel(u64),
+}
+
+/// Forgets every GET request when the callback loop exits, so that their handles see that the
+/// connection to the service was lost rather than waiting forever.
+struct ClearOnExit(Arc<Mutex<Inflight<GetResult>>>);
+
+impl Drop for ClearOnExit {
+  fn drop(&mut self) {
+    self.0.lock().unwrap().clear();
+  }
+}
+
+/// A handle to a locally-running instance of the DHT daemon.
+pub struct DHT {
+  service_writer: ServiceWriter,
+  callback_loop: ServiceReadLoop,
+  next_unique_id: u64,
+  registration_tx: Sender<Registration>,
+  inflight: Arc<Mutex<Inflight<GetResult>>>,
+}
+
+/// Errors returned by `DHT::get`.
+error_def! GetError {
+  XQueryTooLong
+    => "The extended query was too long to send to the service",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the service" ("Specifically {}", cause),
+  Disconnected
+    => "The connection to the service was lost",
+}
+
+/// Errors returned by `DHT::put`.
+error_def! PutError {
+  DataTooLong { len: usize }
+    => "The data was too long to store in the DHT" ("{} bytes is too long", len),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the service" ("Specifically {}", cause),
+  Disconnected
+    => "The connection to the service was lost",
+}
+
+/// Read a DHT `CLIENT_RESULT` message.
+fn read_result(reader: &mut Cursor<Vec<u8>>) -> Result<(u64, u32, GetResult), io::Error> {
+  let tpe = try!(reader.read_u32::<BigEndian>());
+  let put_path_length = try!(reader.read_u32::<BigEndian>());
+  let get_path_length = try!(reader.read_u32::<BigEndian>());
+  let unique_id = try!(reader.read_u64::<BigEndian>());
+  let expiration = try!(reader.read_u64::<BigEndian>());
+  let key = try!(HashCode::deserialize(reader));
+  let remaining = reader.get_ref().len() as u64 - reader.position();
+  if (put_path_length as u64 + get_path_length as u64) * 32 > remaining {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "the paths are longer than the message"));
+  }
+  let mut put_path = Vec::with_capacity(put_path_length as usize);
+  for _ in 0..put_path_length {
+    put_path.push(try!(PeerIdentity::deserialize(reader)));
+  }
+  let mut get_path = Vec::with_capacity(get_path_length as usize);
+  for _ in 0..get_path_length {
+    get_path.push(try!(PeerIdentity::deserialize(reader)));
+  }
+  let mut data = Vec::new();
+  try!(io::Read::read_to_end(reader, &mut data));
+  Ok((unique_id, tpe, GetResult {
+    expiration: expiration,
+    key: key,
+    get_path: get_path,
+    put_path: put_path,
+    block_type: BlockType::Any,
+    type_number: tpe,
+    data: data,
+  }))
+}
+
+impl DHT {
+  /// Connect to the DHT service.
+  ///
+  /// Results are validated using the plugins of `BlockContext::default()` before being delivered.
+  pub fn connect(cfg: &Cfg) -> Result<DHT, service::ConnectError> {
+    DHT::connect_with_block_context(cfg, BlockContext::default())
+  }
+
+  /// Connect to the DHT service, validating results with the plugins in `block_context`.
+  ///
+  /// Results which the context deems invalid are dropped. Results of types which the context does
+  /// not support, including types this library doesn't know, are delivered unchecked.
+  pub fn connect_with_block_context(cfg: &Cfg, block_context: BlockContext) -> Result<DHT, service::ConnectError> {
+    let (registration_tx, registration_rx) = channel::<Registration>();
+    let mut requests: HashMap<u64, GetRequest> = HashMap::new();
+    let mut puts: HashMap<u64, Sender<()>> = HashMap::new();
+    let inflight = Arc::new(Mutex::new(Inflight::new()));
+    let cb_inflight = ClearOnExit(inflight.clone());
+
+    let (service_reader, service_writer) = try!(service::connect(cfg, "dht"));
+    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
+      loop {
+        match registration_rx.try_recv() {
+          Ok(Registration::Get(id, request)) => {
+            requests.insert(id, request);
+          },
+          Ok(Registration::Put(id, sender)) => {
+            puts.insert(id, sender);
+          },
+          Ok(Registration::Cancel(id)) => {
+            requests.remove(&id);
+            puts.remove(&id);
+          },
+          Err(e)  => match e {
+            TryRecvError::Empty         => break,
+            TryRecvError::Disconnected  => return ProcessMessageResult::Shutdown,
+          },
+        }
+      }
+
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_DHT_CLIENT_RESULT => {
+          let (id, result_type, mut result) = match read_result(&mut reader) {
+            Ok(x)   => x,
+            Err(_)  => return ProcessMessageResult::Reconnect,
+          };
+          let done = match requests.get(&id) {
+            Some(request) => {
+              // Types we don't know can't be what a typed request asked for, and can't be checked.
+              let block_type = match BlockType::from_u32(result_type) {
+                Some(bt)                                     => bt,
+                None if request.block_type == BlockType::Any  => BlockType::Any,
+                None                                         => return ProcessMessageResult::Continue,
+              };
+              if request.block_type != BlockType::Any && request.block_type != block_type {
+                return ProcessMessageResult::Continue;
+              }
+              if block_type != BlockType::Any {
+                match block_context.evaluate(block_type, &request.key, &result.data[..]) {
+                  BlockEvaluation::ResultInvalid  => return ProcessMessageResult::Continue,
+                  _                               => (),
+                };
+              }
+              result.block_type = block_type;
+              let mut inflight = cb_inflight.0.lock().unwrap();
+              inflight.deliver(id, result);
+              !inflight.contains(id)
+            },
+            None => false,
+          };
+          // Nobody is waiting for the results any more.
+          if done {
+            requests.remove(&id);
+          }
+        },
+        ll::GNUNET_MESSAGE_TYPE_DHT_CLIENT_PUT_OK => {
+          let reserved = match reader.read_u32::<BigEndian>() {
+            Ok(x)   => x,
+            Err(_)  => return ProcessMessageResult::Reconnect,
+          };
+          let id = match reader.read_u64::<BigEndian>() {
+            Ok(id)  => id,
+            Err(_)  => return ProcessMessageResult::Reconnect,
+          };
+          if service::check_flags(reserved, 0).is_err() || service::check_end(&reader).is_err() {
+            return ProcessMessageResult::Reconnect;
+          }
+          if let Some(sender) = puts.remove(&id) {
+            let _ = sender.send(());
+          }
+        },
+        _ => return ProcessMessageResult::Unhandled,
+      };
+      ProcessMessageResult::Continue
+    }));
+    Ok(DHT {
+      service_writer: service_writer,
+      callback_loop: callback_loop,
+      next_unique_id: 1,
+      registration_tx: registration_tx,
+      inflight: inflight,
+    })
+  }
+
+  /// Send a message the typed API doesn't cover to the DHT service. See
+  /// `ServiceWriter::send_raw`.
+  pub fn send_raw(&mut self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    self.service_writer.send_raw(tpe, body)
+  }
+
+  /// The handler for messages from the DHT service which this handle doesn't understand. Without
+  /// a handler such messages make the handle stop receiving.
+  pub fn catch_all(&self) -> CatchAll {
+    self.callback_loop.catch_all()
+  }
+
+  /// How many GET requests made through this handle were coalesced with identical requests
+  /// already in flight.
+  pub fn dedup_stats(&self) -> DedupStats {
+    self.inflight.lock().unwrap().stats()
+  }
+
+  /// Store `data` of type `block_type` under `key`.
+  ///
+  /// `expiration` is the time the data should expire at, in microseconds since the epoch. Returns
+  /// immediately with a handle that can be used to wait for the service to confirm the request.
+  pub fn put(
+      &mut self,
+      key: &HashCode,
+      block_type: BlockType,
+      data: &[u8],
+      desired_replication_level: u32,
+      options: RouteOptions,
+      expiration: u64
+    ) -> Result<PutHandle, PutError> {
+    let msg_length = match (96 + data.len()).to_u16() {
+      Some(l) => l,
+      None    => return Err(PutError::DataTooLong { len: data.len() }),
+    };
+
+    let id = self.next_unique_id;
+    self.next_unique_id += 1;
+
+    let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_DHT_CLIENT_PUT);
+    mw.write_u32::<BigEndian>(block_type as u32).unwrap();
+    mw.write_u32::<BigEndian>(options.as_u32()).unwrap();
+    mw.write_u32::<BigEndian>(desired_replication_level).unwrap();
+    mw.write_u64::<BigEndian>(id).unwrap();
+    mw.write_u64::<BigEndian>(expiration).unwrap();
+    key.serialize(&mut mw).unwrap();
+    mw.write_all(data).unwrap();
+
+    let (tx, rx) = channel::<()>();
+    // This fails once the callback loop has exited, eg. because the service restarted.
+    if self.registration_tx.send(Registration::Put(id, tx)).is_err() {
+      return Err(PutError::Disconnected);
+    }
+    if let Err(e) = mw.send() {
+      let _ = self.registration_tx.send(Registration::Cancel(id));
+      return Err(PutError::Io { cause: e });
+    }
+    Ok(PutHandle {
+      id: id,
+      receiver: rx,
+      registration_tx: self.registration_tx.clone(),
+    })
+  }
+
+  /// Start a GET request for data of type `block_type` stored under `key`.
+  ///
+  /// Returns immediately with a handle that can be queried for results. Only results which pass
+  /// validation are delivered through the handle.
+  ///
+  /// If an identical GET made through this handle is still running, no new request is sent to the
+  /// service. The new handle is given the results received so far and then shares the results of
+  /// the running request.
+  pub fn get<'a>(
+      &'a mut self,
+      block_type: BlockType,
+      key: &HashCode,
+      desired_replication_level: u32,
+      options: RouteOptions,
+      xquery: &[u8]
+    ) -> Result<GetHandle<'a>, GetError> {
+    let msg_length = match (88 + xquery.len()).to_u16() {
+      Some(l) => l,
+      None    => return Err(GetError::XQueryTooLong),
+    };
+
+    let id = self.next_unique_id;
+    self.next_unique_id += 1;
+
+    // Identical requests are those which differ only in their id.
+    let mut request_key = Vec::with_capacity(76 + xquery.len());
+    request_key.write_u32::<BigEndian>(options.as_u32()).unwrap();
+    request_key.write_u32::<BigEndian>(desired_replication_level).unwrap();
+    request_key.write_u32::<BigEndian>(block_type as u32).unwrap();
+    key.serialize(&mut request_key).unwrap();
+    request_key.write_all(xquery).unwrap();
+
+    let (tx, rx) = channel::<GetResult>();
+    // The lock must not be held while writing to the service, or the callback loop could block on
+    // it while the service blocks on us.
+    let (ticket, send) = self.inflight.lock().unwrap().join(id, request_key, tx);
+    if send {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_DHT_CLIENT_GET);
+      mw.write_u32::<BigEndian>(options.as_u32()).unwrap();
+      mw.write_u32::<BigEndian>(desired_replication_level).unwrap();
+      mw.write_u32::<BigEndian>(block_type as u32).unwrap();
+      key.serialize(&mut mw).unwrap();
+      mw.write_u64::<BigEndian>(id).unwrap();
+      mw.write_all(xquery).unwrap();
+
+      let request = GetRequest {
+        key: key.clone(),
+        block_type: block_type,
+      };
+      // This fails once the callback loop has exited, eg. because the service restarted.
+      if self.registration_tx.send(Registration::Get(id, request)).is_err() {
+        self.inflight.lock().unwrap().finish(id);
+        return Err(GetError::Disconnected);
+      }
+      if let Err(e) = mw.send() {
+        self.inflight.lock().unwrap().finish(id);
+        let _ = self.registration_tx.send(Registration::Cancel(id));
+        return Err(GetError::Io { cause: e });
+      }
+    }
+    Ok(GetHandle {
+      dht: self,
+      key: key.clone(),
+      ticket: ticket,
+      receiver: rx,
+    })
+  }
+
+  /// Tell the service to stop the GET request `id` for `key` and forget about it.
+  fn stop_get(&mut self, id: u64, key: &HashCode) -> Result<(), io::Error> {
+    let _ = self.registration_tx.send(Registration::Cancel(id));
+    let mut mw = self.service_writer.write_message(80, ll::GNUNET_MESSAGE_TYPE_DHT_CLIENT_GET_STOP);
+    mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+    mw.write_u64::<BigEndian>(id).unwrap();
+    key.serialize(&mut mw).unwrap();
+    mw.send()
+  }
+}
+
+/// A handle returned by `DHT::get`.
+///
+/// Used to retrieve the results of a GET request. Dropping the handle stops the request unless an
+/// identical request made through the same `DHT` is still waiting for results.
+pub struct GetHandle<'a> {
+  dht: &'a mut DHT,
+  key: HashCode,
+  ticket: Ticket,
+  receiver: Receiver<GetResult>,
+}
+
+impl<'a> GetHandle<'a> {
+  /// Receive a single result from a GET request.
+  ///
+  /// Blocks until a result is available. This function can be called multiple times on a handle
+  /// to receive multiple results. Fails if the connection to the service is lost.
+  pub fn recv(&mut self) -> Result<GetResult, RecvError> {
+    self.receiver.recv()
+  }
+}
+
+impl<'a> Drop for GetHandle<'a> {
+  fn drop(&mut self) {
+    let left = self.dht.inflight.lock().unwrap().leave(self.ticket);
+    if let Some(id) = left {
+      let _ = self.dht.stop_get(id, &self.key);
+    }
+  }
+}
+
+/// A handle returned by `DHT::put`.
+///
+/// Used to wait for the service to confirm that it has processed a PUT request. The handle may
+/// outlive the `DHT`, in which case the request is reported as disconnected. Dropping the handle
+/// stops waiting for the confirmation but doesn't withdraw the request.
+pub struct PutHandle {
+  id: u64,
+  receiver: Receiver<()>,
+  registration_tx: Sender<Registration>,
+}
+
+/// The state of a PUT request, as returned by `PutHandle::poll`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PutState {
+  /// The service has not yet confirmed the request.
+  Pending,
+  /// The service has confirmed the request.
+  Confirmed,
+  /// The connection to the service was lost before the request was confirmed.
+  Disconnected,
+}
+
+impl PutHandle {
+  /// Check whether the request has been confirmed, without blocking.
+  pub fn poll(&mut self) -> PutState {
+    match self.receiver.try_recv() {
+      Ok(())                            => PutState::Confirmed,
+      Err(TryRecvError::Empty)          => PutState::Pending,
+      Err(TryRecvError::Disconnected)   => PutState::Disconnected,
+    }
+  }
+
+  /// Block until the service confirms the request. Returns `false` if the connection to the
+  /// service is lost first.
+  pub fn wait(&mut self) -> bool {
+    self.receiver.recv().is_ok()
+  }
+}
+
+impl Drop for PutHandle {
+  fn drop(&mut self) {
+    // Nobody is waiting for the confirmation any more. This fails harmlessly if the `DHT` is gone.
+    let _ = self.registration_tx.send(Registration::Cancel(self.id));
+  }
+}
+
+impl Component for DHT {
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    Some(&mut self.callback_loop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use byteorder::{BigEndian, WriteBytesExt};
+  use HashCode;
+  use super::read_result;
+
+  #[test]
+  fn test_read_result_path_lengths() {
+    let mut msg = Vec::new();
+    msg.write_u32::<BigEndian>(99).unwrap(); // a type we don't know
+    msg.write_u32::<BigEndian>(1).unwrap();
+    msg.write_u32::<BigEndian>(0xffffffff).unwrap();
+    msg.write_u64::<BigEndian>(7).unwrap();
+    msg.write_u64::<BigEndian>(0).unwrap();
+    HashCode::from_buffer(b"key").serialize(&mut msg).unwrap();
+    msg.extend_from_slice(&[0u8; 32]);
+    assert!(read_result(&mut Cursor::new(msg.clone())).is_err());
+
+    msg[11] = 0;
+    msg[10] = 0;
+    msg[9] = 0;
+    msg[8] = 0;
+    let (id, tpe, result) = read_result(&mut Cursor::new(msg)).unwrap();
+    assert_eq!((id, tpe, result.type_number), (7, 99, 99));
+    assert_eq!(result.put_path.len(), 1);
+    assert!(result.data.is_empty());
+  }
+}
@@ -0,0 +1,346 @@
+use std::io::{self, Cursor, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use HashCode;
+use PeerIdentity;
+use block::BlockType;
+use service::{self, ServiceReader, ServiceWriter};
+
+/// Chooses which DHT traffic a `DhtMonitor` is told about.
+///
+/// The filter is sent to the service, so traffic which doesn't match is normally never sent to the
+/// client. The monitor checks events against the filter as well, since the service doesn't apply
+/// every part of it to every kind of event.
+#[derive(Clone, Debug)]
+pub struct MonitorFilter {
+  /// Only report traffic for this key.
+  pub key: Option<HashCode>,
+  /// Only report traffic for this type of block. `BlockType::Any` matches every type.
+  pub block_type: BlockType,
+  /// Report GET requests.
+  pub gets: bool,
+  /// Report responses to GET requests.
+  pub get_responses: bool,
+  /// Report PUT requests.
+  pub puts: bool,
+}
+
+impl Default for MonitorFilter {
+  fn default() -> MonitorFilter {
+    MonitorFilter {
+      key: None,
+      block_type: BlockType::Any,
+      gets: true,
+      get_responses: true,
+      puts: true,
+    }
+  }
+}
+
+impl MonitorFilter {
+  /// A filter matching all traffic for `key`.
+  pub fn for_key(key: HashCode) -> MonitorFilter {
+    MonitorFilter {
+      key: Some(key),
+      ..MonitorFilter::default()
+    }
+  }
+
+  /// A filter matching all traffic for blocks of type `block_type`.
+  pub fn for_block_type(block_type: BlockType) -> MonitorFilter {
+    MonitorFilter {
+      block_type: block_type,
+      ..MonitorFilter::default()
+    }
+  }
+
+  /// Returns `true` if traffic for `key` and blocks of type `block_type` passes the key and type
+  /// parts of the filter.
+  pub fn matches(&self, key: &HashCode, block_type: u32) -> bool {
+    let type_ok = self.block_type == BlockType::Any || self.block_type as u32 == block_type;
+    let key_ok = match self.key {
+      Some(ref k) => k == key,
+      None        => true,
+    };
+    type_ok && key_ok
+  }
+
+  /// Returns `true` if `event` passes the filter.
+  pub fn matches_event(&self, event: &MonitorEvent) -> bool {
+    match *event {
+      MonitorEvent::Get(ref e)          => self.gets && self.matches(&e.key, e.block_type),
+      MonitorEvent::GetResponse(ref e)  => self.get_responses && self.matches(&e.key, e.block_type),
+      MonitorEvent::Put(ref e)          => self.puts && self.matches(&e.key, e.block_type),
+    }
+  }
+}
+
+/// A GET request passing through our peer.
+#[derive(Clone, Debug)]
+pub struct MonitorGet {
+  /// The routing options of the request. See `RouteOptions`.
+  pub options: u32,
+  /// The type of block requested.
+  pub block_type: u32,
+  /// The number of hops the request has taken.
+  pub hop_count: u32,
+  /// The replication level requested.
+  pub desired_replication_level: u32,
+  /// The key being looked up.
+  pub key: HashCode,
+  /// The path the request has taken so far, if route recording was enabled.
+  pub get_path: Vec<PeerIdentity>,
+}
+
+/// A response to a GET request passing through our peer.
+#[derive(Clone, Debug)]
+pub struct MonitorGetResponse {
+  /// The type of the block.
+  pub block_type: u32,
+  /// The path the data took when it was stored, if route recording was enabled.
+  pub put_path: Vec<PeerIdentity>,
+  /// The path the GET request took, if route recording was enabled.
+  pub get_path: Vec<PeerIdentity>,
+  /// When the data expires, in microseconds since the epoch.
+  pub expiration: u64,
+  /// The key the data is stored under.
+  pub key: HashCode,
+  /// The data itself.
+  pub data: Vec<u8>,
+}
+
+/// A PUT request passing through our peer.
+#[derive(Clone, Debug)]
+pub struct MonitorPut {
+  /// The routing options of the request. See `RouteOptions`.
+  pub options: u32,
+  /// The type of the block.
+  pub block_type: u32,
+  /// The number of hops the request has taken.
+  pub hop_count: u32,
+  /// The replication level requested.
+  pub desired_replication_level: u32,
+  /// The path the request has taken so far, if route recording was enabled.
+  pub put_path: Vec<PeerIdentity>,
+  /// When the data expires, in microseconds since the epoch.
+  pub expiration: u64,
+  /// The key the data is stored under.
+  pub key: HashCode,
+  /// The data itself.
+  pub data: Vec<u8>,
+}
+
+/// Traffic reported by a `DhtMonitor`.
+#[derive(Clone, Debug)]
+pub enum MonitorEvent {
+  /// A GET request.
+  Get(MonitorGet),
+  /// A response to a GET request.
+  GetResponse(MonitorGetResponse),
+  /// A PUT request.
+  Put(MonitorPut),
+}
+
+/// Errors returned by `DhtMonitor::start`.
+error_def! MonitorStartError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the DHT service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the DHT service" ("Specifically: {}", cause),
+}
+
+/// Errors returned when iterating over a `DhtMonitor`.
+error_def! MonitorError {
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the DHT service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {MonitorError}
+
+/// Read a path of `len` peers, which must fit in what is left of the message.
+fn read_path(r: &mut Cursor<Vec<u8>>, len: u32) -> Result<Vec<PeerIdentity>, io::Error> {
+  let remaining = r.get_ref().len() as u64 - r.position();
+  if len as u64 * 32 > remaining {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "the path is longer than the message"));
+  }
+  let mut path = Vec::with_capacity(len as usize);
+  for _ in 0..len {
+    path.push(try!(PeerIdentity::deserialize(r)));
+  }
+  Ok(path)
+}
+
+/// A subscription to the DHT traffic passing through our peer, as shown by `gnunet-dht-monitor`.
+///
+/// Only traffic matching the monitor's `MonitorFilter` is reported. Events are received by
+/// iterating over the monitor, which blocks until the next event arrives. If the connection to the
+/// service is lost a `Disconnected` error is returned and the iteration ends.
+pub struct DhtMonitor {
+  service_reader: ServiceReader,
+  _service_writer: ServiceWriter,
+  filter: MonitorFilter,
+  disconnected: bool,
+}
+
+impl DhtMonitor {
+  /// Start monitoring the DHT traffic matching `filter`.
+  pub fn start(cfg: &Cfg, filter: MonitorFilter) -> Result<DhtMonitor, MonitorStartError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "dht"));
+    {
+      let mut mw = service_writer.write_message(80, ll::GNUNET_MESSAGE_TYPE_DHT_MONITOR_START);
+      mw.write_u32::<BigEndian>(filter.block_type as u32).unwrap();
+      mw.write_u16::<BigEndian>(filter.gets as u16).unwrap();
+      mw.write_u16::<BigEndian>(filter.get_responses as u16).unwrap();
+      mw.write_u16::<BigEndian>(filter.puts as u16).unwrap();
+      mw.write_u16::<BigEndian>(filter.key.is_some() as u16).unwrap();
+      match filter.key {
+        Some(ref key) => key.serialize(&mut mw).unwrap(),
+        None          => mw.write_all(&[0u8; 64][..]).unwrap(),
+      };
+      try!(mw.send());
+    };
+    Ok(DhtMonitor {
+      service_reader: service_reader,
+      _service_writer: service_writer,
+      filter: filter,
+      disconnected: false,
+    })
+  }
+
+  /// The filter this monitor was started with.
+  pub fn filter(&self) -> &MonitorFilter {
+    &self.filter
+  }
+
+  fn read_event(&mut self) -> Result<MonitorEvent, MonitorError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_DHT_MONITOR_GET => {
+        let options = try!(mr.read_u32::<BigEndian>());
+        let block_type = try!(mr.read_u32::<BigEndian>());
+        let hop_count = try!(mr.read_u32::<BigEndian>());
+        let desired_replication_level = try!(mr.read_u32::<BigEndian>());
+        let get_path_length = try!(mr.read_u32::<BigEndian>());
+        let key = try!(HashCode::deserialize(&mut mr));
+        let get_path = try!(read_path(&mut mr, get_path_length));
+        Ok(MonitorEvent::Get(MonitorGet {
+          options: options,
+          block_type: block_type,
+          hop_count: hop_count,
+          desired_replication_level: desired_replication_level,
+          key: key,
+          get_path: get_path,
+        }))
+      },
+      ll::GNUNET_MESSAGE_TYPE_DHT_MONITOR_GET_RESP => {
+        let block_type = try!(mr.read_u32::<BigEndian>());
+        let put_path_length = try!(mr.read_u32::<BigEndian>());
+        let get_path_length = try!(mr.read_u32::<BigEndian>());
+        let expiration = try!(mr.read_u64::<BigEndian>());
+        let key = try!(HashCode::deserialize(&mut mr));
+        let put_path = try!(read_path(&mut mr, put_path_length));
+        let get_path = try!(read_path(&mut mr, get_path_length));
+        let mut data = Vec::new();
+        try!(mr.read_to_end(&mut data));
+        Ok(MonitorEvent::GetResponse(MonitorGetResponse {
+          block_type: block_type,
+          put_path: put_path,
+          get_path: get_path,
+          expiration: expiration,
+          key: key,
+          data: data,
+        }))
+      },
+      ll::GNUNET_MESSAGE_TYPE_DHT_MONITOR_PUT => {
+        let options = try!(mr.read_u32::<BigEndian>());
+        let block_type = try!(mr.read_u32::<BigEndian>());
+        let hop_count = try!(mr.read_u32::<BigEndian>());
+        let desired_replication_level = try!(mr.read_u32::<BigEndian>());
+        let put_path_length = try!(mr.read_u32::<BigEndian>());
+        let expiration = try!(mr.read_u64::<BigEndian>());
+        let key = try!(HashCode::deserialize(&mut mr));
+        let put_path = try!(read_path(&mut mr, put_path_length));
+        let mut data = Vec::new();
+        try!(mr.read_to_end(&mut data));
+        Ok(MonitorEvent::Put(MonitorPut {
+          options: options,
+          block_type: block_type,
+          hop_count: hop_count,
+          desired_replication_level: desired_replication_level,
+          put_path: put_path,
+          expiration: expiration,
+          key: key,
+          data: data,
+        }))
+      },
+      x => Err(MonitorError::UnexpectedMessageType { ty: x }),
+    }
+  }
+}
+
+impl Iterator for DhtMonitor {
+  type Item = Result<MonitorEvent, MonitorError>;
+
+  fn next(&mut self) -> Option<Result<MonitorEvent, MonitorError>> {
+    if self.disconnected {
+      return None;
+    }
+    loop {
+      match self.read_event() {
+        Ok(event)                           => {
+          if self.filter.matches_event(&event) {
+            return Some(Ok(event));
+          }
+        },
+        Err(MonitorError::ReadMessage { .. })
+        | Err(MonitorError::Disconnected)   => {
+          self.disconnected = true;
+          return Some(Err(MonitorError::Disconnected));
+        },
+        Err(e)                              => return Some(Err(e)),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand;
+  use HashCode;
+  use block::BlockType;
+  use super::*;
+
+  #[test]
+  fn test_monitor_filter() {
+    let key: HashCode = rand::random();
+    let other: HashCode = rand::random();
+    let gns = BlockType::GnsNameRecord as u32;
+
+    assert!(MonitorFilter::default().matches(&key, gns));
+    assert!(MonitorFilter::for_key(key.clone()).matches(&key, gns));
+    assert!(!MonitorFilter::for_key(key.clone()).matches(&other, gns));
+    assert!(MonitorFilter::for_block_type(BlockType::GnsNameRecord).matches(&other, gns));
+    assert!(!MonitorFilter::for_block_type(BlockType::FsUBlock).matches(&key, gns));
+
+    let put = MonitorEvent::Put(MonitorPut {
+      options: 0,
+      block_type: gns,
+      hop_count: 1,
+      desired_replication_level: 3,
+      put_path: Vec::new(),
+      expiration: 0,
+      key: key.clone(),
+      data: Vec::new(),
+    });
+    assert!(MonitorFilter::for_key(key.clone()).matches_event(&put));
+    assert!(!MonitorFilter::for_key(other.clone()).matches_event(&put));
+    assert!(!MonitorFilter { puts: false, ..MonitorFilter::default() }.matches_event(&put));
+  }
+}
@@ -0,0 +1,312 @@
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use Cfg;
+use HashCode;
+use block::BlockType;
+use service;
+use dht::{DHT, PutError, PutHandle, PutState, RouteOptions};
+
+/// Identifies an item queued with a `DhtPublisher`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PublishId(u64);
+
+/// The status of an item queued with a `DhtPublisher`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PublishStatus {
+  /// The item has not been sent to the service yet.
+  Queued,
+  /// The item has been sent and is waiting to be confirmed by the service.
+  InFlight { attempt: u32 },
+  /// An attempt failed. The item will be sent again once its backoff has elapsed.
+  Retrying { attempts: u32 },
+  /// The service confirmed the item.
+  Confirmed,
+  /// Every attempt failed, or the item can never be stored. The publisher has given up on it.
+  Failed { attempts: u32 },
+}
+
+impl PublishStatus {
+  /// Returns `true` if the publisher has finished with the item, successfully or not.
+  pub fn is_finished(&self) -> bool {
+    match *self {
+      PublishStatus::Confirmed | PublishStatus::Failed { .. } => true,
+      _ => false,
+    }
+  }
+}
+
+struct Item {
+  key: HashCode,
+  block_type: BlockType,
+  data: Vec<u8>,
+  desired_replication_level: u32,
+  options: RouteOptions,
+  expiration: u64,
+  status: PublishStatus,
+  attempts: u32,
+  handle: Option<PutHandle>,
+  sent_at: Instant,
+  retry_at: Instant,
+}
+
+/// The delay before retrying an item which has failed `attempts` times.
+fn backoff_delay(initial: Duration, max: Duration, attempts: u32) -> Duration {
+  let mut delay = initial;
+  for _ in 1..attempts {
+    if delay >= max {
+      break;
+    }
+    delay = delay * 2;
+  }
+  min(delay, max)
+}
+
+/// How a `DhtPublisher` retries failed items.
+#[derive(Copy, Clone)]
+struct RetryPolicy {
+  max_attempts: u32,
+  initial_backoff: Duration,
+  max_backoff: Duration,
+}
+
+/// Record that the latest attempt to publish `item` failed.
+fn fail_attempt(item: &mut Item, policy: RetryPolicy, now: Instant) {
+  item.handle = None;
+  if item.attempts >= policy.max_attempts {
+    item.status = PublishStatus::Failed { attempts: item.attempts };
+  }
+  else {
+    item.status = PublishStatus::Retrying { attempts: item.attempts };
+    item.retry_at = now + backoff_delay(policy.initial_backoff, policy.max_backoff, item.attempts);
+  }
+}
+
+/// A queue of DHT PUT requests which are sent, confirmed and retried in the background of the
+/// application's own loop.
+///
+/// Items are added with `enqueue` and sent by `poll` or `run`. An item which isn't confirmed within
+/// the confirmation timeout, or whose connection to the service is lost, is retried with
+/// exponential backoff up to a maximum number of attempts. The connection to the DHT service is
+/// re-established as needed.
+pub struct DhtPublisher {
+  dht: Option<DHT>,
+  items: BTreeMap<PublishId, Item>,
+  next_id: u64,
+  policy: RetryPolicy,
+  confirm_timeout: Duration,
+}
+
+impl DhtPublisher {
+  /// Create an empty publisher. It connects to the DHT service the first time it has something to
+  /// send.
+  ///
+  /// By default each item is attempted 5 times, waiting 30 seconds for each confirmation and
+  /// backing off from 1 second up to 5 minutes between attempts.
+  pub fn new() -> DhtPublisher {
+    DhtPublisher {
+      dht: None,
+      items: BTreeMap::new(),
+      next_id: 0,
+      policy: RetryPolicy {
+        max_attempts: 5,
+        initial_backoff: Duration::from_secs(1),
+        max_backoff: Duration::from_secs(300),
+      },
+      confirm_timeout: Duration::from_secs(30),
+    }
+  }
+
+  /// Set the number of times an item is sent before giving up on it.
+  pub fn set_max_attempts(&mut self, max_attempts: u32) {
+    self.policy.max_attempts = max_attempts;
+  }
+
+  /// Set the delay before the first retry and the maximum delay between retries.
+  pub fn set_backoff(&mut self, initial: Duration, max: Duration) {
+    self.policy.initial_backoff = initial;
+    self.policy.max_backoff = max;
+  }
+
+  /// Set how long to wait for the service to confirm an item before retrying it.
+  pub fn set_confirm_timeout(&mut self, timeout: Duration) {
+    self.confirm_timeout = timeout;
+  }
+
+  /// Queue `data` to be stored under `key`. See `DHT::put` for the meaning of the arguments.
+  pub fn enqueue(&mut self,
+                 key: &HashCode,
+                 block_type: BlockType,
+                 data: &[u8],
+                 desired_replication_level: u32,
+                 options: RouteOptions,
+                 expiration: u64) -> PublishId {
+    let id = PublishId(self.next_id);
+    self.next_id += 1;
+    let now = Instant::now();
+    self.items.insert(id, Item {
+      key: key.clone(),
+      block_type: block_type,
+      data: data.to_vec(),
+      desired_replication_level: desired_replication_level,
+      options: options,
+      expiration: expiration,
+      status: PublishStatus::Queued,
+      attempts: 0,
+      handle: None,
+      sent_at: now,
+      retry_at: now,
+    });
+    id
+  }
+
+  /// Get the status of a queued item. Returns `None` if there is no such item.
+  pub fn status(&self, id: PublishId) -> Option<PublishStatus> {
+    self.items.get(&id).map(|item| item.status)
+  }
+
+  /// The statuses of all items in the queue.
+  pub fn statuses(&self) -> Vec<(PublishId, PublishStatus)> {
+    self.items.iter().map(|(id, item)| (*id, item.status)).collect()
+  }
+
+  /// Remove an item from the queue, returning its last status. An item which is still in flight
+  /// is abandoned.
+  pub fn remove(&mut self, id: PublishId) -> Option<PublishStatus> {
+    self.items.remove(&id).map(|item| item.status)
+  }
+
+  /// Remove all finished items from the queue.
+  pub fn clear_finished(&mut self) {
+    let finished: Vec<PublishId> = self.items.iter()
+                                             .filter(|&(_, item)| item.status.is_finished())
+                                             .map(|(id, _)| *id)
+                                             .collect();
+    for id in finished {
+      self.items.remove(&id);
+    }
+  }
+
+  /// Returns `true` if every item in the queue is finished.
+  pub fn is_idle(&self) -> bool {
+    self.items.values().all(|item| item.status.is_finished())
+  }
+
+  /// Collect confirmations and send any items which are due, without blocking.
+  ///
+  /// Fails if the publisher needs to connect to the DHT service and cannot. The items stay queued
+  /// and another attempt to connect is made on the next call.
+  pub fn poll(&mut self, cfg: &Cfg) -> Result<(), service::ConnectError> {
+    let now = Instant::now();
+    let policy = self.policy;
+    let confirm_timeout = self.confirm_timeout;
+    let mut lost_connection = false;
+
+    for item in self.items.values_mut() {
+      if let PublishStatus::InFlight { .. } = item.status {
+        let state = match item.handle {
+          Some(ref mut handle)  => handle.poll(),
+          None                  => PutState::Disconnected,
+        };
+        match state {
+          PutState::Confirmed     => {
+            item.status = PublishStatus::Confirmed;
+            item.handle = None;
+          },
+          PutState::Disconnected  => {
+            lost_connection = true;
+            fail_attempt(item, policy, now);
+          },
+          PutState::Pending       => {
+            if now.duration_since(item.sent_at) >= confirm_timeout {
+              fail_attempt(item, policy, now);
+            }
+          },
+        }
+      }
+    }
+    if lost_connection {
+      self.dht = None;
+    }
+
+    let due: Vec<PublishId> = self.items.iter()
+                                        .filter(|&(_, item)| match item.status {
+                                          PublishStatus::Queued           => true,
+                                          PublishStatus::Retrying { .. }  => item.retry_at <= now,
+                                          _                               => false,
+                                        })
+                                        .map(|(id, _)| *id)
+                                        .collect();
+    if due.is_empty() {
+      return Ok(());
+    }
+    if self.dht.is_none() {
+      self.dht = Some(try!(DHT::connect(cfg)));
+    }
+
+    for id in due {
+      let item = self.items.get_mut(&id).unwrap(); // id was taken from the map
+      let res = {
+        let dht = self.dht.as_mut().unwrap(); // connected above
+        dht.put(&item.key,
+                item.block_type,
+                &item.data[..],
+                item.desired_replication_level,
+                item.options,
+                item.expiration)
+      };
+      item.attempts += 1;
+      match res {
+        Ok(handle) => {
+          item.status = PublishStatus::InFlight { attempt: item.attempts };
+          item.handle = Some(handle);
+          item.sent_at = now;
+        },
+        Err(PutError::DataTooLong { .. }) => {
+          item.status = PublishStatus::Failed { attempts: item.attempts };
+        },
+        Err(PutError::Io { .. }) | Err(PutError::Disconnected) => {
+          fail_attempt(item, policy, now);
+          self.dht = None;
+          break;
+        },
+      }
+    }
+    Ok(())
+  }
+
+  /// Keep sending and retrying items until they are all finished or `timeout` expires. Returns
+  /// `true` if every item is finished.
+  pub fn run(&mut self, cfg: &Cfg, timeout: Duration) -> Result<bool, service::ConnectError> {
+    let start = Instant::now();
+    loop {
+      try!(self.poll(cfg));
+      if self.is_idle() {
+        return Ok(true);
+      }
+      if start.elapsed() >= timeout {
+        return Ok(false);
+      }
+      thread::sleep(Duration::from_millis(50));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use super::backoff_delay;
+
+  #[test]
+  fn test_backoff_delay() {
+    let initial = Duration::from_secs(1);
+    let max = Duration::from_secs(10);
+    assert_eq!(backoff_delay(initial, max, 1), Duration::from_secs(1));
+    assert_eq!(backoff_delay(initial, max, 2), Duration::from_secs(2));
+    assert_eq!(backoff_delay(initial, max, 4), Duration::from_secs(8));
+    assert_eq!(backoff_delay(initial, max, 5), max);
+    assert_eq!(backoff_delay(initial, max, 100), max);
+  }
+}
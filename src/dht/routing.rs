@@ -0,0 +1,182 @@
+use std::cmp::Ordering;
+
+use HashCode;
+
+/// The number of buckets in a `RoutingTable`, one for each possible length of the prefix a key
+/// shares with the local key.
+pub const NUM_BUCKETS: usize = 512;
+
+/// The result of inserting into a `RoutingTable`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InsertResult {
+  /// The key was added to the table.
+  Inserted,
+  /// The key was already in the table. Its value was replaced and it was marked as the most
+  /// recently seen key in its bucket.
+  Updated,
+  /// The key's bucket is full. `oldest` is the least recently seen key in the bucket. Kademlia
+  /// implementations should check whether it is still alive and, if not, remove it and retry the
+  /// insert.
+  BucketFull { oldest: HashCode },
+  /// The key is the local key, which is never stored in the table.
+  IsLocal,
+}
+
+/// A Kademlia-style routing table keyed by `HashCode`.
+///
+/// Keys are sorted into buckets by the length of the prefix they share with the local key, so
+/// bucket `i` holds keys whose first differing bit from the local key is bit `i`. Each bucket
+/// holds at most `bucket_size` keys, ordered from least to most recently seen.
+pub struct RoutingTable<V> {
+  local: HashCode,
+  bucket_size: usize,
+  buckets: Vec<Vec<(HashCode, V)>>,
+}
+
+impl<V> RoutingTable<V> {
+  /// Create an empty table around the local key `local` with at most `bucket_size` keys per
+  /// bucket.
+  pub fn new(local: HashCode, bucket_size: usize) -> RoutingTable<V> {
+    RoutingTable {
+      local: local,
+      bucket_size: bucket_size,
+      buckets: (0..NUM_BUCKETS).map(|_| Vec::new()).collect(),
+    }
+  }
+
+  /// The local key.
+  pub fn local(&self) -> &HashCode {
+    &self.local
+  }
+
+  /// The index of the bucket `key` belongs in. Returns `None` for the local key.
+  pub fn bucket_index(&self, key: &HashCode) -> Option<usize> {
+    match self.local.matching_prefix_len(key) as usize {
+      NUM_BUCKETS => None,
+      i           => Some(i),
+    }
+  }
+
+  /// Insert `key` with the value `value`. See `InsertResult`.
+  pub fn insert(&mut self, key: HashCode, value: V) -> InsertResult {
+    let idx = match self.bucket_index(&key) {
+      Some(i) => i,
+      None    => return InsertResult::IsLocal,
+    };
+    let bucket_size = self.bucket_size;
+    let bucket = &mut self.buckets[idx];
+    if let Some(pos) = bucket.iter().position(|&(ref k, _)| *k == key) {
+      bucket.remove(pos);
+      bucket.push((key, value));
+      return InsertResult::Updated;
+    }
+    if bucket.len() >= bucket_size {
+      return InsertResult::BucketFull { oldest: bucket[0].0.clone() };
+    }
+    bucket.push((key, value));
+    InsertResult::Inserted
+  }
+
+  /// Remove `key` from the table, returning its value.
+  pub fn remove(&mut self, key: &HashCode) -> Option<V> {
+    let idx = match self.bucket_index(key) {
+      Some(i) => i,
+      None    => return None,
+    };
+    let bucket = &mut self.buckets[idx];
+    match bucket.iter().position(|&(ref k, _)| k == key) {
+      Some(pos) => Some(bucket.remove(pos).1),
+      None      => None,
+    }
+  }
+
+  /// Get the value of `key`.
+  pub fn get(&self, key: &HashCode) -> Option<&V> {
+    let idx = match self.bucket_index(key) {
+      Some(i) => i,
+      None    => return None,
+    };
+    self.buckets[idx].iter()
+                     .find(|&&(ref k, _)| k == key)
+                     .map(|&(_, ref v)| v)
+  }
+
+  /// Returns `true` if `key` is in the table.
+  pub fn contains(&self, key: &HashCode) -> bool {
+    self.get(key).is_some()
+  }
+
+  /// The keys in bucket `idx`, from least to most recently seen.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `idx >= NUM_BUCKETS`.
+  pub fn bucket(&self, idx: usize) -> &[(HashCode, V)] {
+    &self.buckets[idx][..]
+  }
+
+  /// The number of keys in the table.
+  pub fn len(&self) -> usize {
+    self.buckets.iter().fold(0, |n, b| n + b.len())
+  }
+
+  /// Returns `true` if the table is empty.
+  pub fn is_empty(&self) -> bool {
+    self.buckets.iter().all(|b| b.is_empty())
+  }
+
+  /// Iterate over all the keys in the table.
+  pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(&'a HashCode, &'a V)> + 'a> {
+    Box::new(self.buckets.iter().flat_map(|b| b.iter()).map(|&(ref k, ref v)| (k, v)))
+  }
+
+  /// The (at most) `n` keys closest to `target` in the XOR metric, closest first.
+  pub fn closest(&self, target: &HashCode, n: usize) -> Vec<(&HashCode, &V)> {
+    let mut all: Vec<(&HashCode, &V)> = self.iter().collect();
+    all.sort_by(|&(a, _), &(b, _)| target.xor_cmp(a, b));
+    all.truncate(n);
+    all
+  }
+
+  /// Returns `true` if `key` is closer to `target` than any key in the table, ie. whether a
+  /// request for `target` should be answered locally rather than forwarded.
+  pub fn is_closest(&self, key: &HashCode, target: &HashCode) -> bool {
+    self.iter().all(|(k, _)| target.xor_cmp(key, k) != Ordering::Greater)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::{weak_rng, Rng};
+  use HashCode;
+  use super::*;
+
+  #[test]
+  fn test_routing_table() {
+    let mut rng = weak_rng();
+    let local: HashCode = rng.gen();
+    let mut table = RoutingTable::new(local.clone(), 2);
+    assert_eq!(table.insert(local.clone(), 0), InsertResult::IsLocal);
+
+    // Keys differing from the local key in their first bit all go in bucket 0.
+    let mut near = Vec::new();
+    for flip in 1..4u8 {
+      let mut key = local.clone();
+      key.as_mut_slice()[0] ^= (flip << 1) | 1;
+      near.push(key);
+    }
+    assert_eq!(table.bucket_index(&near[0]), Some(0));
+    assert_eq!(table.insert(near[0].clone(), 0), InsertResult::Inserted);
+    assert_eq!(table.insert(near[1].clone(), 1), InsertResult::Inserted);
+    assert_eq!(table.insert(near[0].clone(), 2), InsertResult::Updated);
+    assert_eq!(table.insert(near[2].clone(), 3), InsertResult::BucketFull { oldest: near[1].clone() });
+    assert_eq!(table.get(&near[0]), Some(&2));
+    assert_eq!(table.len(), 2);
+
+    let closest = table.closest(&near[1], 2);
+    assert_eq!(closest[0].0, &near[1]);
+    assert!(table.is_closest(&near[1], &near[1]));
+    assert_eq!(table.remove(&near[1]), Some(1));
+    assert_eq!(table.closest(&near[1], 5).len(), 1);
+  }
+}
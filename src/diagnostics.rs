@@ -0,0 +1,190 @@
+//! Tools for finding out why a peer cannot be reached.
+//!
+//! `diagnose_peer` asks the local services what they know about a peer, tries to connect to it
+//! and returns a `PeerReport` listing any problems that were found. The report includes the
+//! transport service's validation state for the peer's addresses and the addresses ATS knows
+//! about, with their bandwidth and properties.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use Cfg;
+use Hello;
+use PeerIdentity;
+use ats::{self, AddressInfo, ListAddressesError};
+use peerinfo;
+use peerinfo::peerinfo::{IteratePeersError, NextPeerError};
+use time;
+use transport::{self, TransportService, TransportServiceInitError, ValidationInfo, ValidationsError};
+use transport::WaitForConnectionError;
+
+/// A problem which may prevent a peer from being reached.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+  /// The peer is our own peer.
+  IsSelf,
+  /// The peerinfo service has no HELLO for the peer, so we do not know any of its addresses.
+  NoHello,
+  /// The peer's HELLO is friend-only. We can only connect to it if it is one of our friends.
+  FriendOnly,
+  /// None of the peer's addresses have been validated by the transport service.
+  NoValidAddress,
+  /// ATS has not chosen an address for talking to the peer.
+  NoActiveAddress,
+  /// ATS has chosen an address for the peer but assigned it no outbound bandwidth.
+  NoBandwidth,
+  /// The transport service did not report a connection to the peer before the timeout expired.
+  NotConnected,
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let s = match *self {
+      Problem::IsSelf           => "The peer is our own peer",
+      Problem::NoHello          => "No HELLO is known for the peer, its addresses are unknown",
+      Problem::FriendOnly       => "The peer's HELLO is friend-only, it will only talk to its friends",
+      Problem::NoValidAddress   => "None of the peer's addresses have been validated",
+      Problem::NoActiveAddress  => "ATS has not chosen an address for the peer",
+      Problem::NoBandwidth      => "ATS has assigned no outbound bandwidth to the peer",
+      Problem::NotConnected     => "A connection to the peer could not be established",
+    };
+    write!(f, "{}", s)
+  }
+}
+
+/// The result of diagnosing a peer with `diagnose_peer`.
+#[derive(Debug)]
+pub struct PeerReport {
+  /// The peer that was diagnosed.
+  pub peer: PeerIdentity,
+  /// Whether the peer is our own peer.
+  pub is_self: bool,
+  /// The HELLO the peerinfo service has for the peer, if any.
+  pub hello: Option<Hello>,
+  /// The transport service's validation state for each of the peer's addresses.
+  pub validations: Vec<ValidationInfo>,
+  /// The addresses ATS knows about for the peer.
+  pub addresses: Vec<AddressInfo>,
+  /// When the report was made, in microseconds since the epoch.
+  pub time: u64,
+  /// Whether we asked the transport service to connect to the peer.
+  pub connect_attempted: bool,
+  /// Whether we are connected to the peer.
+  pub connected: bool,
+}
+
+impl PeerReport {
+  /// The problems found while diagnosing the peer. Empty if the peer is reachable.
+  pub fn problems(&self) -> Vec<Problem> {
+    let mut ret = Vec::new();
+    if self.is_self {
+      ret.push(Problem::IsSelf);
+      return ret;
+    }
+    match self.hello {
+      None                                  => ret.push(Problem::NoHello),
+      Some(ref hello) if hello.friend_only  => ret.push(Problem::FriendOnly),
+      Some(_)                               => (),
+    };
+    if !self.validations.iter().any(|v| v.is_valid_at(self.time)) {
+      ret.push(Problem::NoValidAddress);
+    }
+    match self.addresses.iter().find(|a| a.active) {
+      None                                => ret.push(Problem::NoActiveAddress),
+      Some(a) if a.bandwidth_out == 0     => ret.push(Problem::NoBandwidth),
+      Some(_)                             => (),
+    };
+    if !self.connected {
+      ret.push(Problem::NotConnected);
+    }
+    ret
+  }
+
+  /// Returns `true` if we are connected to the peer.
+  pub fn is_reachable(&self) -> bool {
+    self.connected
+  }
+}
+
+impl fmt::Display for PeerReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(writeln!(f, "Peer {}:", self.peer));
+    try!(writeln!(f, "  HELLO known:       {}", self.hello.is_some()));
+    for v in self.validations.iter() {
+      try!(writeln!(f, "  validation:        {} {} bytes, {:?}, valid: {}",
+                    v.plugin, v.address.len(), v.state, v.is_valid_at(self.time)));
+    }
+    for a in self.addresses.iter() {
+      try!(write!(f, "  ATS address:       {} {} bytes, active: {}, bandwidth in/out: {}/{}",
+                  a.plugin, a.address.len(), a.active, a.bandwidth_in, a.bandwidth_out));
+      if let Some(latency) = a.latency() {
+        try!(write!(f, ", latency: {}ms", latency.as_secs() * 1000 + (latency.subsec_nanos() / 1000000) as u64));
+      }
+      if let Some(distance) = a.distance() {
+        try!(write!(f, ", distance: {}", distance));
+      }
+      try!(writeln!(f, ""));
+    }
+    try!(writeln!(f, "  connect attempted: {}", self.connect_attempted));
+    try!(writeln!(f, "  connected:         {}", self.connected));
+    for problem in self.problems().iter() {
+      try!(writeln!(f, "  problem: {}", problem));
+    }
+    Ok(())
+  }
+}
+
+/// Errors returned by `diagnose_peer`.
+error_def! DiagnosePeerError {
+  Transport { #[from] cause: TransportServiceInitError }
+    => "Failed to connect to the transport service" ("Reason: {}", cause),
+  Peerinfo { #[from] cause: IteratePeersError }
+    => "Failed to query the peerinfo service" ("Reason: {}", cause),
+  NextPeer { #[from] cause: NextPeerError }
+    => "Failed to read the response from the peerinfo service" ("Reason: {}", cause),
+  Validations { #[from] cause: ValidationsError }
+    => "Failed to query the transport service for the validation state" ("Reason: {}", cause),
+  Ats { #[from] cause: ListAddressesError }
+    => "Failed to query the ATS service for the peer's addresses" ("Reason: {}", cause),
+  Connect { #[from] cause: io::Error }
+    => "Failed to ask the transport service to connect to the peer" ("Specifically: {}", cause),
+  WaitForConnection { #[from] cause: WaitForConnectionError }
+    => "Failed while waiting for a connection to the peer" ("Reason: {}", cause),
+}
+
+/// Find out whether `peer` can be reached, and if not, why not.
+///
+/// Looks up the peer's HELLO with the peerinfo service, the validation state of its addresses with
+/// the transport service and its addresses with ATS, then asks the transport service to connect to
+/// the peer, waiting up to `timeout` for the connection to be established.
+pub fn diagnose_peer(cfg: &Cfg, peer: &PeerIdentity, timeout: Duration) -> Result<PeerReport, DiagnosePeerError> {
+  let mut ts = try!(TransportService::init(cfg));
+  let mut report = PeerReport {
+    peer: *peer,
+    is_self: ts.our_hello().id == *peer,
+    hello: None,
+    validations: Vec::new(),
+    addresses: Vec::new(),
+    time: time::now_micros(),
+    connect_attempted: false,
+    connected: false,
+  };
+  if report.is_self {
+    return Ok(report);
+  }
+
+  for res in try!(peerinfo::get_peer(cfg, peer)) {
+    let (id, hello) = try!(res);
+    if id == *peer && hello.is_some() {
+      report.hello = hello;
+    }
+  }
+  report.validations = try!(transport::validations(cfg, Some(peer)));
+  report.addresses = try!(ats::list_addresses(cfg, Some(peer), true));
+
+  try!(ts.try_connect(peer));
+  report.connect_attempted = true;
+  report.connected = try!(ts.wait_for_connection(peer, timeout));
+  Ok(report)
+}
@@ -0,0 +1,191 @@
+//! Short, stable identifiers for keys, for showing to users.
+//!
+//! A full key is 52 characters long, too long for people to compare by eye. A `Fingerprint` is
+//! made from the hash of a key and shown as 16 crockford base32 characters in groups of four, eg.
+//! `K3T0-9ZQ4-RX1M-7H2E`. Fingerprints of zone keys and peer identities are hashed differently, so
+//! a zone never has the same fingerprint as a peer. The fingerprint of an ego is that of its zone.
+//!
+//! When a user types a fingerprint back in, use `Fingerprint::matches`. It ignores case and
+//! separators and accepts the letters crockford base32 treats as digits.
+
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::str::FromStr;
+
+use EcdsaPublicKey;
+use HashCode;
+use PeerIdentity;
+use data;
+use identity::Ego;
+
+/// The number of bytes of the key's hash kept in a fingerprint.
+const FINGERPRINT_LEN: usize = 10;
+/// The number of characters shown by `Fingerprint::short`.
+const SHORT_LEN: usize = 8;
+/// The fewest characters `Fingerprint::matches` will accept as a match.
+const MIN_MATCH_LEN: usize = 4;
+
+const ZONE_TAG: &'static [u8] = b"gnunet-rs zone fingerprint\0";
+const PEER_TAG: &'static [u8] = b"gnunet-rs peer fingerprint\0";
+
+/// A short identifier derived from the hash of a key.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+  bytes: [u8; FINGERPRINT_LEN],
+}
+
+impl Fingerprint {
+  fn from_tagged(tag: &[u8], key: &[u8]) -> Fingerprint {
+    let mut buf = tag.to_vec();
+    buf.extend_from_slice(key);
+    let hash = HashCode::from_buffer(&buf[..]);
+    let mut bytes = [0u8; FINGERPRINT_LEN];
+    bytes.copy_from_slice(&hash.as_slice()[..FINGERPRINT_LEN]);
+    Fingerprint {
+      bytes: bytes,
+    }
+  }
+
+  /// The fingerprint of the zone key `key`.
+  pub fn of_zone(key: &EcdsaPublicKey) -> Fingerprint {
+    let mut buf = Vec::with_capacity(32);
+    key.serialize(&mut buf).unwrap();
+    Fingerprint::from_tagged(ZONE_TAG, &buf[..])
+  }
+
+  /// The fingerprint of `ego`, which is that of its zone.
+  pub fn of_ego(ego: &Ego) -> Fingerprint {
+    Fingerprint::of_zone(&ego.get_public_key())
+  }
+
+  /// The fingerprint of the peer `peer`.
+  pub fn of_peer(peer: &PeerIdentity) -> Fingerprint {
+    let mut buf = Vec::with_capacity(32);
+    peer.serialize(&mut buf).unwrap();
+    Fingerprint::from_tagged(PEER_TAG, &buf[..])
+  }
+
+  /// The raw bytes of the fingerprint.
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.bytes[..]
+  }
+
+  /// The fingerprint as 16 characters without separators.
+  pub fn encoded(&self) -> String {
+    data::crockford_encode(&self.bytes[..])
+  }
+
+  /// The first 8 characters of the fingerprint, for when space is short. These are still enough
+  /// to tell apart the keys a user is likely to have.
+  pub fn short(&self) -> String {
+    let mut ret = self.encoded();
+    ret.truncate(SHORT_LEN);
+    ret
+  }
+
+  /// The fingerprint in groups of four characters separated by `-`. This is how fingerprints are
+  /// displayed.
+  pub fn grouped(&self) -> String {
+    let encoded = self.encoded();
+    let groups: Vec<&str> = (0..encoded.len() / 4).map(|i| &encoded[i * 4..(i + 1) * 4]).collect();
+    groups.join("-")
+  }
+
+  /// Returns `true` if `input`, as typed by a user, is this fingerprint or the start of it.
+  ///
+  /// At least four characters must be given. Case, spaces and `-` are ignored, and `O`, `I` and
+  /// `L` are read as `0`, `1` and `1`.
+  pub fn matches(&self, input: &str) -> bool {
+    let input = match normalize(input) {
+      Some(i) => i,
+      None    => return false,
+    };
+    input.len() >= MIN_MATCH_LEN && self.encoded().starts_with(&input[..])
+  }
+}
+
+/// Turn user input into the characters of an encoded fingerprint. Returns `None` if it has
+/// characters which can't be part of one.
+fn normalize(input: &str) -> Option<String> {
+  let mut ret = String::with_capacity(input.len());
+  for c in input.chars() {
+    let c = match c.to_ascii_uppercase() {
+      '-' | ' '             => continue,
+      'O'                   => '0',
+      'I' | 'L'             => '1',
+      'U'                   => return None,
+      c @ '0'...'9'         => c,
+      c @ 'A'...'Z'         => c,
+      _                     => return None,
+    };
+    ret.push(c);
+  }
+  Some(ret)
+}
+
+/// Errors returned when parsing a `Fingerprint`.
+error_def! FingerprintFromStrError {
+  ParsingFailed => "Failed to parse the string as a fingerprint",
+}
+
+impl FromStr for Fingerprint {
+  type Err = FingerprintFromStrError;
+
+  /// Parse a whole fingerprint, with or without separators.
+  fn from_str(s: &str) -> Result<Fingerprint, FingerprintFromStrError> {
+    let encoded = match normalize(s) {
+      Some(e) => e,
+      None    => return Err(FingerprintFromStrError::ParsingFailed),
+    };
+    let mut bytes = [0u8; FINGERPRINT_LEN];
+    if encoded.len() != FINGERPRINT_LEN * 8 / 5 || data::crockford_decode(&encoded[..], &mut bytes[..]).is_err() {
+      return Err(FingerprintFromStrError::ParsingFailed);
+    }
+    Ok(Fingerprint {
+      bytes: bytes,
+    })
+  }
+}
+
+impl fmt::Debug for Fingerprint {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Fingerprint({})", self.grouped())
+  }
+}
+
+impl fmt::Display for Fingerprint {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.pad(&self.grouped()[..])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+  use EcdsaPrivateKey;
+  use PeerIdentity;
+  use super::*;
+
+  #[test]
+  fn test_fingerprint() {
+    let zone = EcdsaPrivateKey::anonymous().get_public();
+    let fp = Fingerprint::of_zone(&zone);
+    assert_eq!(fp, Fingerprint::of_zone(&zone));
+    let grouped = fp.grouped();
+    assert_eq!(grouped.len(), 19);
+    assert_eq!(&grouped[4..5], "-");
+    assert_eq!(fp.short(), &fp.encoded()[..8]);
+    assert_eq!(Fingerprint::from_str(&grouped[..]).unwrap(), fp);
+    assert_eq!(format!("{}", fp), grouped);
+
+    assert!(fp.matches(&grouped.to_lowercase()[..]));
+    assert!(fp.matches(&fp.short()[..]));
+    assert!(!fp.matches(&fp.encoded()[..3]));
+    assert!(!fp.matches("not a fingerprint"));
+
+    let mut buf = Vec::new();
+    zone.serialize(&mut buf).unwrap();
+    let peer = PeerIdentity::deserialize(&mut &buf[..]).unwrap();
+    assert!(Fingerprint::of_peer(&peer) != fp);
+  }
+}
@@ -0,0 +1,150 @@
+//! Messages in the wire formats spoken by the GNUnet services, for checking that this crate
+//! encodes and decodes them byte-for-byte. Each message is complete, including its header.
+//!
+//! The tests of the modules which build or parse these messages compare against the fixtures
+//! here, so a change to a message layout shows up in `cargo test` without a running peer. When
+//! a GNUnet release changes a layout, add fixtures for the new layout next to the old ones
+//! rather than replacing them.
+//!
+//! These fixtures were written out by hand from the message structs in GNUnet's C headers, with
+//! placeholder keys, so they check this crate against our reading of those headers rather than
+//! against a daemon. To replace one with a capture, set `RECORD` in the service's section of the
+//! config of a running peer, exercise the service, and copy the message out of the recording read
+//! back with `RecordingReader`, noting the GNUnet version it came from.
+
+/// The zone looked up in by `GNS_LOOKUP_SHORTEN` and `GNS_LOOKUP_NO_SHORTEN`.
+pub const LOOKUP_ZONE: [u8; 32] = [0x11; 32];
+
+/// The name looked up by `GNS_LOOKUP_SHORTEN` and `GNS_LOOKUP_NO_SHORTEN`.
+pub const LOOKUP_NAME: &'static str = "www.gnu";
+
+/// The expiration time of the record in `GNS_LOOKUP_RESULT`, in microseconds since the epoch.
+pub const GNS_LOOKUP_RESULT_EXPIRATION: u64 = 0x0005_4a8e_ae4b_0000;
+
+/// The private key of the ego in `IDENTITY_UPDATE`.
+pub const IDENTITY_KEY: [u8; 32] = [0x22; 32];
+
+/// The identity of the peer in `HELLO` and `PEERINFO_INFO`.
+pub const PEER: [u8; 32] = [0x33; 32];
+
+/// A `GNS_LOOKUP` request for the A records of `www.gnu` in `LOOKUP_ZONE`, with lookup id 7
+/// and `LocalOptions::LocalMaster`, as laid out by `LookupProtocol::Shorten`.
+///
+/// Transcribed from `struct LookupMessage` in `src/gns/gns.h` of GNUnet 0.10.1.
+pub const GNS_LOOKUP_SHORTEN: &'static [u8] = &[
+  // header
+  0x00, 0x58, 0x01, 0xf4,
+  // lookup id
+  0x00, 0x00, 0x00, 0x07,
+  // zone
+  0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+  0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+  // options: LocalMaster, shorten: no
+  0x00, 0x02, 0x00, 0x00,
+  // record type: A
+  0x00, 0x00, 0x00, 0x01,
+  // shorten zone, unused
+  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+  // name
+  0x77, 0x77, 0x77, 0x2e, 0x67, 0x6e, 0x75, 0x00,
+];
+
+/// The request of `GNS_LOOKUP_SHORTEN` as laid out by `LookupProtocol::NoShorten`.
+///
+/// Transcribed from `struct LookupMessage` in `src/gns/gns.h` of GNUnet 0.11.0.
+pub const GNS_LOOKUP_NO_SHORTEN: &'static [u8] = &[
+  // header
+  0x00, 0x38, 0x01, 0xf4,
+  // lookup id
+  0x00, 0x00, 0x00, 0x07,
+  // zone
+  0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+  0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+  // options: LocalMaster, recursion depth limit: 128
+  0x00, 0x02, 0x00, 0x80,
+  // record type: A
+  0x00, 0x00, 0x00, 0x01,
+  // name
+  0x77, 0x77, 0x77, 0x2e, 0x67, 0x6e, 0x75, 0x00,
+];
+
+/// The reply to `GNS_LOOKUP_SHORTEN`: a single A record for 192.0.2.1 expiring at
+/// `GNS_LOOKUP_RESULT_EXPIRATION`.
+///
+/// Transcribed from `struct LookupResultMessage` in `src/gns/gns.h` and the record serialization
+/// in `src/gnsrecord/gnsrecord_serialization.c` of GNUnet 0.10.1.
+pub const GNS_LOOKUP_RESULT: &'static [u8] = &[
+  // header
+  0x00, 0x24, 0x01, 0xf5,
+  // lookup id
+  0x00, 0x00, 0x00, 0x07,
+  // record count
+  0x00, 0x00, 0x00, 0x01,
+  // expiration time, data size, record type: A, flags
+  0x00, 0x05, 0x4a, 0x8e, 0xae, 0x4b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01,
+  0x00, 0x00, 0x00, 0x00,
+  // 192.0.2.1
+  0xc0, 0x00, 0x02, 0x01,
+];
+
+/// An `IDENTITY_UPDATE` describing the ego `gns-master`, whose private key is `IDENTITY_KEY`.
+///
+/// Transcribed from `struct UpdateMessage` in `src/identity/identity.h` of GNUnet 0.10.1.
+pub const IDENTITY_UPDATE: &'static [u8] = &[
+  // header
+  0x00, 0x33, 0x02, 0x72,
+  // name length, end of list: no
+  0x00, 0x0b, 0x00, 0x00,
+  // private key
+  0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+  0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+  // name
+  0x67, 0x6e, 0x73, 0x2d, 0x6d, 0x61, 0x73, 0x74, 0x65, 0x72, 0x00,
+];
+
+/// The `IDENTITY_UPDATE` marking the end of the initial list of egos.
+///
+/// Transcribed from `struct UpdateMessage` in `src/identity/identity.h` of GNUnet 0.10.1.
+pub const IDENTITY_UPDATE_END: &'static [u8] = &[
+  // header
+  0x00, 0x28, 0x02, 0x72,
+  // name length, end of list: yes
+  0x00, 0x00, 0x00, 0x01,
+  // private key, unused
+  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+  0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// A `HELLO` for `PEER`, without addresses.
+///
+/// Transcribed from `struct GNUNET_HELLO_Message` in `src/hello/hello.c` of GNUnet 0.10.1.
+pub const HELLO: &'static [u8] = &[
+  // header
+  0x00, 0x28, 0x00, 0x11,
+  // friend only: no
+  0x00, 0x00, 0x00, 0x00,
+  // peer identity
+  0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+  0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+];
+
+/// A `PEERINFO_INFO` for the peer of `HELLO`, with the HELLO attached.
+///
+/// Transcribed from `struct InfoMessage` in `src/peerinfo/peerinfo.h` of GNUnet 0.10.1.
+pub const PEERINFO_INFO: &'static [u8] = &[
+  // header
+  0x00, 0x50, 0x01, 0x4c,
+  // reserved
+  0x00, 0x00, 0x00, 0x00,
+  // peer identity
+  0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+  0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+  // attached HELLO header
+  0x00, 0x28, 0x00, 0x11,
+  // friend only: no
+  0x00, 0x00, 0x00, 0x00,
+  // peer identity
+  0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+  0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+];
@@ -0,0 +1,396 @@
+use std::io::{self, Read, Write};
+use std::mem::uninitialized;
+use libc::{c_void, size_t};
+
+use ll;
+use HashCode;
+
+/// The size of the data blocks a file is split into.
+pub const DBLOCK_SIZE: usize = 32 * 1024;
+
+/// The number of content hash keys which fit in an indirect block.
+pub const CHK_PER_INODE: usize = 256;
+
+/// The key needed to find and decrypt a block of a file.
+///
+/// Every block is encrypted with a key derived from the hash of its plaintext, and found by the
+/// hash of its ciphertext. Indirect blocks are lists of the `ContentHashKey`s of the blocks below
+/// them, so the `ContentHashKey` of the root block is enough to fetch the whole file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ContentHashKey {
+  /// The hash of the plaintext, which the block is encrypted with.
+  pub key: HashCode,
+  /// The hash of the ciphertext, which the block is stored under.
+  pub query: HashCode,
+}
+
+impl ContentHashKey {
+  /// The size of a serialized `ContentHashKey`.
+  pub fn serialized_len() -> usize {
+    128
+  }
+
+  /// Serialize a `ContentHashKey` to a byte stream.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    try!(self.key.serialize(w));
+    self.query.serialize(w)
+  }
+
+  /// Deserialize a `ContentHashKey` from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<ContentHashKey, io::Error> where T: Read {
+    let key = try!(HashCode::deserialize(r));
+    let query = try!(HashCode::deserialize(r));
+    Ok(ContentHashKey {
+      key: key,
+      query: query,
+    })
+  }
+}
+
+/// Run `data` through libgnunetutil's symmetric cipher with a key and IV derived from `key`.
+fn crypt(key: &HashCode, data: &[u8], encrypt: bool) -> Vec<u8> {
+  let mut ret = vec![0u8; data.len()];
+  unsafe {
+    let mut skey: ll::Struct_GNUNET_CRYPTO_SymmetricSessionKey = uninitialized();
+    let mut iv: ll::Struct_GNUNET_CRYPTO_SymmetricInitializationVector = uninitialized();
+    ll::GNUNET_CRYPTO_hash_to_aes_key(key.as_slice().as_ptr() as *const ll::Struct_GNUNET_HashCode, &mut skey, &mut iv);
+    let block = data.as_ptr() as *const c_void;
+    let result = ret.as_mut_ptr() as *mut c_void;
+    let len = match encrypt {
+      true  => ll::GNUNET_CRYPTO_symmetric_encrypt(block, data.len() as size_t, &skey, &iv, result),
+      false => ll::GNUNET_CRYPTO_symmetric_decrypt(block, data.len() as size_t, &skey, &iv, result),
+    };
+    assert!(len as usize == data.len());
+  }
+  ret
+}
+
+/// Encrypt `data` with a key and IV derived from the hash `key`, as GNUnet does for file-sharing
+/// blocks.
+pub fn hash_encrypt(key: &HashCode, data: &[u8]) -> Vec<u8> {
+  crypt(key, data, true)
+}
+
+/// Decrypt data encrypted by `hash_encrypt` with the same `key`.
+pub fn hash_decrypt(key: &HashCode, data: &[u8]) -> Vec<u8> {
+  crypt(key, data, false)
+}
+
+/// Encrypt a block of plaintext. Returns the ciphertext and the key needed to find and decrypt it.
+pub fn encrypt_block(plaintext: &[u8]) -> (ContentHashKey, Vec<u8>) {
+  let key = HashCode::from_buffer(plaintext);
+  let ciphertext = hash_encrypt(&key, plaintext);
+  let chk = ContentHashKey {
+    key: key,
+    query: HashCode::from_buffer(&ciphertext[..]),
+  };
+  (chk, ciphertext)
+}
+
+/// Decrypt a block which was encrypted under `chk`.
+///
+/// Returns `None` if the block is not the one `chk` describes.
+pub fn decrypt_block(chk: &ContentHashKey, ciphertext: &[u8]) -> Option<Vec<u8>> {
+  if HashCode::from_buffer(ciphertext) != chk.query {
+    return None;
+  }
+  let plaintext = hash_decrypt(&chk.key, ciphertext);
+  match HashCode::from_buffer(&plaintext[..]) == chk.key {
+    true  => Some(plaintext),
+    false => None,
+  }
+}
+
+/// The depth of the tree of blocks for a file of `size` bytes. A file which fits in a single data
+/// block has depth 0.
+pub fn tree_depth(size: u64) -> u32 {
+  let mut depth = 0;
+  let mut covered = DBLOCK_SIZE as u64;
+  while covered < size {
+    depth += 1;
+    covered = match covered.checked_mul(CHK_PER_INODE as u64) {
+      Some(c) => c,
+      None    => return depth,
+    };
+  }
+  depth
+}
+
+/// The number of bytes of the file covered by a block at depth `depth`.
+pub fn tree_size(depth: u32) -> u64 {
+  let mut size = DBLOCK_SIZE as u64;
+  for _ in 0..depth {
+    size = size.saturating_mul(CHK_PER_INODE as u64);
+  }
+  size
+}
+
+/// The size of the block at depth `depth` which starts at byte `offset` of a file of `size`
+/// bytes.
+pub fn block_size(size: u64, offset: u64, depth: u32) -> usize {
+  if depth == 0 {
+    return match offset + DBLOCK_SIZE as u64 > size {
+      true  => (size - offset) as usize,
+      false => DBLOCK_SIZE,
+    };
+  }
+  let child_size = tree_size(depth - 1);
+  let end = match offset.checked_add(child_size.saturating_mul(CHK_PER_INODE as u64)) {
+    Some(e) if e <= size  => e,
+    _                     => size,
+  };
+  let chks = (end - offset + child_size - 1) / child_size;
+  chks as usize * ContentHashKey::serialized_len()
+}
+
+/// A block of a file's tree, produced by `TreeEncoder`.
+#[derive(Clone, Debug)]
+pub struct EncodedBlock {
+  /// The depth of the block in the tree. Data blocks have depth 0.
+  pub depth: u32,
+  /// The offset in the file of the first byte the block covers.
+  pub offset: u64,
+  /// The key needed to find and decrypt the block.
+  pub chk: ContentHashKey,
+  /// The encrypted block.
+  pub data: Vec<u8>,
+}
+
+/// Splits a file into the tree of encrypted blocks used by file-sharing.
+///
+/// The file's data is fed in one data block at a time. Each block of the tree is handed to a
+/// callback as soon as it is complete, so the file never has to be held in memory.
+///
+/// If the size of the file isn't known in advance, use `TreeEncoder::streaming`. The tree is then
+/// grown as data arrives, which produces the same blocks but holds each full indirect block back
+/// until the block after it shows that the tree goes on.
+pub struct TreeEncoder {
+  size: Option<u64>,
+  depth: u32,
+  offset: u64,
+  ended: bool,
+  // `levels[i]` holds the keys collected for the indirect block at depth `i + 1`, which starts
+  // at `level_offsets[i]`.
+  levels: Vec<Vec<u8>>,
+  level_offsets: Vec<u64>,
+  root: Option<ContentHashKey>,
+}
+
+impl TreeEncoder {
+  /// Create an encoder for a file of `size` bytes.
+  pub fn new(size: u64) -> TreeEncoder {
+    let depth = tree_depth(size);
+    TreeEncoder {
+      size: Some(size),
+      depth: depth,
+      offset: 0,
+      ended: false,
+      levels: vec![Vec::new(); depth as usize],
+      level_offsets: vec![0; depth as usize],
+      root: None,
+    }
+  }
+
+  /// Create an encoder for a file whose size is only known once all of it has been fed in.
+  ///
+  /// Every data block but the last must be `DBLOCK_SIZE` bytes long. The first block shorter
+  /// than that is taken to be the last.
+  pub fn streaming() -> TreeEncoder {
+    TreeEncoder {
+      size: None,
+      depth: 0,
+      offset: 0,
+      ended: false,
+      levels: Vec::new(),
+      level_offsets: Vec::new(),
+      root: None,
+    }
+  }
+
+  /// The offset of the next data block, ie. the number of bytes fed in so far.
+  pub fn offset(&self) -> u64 {
+    self.offset
+  }
+
+  /// The size the next data block must have. For a streaming encoder this is the most it may
+  /// have.
+  pub fn next_block_size(&self) -> usize {
+    match self.size {
+      Some(size) => block_size(size, self.offset, 0),
+      None       => DBLOCK_SIZE,
+    }
+  }
+
+  /// Feed in the next data block, which must be `next_block_size` bytes long. `emit` is called for
+  /// every block which is completed.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `data` has the wrong length, or if a streaming encoder has already been given its
+  /// last block.
+  pub fn push<F, E>(&mut self, data: &[u8], emit: &mut F) -> Result<(), E>
+      where F: FnMut(EncodedBlock) -> Result<(), E>
+  {
+    match self.size {
+      Some(size) => assert!(self.offset < size && data.len() == self.next_block_size()),
+      None       => {
+        assert!(!self.ended && !data.is_empty() && data.len() <= DBLOCK_SIZE);
+        self.ended = data.len() < DBLOCK_SIZE;
+      },
+    };
+    let (chk, ciphertext) = encrypt_block(data);
+    try!(emit(EncodedBlock { depth: 0, offset: self.offset, chk: chk.clone(), data: ciphertext }));
+    self.offset += data.len() as u64;
+    self.add(1, chk, emit)
+  }
+
+  /// Complete the tree once all the data has been fed in. Returns the key of the root block.
+  ///
+  /// # Panics
+  ///
+  /// Panics if less than the whole file has been fed in.
+  pub fn finish<F, E>(mut self, emit: &mut F) -> Result<ContentHashKey, E>
+      where F: FnMut(EncodedBlock) -> Result<(), E>
+  {
+    if let Some(size) = self.size {
+      assert_eq!(self.offset, size);
+    }
+    if self.offset == 0 {
+      let (chk, ciphertext) = encrypt_block(&[]);
+      try!(emit(EncodedBlock { depth: 0, offset: 0, chk: chk.clone(), data: ciphertext }));
+      return Ok(chk);
+    }
+    if self.size.is_none() {
+      return self.finish_streaming(emit);
+    }
+    for depth in 1..(self.depth + 1) {
+      if !self.levels[depth as usize - 1].is_empty() {
+        try!(self.flush(depth, emit));
+      }
+    }
+    Ok(self.root.take().unwrap())
+  }
+
+  /// Flush every level from the bottom up until one holds nothing but the root's key.
+  fn finish_streaming<F, E>(mut self, emit: &mut F) -> Result<ContentHashKey, E>
+      where F: FnMut(EncodedBlock) -> Result<(), E>
+  {
+    let mut depth = 1;
+    loop {
+      let i = depth as usize - 1;
+      if i + 1 == self.levels.len() && self.levels[i].len() == ContentHashKey::serialized_len() {
+        return Ok(ContentHashKey::deserialize(&mut &self.levels[i][..]).unwrap());
+      }
+      if !self.levels[i].is_empty() {
+        try!(self.flush(depth, emit));
+      }
+      depth += 1;
+    }
+  }
+
+  fn add<F, E>(&mut self, depth: u32, chk: ContentHashKey, emit: &mut F) -> Result<(), E>
+      where F: FnMut(EncodedBlock) -> Result<(), E>
+  {
+    let full_len = CHK_PER_INODE * ContentHashKey::serialized_len();
+    if self.size.is_none() {
+      // The tree only grows another level once a level overflows, so a full block is held back
+      // until we know it isn't the root.
+      if depth as usize > self.levels.len() {
+        self.levels.push(Vec::new());
+        self.level_offsets.push(0);
+      }
+      if self.levels[depth as usize - 1].len() == full_len {
+        try!(self.flush(depth, emit));
+      }
+      chk.serialize(&mut self.levels[depth as usize - 1]).unwrap();
+      return Ok(());
+    }
+    if depth > self.depth {
+      self.root = Some(chk);
+      return Ok(());
+    }
+    let full = {
+      let level = &mut self.levels[depth as usize - 1];
+      chk.serialize(level).unwrap();
+      level.len() == full_len
+    };
+    if full && depth < self.depth {
+      try!(self.flush(depth, emit));
+    }
+    Ok(())
+  }
+
+  fn flush<F, E>(&mut self, depth: u32, emit: &mut F) -> Result<(), E>
+      where F: FnMut(EncodedBlock) -> Result<(), E>
+  {
+    let i = depth as usize - 1;
+    let plaintext = ::std::mem::replace(&mut self.levels[i], Vec::new());
+    let offset = self.level_offsets[i];
+    self.level_offsets[i] += tree_size(depth);
+    let (chk, ciphertext) = encrypt_block(&plaintext[..]);
+    try!(emit(EncodedBlock { depth: depth, offset: offset, chk: chk.clone(), data: ciphertext }));
+    self.add(depth + 1, chk, emit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_chk_blocks() {
+    let plaintext = b"some file contents".to_vec();
+    let (chk, ciphertext) = encrypt_block(&plaintext[..]);
+    assert!(ciphertext != plaintext);
+    assert_eq!(decrypt_block(&chk, &ciphertext[..]), Some(plaintext));
+    assert_eq!(decrypt_block(&chk, b"something else"), None);
+
+    assert_eq!(tree_depth(0), 0);
+    assert_eq!(tree_depth(DBLOCK_SIZE as u64), 0);
+    assert_eq!(tree_depth(DBLOCK_SIZE as u64 + 1), 1);
+    assert_eq!(tree_depth(tree_size(1) + 1), 2);
+
+    let size = 3 * DBLOCK_SIZE as u64 + 10;
+    assert_eq!(block_size(size, 0, 1), 4 * ContentHashKey::serialized_len());
+    assert_eq!(block_size(size, 0, 0), DBLOCK_SIZE);
+    assert_eq!(block_size(size, 3 * DBLOCK_SIZE as u64, 0), 10);
+  }
+
+  #[test]
+  fn test_tree_encoder() {
+    let size = 3 * DBLOCK_SIZE as u64 + 10;
+    let mut encoder = TreeEncoder::new(size);
+    let mut blocks = Vec::new();
+    while encoder.offset() < size {
+      let data = vec![encoder.offset() as u8; encoder.next_block_size()];
+      encoder.push(&data[..], &mut |b| -> Result<(), ()> { blocks.push(b); Ok(()) }).unwrap();
+    }
+    let root = encoder.finish(&mut |b| -> Result<(), ()> { blocks.push(b); Ok(()) }).unwrap();
+    assert_eq!(blocks.len(), 5);
+
+    let top = blocks.pop().unwrap();
+    assert_eq!((top.depth, top.offset), (1, 0));
+    assert_eq!(top.chk, root);
+    let plaintext = decrypt_block(&root, &top.data[..]).unwrap();
+    assert_eq!(plaintext.len(), block_size(size, 0, 1));
+  }
+
+  #[test]
+  fn test_streaming_tree_encoder() {
+    for &size in [0, 10, DBLOCK_SIZE as u64, 3 * DBLOCK_SIZE as u64 + 10].iter() {
+      let mut known = TreeEncoder::new(size);
+      let mut streaming = TreeEncoder::streaming();
+      let mut known_blocks = Vec::new();
+      let mut streaming_blocks = Vec::new();
+      while known.offset() < size {
+        let data = vec![known.offset() as u8; known.next_block_size()];
+        known.push(&data[..], &mut |b| -> Result<(), ()> { known_blocks.push(b.chk); Ok(()) }).unwrap();
+        streaming.push(&data[..], &mut |b| -> Result<(), ()> { streaming_blocks.push(b.chk); Ok(()) }).unwrap();
+      }
+      let known_root = known.finish(&mut |b| -> Result<(), ()> { known_blocks.push(b.chk); Ok(()) }).unwrap();
+      let streaming_root = streaming.finish(&mut |b| -> Result<(), ()> { streaming_blocks.push(b.chk); Ok(()) }).unwrap();
+      assert_eq!(streaming_root, known_root);
+      assert_eq!(streaming_blocks, known_blocks);
+    }
+  }
+}
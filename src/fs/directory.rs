@@ -0,0 +1,149 @@
+use std::str::from_utf8;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use fs::{MetaData, MetaDataDeserializeError, Uri, DBLOCK_SIZE};
+
+/// The bytes every GNUnet directory starts with.
+pub const DIRECTORY_MAGIC: &'static [u8] = b"\x89GND\r\n\x1a\n";
+
+/// The mime type of a GNUnet directory.
+pub const DIRECTORY_MIME_TYPE: &'static str = "application/gnunet-directory";
+
+/// A file listed in a directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirectoryEntry {
+  /// The URI of the file.
+  pub uri: Uri,
+  /// The metadata of the file.
+  pub metadata: MetaData,
+}
+
+/// A GNUnet directory: a file listing other files, in the format used by `gnunet-directory`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Directory {
+  /// Metadata describing the directory itself.
+  pub metadata: MetaData,
+  /// The files in the directory.
+  pub entries: Vec<DirectoryEntry>,
+}
+
+/// Errors returned by `Directory::parse`.
+error_def! DirectoryParseError {
+  NotADirectory
+    => "The data does not start with the GNUnet directory magic bytes",
+  Truncated
+    => "The directory ends part way through an entry",
+  InvalidUri
+    => "A directory entry has an invalid URI",
+  MetaData { #[from] cause: MetaDataDeserializeError }
+    => "The metadata of the directory or one of its entries is invalid" ("Reason: {}", cause),
+}
+
+/// Returns `true` if `data` looks like a GNUnet directory.
+pub fn is_directory(data: &[u8]) -> bool {
+  data.starts_with(DIRECTORY_MAGIC)
+}
+
+/// Read a 4-byte length followed by that many bytes of metadata, starting at `pos`. Returns the
+/// metadata and the position after it.
+fn read_metadata(data: &[u8], pos: usize) -> Result<(MetaData, usize), DirectoryParseError> {
+  if pos + 4 > data.len() {
+    return Err(DirectoryParseError::Truncated);
+  }
+  let len = BigEndian::read_u32(&data[pos..pos + 4]) as usize;
+  let start = pos + 4;
+  if start + len > data.len() {
+    return Err(DirectoryParseError::Truncated);
+  }
+  let md = try!(MetaData::deserialize(&data[start..start + len]));
+  Ok((md, start + len))
+}
+
+impl Directory {
+  /// Create an empty directory.
+  pub fn new() -> Directory {
+    Directory {
+      metadata: MetaData::new(),
+      entries: Vec::new(),
+    }
+  }
+
+  /// Parse the contents of a directory.
+  pub fn parse(data: &[u8]) -> Result<Directory, DirectoryParseError> {
+    if !is_directory(data) {
+      return Err(DirectoryParseError::NotADirectory);
+    }
+    let (metadata, mut pos) = try!(read_metadata(data, DIRECTORY_MAGIC.len()));
+    let mut entries = Vec::new();
+    while pos < data.len() {
+      if data[pos] == 0 {
+        // URIs are never empty, so a 0 byte is padding up to the next block boundary.
+        pos = (pos / DBLOCK_SIZE + 1) * DBLOCK_SIZE;
+        if pos >= data.len() {
+          break;
+        }
+      }
+      let end = match data[pos..].iter().position(|&b| b == 0) {
+        Some(i) => pos + i,
+        None    => return Err(DirectoryParseError::Truncated),
+      };
+      let uri = match from_utf8(&data[pos..end]).ok().and_then(|s| s.parse::<Uri>().ok()) {
+        Some(uri) => uri,
+        None      => return Err(DirectoryParseError::InvalidUri),
+      };
+      let (md, next) = try!(read_metadata(data, end + 1));
+      entries.push(DirectoryEntry {
+        uri: uri,
+        metadata: md,
+      });
+      pos = next;
+    }
+    Ok(Directory {
+      metadata: metadata,
+      entries: entries,
+    })
+  }
+
+  /// Serialize the directory. Entries are not padded to block boundaries.
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut ret = DIRECTORY_MAGIC.to_vec();
+    let md = self.metadata.serialize();
+    ret.write_u32::<BigEndian>(md.len() as u32).unwrap();
+    ret.extend_from_slice(&md[..]);
+    for entry in self.entries.iter() {
+      ret.extend_from_slice(entry.uri.to_string().as_bytes());
+      ret.push(0);
+      let md = entry.metadata.serialize();
+      ret.write_u32::<BigEndian>(md.len() as u32).unwrap();
+      ret.extend_from_slice(&md[..]);
+    }
+    ret
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand;
+  use fs::{MetaData, MetaType, Uri};
+  use super::*;
+
+  #[test]
+  fn test_directory_round_trip() {
+    let mut md = MetaData::new();
+    md.insert_str(MetaType::Filename, "notes.txt");
+    let mut dir = Directory::new();
+    dir.metadata.insert_str(MetaType::MimeType, DIRECTORY_MIME_TYPE);
+    dir.entries.push(DirectoryEntry {
+      uri: Uri::Chk { key: rand::random(), query: rand::random(), size: 42 },
+      metadata: md,
+    });
+
+    let data = dir.serialize();
+    assert!(is_directory(&data[..]));
+    assert_eq!(Directory::parse(&data[..]).unwrap(), dir);
+    match Directory::parse(&data[..data.len() - 1]) {
+      Err(DirectoryParseError::Truncated) => (),
+      _ => panic!("expected a truncated directory"),
+    };
+  }
+}
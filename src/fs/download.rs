@@ -0,0 +1,309 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write, Cursor};
+use std::path::{Component, Path, PathBuf};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use HashCode;
+use block::BlockType;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+use fs::{ContentHashKey, Directory, DirectoryParseError, Uri};
+use fs::{decrypt_block, is_directory, tree_depth, tree_size, block_size};
+
+/// Options for `download`, corresponding to the flags of `gnunet-download`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DownloadOptions {
+  /// The anonymity level to request the blocks with. 0 allows the request to be traced back to us
+  /// in exchange for speed.
+  pub anonymity: u32,
+  /// If the file is a directory, download the files it lists as well, into a directory of the
+  /// same name without the `.gnd` extension.
+  pub recursive: bool,
+  /// Only look for the blocks in our own datastore, never ask other peers.
+  pub loopback_only: bool,
+}
+
+/// Progress reported by `download`.
+#[derive(Clone, Debug)]
+pub enum DownloadEvent {
+  /// Started downloading a file.
+  Started {
+    /// Where the file is being written.
+    path: PathBuf,
+    /// The size of the file.
+    size: u64,
+  },
+  /// Another block of the file was written.
+  Progress {
+    /// Where the file is being written.
+    path: PathBuf,
+    /// How many bytes of the file have been written so far.
+    completed: u64,
+    /// The size of the file.
+    size: u64,
+  },
+  /// Finished downloading a file.
+  Completed {
+    /// Where the file was written.
+    path: PathBuf,
+  },
+}
+
+/// Errors returned by `download`.
+error_def! DownloadError {
+  NotAFile { uri: Uri }
+    => "The URI does not identify a file" ("\"{}\" is not a CHK URI.", uri),
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the file-sharing service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the service or writing the file" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the file-sharing service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The file-sharing service sent an unexpected message type" ("Message type {} was not expected", ty),
+  CorruptBlock
+    => "A block of the file did not have the size its position in the file requires",
+  InvalidDirectory { #[from] cause: DirectoryParseError }
+    => "The file is not a valid directory" ("Reason: {}", cause),
+  UnsafeFilename { name: String }
+    => "A directory entry has a filename which would be written outside the target directory" ("Filename: \"{}\"", name),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {DownloadError}
+
+/// A block we have asked the service for.
+struct PendingBlock {
+  chk: ContentHashKey,
+  offset: u64,
+  depth: u32,
+}
+
+/// A connection to the file-sharing service used for one download.
+struct Downloader {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  options: DownloadOptions,
+  /// The queries of the directories whose entries have been followed.
+  visited: HashSet<HashCode>,
+}
+
+impl Downloader {
+  fn request(&mut self, query: &HashCode, depth: u32) -> Result<(), io::Error> {
+    let block_type = match depth {
+      0 => BlockType::FsDBlock,
+      _ => BlockType::FsIBlock,
+    };
+    let mut mw = self.service_writer.write_message(112, ll::GNUNET_MESSAGE_TYPE_FS_START_SEARCH);
+    mw.write_u32::<BigEndian>(self.options.loopback_only as u32).unwrap();
+    mw.write_u32::<BigEndian>(block_type as u32).unwrap();
+    mw.write_u32::<BigEndian>(self.options.anonymity).unwrap();
+    mw.write_all(&[0u8; 32]).unwrap(); // no target peer
+    query.serialize(&mut mw).unwrap();
+    mw.send()
+  }
+
+  /// Wait for the block `chk`, which starts at `offset` in the file and is at depth `depth`,
+  /// asking the service for it unless we're already waiting for an identical block.
+  fn add_pending(&mut self,
+                 pending: &mut HashMap<HashCode, Vec<PendingBlock>>,
+                 chk: ContentHashKey,
+                 offset: u64,
+                 depth: u32) -> Result<(), io::Error> {
+    let query = chk.query.clone();
+    let block = PendingBlock { chk: chk, offset: offset, depth: depth };
+    if let Some(blocks) = pending.get_mut(&query) {
+      blocks.push(block);
+      return Ok(());
+    }
+    try!(self.request(&query, depth));
+    pending.insert(query, vec![block]);
+    Ok(())
+  }
+
+  /// Wait for the next block from the service. Returns its data.
+  fn next_block(&mut self) -> Result<Vec<u8>, DownloadError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_FS_PUT {
+      return Err(DownloadError::UnexpectedMessageType { ty: tpe });
+    }
+    let _block_type = try!(mr.read_u32::<BigEndian>());
+    let _expiration = try!(mr.read_u64::<BigEndian>());
+    let _last_transmission = try!(mr.read_u64::<BigEndian>());
+    let _num_transmissions = try!(mr.read_u32::<BigEndian>());
+    let _respect_offered = try!(mr.read_u32::<BigEndian>());
+    let mut data = Vec::new();
+    try!(mr.read_to_end(&mut data));
+    Ok(data)
+  }
+
+  /// Download the file with root block `chk` and size `size` to `path`.
+  fn download_file<F>(&mut self, chk: &ContentHashKey, size: u64, path: &Path, progress: &mut F) -> Result<(), DownloadError>
+      where F: FnMut(&DownloadEvent)
+  {
+    let mut file = try!(OpenOptions::new().write(true).create(true).truncate(true).open(path));
+    try!(file.set_len(size));
+    progress(&DownloadEvent::Started { path: path.to_path_buf(), size: size });
+
+    // Identical blocks, eg. runs of zeros, have the same query so several positions in the file
+    // can be waiting on the same block.
+    let mut pending: HashMap<HashCode, Vec<PendingBlock>> = HashMap::new();
+    if size > 0 {
+      try!(self.add_pending(&mut pending, chk.clone(), 0, tree_depth(size)));
+    }
+
+    let mut completed = 0;
+    while !pending.is_empty() {
+      let data = try!(self.next_block());
+      let query = HashCode::from_buffer(&data[..]);
+      // Blocks we didn't ask for, or already have, are ignored.
+      let blocks = match pending.remove(&query) {
+        Some(b) => b,
+        None    => continue,
+      };
+      for block in blocks {
+        let plaintext = match decrypt_block(&block.chk, &data[..]) {
+          Some(p) => p,
+          None    => return Err(DownloadError::CorruptBlock),
+        };
+        if plaintext.len() != block_size(size, block.offset, block.depth) {
+          return Err(DownloadError::CorruptBlock);
+        }
+        if block.depth == 0 {
+          try!(file.seek(SeekFrom::Start(block.offset)));
+          try!(file.write_all(&plaintext[..]));
+          completed += plaintext.len() as u64;
+          progress(&DownloadEvent::Progress { path: path.to_path_buf(), completed: completed, size: size });
+          continue;
+        }
+        let child_size = tree_size(block.depth - 1);
+        let mut r = Cursor::new(plaintext);
+        let mut offset = block.offset;
+        while (r.position() as usize) < r.get_ref().len() {
+          let child = try!(ContentHashKey::deserialize(&mut r));
+          try!(self.add_pending(&mut pending, child, offset, block.depth - 1));
+          offset += child_size;
+        }
+      }
+    }
+    try!(file.flush());
+    progress(&DownloadEvent::Completed { path: path.to_path_buf() });
+    Ok(())
+  }
+
+  /// Download the file `uri` to `path`, and if it's a directory and we're downloading
+  /// recursively, the files it lists.
+  fn download_uri<F>(&mut self, uri: &Uri, path: &Path, progress: &mut F) -> Result<(), DownloadError>
+      where F: FnMut(&DownloadEvent)
+  {
+    let (chk, size) = match *uri {
+      Uri::Chk { ref key, ref query, size } => (ContentHashKey { key: key.clone(), query: query.clone() }, size),
+      _ => return Err(DownloadError::NotAFile { uri: uri.clone() }),
+    };
+    try!(self.download_file(&chk, size, path, progress));
+    if !self.options.recursive {
+      return Ok(());
+    }
+
+    let mut data = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut data));
+    if !is_directory(&data[..]) {
+      return Ok(());
+    }
+    // A directory listed more than once, eg. in several of the directories being downloaded, is
+    // only followed the first time.
+    if !self.visited.insert(chk.query.clone()) {
+      return Ok(());
+    }
+    let dir = try!(Directory::parse(&data[..]));
+    let dir_path = directory_path(path);
+    try!(fs::create_dir_all(&dir_path));
+    for (i, entry) in dir.entries.iter().enumerate() {
+      if !entry.uri.is_file() {
+        continue;
+      }
+      let name = match entry.metadata.filename() {
+        Some(name)  => try!(safe_filename(name)),
+        None        => PathBuf::from(format!("{}", i)),
+      };
+      let child_path = dir_path.join(name);
+      if let Some(parent) = child_path.parent() {
+        try!(fs::create_dir_all(parent));
+      }
+      try!(self.download_uri(&entry.uri, &child_path, progress));
+    }
+    Ok(())
+  }
+}
+
+/// The directory the contents of the GNUnet directory downloaded to `path` are written to.
+fn directory_path(path: &Path) -> PathBuf {
+  match path.extension() {
+    Some(ext) if ext == "gnd" => path.with_extension(""),
+    _                         => {
+      let mut name = path.as_os_str().to_os_string();
+      name.push(".d");
+      PathBuf::from(name)
+    },
+  }
+}
+
+/// Turn a filename from a directory entry into a relative path, refusing names which would escape
+/// the directory.
+fn safe_filename(name: &str) -> Result<PathBuf, DownloadError> {
+  let path = Path::new(name.trim_right_matches('/'));
+  let mut ret = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::Normal(c)  => ret.push(c),
+      Component::CurDir     => (),
+      _                     => return Err(DownloadError::UnsafeFilename { name: name.to_string() }),
+    }
+  }
+  if ret.as_os_str().is_empty() {
+    return Err(DownloadError::UnsafeFilename { name: name.to_string() });
+  }
+  Ok(ret)
+}
+
+/// Download the file identified by the CHK URI `uri` to `target`, as `gnunet-download` does.
+///
+/// `progress` is called as each file is started, as each block is written and as each file is
+/// finished. With `options.recursive` set, a directory is downloaded along with the files it
+/// lists, which are written to a directory named after `target` without its `.gnd` extension.
+///
+/// This blocks until the download completes. Blocks are fetched from other peers if they are not
+/// in our own datastore, so this may take a long time or never finish if nobody has the file.
+pub fn download<F>(cfg: &Cfg, uri: &Uri, target: &Path, options: DownloadOptions, mut progress: F) -> Result<(), DownloadError>
+    where F: FnMut(&DownloadEvent)
+{
+  if !uri.is_file() {
+    return Err(DownloadError::NotAFile { uri: uri.clone() });
+  }
+  let (service_reader, service_writer) = try!(service::connect(cfg, "fs"));
+  let mut downloader = Downloader {
+    service_reader: service_reader,
+    service_writer: service_writer,
+    options: options,
+    visited: HashSet::new(),
+  };
+  downloader.download_uri(uri, target, &mut progress)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path, PathBuf};
+  use super::{directory_path, safe_filename};
+
+  #[test]
+  fn test_download_paths() {
+    assert_eq!(directory_path(Path::new("/tmp/music.gnd")), PathBuf::from("/tmp/music"));
+    assert_eq!(directory_path(Path::new("/tmp/music")), PathBuf::from("/tmp/music.d"));
+    assert_eq!(safe_filename("album/track.ogg").unwrap(), PathBuf::from("album/track.ogg"));
+    assert_eq!(safe_filename("album/").unwrap(), PathBuf::from("album"));
+    assert!(safe_filename("../etc/passwd").is_err());
+    assert!(safe_filename("/etc/passwd").is_err());
+  }
+}
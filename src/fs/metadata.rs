@@ -0,0 +1,444 @@
+use std::io::{self, Read, Write};
+use std::str::from_utf8;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use ll;
+
+/// The version of the serialization format.
+const HEADER_VERSION: u32 = 2;
+const HEADER_VERSION_MASK: u32 = 0x7fffffff;
+/// Set in the version field if the entries are zlib-compressed.
+const HEADER_COMPRESSED: u32 = 0x80000000;
+
+const HEADER_SIZE: usize = 12;
+const ENTRY_SIZE: usize = 20;
+
+/// The largest amount of (decompressed) metadata we are willing to deserialize.
+const MAX_META_DATA_SIZE: usize = 1 << 24;
+
+/// The kind of a piece of metadata. These are the `EXTRACTOR_MetaType` values of libextractor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MetaType {
+  Reserved,
+  MimeType,
+  Filename,
+  Comment,
+  Title,
+  AuthorName,
+  Keywords,
+  Thumbnail,
+  /// The name of the file as it was published, set by GNUnet FS.
+  OriginalFilename,
+  /// Any other libextractor meta type.
+  Other(u32),
+}
+
+impl MetaType {
+  /// Create a `MetaType` from its libextractor number.
+  pub fn from_u32(x: u32) -> MetaType {
+    match x {
+      ll::EXTRACTOR_METATYPE_RESERVED                 => MetaType::Reserved,
+      ll::EXTRACTOR_METATYPE_MIMETYPE                 => MetaType::MimeType,
+      ll::EXTRACTOR_METATYPE_FILENAME                 => MetaType::Filename,
+      ll::EXTRACTOR_METATYPE_COMMENT                  => MetaType::Comment,
+      ll::EXTRACTOR_METATYPE_TITLE                    => MetaType::Title,
+      ll::EXTRACTOR_METATYPE_AUTHOR_NAME              => MetaType::AuthorName,
+      ll::EXTRACTOR_METATYPE_KEYWORDS                 => MetaType::Keywords,
+      ll::EXTRACTOR_METATYPE_THUMBNAIL                => MetaType::Thumbnail,
+      ll::EXTRACTOR_METATYPE_GNUNET_ORIGINAL_FILENAME => MetaType::OriginalFilename,
+      x                                               => MetaType::Other(x),
+    }
+  }
+
+  /// The libextractor number of this `MetaType`.
+  pub fn to_u32(&self) -> u32 {
+    match *self {
+      MetaType::Reserved          => ll::EXTRACTOR_METATYPE_RESERVED,
+      MetaType::MimeType          => ll::EXTRACTOR_METATYPE_MIMETYPE,
+      MetaType::Filename          => ll::EXTRACTOR_METATYPE_FILENAME,
+      MetaType::Comment           => ll::EXTRACTOR_METATYPE_COMMENT,
+      MetaType::Title             => ll::EXTRACTOR_METATYPE_TITLE,
+      MetaType::AuthorName        => ll::EXTRACTOR_METATYPE_AUTHOR_NAME,
+      MetaType::Keywords          => ll::EXTRACTOR_METATYPE_KEYWORDS,
+      MetaType::Thumbnail         => ll::EXTRACTOR_METATYPE_THUMBNAIL,
+      MetaType::OriginalFilename  => ll::EXTRACTOR_METATYPE_GNUNET_ORIGINAL_FILENAME,
+      MetaType::Other(x)          => x,
+    }
+  }
+}
+
+/// The format of the data of a piece of metadata. These are the `EXTRACTOR_MetaFormat` values of
+/// libextractor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MetaFormat {
+  Unknown,
+  /// A 0-terminated UTF-8 string.
+  Utf8,
+  /// Binary data, eg. an image.
+  Binary,
+  /// A 0-terminated string in an unspecified encoding.
+  CString,
+}
+
+impl MetaFormat {
+  /// Create a `MetaFormat` from its libextractor number.
+  pub fn from_u32(x: u32) -> Option<MetaFormat> {
+    match x {
+      ll::EXTRACTOR_METAFORMAT_UNKNOWN  => Some(MetaFormat::Unknown),
+      ll::EXTRACTOR_METAFORMAT_UTF8     => Some(MetaFormat::Utf8),
+      ll::EXTRACTOR_METAFORMAT_BINARY   => Some(MetaFormat::Binary),
+      ll::EXTRACTOR_METAFORMAT_C_STRING => Some(MetaFormat::CString),
+      _                                 => None,
+    }
+  }
+
+  /// The libextractor number of this `MetaFormat`.
+  pub fn to_u32(&self) -> u32 {
+    match *self {
+      MetaFormat::Unknown => ll::EXTRACTOR_METAFORMAT_UNKNOWN,
+      MetaFormat::Utf8    => ll::EXTRACTOR_METAFORMAT_UTF8,
+      MetaFormat::Binary  => ll::EXTRACTOR_METAFORMAT_BINARY,
+      MetaFormat::CString => ll::EXTRACTOR_METAFORMAT_C_STRING,
+    }
+  }
+
+  fn is_string(&self) -> bool {
+    *self == MetaFormat::Utf8 || *self == MetaFormat::CString
+  }
+}
+
+/// A single piece of metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetaItem {
+  /// The name of the libextractor plugin which produced the item, if known.
+  pub plugin_name: Option<String>,
+  /// What kind of metadata this is.
+  pub meta_type: MetaType,
+  /// The format of `data`.
+  pub format: MetaFormat,
+  /// The mime type of `data`, if known. Typically used for thumbnails.
+  pub data_mime_type: Option<String>,
+  /// The data. For string formats this includes the terminating 0 byte.
+  pub data: Vec<u8>,
+}
+
+impl MetaItem {
+  /// The data as a string, if the item has a string format and the data is valid UTF-8.
+  pub fn as_str(&self) -> Option<&str> {
+    if !self.format.is_string() {
+      return None;
+    }
+    match self.data.split_last() {
+      Some((&0, s)) => from_utf8(s).ok(),
+      _             => None,
+    }
+  }
+}
+
+/// Metadata describing a file, as produced by libextractor and published alongside files in GNUnet
+/// file-sharing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetaData {
+  items: Vec<MetaItem>,
+}
+
+/// Errors returned by `MetaData::deserialize`.
+error_def! MetaDataDeserializeError {
+  TooShort
+    => "The metadata is too short to contain a header",
+  UnsupportedVersion { version: u32 }
+    => "The metadata has an unsupported version" ("Version: {}", version),
+  TooLarge { size: usize }
+    => "The metadata is too large" ("Decompressed size: {} bytes", size),
+  Decompress { #[from] cause: io::Error }
+    => "Failed to decompress the metadata" ("Reason: {}", cause),
+  SizeMismatch
+    => "The size of the metadata does not match its header",
+  InvalidFormat { format: u32 }
+    => "A metadata entry has an invalid format" ("Format: {}", format),
+  Malformed
+    => "A metadata entry is malformed",
+}
+
+/// Read a 0-terminated string of `len` bytes, including the terminator, starting at `start`.
+fn read_c_string(mdata: &[u8], start: usize, len: usize) -> Result<Option<String>, MetaDataDeserializeError> {
+  if len == 0 {
+    return Ok(None);
+  }
+  let bytes = &mdata[start..start + len];
+  if bytes[len - 1] != 0 {
+    return Err(MetaDataDeserializeError::Malformed);
+  }
+  match from_utf8(&bytes[..len - 1]) {
+    Ok(s)   => Ok(Some(s.to_string())),
+    Err(_)  => Err(MetaDataDeserializeError::Malformed),
+  }
+}
+
+impl MetaData {
+  /// Create an empty metadata container.
+  pub fn new() -> MetaData {
+    MetaData {
+      items: Vec::new(),
+    }
+  }
+
+  /// Add an item. Returns `false` and does nothing if an item with the same type and data is
+  /// already present.
+  pub fn insert(&mut self, item: MetaItem) -> bool {
+    if self.items.iter().any(|i| i.meta_type == item.meta_type && i.data == item.data) {
+      return false;
+    }
+    self.items.push(item);
+    true
+  }
+
+  /// Add a UTF-8 string item. Returns `false` if an identical item is already present.
+  pub fn insert_str(&mut self, meta_type: MetaType, value: &str) -> bool {
+    let mut data = Vec::with_capacity(value.len() + 1);
+    data.extend_from_slice(value.as_bytes());
+    data.push(0);
+    self.insert(MetaItem {
+      plugin_name: None,
+      meta_type: meta_type,
+      format: MetaFormat::Utf8,
+      data_mime_type: None,
+      data: data,
+    })
+  }
+
+  /// Remove all items of type `meta_type`. Returns the number of items removed.
+  pub fn remove_type(&mut self, meta_type: MetaType) -> usize {
+    let before = self.items.len();
+    self.items.retain(|i| i.meta_type != meta_type);
+    before - self.items.len()
+  }
+
+  /// Iterate over the items.
+  pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, MetaItem> {
+    self.items.iter()
+  }
+
+  /// The number of items.
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  /// Returns `true` if there are no items.
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+
+  /// The first string item of type `meta_type`.
+  pub fn get_str(&self, meta_type: MetaType) -> Option<&str> {
+    self.items.iter()
+              .filter(|i| i.meta_type == meta_type)
+              .filter_map(|i| i.as_str())
+              .next()
+  }
+
+  /// The mime type of the file.
+  pub fn mime_type(&self) -> Option<&str> {
+    self.get_str(MetaType::MimeType)
+  }
+
+  /// The name of the file. Prefers the name the file was published under over names found by
+  /// libextractor.
+  pub fn filename(&self) -> Option<&str> {
+    self.get_str(MetaType::OriginalFilename).or_else(|| self.get_str(MetaType::Filename))
+  }
+
+  /// The keywords describing the file.
+  pub fn keywords(&self) -> Vec<&str> {
+    self.items.iter()
+              .filter(|i| i.meta_type == MetaType::Keywords)
+              .filter_map(|i| i.as_str())
+              .collect()
+  }
+
+  /// The thumbnail of the file, if any, along with its mime type.
+  pub fn thumbnail(&self) -> Option<(&[u8], Option<&str>)> {
+    self.items.iter()
+              .filter(|i| i.meta_type == MetaType::Thumbnail && i.format == MetaFormat::Binary)
+              .map(|i| (&i.data[..], i.data_mime_type.as_ref().map(|m| &m[..])))
+              .next()
+  }
+
+  /// Serialize the metadata in the format used by `GNUNET_CONTAINER_meta_data_serialize`.
+  ///
+  /// The entries are compressed with zlib when that makes them smaller, as GNUnet does.
+  pub fn serialize(&self) -> Vec<u8> {
+    if self.items.is_empty() {
+      let mut ret = Vec::with_capacity(HEADER_SIZE);
+      ret.write_u32::<BigEndian>(HEADER_VERSION).unwrap();
+      ret.write_u32::<BigEndian>(0).unwrap();
+      ret.write_u32::<BigEndian>(0).unwrap();
+      return ret;
+    }
+
+    // The entry table comes first. The item data is written backwards from the end, each item's
+    // data followed (going backwards) by its plugin name then its mime type.
+    let mut size = self.items.len() * ENTRY_SIZE;
+    for item in self.items.iter() {
+      size += item.data.len();
+      size += item.plugin_name.as_ref().map_or(0, |p| p.len() + 1);
+      size += item.data_mime_type.as_ref().map_or(0, |m| m.len() + 1);
+    }
+    let mut mdata = vec![0u8; size];
+    let mut off = size;
+    for (i, item) in self.items.iter().enumerate() {
+      let plen = item.plugin_name.as_ref().map_or(0, |p| p.len() + 1);
+      let mlen = item.data_mime_type.as_ref().map_or(0, |m| m.len() + 1);
+      {
+        let ent = &mut mdata[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+        BigEndian::write_u32(&mut ent[0..4], item.meta_type.to_u32());
+        BigEndian::write_u32(&mut ent[4..8], item.format.to_u32());
+        BigEndian::write_u32(&mut ent[8..12], item.data.len() as u32);
+        BigEndian::write_u32(&mut ent[12..16], plen as u32);
+        BigEndian::write_u32(&mut ent[16..20], mlen as u32);
+      }
+      off -= item.data.len();
+      mdata[off..off + item.data.len()].clone_from_slice(&item.data[..]);
+      off -= plen;
+      if let Some(ref p) = item.plugin_name {
+        mdata[off..off + p.len()].clone_from_slice(p.as_bytes());
+      }
+      off -= mlen;
+      if let Some(ref m) = item.data_mime_type {
+        mdata[off..off + m.len()].clone_from_slice(m.as_bytes());
+      }
+    }
+    debug_assert_eq!(off, self.items.len() * ENTRY_SIZE);
+
+    let compressed = {
+      let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Best);
+      encoder.write_all(&mdata[..]).and_then(|_| encoder.finish()).ok()
+    };
+    let (version, body) = match compressed {
+      Some(c) if c.len() < mdata.len()  => (HEADER_VERSION | HEADER_COMPRESSED, c),
+      _                                 => (HEADER_VERSION, mdata),
+    };
+    let mut ret = Vec::with_capacity(HEADER_SIZE + body.len());
+    ret.write_u32::<BigEndian>(version).unwrap();
+    ret.write_u32::<BigEndian>(self.items.len() as u32).unwrap();
+    ret.write_u32::<BigEndian>(size as u32).unwrap();
+    ret.extend_from_slice(&body[..]);
+    ret
+  }
+
+  /// Deserialize metadata produced by `serialize` or by GNUnet's
+  /// `GNUNET_CONTAINER_meta_data_serialize`, compressed or not.
+  pub fn deserialize(buf: &[u8]) -> Result<MetaData, MetaDataDeserializeError> {
+    if buf.len() < HEADER_SIZE {
+      return Err(MetaDataDeserializeError::TooShort);
+    }
+    let version = BigEndian::read_u32(&buf[0..4]);
+    let count = BigEndian::read_u32(&buf[4..8]) as usize;
+    let size = BigEndian::read_u32(&buf[8..12]) as usize;
+    if version & HEADER_VERSION_MASK != HEADER_VERSION {
+      return Err(MetaDataDeserializeError::UnsupportedVersion { version: version & HEADER_VERSION_MASK });
+    }
+    if size > MAX_META_DATA_SIZE {
+      return Err(MetaDataDeserializeError::TooLarge { size: size });
+    }
+    if count.checked_mul(ENTRY_SIZE).map_or(true, |s| s > size) {
+      return Err(MetaDataDeserializeError::SizeMismatch);
+    }
+
+    let body = &buf[HEADER_SIZE..];
+    let decompressed;
+    let mdata = if version & HEADER_COMPRESSED != 0 {
+      let mut out = Vec::with_capacity(size);
+      try!(ZlibDecoder::new(body).take(size as u64 + 1).read_to_end(&mut out));
+      decompressed = out;
+      &decompressed[..]
+    }
+    else {
+      body
+    };
+    if mdata.len() != size {
+      return Err(MetaDataDeserializeError::SizeMismatch);
+    }
+
+    let mut ret = MetaData::new();
+    let mut left = size - count * ENTRY_SIZE;
+    for i in 0..count {
+      let ent = &mdata[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+      let meta_type = MetaType::from_u32(BigEndian::read_u32(&ent[0..4]));
+      let format_u32 = BigEndian::read_u32(&ent[4..8]);
+      let format = match MetaFormat::from_u32(format_u32) {
+        Some(f) => f,
+        None    => return Err(MetaDataDeserializeError::InvalidFormat { format: format_u32 }),
+      };
+      let dlen = BigEndian::read_u32(&ent[8..12]) as usize;
+      let plen = BigEndian::read_u32(&ent[12..16]) as usize;
+      let mlen = BigEndian::read_u32(&ent[16..20]) as usize;
+
+      if dlen > left {
+        return Err(MetaDataDeserializeError::Malformed);
+      }
+      left -= dlen;
+      let data = &mdata[count * ENTRY_SIZE + left..count * ENTRY_SIZE + left + dlen];
+      if format.is_string() && data.last() != Some(&0) {
+        return Err(MetaDataDeserializeError::Malformed);
+      }
+      if plen > left {
+        return Err(MetaDataDeserializeError::Malformed);
+      }
+      left -= plen;
+      let plugin_name = try!(read_c_string(mdata, count * ENTRY_SIZE + left, plen));
+      if mlen > left {
+        return Err(MetaDataDeserializeError::Malformed);
+      }
+      left -= mlen;
+      let data_mime_type = try!(read_c_string(mdata, count * ENTRY_SIZE + left, mlen));
+
+      ret.insert(MetaItem {
+        plugin_name: plugin_name,
+        meta_type: meta_type,
+        format: format,
+        data_mime_type: data_mime_type,
+        data: data.to_vec(),
+      });
+    }
+    Ok(ret)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_metadata_round_trip() {
+    let mut md = MetaData::new();
+    assert!(md.insert_str(MetaType::MimeType, "image/png"));
+    assert!(md.insert_str(MetaType::Filename, "cat.png"));
+    assert!(md.insert_str(MetaType::Keywords, "cat"));
+    assert!(md.insert_str(MetaType::Keywords, "photo"));
+    assert!(!md.insert_str(MetaType::Keywords, "cat"));
+    assert!(md.insert(MetaItem {
+      plugin_name: Some("png".to_string()),
+      meta_type: MetaType::Thumbnail,
+      format: MetaFormat::Binary,
+      data_mime_type: Some("image/png".to_string()),
+      data: vec![0x89, 0x50, 0x4e, 0x47, 0x00, 0x01],
+    }));
+
+    let buf = md.serialize();
+    let md2 = MetaData::deserialize(&buf[..]).unwrap();
+    assert_eq!(md, md2);
+    assert_eq!(md2.mime_type(), Some("image/png"));
+    assert_eq!(md2.filename(), Some("cat.png"));
+    assert_eq!(md2.keywords(), vec!["cat", "photo"]);
+    assert_eq!(md2.thumbnail(), Some((&[0x89, 0x50, 0x4e, 0x47, 0x00, 0x01][..], Some("image/png"))));
+
+    let empty = MetaData::new().serialize();
+    assert_eq!(empty.len(), 12);
+    assert!(MetaData::deserialize(&empty[..]).unwrap().is_empty());
+    assert!(MetaData::deserialize(&buf[..buf.len() - 1]).is_err());
+  }
+}
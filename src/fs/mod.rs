@@ -0,0 +1,23 @@
+//! Module for GNUnet file-sharing.
+
+pub use self::chk::*;
+pub use self::directory::*;
+pub use self::download::*;
+pub use self::metadata::*;
+pub use self::namespace::*;
+pub use self::publish::*;
+pub use self::search::*;
+pub use self::ublock::*;
+pub use self::unindex::*;
+pub use self::uri::*;
+
+mod chk;
+mod directory;
+mod download;
+mod metadata;
+mod namespace;
+mod publish;
+mod search;
+mod ublock;
+mod unindex;
+mod uri;
@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use HashCode;
+use block::BlockType;
+use datastore::{Datastore, StoreOptions};
+use service::{self, ServiceReader, ReadMessageError};
+use fs::{MetaData, PublishError, PublishOptions, UBlockContents, Uri};
+use fs::{build_ublock, decrypt_ublock, ublock_query};
+
+/// Publish `uri` in the namespace of the ego key `namespace` under `identifier`.
+///
+/// `update` names the identifier a later version of this entry will be published under, which
+/// lets the publisher replace the content while searchers follow the chain of updates. Returns
+/// the SKS URI of the entry.
+///
+/// The block is put in the local datastore, from where other peers can fetch it.
+pub fn publish_in_namespace(cfg: &Cfg,
+                            namespace: &EcdsaPrivateKey,
+                            identifier: &str,
+                            update: Option<&str>,
+                            uri: &Uri,
+                            metadata: &MetaData,
+                            options: PublishOptions) -> Result<Uri, PublishError> {
+  let contents = UBlockContents {
+    update: update.map(|u| u.to_string()),
+    uri: uri.clone(),
+    metadata: metadata.clone(),
+  };
+  let (query, block) = build_ublock(namespace, identifier, &contents);
+  let store = StoreOptions {
+    priority: options.priority,
+    anonymity: options.anonymity,
+    replication: options.replication,
+    expiration: options.expiration,
+  };
+  let mut datastore = try!(Datastore::connect(cfg));
+  try!(datastore.put(&query, &block[..], BlockType::FsUBlock, &store));
+  Ok(Uri::Sks {
+    namespace: namespace.get_public(),
+    identifier: identifier.to_string(),
+  })
+}
+
+/// Errors returned by `search_namespace`.
+error_def! NamespaceSearchError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the file-sharing service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the file-sharing service" ("Specifically: {}", cause),
+}
+
+/// Errors returned by `NamespaceSearch::next`.
+error_def! NextNamespaceResultError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the file-sharing service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the file-sharing service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The file-sharing service sent an unexpected message type" ("Message type {} was not expected", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {NextNamespaceResultError}
+
+/// The entries found by `search_namespace`.
+///
+/// The search runs until this is dropped. Iterating blocks until the next entry arrives, which may
+/// be never if nobody has published one.
+pub struct NamespaceSearch {
+  service_reader: ServiceReader,
+  namespace: EcdsaPublicKey,
+  identifier: String,
+  seen: HashSet<HashCode>,
+}
+
+/// Search for the entry published under `identifier` in the namespace `namespace`, as
+/// `gnunet-search` does for an SKS URI.
+///
+/// Every version of the entry published under `identifier` is returned. To follow the updates of
+/// an entry, search again for the `update` identifier of each result.
+pub fn search_namespace(cfg: &Cfg, namespace: &EcdsaPublicKey, identifier: &str, anonymity: u32) -> Result<NamespaceSearch, NamespaceSearchError> {
+  let (service_reader, mut service_writer) = try!(service::connect(cfg, "fs"));
+  let query = ublock_query(namespace, identifier);
+  let mut mw = service_writer.write_message(112, ll::GNUNET_MESSAGE_TYPE_FS_START_SEARCH);
+  mw.write_u32::<BigEndian>(0).unwrap(); // options
+  mw.write_u32::<BigEndian>(BlockType::FsUBlock as u32).unwrap();
+  mw.write_u32::<BigEndian>(anonymity).unwrap();
+  mw.write_all(&[0u8; 32]).unwrap(); // no target peer
+  query.serialize(&mut mw).unwrap();
+  try!(mw.send());
+  Ok(NamespaceSearch {
+    service_reader: service_reader,
+    namespace: namespace.clone(),
+    identifier: identifier.to_string(),
+    seen: HashSet::new(),
+  })
+}
+
+impl Iterator for NamespaceSearch {
+  type Item = Result<UBlockContents, NextNamespaceResultError>;
+
+  fn next(&mut self) -> Option<Result<UBlockContents, NextNamespaceResultError>> {
+    loop {
+      let (tpe, mut mr) = match self.service_reader.read_message() {
+        Ok(x)   => x,
+        Err(e)  => return Some(Err(NextNamespaceResultError::ReadMessage { cause: e })),
+      };
+      if tpe != ll::GNUNET_MESSAGE_TYPE_FS_PUT {
+        return Some(Err(NextNamespaceResultError::UnexpectedMessageType { ty: tpe }));
+      }
+      // block type, expiration, last transmission, number of transmissions and respect offered
+      let mut header = [0u8; 4 + 8 + 8 + 4 + 4];
+      if let Err(e) = mr.read_exact(&mut header) {
+        return Some(Err(NextNamespaceResultError::Io { cause: e }));
+      }
+      let mut block = Vec::new();
+      if let Err(e) = mr.read_to_end(&mut block) {
+        return Some(Err(NextNamespaceResultError::Io { cause: e }));
+      }
+      // The same block often arrives from several peers.
+      if !self.seen.insert(HashCode::from_buffer(&block[..])) {
+        continue;
+      }
+      // Blocks which don't verify are ignored, they may be garbage from a malicious peer.
+      if let Some(contents) = decrypt_ublock(&self.namespace, &self.identifier, &block[..]) {
+        return Some(Ok(contents));
+      }
+    }
+  }
+}
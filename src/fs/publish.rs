@@ -0,0 +1,326 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use byteorder::{BigEndian, WriteBytesExt};
+use num::ToPrimitive;
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use HashCode;
+use activity::{self, Activity};
+use block::BlockType;
+use datastore::{Datastore, DatastoreError, StoreOptions};
+use service::{self, ReadMessageError};
+use fs::{EncodedBlock, MetaData, MetaType, TreeEncoder, UBlockContents, Uri, build_ublock};
+
+/// Options for `publish`, corresponding to the flags of `gnunet-publish`.
+#[derive(Copy, Clone, Debug)]
+pub struct PublishOptions {
+  /// Index the file rather than inserting it. An indexed file's data blocks are read from the
+  /// file itself when they are requested, so it must not be moved or modified while it is shared.
+  /// Inserting copies the whole file into the datastore.
+  pub index: bool,
+  /// The anonymity level other peers must provide to get the file's blocks from us.
+  pub anonymity: u32,
+  /// How important the file's blocks are relative to others in the datastore.
+  pub priority: u32,
+  /// How many peers the file's blocks should be pushed to proactively.
+  pub replication: u32,
+  /// When the published blocks expire, in microseconds since the epoch.
+  pub expiration: u64,
+}
+
+impl Default for PublishOptions {
+  /// Index the file with the same defaults as `gnunet-publish`.
+  fn default() -> PublishOptions {
+    let store = StoreOptions::default();
+    PublishOptions {
+      index: true,
+      anonymity: store.anonymity,
+      priority: store.priority,
+      replication: store.replication,
+      expiration: store.expiration,
+    }
+  }
+}
+
+/// The URIs of a published file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublishResult {
+  /// The URI identifying the file by its content. This is enough to download the file.
+  pub chk: Uri,
+  /// The keyword URI the file was published under, or `None` if no keywords were given.
+  pub ksk: Option<Uri>,
+}
+
+/// Errors returned by `publish`, `publish_reader` and `publish_data`.
+error_def! PublishError {
+  NotAFile
+    => "The path to publish is not a regular file",
+  CannotIndex
+    => "Only files can be indexed, data from a reader has to be inserted",
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the datastore or file-sharing service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error reading the file or communicating with a service" ("Specifically: {}", cause),
+  Datastore { #[from] cause: DatastoreError }
+    => "Failed to store a block in the datastore" ("Reason: {}", cause),
+  IndexFailed { message: String }
+    => "The file-sharing service refused to index the file" ("Reason: {}", message),
+  FilenameTooLong
+    => "The path of the file is too long to index",
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the file-sharing service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The file-sharing service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {PublishError}
+
+/// Ask the file-sharing service to serve the data blocks of the file at `path`, whose hash is
+/// `file_id`, from the file itself.
+fn start_indexing(cfg: &Cfg, path: &Path, file_id: &HashCode) -> Result<(), PublishError> {
+  let filename = path.to_string_lossy().into_owned();
+  let msg_length = match (88 + filename.len() + 1).to_u16() {
+    Some(l) => l,
+    None    => return Err(PublishError::FilenameTooLong),
+  };
+  let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "fs"));
+  {
+    let mut mw = service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_FS_INDEX_START);
+    mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+    // We don't give the service the device and inode, so it hashes the file itself to check
+    // it's the one we mean.
+    mw.write_u64::<BigEndian>(0).unwrap();
+    mw.write_u64::<BigEndian>(0).unwrap();
+    file_id.serialize(&mut mw).unwrap();
+    mw.write_all(filename.as_bytes()).unwrap();
+    mw.write_u8(0).unwrap();
+    try!(mw.send());
+  }
+  let (tpe, mut mr) = try!(service_reader.read_message());
+  match tpe {
+    ll::GNUNET_MESSAGE_TYPE_FS_INDEX_START_OK => Ok(()),
+    ll::GNUNET_MESSAGE_TYPE_FS_INDEX_START_FAILED => {
+      let mut msg = Vec::new();
+      try!(mr.read_to_end(&mut msg));
+      while msg.last() == Some(&0) {
+        msg.pop();
+      }
+      Err(PublishError::IndexFailed { message: String::from_utf8_lossy(&msg[..]).into_owned() })
+    },
+    x => Err(PublishError::UnexpectedMessageType { ty: x }),
+  }
+}
+
+/// Store a block of the file's tree. When indexing, data blocks are replaced by on-demand blocks
+/// telling the service where in the file to read them from.
+fn store_block(datastore: &mut Datastore,
+               block: EncodedBlock,
+               file_id: Option<&HashCode>,
+               store: &StoreOptions) -> Result<(), PublishError> {
+  if block.depth > 0 {
+    return Ok(try!(datastore.put(&block.chk.query, &block.data[..], BlockType::FsIBlock, store)));
+  }
+  match file_id {
+    Some(file_id) => {
+      let mut ondemand = Vec::with_capacity(72);
+      file_id.serialize(&mut ondemand).unwrap();
+      ondemand.write_u64::<BigEndian>(block.offset).unwrap();
+      Ok(try!(datastore.put(&block.chk.query, &ondemand[..], BlockType::FsOnDemand, store)))
+    },
+    None => Ok(try!(datastore.put(&block.chk.query, &block.data[..], BlockType::FsDBlock, store))),
+  }
+}
+
+/// Publish the file at `path`, as `gnunet-publish` does.
+///
+/// The file is split into encrypted blocks which are put in the local datastore, either copied
+/// there or, with `options.index` set, indexed so they are read from the file on demand. If any
+/// `keywords` are given, the file's URI and `metadata` are also published under each of them so
+/// that keyword searches find it. The file's name is added to the metadata if it doesn't have
+/// one.
+///
+/// Returns the CHK URI which identifies the file and the KSK URI of the keywords.
+pub fn publish(cfg: &Cfg,
+               path: &Path,
+               keywords: &[&str],
+               metadata: &MetaData,
+               options: PublishOptions) -> Result<PublishResult, PublishError> {
+  let path = try!(fs::canonicalize(path));
+  let file_meta = try!(fs::metadata(&path));
+  if !file_meta.is_file() {
+    return Err(PublishError::NotAFile);
+  }
+  let size = file_meta.len();
+  let mut file = try!(File::open(&path));
+
+  let file_id = match options.index {
+    true  => {
+      let file_id = try!(HashCode::from_reader(&mut file));
+      try!(file.seek(SeekFrom::Start(0)));
+      try!(start_indexing(cfg, &path, &file_id));
+      Some(file_id)
+    },
+    false => None,
+  };
+
+  let store = store_options(&options);
+  let mut datastore = try!(Datastore::connect(cfg));
+  let mut encoder = TreeEncoder::new(size);
+  let mut buf = Vec::new();
+  while encoder.offset() < size {
+    buf.resize(encoder.next_block_size(), 0);
+    try!(file.read_exact(&mut buf[..]));
+    try!(encoder.push(&buf[..], &mut |b| store_block(&mut datastore, b, file_id.as_ref(), &store)));
+  }
+  let chk = try!(encoder.finish(&mut |b| store_block(&mut datastore, b, file_id.as_ref(), &store)));
+  let chk_uri = Uri::Chk {
+    key: chk.key,
+    query: chk.query,
+    size: size,
+  };
+
+  let mut metadata = metadata.clone();
+  if metadata.filename().is_none() {
+    if let Some(name) = path.file_name() {
+      metadata.insert_str(MetaType::Filename, &name.to_string_lossy());
+    }
+  }
+  publish_keywords(cfg, &mut datastore, chk_uri, keywords, metadata, &store)
+}
+
+/// Publish the data read from `reader`, as `publish` does for a file.
+///
+/// The data is encrypted and stored a block at a time as it is read, so it never has to be held
+/// in memory or written to a file first. The blocks are always inserted into the datastore;
+/// `options.index` must not be set since there is no file to serve them from.
+pub fn publish_reader<R: Read>(cfg: &Cfg,
+                               mut reader: R,
+                               keywords: &[&str],
+                               metadata: &MetaData,
+                               options: PublishOptions) -> Result<PublishResult, PublishError> {
+  if options.index {
+    return Err(PublishError::CannotIndex);
+  }
+  let store = store_options(&options);
+  let mut datastore = try!(Datastore::connect(cfg));
+  let mut encoder = TreeEncoder::streaming();
+  let mut buf = vec![0u8; encoder.next_block_size()];
+  loop {
+    let n = try!(read_block(&mut reader, &mut buf[..]));
+    if n > 0 {
+      try!(encoder.push(&buf[..n], &mut |b| store_block(&mut datastore, b, None, &store)));
+    }
+    if n < buf.len() {
+      break;
+    }
+  }
+  let size = encoder.offset();
+  let chk = try!(encoder.finish(&mut |b| store_block(&mut datastore, b, None, &store)));
+  let chk_uri = Uri::Chk {
+    key: chk.key,
+    query: chk.query,
+    size: size,
+  };
+  publish_keywords(cfg, &mut datastore, chk_uri, keywords, metadata.clone(), &store)
+}
+
+/// Publish `data`, as `publish` does for a file. The blocks are always inserted into the
+/// datastore; `options.index` must not be set.
+pub fn publish_data(cfg: &Cfg,
+                    data: &[u8],
+                    keywords: &[&str],
+                    metadata: &MetaData,
+                    options: PublishOptions) -> Result<PublishResult, PublishError> {
+  publish_reader(cfg, data, keywords, metadata, options)
+}
+
+fn store_options(options: &PublishOptions) -> StoreOptions {
+  StoreOptions {
+    priority: options.priority,
+    anonymity: options.anonymity,
+    replication: options.replication,
+    expiration: options.expiration,
+  }
+}
+
+/// Fill `buf` from `reader`, stopping early only at the end of the stream. Returns the number of
+/// bytes read.
+fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, io::Error> {
+  let mut n = 0;
+  while n < buf.len() {
+    match reader.read(&mut buf[n..]) {
+      Ok(0)   => break,
+      Ok(m)   => n += m,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+      Err(e)  => return Err(e),
+    }
+  }
+  Ok(n)
+}
+
+/// Publish the file's URI under its keywords and log the published file to the audit log.
+fn publish_keywords(cfg: &Cfg,
+                    datastore: &mut Datastore,
+                    chk_uri: Uri,
+                    keywords: &[&str],
+                    metadata: MetaData,
+                    store: &StoreOptions) -> Result<PublishResult, PublishError> {
+  let ksk = match keywords.is_empty() {
+    true  => None,
+    false => {
+      let contents = UBlockContents {
+        update: None,
+        uri: chk_uri.clone(),
+        metadata: metadata,
+      };
+      let anonymous = EcdsaPrivateKey::anonymous();
+      for keyword in keywords {
+        let (query, block) = build_ublock(&anonymous, keyword, &contents);
+        try!(datastore.put(&query, &block[..], BlockType::FsUBlock, store));
+      }
+      Some(Uri::Ksk { keywords: keywords.iter().map(|k| k.to_string()).collect() })
+    },
+  };
+  activity::log(cfg, &Activity::FilePublished {
+    uri: chk_uri.to_string(),
+    keywords: ksk.as_ref().map(|k| k.to_string()),
+  });
+  Ok(PublishResult {
+    chk: chk_uri,
+    ksk: ksk,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{self, Read};
+  use super::read_block;
+
+  /// Hands out at most three bytes per read.
+  struct Trickle<'a>(&'a [u8]);
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+      let n = ::std::cmp::min(3, ::std::cmp::min(buf.len(), self.0.len()));
+      buf[..n].copy_from_slice(&self.0[..n]);
+      self.0 = &self.0[n..];
+      Ok(n)
+    }
+  }
+
+  #[test]
+  fn test_read_block() {
+    let data = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    let mut reader = Trickle(&data[..]);
+    let mut buf = [0u8; 8];
+    assert_eq!(read_block(&mut reader, &mut buf[..]).unwrap(), 8);
+    assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(read_block(&mut reader, &mut buf[..]).unwrap(), 2);
+    assert_eq!(&buf[..2], &[9, 10]);
+    assert_eq!(read_block(&mut reader, &mut buf[..]).unwrap(), 0);
+  }
+}
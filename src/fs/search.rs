@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use fs::{MetaData, Uri};
+
+/// A single search result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+  /// The URI of the file that was found.
+  pub uri: Uri,
+  /// The metadata published with the file.
+  pub metadata: MetaData,
+  /// How available the file is. Positive if probes to download the file mostly succeeded,
+  /// negative if they mostly failed.
+  pub availability_rank: i32,
+  /// How many probes `availability_rank` is based on.
+  pub availability_certainty: u32,
+  /// How many of the search's keywords the result matched.
+  pub applicability_rank: u32,
+}
+
+/// A collection of search results which removes duplicates and ranks what's left.
+///
+/// The same file is often found several times, through different keywords or from different
+/// peers. Results with the same URI are merged into one: their metadata is combined and the best
+/// ranks are kept.
+#[derive(Clone, Debug, Default)]
+pub struct SearchResults {
+  results: Vec<SearchResult>,
+  index: HashMap<Uri, usize>,
+}
+
+impl SearchResults {
+  /// Create an empty collection.
+  pub fn new() -> SearchResults {
+    SearchResults {
+      results: Vec::new(),
+      index: HashMap::new(),
+    }
+  }
+
+  /// Add a result. Returns `true` if its URI hasn't been seen before, or `false` if it was merged
+  /// into an earlier result.
+  pub fn add(&mut self, result: SearchResult) -> bool {
+    if let Some(&i) = self.index.get(&result.uri) {
+      let existing = &mut self.results[i];
+      for item in result.metadata.iter() {
+        existing.metadata.insert(item.clone());
+      }
+      if result.availability_certainty > existing.availability_certainty {
+        existing.availability_rank = result.availability_rank;
+        existing.availability_certainty = result.availability_certainty;
+      }
+      if result.applicability_rank > existing.applicability_rank {
+        existing.applicability_rank = result.applicability_rank;
+      }
+      return false;
+    }
+    self.index.insert(result.uri.clone(), self.results.len());
+    self.results.push(result);
+    true
+  }
+
+  /// Get the result for `uri`.
+  pub fn get(&self, uri: &Uri) -> Option<&SearchResult> {
+    self.index.get(uri).map(|&i| &self.results[i])
+  }
+
+  /// The number of distinct results.
+  pub fn len(&self) -> usize {
+    self.results.len()
+  }
+
+  /// Returns `true` if there are no results.
+  pub fn is_empty(&self) -> bool {
+    self.results.is_empty()
+  }
+
+  /// The distinct results in the order they were first found.
+  pub fn iter(&self) -> ::std::slice::Iter<SearchResult> {
+    self.results.iter()
+  }
+
+  /// The distinct results, best first.
+  ///
+  /// Results matching more keywords come first. Ties are broken by availability, then by how
+  /// certain that availability is, then by the order the results were found in.
+  pub fn ranked_results(&self) -> Vec<&SearchResult> {
+    let mut ret: Vec<&SearchResult> = self.results.iter().collect();
+    ret.sort_by(|a, b| compare_rank(a, b));
+    ret
+  }
+}
+
+/// Orders results best first.
+fn compare_rank(a: &SearchResult, b: &SearchResult) -> Ordering {
+  match b.applicability_rank.cmp(&a.applicability_rank) {
+    Ordering::Equal => (),
+    o               => return o,
+  };
+  match b.availability_rank.cmp(&a.availability_rank) {
+    Ordering::Equal => b.availability_certainty.cmp(&a.availability_certainty),
+    o               => o,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use fs::{MetaData, MetaType, Uri};
+  use super::*;
+
+  fn result(keyword: &str, applicability: u32, availability: i32, certainty: u32) -> SearchResult {
+    SearchResult {
+      uri: Uri::Ksk { keywords: vec![keyword.to_string()] },
+      metadata: MetaData::new(),
+      availability_rank: availability,
+      availability_certainty: certainty,
+      applicability_rank: applicability,
+    }
+  }
+
+  #[test]
+  fn test_search_results() {
+    let mut results = SearchResults::new();
+    assert!(results.add(result("a", 1, 0, 0)));
+    assert!(results.add(result("b", 2, -1, 3)));
+    assert!(results.add(result("c", 2, 4, 5)));
+
+    let mut dup = result("a", 3, 2, 1);
+    dup.metadata.insert_str(MetaType::Title, "A");
+    assert!(!results.add(dup));
+    assert_eq!(results.len(), 3);
+
+    let a = results.get(&Uri::Ksk { keywords: vec!["a".to_string()] }).unwrap();
+    assert_eq!(a.applicability_rank, 3);
+    assert_eq!(a.availability_rank, 2);
+    assert_eq!(a.metadata.get_str(MetaType::Title), Some("A"));
+
+    let ranked: Vec<String> = results.ranked_results().iter().map(|r| r.uri.to_string()).collect();
+    assert_eq!(ranked, vec!["gnunet://fs/ksk/a", "gnunet://fs/ksk/c", "gnunet://fs/ksk/b"]);
+  }
+}
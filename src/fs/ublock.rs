@@ -0,0 +1,155 @@
+use std::io::{Cursor, Write};
+use std::ptr;
+use std::str::from_utf8;
+use byteorder::{BigEndian, WriteBytesExt};
+use libc::{c_void, size_t};
+
+use ll;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use EcdsaSignature;
+use HashCode;
+use fs::{MetaData, Uri, hash_encrypt, hash_decrypt};
+
+/// The context used to derive the key which signs a UBlock.
+const UBLOCK_DERIVATION_CONTEXT: &'static str = "fs-ublock";
+
+/// The context used to derive the key which encrypts a UBlock.
+const UBLOCK_ENCRYPTION_CONTEXT: &'static str = "UBLOCK-ENC";
+
+/// The contents of a UBlock: a signed, encrypted pointer to a file, published under a keyword or
+/// in a namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UBlockContents {
+  /// For namespace entries, the identifier of the next update of this entry.
+  pub update: Option<String>,
+  /// The URI of the file.
+  pub uri: Uri,
+  /// The file's metadata.
+  pub metadata: MetaData,
+}
+
+/// Derive the hash which a UBlock's encryption key and IV are derived from.
+fn ublock_key(label: &str, namespace: &EcdsaPublicKey) -> HashCode {
+  let mut ns = Vec::with_capacity(32);
+  namespace.serialize(&mut ns).unwrap();
+  let mut key = HashCode::from_buffer(&[]);
+  unsafe {
+    let res = ll::GNUNET_CRYPTO_kdf(key.as_mut_slice().as_mut_ptr() as *mut c_void, 64,
+                                    UBLOCK_ENCRYPTION_CONTEXT.as_ptr() as *const c_void,
+                                    UBLOCK_ENCRYPTION_CONTEXT.len() as size_t,
+                                    label.as_ptr() as *const c_void, label.len() as size_t,
+                                    ns.as_ptr() as *const c_void, ns.len() as size_t,
+                                    ptr::null::<c_void>(), 0 as size_t);
+    assert_eq!(res, ll::GNUNET_OK);
+  }
+  key
+}
+
+/// The key under which the UBlock for `label` in `namespace` is stored.
+///
+/// Keyword blocks are published in the namespace of the anonymous key.
+pub fn ublock_query(namespace: &EcdsaPublicKey, label: &str) -> HashCode {
+  namespace.derive(label, UBLOCK_DERIVATION_CONTEXT).hash()
+}
+
+/// Build the UBlock publishing `contents` under `label` in the namespace `namespace`.
+///
+/// Returns the key to store the block under and the block itself.
+pub fn build_ublock(namespace: &EcdsaPrivateKey, label: &str, contents: &UBlockContents) -> (HashCode, Vec<u8>) {
+  let mut plaintext = Vec::new();
+  if let Some(ref update) = contents.update {
+    plaintext.extend_from_slice(update.as_bytes());
+  }
+  plaintext.push(0);
+  plaintext.extend_from_slice(contents.uri.to_string().as_bytes());
+  plaintext.push(0);
+  plaintext.extend_from_slice(&contents.metadata.serialize()[..]);
+
+  let ciphertext = hash_encrypt(&ublock_key(label, &namespace.get_public()), &plaintext[..]);
+  let signing_key = namespace.derive(label, UBLOCK_DERIVATION_CONTEXT);
+  let verification_key = signing_key.get_public();
+
+  let mut signed = Vec::with_capacity(8 + 32 + ciphertext.len());
+  signed.write_u32::<BigEndian>((8 + 32 + ciphertext.len()) as u32).unwrap();
+  signed.write_u32::<BigEndian>(ll::GNUNET_SIGNATURE_PURPOSE_FS_UBLOCK).unwrap();
+  verification_key.serialize(&mut signed).unwrap();
+  signed.write_all(&ciphertext[..]).unwrap();
+  let signature = signing_key.sign(&signed[..]);
+
+  let mut block = Vec::with_capacity(64 + signed.len());
+  signature.serialize(&mut block).unwrap();
+  block.extend_from_slice(&signed[..]);
+  (verification_key.hash(), block)
+}
+
+/// Check and decrypt a UBlock published under `label` in `namespace`.
+///
+/// Returns `None` if the block's signature is invalid or it does not decrypt to a valid UBlock.
+pub fn decrypt_ublock(namespace: &EcdsaPublicKey, label: &str, block: &[u8]) -> Option<UBlockContents> {
+  if block.len() < 64 + 8 + 32 {
+    return None;
+  }
+  let signature = match EcdsaSignature::deserialize(&mut Cursor::new(&block[..64])) {
+    Ok(s)   => s,
+    Err(_)  => return None,
+  };
+  // `verify` also checks the length in the purpose header.
+  let signed = &block[64..];
+  let verification_key = namespace.derive(label, UBLOCK_DERIVATION_CONTEXT);
+  if !verification_key.verify(ll::GNUNET_SIGNATURE_PURPOSE_FS_UBLOCK, signed, &signature) {
+    return None;
+  }
+
+  let plaintext = hash_decrypt(&ublock_key(label, namespace), &signed[40..]);
+  let update_end = match plaintext.iter().position(|&b| b == 0) {
+    Some(i) => i,
+    None    => return None,
+  };
+  let uri_end = match plaintext[update_end + 1..].iter().position(|&b| b == 0) {
+    Some(i) => update_end + 1 + i,
+    None    => return None,
+  };
+  let update = match from_utf8(&plaintext[..update_end]) {
+    Ok("")  => None,
+    Ok(s)   => Some(s.to_string()),
+    Err(_)  => return None,
+  };
+  let uri = match from_utf8(&plaintext[update_end + 1..uri_end]).ok().and_then(|s| s.parse().ok()) {
+    Some(uri) => uri,
+    None      => return None,
+  };
+  let metadata = match MetaData::deserialize(&plaintext[uri_end + 1..]) {
+    Ok(md)  => md,
+    Err(_)  => return None,
+  };
+  Some(UBlockContents {
+    update: update,
+    uri: uri,
+    metadata: metadata,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use rand;
+  use EcdsaPrivateKey;
+  use fs::{MetaData, MetaType, Uri};
+  use super::*;
+
+  #[test]
+  fn test_ublock_round_trip() {
+    let ns = EcdsaPrivateKey::anonymous();
+    let mut md = MetaData::new();
+    md.insert_str(MetaType::Filename, "song.ogg");
+    let contents = UBlockContents {
+      update: None,
+      uri: Uri::Chk { key: rand::random(), query: rand::random(), size: 1000 },
+      metadata: md,
+    };
+    let (query, block) = build_ublock(&ns, "music", &contents);
+    assert!(query == ublock_query(&ns.get_public(), "music"));
+    assert_eq!(decrypt_ublock(&ns.get_public(), "music", &block[..]), Some(contents));
+    assert_eq!(decrypt_ublock(&ns.get_public(), "video", &block[..]), None);
+  }
+}
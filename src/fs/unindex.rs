@@ -0,0 +1,143 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use HashCode;
+use datastore::{Datastore, DatastoreError};
+use service::{self, ReadMessageError};
+use fs::{EncodedBlock, TreeEncoder};
+
+/// A file the file-sharing service serves from the local filesystem. Returned by `list_indexed`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedFile {
+  /// Where the file is.
+  pub filename: PathBuf,
+  /// The hash of the file's contents when it was indexed.
+  pub file_id: HashCode,
+}
+
+/// Errors returned by `list_indexed`.
+error_def! ListIndexedError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the file-sharing service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the file-sharing service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the file-sharing service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The file-sharing service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  InvalidResponse
+    => "The response from the file-sharing service was incoherent",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {ListIndexedError}
+
+/// Errors returned by `unindex`.
+error_def! UnindexError {
+  NotAFile
+    => "The path to unindex is not a regular file",
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the datastore or file-sharing service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error reading the file or communicating with a service" ("Specifically: {}", cause),
+  Datastore { #[from] cause: DatastoreError }
+    => "Failed to remove a block from the datastore" ("Reason: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the file-sharing service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The file-sharing service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {UnindexError}
+
+/// List the files the file-sharing service has indexed, as `gnunet-fs -i` does.
+pub fn list_indexed(cfg: &Cfg) -> Result<Vec<IndexedFile>, ListIndexedError> {
+  let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "fs"));
+  {
+    let mw = service_writer.write_message(4, ll::GNUNET_MESSAGE_TYPE_FS_INDEX_LIST_GET);
+    try!(mw.send());
+  }
+  let mut ret = Vec::new();
+  loop {
+    let (tpe, mut mr) = try!(service_reader.read_message());
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_FS_INDEX_LIST_ENTRY => {
+        let _reserved = try!(mr.read_u32::<BigEndian>());
+        let file_id = try!(HashCode::deserialize(&mut mr));
+        let mut filename = Vec::new();
+        try!(mr.read_to_end(&mut filename));
+        if filename.pop() != Some(0) {
+          return Err(ListIndexedError::InvalidResponse);
+        }
+        let filename = match String::from_utf8(filename) {
+          Ok(f)   => f,
+          Err(_)  => return Err(ListIndexedError::InvalidResponse),
+        };
+        ret.push(IndexedFile {
+          filename: PathBuf::from(filename),
+          file_id: file_id,
+        });
+      },
+      ll::GNUNET_MESSAGE_TYPE_FS_INDEX_LIST_END => return Ok(ret),
+      x => return Err(ListIndexedError::UnexpectedMessageType { ty: x }),
+    }
+  }
+}
+
+/// Remove a block of an indexed file's tree from the datastore. Blocks which are already gone,
+/// eg. because the datastore discarded them, are ignored.
+fn remove_block(datastore: &mut Datastore, block: EncodedBlock, file_id: &HashCode) -> Result<(), UnindexError> {
+  if block.depth > 0 {
+    try!(datastore.remove(&block.chk.query, &block.data[..]));
+    return Ok(());
+  }
+  let mut ondemand = Vec::with_capacity(72);
+  file_id.serialize(&mut ondemand).unwrap();
+  ondemand.write_u64::<BigEndian>(block.offset).unwrap();
+  try!(datastore.remove(&block.chk.query, &ondemand[..]));
+  Ok(())
+}
+
+/// Stop sharing the indexed file at `path`, as `gnunet-unindex` does.
+///
+/// The file is encoded again to find its blocks, which are removed from the datastore, then the
+/// file-sharing service is told to forget the file. The file must not have changed since it was
+/// indexed. Keyword blocks published with the file are left to expire.
+pub fn unindex(cfg: &Cfg, path: &Path) -> Result<(), UnindexError> {
+  let path = try!(fs::canonicalize(path));
+  let file_meta = try!(fs::metadata(&path));
+  if !file_meta.is_file() {
+    return Err(UnindexError::NotAFile);
+  }
+  let size = file_meta.len();
+  let file_id = try!(HashCode::from_reader(&mut try!(File::open(&path))));
+
+  let mut file = try!(File::open(&path));
+  let mut datastore = try!(Datastore::connect(cfg));
+  let mut encoder = TreeEncoder::new(size);
+  let mut buf = Vec::new();
+  while encoder.offset() < size {
+    buf.resize(encoder.next_block_size(), 0);
+    try!(file.read_exact(&mut buf[..]));
+    try!(encoder.push(&buf[..], &mut |b| remove_block(&mut datastore, b, &file_id)));
+  }
+  try!(encoder.finish(&mut |b| remove_block(&mut datastore, b, &file_id)));
+
+  let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "fs"));
+  {
+    let mut mw = service_writer.write_message(72, ll::GNUNET_MESSAGE_TYPE_FS_UNINDEX);
+    mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+    file_id.serialize(&mut mw).unwrap();
+    try!(mw.send());
+  }
+  let (tpe, _) = try!(service_reader.read_message());
+  match tpe {
+    ll::GNUNET_MESSAGE_TYPE_FS_UNINDEX_OK => Ok(()),
+    x => Err(UnindexError::UnexpectedMessageType { ty: x }),
+  }
+}
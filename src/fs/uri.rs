@@ -0,0 +1,206 @@
+use std::fmt;
+use std::str::{from_utf8, FromStr};
+
+use EcdsaPublicKey;
+use HashCode;
+
+/// The prefix shared by all file-sharing URIs.
+pub const URI_PREFIX: &'static str = "gnunet://fs/";
+
+/// A file-sharing URI, identifying either a file or a search.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Uri {
+  /// A content hash key, identifying a file by its content.
+  Chk {
+    /// The key used to decrypt the file's root block.
+    key: HashCode,
+    /// The hash of the encrypted root block, used to find it.
+    query: HashCode,
+    /// The size of the file in bytes.
+    size: u64,
+  },
+  /// A keyword search.
+  Ksk {
+    /// The keywords to search for.
+    keywords: Vec<String>,
+  },
+  /// An entry in a namespace, ie. content published under an ego's key.
+  Sks {
+    /// The public key of the namespace.
+    namespace: EcdsaPublicKey,
+    /// The identifier of the entry within the namespace.
+    identifier: String,
+  },
+}
+
+/// Errors returned when parsing a `Uri`.
+error_def! UriParseError {
+  NotAnFsUri
+    => "The string is not a GNUnet file-sharing URI",
+  UnsupportedType { kind: String }
+    => "The file-sharing URI is of an unsupported type" ("Type: {}", kind),
+  Malformed
+    => "The file-sharing URI is malformed",
+}
+
+/// Percent-encode a keyword or identifier.
+fn encode(s: &str, f: &mut fmt::Formatter) -> fmt::Result {
+  for &b in s.as_bytes().iter() {
+    match b {
+      b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => try!(write!(f, "{}", b as char)),
+      _ => try!(write!(f, "%{:02X}", b)),
+    };
+  }
+  Ok(())
+}
+
+/// Decode a percent-encoded keyword or identifier.
+fn decode(s: &str) -> Result<String, UriParseError> {
+  let bytes = s.as_bytes();
+  let mut ret = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' {
+      if i + 3 > bytes.len() {
+        return Err(UriParseError::Malformed);
+      }
+      let hex = match from_utf8(&bytes[i + 1..i + 3]) {
+        Ok(h)   => h,
+        Err(_)  => return Err(UriParseError::Malformed),
+      };
+      match u8::from_str_radix(hex, 16) {
+        Ok(b)   => ret.push(b),
+        Err(_)  => return Err(UriParseError::Malformed),
+      };
+      i += 3;
+    }
+    else {
+      ret.push(bytes[i]);
+      i += 1;
+    }
+  }
+  match String::from_utf8(ret) {
+    Ok(s)   => Ok(s),
+    Err(_)  => Err(UriParseError::Malformed),
+  }
+}
+
+impl Uri {
+  /// Returns `true` if this URI identifies a file rather than a search.
+  pub fn is_file(&self) -> bool {
+    match *self {
+      Uri::Chk { .. } => true,
+      _               => false,
+    }
+  }
+
+  /// The size of the file, for URIs which identify a file.
+  pub fn file_size(&self) -> Option<u64> {
+    match *self {
+      Uri::Chk { size, .. } => Some(size),
+      _                     => None,
+    }
+  }
+}
+
+impl fmt::Display for Uri {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(write!(f, "{}", URI_PREFIX));
+    match *self {
+      Uri::Chk { ref key, ref query, size } => write!(f, "chk/{}.{}.{}", key, query, size),
+      Uri::Ksk { ref keywords } => {
+        try!(write!(f, "ksk/"));
+        for (i, keyword) in keywords.iter().enumerate() {
+          if i > 0 {
+            try!(write!(f, "+"));
+          }
+          try!(encode(&keyword[..], f));
+        }
+        Ok(())
+      },
+      Uri::Sks { ref namespace, ref identifier } => {
+        try!(write!(f, "sks/{}/", namespace));
+        encode(&identifier[..], f)
+      },
+    }
+  }
+}
+
+impl FromStr for Uri {
+  type Err = UriParseError;
+
+  fn from_str(s: &str) -> Result<Uri, UriParseError> {
+    if !s.starts_with(URI_PREFIX) {
+      return Err(UriParseError::NotAnFsUri);
+    }
+    let rest = &s[URI_PREFIX.len()..];
+    let (kind, rest) = match rest.find('/') {
+      Some(i) => (&rest[..i], &rest[i + 1..]),
+      None    => return Err(UriParseError::Malformed),
+    };
+    match kind {
+      "chk" => {
+        let parts: Vec<&str> = rest.split('.').collect();
+        if parts.len() != 3 {
+          return Err(UriParseError::Malformed);
+        }
+        match (HashCode::from_str(parts[0]), HashCode::from_str(parts[1]), parts[2].parse::<u64>()) {
+          (Ok(key), Ok(query), Ok(size))  => Ok(Uri::Chk { key: key, query: query, size: size }),
+          _                               => Err(UriParseError::Malformed),
+        }
+      },
+      "ksk" => {
+        let mut keywords = Vec::new();
+        for keyword in rest.split('+') {
+          if keyword.is_empty() {
+            continue;
+          }
+          keywords.push(try!(decode(keyword)));
+        }
+        if keywords.is_empty() {
+          return Err(UriParseError::Malformed);
+        }
+        Ok(Uri::Ksk { keywords: keywords })
+      },
+      "sks" => {
+        let (namespace, identifier) = match rest.find('/') {
+          Some(i) => (&rest[..i], &rest[i + 1..]),
+          None    => return Err(UriParseError::Malformed),
+        };
+        let namespace = match EcdsaPublicKey::from_str(namespace) {
+          Ok(ns)  => ns,
+          Err(_)  => return Err(UriParseError::Malformed),
+        };
+        Ok(Uri::Sks {
+          namespace: namespace,
+          identifier: try!(decode(identifier)),
+        })
+      },
+      other => Err(UriParseError::UnsupportedType { kind: other.to_string() }),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand;
+  use super::*;
+
+  #[test]
+  fn test_uri_round_trip() {
+    let chk = Uri::Chk {
+      key: rand::random(),
+      query: rand::random(),
+      size: 12345,
+    };
+    assert_eq!(chk.to_string().parse::<Uri>().unwrap(), chk);
+    assert_eq!(chk.file_size(), Some(12345));
+
+    let ksk: Uri = "gnunet://fs/ksk/gnu+free%20software".parse().unwrap();
+    assert_eq!(ksk, Uri::Ksk { keywords: vec!["gnu".to_string(), "free software".to_string()] });
+    assert_eq!(ksk.to_string(), "gnunet://fs/ksk/gnu+free%20software");
+
+    assert!("gnunet://fs/loc/abc".parse::<Uri>().is_err());
+    assert!("http://example.com".parse::<Uri>().is_err());
+  }
+}
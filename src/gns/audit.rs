@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+use byteorder::{BigEndian, WriteBytesExt};
+use rand;
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use gns::{lookup_body, read_lookup_result, name_from_wire, name_to_wire, LookupError, LookupProtocol,
+          LocalOptions, Record, RecordType, MASTERZONE_STR};
+use namestore::{Namestore, RecordResultError};
+use revocation;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+use time;
+
+const RF_RELATIVE_EXPIRATION: u32 = ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION as u32;
+
+/// How long to wait for a DNS server named in a GNS2DNS record to answer.
+const DNS_TIMEOUT_SECS: u64 = 5;
+const DNS_PORT: u16 = 53;
+const DNS_TYPE_SOA: u16 = 6;
+const DNS_CLASS_INTERNET: u16 = 1;
+
+/// A problem with a zone, found by `audit_zone`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditIssue {
+  /// A record with an absolute expiration time has expired.
+  Expired { label: String, record_type: RecordType, expiration_time: u64 },
+  /// The payload of a PKEY or GNS2DNS record can't be parsed.
+  MalformedDelegation { label: String, record_type: RecordType },
+  /// A PKEY record delegates to the audited zone itself.
+  SelfDelegation { label: String },
+  /// A PKEY record delegates to a zone whose key has been revoked.
+  RevokedDelegation { label: String, zone: EcdsaPublicKey },
+  /// Nothing could be resolved at the apex of the zone a PKEY record delegates to.
+  UnresolvableDelegation { label: String, zone: EcdsaPublicKey },
+  /// The DNS server a GNS2DNS record delegates to couldn't be found or didn't answer for the DNS
+  /// zone.
+  UnresolvableDnsDelegation { label: String, dns_zone: String, server: String },
+}
+
+/// The result of auditing a zone.
+#[derive(Clone, Debug)]
+pub struct AuditReport {
+  /// The audited zone.
+  pub zone: EcdsaPublicKey,
+  /// The number of labels in the zone.
+  pub labels: usize,
+  /// The number of records in the zone.
+  pub records: usize,
+  /// The problems found, sorted by label.
+  pub issues: Vec<AuditIssue>,
+}
+
+impl AuditReport {
+  /// Returns `true` if no problems were found.
+  pub fn is_clean(&self) -> bool {
+    self.issues.is_empty()
+  }
+}
+
+/// Errors returned by `audit_zone`.
+error_def! AuditError {
+  ReadZone { #[from] cause: RecordResultError }
+    => "Failed to read the contents of the zone" ("Reason: {}", cause),
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to a service" ("Reason: {}", cause),
+  Revocation { #[from] cause: revocation::QueryError }
+    => "Failed to check whether a delegated zone was revoked" ("Reason: {}", cause),
+  Lookup { #[from] cause: LookupError }
+    => "Failed to look up the apex of a delegated zone" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with a service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a response from the GNS service" ("Reason: {}", cause),
+  InvalidResponse
+    => "The response from the GNS service was incoherent",
+}
+
+/// Follows the delegations found by `audit_records`.
+pub trait DelegationChecker {
+  /// Returns `true` if the key of the delegated zone `zone` has been revoked.
+  fn is_revoked(&mut self, zone: &EcdsaPublicKey) -> Result<bool, AuditError>;
+  /// Returns `true` if anything can be resolved at the apex of the delegated zone `zone`.
+  fn resolves(&mut self, zone: &EcdsaPublicKey) -> Result<bool, AuditError>;
+  /// Returns `true` if the DNS server `server` answers for the DNS zone `dns_zone`.
+  fn dns_resolves(&mut self, dns_zone: &str, server: &str) -> Result<bool, AuditError>;
+}
+
+/// A `DelegationChecker` which asks the revocation service about keys and resolves the apex of
+/// delegated zones through GNS, which fetches them from the DHT.
+///
+/// Zones publish their nickname at the apex, so a reachable zone resolves its apex label to a
+/// NICK record. The label depends on the lookup protocol, see `LookupProtocol::apex_label`.
+///
+/// DNS servers named by GNS2DNS records are found through the system's resolver and asked for
+/// the SOA record of the DNS zone. Servers named relative to the zone, ending in `+`, are resolved
+/// by the GNS daemon itself and aren't checked.
+pub struct ServiceChecker<'c> {
+  cfg: &'c Cfg,
+  gns: Option<(ServiceReader, ServiceWriter)>,
+  protocol: LookupProtocol,
+  lookup_id: u32,
+}
+
+impl<'c> ServiceChecker<'c> {
+  /// Create a checker which uses the services described by `cfg`. Connections are made when they
+  /// are first needed.
+  pub fn new(cfg: &'c Cfg) -> ServiceChecker<'c> {
+    ServiceChecker {
+      cfg: cfg,
+      gns: None,
+      protocol: LookupProtocol::default(),
+      lookup_id: 0,
+    }
+  }
+
+  /// Change the layout of the lookup requests sent to the GNS daemon. See `LookupProtocol`.
+  pub fn set_protocol(&mut self, protocol: LookupProtocol) {
+    self.protocol = protocol;
+  }
+}
+
+impl<'c> DelegationChecker for ServiceChecker<'c> {
+  fn is_revoked(&mut self, zone: &EcdsaPublicKey) -> Result<bool, AuditError> {
+    Ok(try!(revocation::is_revoked(self.cfg, zone)))
+  }
+
+  fn resolves(&mut self, zone: &EcdsaPublicKey) -> Result<bool, AuditError> {
+    if self.gns.is_none() {
+      self.gns = Some(try!(service::connect(self.cfg, "gns")));
+    }
+    let id = self.lookup_id;
+    self.lookup_id = self.lookup_id.wrapping_add(1);
+    let label = self.protocol.apex_label();
+    let body = try!(lookup_body(self.protocol, id, label, zone, RecordType::NICK, LocalOptions::Default, None));
+    let (ref mut service_reader, ref mut service_writer) = *self.gns.as_mut().unwrap();
+    try!(service_writer.send_raw(ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP, &body[..]));
+    // The service always answers a lookup, with no records if nothing was found.
+    loop {
+      let (tpe, mut mr) = try!(service_reader.read_message());
+      if tpe != ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP_RESULT {
+        continue;
+      }
+      match read_lookup_result(&mut mr) {
+        Ok((result_id, ref records)) if result_id == id => return Ok(!records.is_empty()),
+        Ok(_)   => (),
+        Err(()) => return Err(AuditError::InvalidResponse),
+      };
+    }
+  }
+
+  fn dns_resolves(&mut self, dns_zone: &str, server: &str) -> Result<bool, AuditError> {
+    if server == MASTERZONE_STR || server.ends_with(&format!(".{}", MASTERZONE_STR)) {
+      return Ok(true);
+    }
+    let query = match soa_query(rand::random(), dns_zone) {
+      Some(query) => query,
+      None        => return Ok(false),
+    };
+    let addrs = match (server, DNS_PORT).to_socket_addrs() {
+      Ok(addrs) => addrs,
+      Err(_)    => return Ok(false),
+    };
+    for addr in addrs {
+      if try!(dns_answers(addr, &query[..])) {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+}
+
+/// A DNS query with id `id` for the SOA record of `dns_zone`.
+fn soa_query(id: u16, dns_zone: &str) -> Option<Vec<u8>> {
+  let name = match name_to_wire(dns_zone) {
+    Ok(name)  => name,
+    Err(_)    => return None,
+  };
+  let mut query = Vec::with_capacity(12 + name.len() + 4);
+  query.write_u16::<BigEndian>(id).unwrap();
+  query.write_u16::<BigEndian>(0x0100).unwrap(); // recursion desired
+  query.write_u16::<BigEndian>(1).unwrap(); // one question
+  query.extend_from_slice(&[0u8; 6]); // no answer, authority or additional records
+  query.extend_from_slice(&name[..]);
+  query.write_u16::<BigEndian>(DNS_TYPE_SOA).unwrap();
+  query.write_u16::<BigEndian>(DNS_CLASS_INTERNET).unwrap();
+  Some(query)
+}
+
+/// Send `query` to the DNS server at `addr`. Returns `true` if it answers without an error before
+/// the timeout.
+fn dns_answers(addr: SocketAddr, query: &[u8]) -> Result<bool, io::Error> {
+  let local = match addr {
+    SocketAddr::V4(_) => "0.0.0.0:0",
+    SocketAddr::V6(_) => "[::]:0",
+  };
+  let socket = try!(UdpSocket::bind(local));
+  try!(socket.set_read_timeout(Some(Duration::from_secs(DNS_TIMEOUT_SECS))));
+  try!(socket.send_to(query, addr));
+  let mut buf = [0u8; 512];
+  loop {
+    let (n, from) = match socket.recv_from(&mut buf[..]) {
+      Ok(x)   => x,
+      Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                 || e.kind() == io::ErrorKind::TimedOut => return Ok(false),
+      Err(e)  => return Err(e),
+    };
+    // Skip anything which isn't the answer to this query.
+    if from != addr || n < 12 || buf[..2] != query[..2] || buf[2] & 0x80 == 0 {
+      continue;
+    }
+    return Ok(buf[3] & 0x0f == 0);
+  }
+}
+
+/// Split the payload of a GNS2DNS record into the DNS zone delegated to and the DNS server to
+/// ask about it.
+fn parse_gns2dns(data: &[u8]) -> Option<(String, String)> {
+  let mut pos = 0;
+  loop {
+    let len = match data.get(pos) {
+      Some(&l)  => l as usize,
+      None      => return None,
+    };
+    pos += 1 + len;
+    if len == 0 {
+      break;
+    }
+  }
+  if pos > data.len() {
+    return None;
+  }
+  match (name_from_wire(&data[..pos]), name_from_wire(&data[pos..])) {
+    (Ok(dns_zone), Ok(server))  => Some((dns_zone, server)),
+    _                           => None,
+  }
+}
+
+/// Check the contents of the zone `zone`, given as a map from labels to record sets, for
+/// problems. Delegations to other zones are followed with `checker`. `now` is the current time in
+/// microseconds since the epoch.
+pub fn audit_records<C>(zone: &EcdsaPublicKey,
+                        contents: &HashMap<String, Vec<Record>>,
+                        now: u64,
+                        checker: &mut C) -> Result<AuditReport, AuditError>
+    where C: DelegationChecker
+{
+  let mut labels: Vec<&String> = contents.keys().collect();
+  labels.sort();
+  let mut issues = Vec::new();
+  let mut records = 0;
+  for label in labels {
+    for record in contents[label].iter() {
+      records += 1;
+      let record_type = record.record_type();
+      if record.flags() & RF_RELATIVE_EXPIRATION == 0 && record.expiration_time() < now {
+        issues.push(AuditIssue::Expired {
+          label: label.clone(),
+          record_type: record_type,
+          expiration_time: record.expiration_time(),
+        });
+      }
+      match record_type {
+        RecordType::PKEY => {
+          let delegated = match record.data().len() {
+            32  => try!(EcdsaPublicKey::deserialize(&mut record.data())),
+            _   => {
+              issues.push(AuditIssue::MalformedDelegation { label: label.clone(), record_type: record_type });
+              continue;
+            },
+          };
+          if delegated == *zone {
+            issues.push(AuditIssue::SelfDelegation { label: label.clone() });
+          }
+          else if try!(checker.is_revoked(&delegated)) {
+            issues.push(AuditIssue::RevokedDelegation { label: label.clone(), zone: delegated });
+          }
+          else if !try!(checker.resolves(&delegated)) {
+            issues.push(AuditIssue::UnresolvableDelegation { label: label.clone(), zone: delegated });
+          }
+        },
+        RecordType::GNS2DNS => {
+          let (dns_zone, server) = match parse_gns2dns(record.data()) {
+            Some(x) => x,
+            None    => {
+              issues.push(AuditIssue::MalformedDelegation { label: label.clone(), record_type: record_type });
+              continue;
+            },
+          };
+          if !try!(checker.dns_resolves(&dns_zone, &server)) {
+            issues.push(AuditIssue::UnresolvableDnsDelegation {
+              label: label.clone(),
+              dns_zone: dns_zone,
+              server: server,
+            });
+          }
+        },
+        _ => (),
+      };
+    }
+  }
+  Ok(AuditReport {
+    zone: *zone,
+    labels: contents.len(),
+    records: records,
+    issues: issues,
+  })
+}
+
+/// Audit the zone `zone` held by the local namestore, like a `gnunet-zone-lint`.
+///
+/// Reports expired records and broken delegations: PKEY records which are malformed, delegate to
+/// the zone itself or to a revoked zone, or lead to a zone where nothing resolves, and GNS2DNS
+/// records which are malformed or name a DNS server which doesn't answer for the DNS zone.
+/// Following delegations can take a while as delegated zones are looked up in the DHT.
+pub fn audit_zone(cfg: &Cfg, zone: &EcdsaPrivateKey) -> Result<AuditReport, AuditError> {
+  let mut namestore = try!(Namestore::connect(cfg));
+  let contents = try!(namestore.zone_contents(zone));
+  let now = time::now_micros();
+  audit_records(&zone.get_public(), &contents, now, &mut ServiceChecker::new(cfg))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::io::Cursor;
+  use EcdsaPublicKey;
+  use gns::{name_to_wire, Record, RecordType};
+  use super::*;
+
+  /// Treats zones with a first key byte of 1 as revoked and 2 as unreachable, and DNS servers
+  /// given by address as unreachable.
+  struct FakeChecker;
+
+  impl DelegationChecker for FakeChecker {
+    fn is_revoked(&mut self, zone: &EcdsaPublicKey) -> Result<bool, AuditError> {
+      Ok(key_bytes(zone)[0] == 1)
+    }
+
+    fn resolves(&mut self, zone: &EcdsaPublicKey) -> Result<bool, AuditError> {
+      Ok(key_bytes(zone)[0] != 2)
+    }
+
+    fn dns_resolves(&mut self, _dns_zone: &str, server: &str) -> Result<bool, AuditError> {
+      Ok(server.parse::<::std::net::IpAddr>().is_err())
+    }
+  }
+
+  fn key_bytes(key: &EcdsaPublicKey) -> Vec<u8> {
+    let mut ret = Vec::new();
+    key.serialize(&mut ret).unwrap();
+    ret
+  }
+
+  fn key(first: u8) -> EcdsaPublicKey {
+    let mut bytes = [first; 32];
+    bytes[31] = 0x42;
+    EcdsaPublicKey::deserialize(&mut Cursor::new(&bytes[..])).unwrap()
+  }
+
+  fn pkey(first: u8) -> Record {
+    Record::new(RecordType::PKEY, key_bytes(&key(first)), 2000, 0)
+  }
+
+  fn gns2dns(dns_zone: &str, server: &[u8]) -> Record {
+    let mut data = name_to_wire(dns_zone).unwrap();
+    data.extend_from_slice(server);
+    Record::new(RecordType::GNS2DNS, data, 2000, 0)
+  }
+
+  #[test]
+  fn test_audit_records() {
+    let zone = key(9);
+    let mut contents = HashMap::new();
+    contents.insert("good".to_string(), vec![pkey(3)]);
+    contents.insert("self".to_string(), vec![pkey(9)]);
+    contents.insert("revoked".to_string(), vec![pkey(1)]);
+    contents.insert("dead".to_string(), vec![pkey(2)]);
+    contents.insert("short".to_string(), vec![Record::new(RecordType::PKEY, vec![0; 31], 2000, 0)]);
+    contents.insert("old".to_string(), vec![Record::new(RecordType::A, vec![192, 0, 2, 1], 500, 0)]);
+    contents.insert("dns".to_string(), vec![gns2dns("example.com", &name_to_wire("ns1.example.com").unwrap()[..])]);
+    contents.insert("dns-ip".to_string(), vec![gns2dns("example.com", &name_to_wire("192.0.2.1").unwrap()[..])]);
+    contents.insert("dns-broken".to_string(), vec![gns2dns("example.com", b"\x05ns")]);
+
+    let report = audit_records(&zone, &contents, 1000, &mut FakeChecker).unwrap();
+    assert_eq!(report.labels, 9);
+    assert_eq!(report.records, 9);
+    assert_eq!(report.issues, vec![
+      AuditIssue::UnresolvableDelegation { label: "dead".to_string(), zone: key(2) },
+      AuditIssue::MalformedDelegation { label: "dns-broken".to_string(), record_type: RecordType::GNS2DNS },
+      AuditIssue::UnresolvableDnsDelegation {
+        label: "dns-ip".to_string(),
+        dns_zone: "example.com".to_string(),
+        server: "192.0.2.1".to_string(),
+      },
+      AuditIssue::Expired { label: "old".to_string(), record_type: RecordType::A, expiration_time: 500 },
+      AuditIssue::RevokedDelegation { label: "revoked".to_string(), zone: key(1) },
+      AuditIssue::SelfDelegation { label: "self".to_string() },
+      AuditIssue::MalformedDelegation { label: "short".to_string(), record_type: RecordType::PKEY },
+    ]);
+    assert!(!report.is_clean());
+  }
+
+  #[test]
+  fn test_soa_query() {
+    let query = super::soa_query(0x1234, "example.com").unwrap();
+    assert_eq!(&query[..12], &[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0][..]);
+    assert_eq!(&query[12..], &b"\x07example\x03com\x00\x00\x06\x00\x01"[..]);
+  }
+}
@@ -0,0 +1,83 @@
+use std::ffi::CString;
+use std::slice::from_raw_parts;
+use libc::c_void;
+
+use ll;
+use EcdsaPrivateKey;
+use gns::Record;
+
+/// The length of the signature and derived key which come before the signed part of a block.
+const UNSIGNED_HEADER_LEN: usize = 64 + 32;
+
+/// When a block containing `records` should expire: the earliest expiration time of the records,
+/// with relative expiration times counted from `now`. Both are in microseconds. Returns `now` if
+/// there are no records.
+pub fn block_expiration(records: &[Record], now: u64) -> u64 {
+  records.iter().map(|r| {
+    match r.flags() & ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION {
+      0 => r.expiration_time(),
+      _ => now.saturating_add(r.expiration_time()),
+    }
+  }).min().unwrap_or(now)
+}
+
+/// Create the signed and encrypted block under which the GNS service publishes `records`, stored
+/// under `label` in `zone`, to the DHT. The block expires at `expiration`, in microseconds since
+/// the epoch.
+///
+/// The block should be stored under the key given by `query_from_private_key`.
+///
+/// # Panics
+///
+/// Panics if `label` contains a nul byte or the records can't be made into a block.
+pub fn create_block(zone: &EcdsaPrivateKey, label: &str, records: &[Record], expiration: u64) -> Vec<u8> {
+  let label = CString::new(&label.to_lowercase()[..]).unwrap();
+  let mut key = ll::Struct_GNUNET_CRYPTO_EcdsaPrivateKey { d: [0u8; 32] };
+  zone.serialize(&mut &mut key.d[..]).unwrap();
+  let rd: Vec<ll::Struct_GNUNET_GNSRECORD_Data> = records.iter().map(|r| {
+    ll::Struct_GNUNET_GNSRECORD_Data {
+      data:             r.data().as_ptr() as *const c_void,
+      expiration_time:  r.expiration_time(),
+      data_size:        r.data().len(),
+      record_type:      r.record_type().to_u32(),
+      flags:            r.flags(),
+    }
+  }).collect();
+  unsafe {
+    let block = ll::GNUNET_GNSRECORD_block_create(&key,
+                                                  ll::Struct_GNUNET_TIME_Absolute { abs_value_us: expiration },
+                                                  label.as_ptr(),
+                                                  rd.as_ptr(),
+                                                  rd.len() as u32);
+    assert!(!block.is_null());
+    let len = UNSIGNED_HEADER_LEN + u32::from_be((*block).purpose.size) as usize;
+    let ret = from_raw_parts(block as *const u8, len).to_vec();
+    ll::GNUNET_xfree_(block as *mut c_void, b"block.rs\0".as_ptr() as *const i8, line!() as i32);
+    ret
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use block::{self, BlockType};
+  use gns::{query_from_private_key, Record, RecordType};
+  use ll;
+  use EcdsaPrivateKey;
+  use super::*;
+
+  #[test]
+  fn test_create_block() {
+    let zone = EcdsaPrivateKey::anonymous();
+    let records = vec![
+      Record::new(RecordType::A, vec![10, 0, 0, 1], 2000000, 0),
+      Record::new(RecordType::TXT, b"hello".to_vec(), 300000, ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION),
+    ];
+    assert_eq!(block_expiration(&records[..], 1000000), 1300000);
+    assert_eq!(block_expiration(&[], 1000000), 1000000);
+
+    let data = create_block(&zone, "WWW", &records[..], 1300000);
+    // The block verifies and is found under the query for the label.
+    let key = block::get_key(BlockType::GnsNameRecord, &data[..]).unwrap();
+    assert!(key == query_from_private_key(&zone, "www"));
+  }
+}
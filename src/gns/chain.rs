@@ -0,0 +1,263 @@
+use std::ascii::AsciiExt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use Cfg;
+use gns::{lookup_types_in_master, RecordType};
+
+/// The time to wait for the GNS service to answer if the config doesn't say.
+const DEFAULT_GNS_TIMEOUT_SECS: u64 = 5;
+
+/// A source of addresses consulted by a `ResolverChain`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NameSource {
+  /// A local file of petnames in the format of `/etc/hosts`.
+  Hosts,
+  /// The A and AAAA records of the name in the master zone.
+  Gns,
+  /// The system's DNS resolver.
+  Dns,
+}
+
+/// Error generated when parsing a `NameSource` from a string.
+error_def! NameSourceFromStrError {
+  ParsingFailed
+    => "Failed to parse the string as a name source. Expected HOSTS, GNS or DNS",
+}
+
+impl FromStr for NameSource {
+  type Err = NameSourceFromStrError;
+
+  fn from_str(s: &str) -> Result<NameSource, NameSourceFromStrError> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("HOSTS") {
+      Ok(NameSource::Hosts)
+    }
+    else if s.eq_ignore_ascii_case("GNS") {
+      Ok(NameSource::Gns)
+    }
+    else if s.eq_ignore_ascii_case("DNS") {
+      Ok(NameSource::Dns)
+    }
+    else {
+      Err(NameSourceFromStrError::ParsingFailed)
+    }
+  }
+}
+
+/// The addresses a `ResolverChain` found for a name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+  /// The source which answered.
+  pub source: NameSource,
+  /// The addresses it gave, in the order it gave them.
+  pub addrs: Vec<IpAddr>,
+}
+
+/// Errors returned by `ResolverChain::resolve`.
+error_def! ResolveError {
+  NotFound { name: String }
+    => "None of the name sources knew the name" ("No addresses were found for \"{}\".", name),
+}
+
+/// Resolves names to addresses by asking several sources in turn, so that an application can
+/// still reach hosts it knows about when the GNS service is unavailable.
+///
+/// The sources are tried in order until one of them gives at least one address. A source which
+/// fails, eg. because its service can't be reached or its file can't be read, is skipped in the
+/// same way as one that doesn't know the name.
+#[derive(Clone)]
+pub struct ResolverChain {
+  cfg: Cfg,
+  order: Vec<NameSource>,
+  hosts_file: Option<PathBuf>,
+  gns_timeout: Duration,
+}
+
+impl ResolverChain {
+  /// Create a chain from the `[resolver]` section of `cfg`.
+  ///
+  /// `ORDER` lists the sources to try, separated by spaces, and defaults to `HOSTS GNS DNS`.
+  /// Unrecognised sources are ignored. `HOSTS_FILE` names the petname file; without it the
+  /// `HOSTS` source never answers. `GNS_TIMEOUT` is how long to wait for the GNS service before
+  /// moving on and defaults to five seconds.
+  pub fn from_cfg(cfg: &Cfg) -> ResolverChain {
+    let order = match cfg.get_section("resolver").and_then(|s| s.get("ORDER")) {
+      Some(order) => order.split_whitespace().filter_map(|s| s.parse().ok()).collect(),
+      None        => vec![NameSource::Hosts, NameSource::Gns, NameSource::Dns],
+    };
+    let gns_timeout = match cfg.get_relative_time("resolver", "GNS_TIMEOUT") {
+      Ok(t)   => Duration::from(t),
+      Err(_)  => Duration::from_secs(DEFAULT_GNS_TIMEOUT_SECS),
+    };
+    ResolverChain {
+      cfg: cfg.clone(),
+      order: order,
+      hosts_file: cfg.get_filename("resolver", "HOSTS_FILE").ok(),
+      gns_timeout: gns_timeout,
+    }
+  }
+
+  /// The sources tried, in order.
+  pub fn order(&self) -> &[NameSource] {
+    &self.order[..]
+  }
+
+  /// Change the sources tried and the order they are tried in.
+  pub fn set_order(&mut self, order: Vec<NameSource>) {
+    self.order = order;
+  }
+
+  /// Change the petname file consulted by the `Hosts` source.
+  pub fn set_hosts_file(&mut self, hosts_file: Option<PathBuf>) {
+    self.hosts_file = hosts_file;
+  }
+
+  /// Change how long to wait for the GNS service before moving on.
+  pub fn set_gns_timeout(&mut self, timeout: Duration) {
+    self.gns_timeout = timeout;
+  }
+
+  /// Find the addresses of `name`, returning them along with the source which answered.
+  pub fn resolve(&self, name: &str) -> Result<Resolution, ResolveError> {
+    for source in self.order.iter() {
+      let addrs = match *source {
+        NameSource::Hosts => self.resolve_hosts(name),
+        NameSource::Gns   => self.resolve_gns(name),
+        NameSource::Dns   => resolve_dns(name),
+      };
+      if !addrs.is_empty() {
+        return Ok(Resolution {
+          source: *source,
+          addrs: addrs,
+        });
+      }
+    }
+    Err(ResolveError::NotFound { name: name.to_string() })
+  }
+
+  fn resolve_hosts(&self, name: &str) -> Vec<IpAddr> {
+    match self.hosts_file {
+      Some(ref path) => match read_file(path) {
+        Ok(contents)  => hosts_lookup(&contents[..], name),
+        Err(_)        => Vec::new(),
+      },
+      None => Vec::new(),
+    }
+  }
+
+  fn resolve_gns(&self, name: &str) -> Vec<IpAddr> {
+    let record_types = [RecordType::A, RecordType::AAAA];
+    match lookup_types_in_master(&self.cfg, name, &record_types[..], self.gns_timeout) {
+      Ok(records) => records.iter().filter_map(|r| record_addr(r.record_type(), r.data())).collect(),
+      Err(_)      => Vec::new(),
+    }
+  }
+}
+
+fn read_file(path: &Path) -> Result<String, io::Error> {
+  let mut contents = String::new();
+  try!(try!(File::open(path)).read_to_string(&mut contents));
+  Ok(contents)
+}
+
+/// The address held in the payload of an A or AAAA record.
+fn record_addr(record_type: RecordType, data: &[u8]) -> Option<IpAddr> {
+  match (record_type, data.len()) {
+    (RecordType::A, 4) => Some(IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3]))),
+    (RecordType::AAAA, 16) => {
+      let mut segments = [0u16; 8];
+      for (i, s) in segments.iter_mut().enumerate() {
+        *s = ((data[i * 2] as u16) << 8) | data[i * 2 + 1] as u16;
+      }
+      Some(IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                    segments[4], segments[5], segments[6], segments[7])))
+    },
+    _ => None,
+  }
+}
+
+/// The addresses given for `name` in `contents`, which is in the format of `/etc/hosts`. Names
+/// are compared ignoring case and lines which don't start with an address are skipped.
+fn hosts_lookup(contents: &str, name: &str) -> Vec<IpAddr> {
+  let mut ret = Vec::new();
+  for line in contents.lines() {
+    let line = match line.find('#') {
+      Some(i) => &line[..i],
+      None    => line,
+    };
+    let mut fields = line.split_whitespace();
+    let addr: IpAddr = match fields.next().and_then(|a| a.parse().ok()) {
+      Some(addr)  => addr,
+      None        => continue,
+    };
+    if fields.any(|n| n.eq_ignore_ascii_case(name)) && !ret.contains(&addr) {
+      ret.push(addr);
+    }
+  }
+  ret
+}
+
+fn resolve_dns(name: &str) -> Vec<IpAddr> {
+  let mut ret = Vec::new();
+  if let Ok(addrs) = (name, 0).to_socket_addrs() {
+    for addr in addrs {
+      if !ret.contains(&addr.ip()) {
+        ret.push(addr.ip());
+      }
+    }
+  }
+  ret
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::{self, File};
+  use std::io::Write;
+  use std::net::IpAddr;
+  use Cfg;
+  use super::*;
+  use super::hosts_lookup;
+
+  const HOSTS: &'static str = "\
+# petnames
+10.0.0.1    printer.home printer
+10.0.0.2    nas.home        # the file server
+fd00::2     nas.home
+not-an-ip   nas.home
+";
+
+  #[test]
+  fn test_hosts_lookup() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    assert_eq!(hosts_lookup(HOSTS, "PRINTER"), vec![ip("10.0.0.1")]);
+    assert_eq!(hosts_lookup(HOSTS, "nas.home"), vec![ip("10.0.0.2"), ip("fd00::2")]);
+    assert!(hosts_lookup(HOSTS, "petnames").is_empty());
+    assert!(hosts_lookup(HOSTS, "the").is_empty());
+  }
+
+  #[test]
+  fn test_resolver_chain() {
+    let mut path = ::std::env::temp_dir();
+    path.push(format!("gnunet-rs-hosts-test-{}", ::rand::random::<u32>()));
+    File::create(&path).unwrap().write_all(HOSTS.as_bytes()).unwrap();
+
+    let mut cfg = Cfg::empty();
+    cfg.set_string("resolver", "ORDER", "hosts bogus".to_string());
+    cfg.set_string("resolver", "HOSTS_FILE", path.to_str().unwrap().to_string());
+    let chain = ResolverChain::from_cfg(&cfg);
+    assert_eq!(chain.order(), &[NameSource::Hosts]);
+    let res = chain.resolve("printer").unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(res.source, NameSource::Hosts);
+    assert_eq!(res.addrs, vec!["10.0.0.1".parse::<IpAddr>().unwrap()]);
+    assert!(chain.resolve("unknown.home").is_err());
+
+    let chain = ResolverChain::from_cfg(&Cfg::empty());
+    assert_eq!(chain.order(), &[NameSource::Hosts, NameSource::Gns, NameSource::Dns]);
+  }
+}
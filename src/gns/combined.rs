@@ -0,0 +1,357 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Cursor, Write};
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::time::Duration;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use service::{self, CatchAll, ServiceWriter, SharedReadLoop, ProcessMessageResult};
+use gns::{lookup_body, read_lookup_result, LookupError, LookupHandle, LookupProtocol, LocalOptions,
+          Record, RecordType, ResolverConfig};
+use shutdown::{Component, ReadLoop};
+use util::ReadCString;
+
+/// The index of each connection in the shared read loop.
+const IDENTITY_CONNECTION: usize = 0;
+const GNS_CONNECTION: usize = 1;
+
+/// The service whose default ego is the master zone.
+const MASTER_ZONE_SERVICE: &'static str = "gns-master";
+
+/// Errors returned when fetching the master zone through an `IdentityGns`.
+error_def! MasterZoneError {
+  ServiceResponse { response: String }
+    => "The identity service responded with an error message" ("Error: \"{}\"", response),
+  InvalidResponse
+    => "The identity service response was incoherent. You should file a bug-report if you encounter this error.",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the identity service" ("Specifically: {}", cause),
+  Disconnected
+    => "The identity service disconnected before responding",
+}
+byteorder_error_chain! {MasterZoneError}
+
+/// Errors returned by `IdentityGns::lookup_in_master`.
+error_def! IdentityGnsLookupError {
+  MasterZone { #[from] cause: MasterZoneError }
+    => "Failed to retrieve the master zone from the identity service" ("Reason: {}", cause),
+  Lookup { #[from] cause: LookupError }
+    => "Failed to perform the lookup." ("Reason: {}", cause),
+}
+
+/// Requests registered with the read loop.
+enum Registration {
+  MasterZone(Sender<Result<EcdsaPublicKey, MasterZoneError>>),
+  /// The last `MasterZone` request could not be sent, so no response will arrive for it.
+  AbandonMasterZone,
+  Lookup(u32, Sender<Vec<Record>>),
+  Cancel(u32),
+}
+
+/// Read an `IDENTITY_SET_DEFAULT` message sent in response to a request for the master zone.
+fn read_master_zone(reader: &mut Cursor<Vec<u8>>) -> Result<EcdsaPublicKey, MasterZoneError> {
+  let name_len = try!(reader.read_u16::<BigEndian>());
+  let reserved = try!(reader.read_u16::<BigEndian>());
+  if name_len == 0 || reserved != 0 {
+    return Err(MasterZoneError::InvalidResponse);
+  }
+  let pk = try!(EcdsaPrivateKey::deserialize(reader));
+  match reader.read_c_string() {
+    Ok(ref name) if &name[..] == MASTER_ZONE_SERVICE => Ok(pk.get_public()),
+    _ => Err(MasterZoneError::InvalidResponse),
+  }
+}
+
+/// Read an `IDENTITY_RESULT_CODE` message sent in response to a request for the master zone. The
+/// service only sends these for errors.
+fn read_master_zone_error(reader: &mut Cursor<Vec<u8>>) -> MasterZoneError {
+  if reader.read_u32::<BigEndian>().is_err() {
+    return MasterZoneError::InvalidResponse;
+  }
+  match reader.read_c_string() {
+    Ok(response)  => MasterZoneError::ServiceResponse { response: response },
+    Err(_)        => MasterZoneError::InvalidResponse,
+  }
+}
+
+/// A client for the common case of resolving names in the master zone, driving connections to
+/// both the identity and GNS services from a single thread.
+///
+/// `GNS::connect_with_identity` needs a thread for each service and leaves it to the caller to
+/// shut them down in the right order. Here both connections share one read loop and one
+/// lifecycle: if either service goes away the handle stops receiving from both, and dropping the
+/// handle closes both connections.
+pub struct IdentityGns {
+  identity_writer: ServiceWriter,
+  gns_writer: ServiceWriter,
+  registration_tx: Sender<Registration>,
+  lookup_id: u32,
+  master_zone: Option<EcdsaPublicKey>,
+  resolver: ResolverConfig,
+  protocol: LookupProtocol,
+  lookup_timeout: Option<Duration>,
+  read_loop: SharedReadLoop,
+  identity_catch_all: CatchAll,
+  gns_catch_all: CatchAll,
+}
+
+impl IdentityGns {
+  /// Connect to the identity and GNS services.
+  pub fn connect(cfg: &Cfg) -> Result<IdentityGns, service::ConnectError> {
+    let (identity_reader, identity_writer) = try!(service::connect(cfg, "identity"));
+    let (gns_reader, gns_writer) = try!(service::connect(cfg, "gns"));
+    let identity_catch_all = identity_reader.catch_all();
+    let gns_catch_all = gns_reader.catch_all();
+
+    let (registration_tx, registration_rx) = channel::<Registration>();
+    let mut pending: VecDeque<Sender<Result<EcdsaPublicKey, MasterZoneError>>> = VecDeque::new();
+    let mut handles: HashMap<u32, Sender<Vec<Record>>> = HashMap::new();
+
+    let readers = vec![identity_reader, gns_reader];
+    let read_loop = try!(service::spawn_shared_loop(readers, move |connection: usize, tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
+      loop {
+        match registration_rx.try_recv() {
+          Ok(Registration::MasterZone(tx))  => pending.push_back(tx),
+          Ok(Registration::AbandonMasterZone) => {
+            pending.pop_back();
+          },
+          Ok(Registration::Lookup(id, tx))  => {
+            handles.insert(id, tx);
+          },
+          Ok(Registration::Cancel(id))      => {
+            handles.remove(&id);
+          },
+          Err(TryRecvError::Empty)          => break,
+          Err(TryRecvError::Disconnected)   => return ProcessMessageResult::Shutdown,
+        }
+      };
+      match (connection, tpe) {
+        (IDENTITY_CONNECTION, ll::GNUNET_MESSAGE_TYPE_IDENTITY_SET_DEFAULT) => match pending.pop_front() {
+          Some(tx)  => {
+            let _ = tx.send(read_master_zone(&mut reader));
+          },
+          None      => return ProcessMessageResult::Reconnect,
+        },
+        (IDENTITY_CONNECTION, ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE) => match pending.pop_front() {
+          Some(tx)  => {
+            let _ = tx.send(Err(read_master_zone_error(&mut reader)));
+          },
+          None      => return ProcessMessageResult::Reconnect,
+        },
+        (GNS_CONNECTION, ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP_RESULT) => {
+          let (id, records) = match read_lookup_result(&mut reader) {
+            Ok(x)   => x,
+            Err(()) => return ProcessMessageResult::Reconnect,
+          };
+          // The service answers each lookup with a single message.
+          if let Some(sender) = handles.remove(&id) {
+            let _ = sender.send(records);
+          };
+        },
+        _ => return ProcessMessageResult::Unhandled,
+      };
+      ProcessMessageResult::Continue
+    }));
+
+    Ok(IdentityGns {
+      identity_writer: identity_writer,
+      gns_writer: gns_writer,
+      registration_tx: registration_tx,
+      lookup_id: 0,
+      master_zone: None,
+      resolver: ResolverConfig::from_cfg(cfg),
+      protocol: LookupProtocol::default(),
+      lookup_timeout: cfg.get_relative_time("gns", "LOOKUP_TIMEOUT").ok().map(Duration::from),
+      read_loop: read_loop,
+      identity_catch_all: identity_catch_all,
+      gns_catch_all: gns_catch_all,
+    })
+  }
+
+  /// The handler for messages from the GNS service which this handle doesn't understand. Without
+  /// a handler such messages make the handle stop receiving.
+  pub fn catch_all(&self) -> CatchAll {
+    self.gns_catch_all.clone()
+  }
+
+  /// The handler for messages from the identity service which this handle doesn't understand.
+  pub fn identity_catch_all(&self) -> CatchAll {
+    self.identity_catch_all.clone()
+  }
+
+  /// Send a message the typed API doesn't cover to the GNS service. See
+  /// `ServiceWriter::send_raw`.
+  pub fn send_raw(&mut self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    self.gns_writer.send_raw(tpe, body)
+  }
+
+  /// Send a message the typed API doesn't cover to the identity service. See
+  /// `ServiceWriter::send_raw`.
+  pub fn send_raw_identity(&mut self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    self.identity_writer.send_raw(tpe, body)
+  }
+
+  /// The settings used by `lookup_in_master`. These are read from the `[gns]` section of the
+  /// config when connecting.
+  pub fn resolver_config(&self) -> &ResolverConfig {
+    &self.resolver
+  }
+
+  /// Replace the settings used by `lookup_in_master`.
+  pub fn set_resolver_config(&mut self, resolver: ResolverConfig) {
+    self.resolver = resolver;
+  }
+
+  /// The layout of the lookup requests sent to the GNS daemon.
+  pub fn protocol(&self) -> LookupProtocol {
+    self.protocol
+  }
+
+  /// Set the layout of the lookup requests sent to the GNS daemon.
+  pub fn set_protocol(&mut self, protocol: LookupProtocol) {
+    self.protocol = protocol;
+  }
+
+  /// How long `LookupHandle::wait` waits for the results of lookups made through this handle.
+  /// Like `GNS::lookup_timeout` this is read from `[gns] LOOKUP_TIMEOUT` in the config when
+  /// connecting. `None` means waiting forever.
+  pub fn lookup_timeout(&self) -> Option<Duration> {
+    self.lookup_timeout
+  }
+
+  /// Change how long `LookupHandle::wait` waits for the results of later lookups.
+  pub fn set_lookup_timeout(&mut self, timeout: Option<Duration>) {
+    self.lookup_timeout = timeout;
+  }
+
+  /// The public key of the master zone, the default ego of `gns-master`.
+  ///
+  /// The zone is fetched from the identity service the first time this is called, then
+  /// remembered.
+  pub fn master_zone(&mut self) -> Result<EcdsaPublicKey, MasterZoneError> {
+    if let Some(zone) = self.master_zone {
+      return Ok(zone);
+    }
+    let name_len = MASTER_ZONE_SERVICE.len();
+    let (tx, rx) = channel();
+    // This fails once the read loop has exited, eg. because a service restarted.
+    if self.registration_tx.send(Registration::MasterZone(tx)).is_err() {
+      return Err(MasterZoneError::Disconnected);
+    }
+    {
+      let mut mw = self.identity_writer.write_message((8 + name_len + 1) as u16, ll::GNUNET_MESSAGE_TYPE_IDENTITY_GET_DEFAULT);
+      mw.write_u16::<BigEndian>((name_len + 1) as u16).unwrap();
+      mw.write_u16::<BigEndian>(0).unwrap();
+      mw.write_all(MASTER_ZONE_SERVICE.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      if let Err(e) = mw.send() {
+        let _ = self.registration_tx.send(Registration::AbandonMasterZone);
+        return Err(From::from(e));
+      }
+    };
+    let zone = match rx.recv() {
+      Ok(res) => try!(res),
+      Err(_)  => return Err(MasterZoneError::Disconnected),
+    };
+    self.master_zone = Some(zone);
+    Ok(zone)
+  }
+
+  /// Lookup a GNS record in the given zone. Behaves like `GNS::lookup`.
+  pub fn lookup<'a>(
+      &'a mut self,
+      name: &str,
+      zone: &EcdsaPublicKey,
+      record_type: RecordType,
+      options: LocalOptions,
+      shorten: Option<&EcdsaPrivateKey>
+    ) -> Result<LookupHandle<'a>, LookupError> {
+    let body = try!(lookup_body(self.protocol, self.lookup_id, name, zone, record_type, options, shorten));
+    let id = self.lookup_id;
+    self.lookup_id = self.lookup_id.wrapping_add(1);
+
+    let (tx, rx) = channel::<Vec<Record>>();
+    if self.registration_tx.send(Registration::Lookup(id, tx)).is_err() {
+      return Err(LookupError::Disconnected);
+    }
+    if let Err(e) = self.gns_writer.send_raw(ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP, &body[..]) {
+      let _ = self.registration_tx.send(Registration::Cancel(id));
+      return Err(From::from(e));
+    }
+    let cancel_tx = self.registration_tx.clone();
+    let cancel = move || {
+      let _ = cancel_tx.send(Registration::Cancel(id));
+    };
+    Ok(LookupHandle::new(rx, self.lookup_timeout, Box::new(cancel)))
+  }
+
+  /// Lookup a GNS record in the master zone, with the lookup options chosen by the handle's
+  /// `ResolverConfig`.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, gns};
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let mut client = gns::IdentityGns::connect(&config).unwrap();
+  /// let mut lh = client.lookup_in_master("www.gnu", gns::RecordType::A, None).unwrap();
+  /// println!("Got the IPv4 record for www.gnu: {}", lh.wait().unwrap());
+  /// ```
+  pub fn lookup_in_master<'a>(
+      &'a mut self,
+      name: &str,
+      record_type: RecordType,
+      shorten: Option<&EcdsaPrivateKey>
+    ) -> Result<LookupHandle<'a>, IdentityGnsLookupError> {
+    let zone = try!(self.master_zone());
+    let opt = self.resolver.options_for(name);
+    Ok(try!(self.lookup(name, &zone, record_type, opt, shorten)))
+  }
+}
+
+impl Component for IdentityGns {
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    Some(&mut self.read_loop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Cursor, Write};
+  use byteorder::{BigEndian, WriteBytesExt};
+  use EcdsaPrivateKey;
+  use super::*;
+  use super::{read_master_zone, read_master_zone_error, MASTER_ZONE_SERVICE};
+
+  fn set_default(name: &str, key: &EcdsaPrivateKey) -> Cursor<Vec<u8>> {
+    let mut msg = Vec::new();
+    msg.write_u16::<BigEndian>((name.len() + 1) as u16).unwrap();
+    msg.write_u16::<BigEndian>(0).unwrap();
+    key.serialize(&mut msg).unwrap();
+    msg.write_all(name.as_bytes()).unwrap();
+    msg.write_u8(0).unwrap();
+    Cursor::new(msg)
+  }
+
+  #[test]
+  fn test_read_master_zone() {
+    let key = EcdsaPrivateKey::anonymous();
+    let zone = read_master_zone(&mut set_default(MASTER_ZONE_SERVICE, &key)).unwrap();
+    assert!(zone == key.get_public());
+    match read_master_zone(&mut set_default("fs-sks", &key)) {
+      Err(MasterZoneError::InvalidResponse) => (),
+      _ => panic!("default ego of the wrong service accepted"),
+    };
+
+    let mut result_code = vec![0, 0, 0, 1];
+    result_code.extend_from_slice(b"no default known\0");
+    match read_master_zone_error(&mut Cursor::new(result_code)) {
+      MasterZoneError::ServiceResponse { ref response } if &response[..] == "no default known" => (),
+      e => panic!("unexpected error: {}", e),
+    };
+  }
+}
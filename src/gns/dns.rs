@@ -0,0 +1,484 @@
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::str::{FromStr, from_utf8};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rustc_serialize::hex::{FromHex, ToHex};
+
+use gns::{name_from_wire, name_to_wire};
+
+/// The presentation form of the root name, whose wire form is a single zero byte.
+const ROOT_NAME: &'static str = ".";
+
+/// Errors returned when parsing the human-readable form of a DNS record payload.
+error_def! DnsDataFromStrError {
+  ParsingFailed => "Failed to parse the string as a record payload",
+}
+
+/// The payload of an SRV record, which locates the servers providing a service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvData {
+  /// Servers with a lower priority are tried first.
+  pub priority: u16,
+  /// How often a server is picked relative to others with the same priority.
+  pub weight: u16,
+  /// The port the service is on.
+  pub port: u16,
+  /// The name of the server.
+  pub target: String,
+}
+
+/// The payload of a CAA record, which says which certificate authorities may issue certificates
+/// for a name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaaData {
+  /// The flags of the record. `128` marks the record as critical.
+  pub flags: u8,
+  /// The property the record sets, eg. `issue` or `iodef`.
+  pub tag: String,
+  /// The value of the property.
+  pub value: Vec<u8>,
+}
+
+/// The payload of a DS record, which identifies the key signing a delegated DNSSEC zone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DsData {
+  /// The tag of the key.
+  pub key_tag: u16,
+  /// The algorithm of the key.
+  pub algorithm: u8,
+  /// The algorithm used to make `digest`.
+  pub digest_type: u8,
+  /// The digest of the key.
+  pub digest: Vec<u8>,
+}
+
+/// The payload of a NAPTR record, which rewrites a name into a URI or another name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NaptrData {
+  /// Records with a lower order are used first.
+  pub order: u16,
+  /// Which of the records with the same order to prefer.
+  pub preference: u16,
+  /// Flags controlling how the rewritten name is used.
+  pub flags: String,
+  /// The services available down this rewrite path.
+  pub services: String,
+  /// A substitution expression applied to the name.
+  pub regexp: String,
+  /// The next name to look up, or `.` if `regexp` is used instead.
+  pub replacement: String,
+}
+
+/// Split the wire form of the name at the start of `data` off from the rest of `data`.
+fn split_name(data: &[u8]) -> Option<(&[u8], &[u8])> {
+  let mut pos = 0;
+  loop {
+    let len = match data.get(pos) {
+      Some(&l)  => l as usize,
+      None      => return None,
+    };
+    pos += 1 + len;
+    if len == 0 {
+      break;
+    }
+  }
+  if pos > data.len() {
+    return None;
+  }
+  Some(data.split_at(pos))
+}
+
+fn name_to_dns_wire(name: &str) -> Option<Vec<u8>> {
+  let name = name.trim_right_matches('.');
+  if name.is_empty() {
+    return Some(vec![0]);
+  }
+  name_to_wire(name).ok()
+}
+
+fn name_from_dns_wire(wire: &[u8]) -> Option<String> {
+  if wire == &[0][..] {
+    return Some(ROOT_NAME.to_string());
+  }
+  name_from_wire(wire).ok()
+}
+
+/// Read a length-prefixed character string.
+fn read_character_string(r: &mut Cursor<&[u8]>) -> Option<String> {
+  let len = match r.read_u8() {
+    Ok(l)   => l as usize,
+    Err(_)  => return None,
+  };
+  let mut buf = vec![0u8; len];
+  if r.read_exact(&mut buf[..]).is_err() {
+    return None;
+  }
+  String::from_utf8(buf).ok()
+}
+
+fn write_character_string(w: &mut Vec<u8>, s: &str) -> Option<()> {
+  if s.len() > 255 {
+    return None;
+  }
+  w.push(s.len() as u8);
+  w.extend_from_slice(s.as_bytes());
+  Some(())
+}
+
+/// Split `s` into fields separated by whitespace. A field may be quoted, in which case it can
+/// contain whitespace and `\"`.
+fn split_fields(s: &str) -> Option<Vec<String>> {
+  let mut ret = Vec::new();
+  let mut chars = s.trim().chars().peekable();
+  loop {
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+      chars.next();
+    }
+    let mut field = String::new();
+    match chars.peek() {
+      None        => return Some(ret),
+      Some(&'"')  => {
+        chars.next();
+        loop {
+          match chars.next() {
+            Some('"')   => break,
+            Some('\\')  => match chars.next() {
+              Some(c) => field.push(c),
+              None    => return None,
+            },
+            Some(c)     => field.push(c),
+            None        => return None,
+          }
+        }
+      },
+      Some(_)     => {
+        while let Some(&c) = chars.peek() {
+          if c.is_whitespace() {
+            break;
+          }
+          field.push(c);
+          chars.next();
+        }
+      },
+    }
+    ret.push(field);
+  }
+}
+
+fn quote(s: &str) -> String {
+  format!("\"{}\"", s.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+impl SrvData {
+  /// Parse the payload of an SRV record.
+  pub fn from_bytes(data: &[u8]) -> Option<SrvData> {
+    if data.len() < 6 {
+      return None;
+    }
+    let mut r = Cursor::new(data);
+    let priority = r.read_u16::<BigEndian>().unwrap();
+    let weight = r.read_u16::<BigEndian>().unwrap();
+    let port = r.read_u16::<BigEndian>().unwrap();
+    let target = match name_from_dns_wire(&data[6..]) {
+      Some(t) => t,
+      None    => return None,
+    };
+    Some(SrvData {
+      priority: priority,
+      weight: weight,
+      port: port,
+      target: target,
+    })
+  }
+
+  /// Serialize the payload of an SRV record. Returns `None` if `target` is not a valid name.
+  pub fn to_bytes(&self) -> Option<Vec<u8>> {
+    let target = match name_to_dns_wire(&self.target[..]) {
+      Some(t) => t,
+      None    => return None,
+    };
+    let mut ret = Vec::with_capacity(6 + target.len());
+    ret.write_u16::<BigEndian>(self.priority).unwrap();
+    ret.write_u16::<BigEndian>(self.weight).unwrap();
+    ret.write_u16::<BigEndian>(self.port).unwrap();
+    ret.extend_from_slice(&target[..]);
+    Some(ret)
+  }
+}
+
+/// The human-readable form is `priority weight port target`, eg. `10 5 5060 sip.example.gnu`.
+impl FromStr for SrvData {
+  type Err = DnsDataFromStrError;
+
+  fn from_str(s: &str) -> Result<SrvData, DnsDataFromStrError> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if fields.len() != 4 {
+      return Err(DnsDataFromStrError::ParsingFailed);
+    }
+    match (u16::from_str(fields[0]), u16::from_str(fields[1]), u16::from_str(fields[2])) {
+      (Ok(priority), Ok(weight), Ok(port)) => Ok(SrvData {
+        priority: priority,
+        weight: weight,
+        port: port,
+        target: fields[3].to_string(),
+      }),
+      _ => Err(DnsDataFromStrError::ParsingFailed),
+    }
+  }
+}
+
+impl fmt::Display for SrvData {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {} {} {}", self.priority, self.weight, self.port, self.target)
+  }
+}
+
+impl CaaData {
+  /// Parse the payload of a CAA record.
+  pub fn from_bytes(data: &[u8]) -> Option<CaaData> {
+    if data.len() < 2 {
+      return None;
+    }
+    let flags = data[0];
+    let tag_len = data[1] as usize;
+    if tag_len == 0 || data.len() < 2 + tag_len {
+      return None;
+    }
+    let tag = match from_utf8(&data[2..2 + tag_len]) {
+      Ok(t)   => t.to_string(),
+      Err(_)  => return None,
+    };
+    Some(CaaData {
+      flags: flags,
+      tag: tag,
+      value: data[2 + tag_len..].to_vec(),
+    })
+  }
+
+  /// Serialize the payload of a CAA record. Returns `None` if `tag` is empty or too long.
+  pub fn to_bytes(&self) -> Option<Vec<u8>> {
+    if self.tag.is_empty() || self.tag.len() > 255 {
+      return None;
+    }
+    let mut ret = Vec::with_capacity(2 + self.tag.len() + self.value.len());
+    ret.push(self.flags);
+    ret.push(self.tag.len() as u8);
+    ret.extend_from_slice(self.tag.as_bytes());
+    ret.extend_from_slice(&self.value[..]);
+    Some(ret)
+  }
+}
+
+/// The human-readable form is `flags tag value`, eg. `0 issue ca.example.net`, as written by
+/// GNUnet. The value may be quoted when parsing, so that it can contain spaces.
+impl FromStr for CaaData {
+  type Err = DnsDataFromStrError;
+
+  fn from_str(s: &str) -> Result<CaaData, DnsDataFromStrError> {
+    let fields = match split_fields(s) {
+      Some(f) => f,
+      None    => return Err(DnsDataFromStrError::ParsingFailed),
+    };
+    if fields.len() != 3 || fields[1].is_empty() || !fields[1].chars().all(|c| c.is_alphanumeric()) {
+      return Err(DnsDataFromStrError::ParsingFailed);
+    }
+    match u8::from_str(&fields[0][..]) {
+      Ok(flags) => Ok(CaaData {
+        flags: flags,
+        tag: fields[1].clone(),
+        value: fields[2].as_bytes().to_vec(),
+      }),
+      Err(_)    => Err(DnsDataFromStrError::ParsingFailed),
+    }
+  }
+}
+
+impl fmt::Display for CaaData {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {} {}", self.flags, self.tag, String::from_utf8_lossy(&self.value[..]))
+  }
+}
+
+impl DsData {
+  /// Parse the payload of a DS record.
+  pub fn from_bytes(data: &[u8]) -> Option<DsData> {
+    if data.len() < 4 {
+      return None;
+    }
+    let mut r = Cursor::new(data);
+    Some(DsData {
+      key_tag: r.read_u16::<BigEndian>().unwrap(),
+      algorithm: r.read_u8().unwrap(),
+      digest_type: r.read_u8().unwrap(),
+      digest: data[4..].to_vec(),
+    })
+  }
+
+  /// Serialize the payload of a DS record.
+  pub fn to_bytes(&self) -> Option<Vec<u8>> {
+    let mut ret = Vec::with_capacity(4 + self.digest.len());
+    ret.write_u16::<BigEndian>(self.key_tag).unwrap();
+    ret.push(self.algorithm);
+    ret.push(self.digest_type);
+    ret.extend_from_slice(&self.digest[..]);
+    Some(ret)
+  }
+}
+
+/// The human-readable form is `key_tag algorithm digest_type digest` with the digest in hex, eg.
+/// `60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118`.
+impl FromStr for DsData {
+  type Err = DnsDataFromStrError;
+
+  fn from_str(s: &str) -> Result<DsData, DnsDataFromStrError> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    if fields.len() < 4 {
+      return Err(DnsDataFromStrError::ParsingFailed);
+    }
+    // Long digests are sometimes split into several fields.
+    let digest: String = fields[3..].concat();
+    match (u16::from_str(fields[0]), u8::from_str(fields[1]), u8::from_str(fields[2]), digest.from_hex()) {
+      (Ok(key_tag), Ok(algorithm), Ok(digest_type), Ok(digest)) => Ok(DsData {
+        key_tag: key_tag,
+        algorithm: algorithm,
+        digest_type: digest_type,
+        digest: digest,
+      }),
+      _ => Err(DnsDataFromStrError::ParsingFailed),
+    }
+  }
+}
+
+impl fmt::Display for DsData {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {} {} {}", self.key_tag, self.algorithm, self.digest_type,
+           self.digest.to_hex().to_uppercase())
+  }
+}
+
+impl NaptrData {
+  /// Parse the payload of a NAPTR record.
+  pub fn from_bytes(data: &[u8]) -> Option<NaptrData> {
+    let mut r = Cursor::new(data);
+    let (order, preference) = match (r.read_u16::<BigEndian>(), r.read_u16::<BigEndian>()) {
+      (Ok(o), Ok(p))  => (o, p),
+      _               => return None,
+    };
+    let flags = read_character_string(&mut r);
+    let services = read_character_string(&mut r);
+    let regexp = read_character_string(&mut r);
+    let (flags, services, regexp) = match (flags, services, regexp) {
+      (Some(f), Some(s), Some(r)) => (f, s, r),
+      _                           => return None,
+    };
+    let rest = &data[r.position() as usize..];
+    let replacement = match split_name(rest) {
+      Some((name, tail)) if tail.is_empty() => name_from_dns_wire(name),
+      _                                     => None,
+    };
+    replacement.map(|replacement| NaptrData {
+      order: order,
+      preference: preference,
+      flags: flags,
+      services: services,
+      regexp: regexp,
+      replacement: replacement,
+    })
+  }
+
+  /// Serialize the payload of a NAPTR record. Returns `None` if a string is longer than 255 bytes
+  /// or `replacement` is not a valid name.
+  pub fn to_bytes(&self) -> Option<Vec<u8>> {
+    let mut ret = Vec::new();
+    ret.write_u16::<BigEndian>(self.order).unwrap();
+    ret.write_u16::<BigEndian>(self.preference).unwrap();
+    for s in [&self.flags, &self.services, &self.regexp].iter() {
+      if write_character_string(&mut ret, &s[..]).is_none() {
+        return None;
+      }
+    }
+    name_to_dns_wire(&self.replacement[..]).map(|replacement| {
+      ret.extend_from_slice(&replacement[..]);
+      ret
+    })
+  }
+}
+
+/// The human-readable form is `order preference "flags" "services" "regexp" replacement`, eg.
+/// `100 10 "u" "E2U+sip" "!^.*$!sip:info@example.gnu!" .`.
+impl FromStr for NaptrData {
+  type Err = DnsDataFromStrError;
+
+  fn from_str(s: &str) -> Result<NaptrData, DnsDataFromStrError> {
+    let fields = match split_fields(s) {
+      Some(f) => f,
+      None    => return Err(DnsDataFromStrError::ParsingFailed),
+    };
+    if fields.len() != 6 {
+      return Err(DnsDataFromStrError::ParsingFailed);
+    }
+    match (u16::from_str(&fields[0][..]), u16::from_str(&fields[1][..])) {
+      (Ok(order), Ok(preference)) => Ok(NaptrData {
+        order: order,
+        preference: preference,
+        flags: fields[2].clone(),
+        services: fields[3].clone(),
+        regexp: fields[4].clone(),
+        replacement: fields[5].clone(),
+      }),
+      _ => Err(DnsDataFromStrError::ParsingFailed),
+    }
+  }
+}
+
+impl fmt::Display for NaptrData {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {} {} {} {} {}", self.order, self.preference, quote(&self.flags[..]),
+           quote(&self.services[..]), quote(&self.regexp[..]), self.replacement)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+  use super::*;
+
+  #[test]
+  fn test_round_trips() {
+    let srv = SrvData::from_str("10 5 5060 _sip._udp.example.gnu").unwrap();
+    let bytes = srv.to_bytes().unwrap();
+    assert_eq!(&bytes[..7], &[0, 10, 0, 5, 0x13, 0xc4, 4][..]);
+    assert_eq!(SrvData::from_bytes(&bytes[..]).unwrap(), srv);
+    assert_eq!(srv.to_string(), "10 5 5060 _sip._udp.example.gnu");
+
+    let caa = CaaData::from_str("0 issue \"ca.example.net\"").unwrap();
+    let bytes = caa.to_bytes().unwrap();
+    assert_eq!(&bytes[..7], b"\x00\x05issue");
+    assert_eq!(CaaData::from_bytes(&bytes[..]).unwrap(), caa);
+    assert_eq!(caa.to_string(), "0 issue ca.example.net");
+    assert_eq!(CaaData::from_str("0 issue ca.example.net").unwrap(), caa);
+
+    let ds = DsData::from_str("60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118").unwrap();
+    let bytes = ds.to_bytes().unwrap();
+    assert_eq!(bytes.len(), 24);
+    assert_eq!(DsData::from_bytes(&bytes[..]).unwrap(), ds);
+    assert_eq!(ds.to_string(), "60485 5 1 2BB183AF5F22588179A53B0A98631FAD1A292118");
+
+    let naptr = NaptrData::from_str("100 10 \"u\" \"E2U+sip\" \"!^.*$!sip:info@example.gnu!\" .").unwrap();
+    assert_eq!(naptr.regexp, "!^.*$!sip:info@example.gnu!");
+    let bytes = naptr.to_bytes().unwrap();
+    assert_eq!(*bytes.last().unwrap(), 0);
+    assert_eq!(NaptrData::from_bytes(&bytes[..]).unwrap(), naptr);
+    assert_eq!(naptr.to_string(), "100 10 \"u\" \"E2U+sip\" \"!^.*$!sip:info@example.gnu!\" .");
+  }
+
+  #[test]
+  fn test_malformed() {
+    assert!(SrvData::from_bytes(&[0, 10, 0, 5]).is_none());
+    assert!(SrvData::from_str("10 5 sip.example.gnu").is_err());
+    assert!(CaaData::from_bytes(&[0, 9, b'i']).is_none());
+    assert!(CaaData::from_str("0 issue \"unterminated").is_err());
+    assert!(DsData::from_str("60485 5 1 XYZ").is_err());
+    assert!(NaptrData::from_bytes(&[0, 100, 0, 10, 1, b'u', 0, 0, 3]).is_none());
+  }
+}
@@ -0,0 +1,148 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use Cfg;
+use gns::{lookup_types_in_master, ConnectLookupInMasterError, Record, RecordType};
+
+/// How long answers are remembered if not set otherwise.
+const DEFAULT_TTL_SECS: u64 = 60;
+/// How long to wait for the GNS service if not set otherwise.
+const DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+/// Errors returned by `HostResolver::resolve`.
+error_def! HostLookupError {
+  InvalidHost { host: String }
+    => "The host is not a name which can be looked up" ("Host: \"{}\"", host),
+  Lookup { #[from] cause: ConnectLookupInMasterError }
+    => "Failed to look the name up in the master zone" ("Reason: {}", cause),
+}
+
+/// Looks up the hosts named in web requests in the master zone.
+///
+/// A gateway between the web and GNS, such as a proxy which lets browsers visit `.gnu` sites,
+/// needs the records of the host named in each request's `Host` header. A `HostResolver` gives up
+/// on lookups which take too long and remembers the answers for a while, so that the many
+/// requests made for one page don't each cost a lookup. It is not tied to any HTTP library: give
+/// it the header's value and attach the records it returns to the request in whatever way the
+/// server allows. With the `web` feature it is also Iron middleware, see `HostRecords`. It can be
+/// shared by the threads handling requests.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use gnunet::Cfg;
+/// use gnunet::gns::HostResolver;
+///
+/// let config = Cfg::default().unwrap();
+/// let mut resolver = HostResolver::new(&config);
+/// resolver.set_timeout(Duration::from_secs(2));
+/// for record in resolver.resolve("www.gnu:8080").unwrap() {
+///   println!("{}", record);
+/// }
+/// ```
+pub struct HostResolver {
+  cfg: Cfg,
+  record_types: Vec<RecordType>,
+  timeout: Duration,
+  ttl: Duration,
+  cache: Mutex<HashMap<String, (Instant, Vec<Record>)>>,
+}
+
+/// The name in `host`, the value of a `Host` header. The port is removed and the name is made
+/// lower case. Returns `None` for IP addresses and empty names.
+pub fn host_name(host: &str) -> Option<String> {
+  let host = host.trim();
+  if host.starts_with('[') {
+    return None;
+  }
+  let name = match host.rfind(':') {
+    Some(i) if host[i + 1..].chars().all(|c| c.is_digit(10)) => &host[..i],
+    _ => host,
+  };
+  let name = name.trim_right_matches('.');
+  if name.is_empty() || name.parse::<IpAddr>().is_ok() {
+    return None;
+  }
+  Some(name.to_ascii_lowercase())
+}
+
+impl HostResolver {
+  /// Create a resolver which talks to the services described by `cfg`. It looks up A, AAAA and
+  /// LEHO records, waits up to five seconds for them and remembers the answers for a minute.
+  pub fn new(cfg: &Cfg) -> HostResolver {
+    HostResolver {
+      cfg: cfg.clone(),
+      record_types: vec![RecordType::A, RecordType::AAAA, RecordType::LEHO],
+      timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+      ttl: Duration::from_secs(DEFAULT_TTL_SECS),
+      cache: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Change the types of records looked up.
+  pub fn set_record_types(&mut self, record_types: Vec<RecordType>) {
+    self.record_types = record_types;
+  }
+
+  /// Change how long to wait for the GNS service to answer all the lookups for a host.
+  pub fn set_timeout(&mut self, timeout: Duration) {
+    self.timeout = timeout;
+  }
+
+  /// Change how long answers are remembered. Answers without any records are remembered too.
+  pub fn set_ttl(&mut self, ttl: Duration) {
+    self.ttl = ttl;
+  }
+
+  /// Forget all the answers remembered so far.
+  pub fn clear_cache(&self) {
+    self.cache.lock().unwrap().clear();
+  }
+
+  /// Get the records of the host named by `host`, the value of a request's `Host` header. Returns
+  /// no records if the service doesn't answer in time.
+  pub fn resolve(&self, host: &str) -> Result<Vec<Record>, HostLookupError> {
+    let name = match host_name(host) {
+      Some(name)  => name,
+      None        => return Err(HostLookupError::InvalidHost { host: host.to_string() }),
+    };
+    let now = Instant::now();
+    if let Some(&(expires, ref records)) = self.cache.lock().unwrap().get(&name) {
+      if expires > now {
+        return Ok(records.clone());
+      }
+    }
+
+    let records = try!(lookup_types_in_master(&self.cfg, &name, &self.record_types[..], self.timeout));
+    let mut cache = self.cache.lock().unwrap();
+    let now = Instant::now();
+    let expired: Vec<String> = cache.iter()
+                                    .filter(|&(_, &(expires, _))| expires <= now)
+                                    .map(|(name, _)| name.clone())
+                                    .collect();
+    for name in expired {
+      cache.remove(&name);
+    }
+    cache.insert(name, (now + self.ttl, records.clone()));
+    Ok(records)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_host_name() {
+    assert_eq!(host_name("www.gnu"), Some("www.gnu".to_string()));
+    assert_eq!(host_name("WWW.Alice.gnu.:8080"), Some("www.alice.gnu".to_string()));
+    assert_eq!(host_name(" www.gnu:80 "), Some("www.gnu".to_string()));
+    assert_eq!(host_name("10.0.0.1:80"), None);
+    assert_eq!(host_name("[fd00::1]:80"), None);
+    assert_eq!(host_name(":80"), None);
+  }
+}
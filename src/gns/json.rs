@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::str::{FromStr, from_utf8};
+use std::u64;
+use rustc_serialize::json::{self, Json};
+
+use ll;
+use time;
+use gns::{Record, RecordType};
+
+/// Errors returned when converting records to or from JSON.
+error_def! RecordJsonError {
+  Parse { #[from] cause: json::ParserError }
+    => "The JSON is malformed" ("Reason: {}", cause),
+  MissingField { field: &'static str }
+    => "A required field is missing" ("Field \"{}\" is missing", field),
+  WrongType { field: &'static str }
+    => "A field has the wrong type" ("Field \"{}\" has the wrong type", field),
+  UnknownRecordType { name: String }
+    => "The record type is not known" ("Record type \"{}\" is not known", name),
+  InvalidValue { record_type: RecordType, value: String }
+    => "The value is not valid for its record type" ("\"{}\" is not a valid {} value", value, record_type),
+  InvalidExpiration { value: String }
+    => "The expiration time could not be parsed" ("\"{}\" is not a valid expiration time", value),
+  MalformedRecord { record_type: RecordType }
+    => "A record's data is malformed and has no human-readable form" ("Malformed {} record", record_type),
+}
+
+/// The flags which have their own field in the JSON representation.
+const RF_PRIVATE: u32 = ll::GNUNET_GNSRECORD_RF_PRIVATE as u32;
+const RF_RELATIVE_EXPIRATION: u32 = ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION as u32;
+const RF_SHADOW_RECORD: u32 = ll::GNUNET_GNSRECORD_RF_SHADOW_RECORD as u32;
+
+/// Write a relative time in the largest unit which represents it exactly, eg. `"2 h"`.
+fn relative_to_string(micros: u64) -> String {
+  const UNITS: [(&'static str, u64); 6] = [
+    ("d", 24 * 60 * 60 * 1000 * 1000),
+    ("h", 60 * 60 * 1000 * 1000),
+    ("m", 60 * 1000 * 1000),
+    ("s", 1000 * 1000),
+    ("ms", 1000),
+    ("us", 1),
+  ];
+
+  if micros == u64::MAX {
+    return "forever".to_string();
+  }
+  for &(unit, size) in UNITS.iter() {
+    if micros % size == 0 && micros > 0 {
+      return format!("{} {}", micros / size, unit);
+    }
+  }
+  "0 us".to_string()
+}
+
+fn relative_from_str(s: &str) -> Option<u64> {
+  match s.trim() {
+    "forever" => Some(u64::MAX),
+    s         => time::Relative::from_str(s).ok().map(|r| r.as_micros()),
+  }
+}
+
+/// Write an absolute time the way GNUnet does. GNUnet's format has a resolution of one second,
+/// so times with a fractional second are written as a number of microseconds instead.
+fn absolute_to_string(micros: u64) -> String {
+  if micros == u64::MAX {
+    return "end of time".to_string();
+  }
+  if micros % 1000000 != 0 {
+    return micros.to_string();
+  }
+  unsafe {
+    let t = ll::Struct_GNUNET_TIME_Absolute { abs_value_us: micros };
+    let cs = ll::GNUNET_STRINGS_absolute_time_to_string(t);
+    from_utf8(CStr::from_ptr(cs).to_bytes()).unwrap_or("").to_string()
+  }
+}
+
+fn absolute_from_str(s: &str) -> Option<u64> {
+  let s = s.trim();
+  if s == "end of time" {
+    return Some(u64::MAX);
+  }
+  if !s.is_empty() && s.bytes().all(|b| b >= b'0' && b <= b'9') {
+    return u64::from_str(s).ok();
+  }
+  let cs = match CString::new(s) {
+    Ok(cs)  => cs,
+    Err(_)  => return None,
+  };
+  unsafe {
+    let mut t = ll::Struct_GNUNET_TIME_Absolute { abs_value_us: 0 };
+    match ll::GNUNET_STRINGS_fancy_time_to_absolute(cs.as_ptr(), &mut t) {
+      ll::GNUNET_OK => Some(t.abs_value_us),
+      _             => None,
+    }
+  }
+}
+
+/// Convert a record to the JSON object used by `gnunet-namestore` and the namestore REST API.
+///
+/// The object has the fields `value`, `record_type`, `expiration_time`, `private`,
+/// `relative_expiration` and `shadow`. Fails if the record's data has no human-readable form.
+pub fn record_to_json(record: &Record) -> Result<Json, RecordJsonError> {
+  let value = match record.value_to_string() {
+    Some(v) => v,
+    None    => return Err(RecordJsonError::MalformedRecord { record_type: record.record_type() }),
+  };
+  let flags = record.flags();
+  let relative = flags & RF_RELATIVE_EXPIRATION != 0;
+  let expiration = match relative {
+    true  => relative_to_string(record.expiration_time()),
+    false => absolute_to_string(record.expiration_time()),
+  };
+  let mut obj = BTreeMap::new();
+  obj.insert("value".to_string(), Json::String(value));
+  obj.insert("record_type".to_string(), Json::String(record.record_type().to_string()));
+  obj.insert("expiration_time".to_string(), Json::String(expiration));
+  obj.insert("private".to_string(), Json::Boolean(flags & RF_PRIVATE != 0));
+  obj.insert("relative_expiration".to_string(), Json::Boolean(relative));
+  obj.insert("shadow".to_string(), Json::Boolean(flags & RF_SHADOW_RECORD != 0));
+  Ok(Json::Object(obj))
+}
+
+fn get_str<'a>(obj: &'a BTreeMap<String, Json>, field: &'static str) -> Result<&'a str, RecordJsonError> {
+  match obj.get(field) {
+    Some(&Json::String(ref s))  => Ok(&s[..]),
+    Some(_)                     => Err(RecordJsonError::WrongType { field: field }),
+    None                        => Err(RecordJsonError::MissingField { field: field }),
+  }
+}
+
+/// Missing flags default to `false`.
+fn get_bool(obj: &BTreeMap<String, Json>, field: &'static str) -> Result<bool, RecordJsonError> {
+  match obj.get(field) {
+    Some(&Json::Boolean(b)) => Ok(b),
+    Some(_)                 => Err(RecordJsonError::WrongType { field: field }),
+    None                    => Ok(false),
+  }
+}
+
+/// Parse a record from the JSON object produced by `record_to_json` or `gnunet-namestore`.
+pub fn record_from_json(json: &Json) -> Result<Record, RecordJsonError> {
+  let obj = match *json {
+    Json::Object(ref obj) => obj,
+    _                     => return Err(RecordJsonError::WrongType { field: "data" }),
+  };
+  let type_name = try!(get_str(obj, "record_type"));
+  let record_type = match RecordType::from_str(type_name) {
+    Ok(t)   => t,
+    Err(_)  => return Err(RecordJsonError::UnknownRecordType { name: type_name.to_string() }),
+  };
+  let mut flags = 0;
+  if try!(get_bool(obj, "private")) {
+    flags |= RF_PRIVATE;
+  }
+  if try!(get_bool(obj, "shadow")) {
+    flags |= RF_SHADOW_RECORD;
+  }
+  let relative = try!(get_bool(obj, "relative_expiration"));
+  let expiration_str = try!(get_str(obj, "expiration_time"));
+  let expiration = match relative {
+    true  => {
+      flags |= RF_RELATIVE_EXPIRATION;
+      relative_from_str(expiration_str)
+    },
+    false => absolute_from_str(expiration_str),
+  };
+  let expiration = match expiration {
+    Some(e) => e,
+    None    => return Err(RecordJsonError::InvalidExpiration { value: expiration_str.to_string() }),
+  };
+  let value = try!(get_str(obj, "value"));
+  match Record::from_value_str(record_type, value, expiration, flags) {
+    Some(r) => Ok(r),
+    None    => Err(RecordJsonError::InvalidValue { record_type: record_type, value: value.to_string() }),
+  }
+}
+
+fn record_set_to_json(label: &str, records: &[Record]) -> Result<Json, RecordJsonError> {
+  let mut data = Vec::with_capacity(records.len());
+  for record in records.iter() {
+    data.push(try!(record_to_json(record)));
+  }
+  let mut obj = BTreeMap::new();
+  obj.insert("record_name".to_string(), Json::String(label.to_string()));
+  obj.insert("data".to_string(), Json::Array(data));
+  Ok(Json::Object(obj))
+}
+
+fn record_set_from_json(json: &Json) -> Result<(String, Vec<Record>), RecordJsonError> {
+  let obj = match *json {
+    Json::Object(ref obj) => obj,
+    _                     => return Err(RecordJsonError::WrongType { field: "record_name" }),
+  };
+  let label = try!(get_str(obj, "record_name")).to_string();
+  let data = match obj.get("data") {
+    Some(&Json::Array(ref data))  => data,
+    Some(_)                       => return Err(RecordJsonError::WrongType { field: "data" }),
+    None                          => return Err(RecordJsonError::MissingField { field: "data" }),
+  };
+  let mut records = Vec::with_capacity(data.len());
+  for record in data.iter() {
+    records.push(try!(record_from_json(record)));
+  }
+  Ok((label, records))
+}
+
+/// Write the records under `label` as a JSON object of the form
+/// `{"record_name": label, "data": [records...]}`.
+pub fn records_to_json(label: &str, records: &[Record]) -> Result<String, RecordJsonError> {
+  Ok(try!(record_set_to_json(label, records)).to_string())
+}
+
+/// Parse a JSON object written by `records_to_json`. Returns the label and its records.
+pub fn records_from_json(s: &str) -> Result<(String, Vec<Record>), RecordJsonError> {
+  record_set_from_json(&try!(Json::from_str(s)))
+}
+
+/// Write a whole zone as a JSON array with one object per label, as `records_to_json` writes
+/// them.
+pub fn zone_to_json(zone: &[(String, Vec<Record>)]) -> Result<String, RecordJsonError> {
+  let mut sets = Vec::with_capacity(zone.len());
+  for &(ref label, ref records) in zone.iter() {
+    sets.push(try!(record_set_to_json(label, &records[..])));
+  }
+  Ok(Json::Array(sets).to_string())
+}
+
+/// Parse a zone written by `zone_to_json`.
+pub fn zone_from_json(s: &str) -> Result<Vec<(String, Vec<Record>)>, RecordJsonError> {
+  let sets = match try!(Json::from_str(s)) {
+    Json::Array(sets) => sets,
+    _                 => return Err(RecordJsonError::WrongType { field: "record_name" }),
+  };
+  let mut ret = Vec::with_capacity(sets.len());
+  for set in sets.iter() {
+    ret.push(try!(record_set_from_json(set)));
+  }
+  Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::u64;
+  use gns::{Record, RecordType};
+  use super::*;
+  use super::{relative_to_string, relative_from_str, absolute_from_str, RF_PRIVATE, RF_RELATIVE_EXPIRATION};
+
+  #[test]
+  fn test_json_times() {
+    assert_eq!(relative_to_string(2 * 60 * 60 * 1000 * 1000), "2 h");
+    assert_eq!(relative_to_string(1500 * 1000), "1500 ms");
+    assert_eq!(relative_to_string(0), "0 us");
+    assert_eq!(relative_to_string(u64::MAX), "forever");
+    assert_eq!(relative_from_str("2 h"), Some(2 * 60 * 60 * 1000 * 1000));
+    assert_eq!(relative_from_str("forever"), Some(u64::MAX));
+    assert_eq!(absolute_from_str("end of time"), Some(u64::MAX));
+    assert_eq!(absolute_from_str("1461234567123456"), Some(1461234567123456));
+  }
+
+  #[test]
+  fn test_records_json_round_trip() {
+    let records = vec![
+      Record::from_value_str(RecordType::A, "10.0.0.1", 60 * 60 * 1000 * 1000, RF_RELATIVE_EXPIRATION).unwrap(),
+      Record::from_value_str(RecordType::TXT, "hello \"world\"", 1461234567123456, RF_PRIVATE).unwrap(),
+      Record::from_value_str(RecordType::SRV, "10 5 5060 sip.example.gnu", 1461234567000000, 0).unwrap(),
+      Record::from_value_str(RecordType::CAA, "0 issue \"ca.example.net\"", 1461234567000000, 0).unwrap(),
+    ];
+    let json = records_to_json("www", &records[..]).unwrap();
+    let (label, parsed) = records_from_json(&json).unwrap();
+    assert_eq!(label, "www");
+    assert_eq!(parsed, records);
+
+    match records_from_json(r#"{"record_name": "www", "data": [{"record_type": "BOGUS"}]}"#) {
+      Err(RecordJsonError::UnknownRecordType { .. }) => (),
+      x => panic!("unexpected result: {:?}", x),
+    };
+  }
+}
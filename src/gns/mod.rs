@@ -1,26 +1,67 @@
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::marker::PhantomData;
-use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use std::io::{self, Write, Cursor};
+use std::time::{Duration, Instant};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use num::ToPrimitive;
 
-use identity;
+use capabilities::Capabilities;
+use identity::{self, IdentityService};
 use ll;
-use service::{self, ServiceReadLoop, ServiceWriter, ProcessMessageResult};
+use service::{self, CatchAll, DedupStats, Inflight, ServiceReadLoop, ServiceWriter, ProcessMessageResult, RetryPolicy, Transient};
 use EcdsaPublicKey;
 use EcdsaPrivateKey;
 use Cfg;
+use shutdown::{Component, ReadLoop};
 pub use self::record::*;
+pub use self::query::{derive_block_key, query_from_public_key, query_from_private_key};
+pub use self::name::*;
+pub use self::json::*;
+pub use self::resolver::*;
+pub use self::protocol::*;
+pub use self::combined::*;
+pub use self::audit::*;
+pub use self::dns::*;
+pub use self::chain::*;
+pub use self::block::*;
+pub use self::gateway::*;
+pub use self::start_zone::*;
+#[cfg(feature = "web")]
+pub use self::web::*;
 
 mod record;
+mod query;
+mod name;
+mod json;
+mod resolver;
+mod protocol;
+mod combined;
+mod audit;
+mod dns;
+mod chain;
+mod block;
+mod gateway;
+mod start_zone;
+#[cfg(feature = "web")]
+mod web;
+
+/// The record flags defined by the GNS service.
+const KNOWN_RECORD_FLAGS: u32 = ll::GNUNET_GNSRECORD_RF_PRIVATE
+                              | ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION
+                              | ll::GNUNET_GNSRECORD_RF_SHADOW_RECORD;
 
 /// A handle to a locally-running instance of the GNS daemon.
 pub struct GNS {
   service_writer: ServiceWriter,
-  _callback_loop: ServiceReadLoop,
+  callback_loop: ServiceReadLoop,
   lookup_id: u32,
-  lookup_tx: Sender<(u32, Sender<Record>)>,
+  inflight: Arc<Mutex<Inflight<Vec<Record>>>>,
+  identity: Option<Arc<Mutex<IdentityService>>>,
+  master_zone: Option<EcdsaPublicKey>,
+  resolver: ResolverConfig,
+  protocol: LookupProtocol,
+  lookup_timeout: Option<Duration>,
 }
 
 /// Options for GNS lookups.
@@ -39,8 +80,97 @@ pub enum LocalOptions {
 error_def! LookupError {
   NameTooLong { name: String }
     => "The domain name was too long" ("The domain name \"{}\" is too long to lookup.", name),
+  ShortenUnsupported
+    => "A shorten zone was given but the GNS daemon does not support shortening",
   Io { #[from] cause: io::Error }
     => "There was an I/O error communicating with the service" ("Specifically {}", cause),
+  Timeout
+    => "The lookup did not finish in time",
+  Disconnected
+    => "The connection to the GNS service was lost before the lookup finished",
+}
+
+/// Errors returned by `GNS::lookup_in_master`.
+error_def! LookupInMasterError {
+  NoIdentityService
+    => "The GNS handle was not given an identity service handle to find the master zone with",
+  GetDefaultEgo { #[from] cause: identity::GetDefaultEgoError }
+    => "Failed to retrieve the default identity for gns-master from the identity service" ("Reason: {}", cause),
+  Lookup { #[from] cause: LookupError }
+    => "Failed to perform the lookup." ("Reason: {}", cause),
+}
+
+impl Transient for LookupError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      LookupError::Io { .. }        => true,
+      LookupError::Timeout          => true,
+      LookupError::Disconnected     => true,
+      _                             => false,
+    }
+  }
+}
+
+impl Transient for LookupInMasterError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      LookupInMasterError::GetDefaultEgo { ref cause }  => cause.is_transient(),
+      LookupInMasterError::Lookup { ref cause }         => cause.is_transient(),
+      LookupInMasterError::NoIdentityService            => false,
+    }
+  }
+}
+
+/// Build the body of a `GNS_LOOKUP` request with id `id`, laid out according to `protocol`.
+fn lookup_body(
+    protocol: LookupProtocol,
+    id: u32,
+    name: &str,
+    zone: &EcdsaPublicKey,
+    record_type: RecordType,
+    options: LocalOptions,
+    shorten: Option<&EcdsaPrivateKey>
+  ) -> Result<Vec<u8>, LookupError> {
+  let name_len = name.len();
+  if name_len > ll::GNUNET_DNSPARSER_MAX_NAME_LENGTH as usize {
+    return Err(LookupError::NameTooLong { name: name.to_string() });
+  };
+
+  if shorten.is_some() && !protocol.supports_shorten() {
+    return Err(LookupError::ShortenUnsupported);
+  };
+
+  let mut body = Vec::with_capacity(protocol.lookup_message_len(name_len) - 4);
+  body.write_u32::<BigEndian>(id).unwrap();
+  zone.serialize(&mut body).unwrap();
+  body.write_i16::<BigEndian>(options as i16).unwrap();
+  match protocol {
+    LookupProtocol::Shorten   => body.write_i16::<BigEndian>(shorten.is_some() as i16).unwrap(),
+    LookupProtocol::NoShorten => body.write_u16::<BigEndian>(DEFAULT_RECURSION_DEPTH_LIMIT).unwrap(),
+  };
+  body.write_i32::<BigEndian>(record_type.to_u32() as i32).unwrap();
+  match (protocol, shorten) {
+    (LookupProtocol::Shorten, Some(z))  => z.serialize(&mut body).unwrap(),
+    (LookupProtocol::Shorten, None)     => body.write_all(&[0u8; 32]).unwrap(),
+    (LookupProtocol::NoShorten, _)      => (),
+  };
+  body.write_all(name.as_bytes()).unwrap();
+  body.write_u8(0u8).unwrap();
+  Ok(body)
+}
+
+/// Read a `GNS_LOOKUP_RESULT` message. Returns the id of the lookup and the records found.
+fn read_lookup_result(reader: &mut Cursor<Vec<u8>>) -> Result<(u32, Vec<Record>), ()> {
+  let id = try!(reader.read_u32::<BigEndian>().map_err(|_| ()));
+  let rd_count = try!(reader.read_u32::<BigEndian>().map_err(|_| ()));
+  let mut records = Vec::new();
+  for _ in 0..rd_count {
+    let record = try!(Record::deserialize(reader).map_err(|_| ()));
+    try!(service::check_flags(record.flags(), KNOWN_RECORD_FLAGS).map_err(|_| ()));
+    records.push(record);
+  }
+  try!(service::check_end(reader).map_err(|_| ()));
+  Ok((id, records))
 }
 
 impl GNS {
@@ -49,72 +179,128 @@ impl GNS {
   /// Returns either a handle to the GNS service or a `service::ConnectError`. `cfg` contains the
   /// configuration to use to connect to the service.
   pub fn connect(cfg: &Cfg) -> Result<GNS, service::ConnectError> {
-    let (lookup_tx, lookup_rx) = channel::<(u32, Sender<Record>)>();
-    let mut handles: HashMap<u32, Sender<Record>> = HashMap::new();
+    GNS::connect_inner(cfg, None)
+  }
 
-    let (service_reader, service_writer) = try!(service::connect(cfg, "gns"));
-    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
-      println!("GNS got message!");
-      loop {
-        match lookup_rx.try_recv() {
-          Ok((id, sender)) => {
-            handles.insert(id, sender);
-          },
-          Err(e)  => match e {
-            TryRecvError::Empty         => break,
-            TryRecvError::Disconnected  => return ProcessMessageResult::Shutdown,
-          },
-        }
-      }
+  /// Connect to the GNS service, sharing an existing connection to the identity service.
+  ///
+  /// The identity service is used by `lookup_in_master` to find the master zone. The same handle
+  /// can be shared with other parts of the application, it is available through `identity`.
+  pub fn connect_with_identity(cfg: &Cfg, identity: Arc<Mutex<IdentityService>>) -> Result<GNS, service::ConnectError> {
+    GNS::connect_inner(cfg, Some(identity))
+  }
 
-      println!("tpe == {}", tpe);
+  fn connect_inner(cfg: &Cfg, identity: Option<Arc<Mutex<IdentityService>>>) -> Result<GNS, service::ConnectError> {
+    let inflight = Arc::new(Mutex::new(Inflight::new()));
+    let cb_inflight = inflight.clone();
 
-      // TODO: drop expired senders, this currently leaks memory as `handles` only gets bigger
-      //       need a way to detect when the remote Receiver has hung up
+    let (service_reader, service_writer) = try!(service::connect(cfg, "gns"));
+    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
+      // Lookups are registered in `inflight` until the service answers them or their handles are
+      // dropped, so nothing is kept for a lookup once it is over.
       match tpe {
         ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP_RESULT => {
-          let id = match reader.read_u32::<BigEndian>() {
-            Ok(id)  => id,
-            Err(_)  => return ProcessMessageResult::Reconnect,
-          };
-          println!("WOW id == {}", id);
-          match handles.get(&id) {
-            Some(sender) => {
-              println!("WOW there's a sender for that");
-              let rd_count = match reader.read_u32::<BigEndian>() {
-                Ok(x)   => x,
-                Err(_)  => return ProcessMessageResult::Reconnect,
-              };
-              println!("WOW rd_count == {}", rd_count);
-              for _ in 0..rd_count {
-                let rec = match Record::deserialize(&mut reader) {
-                  Ok(r)   => r,
-                  Err(_)  => return ProcessMessageResult::Reconnect,
-                };
-                println!("WOW we deserialised it");
-                let _ = sender.send(rec);
-              };
-            },
-            _ => (),
+          let (id, records) = match read_lookup_result(&mut reader) {
+            Ok(x)   => x,
+            Err(()) => return ProcessMessageResult::Reconnect,
           };
+          // The service answers each lookup with a single message, so later identical lookups
+          // must be sent afresh.
+          let senders = cb_inflight.lock().unwrap().finish(id as u64);
+          for sender in senders.iter() {
+            let _ = sender.send(records.clone());
+          }
         },
-        _ => return ProcessMessageResult::Reconnect,
+        _ => return ProcessMessageResult::Unhandled,
       };
       ProcessMessageResult::Continue
     }));
     Ok(GNS {
       service_writer: service_writer,
-      _callback_loop: callback_loop,
+      callback_loop: callback_loop,
       lookup_id: 0,
-      lookup_tx: lookup_tx,
+      inflight: inflight,
+      identity: identity,
+      master_zone: None,
+      resolver: ResolverConfig::from_cfg(cfg),
+      protocol: LookupProtocol::default(),
+      lookup_timeout: cfg.get_relative_time("gns", "LOOKUP_TIMEOUT").ok().map(Duration::from),
     })
   }
 
+  /// How long `LookupHandle::wait` waits for the results of lookups made through this handle.
+  /// This is read from `[gns] LOOKUP_TIMEOUT` in the config when connecting. `None` means
+  /// waiting forever.
+  pub fn lookup_timeout(&self) -> Option<Duration> {
+    self.lookup_timeout
+  }
+
+  /// Change how long `LookupHandle::wait` waits for the results of later lookups.
+  pub fn set_lookup_timeout(&mut self, timeout: Option<Duration>) {
+    self.lookup_timeout = timeout;
+  }
+
+  /// Send a message the typed API doesn't cover to the GNS service. See
+  /// `ServiceWriter::send_raw`.
+  pub fn send_raw(&mut self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    self.service_writer.send_raw(tpe, body)
+  }
+
+  /// The handler for messages from the GNS service which this handle doesn't understand. Without
+  /// a handler such messages make the handle stop receiving.
+  pub fn catch_all(&self) -> CatchAll {
+    self.callback_loop.catch_all()
+  }
+
+  /// The identity service handle this GNS handle was connected with, if any.
+  pub fn identity(&self) -> Option<Arc<Mutex<IdentityService>>> {
+    self.identity.clone()
+  }
+
+  /// The settings used by `lookup_in_master`. These are read from the `[gns]` section of the
+  /// config when connecting.
+  pub fn resolver_config(&self) -> &ResolverConfig {
+    &self.resolver
+  }
+
+  /// Replace the settings used by `lookup_in_master`.
+  pub fn set_resolver_config(&mut self, resolver: ResolverConfig) {
+    self.resolver = resolver;
+  }
+
+  /// How many lookups made through this handle were coalesced with identical lookups already in
+  /// flight.
+  pub fn dedup_stats(&self) -> DedupStats {
+    self.inflight.lock().unwrap().stats()
+  }
+
+  /// The layout of the lookup requests sent to the daemon. This is `LookupProtocol::default()`
+  /// unless changed with `set_protocol` or `set_capabilities`.
+  pub fn protocol(&self) -> LookupProtocol {
+    self.protocol
+  }
+
+  /// Set the layout of the lookup requests sent to the daemon.
+  pub fn set_protocol(&mut self, protocol: LookupProtocol) {
+    self.protocol = protocol;
+  }
+
+  /// Lay out lookup requests the way a daemon with `capabilities` expects.
+  pub fn set_capabilities(&mut self, capabilities: &Capabilities) {
+    self.protocol = capabilities.gns_lookup;
+  }
+
   /// Lookup a GNS record in the given zone.
   ///
   /// If `shorten` is not `None` then the result is added to the given shorten zone. Returns
   /// immediately with a handle that can be queried for results.
   ///
+  /// Shortening is only supported by daemons speaking `LookupProtocol::Shorten`, lookups with a
+  /// shorten zone fail with `ShortenUnsupported` otherwise. New code should pass `None`.
+  ///
+  /// If an identical lookup made through this handle is still waiting for its result, no new
+  /// request is sent to the daemon and both handles receive the result of the first one.
+  ///
   /// # Example
   ///
   /// ```rust
@@ -129,7 +315,7 @@ impl GNS {
   ///                         gns::RecordType::A,
   ///                         gns::LocalOptions::LocalMaster,
   ///                         None).unwrap();
-  /// let record = lh.recv();
+  /// let record = lh.wait().unwrap();
   /// println!("Got the IPv4 record for www.gnu: {}", record);
   /// ```
   pub fn lookup<'a>(
@@ -141,35 +327,55 @@ impl GNS {
       shorten: Option<&EcdsaPrivateKey>
     ) -> Result<LookupHandle<'a>, LookupError> {
 
-    let name_len = name.len();
-    if name_len > ll::GNUNET_DNSPARSER_MAX_NAME_LENGTH as usize {
-      return Err(LookupError::NameTooLong { name: name.to_string() });
-    };
-
+    let body = try!(lookup_body(self.protocol, self.lookup_id, name, zone, record_type, options, shorten));
     let id = self.lookup_id;
     self.lookup_id += 1;
 
-    let msg_length = (80 + name_len + 1).to_u16().unwrap();
-    let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP);
-    mw.write_u32::<BigEndian>(id).unwrap();
-    zone.serialize(&mut mw).unwrap();
-    mw.write_i16::<BigEndian>(options as i16).unwrap();
-    mw.write_i16::<BigEndian>(shorten.is_some() as i16).unwrap();
-    mw.write_i32::<BigEndian>(record_type as i32).unwrap();
-    match shorten {
-      Some(z) => z.serialize(&mut mw).unwrap(),
-      None    => mw.write_all(&[0u8; 32]).unwrap(),
+    let (tx, rx) = channel::<Vec<Record>>();
+    // The key is the request without its id. The lock must not be held while writing to the
+    // service, or the callback loop could block on it while the service blocks on us.
+    let (ticket, send) = self.inflight.lock().unwrap().join(id as u64, body[4..].to_vec(), tx);
+    if send {
+      if let Err(e) = self.service_writer.send_raw(ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP, &body[..]) {
+        self.inflight.lock().unwrap().finish(id as u64);
+        return Err(LookupError::Io { cause: e });
+      }
+    }
+    // The service has no message for cancelling a lookup, so a dropped handle only stops waiting
+    // for the answer, which is discarded when it arrives.
+    let cancel_inflight = self.inflight.clone();
+    let cancel = move || {
+      let _ = cancel_inflight.lock().unwrap().leave(ticket);
     };
-    mw.write_all(name.as_bytes()).unwrap();
-    mw.write_u8(0u8).unwrap();
+    Ok(LookupHandle::new(rx, self.lookup_timeout, Box::new(cancel)))
+  }
 
-    let (tx, rx) = channel::<Record>();
-    self.lookup_tx.send((id, tx)).unwrap(); // panics if the callback loop has panicked
-    try!(mw.send());
-    Ok(LookupHandle {
-      marker: PhantomData,
-      receiver: rx,
-    })
+  /// Lookup a GNS record in the master zone.
+  ///
+  /// The master zone is fetched from the identity service the handle was connected with, then
+  /// remembered for later lookups. Fails with `NoIdentityService` if the handle was created with
+  /// `GNS::connect` rather than `GNS::connect_with_identity`. The lookup options are chosen by the
+  /// handle's `ResolverConfig`.
+  pub fn lookup_in_master<'a>(
+      &'a mut self,
+      name: &str,
+      record_type: RecordType,
+      shorten: Option<&EcdsaPrivateKey>
+    ) -> Result<LookupHandle<'a>, LookupInMasterError> {
+    let zone = match self.master_zone {
+      Some(zone)  => zone,
+      None        => {
+        let ego = match self.identity {
+          Some(ref identity)  => try!(identity.lock().unwrap().get_default_ego("gns-master")),
+          None                => return Err(LookupInMasterError::NoIdentityService),
+        };
+        let zone = ego.get_public_key();
+        self.master_zone = Some(zone);
+        zone
+      },
+    };
+    let opt = self.resolver.options_for(name);
+    Ok(try!(self.lookup(name, &zone, record_type, opt, shorten)))
   }
 }
 
@@ -219,15 +425,17 @@ pub fn lookup(
   println!("connected to GNS");
   let mut h = try!(gns.lookup(name, zone, record_type, options, shorten));
   println!("doing lookup");
-  Ok(h.recv())
+  Ok(try!(h.wait()))
 }
 
 /// Errors returned by `gns::lookup_in_master`.
 error_def! ConnectLookupInMasterError {
-  GnsLookup { #[from] cause: ConnectLookupError }
-    => "Failed to connect to the GNS service and perform the lookup" ("Reason: {}", cause),
-  IdentityGetDefaultEgo { #[from] cause: identity::ConnectGetDefaultEgoError }
-    => "Failed to retrieve the default identity for gns-master from the identity service" ("Reason: {}", cause),
+  IdentityConnect { #[from] cause: identity::ConnectError }
+    => "Failed to connect to the identity service" ("Reason: {}", cause),
+  GnsConnect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the GNS service" ("Reason: {}", cause),
+  Lookup { #[from] cause: LookupInMasterError }
+    => "Failed to perform the lookup in the master zone" ("Reason: {}", cause),
 }
 
 /// Lookup a GNS record in the master zone.
@@ -252,43 +460,299 @@ error_def! ConnectLookupInMasterError {
 /// This is a convenience function that connects to the identity service, fetches the default ego
 /// for gns-master, then connects to the GNS service, performs the lookup, retrieves one result,
 /// then disconnects from everything. If you are performing lots of lookups this function should be
-/// avoided and `GNS::lookup_in_zone` used instead.
+/// avoided and `GNS::lookup_in_master` used instead.
 pub fn lookup_in_master(
     cfg: &Cfg,
     name: &str,
     record_type: RecordType,
     shorten: Option<&EcdsaPrivateKey>) -> Result<Record, ConnectLookupInMasterError> {
-  println!("Getting default ego");
-  let ego = try!(identity::get_default_ego(cfg, "gns-master"));
-  println!("got default ego: {}", ego);
-  let pk = ego.get_public_key();
-  let mut it = name.split('.');
-  let opt = match (it.next(), it.next(), it.next()) {
-    (Some(_), Some("gnu"), None)  => LocalOptions::NoDHT,
-    _                             => LocalOptions::LocalMaster,
-  };
-  println!("doing lookup");
-  let ret = try!(lookup(cfg, name, &pk, record_type, opt, shorten));
-  println!("lookup succeeded");
-  Ok(ret)
+  let identity = try!(IdentityService::connect(cfg));
+  let mut gns = try!(GNS::connect_with_identity(cfg, Arc::new(Mutex::new(identity))));
+  let mut h = try!(gns.lookup_in_master(name, record_type, shorten));
+  Ok(try!(h.wait().map_err(LookupInMasterError::from)))
+}
+
+impl Transient for ConnectLookupInMasterError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      ConnectLookupInMasterError::IdentityConnect { ref cause } => cause.is_transient(),
+      ConnectLookupInMasterError::GnsConnect { ref cause }      => cause.is_transient(),
+      ConnectLookupInMasterError::Lookup { ref cause }          => cause.is_transient(),
+    }
+  }
+}
+
+/// Like `lookup_in_master` but retries according to `policy` if the identity or GNS services
+/// can't be reached, eg. because they are being restarted.
+pub fn lookup_in_master_with_retry(
+    cfg: &Cfg,
+    name: &str,
+    record_type: RecordType,
+    shorten: Option<&EcdsaPrivateKey>,
+    policy: &RetryPolicy) -> Result<Record, ConnectLookupInMasterError> {
+  policy.run(|| lookup_in_master(cfg, name, record_type, shorten))
+}
+
+/// Look up the records of each of `record_types` for `name` in the master zone, waiting at most
+/// `timeout` for all of them together.
+///
+/// The service answers a name without records of the type asked for with an empty result, but a
+/// lookup which has to go to the DHT may not be answered for a long time, so the lookups share the
+/// timeout rather than each waiting for it. Types which aren't answered in time add no records.
+pub fn lookup_types_in_master(
+    cfg: &Cfg,
+    name: &str,
+    record_types: &[RecordType],
+    timeout: Duration) -> Result<Vec<Record>, ConnectLookupInMasterError> {
+  let identity = try!(IdentityService::connect(cfg));
+  let mut gns = try!(GNS::connect_with_identity(cfg, Arc::new(Mutex::new(identity))));
+  let deadline = Instant::now() + timeout;
+  let mut records = Vec::new();
+  for record_type in record_types.iter() {
+    let now = Instant::now();
+    if now >= deadline {
+      break;
+    }
+    let mut h = try!(gns.lookup_in_master(name, *record_type, None));
+    if let Ok(found) = h.recv_all_timeout(deadline - now) {
+      records.extend(found);
+    }
+  }
+  Ok(records)
 }
 
 /// A handle returned by `GNS::lookup`.
 ///
-/// Used to retrieve the results of a lookup.
+/// Used to retrieve the results of a lookup. Dropping the handle cancels the lookup if no other
+/// handle is waiting for the same results.
 pub struct LookupHandle<'a> {
   marker: PhantomData<&'a GNS>,
-  receiver: Receiver<Record>,
+  receiver: Receiver<Vec<Record>>,
+  pending: VecDeque<Record>,
+  timeout: Option<Duration>,
+  cancel: Box<FnMut() + Send>,
 }
 
 impl<'a> LookupHandle<'a> {
+  /// `cancel` is called when the handle is dropped, to unregister the lookup.
+  fn new(
+      receiver: Receiver<Vec<Record>>,
+      timeout: Option<Duration>,
+      cancel: Box<FnMut() + Send>
+    ) -> LookupHandle<'a> {
+    LookupHandle {
+      marker: PhantomData,
+      receiver: receiver,
+      pending: VecDeque::new(),
+      timeout: timeout,
+      cancel: cancel,
+    }
+  }
+
   /// Receive a single result from a lookup.
   ///
   /// Blocks until a result is available. This function can be called multiple times on a handle to
   /// receive multiple results.
+  ///
+  /// # Panics
+  ///
+  /// Panics if all the results of the lookup have already been received or the connection to the
+  /// service was lost. Use `wait` to get an error instead.
+  #[deprecated(since = "0.0.16", note = "panics once the lookup is over, use `wait` instead")]
   pub fn recv(&mut self) -> Record {
-    // unwrap is safe because the LookupHandle cannot outlive the remote sender.
-    self.receiver.recv().unwrap()
+    self.recv_until(None).expect("the lookup is over")
+  }
+
+  /// Receive the complete set of records sent in one response from the service.
+  ///
+  /// Blocks until a response is available. The set may be empty if the name exists but has no
+  /// records of the type asked for. If some records of a response have already been taken with
+  /// `recv`, the rest of that response is returned.
+  ///
+  /// # Panics
+  ///
+  /// Panics in the same cases as `recv`. Use `wait_all` to get an error instead.
+  #[deprecated(since = "0.0.16", note = "panics once the lookup is over, use `wait_all` instead")]
+  pub fn recv_all(&mut self) -> Vec<Record> {
+    self.recv_all_until(None).expect("the lookup is over")
+  }
+
+  /// Receive a single result from a lookup, waiting at most `timeout` for it.
+  pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Record, LookupError> {
+    self.recv_until(Some(Instant::now() + timeout))
+  }
+
+  /// Receive the complete set of records sent in one response, waiting at most `timeout` for it.
+  /// See `recv_all`.
+  pub fn recv_all_timeout(&mut self, timeout: Duration) -> Result<Vec<Record>, LookupError> {
+    self.recv_all_until(Some(Instant::now() + timeout))
+  }
+
+  /// Receive a single result from a lookup, waiting at most for the lookup timeout of the `GNS`
+  /// handle the lookup was made through. See `GNS::set_lookup_timeout`.
+  pub fn wait(&mut self) -> Result<Record, LookupError> {
+    let deadline = self.timeout.map(|t| Instant::now() + t);
+    self.recv_until(deadline)
+  }
+
+  /// Receive the complete set of records sent in one response, waiting at most for the lookup
+  /// timeout of the `GNS` handle the lookup was made through.
+  pub fn wait_all(&mut self) -> Result<Vec<Record>, LookupError> {
+    let deadline = self.timeout.map(|t| Instant::now() + t);
+    self.recv_all_until(deadline)
+  }
+
+  fn recv_until(&mut self, deadline: Option<Instant>) -> Result<Record, LookupError> {
+    loop {
+      if let Some(record) = self.pending.pop_front() {
+        return Ok(record);
+      }
+      let records = try!(self.recv_set(deadline));
+      self.pending.extend(records);
+    }
+  }
+
+  fn recv_all_until(&mut self, deadline: Option<Instant>) -> Result<Vec<Record>, LookupError> {
+    if !self.pending.is_empty() {
+      return Ok(self.pending.drain(..).collect());
+    }
+    self.recv_set(deadline)
+  }
+
+  /// Receive the next response from the service, waiting until `deadline` if there is one.
+  fn recv_set(&mut self, deadline: Option<Instant>) -> Result<Vec<Record>, LookupError> {
+    let deadline = match deadline {
+      Some(deadline)  => deadline,
+      None            => return self.receiver.recv().map_err(|_| LookupError::Disconnected),
+    };
+    let now = Instant::now();
+    let left = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+    match self.receiver.recv_timeout(left) {
+      Ok(records)                         => Ok(records),
+      Err(RecvTimeoutError::Timeout)      => Err(LookupError::Timeout),
+      Err(RecvTimeoutError::Disconnected) => Err(LookupError::Disconnected),
+    }
   }
 }
 
+impl<'a> Drop for LookupHandle<'a> {
+  fn drop(&mut self) {
+    (self.cancel)();
+  }
+}
+
+impl Component for GNS {
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    Some(&mut self.callback_loop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use std::iter;
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::time::Duration;
+  use fixtures;
+  use ll;
+  use service;
+  use EcdsaPublicKey;
+  use super::*;
+  use super::{lookup_body, read_lookup_result};
+
+  #[test]
+  fn test_lookup_fixtures() {
+    let zone = EcdsaPublicKey::deserialize(&mut Cursor::new(&fixtures::LOOKUP_ZONE[..])).unwrap();
+    let expected = [
+      (LookupProtocol::Shorten, fixtures::GNS_LOOKUP_SHORTEN),
+      (LookupProtocol::NoShorten, fixtures::GNS_LOOKUP_NO_SHORTEN),
+    ];
+    for &(protocol, fixture) in expected.iter() {
+      let body = lookup_body(protocol, 7, fixtures::LOOKUP_NAME, &zone, RecordType::A, LocalOptions::LocalMaster, None).unwrap();
+      let msg = service::encode_message(ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP, &body[..]).unwrap();
+      assert_eq!(&msg[..], fixture);
+    }
+
+    let (tpe, mut mr) = service::split_frame(fixtures::GNS_LOOKUP_RESULT.to_vec());
+    assert_eq!(tpe, ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP_RESULT);
+    let (id, records) = read_lookup_result(&mut mr).unwrap();
+    assert_eq!(id, 7);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].record_type(), RecordType::A);
+    assert_eq!(records[0].expiration_time(), fixtures::GNS_LOOKUP_RESULT_EXPIRATION);
+    assert_eq!(records[0].data(), &[192, 0, 2, 1][..]);
+
+    // Header, lookup id and record count come before the record.
+    let mut reencoded = Vec::new();
+    records[0].serialize(&mut reencoded).unwrap();
+    assert_eq!(&reencoded[..], &fixtures::GNS_LOOKUP_RESULT[12..]);
+  }
+
+  #[test]
+  fn test_lookup_name_length_boundaries() {
+    let zone = EcdsaPublicKey::deserialize(&mut Cursor::new(&fixtures::LOOKUP_ZONE[..])).unwrap();
+    let max = ll::GNUNET_DNSPARSER_MAX_NAME_LENGTH as usize;
+    for &protocol in [LookupProtocol::Shorten, LookupProtocol::NoShorten].iter() {
+      for len in (max - 3)..(max + 4) {
+        let name: String = iter::repeat('a').take(len).collect();
+        match lookup_body(protocol, 0, &name, &zone, RecordType::A, LocalOptions::Default, None) {
+          Ok(body) => {
+            assert!(len <= max);
+            assert_eq!(body.len() + 4, protocol.lookup_message_len(len));
+          },
+          Err(LookupError::NameTooLong { name: ref n }) => {
+            assert!(len > max);
+            assert_eq!(n, &name);
+          },
+          Err(e) => panic!("unexpected error: {}", e),
+        };
+      }
+      // Lengths are in bytes, not characters.
+      let wide: String = iter::repeat('\u{00e9}').take(max / 2 + 1).collect();
+      match lookup_body(protocol, 0, &wide, &zone, RecordType::A, LocalOptions::Default, None) {
+        Err(LookupError::NameTooLong { .. }) => (),
+        _ => panic!("name of {} bytes accepted", wide.len()),
+      };
+    }
+  }
+
+  #[test]
+  fn test_lookup_handle_record_sets() {
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    let mut h = LookupHandle::new(rx, None, Box::new(|| ()));
+    let a = Record::new(RecordType::A, vec![10, 0, 0, 1], 0, 0);
+    let b = Record::new(RecordType::A, vec![10, 0, 0, 2], 0, 0);
+    tx.send(vec![a.clone(), b.clone()]).unwrap();
+    tx.send(vec![]).unwrap();
+    tx.send(vec![a.clone()]).unwrap();
+    assert!(h.wait().unwrap() == a);
+    // The rest of the first set, then the empty second set.
+    assert!(h.wait_all().unwrap() == vec![b]);
+    assert!(h.wait_all().unwrap().is_empty());
+    assert!(h.wait_all().unwrap() == vec![a]);
+  }
+
+  #[test]
+  fn test_lookup_handle_timeout() {
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancel_flag = cancelled.clone();
+    let cancel = move || cancel_flag.store(true, Ordering::SeqCst);
+    let mut h = LookupHandle::new(rx, Some(Duration::from_millis(10)), Box::new(cancel));
+    match h.wait() {
+      Err(LookupError::Timeout) => (),
+      _ => panic!("expected the lookup to time out"),
+    }
+    let a = Record::new(RecordType::A, vec![10, 0, 0, 1], 0, 0);
+    tx.send(vec![a.clone()]).unwrap();
+    assert!(h.recv_timeout(Duration::from_millis(10)).unwrap() == a);
+    drop(tx);
+    match h.recv_all_timeout(Duration::from_millis(10)) {
+      Err(LookupError::Disconnected) => (),
+      _ => panic!("expected the lookup to be disconnected"),
+    }
+    drop(h);
+    assert!(cancelled.load(Ordering::SeqCst));
+  }
+}
@@ -0,0 +1,186 @@
+use std::str::from_utf8;
+
+use ll;
+
+/// The maximum length of a single label, in bytes.
+pub const MAX_LABEL_LENGTH: usize = 63;
+
+/// The maximum length of a name, in bytes.
+pub const MAX_NAME_LENGTH: usize = ll::GNUNET_DNSPARSER_MAX_NAME_LENGTH as usize;
+
+/// The label used for records stored at the apex of a zone.
+pub const EMPTY_LABEL_AT: &'static str = "@";
+
+/// The pseudo-TLD referring to the zone the lookup started in.
+pub const MASTERZONE_STR: &'static str = "+";
+
+/// Errors returned when validating or converting GNS names.
+error_def! NameError {
+  Empty
+    => "The name is empty",
+  TooLong { len: usize }
+    => "The name is too long" ("A name of {} bytes exceeds the maximum length", len),
+  EmptyLabel
+    => "The name contains an empty label",
+  LabelTooLong { label: String }
+    => "A label in the name is too long" ("The label \"{}\" exceeds the maximum length", label),
+  InvalidCharacter { label: String, c: char }
+    => "A label in the name contains an invalid character" ("The label \"{}\" contains {:?}", label, c),
+  InvalidHyphen { label: String }
+    => "A label begins or ends with a hyphen" ("Label: \"{}\"", label),
+  MalformedWireName
+    => "The wire form of the name is malformed",
+}
+
+/// Check that `label` is a valid GNS label.
+///
+/// A label is either one of the special labels `@` and `+` or a non-empty string of at most
+/// `MAX_LABEL_LENGTH` bytes made of letters, digits, `-` and `_`, which doesn't begin or end with
+/// a `-`. Letters outside of ASCII are allowed.
+pub fn validate_label(label: &str) -> Result<(), NameError> {
+  if label.is_empty() {
+    return Err(NameError::EmptyLabel);
+  }
+  if label.len() > MAX_LABEL_LENGTH {
+    return Err(NameError::LabelTooLong { label: label.to_string() });
+  }
+  if label == EMPTY_LABEL_AT || label == MASTERZONE_STR {
+    return Ok(());
+  }
+  for c in label.chars() {
+    if !(c.is_alphanumeric() || c == '-' || c == '_') {
+      return Err(NameError::InvalidCharacter { label: label.to_string(), c: c });
+    }
+  }
+  if label.starts_with('-') || label.ends_with('-') {
+    return Err(NameError::InvalidHyphen { label: label.to_string() });
+  }
+  Ok(())
+}
+
+/// Returns `true` if `label` is a valid GNS label. See `validate_label`.
+pub fn is_valid_label(label: &str) -> bool {
+  validate_label(label).is_ok()
+}
+
+/// Split a name into its labels, checking that each of them is valid.
+///
+/// The labels are returned in the order they appear in the name, eg. `"www.gnu"` gives
+/// `["www", "gnu"]`.
+pub fn split_labels(name: &str) -> Result<Vec<&str>, NameError> {
+  if name.is_empty() {
+    return Err(NameError::Empty);
+  }
+  if name.len() > MAX_NAME_LENGTH {
+    return Err(NameError::TooLong { len: name.len() });
+  }
+  let labels: Vec<&str> = name.split('.').collect();
+  for label in labels.iter() {
+    try!(validate_label(label));
+  }
+  Ok(labels)
+}
+
+/// Join labels into a name, checking that each label and the resulting name are valid.
+pub fn join_labels<S: AsRef<str>>(labels: &[S]) -> Result<String, NameError> {
+  let mut name = String::new();
+  for label in labels.iter() {
+    let label = label.as_ref();
+    try!(validate_label(label));
+    if !name.is_empty() {
+      name.push('.');
+    }
+    name.push_str(label);
+  }
+  if name.is_empty() {
+    return Err(NameError::Empty);
+  }
+  if name.len() > MAX_NAME_LENGTH {
+    return Err(NameError::TooLong { len: name.len() });
+  }
+  Ok(name)
+}
+
+/// Check that `name` is a valid GNS name: a dot-separated list of valid labels no longer than
+/// `MAX_NAME_LENGTH` bytes.
+pub fn validate_name(name: &str) -> Result<(), NameError> {
+  split_labels(name).map(|_| ())
+}
+
+/// Returns `true` if `name` is a valid GNS name. See `validate_name`.
+pub fn is_valid_name(name: &str) -> bool {
+  validate_name(name).is_ok()
+}
+
+/// Convert a name from its presentation form (`www.gnu`) to its wire form, a sequence of
+/// length-prefixed labels terminated by a zero byte.
+pub fn name_to_wire(name: &str) -> Result<Vec<u8>, NameError> {
+  let labels = try!(split_labels(name));
+  let mut ret = Vec::with_capacity(name.len() + 2);
+  for label in labels {
+    ret.push(label.len() as u8);
+    ret.extend_from_slice(label.as_bytes());
+  }
+  ret.push(0);
+  Ok(ret)
+}
+
+/// Convert a name from its wire form back to its presentation form.
+///
+/// The wire form must contain exactly one name with no trailing bytes.
+pub fn name_from_wire(wire: &[u8]) -> Result<String, NameError> {
+  let mut labels = Vec::new();
+  let mut pos = 0;
+  loop {
+    let len = match wire.get(pos) {
+      Some(&l)  => l as usize,
+      None      => return Err(NameError::MalformedWireName),
+    };
+    pos += 1;
+    if len == 0 {
+      break;
+    }
+    if pos + len > wire.len() {
+      return Err(NameError::MalformedWireName);
+    }
+    match from_utf8(&wire[pos..pos + len]) {
+      Ok(label) => labels.push(label),
+      Err(_)    => return Err(NameError::MalformedWireName),
+    };
+    pos += len;
+  }
+  if pos != wire.len() {
+    return Err(NameError::MalformedWireName);
+  }
+  join_labels(&labels[..])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_validate_name() {
+    assert!(is_valid_name("www.gnu"));
+    assert!(is_valid_name("@.example"));
+    assert!(is_valid_name("bücher.gnu"));
+    assert!(is_valid_name("my_host-1.+"));
+    assert!(!is_valid_name(""));
+    assert!(!is_valid_name("www..gnu"));
+    assert!(!is_valid_name("www.gnu."));
+    assert!(!is_valid_name("-www.gnu"));
+    assert!(!is_valid_name("w w.gnu"));
+    let long_label: String = (0..MAX_LABEL_LENGTH + 1).map(|_| 'a').collect();
+    assert!(!is_valid_name(&long_label[..]));
+    assert!(is_valid_name(&long_label[1..]));
+  }
+
+  #[test]
+  fn test_wire_round_trip() {
+    let wire = name_to_wire("www.gnu").unwrap();
+    assert_eq!(&wire[..], &b"\x03www\x03gnu\x00"[..]);
+    assert_eq!(name_from_wire(&wire[..]).unwrap(), "www.gnu");
+    assert!(name_from_wire(&b"\x03www\x04gnu\x00"[..]).is_err());
+    assert!(name_from_wire(&b"\x03www\x00extra"[..]).is_err());
+  }
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use Cfg;
+use EcdsaPrivateKey;
+use ll;
+use service::{self, ProbeOutcome};
+use gns::{lookup_body, LocalOptions, RecordType, EMPTY_LABEL_AT, MASTERZONE_STR};
+
+/// The recursion depth limit sent with lookups to daemons without shortening, the same as
+/// `GNUNET_GNS_lookup` uses. It bounds how many zones the daemon follows delegations through.
+pub const DEFAULT_RECURSION_DEPTH_LIMIT: u16 = 128;
+
+/// The layout of lookup requests understood by the GNS daemon.
+///
+/// GNUnet 0.10 could add the results of a lookup to a shorten zone, so its lookup requests carry
+/// the private key of that zone. Later versions removed shortening along with the key from the
+/// request, and send a recursion depth limit where 0.10 flagged whether the key was set. Sending
+/// the wrong layout makes the daemon drop the connection.
+///
+/// The daemon doesn't announce which layout it expects. Handles default to the layout of current
+/// daemons, use `probe` and `set_protocol` to talk to a GNUnet 0.10 daemon.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LookupProtocol {
+  /// Requests carry a shorten zone key, as GNUnet 0.10 expects.
+  Shorten,
+  /// Requests have no shorten zone key, as daemons without shortening expect.
+  NoShorten,
+}
+
+impl Default for LookupProtocol {
+  fn default() -> LookupProtocol {
+    LookupProtocol::NoShorten
+  }
+}
+
+impl LookupProtocol {
+  /// Ask the daemon which layout it expects, waiting up to `timeout` for it to react.
+  ///
+  /// A lookup without a shorten zone key is sent. A daemon expecting the key finds the request
+  /// too short and drops the connection, other daemons look the name up.
+  pub fn probe(cfg: &Cfg, timeout: Duration) -> Result<LookupProtocol, service::ConnectError> {
+    let zone = EcdsaPrivateKey::anonymous().get_public();
+    // The name must be short enough that the request stays shorter than one with the key.
+    let body = lookup_body(LookupProtocol::NoShorten, 0, "probe", &zone, RecordType::A, LocalOptions::NoDHT, None).unwrap();
+    match try!(service::probe(cfg, "gns", ll::GNUNET_MESSAGE_TYPE_GNS_LOOKUP, &body[..], timeout)) {
+      ProbeOutcome::Rejected  => Ok(LookupProtocol::Shorten),
+      _                       => Ok(LookupProtocol::NoShorten),
+    }
+  }
+
+  /// Whether the daemon can add lookup results to a shorten zone.
+  pub fn supports_shorten(&self) -> bool {
+    *self == LookupProtocol::Shorten
+  }
+
+  /// The label to look up to resolve the apex of a zone. GNUnet 0.10 keeps the records at the
+  /// apex under `+`, later versions under `@`.
+  pub fn apex_label(&self) -> &'static str {
+    match *self {
+      LookupProtocol::Shorten   => MASTERZONE_STR,
+      LookupProtocol::NoShorten => EMPTY_LABEL_AT,
+    }
+  }
+
+  /// The length of a lookup request for a name of `name_len` bytes, including the header.
+  pub fn lookup_message_len(&self, name_len: usize) -> usize {
+    match *self {
+      LookupProtocol::Shorten   => 80 + name_len + 1,
+      LookupProtocol::NoShorten => 48 + name_len + 1,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_lookup_protocol() {
+    assert_eq!(LookupProtocol::default(), LookupProtocol::NoShorten);
+    assert!(!LookupProtocol::NoShorten.supports_shorten());
+    assert_eq!(LookupProtocol::NoShorten.lookup_message_len(3), 52);
+    assert_eq!(LookupProtocol::Shorten.lookup_message_len(3), 84);
+    assert_eq!(LookupProtocol::NoShorten.apex_label(), "@");
+    assert_eq!(LookupProtocol::Shorten.apex_label(), "+");
+  }
+}
@@ -0,0 +1,50 @@
+use EcdsaPublicKey;
+use EcdsaPrivateKey;
+use HashCode;
+
+/// The context string used when deriving keys for GNS records.
+const GNS_DERIVATION_CONTEXT: &'static str = "gns";
+
+/// Derive the public key which signs the records stored under `label` in `zone`.
+///
+/// Labels are case-insensitive, `label` is converted to lower case before the key is derived.
+///
+/// # Panics
+///
+/// Panics if `label` contains a nul byte.
+pub fn derive_block_key(zone: &EcdsaPublicKey, label: &str) -> EcdsaPublicKey {
+  zone.derive(&label.to_lowercase()[..], GNS_DERIVATION_CONTEXT)
+}
+
+/// Compute the DHT key under which the records stored under `label` in `zone` are published.
+///
+/// This is the hash of the key returned by `derive_block_key`. It can be used to fetch the block
+/// containing the records directly from the DHT or to watch for it being published.
+///
+/// # Panics
+///
+/// Panics if `label` contains a nul byte.
+pub fn query_from_public_key(zone: &EcdsaPublicKey, label: &str) -> HashCode {
+  derive_block_key(zone, label).hash()
+}
+
+/// Compute the DHT key for `label` in the zone of the private key `zone`.
+///
+/// Equivalent to calling `query_from_public_key` with the zone's public key.
+///
+/// # Panics
+///
+/// Panics if `label` contains a nul byte.
+pub fn query_from_private_key(zone: &EcdsaPrivateKey, label: &str) -> HashCode {
+  query_from_public_key(&zone.get_public(), label)
+}
+
+#[test]
+fn test_query_from_key() {
+  let zone = EcdsaPrivateKey::anonymous();
+  let q0 = query_from_private_key(&zone, "www");
+  let q1 = query_from_public_key(&zone.get_public(), "WWW");
+  assert!(q0 == q1);
+  assert!(q0 == derive_block_key(&zone.get_public(), "www").hash());
+  assert!(q0 != query_from_private_key(&zone, "mail"));
+}
@@ -2,52 +2,177 @@ use std::str::FromStr;
 use std::fmt::{Debug, Formatter};
 use std::fmt;
 use std::str::from_utf8;
-use std::ffi::CStr;
-use std::io::{self, Read};
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::slice;
+use std::io::{self, Read, Write};
 //use std::c_str::CString;
-use byteorder::{BigEndian, ReadBytesExt};
-use libc::{free, c_char, c_void};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libc::{free, c_char, c_void, size_t};
 
 use ll;
+use gns::{CaaData, DsData, NaptrData, SrvData};
 use self::RecordType::*;
 use util::io::ReadUtil;
 
+/// Record flag: the record is only for the zone's owner and is never published.
+pub const RF_PRIVATE: u32 = ll::GNUNET_GNSRECORD_RF_PRIVATE as u32;
+/// Record flag: the expiration time is relative to when the record is published.
+pub const RF_RELATIVE_EXPIRATION: u32 = ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION as u32;
+/// Record flag: the record is only used once the records of its type without this flag expire.
+pub const RF_SHADOW_RECORD: u32 = ll::GNUNET_GNSRECORD_RF_SHADOW_RECORD as u32;
+
 /// An enum of the different GNS record types.
 ///
 /// Some of these records exist in the legacy DNS (but are still used in GNS). Others are specific
 /// to GNS. These are marked **Legacy** and **GNS** respectively.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, Eq)]
 pub enum RecordType {
   /// **Legacy.** Address record. Stores a 32bit IPv4 address.
-  A       = 1,
+  A,
   /// **Legacy.** Name server record. Delegates a DNS zone to use the given authoritative name servers.
-  NS      = 2,
+  NS,
   /// **Legacy.** Canonical name record. Alias of one name to another.
-  CNAME   = 5,
+  CNAME,
   /// **Legacy.** Start of authority record. Specifies authoritative information about a DNS zone.
-  SOA     = 6,
+  SOA,
   /// **Legacy.** Pointer record. Pointer to a canonical name.
-  PTR     = 12,
+  PTR,
   /// **Legacy.** Mail exchange record. Maps a domain name to a list of message transfer agents for that
   /// domain.
-  MX      = 15,
+  MX,
   /// **Legacy.** Text record. Used to store human-readable data and various forms of machine-readable data.
-  TXT     = 16,
+  TXT,
+  /// **Legacy.** Responsible person record. Names the mailbox of the person responsible for a name.
+  RP,
+  /// **Legacy.** AFS database record. Locates the database servers of an AFS cell.
+  AFSDB,
+  /// **Legacy.** Signature record. Superseded by `RRSIG`.
+  SIG,
+  /// **Legacy.** Key record. Superseded by `DNSKEY`.
+  KEY,
   /// **Legacy.** Address record. Stores a 128bit IPv6 address.
-  AAAA    = 28,
+  AAAA,
+  /// **Legacy.** Location record. Gives the geographical location of a name.
+  LOC,
+  /// **Legacy.** Service locator. Gives the servers and ports providing a service. See `SrvData`.
+  SRV,
+  /// **Legacy.** Naming authority pointer. Rewrites a name into a URI or another name. See `NaptrData`.
+  NAPTR,
+  /// **Legacy.** Key exchanger record. Names the host to negotiate keys with for a name.
+  KX,
+  /// **Legacy.** Certificate record. Stores a certificate or a certificate revocation list.
+  CERT,
+  /// **Legacy.** Delegation name record. Alias of a whole subtree of names to another.
+  DNAME,
+  /// **Legacy.** Address prefix list. Lists ranges of addresses.
+  APL,
+  /// **Legacy.** Delegation signer record. Identifies the key signing a delegated DNSSEC zone. See
+  /// `DsData`.
+  DS,
+  /// **Legacy.** SSH public key fingerprint. Publishes the fingerprints of a host's SSH keys.
+  SSHFP,
+  /// **Legacy.** IPsec key record. Stores a key for use with IPsec.
+  IPSECKEY,
+  /// **Legacy.** DNSSEC signature. Signs a set of records.
+  RRSIG,
+  /// **Legacy.** Next secure record. Proves that a name doesn't exist in a DNSSEC zone.
+  NSEC,
+  /// **Legacy.** DNS key record. Stores a key used to sign a DNSSEC zone.
+  DNSKEY,
+  /// **Legacy.** DHCP identifier. Ties a name to the DHCP client it was assigned to.
+  DHCID,
+  /// **Legacy.** Next secure record version 3. Like `NSEC` but without revealing the names of the zone.
+  NSEC3,
+  /// **Legacy.** NSEC3 parameters. Gives the parameters used to build the zone's `NSEC3` records.
+  NSEC3PARAM,
   /// **Legacy.** TLSA certificate association. A record for DNS-based Authentication of Named Entities (DANE).
-  TLSA    = 52,
+  TLSA,
+  /// **Legacy.** S/MIME certificate association. Like `TLSA` but for email addresses.
+  SMIMEA,
+  /// **Legacy.** Host identity protocol record. Stores a host identity and its rendezvous servers.
+  HIP,
+  /// **Legacy.** Child copy of a `DS` record, for transfer to the parent zone.
+  CDS,
+  /// **Legacy.** Child copy of a `DNSKEY` record, for transfer to the parent zone.
+  CDNSKEY,
+  /// **Legacy.** OpenPGP public key record. Publishes the OpenPGP key of an email address.
+  OPENPGPKEY,
+  /// **Legacy.** Transaction key record. Used to establish keys for `TSIG`.
+  TKEY,
+  /// **Legacy.** Transaction signature. Authenticates a DNS message.
+  TSIG,
+  /// **Legacy.** Pseudo-type matching records of every type. Only used in queries.
+  ANY,
+  /// **Legacy.** Uniform resource identifier record. Maps a name to a URI.
+  URI,
+  /// **Legacy.** Certification authority authorization. Restricts which CAs may issue certificates for
+  /// a name. See `CaaData`.
+  CAA,
+  /// **Legacy.** DNSSEC trust authority. Identifies a trust anchor.
+  TA,
+  /// **Legacy.** DNSSEC lookaside validation record. Like `DS` but published outside the parent zone.
+  DLV,
 
   /// **GNS.** Petname key record. Used to delegate to other users' zones and give those zones a petname.
-  PKEY    = 65536,
+  PKEY,
   /// **GNS.** Nickname record. Used to give a zone a name.
-  NICK    = 65537,
+  NICK,
   /// **GNS.** Legacy hostname record.
-  LEHO    = 65538,
+  LEHO,
   /// **GNS.** Virtual public network record.
-  VPN     = 65539,
+  VPN,
   /// **GNS.** GNS2DNS record. Used to delegate authority to a legacy DNS zone.
-  GNS2DNS = 65540,
+  GNS2DNS,
+  /// **GNS.** Box record. Holds a record for a particular protocol and port, eg. a `TLSA` record
+  /// for `_443._tcp`.
+  BOX,
+  /// **GNS.** Place record. Identifies a social place, eg. a group chat, and the peer hosting it.
+  PLACE,
+  /// **GNS.** Phone record. Identifies the peer and line a conversation phone is registered on.
+  PHONE,
+  /// **GNS.** re:claimID attribute record. Stores an identity attribute.
+  RECLAIM_ATTRIBUTE,
+  /// **GNS.** re:claimID ticket record. Grants a relying party access to attributes.
+  RECLAIM_TICKET,
+  /// **GNS.** Service box record. Like `BOX`, but for a service and protocol given by name.
+  SBOX,
+  /// **GNS.** Delegate record. Used by the ABD service to delegate attributes.
+  DELEGATE,
+  /// **GNS.** Attribute record. Used by the ABD service to issue attributes.
+  ATTRIBUTE,
+  /// **GNS.** re:claimID attribute reference. Refers to an attribute of a credential.
+  RECLAIM_ATTRIBUTE_REF,
+  /// **GNS.** Redirect record. Redirects resolution of the name to another GNS name.
+  REDIRECT,
+  /// **GNS.** re:claimID OpenID Connect client record. Describes a relying party.
+  RECLAIM_OIDC_CLIENT,
+  /// **GNS.** re:claimID OpenID Connect redirect record. Gives a relying party's redirect URI.
+  RECLAIM_OIDC_REDIRECT,
+  /// **GNS.** re:claimID credential record. Stores a credential vouching for attributes.
+  RECLAIM_CREDENTIAL,
+  /// **GNS.** re:claimID presentation record. Stores a presentation of a credential.
+  RECLAIM_PRESENTATION,
+  /// **GNS.** EdDSA zone delegation record. Like `PKEY` but for zones with EdDSA keys.
+  EDKEY,
+  /// **GNS.** ERIS read capability. Refers to content encoded with ERIS.
+  ERIS_READ_CAPABILITY,
+  /// **GNS.** Messenger room entry. Identifies a messenger room and a peer hosting it.
+  MESSENGER_ROOM_ENTRY,
+  /// **GNS.** Tombstone record. Marks that the records under a label were deleted.
+  TOMBSTONE,
+
+  /// A record of a type this crate doesn't know about, with its record type number. `from_u32`,
+  /// parsing and `Record::record_type` never produce this for the types above, but if it is built
+  /// with one of their numbers it still compares equal to that type.
+  Unknown(u32),
+}
+
+/// Record types are compared by their record type number, so that eg. `Unknown(1) == A`.
+impl PartialEq for RecordType {
+  fn eq(&self, other: &RecordType) -> bool {
+    self.to_u32() == other.to_u32()
+  }
 }
 
 impl RecordType {
@@ -72,18 +197,149 @@ impl RecordType {
       12 => PTR,
       15 => MX,
       16 => TXT,
+      17 => RP,
+      18 => AFSDB,
+      24 => SIG,
+      25 => KEY,
       28 => AAAA,
+      29 => LOC,
+      33 => SRV,
+      35 => NAPTR,
+      36 => KX,
+      37 => CERT,
+      39 => DNAME,
+      42 => APL,
+      43 => DS,
+      44 => SSHFP,
+      45 => IPSECKEY,
+      46 => RRSIG,
+      47 => NSEC,
+      48 => DNSKEY,
+      49 => DHCID,
+      50 => NSEC3,
+      51 => NSEC3PARAM,
       52 => TLSA,
+      53 => SMIMEA,
+      55 => HIP,
+      59 => CDS,
+      60 => CDNSKEY,
+      61 => OPENPGPKEY,
+      249 => TKEY,
+      250 => TSIG,
+      255 => ANY,
+      256 => URI,
+      257 => CAA,
+      32768 => TA,
+      32769 => DLV,
 
       65536 => PKEY,
       65537 => NICK,
       65538 => LEHO,
       65539 => VPN,
       65540 => GNS2DNS,
+      65541 => BOX,
+      65542 => PLACE,
+      65543 => PHONE,
+      65544 => RECLAIM_ATTRIBUTE,
+      65545 => RECLAIM_TICKET,
+      65547 => SBOX,
+      65548 => DELEGATE,
+      65549 => ATTRIBUTE,
+      65550 => RECLAIM_ATTRIBUTE_REF,
+      65551 => REDIRECT,
+      65552 => RECLAIM_OIDC_CLIENT,
+      65553 => RECLAIM_OIDC_REDIRECT,
+      65554 => RECLAIM_CREDENTIAL,
+      65555 => RECLAIM_PRESENTATION,
+      65556 => EDKEY,
+      65557 => ERIS_READ_CAPABILITY,
+      65558 => MESSENGER_ROOM_ENTRY,
+      65559 => TOMBSTONE,
 
       _ => return None,
     })
   }
+
+  /// The record type number of a record type.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::gns::RecordType;
+  ///
+  /// assert_eq!(RecordType::AAAA.to_u32(), 28);
+  /// assert_eq!(RecordType::Unknown(1234).to_u32(), 1234);
+  /// ```
+  pub fn to_u32(&self) -> u32 {
+    match *self {
+      A                     => 1,
+      NS                    => 2,
+      CNAME                 => 5,
+      SOA                   => 6,
+      PTR                   => 12,
+      MX                    => 15,
+      TXT                   => 16,
+      RP                    => 17,
+      AFSDB                 => 18,
+      SIG                   => 24,
+      KEY                   => 25,
+      AAAA                  => 28,
+      LOC                   => 29,
+      SRV                   => 33,
+      NAPTR                 => 35,
+      KX                    => 36,
+      CERT                  => 37,
+      DNAME                 => 39,
+      APL                   => 42,
+      DS                    => 43,
+      SSHFP                 => 44,
+      IPSECKEY              => 45,
+      RRSIG                 => 46,
+      NSEC                  => 47,
+      DNSKEY                => 48,
+      DHCID                 => 49,
+      NSEC3                 => 50,
+      NSEC3PARAM            => 51,
+      TLSA                  => 52,
+      SMIMEA                => 53,
+      HIP                   => 55,
+      CDS                   => 59,
+      CDNSKEY               => 60,
+      OPENPGPKEY            => 61,
+      TKEY                  => 249,
+      TSIG                  => 250,
+      ANY                   => 255,
+      URI                   => 256,
+      CAA                   => 257,
+      TA                    => 32768,
+      DLV                   => 32769,
+
+      PKEY                  => 65536,
+      NICK                  => 65537,
+      LEHO                  => 65538,
+      VPN                   => 65539,
+      GNS2DNS               => 65540,
+      BOX                   => 65541,
+      PLACE                 => 65542,
+      PHONE                 => 65543,
+      RECLAIM_ATTRIBUTE     => 65544,
+      RECLAIM_TICKET        => 65545,
+      SBOX                  => 65547,
+      DELEGATE              => 65548,
+      ATTRIBUTE             => 65549,
+      RECLAIM_ATTRIBUTE_REF => 65550,
+      REDIRECT              => 65551,
+      RECLAIM_OIDC_CLIENT   => 65552,
+      RECLAIM_OIDC_REDIRECT => 65553,
+      RECLAIM_CREDENTIAL    => 65554,
+      RECLAIM_PRESENTATION  => 65555,
+      EDKEY                 => 65556,
+      ERIS_READ_CAPABILITY  => 65557,
+      MESSENGER_ROOM_ENTRY  => 65558,
+      TOMBSTONE             => 65559,
+      Unknown(x)            => x,
+    }
+  }
 }
 
 /// Error generated when attempting to parse a `RecordType`
@@ -103,22 +359,85 @@ impl FromStr for RecordType {
       "PTR"     => Ok(PTR),
       "MX"      => Ok(MX),
       "TXT"     => Ok(TXT),
+      "RP"      => Ok(RP),
+      "AFSDB"   => Ok(AFSDB),
+      "SIG"     => Ok(SIG),
+      "KEY"     => Ok(KEY),
       "AAAA"    => Ok(AAAA),
+      "LOC"     => Ok(LOC),
+      "SRV"     => Ok(SRV),
+      "NAPTR"   => Ok(NAPTR),
+      "KX"      => Ok(KX),
+      "CERT"    => Ok(CERT),
+      "DNAME"   => Ok(DNAME),
+      "APL"     => Ok(APL),
+      "DS"      => Ok(DS),
+      "SSHFP"   => Ok(SSHFP),
+      "IPSECKEY" => Ok(IPSECKEY),
+      "RRSIG"   => Ok(RRSIG),
+      "NSEC"    => Ok(NSEC),
+      "DNSKEY"  => Ok(DNSKEY),
+      "DHCID"   => Ok(DHCID),
+      "NSEC3"   => Ok(NSEC3),
+      "NSEC3PARAM" => Ok(NSEC3PARAM),
       "TLSA"    => Ok(TLSA),
+      "SMIMEA"  => Ok(SMIMEA),
+      "HIP"     => Ok(HIP),
+      "CDS"     => Ok(CDS),
+      "CDNSKEY" => Ok(CDNSKEY),
+      "OPENPGPKEY" => Ok(OPENPGPKEY),
+      "TKEY"    => Ok(TKEY),
+      "TSIG"    => Ok(TSIG),
+      "ANY"     => Ok(ANY),
+      "URI"     => Ok(URI),
+      "CAA"     => Ok(CAA),
+      "TA"      => Ok(TA),
+      "DLV"     => Ok(DLV),
 
       "PKEY"    => Ok(PKEY),
       "NICK"    => Ok(NICK),
       "LEHO"    => Ok(LEHO),
       "VPN"     => Ok(VPN),
       "GNS2DNS" => Ok(GNS2DNS),
-      _         => Err(RecordTypeFromStrError::ParsingFailed),
+      "BOX"     => Ok(BOX),
+      "PLACE"   => Ok(PLACE),
+      "PHONE"   => Ok(PHONE),
+      "RECLAIM_ATTRIBUTE" => Ok(RECLAIM_ATTRIBUTE),
+      "RECLAIM_TICKET" => Ok(RECLAIM_TICKET),
+      "SBOX"    => Ok(SBOX),
+      "DELEGATE" => Ok(DELEGATE),
+      "ATTRIBUTE" => Ok(ATTRIBUTE),
+      "RECLAIM_ATTRIBUTE_REF" => Ok(RECLAIM_ATTRIBUTE_REF),
+      "REDIRECT" => Ok(REDIRECT),
+      "RECLAIM_OIDC_CLIENT" => Ok(RECLAIM_OIDC_CLIENT),
+      "RECLAIM_OIDC_REDIRECT" => Ok(RECLAIM_OIDC_REDIRECT),
+      "RECLAIM_CREDENTIAL" => Ok(RECLAIM_CREDENTIAL),
+      "RECLAIM_PRESENTATION" => Ok(RECLAIM_PRESENTATION),
+      "EDKEY"   => Ok(EDKEY),
+      "ERIS_READ_CAPABILITY" => Ok(ERIS_READ_CAPABILITY),
+      "MESSENGER_ROOM_ENTRY" => Ok(MESSENGER_ROOM_ENTRY),
+      "TOMBSTONE" => Ok(TOMBSTONE),
+      _         => {
+        // Types without a name are written `TYPE<number>`, as in RFC 3597.
+        let number = match s.starts_with("TYPE") {
+          true  => s[4..].parse::<u32>().ok(),
+          false => None,
+        };
+        match number {
+          Some(x) => Ok(RecordType::from_u32(x).unwrap_or(Unknown(x))),
+          None    => Err(RecordTypeFromStrError::ParsingFailed),
+        }
+      },
     }
   }
 }
 
 impl fmt::Display for RecordType {
   fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-    Debug::fmt(self, f)
+    match *self {
+      Unknown(x)  => write!(f, "TYPE{}", x),
+      _           => Debug::fmt(self, f),
+    }
   }
 }
 
@@ -130,6 +449,38 @@ pub struct Record {
 }
 
 impl Record {
+  /// Create a new record from its raw parts.
+  ///
+  /// `expiration_time` is either an absolute time or, if the `RF_RELATIVE_EXPIRATION` flag is
+  /// set, a relative time. Both are given in microseconds.
+  pub fn new(record_type: RecordType, data: Vec<u8>, expiration_time: u64, flags: u32) -> Record {
+    let ptr = data.as_ptr() as *const c_void;
+    Record {
+      data: ll::Struct_GNUNET_GNSRECORD_Data {
+        data:             ptr,
+        expiration_time:  expiration_time,
+        data_size:        data.len(),
+        record_type:      record_type.to_u32(),
+        flags:            flags,
+      },
+      buff: data,
+    }
+  }
+
+  /// Serialize a record to a byte stream.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    try!(w.write_u64::<BigEndian>(self.data.expiration_time));
+    try!(w.write_u32::<BigEndian>(self.buff.len() as u32));
+    try!(w.write_u32::<BigEndian>(self.data.record_type));
+    try!(w.write_u32::<BigEndian>(self.data.flags));
+    w.write_all(&self.buff[..])
+  }
+
+  /// The number of bytes this record takes up when serialized.
+  pub fn serialized_len(&self) -> usize {
+    20 + self.buff.len()
+  }
+
   /// Deserialize a record from a byte stream.
   pub fn deserialize<T>(reader: &mut T) -> Result<Record, io::Error> where T: Read {
     let expiration_time = try!(reader.read_u64::<BigEndian>());
@@ -151,32 +502,119 @@ impl Record {
     })
   }
 
-  /// Get the type of a record.
+  /// Get the type of a record. Types this crate doesn't know about are returned as
+  /// `RecordType::Unknown`.
   pub fn record_type(&self) -> RecordType {
-    RecordType::from_u32(self.data.record_type).unwrap()
+    let x = self.data.record_type;
+    RecordType::from_u32(x).unwrap_or(Unknown(x))
+  }
+
+  /// Get the raw payload of a record.
+  pub fn data(&self) -> &[u8] {
+    &self.buff[..]
+  }
+
+  /// Get the expiration time of a record in microseconds.
+  pub fn expiration_time(&self) -> u64 {
+    self.data.expiration_time
+  }
+
+  /// Get the flags of a record.
+  pub fn flags(&self) -> u32 {
+    self.data.flags
+  }
+
+  /// Create a record from the human-readable form of its value, as accepted by
+  /// `gnunet-namestore -V`. Returns `None` if `value` is not valid for `record_type`.
+  ///
+  /// SRV, NAPTR, DS and CAA values are read in their usual zone file form. See the `FromStr`
+  /// impls of `SrvData`, `NaptrData`, `DsData` and `CaaData`.
+  pub fn from_value_str(record_type: RecordType, value: &str, expiration_time: u64, flags: u32) -> Option<Record> {
+    let data = match record_type {
+      SRV   => Some(SrvData::from_str(value).ok().and_then(|d| d.to_bytes())),
+      NAPTR => Some(NaptrData::from_str(value).ok().and_then(|d| d.to_bytes())),
+      DS    => Some(DsData::from_str(value).ok().and_then(|d| d.to_bytes())),
+      CAA   => Some(CaaData::from_str(value).ok().and_then(|d| d.to_bytes())),
+      _     => None,
+    };
+    if let Some(data) = data {
+      return data.map(|d| Record::new(record_type, d, expiration_time, flags));
+    }
+
+    let value = match CString::new(value) {
+      Ok(v)   => v,
+      Err(_)  => return None,
+    };
+    unsafe {
+      let mut data: *mut c_void = ptr::null_mut();
+      let mut data_size: size_t = 0;
+      let res = ll::GNUNET_GNSRECORD_string_to_value(record_type.to_u32(), value.as_ptr(), &mut data, &mut data_size);
+      if res != ll::GNUNET_OK {
+        return None;
+      }
+      let buff = slice::from_raw_parts(data as *const u8, data_size as usize).to_vec();
+      free(data);
+      Some(Record::new(record_type, buff, expiration_time, flags))
+    }
+  }
+
+  /// The human-readable form of the record's value, as printed by `gnunet-namestore`. Returns
+  /// `None` if the record's data is malformed.
+  pub fn value_to_string(&self) -> Option<String> {
+    let data = self.data();
+    match self.record_type() {
+      SRV   => return SrvData::from_bytes(data).map(|d| d.to_string()),
+      NAPTR => return NaptrData::from_bytes(data).map(|d| d.to_string()),
+      DS    => return DsData::from_bytes(data).map(|d| d.to_string()),
+      CAA   => return CaaData::from_bytes(data).map(|d| d.to_string()),
+      _     => (),
+    }
+    unsafe {
+      let cs = ll::GNUNET_GNSRECORD_value_to_string(self.data.record_type, self.data.data, self.data.data_size);
+      if cs.is_null() {
+        return None;
+      }
+      let ret = from_utf8(CStr::from_ptr(cs as *const c_char).to_bytes()).ok().map(|s| s.to_string());
+      free(cs as *mut c_void);
+      ret
+    }
+  }
+}
+
+impl Clone for Record {
+  fn clone(&self) -> Record {
+    let buff = self.buff.clone();
+    let data = ll::Struct_GNUNET_GNSRECORD_Data {
+      data:             buff.as_ptr() as *const c_void,
+      expiration_time:  self.data.expiration_time,
+      data_size:        self.data.data_size,
+      record_type:      self.data.record_type,
+      flags:            self.data.flags,
+    };
+    Record {
+      data: data,
+      buff: buff,
+    }
   }
 }
 
+impl PartialEq for Record {
+  fn eq(&self, other: &Record) -> bool {
+    self.data.record_type == other.data.record_type &&
+    self.data.expiration_time == other.data.expiration_time &&
+    self.data.flags == other.data.flags &&
+    self.buff == other.buff
+  }
+}
+
+impl Eq for Record {}
+
 impl Debug for Record {
   fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-    let tpe = self.data.record_type;
-    try!(write!(f, "{:?}: ", RecordType::from_u32(tpe).unwrap()));
-    unsafe {
-      let cs = ll::GNUNET_GNSRECORD_value_to_string(tpe, self.data.data, self.data.data_size);
-      match cs.is_null() {
-        true  => write!(f, "<malformed record data>"),
-        false => {
-          let constified = cs as *const c_char;
-          let s = from_utf8(CStr::from_ptr(constified).to_bytes());
-          let ret = match s {
-            Ok(ss)  => write!(f, "{}", ss),
-            Err(_)  => write!(f, "<invalid utf8>"),
-          };
-          // TODO: use the c-string wrapper that automatically dealloces when it exists
-          free(cs as *mut c_void);
-          ret
-        },
-      }
+    try!(write!(f, "{:?}: ", self.record_type()));
+    match self.value_to_string() {
+      Some(s) => write!(f, "{}", s),
+      None    => write!(f, "<malformed record data>"),
     }
   }
 }
@@ -187,3 +625,37 @@ impl fmt::Display for Record {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+  use super::*;
+
+  #[test]
+  fn test_record_type_names() {
+    for &x in [1u32, 37, 61, 256, 32769, 65541, 65551, 65559].iter() {
+      let rt = RecordType::from_u32(x).unwrap();
+      assert_eq!(rt.to_u32(), x);
+      assert_eq!(RecordType::from_str(&rt.to_string()).unwrap(), rt);
+    }
+    assert_eq!(RecordType::from_str("OPENPGPKEY").unwrap(), RecordType::OPENPGPKEY);
+    assert_eq!(RecordType::from_u32(65546), None);
+  }
+
+  #[test]
+  fn test_unknown_record_type() {
+    let unknown = RecordType::Unknown(65546);
+    assert_eq!(unknown.to_string(), "TYPE65546");
+    assert_eq!(RecordType::from_str("TYPE65546").unwrap(), unknown);
+    assert_eq!(RecordType::from_str("TYPE28").unwrap(), RecordType::AAAA);
+    assert!(RecordType::from_str("TYPE").is_err());
+    assert_eq!(RecordType::Unknown(1), RecordType::A);
+    assert!(RecordType::Unknown(2) != RecordType::A);
+
+    let record = Record::new(unknown, vec![1, 2, 3], 0, 0);
+    let mut buf = Vec::new();
+    record.serialize(&mut buf).unwrap();
+    let read = Record::deserialize(&mut &buf[..]).unwrap();
+    assert_eq!(read.record_type(), unknown);
+    assert!(read == record);
+  }
+}
@@ -0,0 +1,101 @@
+use std::ascii::AsciiExt;
+use std::str::FromStr;
+
+use Cfg;
+use gns::LocalOptions;
+
+/// Error generated when parsing a `LocalOptions` from a string.
+error_def! LocalOptionsFromStrError {
+  ParsingFailed
+    => "Failed to parse the string as lookup options. Expected DEFAULT, NO_DHT or LOCAL_MASTER",
+}
+
+impl FromStr for LocalOptions {
+  type Err = LocalOptionsFromStrError;
+
+  fn from_str(s: &str) -> Result<LocalOptions, LocalOptionsFromStrError> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("DEFAULT") {
+      Ok(LocalOptions::Default)
+    }
+    else if s.eq_ignore_ascii_case("NO_DHT") {
+      Ok(LocalOptions::NoDHT)
+    }
+    else if s.eq_ignore_ascii_case("LOCAL_MASTER") {
+      Ok(LocalOptions::LocalMaster)
+    }
+    else {
+      Err(LocalOptionsFromStrError::ParsingFailed)
+    }
+  }
+}
+
+/// Settings for lookups in the master zone, used by `GNS::lookup_in_master`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolverConfig {
+  /// The options used for every lookup. If `None`, lookups of names directly under `.gnu` use
+  /// `NoDHT` and other lookups use `LocalMaster`.
+  pub default_options: Option<LocalOptions>,
+  /// Never look in the DHT, whatever `default_options` says.
+  pub no_dht: bool,
+}
+
+impl ResolverConfig {
+  /// Read the settings from the `[gns]` section of `cfg`.
+  ///
+  /// `DEFAULT_LOOKUP_OPTIONS` may be `DEFAULT`, `NO_DHT` or `LOCAL_MASTER`. Setting `NO_DHT` to
+  /// `YES` keeps every lookup in the local cache. Unrecognised values are ignored.
+  pub fn from_cfg(cfg: &Cfg) -> ResolverConfig {
+    let section = match cfg.get_section("gns") {
+      Some(s) => s,
+      None    => return ResolverConfig::default(),
+    };
+    ResolverConfig {
+      default_options: section.get("DEFAULT_LOOKUP_OPTIONS").and_then(|v| v.parse().ok()),
+      no_dht: match section.get("NO_DHT") {
+        Some(v) => v.eq_ignore_ascii_case("YES"),
+        None    => false,
+      },
+    }
+  }
+
+  /// The options to use when looking up `name`.
+  pub fn options_for(&self, name: &str) -> LocalOptions {
+    if self.no_dht {
+      return LocalOptions::NoDHT;
+    }
+    if let Some(options) = self.default_options {
+      return options;
+    }
+    let mut it = name.split('.');
+    match (it.next(), it.next(), it.next()) {
+      (Some(_), Some("gnu"), None)  => LocalOptions::NoDHT,
+      _                             => LocalOptions::LocalMaster,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use Cfg;
+  use gns::LocalOptions;
+  use super::*;
+
+  #[test]
+  fn test_resolver_config() {
+    let mut cfg = Cfg::empty();
+    let rc = ResolverConfig::from_cfg(&cfg);
+    assert_eq!(rc, ResolverConfig::default());
+    assert_eq!(rc.options_for("www.gnu"), LocalOptions::NoDHT);
+    assert_eq!(rc.options_for("www.example.gnu"), LocalOptions::LocalMaster);
+
+    cfg.set_string("gns", "DEFAULT_LOOKUP_OPTIONS", "default".to_string());
+    let rc = ResolverConfig::from_cfg(&cfg);
+    assert_eq!(rc.default_options, Some(LocalOptions::Default));
+    assert_eq!(rc.options_for("www.gnu"), LocalOptions::Default);
+
+    cfg.set_string("gns", "NO_DHT", "YES".to_string());
+    let rc = ResolverConfig::from_cfg(&cfg);
+    assert_eq!(rc.options_for("www.example.gnu"), LocalOptions::NoDHT);
+  }
+}
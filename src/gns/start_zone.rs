@@ -0,0 +1,238 @@
+use std::ascii::AsciiExt;
+
+use Cfg;
+use EcdsaPublicKey;
+use identity::{self, IdentityService};
+use service::{self, Transient};
+use gns::{GNS, LookupError, Record, RecordType, ResolverConfig, EMPTY_LABEL_AT};
+
+/// How the zone to start resolving a name in was found.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZoneSource {
+  /// The last label of the name is a zone key.
+  Zkey,
+  /// A TLD mapped to a zone in the `[gns]` section of the config.
+  Tld,
+  /// An ego whose name is a suffix of the name.
+  Ego,
+  /// The default zone, ie. the zone of the default ego for gns-master.
+  Default,
+}
+
+/// Where to start resolving a name, as worked out by `start_zone`.
+#[derive(Clone, Debug)]
+pub struct StartZone {
+  /// The zone to look the name up in.
+  pub zone: EcdsaPublicKey,
+  /// The name with the part identifying `zone` removed. This is `@` if nothing is left.
+  pub name: String,
+  /// How `zone` was found.
+  pub source: ZoneSource,
+}
+
+/// Errors returned by `start_zone`.
+error_def! StartZoneError {
+  GetEgo { #[from] cause: identity::GetEgoError }
+    => "Failed to ask the identity service for an ego matching the name" ("Reason: {}", cause),
+  GetDefaultEgo { #[from] cause: identity::GetDefaultEgoError }
+    => "Failed to retrieve the default identity for gns-master from the identity service" ("Reason: {}", cause),
+}
+
+impl Transient for StartZoneError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      StartZoneError::GetEgo { ref cause }        => cause.is_transient(),
+      StartZoneError::GetDefaultEgo { ref cause } => cause.is_transient(),
+    }
+  }
+}
+
+/// Remove `suffix`, which must be a suffix of `name` starting at a label, from `name`.
+fn strip_suffix(name: &str, suffix: &str) -> String {
+  match name.len() == suffix.len() {
+    true  => EMPTY_LABEL_AT.to_string(),
+    false => name[..name.len() - suffix.len() - 1].to_string(),
+  }
+}
+
+/// Whether `suffix` is a suffix of `name` starting at a label, ignoring ASCII case.
+fn has_suffix(name: &str, suffix: &str) -> bool {
+  if suffix.is_empty() || suffix.len() > name.len() {
+    return false;
+  }
+  let start = name.len() - suffix.len();
+  name.is_char_boundary(start)
+    && name[start..].eq_ignore_ascii_case(suffix)
+    && (start == 0 || name[..start].ends_with('.'))
+}
+
+/// Find the start zone of `name` without asking the identity service.
+///
+/// If the last label of `name` is a zone key, that zone is used. Otherwise the `[gns]` section of
+/// `cfg` is searched for TLD entries, whose keys start with a `.`, eg. `.gnu = <zone key>`. The
+/// longest TLD which is a suffix of `name` is used. Returns `None` if neither applies.
+pub fn tld_zone(cfg: &Cfg, name: &str) -> Option<StartZone> {
+  let last = match name.rfind('.') {
+    Some(i) => &name[i + 1..],
+    None    => name,
+  };
+  if let Ok(zone) = last.parse::<EcdsaPublicKey>() {
+    return Some(StartZone {
+      zone: zone,
+      name: strip_suffix(name, last),
+      source: ZoneSource::Zkey,
+    });
+  }
+
+  let section = match cfg.get_section("gns") {
+    Some(s) => s,
+    None    => return None,
+  };
+  let mut best: Option<(&str, EcdsaPublicKey)> = None;
+  for (key, value) in section.iter() {
+    if !key.starts_with('.') {
+      continue;
+    }
+    let tld = &key[1..];
+    if !has_suffix(name, tld) {
+      continue;
+    }
+    let zone = match value.trim().parse::<EcdsaPublicKey>() {
+      Ok(zone)  => zone,
+      Err(_)    => continue,
+    };
+    let longer = match best {
+      Some((b, _))  => tld.len() > b.len(),
+      None          => true,
+    };
+    if longer {
+      best = Some((tld, zone));
+    }
+  }
+  best.map(|(tld, zone)| StartZone {
+    zone: zone,
+    name: strip_suffix(name, tld),
+    source: ZoneSource::Tld,
+  })
+}
+
+/// Work out which zone to start resolving `name` in, the way `gnunet-gns` does.
+///
+/// The zone is taken from `tld_zone` if it finds one. Otherwise `identity` is asked for the ego
+/// whose name is the longest suffix of `name`, and failing that the default zone of gns-master is
+/// used with the whole name.
+pub fn start_zone(cfg: &Cfg, identity: &mut IdentityService, name: &str) -> Result<StartZone, StartZoneError> {
+  if let Some(start) = tld_zone(cfg, name) {
+    return Ok(start);
+  }
+  if let Some(ego) = try!(identity.get_ego_by_suffix(name)) {
+    if let Some(ego_name) = ego.get_name() {
+      return Ok(StartZone {
+        zone: ego.get_public_key(),
+        name: strip_suffix(name, &ego_name),
+        source: ZoneSource::Ego,
+      });
+    }
+  }
+  let ego = try!(identity.get_default_ego("gns-master"));
+  Ok(StartZone {
+    zone: ego.get_public_key(),
+    name: name.to_string(),
+    source: ZoneSource::Default,
+  })
+}
+
+/// Errors returned by `gns::resolve`.
+error_def! ResolveError {
+  IdentityConnect { #[from] cause: identity::ConnectError }
+    => "Failed to connect to the identity service" ("Reason: {}", cause),
+  GnsConnect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the GNS service" ("Reason: {}", cause),
+  StartZone { #[from] cause: StartZoneError }
+    => "Failed to work out which zone to start resolving the name in" ("Reason: {}", cause),
+  Lookup { #[from] cause: LookupError }
+    => "Failed to perform the lookup." ("Reason: {}", cause),
+}
+
+impl Transient for ResolveError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      ResolveError::IdentityConnect { ref cause } => cause.is_transient(),
+      ResolveError::GnsConnect { ref cause }      => cause.is_transient(),
+      ResolveError::StartZone { ref cause }       => cause.is_transient(),
+      ResolveError::Lookup { ref cause }          => cause.is_transient(),
+    }
+  }
+}
+
+/// Resolve `name`, starting in the zone chosen by `start_zone`, and return the records of type
+/// `record_type` found for it.
+///
+/// Unlike `lookup_in_master` this resolves names under any TLD configured in `cfg` or named after
+/// an ego, not just those under `.gnu`. The lookup options are chosen by the `ResolverConfig`
+/// read from `cfg`, and the lookup gives up after `[gns] LOOKUP_TIMEOUT` if that is set.
+///
+/// # Example
+///
+/// ```rust
+/// use gnunet::{Cfg, gns};
+///
+/// let config = Cfg::default().unwrap();
+/// for record in gns::resolve(&config, "www.gnu", gns::RecordType::A).unwrap() {
+///   println!("Got an IPv4 record for www.gnu: {}", record);
+/// }
+/// ```
+///
+/// # Note
+///
+/// This is a convenience function that connects to the identity and GNS services for a single
+/// lookup. To resolve many names, keep an `IdentityService` and a `GNS` handle and use
+/// `start_zone` with `GNS::lookup`.
+pub fn resolve(cfg: &Cfg, name: &str, record_type: RecordType) -> Result<Vec<Record>, ResolveError> {
+  let mut identity = try!(IdentityService::connect(cfg));
+  let start = try!(start_zone(cfg, &mut identity, name));
+  let mut gns = try!(GNS::connect(cfg));
+  let options = ResolverConfig::from_cfg(cfg).options_for(name);
+  let mut h = try!(gns.lookup(&start.name, &start.zone, record_type, options, None));
+  Ok(try!(h.wait_all()))
+}
+
+#[cfg(test)]
+mod tests {
+  use Cfg;
+  use EcdsaPrivateKey;
+  use super::*;
+  use super::{has_suffix, strip_suffix};
+
+  #[test]
+  fn test_tld_zone() {
+    assert!(has_suffix("www.alice.gnu", "alice.gnu"));
+    assert!(has_suffix("www.GNU", "gnu"));
+    assert!(!has_suffix("www.malice", "alice"));
+    assert_eq!(strip_suffix("www.alice", "alice"), "www");
+    assert_eq!(strip_suffix("alice", "alice"), "@");
+
+    let gnu = EcdsaPrivateKey::generate().get_public();
+    let alice = EcdsaPrivateKey::generate().get_public();
+    let mut cfg = Cfg::empty();
+    assert!(tld_zone(&cfg, "www.gnu").is_none());
+    cfg.set_string("gns", ".gnu", gnu.to_string());
+    cfg.set_string("gns", ".alice.gnu", alice.to_string());
+    cfg.set_string("gns", ".broken", "not a key".to_string());
+
+    let start = tld_zone(&cfg, "www.gnu").unwrap();
+    assert!(start.zone == gnu);
+    assert_eq!(start.name, "www");
+    assert_eq!(start.source, ZoneSource::Tld);
+    let start = tld_zone(&cfg, "www.alice.gnu").unwrap();
+    assert!(start.zone == alice);
+    assert_eq!(start.name, "www");
+    assert!(tld_zone(&cfg, "www.broken").is_none());
+    assert!(tld_zone(&cfg, "www.example").is_none());
+
+    let start = tld_zone(&Cfg::empty(), &format!("www.{}", alice)).unwrap();
+    assert!(start.zone == alice);
+    assert_eq!(start.name, "www");
+    assert_eq!(start.source, ZoneSource::Zkey);
+  }
+}
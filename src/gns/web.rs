@@ -0,0 +1,55 @@
+use iron::{BeforeMiddleware, IronError, IronResult, Request, status};
+use iron::headers::Host;
+use iron::typemap::Key;
+
+use gns::{HostLookupError, HostResolver, Record};
+
+/// The key under which `HostResolver` stores the records of a request's host in the request's
+/// extensions, when used as Iron middleware.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// extern crate iron;
+/// extern crate gnunet;
+///
+/// use iron::prelude::*;
+/// use iron::status;
+/// use gnunet::Cfg;
+/// use gnunet::gns::{HostRecords, HostResolver};
+///
+/// fn main() {
+///   let config = Cfg::default().unwrap();
+///   let mut chain = Chain::new(|req: &mut Request| {
+///     let records = req.extensions.get::<HostRecords>().cloned().unwrap_or(Vec::new());
+///     Ok(Response::with((status::Ok, format!("{} records", records.len()))))
+///   });
+///   chain.link_before(HostResolver::new(&config));
+///   Iron::new(chain).http("localhost:3000").unwrap();
+/// }
+/// ```
+pub struct HostRecords;
+
+impl Key for HostRecords {
+  type Value = Vec<Record>;
+}
+
+/// Resolves the host of each request before it is handled and stores its records under
+/// `HostRecords`. Requests made to an IP address, or without a `Host` header, are passed on
+/// without records. If the lookup fails the request is answered with `502 Bad Gateway`.
+impl BeforeMiddleware for HostResolver {
+  fn before(&self, req: &mut Request) -> IronResult<()> {
+    let host = match req.headers.get::<Host>() {
+      Some(host)  => host.hostname.clone(),
+      None        => return Ok(()),
+    };
+    match self.resolve(&host) {
+      Ok(records) => {
+        req.extensions.insert::<HostRecords>(records);
+        Ok(())
+      },
+      Err(HostLookupError::InvalidHost { .. }) => Ok(()),
+      Err(e) => Err(IronError::new(e, status::BadGateway)),
+    }
+  }
+}
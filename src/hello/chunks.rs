@@ -0,0 +1,202 @@
+/// The prefix of every chunk made by `split_uri`.
+pub const CHUNK_PREFIX: &'static str = "GNH1";
+
+/// The largest number of chunks `ChunkAssembler` accepts text split into.
+pub const MAX_CHUNKS: usize = 1024;
+
+/// Compute the CRC-32 (as used by zlib) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xffffffffu32;
+  for &b in data.iter() {
+    crc ^= b as u32;
+    for _ in 0..8 {
+      let mask = (!(crc & 1)).wrapping_add(1);
+      crc = (crc >> 1) ^ (0xedb88320 & mask);
+    }
+  }
+  !crc
+}
+
+/// Split a HELLO URI (or any other text) into chunks of at most `max_len` characters of payload,
+/// eg. to show them as a series of QR codes. Use `ChunkAssembler` to put them back together.
+///
+/// Each chunk has the form `GNH1/<index>/<count>/<checksum>/<payload>`, where the checksum is a
+/// CRC-32 of the whole text. This lets the other side check that the chunks came from the same
+/// text and that nothing was lost in between.
+///
+/// # Panics
+///
+/// Panics if `max_len` is zero.
+pub fn split_uri(uri: &str, max_len: usize) -> Vec<String> {
+  assert!(max_len > 0);
+  let checksum = crc32(uri.as_bytes());
+  let mut pieces = Vec::new();
+  let mut rest = uri;
+  while !rest.is_empty() {
+    let mut end = if rest.len() < max_len { rest.len() } else { max_len };
+    while !rest.is_char_boundary(end) {
+      end -= 1;
+    }
+    // Always make progress, even if a single character is longer than max_len.
+    if end == 0 {
+      end = rest.chars().next().unwrap().len_utf8();
+    }
+    pieces.push(&rest[..end]);
+    rest = &rest[end..];
+  }
+  if pieces.is_empty() {
+    pieces.push("");
+  }
+  let count = pieces.len();
+  pieces.iter()
+        .enumerate()
+        .map(|(i, piece)| format!("{}/{}/{}/{:08X}/{}", CHUNK_PREFIX, i, count, checksum, piece))
+        .collect()
+}
+
+/// Errors returned by `ChunkAssembler::add` and `join_chunks`.
+error_def! ChunkError {
+  Malformed
+    => "The chunk is not in the format made by split_uri",
+  TooManyChunks { count: usize }
+    => "The text was split into too many chunks" ("{} chunks is more than the maximum of {}", count, MAX_CHUNKS),
+  Mismatch
+    => "The chunk's count or checksum doesn't match the earlier chunks, so it came from different text",
+  ChecksumMismatch
+    => "The reassembled text doesn't match its checksum",
+}
+
+struct ParsedChunk<'a> {
+  index: usize,
+  count: usize,
+  checksum: u32,
+  payload: &'a str,
+}
+
+fn parse_chunk(chunk: &str) -> Result<ParsedChunk, ChunkError> {
+  let mut parts = chunk.trim().splitn(5, '/');
+  if parts.next() != Some(CHUNK_PREFIX) {
+    return Err(ChunkError::Malformed);
+  }
+  let index = parts.next().and_then(|s| s.parse::<usize>().ok());
+  let count = parts.next().and_then(|s| s.parse::<usize>().ok());
+  let checksum = parts.next().and_then(|s| u32::from_str_radix(s, 16).ok());
+  let payload = parts.next();
+  match (index, count, checksum, payload) {
+    (Some(index), Some(count), Some(checksum), Some(payload)) if index < count => Ok(ParsedChunk {
+      index: index,
+      count: count,
+      checksum: checksum,
+      payload: payload,
+    }),
+    _ => Err(ChunkError::Malformed),
+  }
+}
+
+/// Puts the chunks made by `split_uri` back together. The chunks can be added in any order and
+/// adding the same chunk twice is harmless.
+pub struct ChunkAssembler {
+  checksum: u32,
+  chunks: Vec<Option<String>>,
+}
+
+impl ChunkAssembler {
+  /// Create an assembler which hasn't seen any chunks yet.
+  pub fn new() -> ChunkAssembler {
+    ChunkAssembler {
+      checksum: 0,
+      chunks: Vec::new(),
+    }
+  }
+
+  /// Add a chunk. Returns the reassembled text once every chunk has been added.
+  pub fn add(&mut self, chunk: &str) -> Result<Option<String>, ChunkError> {
+    let parsed = try!(parse_chunk(chunk));
+    if parsed.count > MAX_CHUNKS {
+      return Err(ChunkError::TooManyChunks { count: parsed.count });
+    }
+    if self.chunks.is_empty() {
+      self.checksum = parsed.checksum;
+      self.chunks = vec![None; parsed.count];
+    }
+    else if parsed.count != self.chunks.len() || parsed.checksum != self.checksum {
+      return Err(ChunkError::Mismatch);
+    }
+    self.chunks[parsed.index] = Some(parsed.payload.to_string());
+    if self.missing() > 0 {
+      return Ok(None);
+    }
+    let text = self.chunks.iter().fold(String::new(), |mut text, c| {
+      text.push_str(&c.as_ref().unwrap()[..]);
+      text
+    });
+    if crc32(text.as_bytes()) != self.checksum {
+      return Err(ChunkError::ChecksumMismatch);
+    }
+    Ok(Some(text))
+  }
+
+  /// The number of chunks still needed, or zero if no chunks have been added yet.
+  pub fn missing(&self) -> usize {
+    self.chunks.iter().filter(|c| c.is_none()).count()
+  }
+
+  /// The total number of chunks, or zero if no chunks have been added yet.
+  pub fn count(&self) -> usize {
+    self.chunks.len()
+  }
+}
+
+/// Reassemble the text from a complete set of chunks made by `split_uri`. Returns `Ok(None)` if
+/// some chunks are missing.
+pub fn join_chunks(chunks: &[&str]) -> Result<Option<String>, ChunkError> {
+  let mut assembler = ChunkAssembler::new();
+  let mut ret = None;
+  for chunk in chunks.iter() {
+    ret = try!(assembler.add(chunk));
+  }
+  Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_split_and_join() {
+    let uri = "gnunet://hello/JHBMSXHRAHRC7ABC10A5XJ5GC5ZTCRVE3S0F0RBMHT0Q9X2GPMC0+1464300000+tcp+127.0.0.1:2086";
+    let chunks = split_uri(uri, 20);
+    assert_eq!(chunks.len(), 5);
+
+    let mut assembler = ChunkAssembler::new();
+    for i in [4, 1, 3, 1, 0].iter() {
+      assert_eq!(assembler.add(&chunks[*i]).unwrap(), None);
+    }
+    assert_eq!(assembler.missing(), 1);
+    assert_eq!(assembler.add(&chunks[2]).unwrap(), Some(uri.to_string()));
+
+    match ChunkAssembler::new().add("hello") {
+      Err(ChunkError::Malformed) => (),
+      _ => panic!("expected Malformed"),
+    };
+    match ChunkAssembler::new().add("GNH1/0/18446744073709551615/00000000/x") {
+      Err(ChunkError::TooManyChunks { .. }) => (),
+      _ => panic!("expected TooManyChunks"),
+    };
+    let other = split_uri("gnunet://hello/something-else", 20);
+    let mut assembler = ChunkAssembler::new();
+    assembler.add(&chunks[0]).unwrap();
+    match assembler.add(&other[1]) {
+      Err(ChunkError::Mismatch) => (),
+      _ => panic!("expected Mismatch"),
+    };
+
+    let corrupted = format!("{}X", &chunks[3][..chunks[3].len() - 1]);
+    let mut all: Vec<&str> = chunks.iter().map(|c| &c[..]).collect();
+    all[3] = &corrupted[..];
+    match join_chunks(&all[..]) {
+      Err(ChunkError::ChecksumMismatch) => (),
+      _ => panic!("expected ChecksumMismatch"),
+    };
+  }
+}
@@ -0,0 +1,337 @@
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use byteorder::{self, ReadBytesExt, WriteBytesExt, BigEndian};
+
+use PeerIdentity;
+use peerinfo::peerinfo::PeerIdentityFromStrError;
+pub use self::chunks::*;
+
+mod chunks;
+
+/// The prefix of the URI of a HELLO.
+pub const HELLO_URI_PREFIX: &'static str = "gnunet://hello/";
+
+/// The prefix of the URI of a friend-only HELLO.
+pub const FRIEND_HELLO_URI_PREFIX: &'static str = "gnunet://friend-hello/";
+
+/// The separator between the parts of a HELLO URI.
+const URI_SEP: char = '+';
+
+/// An address of a peer, as listed in its HELLO.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HelloAddress {
+  /// The name of the transport plugin the address belongs to, eg. `"tcp"`.
+  pub plugin: String,
+  /// When the address expires, in microseconds since the epoch.
+  pub expiration: u64,
+  /// The address, in the plugin's own format.
+  pub address: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Hello {
+  /// Use this peer in F2F mode. Do not gossip this hello.
+  pub friend_only: bool,
+
+  /// The identity of the peer.
+  pub id: PeerIdentity,
+
+  /// The addresses of the peer.
+  pub addresses: Vec<HelloAddress>,
+}
+
+error_def! HelloDeserializeError {
+  ShortMessage
+    => "Unexpected EOF when deserializing the hello",
+  Malformed
+    => "The hello's list of addresses is malformed",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error reading the hello" ("Error: {}", cause),
+}
+
+/// Errors returned by `Hello::from_uri`.
+error_def! HelloFromUriError {
+  NotAHelloUri
+    => "The string is not a HELLO URI",
+  InvalidPeerIdentity { #[from] cause: PeerIdentityFromStrError }
+    => "The HELLO URI contains an invalid peer identity" ("Reason: {}", cause),
+  InvalidAddress { address: String }
+    => "The HELLO URI contains an address which could not be parsed" ("Address: {}", address),
+}
+
+/// The number of days from 1970-01-01 to the date `y-m-d`.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+  let y = if m <= 2 { y - 1 } else { y };
+  let era = (if y >= 0 { y } else { y - 399 }) / 400;
+  let yoe = y - era * 400;
+  let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+/// Format an expiration time the way HELLO URIs do, as `%Y%m%d%H%M%S` in UTC.
+fn format_expiration(micros: u64) -> String {
+  let secs = (micros / 1000000) as i64;
+  let (days, rem) = (secs / 86400, secs % 86400);
+  let z = days + 719468;
+  let era = z / 146097;
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let d = doy - (153 * mp + 2) / 5 + 1;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 };
+  let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+  format!("{:04}{:02}{:02}{:02}{:02}{:02}", y, m, d, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+/// Parse an expiration time formatted by `format_expiration`.
+fn parse_expiration(s: &str) -> Option<u64> {
+  if s.len() != 14 || !s.bytes().all(|b| b >= b'0' && b <= b'9') {
+    return None;
+  }
+  let field = |a: usize, b: usize| s[a..b].parse::<i64>().unwrap();
+  let (y, m, d) = (field(0, 4), field(4, 6), field(6, 8));
+  let (h, min, sec) = (field(8, 10), field(10, 12), field(12, 14));
+  if y < 1970 || m < 1 || m > 12 || d < 1 || d > 31 || h > 23 || min > 59 || sec > 59 {
+    return None;
+  }
+  let secs = days_from_civil(y, m, d) * 86400 + h * 3600 + min * 60 + sec;
+  Some(secs as u64 * 1000000)
+}
+
+/// Format an address of the `tcp` or `udp` plugin the way the plugin's `address_to_string` does,
+/// eg. `tcp.0.127.0.0.1:2086`. Returns `None` for other plugins and malformed addresses.
+fn address_to_string(plugin: &str, address: &[u8]) -> Option<String> {
+  if plugin != "tcp" && plugin != "udp" {
+    return None;
+  }
+  let mut r = Cursor::new(address);
+  let options = match r.read_u32::<BigEndian>() {
+    Ok(o)   => o,
+    Err(_)  => return None,
+  };
+  let ip = match address.len() {
+    10  => format!("{}", Ipv4Addr::new(address[4], address[5], address[6], address[7])),
+    22  => {
+      let mut segments = [0u16; 8];
+      for s in segments.iter_mut() {
+        *s = r.read_u16::<BigEndian>().unwrap();
+      }
+      format!("[{}]", Ipv6Addr::new(segments[0], segments[1], segments[2], segments[3],
+                                    segments[4], segments[5], segments[6], segments[7]))
+    },
+    _   => return None,
+  };
+  let port = ((address[address.len() - 2] as u16) << 8) | address[address.len() - 1] as u16;
+  Some(format!("{}.{}.{}:{}", plugin, options, ip, port))
+}
+
+/// Parse an address formatted by `address_to_string`.
+fn string_to_address(plugin: &str, s: &str) -> Option<Vec<u8>> {
+  let mut parts = s.splitn(3, '.');
+  if parts.next() != Some(plugin) || (plugin != "tcp" && plugin != "udp") {
+    return None;
+  }
+  let options = match parts.next().and_then(|o| o.parse::<u32>().ok()) {
+    Some(o) => o,
+    None    => return None,
+  };
+  let rest = match parts.next() {
+    Some(r) => r,
+    None    => return None,
+  };
+  let colon = match rest.rfind(':') {
+    Some(i) => i,
+    None    => return None,
+  };
+  let port = match rest[colon + 1..].parse::<u16>() {
+    Ok(p)   => p,
+    Err(_)  => return None,
+  };
+  let host = &rest[..colon];
+  let mut ret = Vec::new();
+  ret.write_u32::<BigEndian>(options).unwrap();
+  if host.starts_with('[') && host.ends_with(']') {
+    let ip = match host[1..host.len() - 1].parse::<Ipv6Addr>() {
+      Ok(ip)  => ip,
+      Err(_)  => return None,
+    };
+    for s in ip.segments().iter() {
+      ret.write_u16::<BigEndian>(*s).unwrap();
+    }
+  }
+  else {
+    let ip = match host.parse::<Ipv4Addr>() {
+      Ok(ip)  => ip,
+      Err(_)  => return None,
+    };
+    ret.extend_from_slice(&ip.octets()[..]);
+  }
+  ret.write_u16::<BigEndian>(port).unwrap();
+  Some(ret)
+}
+
+/// Parse the addresses following the peer identity in a HELLO.
+fn read_addresses(buf: Vec<u8>) -> Result<Vec<HelloAddress>, HelloDeserializeError> {
+  let len = buf.len();
+  let mut r = Cursor::new(buf);
+  let mut ret = Vec::new();
+  while (r.position() as usize) < len {
+    let start = r.position() as usize;
+    let nul = match r.get_ref()[start..].iter().position(|&b| b == 0) {
+      Some(i) => start + i,
+      None    => return Err(HelloDeserializeError::Malformed),
+    };
+    let plugin = match String::from_utf8(r.get_ref()[start..nul].to_vec()) {
+      Ok(p)   => p,
+      Err(_)  => return Err(HelloDeserializeError::Malformed),
+    };
+    r.set_position(nul as u64 + 1);
+    let (address_len, expiration) = match (r.read_u16::<BigEndian>(), r.read_u64::<BigEndian>()) {
+      (Ok(l), Ok(e))  => (l as usize, e),
+      _               => return Err(HelloDeserializeError::Malformed),
+    };
+    let pos = r.position() as usize;
+    if address_len > len - pos {
+      return Err(HelloDeserializeError::Malformed);
+    }
+    let address = r.get_ref()[pos..pos + address_len].to_vec();
+    r.set_position((pos + address_len) as u64);
+    ret.push(HelloAddress {
+      plugin: plugin,
+      expiration: expiration,
+      address: address,
+    });
+  }
+  Ok(ret)
+}
+
+impl Hello {
+  /// The URI of this HELLO, as used by `gnunet-peerinfo -g`.
+  ///
+  /// Each address is written as `+<expiration>+<plugin>+<address>`. Only addresses of the `tcp`
+  /// and `udp` plugins can be written, addresses of other plugins are left out.
+  pub fn uri(&self) -> String {
+    let prefix = if self.friend_only { FRIEND_HELLO_URI_PREFIX } else { HELLO_URI_PREFIX };
+    let mut ret = format!("{}{}", prefix, self.id);
+    for a in self.addresses.iter() {
+      if let Some(address) = address_to_string(&a.plugin, &a.address[..]) {
+        ret.push_str(&format!("{}{}{}{}{}{}", URI_SEP, format_expiration(a.expiration), URI_SEP, a.plugin, URI_SEP, address));
+      }
+    }
+    ret
+  }
+
+  /// Parse a HELLO URI made by `uri` or `gnunet-peerinfo -g`.
+  ///
+  /// Expiration times in URIs only have a precision of seconds.
+  pub fn from_uri(uri: &str) -> Result<Hello, HelloFromUriError> {
+    let (friend_only, rest) = if uri.starts_with(HELLO_URI_PREFIX) {
+      (false, &uri[HELLO_URI_PREFIX.len()..])
+    }
+    else if uri.starts_with(FRIEND_HELLO_URI_PREFIX) {
+      (true, &uri[FRIEND_HELLO_URI_PREFIX.len()..])
+    }
+    else {
+      return Err(HelloFromUriError::NotAHelloUri);
+    };
+    let mut parts = rest.split(URI_SEP);
+    let id = try!(PeerIdentity::from_str(parts.next().unwrap_or("")));
+    let mut addresses = Vec::new();
+    loop {
+      let (expiration, plugin, address) = match (parts.next(), parts.next(), parts.next()) {
+        (None, _, _)                      => break,
+        (Some(e), Some(p), Some(a))       => (e, p, a),
+        (Some(e), _, _)                   => return Err(HelloFromUriError::InvalidAddress { address: e.to_string() }),
+      };
+      match (parse_expiration(expiration), string_to_address(plugin, address)) {
+        (Some(e), Some(a))  => addresses.push(HelloAddress {
+          plugin: plugin.to_string(),
+          expiration: e,
+          address: a,
+        }),
+        _                   => return Err(HelloFromUriError::InvalidAddress { address: address.to_string() }),
+      };
+    }
+    Ok(Hello {
+      friend_only: friend_only,
+      id:          id,
+      addresses:   addresses,
+    })
+  }
+
+  pub fn deserialize<R>(r: &mut R) -> Result<Hello, HelloDeserializeError>
+      where R: Read
+  {
+    let friend_only = match r.read_u32::<BigEndian>() {
+      Ok(x)  => x != 0,
+      Err(e) => return Err(match e {
+        byteorder::Error::UnexpectedEOF => HelloDeserializeError::ShortMessage,
+        byteorder::Error::Io(e)         => HelloDeserializeError::Io { cause: e },
+      }),
+    };
+    let id = try!(PeerIdentity::deserialize(r));
+    let mut rest = Vec::new();
+    try!(r.read_to_end(&mut rest));
+    Ok(Hello {
+      friend_only: friend_only,
+      id:          id,
+      addresses:   try!(read_addresses(rest)),
+    })
+  }
+}
+
+impl fmt::Display for Hello {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Hello!")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use fixtures;
+  use ll;
+  use service;
+  use super::*;
+
+  #[test]
+  fn test_hello_fixture() {
+    let (tpe, mut mr) = service::split_frame(fixtures::HELLO.to_vec());
+    assert_eq!(tpe, ll::GNUNET_MESSAGE_TYPE_HELLO);
+    let hello = Hello::deserialize(&mut mr).unwrap();
+    assert!(!hello.friend_only);
+    let mut id = Vec::new();
+    hello.id.serialize(&mut id).unwrap();
+    assert_eq!(&id[..], &fixtures::PEER[..]);
+    assert!(hello.addresses.is_empty());
+  }
+
+  #[test]
+  fn test_hello_uri_round_trip() {
+    let (_, mut mr) = service::split_frame(fixtures::HELLO.to_vec());
+    let mut hello = Hello::deserialize(&mut mr).unwrap();
+    hello.addresses.push(HelloAddress {
+      plugin: "tcp".to_string(),
+      expiration: 1464300000 * 1000000,
+      address: vec![0, 0, 0, 0, 127, 0, 0, 1, 0x08, 0x26],
+    });
+    hello.addresses.push(HelloAddress {
+      plugin: "udp".to_string(),
+      expiration: 1464300001 * 1000000,
+      address: vec![0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x08, 0x26],
+    });
+    let uri = hello.uri();
+    assert!(uri.ends_with("+20160526220000+tcp+tcp.0.127.0.0.1:2086+20160526220001+udp+udp.1.[::1]:2086"));
+    let parsed = Hello::from_uri(&uri).unwrap();
+    assert!(parsed.id == hello.id);
+    assert_eq!(parsed.addresses, hello.addresses);
+
+    match Hello::from_uri(&format!("{}+20160526220000+tcp", uri)) {
+      Err(HelloFromUriError::InvalidAddress { .. }) => (),
+      r => panic!("unexpected result: {:?}", r),
+    };
+  }
+}
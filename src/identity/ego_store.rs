@@ -0,0 +1,170 @@
+//! Reading and writing the key files the identity service keeps its egos in.
+//!
+//! The service stores each ego as a file named after the ego and holding its private key. These
+//! helpers work on those files directly, so that egos can be inspected or backed up without the
+//! service running. Changes made while the service is running are only noticed by it when it is
+//! restarted.
+//!
+//! ```rust
+//! use gnunet::Cfg;
+//! use gnunet::identity::ego_store;
+//!
+//! let config = Cfg::default().unwrap();
+//! for ego in ego_store::read_egos(&config).unwrap() {
+//!   println!("{}", ego);
+//! }
+//! ```
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use Cfg;
+use EcdsaPrivateKey;
+use configuration::CfgExpandDollarError;
+use identity::Ego;
+
+/// Where the service keeps its ego files if the config doesn't say.
+pub const DEFAULT_EGO_DIR: &'static str = "$GNUNET_DATA_HOME/identity/egos/";
+
+/// The size of an ego file.
+const EGO_FILE_LEN: usize = 32;
+
+/// Errors returned by the functions which find the ego files through the config.
+error_def! EgoStoreError {
+  EgoDir { #[from] cause: CfgExpandDollarError }
+    => "The directory holding the ego files could not be worked out from the config" ("Reason: {}", cause),
+  InvalidName { name: String }
+    => "The name can't be used as the name of an ego file" ("Name: \"{}\"", name),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error accessing the ego files" ("Specifically: {}", cause),
+}
+
+/// The directory the identity service keeps its ego files in, taken from `[identity] EGODIR` in
+/// `cfg` or `DEFAULT_EGO_DIR` if it isn't set.
+pub fn ego_dir(cfg: &Cfg) -> Result<PathBuf, CfgExpandDollarError> {
+  match cfg.get_filename("identity", "EGODIR") {
+    Ok(dir) => Ok(dir),
+    Err(_)  => Ok(PathBuf::from(try!(cfg.expand_dollar(DEFAULT_EGO_DIR)))),
+  }
+}
+
+/// Read the ego file at `path`. The ego is named after the file.
+pub fn read_ego_file<P: AsRef<Path>>(path: P) -> Result<Ego, io::Error> {
+  let path = path.as_ref();
+  let name = match path.file_name().and_then(|n| n.to_str()) {
+    Some(name)  => name.to_string(),
+    None        => return Err(io::Error::new(io::ErrorKind::InvalidInput, "the file name is not a valid ego name")),
+  };
+  let mut data = Vec::new();
+  try!(try!(File::open(path)).read_to_end(&mut data));
+  if data.len() != EGO_FILE_LEN {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "the file does not hold a private key"));
+  }
+  let pk = try!(EcdsaPrivateKey::deserialize(&mut &data[..]));
+  let id = pk.get_public().hash();
+  Ok(Ego {
+    pk: pk,
+    name: Some(name),
+    id: id,
+  })
+}
+
+/// Write `key` to `path` in the format of an ego file. The file is only readable by its owner and
+/// must not exist yet.
+pub fn write_ego_file<P: AsRef<Path>>(path: P, key: &EcdsaPrivateKey) -> Result<(), io::Error> {
+  let mut f = try!(OpenOptions::new().write(true).create_new(true).mode(0o600).open(path));
+  key.serialize(&mut f)
+}
+
+/// Read all the ego files in the directory given by `ego_dir`, sorted by name. Returns no egos if
+/// the directory doesn't exist, as is the case before the service has created any.
+pub fn read_egos(cfg: &Cfg) -> Result<Vec<Ego>, EgoStoreError> {
+  let dir = try!(ego_dir(cfg));
+  let entries = match fs::read_dir(&dir) {
+    Ok(entries) => entries,
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e)      => return Err(From::from(e)),
+  };
+  let mut ret = Vec::new();
+  for entry in entries {
+    let entry = try!(entry);
+    if try!(entry.file_type()).is_file() {
+      ret.push(try!(read_ego_file(entry.path())));
+    }
+  }
+  ret.sort_by(|a, b| a.name.cmp(&b.name));
+  Ok(ret)
+}
+
+/// Read the ego called `name` from the directory given by `ego_dir`. Returns `None` if there is
+/// no such ego.
+pub fn read_ego(cfg: &Cfg, name: &str) -> Result<Option<Ego>, EgoStoreError> {
+  let path = try!(ego_path(cfg, name));
+  match read_ego_file(&path) {
+    Ok(ego)                                           => Ok(Some(ego)),
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(e)                                            => Err(From::from(e)),
+  }
+}
+
+/// Store `key` as the ego called `name` in the directory given by `ego_dir`, creating the
+/// directory if needed. Fails if there already is an ego with that name.
+pub fn write_ego(cfg: &Cfg, name: &str, key: &EcdsaPrivateKey) -> Result<Ego, EgoStoreError> {
+  let path = try!(ego_path(cfg, name));
+  if let Some(dir) = path.parent() {
+    try!(fs::create_dir_all(dir));
+  }
+  try!(write_ego_file(&path, key));
+  Ok(Ego {
+    pk: key.clone(),
+    name: Some(name.to_string()),
+    id: key.get_public().hash(),
+  })
+}
+
+/// The path of the file for the ego called `name`.
+fn ego_path(cfg: &Cfg, name: &str) -> Result<PathBuf, EgoStoreError> {
+  if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+    return Err(EgoStoreError::InvalidName { name: name.to_string() });
+  }
+  let mut path = try!(ego_dir(cfg));
+  path.push(name);
+  Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use Cfg;
+  use EcdsaPrivateKey;
+  use super::*;
+
+  #[test]
+  fn test_ego_store() {
+    let mut dir = env::temp_dir();
+    dir.push(format!("gnunet-rs-egos-{}", ::rand::random::<u32>()));
+    let mut cfg = Cfg::empty();
+    cfg.set_string("identity", "EGODIR", dir.to_str().unwrap().to_string());
+    assert!(read_egos(&cfg).unwrap().is_empty());
+
+    let key = EcdsaPrivateKey::generate();
+    let written = write_ego(&cfg, "bob", &key).unwrap();
+    write_ego(&cfg, "alice", &EcdsaPrivateKey::generate()).unwrap();
+    let dup = write_ego(&cfg, "bob", &key).is_err();
+    let bad_name = write_ego(&cfg, "../bob", &key).is_err();
+    let read = read_ego(&cfg, "bob").unwrap().unwrap();
+    let missing = read_ego(&cfg, "carol").unwrap().is_none();
+    let names: Vec<Option<String>> = read_egos(&cfg).unwrap().iter().map(|e| e.get_name()).collect();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(dup);
+    assert!(bad_name);
+    assert!(missing);
+    assert_eq!(read.get_name(), Some("bob".to_string()));
+    assert!(read.get_id() == written.get_id());
+    assert_eq!(names, vec![Some("alice".to_string()), Some("bob".to_string())]);
+  }
+}
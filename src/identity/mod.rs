@@ -1,17 +1,28 @@
 use std::string;
 use std::collections::HashMap;
-use std::io::{self, Read, Write};
+use std::collections::hash_map;
+use std::io::{self, Cursor, Read, Write};
 use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use num::ToPrimitive;
 
 use ll;
 use EcdsaPrivateKey;
 use EcdsaPublicKey;
 use HashCode;
-use service::{self, ServiceReader, ServiceWriter};
+use activity::{Activity, ActivityLog};
+use service::{self, CatchAll, ServiceReadLoop, ServiceWriter, ProcessMessageResult, RetryPolicy, Transient};
 use configuration::Cfg;
+use gns::{self, LocalOptions, Record, RecordType};
 use util::{ReadCString, ReadCStringError, ReadCStringWithLenError};
+use util::strings::c_string_message_len;
+use shutdown::{Component, ReadLoop};
+pub use self::multiplex::*;
+
+pub mod ego_store;
+mod multiplex;
 
 /// A GNUnet identity.
 ///
@@ -54,6 +65,32 @@ impl Ego {
   pub fn get_id(&self) -> &HashCode {
     &self.id
   }
+
+  /// Get the GNS zone of an ego. This is the ego's public key.
+  pub fn zone(&self) -> EcdsaPublicKey {
+    self.get_public_key()
+  }
+
+  /// Lookup a GNS record relative to this ego's zone.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, identity, gns};
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let ego = identity::get_default_ego(&config, "gns-master").unwrap();
+  /// let record = ego.lookup(&config, "www", gns::RecordType::A).unwrap();
+  /// println!("Got the IPv4 record for www in {}: {}", ego, record);
+  /// ```
+  ///
+  /// # Note
+  ///
+  /// This is a convenience function that connects to the GNS service, performs the lookup,
+  /// retrieves one result, then disconnects. See `gns::lookup`.
+  pub fn lookup(&self, cfg: &Cfg, name: &str, record_type: RecordType) -> Result<Record, gns::ConnectLookupError> {
+    gns::lookup(cfg, name, &self.zone(), record_type, LocalOptions::Default, None)
+  }
 }
 
 impl fmt::Display for Ego {
@@ -66,11 +103,82 @@ impl fmt::Display for Ego {
   }
 }
 
+/// A change to the egos, as delivered by `IdentityService::updates`.
+#[derive(Clone)]
+pub enum EgoChange {
+  /// A new ego was created.
+  Created(Ego),
+  /// The ego was renamed from `old_name` to the name it has now.
+  Renamed { ego: Ego, old_name: String },
+  /// The ego was deleted.
+  Deleted(Ego),
+}
+
+/// The egos known to an `IdentityService`, as returned by `IdentityService::iter_egos`.
+///
+/// The handle's egos are locked while this exists, so that they can be borrowed. Updates from the
+/// service wait until it is dropped.
+pub struct Egos<'a> {
+  egos: MutexGuard<'a, HashMap<HashCode, Ego>>,
+}
+
+/// An iterator over borrowed egos, created by `Egos::iter`.
+pub struct EgosIter<'b> {
+  inner: hash_map::Values<'b, HashCode, Ego>,
+}
+
+impl<'a> Egos<'a> {
+  /// Iterate over the egos, in no particular order.
+  pub fn iter<'b>(&'b self) -> EgosIter<'b> {
+    EgosIter {
+      inner: self.egos.values(),
+    }
+  }
+
+  /// The number of egos.
+  pub fn len(&self) -> usize {
+    self.egos.len()
+  }
+
+  /// Whether there are no egos.
+  pub fn is_empty(&self) -> bool {
+    self.egos.is_empty()
+  }
+}
+
+impl<'a, 'b> IntoIterator for &'b Egos<'a> {
+  type Item = &'b Ego;
+  type IntoIter = EgosIter<'b>;
+
+  fn into_iter(self) -> EgosIter<'b> {
+    self.iter()
+  }
+}
+
+impl<'b> Iterator for EgosIter<'b> {
+  type Item = &'b Ego;
+
+  fn next(&mut self) -> Option<&'b Ego> {
+    self.inner.next()
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.inner.size_hint()
+  }
+}
+
 /// A handle to the identity service.
+///
+/// The service tells its clients about changes to the egos as they happen. A background thread
+/// keeps the handle's list of egos up to date with them and passes them on to `updates`.
 pub struct IdentityService {
-  service_reader: ServiceReader,
   service_writer: ServiceWriter,
-  egos: HashMap<HashCode, Ego>,
+  callback_loop: ServiceReadLoop,
+  replies: Receiver<(u16, Cursor<Vec<u8>>)>,
+  lookup_pending: Arc<AtomicBool>,
+  egos: Arc<Mutex<HashMap<HashCode, Ego>>>,
+  watchers: Arc<Mutex<Vec<Sender<EgoChange>>>>,
+  activity: Option<ActivityLog>,
 }
 
 /// Errors returned by `IdentityService::connect`
@@ -90,6 +198,113 @@ error_def! ConnectError {
 }
 byteorder_error_chain! {ConnectError}
 
+impl Transient for ConnectError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      ConnectError::Connect { ref cause }     => cause.is_transient(),
+      ConnectError::ReadMessage { ref cause } => cause.is_transient(),
+      ConnectError::Io { .. }                 => true,
+      ConnectError::Disconnected              => true,
+      _                                       => false,
+    }
+  }
+}
+
+/// The length of an `IDENTITY_GET_DEFAULT` request for the service `name`, or `None` if the name
+/// is too long to send.
+fn get_default_message_len(name: &str) -> Option<u16> {
+  c_string_message_len(8, name)
+}
+
+/// The length of an `IDENTITY_SET_DEFAULT` request for the service `name`, or `None` if the name
+/// is too long to send.
+fn set_default_message_len(name: &str) -> Option<u16> {
+  c_string_message_len(40, name)
+}
+
+/// The length of an `IDENTITY_CREATE` request for an ego called `name`, or `None` if the name is
+/// too long to send.
+fn create_message_len(name: &str) -> Option<u16> {
+  c_string_message_len(40, name)
+}
+
+/// The length of an `IDENTITY_RENAME` request renaming the ego `old_name` to `new_name`, or `None`
+/// if the names are too long to send.
+fn rename_message_len(old_name: &str, new_name: &str) -> Option<u16> {
+  c_string_message_len(8 + old_name.len() + 1, new_name)
+}
+
+/// The length of an `IDENTITY_DELETE` request for the ego called `name`, or `None` if the name is
+/// too long to send.
+fn delete_message_len(name: &str) -> Option<u16> {
+  c_string_message_len(8, name)
+}
+
+/// The length of an `IDENTITY_LOOKUP` request for the ego called `name`, or `None` if the name is
+/// too long to send.
+fn lookup_message_len(name: &str) -> Option<u16> {
+  c_string_message_len(4, name)
+}
+
+/// Whether the ego name `suffix` is `name` or the end of it starting at a label.
+fn is_name_suffix(suffix: &str, name: &str) -> bool {
+  name == suffix || (name.ends_with(suffix) && name[..name.len() - suffix.len()].ends_with('.'))
+}
+
+/// Read the body of an `IDENTITY_RESULT_CODE` message. Returns `None` on success, otherwise the
+/// error message sent by the service.
+fn read_result_code(reader: &mut Cursor<Vec<u8>>) -> Result<Option<String>, io::Error> {
+  if try!(reader.read_u32::<BigEndian>()) == 0 {
+    return Ok(None);
+  }
+  let mut msg = Vec::new();
+  try!(reader.read_to_end(&mut msg));
+  Ok(Some(String::from_utf8_lossy(&msg[..]).trim_right_matches('\0').to_string()))
+}
+
+/// Read an `IDENTITY_UPDATE` message. Returns `None` for the end-of-list marker, otherwise the
+/// private key and name of the ego, with no name meaning the ego was deleted.
+fn read_update(reader: &mut Cursor<Vec<u8>>) -> Result<Option<(EcdsaPrivateKey, Option<String>)>, ()> {
+  let name_len = try!(reader.read_u16::<BigEndian>().map_err(|_| ()));
+  let eol = try!(reader.read_u16::<BigEndian>().map_err(|_| ()));
+  if eol != 0 {
+    return Ok(None);
+  }
+  let pk = try!(EcdsaPrivateKey::deserialize(reader).map_err(|_| ()));
+  if name_len == 0 {
+    return Ok(Some((pk, None)));
+  }
+  let name = try!(reader.read_c_string().map_err(|_| ()));
+  try!(service::check_end(reader).map_err(|_| ()));
+  Ok(Some((pk, Some(name))))
+}
+
+/// Apply an update read by `read_update` to `egos`. Returns the change it made, if any.
+fn apply_update(egos: &mut HashMap<HashCode, Ego>, update: Option<(EcdsaPrivateKey, Option<String>)>) -> Option<EgoChange> {
+  match update {
+    Some((pk, Some(name))) => {
+      let id = pk.get_public().hash();
+      let ego = Ego {
+        pk: pk,
+        name: Some(name),
+        id: id.clone(),
+      };
+      match egos.insert(id, ego.clone()) {
+        None => Some(EgoChange::Created(ego)),
+        Some(old) => match old.name {
+          Some(old_name) => match ego.name != Some(old_name.clone()) {
+            true  => Some(EgoChange::Renamed { ego: ego, old_name: old_name }),
+            false => None,
+          },
+          None => Some(EgoChange::Created(ego)),
+        },
+      }
+    },
+    Some((pk, None)) => egos.remove(&pk.get_public().hash()).map(EgoChange::Deleted),
+    None => None,
+  }
+}
+
 /// Errors returned by `IdentityService::get_default_ego`
 error_def! GetDefaultEgoError {
   NameTooLong { name: String }
@@ -113,6 +328,114 @@ error_def! GetDefaultEgoError {
 }
 byteorder_error_chain! {GetDefaultEgoError}
 
+/// Errors returned by `IdentityService::set_default_ego`
+error_def! SetDefaultEgoError {
+  NameTooLong { name: String }
+    => "The name of the service was too long" ("\"{}\" is too long to be the name of a service.", name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the identity service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  ServiceResponse { response: String }
+    => "The service responded with an error message" ("Error: \"{}\"", response),
+  InvalidResponse
+    => "The service response was incoherent. You should file a bug-report if you encounter this error.",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {SetDefaultEgoError}
+
+/// Errors returned by `IdentityService::get_ego`
+error_def! GetEgoError {
+  NameTooLong { name: String }
+    => "The name of the ego was too long" ("\"{}\" is too long to be the name of an ego.", name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the identity service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  InvalidResponse
+    => "The service response was incoherent. You should file a bug-report if you encounter this error.",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {GetEgoError}
+
+impl Transient for GetEgoError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      GetEgoError::ReadMessage { ref cause }  => cause.is_transient(),
+      GetEgoError::Io { .. }                  => true,
+      GetEgoError::Disconnected               => true,
+      _                                       => false,
+    }
+  }
+}
+
+/// Errors returned by `IdentityService::create_ego`
+error_def! CreateEgoError {
+  NameTooLong { name: String }
+    => "The name of the ego was too long" ("\"{}\" is too long to be the name of an ego.", name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the identity service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  ServiceResponse { response: String }
+    => "The service responded with an error message" ("Error: \"{}\"", response),
+  InvalidResponse
+    => "The service response was incoherent. You should file a bug-report if you encounter this error.",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {CreateEgoError}
+
+/// Errors returned by `IdentityService::rename_ego`
+error_def! RenameEgoError {
+  NameTooLong
+    => "The names of the egos were too long to send to the identity service",
+  NameTaken { name: String }
+    => "An ego with the new name already exists" ("There is already an ego called \"{}\".", name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the identity service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  ServiceResponse { response: String }
+    => "The service responded with an error message" ("Error: \"{}\"", response),
+  InvalidResponse
+    => "The service response was incoherent. You should file a bug-report if you encounter this error.",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {RenameEgoError}
+
+/// Errors returned by `IdentityService::delete_ego`
+error_def! DeleteEgoError {
+  NameTooLong { name: String }
+    => "The name of the ego was too long" ("\"{}\" is too long to be the name of an ego.", name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the identity service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the server" ("Specifically: {}", cause),
+  ServiceResponse { response: String }
+    => "The service responded with an error message" ("Error: \"{}\"", response),
+  InvalidResponse
+    => "The service response was incoherent. You should file a bug-report if you encounter this error.",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {DeleteEgoError}
+
+impl Transient for GetDefaultEgoError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      GetDefaultEgoError::Connect { ref cause }     => cause.is_transient(),
+      GetDefaultEgoError::ReadMessage { ref cause } => cause.is_transient(),
+      GetDefaultEgoError::Io { .. }                 => true,
+      GetDefaultEgoError::Disconnected              => true,
+      _                                             => false,
+    }
+  }
+}
+
 impl IdentityService {
   /// Connect to the identity service.
   ///
@@ -165,13 +488,92 @@ impl IdentityService {
         _ => return Err(ConnectError::UnexpectedMessageType { ty: tpe }),
       };
     };
+
+    // The callback loop keeps its own copy of the egos to tell what each update changed, since
+    // the requests made through the handle update the shared copy themselves.
+    let mut known = egos.clone();
+    let egos = Arc::new(Mutex::new(egos));
+    let watchers: Arc<Mutex<Vec<Sender<EgoChange>>>> = Arc::new(Mutex::new(Vec::new()));
+    let lookup_pending = Arc::new(AtomicBool::new(false));
+    let (reply_tx, reply_rx) = channel();
+    let cb_egos = egos.clone();
+    let cb_watchers = watchers.clone();
+    let cb_lookup_pending = lookup_pending.clone();
+    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
+      if tpe == ll::GNUNET_MESSAGE_TYPE_IDENTITY_UPDATE {
+        let update = match read_update(&mut reader) {
+          Ok(u)   => u,
+          Err(()) => return ProcessMessageResult::Reconnect,
+        };
+        let _ = apply_update(&mut cb_egos.lock().unwrap(), update.clone());
+        if let Some(change) = apply_update(&mut known, update) {
+          cb_watchers.lock().unwrap().retain(|w| w.send(change.clone()).is_ok());
+        }
+        // The reply to a lookup is an update, so it is passed on while one is waiting.
+        if !cb_lookup_pending.load(Ordering::SeqCst) {
+          return ProcessMessageResult::Continue;
+        }
+        reader.set_position(0);
+      }
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_IDENTITY_UPDATE
+        | ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE
+        | ll::GNUNET_MESSAGE_TYPE_IDENTITY_SET_DEFAULT  => (),
+        _                                               => return ProcessMessageResult::Unhandled,
+      };
+      match reply_tx.send((tpe, reader)) {
+        Ok(())  => ProcessMessageResult::Continue,
+        Err(_)  => ProcessMessageResult::Shutdown,
+      }
+    }));
     Ok(IdentityService {
-      service_reader: service_reader,
       service_writer: service_writer,
+      callback_loop: callback_loop,
+      replies: reply_rx,
+      lookup_pending: lookup_pending,
       egos: egos,
+      watchers: watchers,
+      activity: ActivityLog::from_cfg(cfg),
     })
   }
 
+  /// Send a message the typed API doesn't cover to the identity service. See
+  /// `ServiceWriter::send_raw`.
+  pub fn send_raw(&mut self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    self.service_writer.send_raw(tpe, body)
+  }
+
+  /// The handler for messages from the identity service which this handle doesn't understand.
+  /// Without a handler such messages make the handle stop receiving.
+  pub fn catch_all(&self) -> CatchAll {
+    self.callback_loop.catch_all()
+  }
+
+  /// Receive the changes made to the egos from now on, whether through this handle or by other
+  /// clients of the service.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, IdentityService};
+  /// use gnunet::identity::EgoChange;
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let ids = IdentityService::connect(&config).unwrap();
+  /// for change in ids.updates() {
+  ///   match change {
+  ///     EgoChange::Created(ego)               => println!("created {}", ego),
+  ///     EgoChange::Renamed { ego, old_name }  => println!("renamed {} to {}", old_name, ego),
+  ///     EgoChange::Deleted(ego)               => println!("deleted {}", ego),
+  ///   }
+  /// }
+  /// ```
+  pub fn updates(&self) -> Receiver<EgoChange> {
+    let (tx, rx) = channel();
+    self.watchers.lock().unwrap().push(tx);
+    rx
+  }
+
   /// Get the default identity associated with a service.
   ///
   /// # Example
@@ -188,7 +590,7 @@ impl IdentityService {
   pub fn get_default_ego(&mut self, name: &str) -> Result<Ego, GetDefaultEgoError> {
     let name_len = name.len();
 
-    let msg_length = match (8 + name_len + 1).to_u16() {
+    let msg_length = match get_default_message_len(name) {
       Some(l) => l,
       None    => return Err(GetDefaultEgoError::NameTooLong { name: name.to_string() }),
     };
@@ -201,7 +603,7 @@ impl IdentityService {
       try!(mw.send());
     };
 
-    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    let (tpe, mut mr) = try!(self.read_reply());
     match tpe {
       ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE => {
         try!(mr.read_u32::<BigEndian>());
@@ -225,7 +627,7 @@ impl IdentityService {
               match &s[..] == name {
                 true  =>  {
                   let id = pk.get_public().hash();
-                  Ok(self.egos[&id].clone())
+                  Ok(self.egos.lock().unwrap()[&id].clone())
                 },
                 false => Err(GetDefaultEgoError::InvalidResponse),
               }
@@ -237,6 +639,313 @@ impl IdentityService {
       _ => Err(GetDefaultEgoError::InvalidResponse),
     }
   }
+
+  /// Get the ego called `name`, or `None` if there is no such ego.
+  ///
+  /// The egos sent by the service when the handle connected are searched first. If `name` isn't
+  /// among them the service is asked, in case the ego was created since.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, IdentityService};
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let mut ids = IdentityService::connect(&config).unwrap();
+  /// match ids.get_ego("alice").unwrap() {
+  ///   Some(ego) => println!("alice's zone is {}", ego.zone()),
+  ///   None      => println!("There is no ego called alice"),
+  /// }
+  /// ```
+  pub fn get_ego(&mut self, name: &str) -> Result<Option<Ego>, GetEgoError> {
+    if let Some(ego) = self.cached_ego(name) {
+      return Ok(Some(ego));
+    }
+    self.lookup_ego(ll::GNUNET_MESSAGE_TYPE_IDENTITY_LOOKUP, name, |ego_name| ego_name == name)
+  }
+
+  /// Get the ego whose name is the longest suffix of `name`, eg. the ego `alice` for the name
+  /// `www.alice`. This is how GNS picks the zone to start resolving a name in. Returns `None` if
+  /// no ego's name is a suffix of `name`.
+  ///
+  /// A suffix must start at a label, so `alice` is not a suffix of `www.malice`. The service is
+  /// always asked, since it knows about egos the handle may not have heard of yet.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, IdentityService};
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let mut ids = IdentityService::connect(&config).unwrap();
+  /// if let Some(ego) = ids.get_ego_by_suffix("www.alice").unwrap() {
+  ///   println!("www.alice is resolved starting in the zone of {}", ego);
+  /// }
+  /// ```
+  pub fn get_ego_by_suffix(&mut self, name: &str) -> Result<Option<Ego>, GetEgoError> {
+    self.lookup_ego(ll::GNUNET_MESSAGE_TYPE_IDENTITY_LOOKUP_BY_SUFFIX, name, |ego_name| is_name_suffix(ego_name, name))
+  }
+
+  /// Send a lookup request of type `tpe` for `name`. The service answers with an update about the
+  /// ego found, which must satisfy `accept`, or with an error if there is none.
+  fn lookup_ego<F>(&mut self, tpe: u16, name: &str, accept: F) -> Result<Option<Ego>, GetEgoError>
+      where F: Fn(&str) -> bool
+  {
+    let msg_length = match lookup_message_len(name) {
+      Some(l) => l,
+      None    => return Err(GetEgoError::NameTooLong { name: name.to_string() }),
+    };
+    self.lookup_pending.store(true, Ordering::SeqCst);
+    let res = self.lookup_ego_inner(tpe, name, msg_length, accept);
+    self.lookup_pending.store(false, Ordering::SeqCst);
+    res
+  }
+
+  fn lookup_ego_inner<F>(&mut self, tpe: u16, name: &str, msg_length: u16, accept: F) -> Result<Option<Ego>, GetEgoError>
+      where F: Fn(&str) -> bool
+  {
+    {
+      let mut mw = self.service_writer.write_message(msg_length, tpe);
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    // The ego comes back as an `IDENTITY_UPDATE`, which may be preceded by updates about other
+    // egos. The callback loop has applied each update before passing it on.
+    loop {
+      let (tpe, mut mr) = try!(self.recv_reply());
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_IDENTITY_UPDATE => match read_update(&mut mr) {
+          Ok(Some((pk, Some(ref ego_name)))) if accept(ego_name) => {
+            let id = pk.get_public().hash();
+            return Ok(Some(Ego {
+              pk: pk,
+              name: Some(ego_name.clone()),
+              id: id,
+            }));
+          },
+          Ok(_)   => (),
+          Err(()) => return Err(GetEgoError::InvalidResponse),
+        },
+        // The service answers with an error if it doesn't know the name.
+        ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE => return Ok(None),
+        _ => return Err(GetEgoError::InvalidResponse),
+      }
+    }
+  }
+
+  /// All the egos known to the handle, sorted by name.
+  pub fn egos(&self) -> Vec<Ego> {
+    let mut ret: Vec<Ego> = self.egos.lock().unwrap().values().cloned().collect();
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    ret
+  }
+
+  /// Borrow the egos known to the handle, eg. to list them the way `gnunet-identity -d` does.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, IdentityService};
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let ids = IdentityService::connect(&config).unwrap();
+  /// for ego in &ids.iter_egos() {
+  ///   println!("{}", ego);
+  /// }
+  /// ```
+  pub fn iter_egos(&self) -> Egos {
+    Egos {
+      egos: self.egos.lock().unwrap(),
+    }
+  }
+
+  /// Iterate over copies of the egos known to the handle, sorted by name. Unlike `iter_egos`
+  /// this doesn't hold up updates from the service while iterating.
+  pub fn iter_egos_owned(&self) -> ::std::vec::IntoIter<Ego> {
+    self.egos().into_iter()
+  }
+
+  fn cached_ego(&self, name: &str) -> Option<Ego> {
+    self.egos.lock().unwrap().values().find(|ego| ego.name.as_ref().map(|n| &n[..]) == Some(name)).cloned()
+  }
+
+  /// Make `ego` the default identity of the service `name`, eg. `"gns-master"` or `"fs-sks"`.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, IdentityService};
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let mut ids = IdentityService::connect(&config).unwrap();
+  /// let ego = ids.create_ego("my-zone").unwrap();
+  /// ids.set_default_ego("gns-master", &ego).unwrap();
+  /// ```
+  pub fn set_default_ego(&mut self, name: &str, ego: &Ego) -> Result<(), SetDefaultEgoError> {
+    let msg_length = match set_default_message_len(name) {
+      Some(l) => l,
+      None    => return Err(SetDefaultEgoError::NameTooLong { name: name.to_string() }),
+    };
+    {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_IDENTITY_SET_DEFAULT);
+      mw.write_u16::<BigEndian>((name.len() + 1) as u16).unwrap();
+      mw.write_u16::<BigEndian>(0).unwrap();
+      ego.pk.serialize(&mut mw).unwrap();
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    let (tpe, mut mr) = try!(self.read_reply());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE {
+      return Err(SetDefaultEgoError::InvalidResponse);
+    }
+    match try!(read_result_code(&mut mr)) {
+      Some(response)  => Err(SetDefaultEgoError::ServiceResponse { response: response }),
+      None            => Ok(()),
+    }
+  }
+
+  /// Create a new ego called `name` with a freshly generated key.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// use gnunet::{Cfg, IdentityService};
+  ///
+  /// let config = Cfg::default().unwrap();
+  /// let mut ids = IdentityService::connect(&config).unwrap();
+  /// let ego = ids.create_ego("alice").unwrap();
+  /// println!("Created {}", ego);
+  /// ```
+  pub fn create_ego(&mut self, name: &str) -> Result<Ego, CreateEgoError> {
+    let msg_length = match create_message_len(name) {
+      Some(l) => l,
+      None    => return Err(CreateEgoError::NameTooLong { name: name.to_string() }),
+    };
+    let pk = EcdsaPrivateKey::generate();
+    {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_IDENTITY_CREATE);
+      mw.write_u16::<BigEndian>((name.len() + 1) as u16).unwrap();
+      mw.write_u16::<BigEndian>(0).unwrap();
+      pk.serialize(&mut mw).unwrap();
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    let (tpe, mut mr) = try!(self.read_reply());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE {
+      return Err(CreateEgoError::InvalidResponse);
+    }
+    if let Some(response) = try!(read_result_code(&mut mr)) {
+      return Err(CreateEgoError::ServiceResponse { response: response });
+    }
+    let id = pk.get_public().hash();
+    let ego = Ego {
+      pk: pk,
+      name: Some(name.to_string()),
+      id: id.clone(),
+    };
+    self.egos.lock().unwrap().insert(id, ego.clone());
+    if let Some(ref log) = self.activity {
+      let _ = log.log(&Activity::EgoCreated { name: name.to_string(), zone: ego.zone() });
+    }
+    Ok(ego)
+  }
+
+  /// Rename the ego called `old_name` to `new_name`.
+  ///
+  /// Fails with `NameTaken` if an ego called `new_name` already exists.
+  pub fn rename_ego(&mut self, old_name: &str, new_name: &str) -> Result<(), RenameEgoError> {
+    let msg_length = match rename_message_len(old_name, new_name) {
+      Some(l) => l,
+      None    => return Err(RenameEgoError::NameTooLong),
+    };
+    if self.egos.lock().unwrap().values().any(|ego| ego.name.as_ref().map(|n| &n[..]) == Some(new_name)) {
+      return Err(RenameEgoError::NameTaken { name: new_name.to_string() });
+    }
+    {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_IDENTITY_RENAME);
+      mw.write_u16::<BigEndian>((old_name.len() + 1) as u16).unwrap();
+      mw.write_u16::<BigEndian>((new_name.len() + 1) as u16).unwrap();
+      mw.write_all(old_name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      mw.write_all(new_name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    let (tpe, mut mr) = try!(self.read_reply());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE {
+      return Err(RenameEgoError::InvalidResponse);
+    }
+    if let Some(response) = try!(read_result_code(&mut mr)) {
+      return Err(RenameEgoError::ServiceResponse { response: response });
+    }
+    for ego in self.egos.lock().unwrap().values_mut() {
+      if ego.name.as_ref().map(|n| &n[..]) == Some(old_name) {
+        ego.name = Some(new_name.to_string());
+      }
+    }
+    Ok(())
+  }
+
+  /// Delete the ego called `name`.
+  pub fn delete_ego(&mut self, name: &str) -> Result<(), DeleteEgoError> {
+    let msg_length = match delete_message_len(name) {
+      Some(l) => l,
+      None    => return Err(DeleteEgoError::NameTooLong { name: name.to_string() }),
+    };
+    {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_IDENTITY_DELETE);
+      mw.write_u16::<BigEndian>((name.len() + 1) as u16).unwrap();
+      mw.write_u16::<BigEndian>(0).unwrap();
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    let (tpe, mut mr) = try!(self.read_reply());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE {
+      return Err(DeleteEgoError::InvalidResponse);
+    }
+    if let Some(response) = try!(read_result_code(&mut mr)) {
+      return Err(DeleteEgoError::ServiceResponse { response: response });
+    }
+    // The update the service sends about the deletion may not arrive until the next request, so
+    // don't wait for it.
+    let mut egos = self.egos.lock().unwrap();
+    let deleted: Vec<HashCode> = egos.iter()
+                                     .filter(|&(_, ego)| ego.name.as_ref().map(|n| &n[..]) == Some(name))
+                                     .map(|(id, _)| id.clone())
+                                     .collect();
+    for id in deleted {
+      egos.remove(&id);
+    }
+    Ok(())
+  }
+
+  /// Read the reply to a request. `IDENTITY_UPDATE` messages passed on by the callback loop
+  /// while a lookup was waiting may come first, they are skipped.
+  fn read_reply(&mut self) -> Result<(u16, Cursor<Vec<u8>>), service::ReadMessageError> {
+    loop {
+      let (tpe, mr) = try!(self.recv_reply());
+      if tpe != ll::GNUNET_MESSAGE_TYPE_IDENTITY_UPDATE {
+        return Ok((tpe, mr));
+      }
+    }
+  }
+
+  /// Receive the next message passed on by the callback loop.
+  fn recv_reply(&mut self) -> Result<(u16, Cursor<Vec<u8>>), service::ReadMessageError> {
+    match self.replies.recv() {
+      Ok(x)   => Ok(x),
+      Err(_)  => Err(service::ReadMessageError::Disconnected),
+    }
+  }
 }
 
 /// Errors returned by `identity::get_default_ego`
@@ -273,3 +982,122 @@ pub fn get_default_ego(
   Ok(ret)
 }
 
+impl Transient for ConnectGetDefaultEgoError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      ConnectGetDefaultEgoError::GetDefaultEgo { ref cause }  => cause.is_transient(),
+      ConnectGetDefaultEgoError::Connect { ref cause }        => cause.is_transient(),
+    }
+  }
+}
+
+/// Like `get_default_ego` but retries according to `policy` if the identity service can't be
+/// reached, eg. because it is being restarted.
+pub fn get_default_ego_with_retry(
+    cfg: &Cfg,
+    name: &str,
+    policy: &RetryPolicy) -> Result<Ego, ConnectGetDefaultEgoError> {
+  policy.run(|| get_default_ego(cfg, name))
+}
+
+impl Component for IdentityService {
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    Some(&mut self.callback_loop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::iter;
+  use std::io::Cursor;
+  use std::sync::Mutex;
+  use EcdsaPrivateKey;
+  use super::*;
+  use super::{get_default_message_len, set_default_message_len, create_message_len,
+              delete_message_len, rename_message_len, lookup_message_len, read_result_code,
+              apply_update, is_name_suffix};
+
+  fn name(len: usize) -> String {
+    iter::repeat('a').take(len).collect()
+  }
+
+  #[test]
+  fn test_name_length_boundaries() {
+    // The longest names which fit, and the shortest which don't.
+    let longest_service = 0xffff - 8 - 1;
+    assert_eq!(get_default_message_len(&name(longest_service)), Some(0xffff));
+    assert_eq!(get_default_message_len(&name(longest_service + 1)), None);
+    let longest_ego = 0xffff - 40 - 1;
+    assert_eq!(create_message_len(&name(longest_ego)), Some(0xffff));
+    assert_eq!(create_message_len(&name(longest_ego + 1)), None);
+
+    for len in 0..300 {
+      assert_eq!(get_default_message_len(&name(len)), Some((8 + len + 1) as u16));
+      assert_eq!(create_message_len(&name(len)), Some((40 + len + 1) as u16));
+      assert_eq!(set_default_message_len(&name(len)), Some((40 + len + 1) as u16));
+      assert_eq!(delete_message_len(&name(len)), Some((8 + len + 1) as u16));
+      assert_eq!(lookup_message_len(&name(len)), Some((4 + len + 1) as u16));
+      assert_eq!(rename_message_len(&name(len), "b"), Some((8 + len + 1 + 2) as u16));
+    }
+    // Lengths are in bytes, not characters.
+    let wide: String = iter::repeat('\u{00e9}').take(longest_service / 2 + 1).collect();
+    assert_eq!(get_default_message_len(&wide), None);
+  }
+
+  #[test]
+  fn test_read_result_code() {
+    let mut ok = Cursor::new(vec![0, 0, 0, 0]);
+    assert_eq!(read_result_code(&mut ok).unwrap(), None);
+    let mut err = Cursor::new(b"\x00\x00\x00\x01no such ego\x00".to_vec());
+    assert_eq!(read_result_code(&mut err).unwrap(), Some("no such ego".to_string()));
+  }
+
+  #[test]
+  fn test_apply_update() {
+    let pk = EcdsaPrivateKey::generate();
+    let mut egos = HashMap::new();
+    match apply_update(&mut egos, Some((pk.clone(), Some("alice".to_string())))) {
+      Some(EgoChange::Created(ref ego)) => assert_eq!(ego.get_name(), Some("alice".to_string())),
+      _ => panic!("expected the ego to be created"),
+    }
+    // Hearing about an ego again changes nothing.
+    assert!(apply_update(&mut egos, Some((pk.clone(), Some("alice".to_string())))).is_none());
+    match apply_update(&mut egos, Some((pk.clone(), Some("bob".to_string())))) {
+      Some(EgoChange::Renamed { ref ego, ref old_name }) => {
+        assert_eq!(ego.get_name(), Some("bob".to_string()));
+        assert_eq!(&old_name[..], "alice");
+      },
+      _ => panic!("expected the ego to be renamed"),
+    }
+    match apply_update(&mut egos, Some((pk.clone(), None))) {
+      Some(EgoChange::Deleted(ref ego)) => assert_eq!(ego.get_name(), Some("bob".to_string())),
+      _ => panic!("expected the ego to be deleted"),
+    }
+    assert!(egos.is_empty());
+    assert!(apply_update(&mut egos, None).is_none());
+  }
+
+  #[test]
+  fn test_egos_iter() {
+    let egos = Mutex::new(HashMap::new());
+    for name in ["alice", "bob"].iter() {
+      apply_update(&mut egos.lock().unwrap(), Some((EcdsaPrivateKey::generate(), Some(name.to_string()))));
+    }
+    let borrowed = Egos { egos: egos.lock().unwrap() };
+    assert_eq!(borrowed.len(), 2);
+    let mut names: Vec<String> = borrowed.iter().filter_map(|ego| ego.get_name()).collect();
+    names.sort();
+    assert_eq!(names, vec!["alice".to_string(), "bob".to_string()]);
+    assert_eq!((&borrowed).into_iter().count(), 2);
+  }
+
+  #[test]
+  fn test_is_name_suffix() {
+    assert!(is_name_suffix("alice", "alice"));
+    assert!(is_name_suffix("alice", "www.alice"));
+    assert!(is_name_suffix("b.alice", "a.b.alice"));
+    assert!(!is_name_suffix("alice", "www.malice"));
+    assert!(!is_name_suffix("www.alice", "alice"));
+  }
+}
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Cursor, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use byteorder::{BigEndian, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use HashCode;
+use activity::{Activity, ActivityLog};
+use service::{self, CatchAll, ServiceReadLoop, ServiceWriter, ProcessMessageResult};
+use shutdown::{Component, ReadLoop};
+use identity::{Ego, ConnectError, get_default_message_len, create_message_len, read_update, read_result_code};
+
+/// A change to the egos known to the identity service, delivered to watchers of an
+/// `AsyncIdentityService`.
+#[derive(Clone)]
+pub enum EgoEvent {
+  /// An ego was created or renamed.
+  Updated(Ego),
+  /// The ego with this id was deleted.
+  Deleted(HashCode),
+}
+
+/// Errors returned by requests made through an `AsyncIdentityService`.
+error_def! AsyncRequestError {
+  NameTooLong { name: String }
+    => "The name was too long" ("\"{}\" is too long to be sent to the identity service.", name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the identity service" ("Specifically: {}", cause),
+  ServiceResponse { response: String }
+    => "The service responded with an error message" ("Error: \"{}\"", response),
+  InvalidResponse
+    => "The service response was incoherent. You should file a bug-report if you encounter this error.",
+  Disconnected
+    => "The service disconnected before responding",
+}
+
+/// The pending response to a request made through an `AsyncIdentityService`.
+pub struct AsyncResponse<T> {
+  receiver: Receiver<Result<T, AsyncRequestError>>,
+}
+
+impl<T> AsyncResponse<T> {
+  /// Block until the response arrives.
+  pub fn wait(self) -> Result<T, AsyncRequestError> {
+    match self.receiver.recv() {
+      Ok(r)   => r,
+      Err(_)  => Err(AsyncRequestError::Disconnected),
+    }
+  }
+
+  /// Get the response if it has arrived, without blocking.
+  pub fn poll(&mut self) -> Option<Result<T, AsyncRequestError>> {
+    match self.receiver.try_recv() {
+      Ok(r)                           => Some(r),
+      Err(TryRecvError::Empty)        => None,
+      Err(TryRecvError::Disconnected) => Some(Err(AsyncRequestError::Disconnected)),
+    }
+  }
+}
+
+/// Requests registered with the callback loop.
+enum Registration {
+  GetDefault(String, Sender<Result<Ego, AsyncRequestError>>),
+  Create(String, EcdsaPublicKey, Sender<Result<(), AsyncRequestError>>),
+  Watch(Sender<EgoEvent>),
+  /// The last `GetDefault` or `Create` request could not be sent, so no response will arrive for
+  /// it.
+  Abandon,
+}
+
+/// Requests waiting for a response. The identity service answers requests in the order they were
+/// sent.
+enum Pending {
+  GetDefault(String, Sender<Result<Ego, AsyncRequestError>>),
+  /// The name and zone of the ego being created, for the audit log.
+  Create(String, EcdsaPublicKey, Sender<Result<(), AsyncRequestError>>),
+}
+
+struct Outgoing {
+  service_writer: ServiceWriter,
+  registration_tx: Sender<Registration>,
+}
+
+/// A handle to the identity service which can have many requests in flight at once.
+///
+/// Unlike `IdentityService`, requests return immediately with an `AsyncResponse` and responses
+/// are read by a background thread, so a slow request doesn't block the handle. The handle can be
+/// shared between threads. The set of egos is kept up to date as the service reports changes, and
+/// those changes can be watched with `watch`.
+pub struct AsyncIdentityService {
+  outgoing: Mutex<Outgoing>,
+  egos: Arc<Mutex<HashMap<HashCode, Ego>>>,
+  callback_loop: ServiceReadLoop,
+}
+
+/// Read an `IDENTITY_SET_DEFAULT` message sent in response to a request for the default ego of
+/// `service`.
+fn read_default(reader: &mut Cursor<Vec<u8>>, service: &str, egos: &HashMap<HashCode, Ego>) -> Result<Ego, AsyncRequestError> {
+  match read_update(reader) {
+    Ok(Some((pk, Some(ref name)))) if &name[..] == service => {
+      let id = pk.get_public().hash();
+      Ok(match egos.get(&id) {
+        Some(ego) => ego.clone(),
+        None      => Ego {
+          pk: pk,
+          name: None,
+          id: id,
+        },
+      })
+    },
+    _ => Err(AsyncRequestError::InvalidResponse),
+  }
+}
+
+impl AsyncIdentityService {
+  /// Connect to the identity service.
+  ///
+  /// Blocks until the service has sent the current list of egos.
+  pub fn connect(cfg: &Cfg) -> Result<AsyncIdentityService, ConnectError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "identity"));
+    let (registration_tx, registration_rx) = channel::<Registration>();
+    let (synced_tx, synced_rx) = channel::<()>();
+    let egos = Arc::new(Mutex::new(HashMap::new()));
+    let cb_egos = egos.clone();
+    let mut synced_tx = Some(synced_tx);
+    let mut pending: VecDeque<Pending> = VecDeque::new();
+    let mut watchers: Vec<Sender<EgoEvent>> = Vec::new();
+    let activity = ActivityLog::from_cfg(cfg);
+
+    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
+      loop {
+        match registration_rx.try_recv() {
+          Ok(Registration::GetDefault(s, tx)) => pending.push_back(Pending::GetDefault(s, tx)),
+          Ok(Registration::Create(n, z, tx))  => pending.push_back(Pending::Create(n, z, tx)),
+          Ok(Registration::Watch(tx))         => watchers.push(tx),
+          Ok(Registration::Abandon)           => {
+            pending.pop_back();
+          },
+          Err(TryRecvError::Empty)            => break,
+          Err(TryRecvError::Disconnected)     => return ProcessMessageResult::Shutdown,
+        }
+      };
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_IDENTITY_UPDATE => {
+          let event = match read_update(&mut reader) {
+            Ok(None) => {
+              if let Some(tx) = synced_tx.take() {
+                let _ = tx.send(());
+              }
+              return ProcessMessageResult::Continue;
+            },
+            Ok(Some((pk, Some(name)))) => {
+              let id = pk.get_public().hash();
+              let ego = Ego {
+                pk: pk,
+                name: Some(name),
+                id: id.clone(),
+              };
+              cb_egos.lock().unwrap().insert(id, ego.clone());
+              EgoEvent::Updated(ego)
+            },
+            Ok(Some((pk, None))) => {
+              let id = pk.get_public().hash();
+              cb_egos.lock().unwrap().remove(&id);
+              EgoEvent::Deleted(id)
+            },
+            Err(()) => return ProcessMessageResult::Reconnect,
+          };
+          watchers.retain(|w| w.send(event.clone()).is_ok());
+        },
+        ll::GNUNET_MESSAGE_TYPE_IDENTITY_SET_DEFAULT => match pending.pop_front() {
+          Some(Pending::GetDefault(service, tx)) => {
+            let res = read_default(&mut reader, &service[..], &cb_egos.lock().unwrap());
+            let _ = tx.send(res);
+          },
+          _ => return ProcessMessageResult::Reconnect,
+        },
+        ll::GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE => match pending.pop_front() {
+          Some(Pending::GetDefault(_, tx)) => {
+            let res = match read_result_code(&mut reader).map_err(|_| AsyncRequestError::InvalidResponse) {
+              Ok(Some(msg)) => Err(AsyncRequestError::ServiceResponse { response: msg }),
+              Ok(None)      => Err(AsyncRequestError::InvalidResponse),
+              Err(e)        => Err(e),
+            };
+            let _ = tx.send(res);
+          },
+          Some(Pending::Create(name, zone, tx)) => {
+            let res = match read_result_code(&mut reader).map_err(|_| AsyncRequestError::InvalidResponse) {
+              Ok(Some(msg)) => Err(AsyncRequestError::ServiceResponse { response: msg }),
+              Ok(None)      => Ok(()),
+              Err(e)        => Err(e),
+            };
+            if let (&Ok(()), &Some(ref log)) = (&res, &activity) {
+              let _ = log.log(&Activity::EgoCreated { name: name, zone: zone });
+            }
+            let _ = tx.send(res);
+          },
+          None => return ProcessMessageResult::Reconnect,
+        },
+        _ => return ProcessMessageResult::Unhandled,
+      };
+      ProcessMessageResult::Continue
+    }));
+
+    {
+      let mw = service_writer.write_message(4, ll::GNUNET_MESSAGE_TYPE_IDENTITY_START);
+      try!(mw.send());
+    };
+    if synced_rx.recv().is_err() {
+      return Err(ConnectError::Disconnected);
+    }
+
+    Ok(AsyncIdentityService {
+      outgoing: Mutex::new(Outgoing {
+        service_writer: service_writer,
+        registration_tx: registration_tx,
+      }),
+      egos: egos,
+      callback_loop: callback_loop,
+    })
+  }
+
+  /// Request the default ego of the service `name`, eg. `"gns-master"`.
+  pub fn get_default_ego(&self, name: &str) -> Result<AsyncResponse<Ego>, AsyncRequestError> {
+    let name_len = name.len();
+    let msg_length = match get_default_message_len(name) {
+      Some(l) => l,
+      None    => return Err(AsyncRequestError::NameTooLong { name: name.to_string() }),
+    };
+    let (tx, rx) = channel();
+    let mut outgoing = self.outgoing.lock().unwrap();
+    // Register before sending so the callback loop knows about the request before the response
+    // arrives. Holding the lock keeps registrations in the same order as the requests.
+    // This fails once the callback loop has exited, eg. because the service restarted.
+    if outgoing.registration_tx.send(Registration::GetDefault(name.to_string(), tx)).is_err() {
+      return Err(AsyncRequestError::Disconnected);
+    }
+    let sent = {
+      let mut mw = outgoing.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_IDENTITY_GET_DEFAULT);
+      mw.write_u16::<BigEndian>((name_len + 1) as u16).unwrap();
+      mw.write_u16::<BigEndian>(0).unwrap();
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      mw.send()
+    };
+    if let Err(e) = sent {
+      // The lock is still held, so the request is the last one registered.
+      let _ = outgoing.registration_tx.send(Registration::Abandon);
+      return Err(From::from(e));
+    }
+    Ok(AsyncResponse {
+      receiver: rx,
+    })
+  }
+
+  /// Request the creation of an ego named `name` with the private key `key`.
+  ///
+  /// If the service creates the ego, it is logged to the audit log configured when connecting.
+  pub fn create_ego(&self, name: &str, key: &EcdsaPrivateKey) -> Result<AsyncResponse<()>, AsyncRequestError> {
+    let name_len = name.len();
+    let msg_length = match create_message_len(name) {
+      Some(l) => l,
+      None    => return Err(AsyncRequestError::NameTooLong { name: name.to_string() }),
+    };
+    let (tx, rx) = channel();
+    let mut outgoing = self.outgoing.lock().unwrap();
+    if outgoing.registration_tx.send(Registration::Create(name.to_string(), key.get_public(), tx)).is_err() {
+      return Err(AsyncRequestError::Disconnected);
+    }
+    let sent = {
+      let mut mw = outgoing.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_IDENTITY_CREATE);
+      mw.write_u16::<BigEndian>((name_len + 1) as u16).unwrap();
+      mw.write_u16::<BigEndian>(0).unwrap();
+      key.serialize(&mut mw).unwrap();
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      mw.send()
+    };
+    if let Err(e) = sent {
+      // The lock is still held, so the request is the last one registered.
+      let _ = outgoing.registration_tx.send(Registration::Abandon);
+      return Err(From::from(e));
+    }
+    Ok(AsyncResponse {
+      receiver: rx,
+    })
+  }
+
+  /// Send a message the typed API doesn't cover to the identity service. See
+  /// `ServiceWriter::send_raw`.
+  pub fn send_raw(&self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    self.outgoing.lock().unwrap().service_writer.send_raw(tpe, body)
+  }
+
+  /// The handler for messages from the identity service which this handle doesn't understand.
+  /// Without a handler such messages make the handle stop receiving.
+  pub fn catch_all(&self) -> CatchAll {
+    self.callback_loop.catch_all()
+  }
+
+  /// Find an ego by name among the egos currently known to the service.
+  pub fn lookup(&self, name: &str) -> Option<Ego> {
+    self.egos.lock().unwrap()
+             .values()
+             .find(|ego| ego.name.as_ref().map(|n| &n[..]) == Some(name))
+             .cloned()
+  }
+
+  /// All the egos currently known to the service.
+  pub fn egos(&self) -> Vec<Ego> {
+    self.egos.lock().unwrap().values().cloned().collect()
+  }
+
+  /// Receive the changes made to the egos from now on.
+  ///
+  /// If the callback loop has exited, eg. because the service restarted, the receiver is already
+  /// disconnected.
+  pub fn watch(&self) -> Receiver<EgoEvent> {
+    let (tx, rx) = channel();
+    let outgoing = self.outgoing.lock().unwrap();
+    // On failure the sender is dropped along with the registration, which disconnects `rx`.
+    let _ = outgoing.registration_tx.send(Registration::Watch(tx));
+    rx
+  }
+}
+
+impl Component for AsyncIdentityService {
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    Some(&mut self.callback_loop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use fixtures;
+  use ll;
+  use service;
+  use identity::read_update;
+
+  #[test]
+  fn test_update_fixtures() {
+    let (tpe, mut mr) = service::split_frame(fixtures::IDENTITY_UPDATE.to_vec());
+    assert_eq!(tpe, ll::GNUNET_MESSAGE_TYPE_IDENTITY_UPDATE);
+    let (pk, name) = read_update(&mut mr).unwrap().unwrap();
+    let mut key = Vec::new();
+    pk.serialize(&mut key).unwrap();
+    assert_eq!(&key[..], &fixtures::IDENTITY_KEY[..]);
+    assert_eq!(name, Some("gns-master".to_string()));
+
+    let (_, mut mr) = service::split_frame(fixtures::IDENTITY_UPDATE_END.to_vec());
+    assert!(read_update(&mut mr).unwrap().is_none());
+  }
+}
@@ -32,16 +32,23 @@ extern crate byteorder;
 extern crate crypto as rcrypto;
 extern crate num;
 extern crate regex;
+extern crate flate2;
+extern crate rustc_serialize;
+#[cfg(feature = "web")]
+extern crate iron;
+#[cfg(feature = "async")]
+extern crate futures;
 
 pub use configuration::Cfg;
-pub use crypto::{EcdsaPublicKey, EcdsaPrivateKey, HashCode};
+pub use crypto::{EcdsaPublicKey, EcdsaPrivateKey, EcdsaSignature, EddsaPrivateKey, HashCode};
 
 pub use gns::{Record, RecordType};
 pub use gns::{GNS, LocalOptions};
 pub use identity::{Ego, IdentityService};
 pub use hello::Hello;
 pub use peerinfo::{iterate_peers, self_id, PeerIdentity};
-//pub use dht::DHT;
+pub use namestore::Namestore;
+pub use dht::DHT;
 
 /*
 macro_rules! error_chain {
@@ -115,13 +122,41 @@ pub mod configuration;
 pub mod time;
 pub mod paths;
 pub mod gns;
-//pub mod dht;
+pub mod dht;
 mod crypto;
 pub mod identity;
 mod util;
 pub mod peerinfo;
 pub mod hello;
-//pub mod cadet;
+pub mod cadet;
 pub mod data;
 pub mod transport;
+pub mod namestore;
+pub mod block;
+pub mod rpc;
+pub mod diagnostics;
+pub mod fs;
+pub mod datastore;
+pub mod arm;
+pub mod nse;
+pub mod statistics;
+pub mod core;
+pub mod ats;
+pub mod reclaim;
+pub mod conversation;
+pub mod messenger;
+pub mod abd;
+pub mod set;
+pub mod revocation;
+pub mod vpn;
+pub mod nat;
+pub mod fingerprint;
+pub mod testbed;
+pub mod activity;
+pub mod capabilities;
+pub mod republish;
+pub mod cache;
+pub mod shutdown;
+#[cfg(test)]
+mod fixtures;
 
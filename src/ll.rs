@@ -10867,10 +10867,61 @@ extern "C" {
 
 pub const GNUNET_NO: ::libc::c_int = 0;
 pub const GNUNET_OK: ::libc::c_int = 1;
+pub const GNUNET_MESSAGE_TYPE_ARM_START: u16 = 8;
+pub const GNUNET_MESSAGE_TYPE_ARM_STOP: u16 = 9;
+pub const GNUNET_MESSAGE_TYPE_ARM_RESULT: u16 = 10;
+pub const GNUNET_MESSAGE_TYPE_ARM_STATUS: u16 = 11;
+pub const GNUNET_MESSAGE_TYPE_ARM_MONITOR: u16 = 14;
 pub const GNUNET_MESSAGE_TYPE_HELLO: u16 = 17;
+pub const GNUNET_MESSAGE_TYPE_CORE_INIT: u16 = 64;
+pub const GNUNET_MESSAGE_TYPE_CORE_INIT_REPLY: u16 = 65;
+pub const GNUNET_MESSAGE_TYPE_CORE_NOTIFY_CONNECT: u16 = 67;
+pub const GNUNET_MESSAGE_TYPE_CORE_NOTIFY_DISCONNECT: u16 = 68;
+pub const GNUNET_MESSAGE_TYPE_CORE_NOTIFY_INBOUND: u16 = 70;
+pub const GNUNET_MESSAGE_TYPE_CORE_SEND_REQUEST: u16 = 74;
+pub const GNUNET_MESSAGE_TYPE_CORE_SEND_READY: u16 = 75;
+pub const GNUNET_MESSAGE_TYPE_CORE_SEND: u16 = 76;
+pub const GNUNET_MESSAGE_TYPE_CORE_MONITOR_PEERS: u16 = 78;
+pub const GNUNET_MESSAGE_TYPE_CORE_MONITOR_NOTIFY: u16 = 79;
+pub const GNUNET_MESSAGE_TYPE_DATASTORE_STATUS: u16 = 94;
+pub const GNUNET_MESSAGE_TYPE_DATASTORE_PUT: u16 = 95;
+pub const GNUNET_MESSAGE_TYPE_DATASTORE_REMOVE: u16 = 101;
+pub const GNUNET_MESSAGE_TYPE_FS_INDEX_START: u16 = 128;
+pub const GNUNET_MESSAGE_TYPE_FS_INDEX_START_OK: u16 = 129;
+pub const GNUNET_MESSAGE_TYPE_FS_INDEX_START_FAILED: u16 = 130;
+pub const GNUNET_MESSAGE_TYPE_FS_INDEX_LIST_GET: u16 = 131;
+pub const GNUNET_MESSAGE_TYPE_FS_INDEX_LIST_ENTRY: u16 = 132;
+pub const GNUNET_MESSAGE_TYPE_FS_INDEX_LIST_END: u16 = 133;
+pub const GNUNET_MESSAGE_TYPE_FS_UNINDEX: u16 = 134;
+pub const GNUNET_MESSAGE_TYPE_FS_UNINDEX_OK: u16 = 135;
+pub const GNUNET_MESSAGE_TYPE_FS_START_SEARCH: u16 = 136;
+pub const GNUNET_MESSAGE_TYPE_FS_PUT: u16 = 137;
+pub const GNUNET_MESSAGE_TYPE_DHT_CLIENT_PUT: u16 = 142;
+pub const GNUNET_MESSAGE_TYPE_DHT_CLIENT_GET: u16 = 143;
+pub const GNUNET_MESSAGE_TYPE_DHT_CLIENT_GET_STOP: u16 = 144;
+pub const GNUNET_MESSAGE_TYPE_DHT_CLIENT_RESULT: u16 = 145;
+pub const GNUNET_MESSAGE_TYPE_DHT_MONITOR_GET: u16 = 149;
+pub const GNUNET_MESSAGE_TYPE_DHT_MONITOR_GET_RESP: u16 = 150;
+pub const GNUNET_MESSAGE_TYPE_DHT_MONITOR_PUT: u16 = 151;
+pub const GNUNET_MESSAGE_TYPE_DHT_MONITOR_START: u16 = 153;
+pub const GNUNET_MESSAGE_TYPE_DHT_CLIENT_PUT_OK: u16 = 155;
+pub const GNUNET_MESSAGE_TYPE_STATISTICS_SET: u16 = 168;
+pub const GNUNET_MESSAGE_TYPE_STATISTICS_GET: u16 = 169;
+pub const GNUNET_MESSAGE_TYPE_STATISTICS_VALUE: u16 = 170;
+pub const GNUNET_MESSAGE_TYPE_STATISTICS_END: u16 = 171;
+pub const GNUNET_MESSAGE_TYPE_VPN_CLIENT_REDIRECT_TO_IP: u16 = 186;
+pub const GNUNET_MESSAGE_TYPE_VPN_CLIENT_REDIRECT_TO_SERVICE: u16 = 187;
+pub const GNUNET_MESSAGE_TYPE_VPN_CLIENT_USE_IP: u16 = 188;
+pub const GNUNET_MESSAGE_TYPE_NSE_START: u16 = 321;
+pub const GNUNET_MESSAGE_TYPE_NSE_ESTIMATE: u16 = 322;
+pub const GNUNET_MESSAGE_TYPE_PEERINFO_GET: u16 = 330;
 pub const GNUNET_MESSAGE_TYPE_PEERINFO_GET_ALL: u16 = 331;
 pub const GNUNET_MESSAGE_TYPE_PEERINFO_INFO: u16 = 332;
 pub const GNUNET_MESSAGE_TYPE_PEERINFO_INFO_END: u16 = 333;
+pub const GNUNET_MESSAGE_TYPE_ATS_START: u16 = 340;
+pub const GNUNET_MESSAGE_TYPE_ATS_PEER_INFORMATION: u16 = 347;
+pub const GNUNET_MESSAGE_TYPE_ATS_ADDRESSLIST_REQUEST: u16 = 354;
+pub const GNUNET_MESSAGE_TYPE_ATS_ADDRESSLIST_RESPONSE: u16 = 355;
 pub const GNUNET_MESSAGE_TYPE_GNS_LOOKUP: u16 = 500;
 pub const GNUNET_MESSAGE_TYPE_GNS_LOOKUP_RESULT: u16 = 501;
 pub const GNUNET_MESSAGE_TYPE_IDENTITY_START: u16 = 624;
@@ -10878,10 +10929,115 @@ pub const GNUNET_MESSAGE_TYPE_IDENTITY_RESULT_CODE: u16 = 625;
 pub const GNUNET_MESSAGE_TYPE_IDENTITY_UPDATE: u16 = 626;
 pub const GNUNET_MESSAGE_TYPE_IDENTITY_GET_DEFAULT: u16 = 627;
 pub const GNUNET_MESSAGE_TYPE_IDENTITY_SET_DEFAULT: u16 = 628;
+pub const GNUNET_MESSAGE_TYPE_IDENTITY_CREATE: u16 = 629;
+pub const GNUNET_MESSAGE_TYPE_IDENTITY_RENAME: u16 = 630;
+pub const GNUNET_MESSAGE_TYPE_IDENTITY_DELETE: u16 = 631;
+pub const GNUNET_MESSAGE_TYPE_IDENTITY_LOOKUP: u16 = 632;
+pub const GNUNET_MESSAGE_TYPE_IDENTITY_LOOKUP_BY_SUFFIX: u16 = 633;
 pub const GNUNET_MESSAGE_TYPE_CADET_LOCAL_CONNECT: u16 = 272;
 pub const GNUNET_MESSAGE_TYPE_CADET_LOCAL_CHANNEL_CREATE: u16 = 273;
+pub const GNUNET_MESSAGE_TYPE_CADET_LOCAL_CHANNEL_DESTROY: u16 = 274;
+pub const GNUNET_MESSAGE_TYPE_CADET_LOCAL_DATA: u16 = 285;
+pub const GNUNET_MESSAGE_TYPE_CADET_LOCAL_ACK: u16 = 286;
 pub const GNUNET_MESSAGE_TYPE_TRANSPORT_START: u16 = 360;
+pub const GNUNET_MESSAGE_TYPE_TRANSPORT_CONNECT: u16 = 361;
+pub const GNUNET_MESSAGE_TYPE_TRANSPORT_BLACKLIST_INIT: u16 = 368;
+pub const GNUNET_MESSAGE_TYPE_TRANSPORT_BLACKLIST_QUERY: u16 = 369;
+pub const GNUNET_MESSAGE_TYPE_TRANSPORT_BLACKLIST_REPLY: u16 = 370;
+pub const GNUNET_MESSAGE_TYPE_TRANSPORT_REQUEST_CONNECT: u16 = 374;
+pub const GNUNET_MESSAGE_TYPE_TRANSPORT_MONITOR_VALIDATION_REQUEST: u16 = 381;
+pub const GNUNET_MESSAGE_TYPE_TRANSPORT_MONITOR_VALIDATION_RESPONSE: u16 = 383;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_STORE: u16 = 435;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_STORE_RESPONSE: u16 = 436;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_LOOKUP: u16 = 437;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_LOOKUP_RESPONSE: u16 = 438;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_MONITOR_START: u16 = 441;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_MONITOR_SYNC: u16 = 442;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_RESULT: u16 = 443;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_ZONE_ITERATION_START: u16 = 445;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_ZONE_ITERATION_NEXT: u16 = 447;
+pub const GNUNET_MESSAGE_TYPE_NAMESTORE_ZONE_ITERATION_STOP: u16 = 448;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_INIT: u16 = 460;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_CREATE_PEER: u16 = 464;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_START_PEER: u16 = 466;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_STOP_PEER: u16 = 467;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_DESTROY_PEER: u16 = 468;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_OVERLAY_CONNECT: u16 = 470;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_PEER_EVENT: u16 = 471;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_PEER_CONNECT_EVENT: u16 = 472;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_OPERATION_FAIL_EVENT: u16 = 473;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_CREATE_PEER_SUCCESS: u16 = 474;
+pub const GNUNET_MESSAGE_TYPE_TESTBED_GENERIC_OPERATION_SUCCESS: u16 = 475;
+pub const GNUNET_MESSAGE_TYPE_SETI_ACCEPT: u16 = 500;
+pub const GNUNET_MESSAGE_TYPE_SETI_ADD: u16 = 501;
+pub const GNUNET_MESSAGE_TYPE_SETI_CANCEL: u16 = 502;
+pub const GNUNET_MESSAGE_TYPE_SETI_CREATE: u16 = 503;
+pub const GNUNET_MESSAGE_TYPE_SETI_EVALUATE: u16 = 504;
+pub const GNUNET_MESSAGE_TYPE_SETI_LISTEN: u16 = 505;
+pub const GNUNET_MESSAGE_TYPE_SETI_REJECT: u16 = 506;
+pub const GNUNET_MESSAGE_TYPE_SETI_REQUEST: u16 = 507;
+pub const GNUNET_MESSAGE_TYPE_SETI_RESULT: u16 = 508;
+pub const GNUNET_MESSAGE_TYPE_SETU_ACCEPT: u16 = 550;
+pub const GNUNET_MESSAGE_TYPE_SETU_ADD: u16 = 551;
+pub const GNUNET_MESSAGE_TYPE_SETU_CANCEL: u16 = 552;
+pub const GNUNET_MESSAGE_TYPE_SETU_CREATE: u16 = 553;
+pub const GNUNET_MESSAGE_TYPE_SETU_EVALUATE: u16 = 554;
+pub const GNUNET_MESSAGE_TYPE_SETU_LISTEN: u16 = 555;
+pub const GNUNET_MESSAGE_TYPE_SETU_REJECT: u16 = 556;
+pub const GNUNET_MESSAGE_TYPE_SETU_REQUEST: u16 = 557;
+pub const GNUNET_MESSAGE_TYPE_SETU_RESULT: u16 = 558;
+pub const GNUNET_MESSAGE_TYPE_SET_REJECT: u16 = 569;
+pub const GNUNET_MESSAGE_TYPE_SET_CANCEL: u16 = 570;
+pub const GNUNET_MESSAGE_TYPE_SET_RESULT: u16 = 572;
+pub const GNUNET_MESSAGE_TYPE_SET_ADD: u16 = 573;
+pub const GNUNET_MESSAGE_TYPE_SET_LISTEN: u16 = 575;
+pub const GNUNET_MESSAGE_TYPE_SET_ACCEPT: u16 = 576;
+pub const GNUNET_MESSAGE_TYPE_SET_EVALUATE: u16 = 577;
+pub const GNUNET_MESSAGE_TYPE_SET_REQUEST: u16 = 579;
+pub const GNUNET_MESSAGE_TYPE_SET_CREATE: u16 = 580;
+pub const GNUNET_MESSAGE_TYPE_REVOCATION_QUERY: u16 = 636;
+pub const GNUNET_MESSAGE_TYPE_REVOCATION_QUERY_RESPONSE: u16 = 637;
+pub const GNUNET_MESSAGE_TYPE_REVOCATION_REVOKE: u16 = 638;
+pub const GNUNET_MESSAGE_TYPE_REVOCATION_REVOKE_RESPONSE: u16 = 639;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_REGISTER: u16 = 731;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_PICK_UP: u16 = 732;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_HANG_UP: u16 = 733;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_CALL: u16 = 734;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_RING: u16 = 735;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_SUSPEND: u16 = 736;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_RESUME: u16 = 737;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_PHONE_PICKED_UP: u16 = 738;
+pub const GNUNET_MESSAGE_TYPE_CONVERSATION_CS_AUDIO: u16 = 739;
+pub const GNUNET_MESSAGE_TYPE_RECLAIM_ATTRIBUTE_STORE: u16 = 961;
+pub const GNUNET_MESSAGE_TYPE_RECLAIM_SUCCESS_RESPONSE: u16 = 962;
+pub const GNUNET_MESSAGE_TYPE_RECLAIM_ISSUE_TICKET: u16 = 967;
+pub const GNUNET_MESSAGE_TYPE_RECLAIM_TICKET_RESULT: u16 = 968;
+pub const GNUNET_MESSAGE_TYPE_RECLAIM_CONSUME_TICKET: u16 = 971;
+pub const GNUNET_MESSAGE_TYPE_RECLAIM_CONSUME_TICKET_RESULT: u16 = 972;
+pub const GNUNET_MESSAGE_TYPE_RECLAIM_ATTRIBUTE_DELETE: u16 = 976;
+pub const GNUNET_MESSAGE_TYPE_ABD_VERIFY: u16 = 991;
+pub const GNUNET_MESSAGE_TYPE_ABD_VERIFY_RESULT: u16 = 992;
+pub const GNUNET_MESSAGE_TYPE_NAT_REGISTER: u16 = 1060;
+pub const GNUNET_MESSAGE_TYPE_NAT_REQUEST_CONNECTION_REVERSAL: u16 = 1062;
+pub const GNUNET_MESSAGE_TYPE_NAT_CONNECTION_REVERSAL_REQUESTED: u16 = 1063;
+pub const GNUNET_MESSAGE_TYPE_NAT_ADDRESS_CHANGE: u16 = 1064;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_CONNECTION_CREATE: u16 = 1600;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_CONNECTION_UPDATE: u16 = 1601;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_CONNECTION_DESTROY: u16 = 1602;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_CONNECTION_GET_NAME: u16 = 1603;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_CONNECTION_SET_NAME: u16 = 1604;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_OPEN: u16 = 1610;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_ENTRY: u16 = 1611;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_CLOSE: u16 = 1612;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_SEND_MESSAGE: u16 = 1614;
+pub const GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_RECV_MESSAGE: u16 = 1615;
 pub const GNUNET_DNSPARSER_MAX_NAME_LENGTH: u16 = 253;
+pub const GNUNET_SIGNATURE_PURPOSE_REVOCATION: u32 = 12;
+pub const GNUNET_SIGNATURE_PURPOSE_GNS_RECORD_SIGN: u32 = 15;
+pub const GNUNET_SIGNATURE_PURPOSE_FS_UBLOCK: u32 = 17;
+pub const GNUNET_SIGNATURE_PURPOSE_GNUID_TOKEN: u32 = 26;
+pub const GNUNET_SIGNATURE_PURPOSE_RECLAIM_CODE_SIGN: u32 = 27;
+pub const GNUNET_SIGNATURE_PURPOSE_DELEGATE: u32 = 28;
 
 unsafe impl Send for Struct_GNUNET_GNSRECORD_Data {}
 
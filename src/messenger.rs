@@ -0,0 +1,221 @@
+//! Module for group communication through the messenger service.
+//!
+//! Rooms are identified by a `HashCode` key. A peer hosting a room `open`s it, other peers
+//! `enter` it through a peer which has it open (the door). Every member receives the messages
+//! sent to the room, including notices of members joining and leaving.
+
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use HashCode;
+use PeerIdentity;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+
+/// Kinds of messenger messages this module understands.
+const KIND_JOIN: u32 = 2;
+const KIND_LEAVE: u32 = 3;
+const KIND_TEXT: u32 = 12;
+
+/// The length of an encoded message header without its signature: timestamp, sender id, hash of
+/// the previous message and kind.
+const HEADER_LEN: usize = 8 + 32 + 64 + 4;
+
+/// Something which happened in a room.
+#[derive(Debug)]
+pub enum MessengerEvent {
+  /// A member joined the room.
+  Join {
+    /// The key of the room.
+    room: HashCode,
+    /// The hash of the member's public key.
+    member: HashCode,
+  },
+  /// A member left the room.
+  Leave { room: HashCode, member: HashCode },
+  /// A member sent a text message to the room.
+  Text { room: HashCode, member: HashCode, text: String },
+  /// A message of a kind this module doesn't decode, such as a member changing their name or a
+  /// file being shared.
+  Other { room: HashCode, member: HashCode, kind: u32 },
+}
+
+/// Errors returned by `Messenger::next_event`.
+error_def! MessengerError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the messenger service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the messenger service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The messenger service sent an unexpected message type" ("Message type {} was not expected", ty),
+  InvalidText
+    => "A text message was not valid UTF-8",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {MessengerError}
+
+/// Errors returned by `Messenger::connect`.
+error_def! ConnectError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the messenger service" ("Reason: {}", cause),
+  Name { #[from] cause: NameError }
+    => "Failed to send the name to the messenger service" ("Reason: {}", cause),
+}
+
+/// Errors returned by `Messenger::set_name`.
+error_def! NameError {
+  TooLong { len: usize }
+    => "The name is too long to send in one message" ("{} bytes is too long", len),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the messenger service" ("Specifically: {}", cause),
+}
+
+/// Errors returned by `Messenger::send_text`.
+error_def! SendTextError {
+  TooLong { len: usize }
+    => "The text is too long to send in one message" ("{} bytes is too long", len),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the messenger service" ("Specifically: {}", cause),
+}
+
+/// Decode the message a member sent to `room`, as carried by a `RECV_MESSAGE`.
+///
+/// The message starts with the sender's signature, which the service has already checked.
+fn decode_message<R>(room: HashCode, member: HashCode, r: &mut R) -> Result<MessengerEvent, MessengerError> where R: Read {
+  let mut signature = [0u8; 4 + 64];
+  try!(r.read_exact(&mut signature));
+  let mut header = [0u8; HEADER_LEN - 4];
+  try!(r.read_exact(&mut header));
+  let kind = try!(r.read_u32::<BigEndian>());
+  match kind {
+    KIND_JOIN   => Ok(MessengerEvent::Join { room: room, member: member }),
+    KIND_LEAVE  => Ok(MessengerEvent::Leave { room: room, member: member }),
+    KIND_TEXT   => {
+      let mut text = Vec::new();
+      try!(r.read_to_end(&mut text));
+      match String::from_utf8(text) {
+        Ok(text)  => Ok(MessengerEvent::Text { room: room, member: member, text: text }),
+        Err(_)    => Err(MessengerError::InvalidText),
+      }
+    },
+    kind => Ok(MessengerEvent::Other { room: room, member: member, kind: kind }),
+  }
+}
+
+/// Send a message of type `tpe` carrying the NUL-terminated `name`.
+fn send_name(service_writer: &mut ServiceWriter, tpe: u16, name: &str) -> Result<(), NameError> {
+  let len = 4 + name.len() + 1;
+  if len > 0xffff {
+    return Err(NameError::TooLong { len: name.len() });
+  }
+  let mut mw = service_writer.write_message(len as u16, tpe);
+  mw.write_all(name.as_bytes()).unwrap();
+  mw.write_u8(0).unwrap();
+  Ok(try!(mw.send()))
+}
+
+/// A connection to the messenger service.
+pub struct Messenger {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+}
+
+impl Messenger {
+  /// Connect to the messenger service. `name` is the name other members see.
+  pub fn connect(cfg: &Cfg, name: &str) -> Result<Messenger, ConnectError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "messenger"));
+    try!(send_name(&mut service_writer, ll::GNUNET_MESSAGE_TYPE_MESSENGER_CONNECTION_CREATE, name));
+    Ok(Messenger {
+      service_reader: service_reader,
+      service_writer: service_writer,
+    })
+  }
+
+  /// Change the name other members see.
+  pub fn set_name(&mut self, name: &str) -> Result<(), NameError> {
+    send_name(&mut self.service_writer, ll::GNUNET_MESSAGE_TYPE_MESSENGER_CONNECTION_SET_NAME, name)
+  }
+
+  /// Open the room `key` on this peer, creating it if it doesn't exist yet. Other peers can then
+  /// enter it through this peer.
+  pub fn open_room(&mut self, key: &HashCode) -> Result<(), io::Error> {
+    let mut mw = self.service_writer.write_message(68, ll::GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_OPEN);
+    key.serialize(&mut mw).unwrap();
+    mw.send()
+  }
+
+  /// Enter the room `key` through `door`, a peer which has the room open.
+  pub fn enter_room(&mut self, door: &PeerIdentity, key: &HashCode) -> Result<(), io::Error> {
+    let mut mw = self.service_writer.write_message(100, ll::GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_ENTRY);
+    door.serialize(&mut mw).unwrap();
+    key.serialize(&mut mw).unwrap();
+    mw.send()
+  }
+
+  /// Leave the room `key`.
+  pub fn close_room(&mut self, key: &HashCode) -> Result<(), io::Error> {
+    let mut mw = self.service_writer.write_message(68, ll::GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_CLOSE);
+    key.serialize(&mut mw).unwrap();
+    mw.send()
+  }
+
+  /// Send `text` to the members of the room `room`.
+  pub fn send_text(&mut self, room: &HashCode, text: &str) -> Result<(), SendTextError> {
+    let len = 4 + 64 + 4 + HEADER_LEN + text.len();
+    if len > 0xffff {
+      return Err(SendTextError::TooLong { len: text.len() });
+    }
+    let mut mw = self.service_writer.write_message(len as u16, ll::GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_SEND_MESSAGE);
+    room.serialize(&mut mw).unwrap();
+    mw.write_u32::<BigEndian>(0).unwrap(); // flags
+    // The service fills in the timestamp, sender id and previous message hash.
+    mw.write_all(&[0u8; HEADER_LEN - 4]).unwrap();
+    mw.write_u32::<BigEndian>(KIND_TEXT).unwrap();
+    mw.write_all(text.as_bytes()).unwrap();
+    Ok(try!(mw.send()))
+  }
+
+  /// Wait for the next event in any of the rooms this connection has opened or entered.
+  pub fn next_event(&mut self) -> Result<MessengerEvent, MessengerError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_MESSENGER_ROOM_RECV_MESSAGE => {
+        let room = try!(HashCode::deserialize(&mut mr));
+        let member = try!(HashCode::deserialize(&mut mr));
+        let _context = try!(HashCode::deserialize(&mut mr));
+        let _hash = try!(HashCode::deserialize(&mut mr));
+        let _flags = try!(mr.read_u32::<BigEndian>());
+        decode_message(room, member, &mut mr)
+      },
+      x => Err(MessengerError::UnexpectedMessageType { ty: x }),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use byteorder::{BigEndian, WriteBytesExt};
+  use HashCode;
+  use super::*;
+  use super::{decode_message, HEADER_LEN, KIND_TEXT};
+
+  #[test]
+  fn test_decode_text_message() {
+    let mut msg = vec![0u8; 4 + 64 + HEADER_LEN - 4];
+    msg.write_u32::<BigEndian>(KIND_TEXT).unwrap();
+    msg.extend_from_slice(b"hello room");
+    let room = HashCode::from_buffer(b"room");
+    let member = HashCode::from_buffer(b"member");
+    match decode_message(room.clone(), member.clone(), &mut Cursor::new(msg)).unwrap() {
+      MessengerEvent::Text { room: r, member: m, text } => {
+        assert_eq!(r, room);
+        assert_eq!(m, member);
+        assert_eq!(text, "hello room");
+      },
+      x => panic!("unexpected event: {:?}", x),
+    };
+  }
+}
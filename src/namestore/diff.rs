@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use EcdsaPrivateKey;
+use gns::Record;
+use namestore::{Namestore, StoreError, RecordResultError};
+
+/// A change to the set of records stored under a single label.
+#[derive(Clone)]
+pub struct RecordSetChange {
+  /// The label whose records change.
+  pub label: String,
+  /// The records currently stored under the label. Empty if the label is being added.
+  pub current: Vec<Record>,
+  /// The records that should be stored under the label. Empty if the label is being removed.
+  pub desired: Vec<Record>,
+}
+
+impl RecordSetChange {
+  /// Returns `true` if this change adds a label which is not yet in the zone.
+  pub fn is_addition(&self) -> bool {
+    self.current.is_empty()
+  }
+
+  /// Returns `true` if this change removes a label from the zone.
+  pub fn is_removal(&self) -> bool {
+    self.desired.is_empty()
+  }
+}
+
+/// Returns `true` if two record sets contain the same records, ignoring order.
+fn same_records(a: &[Record], b: &[Record]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  let mut matched = vec![false; b.len()];
+  'outer: for ra in a.iter() {
+    for (i, rb) in b.iter().enumerate() {
+      if !matched[i] && ra == rb {
+        matched[i] = true;
+        continue 'outer;
+      }
+    }
+    return false;
+  }
+  true
+}
+
+/// Compute the changes required to turn the zone contents `current` into `desired`.
+///
+/// Both arguments map labels to the records stored under them. Labels whose record sets are
+/// equal (ignoring the order of records) produce no change. The returned changes are sorted by
+/// label.
+pub fn diff_zone(current: &HashMap<String, Vec<Record>>,
+                 desired: &HashMap<String, Vec<Record>>) -> Vec<RecordSetChange> {
+  let mut changes = Vec::new();
+  for (label, want) in desired.iter() {
+    let have = match current.get(label) {
+      Some(have)  => &have[..],
+      None        => &[][..],
+    };
+    if !same_records(have, want) {
+      changes.push(RecordSetChange {
+        label: label.clone(),
+        current: have.to_vec(),
+        desired: want.clone(),
+      });
+    }
+  }
+  for (label, have) in current.iter() {
+    if !desired.contains_key(label) && !have.is_empty() {
+      changes.push(RecordSetChange {
+        label: label.clone(),
+        current: have.clone(),
+        desired: Vec::new(),
+      });
+    }
+  }
+  changes.sort_by(|a, b| a.label.cmp(&b.label));
+  changes
+}
+
+/// Errors returned by `Namestore::sync_zone`.
+error_def! SyncZoneError {
+  ReadZone { #[from] cause: RecordResultError }
+    => "Failed to read the current contents of the zone" ("Reason: {}", cause),
+  Store { #[from] cause: StoreError }
+    => "Failed to apply a change to the zone" ("Reason: {}", cause),
+}
+
+impl Namestore {
+  /// Read the entire contents of a zone into a map from labels to record sets.
+  pub fn zone_contents(&mut self, zone: &EcdsaPrivateKey) -> Result<HashMap<String, Vec<Record>>, RecordResultError> {
+    let mut ret = HashMap::new();
+    for res in try!(self.iterate_zone(zone)) {
+      let rs = try!(res);
+      ret.insert(rs.label, rs.records);
+    }
+    Ok(ret)
+  }
+
+  /// Apply a list of changes, as computed by `diff_zone`, to a zone.
+  pub fn apply_changes(&mut self, zone: &EcdsaPrivateKey, changes: &[RecordSetChange]) -> Result<(), StoreError> {
+    for change in changes.iter() {
+      try!(self.store(zone, &change.label, &change.desired[..]));
+    }
+    Ok(())
+  }
+
+  /// Make the contents of a zone equal to `desired`, only storing the labels that differ.
+  ///
+  /// Labels present in the zone but not in `desired` are removed. Returns the changes that were
+  /// applied. Calling this repeatedly with the same `desired` map is idempotent.
+  pub fn sync_zone(&mut self,
+                   zone: &EcdsaPrivateKey,
+                   desired: &HashMap<String, Vec<Record>>) -> Result<Vec<RecordSetChange>, SyncZoneError> {
+    let current = try!(self.zone_contents(zone));
+    let changes = diff_zone(&current, desired);
+    try!(self.apply_changes(zone, &changes[..]));
+    Ok(changes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use gns::{Record, RecordType};
+  use super::*;
+
+  fn a_record(addr: [u8; 4]) -> Record {
+    Record::new(RecordType::A, addr.to_vec(), 0, 0)
+  }
+
+  #[test]
+  fn test_diff_zone() {
+    let mut current = HashMap::new();
+    current.insert("www".to_string(), vec![a_record([1, 2, 3, 4]), a_record([5, 6, 7, 8])]);
+    current.insert("mail".to_string(), vec![a_record([1, 1, 1, 1])]);
+    current.insert("old".to_string(), vec![a_record([2, 2, 2, 2])]);
+
+    let mut desired = HashMap::new();
+    desired.insert("www".to_string(), vec![a_record([5, 6, 7, 8]), a_record([1, 2, 3, 4])]);
+    desired.insert("mail".to_string(), vec![a_record([3, 3, 3, 3])]);
+    desired.insert("new".to_string(), vec![a_record([4, 4, 4, 4])]);
+
+    let changes = diff_zone(&current, &desired);
+    let labels: Vec<&str> = changes.iter().map(|c| &c.label[..]).collect();
+    assert_eq!(labels, vec!["mail", "new", "old"]);
+    assert!(!changes[0].is_addition() && !changes[0].is_removal());
+    assert!(changes[1].is_addition());
+    assert!(changes[2].is_removal());
+
+    assert!(diff_zone(&desired, &desired).is_empty());
+  }
+}
@@ -0,0 +1,407 @@
+//! Module for storing and retrieving records in the local namestore. The namestore is where the
+//! records of the zones we are authoritative for are kept before being published to the DHT by
+//! the GNS service.
+
+use std::io::{self, Write, Cursor};
+use std::time::Duration;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num::ToPrimitive;
+
+use ll;
+use EcdsaPrivateKey;
+use Cfg;
+use activity::{Activity, ActivityLog};
+use capabilities::{Capabilities, PROBE_TIMEOUT_MILLIS};
+use gns::Record;
+use service::{self, ProbeOutcome, ServiceReader, ServiceWriter, ReadMessageError};
+use time::now_micros;
+use util::{ReadCString, ReadCStringWithLenError};
+pub use self::diff::*;
+pub use self::monitor::*;
+pub use self::replica::*;
+pub use self::signing::*;
+pub use self::validate::*;
+
+mod diff;
+mod monitor;
+mod replica;
+mod signing;
+mod validate;
+
+/// A handle to a locally-running instance of the namestore daemon.
+pub struct Namestore {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  next_request_id: u32,
+  activity: Option<ActivityLog>,
+  capabilities: Capabilities,
+}
+
+/// The set of records stored under a label in a zone.
+#[derive(Clone)]
+pub struct RecordSet {
+  /// The private key of the zone the records belong to.
+  pub zone: EcdsaPrivateKey,
+  /// The label the records are stored under.
+  pub label: String,
+  /// The records themselves.
+  pub records: Vec<Record>,
+}
+
+/// Errors returned by `Namestore::store`.
+error_def! StoreError {
+  Invalid { #[from] cause: RecordSetValidationError }
+    => "The record set is not valid" ("Reason: {}", cause),
+  TooLong { label: String }
+    => "The label and record set were too large to send to the service" ("The record set for \"{}\" does not fit in a single message.", label),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the namestore service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the namestore service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The namestore service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  InvalidResponse
+    => "The response from the namestore service was incoherent",
+  Failed
+    => "The namestore service failed to store the records",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {StoreError}
+
+/// Errors that can occur while reading a record set sent by the namestore service.
+error_def! RecordResultError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the namestore service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the namestore service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The namestore service sent an unexpected message type" ("Message type {} was not expected", ty),
+  InvalidLabel { #[from] cause: ReadCStringWithLenError }
+    => "Failed to read the label of a record set" ("Reason: {}", cause),
+  InvalidResponse
+    => "The response from the namestore service was incoherent",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {RecordResultError}
+
+/// Errors returned by `Namestore::lookup`.
+error_def! LookupError {
+  LabelTooLong { label: String }
+    => "The label was too long to send to the service" ("\"{}\" is too long to be a label.", label),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the namestore service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the namestore service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The namestore service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  InvalidLabel { #[from] cause: ReadCStringWithLenError }
+    => "Failed to read the label of the record set" ("Reason: {}", cause),
+  Iterate { #[from] cause: RecordResultError }
+    => "Failed to iterate over the zone to find the label" ("Reason: {}", cause),
+  InvalidResponse
+    => "The response from the namestore service was incoherent",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {LookupError}
+
+impl Namestore {
+  /// Connect to the namestore service.
+  ///
+  /// Returns either a handle to the namestore service or a `service::ConnectError`. `cfg`
+  /// contains the configuration to use to connect to the service.
+  ///
+  /// The service is probed over a second connection to find out whether it can look up single
+  /// labels. If the probe fails the handle assumes it can.
+  pub fn connect(cfg: &Cfg) -> Result<Namestore, service::ConnectError> {
+    let (service_reader, service_writer) = try!(service::connect(cfg, "namestore"));
+    let mut capabilities = Capabilities::default();
+    let timeout = Duration::from_millis(PROBE_TIMEOUT_MILLIS);
+    if let Ok(supported) = probe_record_lookup(cfg, timeout) {
+      capabilities.namestore_record_lookup = supported;
+    }
+    Ok(Namestore {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      next_request_id: 0,
+      activity: ActivityLog::from_cfg(cfg),
+      capabilities: capabilities,
+    })
+  }
+
+  /// Send requests the way a daemon with `capabilities` expects.
+  pub fn set_capabilities(&mut self, capabilities: &Capabilities) {
+    self.capabilities = *capabilities;
+  }
+
+  fn request_id(&mut self) -> u32 {
+    let id = self.next_request_id;
+    self.next_request_id = self.next_request_id.wrapping_add(1);
+    id
+  }
+
+  /// Store a set of records under `label` in the zone `zone`.
+  ///
+  /// This replaces any records previously stored under the label. Storing an empty set of records
+  /// removes the label from the zone.
+  ///
+  /// The records are checked with `validate_record_set` first and an invalid set is not sent.
+  pub fn store(&mut self, zone: &EcdsaPrivateKey, label: &str, records: &[Record]) -> Result<(), StoreError> {
+    try!(validate_record_set(label, records, now_micros()));
+    let name_len = label.len() + 1;
+    let rd_len = records.iter().fold(0, |acc, r| acc + r.serialized_len());
+    let (msg_length, rd_count) = match ((48 + name_len + rd_len).to_u16(), records.len().to_u16()) {
+      (Some(l), Some(c))  => (l, c),
+      _                   => return Err(StoreError::TooLong { label: label.to_string() }),
+    };
+
+    let id = self.request_id();
+    {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_STORE);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      mw.write_u16::<BigEndian>(name_len as u16).unwrap();
+      mw.write_u16::<BigEndian>(rd_len as u16).unwrap();
+      mw.write_u16::<BigEndian>(rd_count).unwrap();
+      mw.write_u16::<BigEndian>(0).unwrap();
+      zone.serialize(&mut mw).unwrap();
+      mw.write_all(label.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      for r in records.iter() {
+        r.serialize(&mut mw).unwrap();
+      }
+      try!(mw.send());
+    };
+
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_STORE_RESPONSE => {
+        let rid = try!(mr.read_u32::<BigEndian>());
+        if rid != id {
+          return Err(StoreError::InvalidResponse);
+        }
+        match try!(mr.read_i32::<BigEndian>()) {
+          1 => (),
+          _ => return Err(StoreError::Failed),
+        };
+        if let Some(ref log) = self.activity {
+          let _ = log.log(&Activity::RecordStored {
+            zone: zone.get_public(),
+            label: label.to_string(),
+            record_types: records.iter().map(|r| r.record_type()).collect(),
+          });
+        }
+        Ok(())
+      },
+      x => Err(StoreError::UnexpectedMessageType { ty: x }),
+    }
+  }
+
+  /// Look up the records stored under `label` in the zone `zone`.
+  ///
+  /// Returns `None` if there are no records under the label. Private records are included, unlike
+  /// in the results of a GNS lookup.
+  pub fn lookup(&mut self, zone: &EcdsaPrivateKey, label: &str) -> Result<Option<RecordSet>, LookupError> {
+    if !self.capabilities.namestore_record_lookup {
+      // The daemon can't look up a single label, so search the whole zone for it.
+      for rs in try!(self.iterate_zone(zone)) {
+        let rs = try!(rs);
+        if rs.label == label {
+          return Ok(Some(rs));
+        }
+      }
+      return Ok(None);
+    }
+    let name_len = label.len() + 1;
+    let msg_length = match (44 + name_len).to_u16() {
+      Some(l) => l,
+      None    => return Err(LookupError::LabelTooLong { label: label.to_string() }),
+    };
+
+    let id = self.request_id();
+    {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_LOOKUP);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      mw.write_u32::<BigEndian>(name_len as u32).unwrap();
+      zone.serialize(&mut mw).unwrap();
+      mw.write_all(label.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_LOOKUP_RESPONSE {
+      return Err(LookupError::UnexpectedMessageType { ty: tpe });
+    }
+    if try!(mr.read_u32::<BigEndian>()) != id {
+      return Err(LookupError::InvalidResponse);
+    }
+    let name_len = try!(mr.read_u16::<BigEndian>());
+    let _rd_len = try!(mr.read_u16::<BigEndian>());
+    let rd_count = try!(mr.read_u16::<BigEndian>());
+    let found = try!(mr.read_i16::<BigEndian>());
+    let zone = try!(EcdsaPrivateKey::deserialize(&mut mr));
+    if found == 0 || rd_count == 0 {
+      return Ok(None);
+    }
+    if name_len == 0 {
+      return Err(LookupError::InvalidResponse);
+    }
+    let label = try!(mr.read_c_string_with_len((name_len - 1) as usize));
+    let mut records = Vec::with_capacity(rd_count as usize);
+    for _ in 0..rd_count {
+      records.push(try!(Record::deserialize(&mut mr)));
+    }
+    Ok(Some(RecordSet {
+      zone: zone,
+      label: label,
+      records: records,
+    }))
+  }
+
+  /// Iterate over all the record sets in the zone `zone`.
+  ///
+  /// The iteration is driven by the returned iterator. Each call to `next` requests the following
+  /// record set from the service.
+  pub fn iterate_zone<'a>(&'a mut self, zone: &EcdsaPrivateKey) -> Result<ZoneIterator<'a>, io::Error> {
+    let id = self.request_id();
+    {
+      let mut mw = self.service_writer.write_message(40, ll::GNUNET_MESSAGE_TYPE_NAMESTORE_ZONE_ITERATION_START);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      zone.serialize(&mut mw).unwrap();
+      try!(mw.send());
+    };
+    Ok(ZoneIterator {
+      namestore: self,
+      request_id: id,
+      started: false,
+      finished: false,
+    })
+  }
+
+  fn send_request_id(&mut self, tpe: u16, id: u32) -> Result<(), io::Error> {
+    let mut mw = self.service_writer.write_message(8, tpe);
+    mw.write_u32::<BigEndian>(id).unwrap();
+    mw.send()
+  }
+}
+
+/// Read the body of a `RECORD_RESULT` message. Returns `None` if the message is the empty result
+/// the service uses to mark the end of an iteration.
+fn read_record_result(mr: &mut Cursor<Vec<u8>>) -> Result<Option<RecordSet>, RecordResultError> {
+  let name_len = try!(mr.read_u16::<BigEndian>());
+  let _rd_len = try!(mr.read_u16::<BigEndian>());
+  let rd_count = try!(mr.read_u16::<BigEndian>());
+  let reserved = try!(mr.read_u16::<BigEndian>());
+  try!(service::check_flags(reserved as u32, 0));
+  let zone = try!(EcdsaPrivateKey::deserialize(mr));
+  if name_len == 0 {
+    return match rd_count {
+      0 => Ok(None),
+      _ => Err(RecordResultError::InvalidResponse),
+    };
+  }
+  let label = try!(mr.read_c_string_with_len((name_len - 1) as usize));
+  let mut records = Vec::with_capacity(rd_count as usize);
+  for _ in 0..rd_count {
+    records.push(try!(Record::deserialize(mr)));
+  }
+  try!(service::check_end(mr));
+  Ok(Some(RecordSet {
+    zone: zone,
+    label: label,
+    records: records,
+  }))
+}
+
+/// Find out whether the namestore daemon can look up the records under a single label, waiting
+/// up to `timeout` for it to react to a lookup in the anonymous zone.
+pub fn probe_record_lookup(cfg: &Cfg, timeout: Duration) -> Result<bool, service::ConnectError> {
+  let label = "@";
+  let mut body = Vec::with_capacity(40 + label.len() + 1);
+  body.write_u32::<BigEndian>(0).unwrap();
+  body.write_u32::<BigEndian>((label.len() + 1) as u32).unwrap();
+  EcdsaPrivateKey::anonymous().serialize(&mut body).unwrap();
+  body.write_all(label.as_bytes()).unwrap();
+  body.write_u8(0u8).unwrap();
+  let outcome = try!(service::probe(cfg, "namestore", ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_LOOKUP, &body[..], timeout));
+  Ok(outcome == ProbeOutcome::Answered { tpe: ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_LOOKUP_RESPONSE })
+}
+
+/// An iterator over the record sets in a zone. Created by `Namestore::iterate_zone`.
+pub struct ZoneIterator<'a> {
+  namestore: &'a mut Namestore,
+  request_id: u32,
+  started: bool,
+  finished: bool,
+}
+
+impl<'a> Iterator for ZoneIterator<'a> {
+  type Item = Result<RecordSet, RecordResultError>;
+
+  fn next(&mut self) -> Option<Result<RecordSet, RecordResultError>> {
+    if self.finished {
+      return None;
+    }
+    if self.started {
+      let id = self.request_id;
+      if let Err(e) = self.namestore.send_request_id(ll::GNUNET_MESSAGE_TYPE_NAMESTORE_ZONE_ITERATION_NEXT, id) {
+        self.finished = true;
+        return Some(Err(RecordResultError::Io { cause: e }));
+      }
+    }
+    self.started = true;
+
+    let (tpe, mut mr) = match self.namestore.service_reader.read_message() {
+      Ok(x)   => x,
+      Err(e)  => {
+        self.finished = true;
+        return Some(Err(RecordResultError::ReadMessage { cause: e }));
+      },
+    };
+    let res = match tpe {
+      ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_RESULT => match mr.read_u32::<BigEndian>() {
+        Ok(id) if id == self.request_id => read_record_result(&mut mr),
+        Ok(_)   => Err(RecordResultError::InvalidResponse),
+        Err(e)  => Err(From::from(e)),
+      },
+      x => Err(RecordResultError::UnexpectedMessageType { ty: x }),
+    };
+    match res {
+      Ok(Some(rs))  => Some(Ok(rs)),
+      Ok(None)      => {
+        self.finished = true;
+        None
+      },
+      Err(e)        => {
+        self.finished = true;
+        Some(Err(e))
+      },
+    }
+  }
+}
+
+impl<'a> Drop for ZoneIterator<'a> {
+  fn drop(&mut self) {
+    if self.finished {
+      return;
+    }
+    if !self.started {
+      // The first result is already on its way. Consume it so that it isn't mistaken for the
+      // response to a later request.
+      match self.namestore.service_reader.read_message() {
+        Ok((ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_RESULT, mut mr)) => {
+          let _ = mr.read_u32::<BigEndian>();
+          match read_record_result(&mut mr) {
+            Ok(Some(_)) => (),
+            _           => return,
+          }
+        },
+        _ => return,
+      }
+    }
+    let id = self.request_id;
+    let _ = self.namestore.send_request_id(ll::GNUNET_MESSAGE_TYPE_NAMESTORE_ZONE_ITERATION_STOP, id);
+  }
+}
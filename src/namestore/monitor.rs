@@ -0,0 +1,89 @@
+use std::io;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use service::{self, ServiceReader, ServiceWriter};
+use namestore::{RecordSet, RecordResultError, read_record_result};
+
+/// An event delivered by a `ZoneMonitor`.
+pub enum MonitorEvent {
+  /// The records under a label were added or changed. An empty set of records means the label was
+  /// removed.
+  Changed(RecordSet),
+  /// All the records which existed when monitoring started have been delivered. Everything after
+  /// this is a live change.
+  Synced,
+}
+
+/// Errors returned by `ZoneMonitor::start`.
+error_def! MonitorStartError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the namestore service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the namestore service" ("Specifically: {}", cause),
+}
+
+/// A subscription to the changes made to a zone.
+///
+/// The monitor uses its own connection to the namestore service. Events are received by iterating
+/// over the monitor, which blocks until the next change arrives. If the connection to the service
+/// is lost a `Disconnected` error is returned and the iteration ends.
+pub struct ZoneMonitor {
+  service_reader: ServiceReader,
+  _service_writer: ServiceWriter,
+  disconnected: bool,
+}
+
+impl ZoneMonitor {
+  /// Start monitoring the zone `zone`.
+  ///
+  /// If `iterate_first` is `true` the records already in the zone are delivered first, followed by
+  /// `MonitorEvent::Synced`. Otherwise `Synced` is delivered straight away and only changes made
+  /// from now on are reported.
+  pub fn start(cfg: &Cfg, zone: &EcdsaPrivateKey, iterate_first: bool) -> Result<ZoneMonitor, MonitorStartError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "namestore"));
+    {
+      let mut mw = service_writer.write_message(40, ll::GNUNET_MESSAGE_TYPE_NAMESTORE_MONITOR_START);
+      mw.write_u32::<BigEndian>(iterate_first as u32).unwrap();
+      zone.serialize(&mut mw).unwrap();
+      try!(mw.send());
+    };
+    Ok(ZoneMonitor {
+      service_reader: service_reader,
+      _service_writer: service_writer,
+      disconnected: false,
+    })
+  }
+}
+
+impl Iterator for ZoneMonitor {
+  type Item = Result<MonitorEvent, RecordResultError>;
+
+  fn next(&mut self) -> Option<Result<MonitorEvent, RecordResultError>> {
+    if self.disconnected {
+      return None;
+    }
+    let (tpe, mut mr) = match self.service_reader.read_message() {
+      Ok(x)   => x,
+      Err(_)  => {
+        self.disconnected = true;
+        return Some(Err(RecordResultError::Disconnected));
+      },
+    };
+    let res = match tpe {
+      ll::GNUNET_MESSAGE_TYPE_NAMESTORE_MONITOR_SYNC => Ok(MonitorEvent::Synced),
+      ll::GNUNET_MESSAGE_TYPE_NAMESTORE_RECORD_RESULT => match mr.read_u32::<BigEndian>() {
+        Ok(_)   => match read_record_result(&mut mr) {
+          Ok(Some(rs))  => Ok(MonitorEvent::Changed(rs)),
+          Ok(None)      => Err(RecordResultError::InvalidResponse),
+          Err(e)        => Err(e),
+        },
+        Err(e)  => Err(From::from(e)),
+      },
+      x => Err(RecordResultError::UnexpectedMessageType { ty: x }),
+    };
+    Some(res)
+  }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak, Mutex, Condvar};
+use std::thread;
+
+use Cfg;
+use EcdsaPrivateKey;
+use gns::{Record, RecordType};
+use namestore::{ZoneMonitor, MonitorEvent, MonitorStartError};
+
+/// The contents of a replicated zone, shared between a `ZoneReplica` and its monitor thread.
+struct ReplicaState {
+  records: HashMap<String, Vec<Record>>,
+  synced: bool,
+  running: bool,
+}
+
+impl ReplicaState {
+  fn new() -> ReplicaState {
+    ReplicaState {
+      records: HashMap::new(),
+      synced: false,
+      running: true,
+    }
+  }
+
+  fn apply(&mut self, event: MonitorEvent) {
+    match event {
+      MonitorEvent::Changed(rs) => {
+        if rs.records.is_empty() {
+          self.records.remove(&rs.label);
+        }
+        else {
+          self.records.insert(rs.label, rs.records);
+        }
+      },
+      MonitorEvent::Synced => self.synced = true,
+    }
+  }
+}
+
+struct Shared {
+  state: Mutex<ReplicaState>,
+  cond: Condvar,
+}
+
+/// An in-memory copy of a zone's records, kept up to date by a `ZoneMonitor`.
+///
+/// Queries are answered from memory without talking to the namestore service. A background thread
+/// applies changes as the service reports them. If the connection to the service is lost the
+/// replica stops updating and `is_running` returns `false`; the records it holds are left as they
+/// were.
+///
+/// The background thread notices that the replica has been dropped the next time the zone
+/// changes, and exits then.
+pub struct ZoneReplica {
+  shared: Arc<Shared>,
+}
+
+impl ZoneReplica {
+  /// Start replicating the zone `zone`.
+  ///
+  /// The replica starts out empty and fills up as the records already in the zone are delivered.
+  /// Use `wait_for_sync` to block until this is done.
+  pub fn start(cfg: &Cfg, zone: &EcdsaPrivateKey) -> Result<ZoneReplica, MonitorStartError> {
+    let monitor = try!(ZoneMonitor::start(cfg, zone, true));
+    let shared = Arc::new(Shared {
+      state: Mutex::new(ReplicaState::new()),
+      cond: Condvar::new(),
+    });
+    let weak = Arc::downgrade(&shared);
+    try!(thread::Builder::new().name("gnunet zone replica".to_string()).spawn(move || {
+      run_monitor(monitor, weak)
+    }));
+    Ok(ZoneReplica {
+      shared: shared,
+    })
+  }
+
+  /// Block until the records that were in the zone when the replica started have all been
+  /// received. Returns `false` if the monitor stopped before this happened.
+  pub fn wait_for_sync(&self) -> bool {
+    let mut state = self.shared.state.lock().unwrap();
+    while !state.synced && state.running {
+      state = self.shared.cond.wait(state).unwrap();
+    }
+    state.synced
+  }
+
+  /// Returns `true` once the records that were in the zone when the replica started have all been
+  /// received.
+  pub fn is_synced(&self) -> bool {
+    self.shared.state.lock().unwrap().synced
+  }
+
+  /// Returns `true` if the replica is still receiving changes from the namestore service.
+  pub fn is_running(&self) -> bool {
+    self.shared.state.lock().unwrap().running
+  }
+
+  /// The records stored under `label`, or `None` if there are none.
+  pub fn get(&self, label: &str) -> Option<Vec<Record>> {
+    self.shared.state.lock().unwrap().records.get(label).cloned()
+  }
+
+  /// The records of type `record_type` stored under `label`.
+  pub fn get_by_type(&self, label: &str, record_type: RecordType) -> Vec<Record> {
+    let state = self.shared.state.lock().unwrap();
+    match state.records.get(label) {
+      Some(records) => records.iter().filter(|r| r.record_type() == record_type).cloned().collect(),
+      None          => Vec::new(),
+    }
+  }
+
+  /// Returns `true` if there are records stored under `label`.
+  pub fn contains_label(&self, label: &str) -> bool {
+    self.shared.state.lock().unwrap().records.contains_key(label)
+  }
+
+  /// All the labels in the zone, sorted.
+  pub fn labels(&self) -> Vec<String> {
+    let state = self.shared.state.lock().unwrap();
+    let mut labels: Vec<String> = state.records.keys().cloned().collect();
+    labels.sort();
+    labels
+  }
+
+  /// The number of labels in the zone.
+  pub fn len(&self) -> usize {
+    self.shared.state.lock().unwrap().records.len()
+  }
+
+  /// Returns `true` if the zone has no records.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// A copy of the entire zone, in the form used by `diff_zone`.
+  pub fn snapshot(&self) -> HashMap<String, Vec<Record>> {
+    self.shared.state.lock().unwrap().records.clone()
+  }
+}
+
+/// Apply the events from `monitor` to the replica until either the monitor fails or the replica
+/// is dropped.
+fn run_monitor(monitor: ZoneMonitor, shared: Weak<Shared>) {
+  for res in monitor {
+    let shared = match shared.upgrade() {
+      Some(s) => s,
+      None    => return,
+    };
+    let mut state = shared.state.lock().unwrap();
+    match res {
+      Ok(event) => state.apply(event),
+      Err(_)    => {
+        state.running = false;
+        shared.cond.notify_all();
+        return;
+      },
+    };
+    shared.cond.notify_all();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use EcdsaPrivateKey;
+  use gns::{Record, RecordType};
+  use namestore::{RecordSet, MonitorEvent};
+  use super::ReplicaState;
+
+  fn changed(label: &str, records: Vec<Record>) -> MonitorEvent {
+    MonitorEvent::Changed(RecordSet {
+      zone: EcdsaPrivateKey::anonymous(),
+      label: label.to_string(),
+      records: records,
+    })
+  }
+
+  #[test]
+  fn test_replica_state() {
+    let a = Record::new(RecordType::A, vec![1, 2, 3, 4], 0, 0);
+    let mut state = ReplicaState::new();
+    state.apply(changed("www", vec![a.clone()]));
+    state.apply(changed("mail", vec![a.clone()]));
+    assert!(!state.synced);
+    state.apply(MonitorEvent::Synced);
+    assert!(state.synced);
+    assert_eq!(state.records.len(), 2);
+
+    state.apply(changed("mail", Vec::new()));
+    assert_eq!(state.records.len(), 1);
+    assert!(state.records.get("www").unwrap() == &vec![a]);
+  }
+}
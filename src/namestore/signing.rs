@@ -0,0 +1,152 @@
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, WriteBytesExt};
+
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use EcdsaSignature;
+use gns::Record;
+use namestore::RecordSet;
+
+/// The signature purpose used for detached record set signatures.
+///
+/// This is specific to this library and is outside the range used by GNUnet, so these signatures
+/// can never be mistaken for signatures over GNS blocks or any other GNUnet data.
+pub const RECORD_SET_SIGNATURE_PURPOSE: u32 = 0x72730001;
+
+/// A detached signature over a record set, made by `RecordSet::sign` or `sign_records`.
+///
+/// The signature covers the zone's public key, the label and the records in order. It can be
+/// stored or exported alongside the records and checked later without the namestore service.
+#[derive(Copy, Clone)]
+pub struct RecordSetSignature {
+  /// The public key of the signer.
+  pub signer: EcdsaPublicKey,
+  /// The signature itself.
+  pub signature: EcdsaSignature,
+}
+
+impl RecordSetSignature {
+  /// Serialize a signature to a byte stream.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    try!(self.signer.serialize(w));
+    self.signature.serialize(w)
+  }
+
+  /// Deserialize a signature from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<RecordSetSignature, io::Error> where T: Read {
+    let signer = try!(EcdsaPublicKey::deserialize(r));
+    let signature = try!(EcdsaSignature::deserialize(r));
+    Ok(RecordSetSignature {
+      signer: signer,
+      signature: signature,
+    })
+  }
+
+  /// Check that this is a valid signature by the owner of the zone `zone` over `records` stored
+  /// under `label` in it. Signatures made with any other key than the zone's are rejected.
+  pub fn verify(&self, zone: &EcdsaPublicKey, label: &str, records: &[Record]) -> bool {
+    if self.signer != *zone {
+      return false;
+    }
+    let signed = signed_data(zone, label, records);
+    zone.verify(RECORD_SET_SIGNATURE_PURPOSE, &signed[..], &self.signature)
+  }
+}
+
+/// Build the data covered by a record set signature: the purpose header, the zone key, the
+/// length-prefixed label, the number of records and the records themselves.
+fn signed_data(zone: &EcdsaPublicKey, label: &str, records: &[Record]) -> Vec<u8> {
+  let records_len = records.iter().fold(0, |n, r| n + r.serialized_len());
+  let len = 8 + 32 + 4 + label.len() + 4 + records_len;
+  let mut ret = Vec::with_capacity(len);
+  ret.write_u32::<BigEndian>(len as u32).unwrap();
+  ret.write_u32::<BigEndian>(RECORD_SET_SIGNATURE_PURPOSE).unwrap();
+  zone.serialize(&mut ret).unwrap();
+  ret.write_u32::<BigEndian>(label.len() as u32).unwrap();
+  ret.extend_from_slice(label.as_bytes());
+  ret.write_u32::<BigEndian>(records.len() as u32).unwrap();
+  for record in records.iter() {
+    record.serialize(&mut ret).unwrap();
+  }
+  ret
+}
+
+/// Sign `records` stored under `label` in the zone `zone` with the key `key`. Only signatures made
+/// with the zone's own private key, eg. that of the ego owning the zone, pass `verify`.
+pub fn sign_records(key: &EcdsaPrivateKey, zone: &EcdsaPublicKey, label: &str, records: &[Record]) -> RecordSetSignature {
+  let signed = signed_data(zone, label, records);
+  RecordSetSignature {
+    signer: key.get_public(),
+    signature: key.sign(&signed[..]),
+  }
+}
+
+impl RecordSet {
+  /// Make a detached signature over this record set with the key `key`.
+  pub fn sign(&self, key: &EcdsaPrivateKey) -> RecordSetSignature {
+    sign_records(key, &self.zone.get_public(), &self.label[..], &self.records[..])
+  }
+
+  /// Check a detached signature over this record set. The signature must have been made with the
+  /// key of the record set's zone.
+  pub fn verify(&self, signature: &RecordSetSignature) -> bool {
+    signature.verify(&self.zone.get_public(), &self.label[..], &self.records[..])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use EcdsaPrivateKey;
+  use gns::{Record, RecordType};
+  use namestore::RecordSet;
+  use super::*;
+  use super::signed_data;
+
+  #[test]
+  fn test_sign_record_set() {
+    let key = EcdsaPrivateKey::anonymous();
+    let mut rs = RecordSet {
+      zone: key.clone(),
+      label: "www".to_string(),
+      records: vec![Record::new(RecordType::A, vec![1, 2, 3, 4], 0, 0)],
+    };
+    let sig = rs.sign(&key);
+    assert!(rs.verify(&sig));
+
+    let mut buf = Vec::new();
+    sig.serialize(&mut buf).unwrap();
+    let sig = RecordSetSignature::deserialize(&mut Cursor::new(buf)).unwrap();
+    assert!(rs.verify(&sig));
+
+    rs.records[0] = Record::new(RecordType::A, vec![1, 2, 3, 5], 0, 0);
+    assert!(!rs.verify(&sig));
+    rs.label = "mail".to_string();
+    assert!(!rs.verify(&sig));
+  }
+
+  #[test]
+  fn test_verify_rejects_other_signer() {
+    let zone = EcdsaPrivateKey::generate();
+    let other = EcdsaPrivateKey::generate();
+    let rs = RecordSet {
+      zone: zone.clone(),
+      label: "www".to_string(),
+      records: vec![Record::new(RecordType::A, vec![1, 2, 3, 4], 0, 0)],
+    };
+    // A valid signature over the right data, but not by the zone's owner.
+    let forged = rs.sign(&other);
+    assert!(forged.signer.verify(RECORD_SET_SIGNATURE_PURPOSE,
+                                 &signed_data(&zone.get_public(), "www", &rs.records[..])[..],
+                                 &forged.signature));
+    assert!(!rs.verify(&forged));
+    assert!(!forged.verify(&zone.get_public(), "www", &rs.records[..]));
+
+    // Claiming to be the zone doesn't help either.
+    let claimed = RecordSetSignature {
+      signer: zone.get_public(),
+      signature: forged.signature,
+    };
+    assert!(!rs.verify(&claimed));
+  }
+}
@@ -0,0 +1,118 @@
+use ll;
+use gns::{Record, RecordType};
+
+const RF_PRIVATE: u32 = ll::GNUNET_GNSRECORD_RF_PRIVATE as u32;
+const RF_RELATIVE_EXPIRATION: u32 = ll::GNUNET_GNSRECORD_RF_RELATIVE_EXPIRATION as u32;
+const RF_SHADOW_RECORD: u32 = ll::GNUNET_GNSRECORD_RF_SHADOW_RECORD as u32;
+
+/// Problems which make a record set unfit to be stored. Returned by `validate_record_set`.
+error_def! RecordSetValidationError {
+  DuplicatePkey { label: String }
+    => "There is more than one PKEY record under the label" ("\"{}\" can only delegate to one zone, remove all but one PKEY record", label),
+  MalformedPkey { label: String, len: usize }
+    => "A PKEY record does not hold a public key" ("The PKEY record under \"{}\" is {} bytes long, a public key is 32 bytes", label, len),
+  Expired { label: String, record_type: RecordType, expiration_time: u64 }
+    => "A record has already expired" ("The {} record under \"{}\" expired at {}, give it a later or relative expiration time", record_type, label, expiration_time),
+  OrphanShadow { label: String, record_type: RecordType }
+    => "A shadow record has no active record to shadow" ("The shadow {} record under \"{}\" needs an active {} record alongside it", record_type, label, record_type),
+  ShadowPrivacyMismatch { label: String, record_type: RecordType }
+    => "A shadow record and the record it shadows differ in privacy" ("The {} records under \"{}\" must all be private or all be public", record_type, label),
+}
+
+/// Check a record set the way the namestore and GNS services will before storing it under
+/// `label`. `now` is the current time in microseconds since the epoch, used to find expired
+/// records.
+///
+/// The set is rejected if it
+///
+///  * contains more than one PKEY record, or a PKEY record which is not a public key,
+///  * contains a record with an absolute expiration time which has passed,
+///  * contains a shadow record without an active record of the same type, or whose private flag
+///    differs from the active record's.
+///
+/// An empty set is always valid, storing it removes the label.
+pub fn validate_record_set(label: &str, records: &[Record], now: u64) -> Result<(), RecordSetValidationError> {
+  let mut pkeys = 0;
+  for record in records.iter() {
+    let flags = record.flags();
+    if record.record_type() == RecordType::PKEY {
+      pkeys += 1;
+      if pkeys > 1 {
+        return Err(RecordSetValidationError::DuplicatePkey { label: label.to_string() });
+      }
+      if record.data().len() != 32 {
+        return Err(RecordSetValidationError::MalformedPkey {
+          label: label.to_string(),
+          len: record.data().len(),
+        });
+      }
+    }
+    if flags & RF_RELATIVE_EXPIRATION == 0 && record.expiration_time() < now {
+      return Err(RecordSetValidationError::Expired {
+        label: label.to_string(),
+        record_type: record.record_type(),
+        expiration_time: record.expiration_time(),
+      });
+    }
+    if flags & RF_SHADOW_RECORD != 0 {
+      let mut active = records.iter().filter(|r| {
+        r.record_type() == record.record_type() && r.flags() & RF_SHADOW_RECORD == 0
+      }).peekable();
+      if active.peek().is_none() {
+        return Err(RecordSetValidationError::OrphanShadow {
+          label: label.to_string(),
+          record_type: record.record_type(),
+        });
+      }
+      if active.any(|r| r.flags() & RF_PRIVATE != flags & RF_PRIVATE) {
+        return Err(RecordSetValidationError::ShadowPrivacyMismatch {
+          label: label.to_string(),
+          record_type: record.record_type(),
+        });
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::u64;
+  use gns::{Record, RecordType};
+  use super::*;
+  use super::{RF_PRIVATE, RF_RELATIVE_EXPIRATION, RF_SHADOW_RECORD};
+
+  #[test]
+  fn test_validate_record_set() {
+    let now = 1000;
+    let a = Record::new(RecordType::A, vec![1, 2, 3, 4], u64::MAX, 0);
+    let pkey = Record::new(RecordType::PKEY, vec![0; 32], u64::MAX, 0);
+    assert!(validate_record_set("www", &[], now).is_ok());
+    assert!(validate_record_set("www", &[a.clone(), pkey.clone()], now).is_ok());
+
+    match validate_record_set("www", &[pkey.clone(), pkey.clone()], now) {
+      Err(RecordSetValidationError::DuplicatePkey { .. }) => (),
+      x => panic!("unexpected result: {:?}", x),
+    };
+
+    let expired = Record::new(RecordType::A, vec![1, 2, 3, 4], 999, 0);
+    match validate_record_set("www", &[expired], now) {
+      Err(RecordSetValidationError::Expired { expiration_time: 999, .. }) => (),
+      x => panic!("unexpected result: {:?}", x),
+    };
+    let relative = Record::new(RecordType::A, vec![1, 2, 3, 4], 999, RF_RELATIVE_EXPIRATION);
+    assert!(validate_record_set("www", &[relative], now).is_ok());
+
+    let shadow = Record::new(RecordType::A, vec![5, 6, 7, 8], u64::MAX, RF_SHADOW_RECORD);
+    match validate_record_set("www", &[shadow.clone()], now) {
+      Err(RecordSetValidationError::OrphanShadow { .. }) => (),
+      x => panic!("unexpected result: {:?}", x),
+    };
+    assert!(validate_record_set("www", &[a.clone(), shadow.clone()], now).is_ok());
+    let private = Record::new(RecordType::A, vec![1, 2, 3, 4], u64::MAX, RF_PRIVATE);
+    match validate_record_set("www", &[private, shadow], now) {
+      Err(RecordSetValidationError::ShadowPrivacyMismatch { .. }) => (),
+      x => panic!("unexpected result: {:?}", x),
+    };
+  }
+}
@@ -0,0 +1,314 @@
+//! Module for registering listening addresses with the NAT service.
+//!
+//! A program that listens on its own sockets registers their addresses with the NAT service. The
+//! service works out which addresses other peers can reach them on, taking account of NAT boxes,
+//! UPnP and addresses given in the config, and reports each address as it is found or goes
+//! away. Those are the addresses the program should advertise.
+//!
+//! # Example
+//!
+//! ```rust
+//! use gnunet::{Cfg, nat};
+//!
+//! let config = Cfg::default().unwrap();
+//! let addr = "0.0.0.0:2086".parse().unwrap();
+//! let nat = nat::Nat::register(&config, "transport-tcp", nat::Protocol::Tcp, &[addr], false).unwrap();
+//! for event in nat {
+//!   match event.unwrap() {
+//!     nat::NatEvent::AddressAdded { addr, .. }   => println!("advertise {}", addr),
+//!     nat::NatEvent::AddressRemoved { addr, .. } => println!("stop advertising {}", addr),
+//!     nat::NatEvent::ReversalRequested(_)        => (),
+//!   }
+//! }
+//! ```
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use byteorder::{BigEndian, NativeEndian, ReadBytesExt, WriteBytesExt};
+use libc;
+use num::ToPrimitive;
+
+use ll;
+use Cfg;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+
+/// Ask to be told about the external addresses of the registered sockets.
+const RF_ADDRESSES: u8 = 1;
+/// Ask to be told when another peer asks us to connect to it.
+const RF_REVERSAL: u8 = 2;
+
+/// The transport protocol of the registered sockets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+  /// TCP.
+  Tcp,
+  /// UDP.
+  Udp,
+}
+
+impl Protocol {
+  fn to_ipproto(&self) -> u8 {
+    match *self {
+      Protocol::Tcp => ll::IPPROTO_TCP as u8,
+      Protocol::Udp => ll::IPPROTO_UDP as u8,
+    }
+  }
+}
+
+/// What kind of network an address reported by the NAT service is on. This is a set of flags.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AddressClass {
+  /// The flags as sent by the service.
+  pub bits: u32,
+}
+
+impl AddressClass {
+  /// The address is in a private range, eg. `10.0.0.0/8`.
+  pub fn is_private(&self) -> bool {
+    self.bits & 2 != 0
+  }
+
+  /// The address is globally routable.
+  pub fn is_global(&self) -> bool {
+    self.bits & 4 != 0
+  }
+
+  /// The address is on the local network.
+  pub fn is_lan(&self) -> bool {
+    self.bits & 8 != 0
+  }
+
+  /// The address is a loopback address.
+  pub fn is_loopback(&self) -> bool {
+    self.bits & 64 != 0
+  }
+
+  /// The address is the external address of a NAT box, found by the service.
+  pub fn is_extern(&self) -> bool {
+    self.bits & 128 != 0
+  }
+
+  /// The address was set in the config.
+  pub fn is_manual(&self) -> bool {
+    self.bits & 256 != 0
+  }
+}
+
+/// Something reported by the NAT service about the registered sockets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NatEvent {
+  /// Other peers can reach us at `addr`.
+  AddressAdded {
+    /// The address.
+    addr: SocketAddr,
+    /// What kind of network the address is on.
+    class: AddressClass,
+  },
+  /// Other peers can no longer reach us at `addr`.
+  AddressRemoved {
+    /// The address.
+    addr: SocketAddr,
+    /// What kind of network the address is on.
+    class: AddressClass,
+  },
+  /// A peer behind a NAT box can't accept connections and has asked us to connect to it at this
+  /// address instead.
+  ReversalRequested(SocketAddrV4),
+}
+
+/// Errors returned by `Nat::register`.
+error_def! RegisterError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the NAT service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the NAT service" ("Specifically: {}", cause),
+  TooLong
+    => "The addresses and config section name are too long to fit in a message",
+}
+
+/// Errors returned by `Nat::next_event`.
+error_def! NatError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the NAT service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the NAT service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The NAT service sent an unexpected message type" ("Message type {} was not expected", ty),
+  InvalidAddress { family: u16 }
+    => "The NAT service sent an address of an unknown family" ("Address family: {}", family),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {NatError}
+
+/// A registration of listening sockets with the NAT service.
+///
+/// The registration lasts until this is dropped. Events are received with `next_event` or by
+/// iterating over the registration, which blocks until the next event arrives. If the connection
+/// to the service is lost the iterator returns a `Disconnected` error and ends.
+pub struct Nat {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  disconnected: bool,
+}
+
+impl Nat {
+  /// Register the sockets bound to `addrs` with the NAT service.
+  ///
+  /// `config_section` is the section of the config holding NAT settings for these sockets, eg.
+  /// `transport-tcp`. If `watch_reversals` is set, requests from other peers to connect to them
+  /// are reported as `NatEvent::ReversalRequested`.
+  pub fn register(cfg: &Cfg,
+                  config_section: &str,
+                  protocol: Protocol,
+                  addrs: &[SocketAddr],
+                  watch_reversals: bool) -> Result<Nat, RegisterError> {
+    let addrs_len = addrs.iter().fold(0, |acc, a| acc + sockaddr_len(a));
+    let str_len = config_section.len() + 1;
+    let (msg_length, str_len, num_addrs) = match ((10 + addrs_len + str_len).to_u16(), str_len.to_u16(), addrs.len().to_u16()) {
+      (Some(m), Some(s), Some(n)) => (m, s, n),
+      _                           => return Err(RegisterError::TooLong),
+    };
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "nat"));
+    {
+      let mut mw = service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_NAT_REGISTER);
+      let flags = match watch_reversals {
+        true  => RF_ADDRESSES | RF_REVERSAL,
+        false => RF_ADDRESSES,
+      };
+      mw.write_u8(flags).unwrap();
+      mw.write_u8(protocol.to_ipproto()).unwrap();
+      mw.write_u16::<BigEndian>(str_len).unwrap();
+      mw.write_u16::<BigEndian>(num_addrs).unwrap();
+      for addr in addrs {
+        write_sockaddr(&mut mw, addr).unwrap();
+      }
+      mw.write_all(config_section.as_bytes()).unwrap();
+      mw.write_u8(0).unwrap();
+      try!(mw.send());
+    }
+    Ok(Nat {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      disconnected: false,
+    })
+  }
+
+  /// Ask the peer behind a NAT box at `remote` to connect to us at `local`. `local` must be one of
+  /// the registered addresses.
+  pub fn request_reversal(&mut self, local: SocketAddrV4, remote: SocketAddrV4) -> Result<(), io::Error> {
+    let mut mw = self.service_writer.write_message(8 + 2 * 16, ll::GNUNET_MESSAGE_TYPE_NAT_REQUEST_CONNECTION_REVERSAL);
+    mw.write_u16::<BigEndian>(16).unwrap();
+    mw.write_u16::<BigEndian>(16).unwrap();
+    write_sockaddr(&mut mw, &SocketAddr::V4(local)).unwrap();
+    write_sockaddr(&mut mw, &SocketAddr::V4(remote)).unwrap();
+    mw.send()
+  }
+
+  /// Wait for the next event from the service.
+  pub fn next_event(&mut self) -> Result<NatEvent, NatError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    match tpe {
+      ll::GNUNET_MESSAGE_TYPE_NAT_ADDRESS_CHANGE => {
+        let add_remove = try!(mr.read_i32::<BigEndian>());
+        let class = AddressClass { bits: try!(mr.read_u32::<BigEndian>()) };
+        let addr = try!(read_sockaddr(&mut mr));
+        Ok(match add_remove {
+          0 => NatEvent::AddressRemoved { addr: addr, class: class },
+          _ => NatEvent::AddressAdded { addr: addr, class: class },
+        })
+      },
+      ll::GNUNET_MESSAGE_TYPE_NAT_CONNECTION_REVERSAL_REQUESTED => {
+        match try!(read_sockaddr(&mut mr)) {
+          SocketAddr::V4(addr)  => Ok(NatEvent::ReversalRequested(addr)),
+          SocketAddr::V6(_)     => Err(NatError::InvalidAddress { family: libc::AF_INET6 as u16 }),
+        }
+      },
+      x => Err(NatError::UnexpectedMessageType { ty: x }),
+    }
+  }
+}
+
+impl Iterator for Nat {
+  type Item = Result<NatEvent, NatError>;
+
+  fn next(&mut self) -> Option<Result<NatEvent, NatError>> {
+    if self.disconnected {
+      return None;
+    }
+    match self.next_event() {
+      Err(NatError::ReadMessage { .. })
+      | Err(NatError::Disconnected)   => {
+        self.disconnected = true;
+        Some(Err(NatError::Disconnected))
+      },
+      res                             => Some(res),
+    }
+  }
+}
+
+/// The size of the `struct sockaddr` the service expects for `addr`.
+fn sockaddr_len(addr: &SocketAddr) -> usize {
+  match *addr {
+    SocketAddr::V4(_) => 16,
+    SocketAddr::V6(_) => 28,
+  }
+}
+
+/// Write `addr` as a `struct sockaddr_in` or `struct sockaddr_in6`. The family is in host byte
+/// order, everything else in network byte order.
+fn write_sockaddr<W: Write>(w: &mut W, addr: &SocketAddr) -> Result<(), io::Error> {
+  match *addr {
+    SocketAddr::V4(ref a) => {
+      try!(w.write_u16::<NativeEndian>(libc::AF_INET as u16));
+      try!(w.write_u16::<BigEndian>(a.port()));
+      try!(w.write_all(&a.ip().octets()[..]));
+      w.write_all(&[0u8; 8])
+    },
+    SocketAddr::V6(ref a) => {
+      try!(w.write_u16::<NativeEndian>(libc::AF_INET6 as u16));
+      try!(w.write_u16::<BigEndian>(a.port()));
+      try!(w.write_u32::<BigEndian>(a.flowinfo()));
+      try!(w.write_all(&a.ip().octets()[..]));
+      w.write_u32::<NativeEndian>(a.scope_id())
+    },
+  }
+}
+
+fn read_sockaddr<R: Read>(r: &mut R) -> Result<SocketAddr, NatError> {
+  let family = try!(r.read_u16::<NativeEndian>());
+  let port = try!(r.read_u16::<BigEndian>());
+  if family == libc::AF_INET as u16 {
+    let mut octets = [0u8; 4];
+    try!(r.read_exact(&mut octets[..]));
+    return Ok(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(octets), port)));
+  }
+  if family == libc::AF_INET6 as u16 {
+    let flowinfo = try!(r.read_u32::<BigEndian>());
+    let mut octets = [0u8; 16];
+    try!(r.read_exact(&mut octets[..]));
+    let scope_id = try!(r.read_u32::<NativeEndian>());
+    return Ok(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(octets), port, flowinfo, scope_id)));
+  }
+  Err(NatError::InvalidAddress { family: family })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use std::net::SocketAddr;
+  use super::{read_sockaddr, sockaddr_len, write_sockaddr};
+
+  #[test]
+  fn test_sockaddr_round_trip() {
+    for s in ["192.168.1.7:2086", "[2001:db8::1]:2086"].iter() {
+      let addr: SocketAddr = s.parse().unwrap();
+      let mut buf = Vec::new();
+      write_sockaddr(&mut buf, &addr).unwrap();
+      assert_eq!(buf.len(), sockaddr_len(&addr));
+      assert_eq!(&buf[2..4], &[0x08, 0x26]);
+      assert_eq!(read_sockaddr(&mut Cursor::new(buf)).unwrap(), addr);
+    }
+    assert!(read_sockaddr(&mut Cursor::new(vec![0xff, 0xff, 0, 0])).is_err());
+  }
+}
@@ -0,0 +1,244 @@
+//! Module for receiving network size estimates from the NSE service.
+//!
+//! The NSE service estimates the number of peers in the network from flood messages. Estimates are
+//! given as the base-2 logarithm of the network size along with a standard deviation. An
+//! `EstimateHistory` can be used to smooth out the estimates received over time.
+//!
+//! # Example
+//!
+//! ```rust
+//! use gnunet::{Cfg, nse};
+//!
+//! let config = Cfg::default().unwrap();
+//! let mut nse = nse::NSE::connect(&config).unwrap();
+//! let estimate = nse.next_estimate().unwrap();
+//! println!("There are about {} peers in the network", estimate.size());
+//! ```
+
+use std::collections::VecDeque;
+use std::io;
+use byteorder::{BigEndian, NativeEndian, ReadBytesExt};
+
+use ll;
+use Cfg;
+use service::{self, ServiceReader, ServiceWriter};
+
+/// Convert the base-2 logarithm of a network size, as estimated by NSE, to a number of peers.
+pub fn log2_to_size(log2_size: f64) -> f64 {
+  log2_size.exp2()
+}
+
+/// Convert a number of peers to the base-2 logarithm used by NSE.
+pub fn size_to_log2(size: f64) -> f64 {
+  size.log2()
+}
+
+/// A network size estimate received from the NSE service.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Estimate {
+  /// When the estimate was made, in microseconds since the epoch.
+  pub timestamp: u64,
+  /// The base-2 logarithm of the estimated network size.
+  pub log2_size: f64,
+  /// The standard deviation of `log2_size`.
+  pub std_deviation: f64,
+}
+
+impl Estimate {
+  /// The estimated number of peers in the network.
+  pub fn size(&self) -> f64 {
+    log2_to_size(self.log2_size)
+  }
+
+  /// The range of network sizes within one standard deviation of the estimate.
+  pub fn size_range(&self) -> (f64, f64) {
+    (log2_to_size(self.log2_size - self.std_deviation),
+     log2_to_size(self.log2_size + self.std_deviation))
+  }
+}
+
+/// A handle to the NSE service.
+///
+/// Once connected the service sends its current estimate, then a new estimate each time it
+/// changes. Estimates are received with `next_estimate` or by iterating over the handle, which
+/// blocks until the next estimate arrives. If the connection to the service is lost the iterator
+/// returns a `Disconnected` error and ends.
+pub struct NSE {
+  service_reader: ServiceReader,
+  _service_writer: ServiceWriter,
+  disconnected: bool,
+}
+
+/// Errors returned by `NSE::connect`.
+error_def! ConnectError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the NSE service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the NSE service" ("Specifically: {}", cause),
+}
+
+/// Errors returned by `NSE::next_estimate`.
+error_def! ReadEstimateError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the NSE service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the service" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {ReadEstimateError}
+
+impl NSE {
+  /// Connect to the NSE service and subscribe to its estimates.
+  pub fn connect(cfg: &Cfg) -> Result<NSE, ConnectError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "nse"));
+    {
+      let mw = service_writer.write_message(4, ll::GNUNET_MESSAGE_TYPE_NSE_START);
+      try!(mw.send());
+    };
+    Ok(NSE {
+      service_reader: service_reader,
+      _service_writer: service_writer,
+      disconnected: false,
+    })
+  }
+
+  /// Wait for the next estimate from the service.
+  pub fn next_estimate(&mut self) -> Result<Estimate, ReadEstimateError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_NSE_ESTIMATE {
+      return Err(ReadEstimateError::UnexpectedMessageType { ty: tpe });
+    }
+    let _reserved = try!(mr.read_u32::<BigEndian>());
+    let timestamp = try!(mr.read_u64::<BigEndian>());
+    // The service sends the estimate as doubles in host byte order.
+    let log2_size = try!(mr.read_f64::<NativeEndian>());
+    let std_deviation = try!(mr.read_f64::<NativeEndian>());
+    Ok(Estimate {
+      timestamp: timestamp,
+      log2_size: log2_size,
+      std_deviation: std_deviation,
+    })
+  }
+}
+
+impl Iterator for NSE {
+  type Item = Result<Estimate, ReadEstimateError>;
+
+  fn next(&mut self) -> Option<Result<Estimate, ReadEstimateError>> {
+    if self.disconnected {
+      return None;
+    }
+    match self.next_estimate() {
+      Err(ReadEstimateError::ReadMessage { .. })
+      | Err(ReadEstimateError::Disconnected)  => {
+        self.disconnected = true;
+        Some(Err(ReadEstimateError::Disconnected))
+      },
+      res                                     => Some(res),
+    }
+  }
+}
+
+/// A rolling history of the most recent network size estimates.
+#[derive(Clone, Debug)]
+pub struct EstimateHistory {
+  capacity: usize,
+  estimates: VecDeque<Estimate>,
+}
+
+impl EstimateHistory {
+  /// Create an empty history which remembers at most `capacity` estimates.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `capacity` is zero.
+  pub fn new(capacity: usize) -> EstimateHistory {
+    assert!(capacity > 0);
+    EstimateHistory {
+      capacity: capacity,
+      estimates: VecDeque::with_capacity(capacity),
+    }
+  }
+
+  /// Add an estimate, forgetting the oldest one if the history is full.
+  pub fn push(&mut self, estimate: Estimate) {
+    if self.estimates.len() == self.capacity {
+      self.estimates.pop_front();
+    }
+    self.estimates.push_back(estimate);
+  }
+
+  /// The most recent estimate.
+  pub fn latest(&self) -> Option<&Estimate> {
+    self.estimates.back()
+  }
+
+  /// The estimates in the history, oldest first.
+  pub fn iter(&self) -> ::std::collections::vec_deque::Iter<Estimate> {
+    self.estimates.iter()
+  }
+
+  /// The number of estimates in the history.
+  pub fn len(&self) -> usize {
+    self.estimates.len()
+  }
+
+  /// Returns `true` if the history is empty.
+  pub fn is_empty(&self) -> bool {
+    self.estimates.is_empty()
+  }
+
+  /// The mean of the base-2 logarithms of the estimates.
+  pub fn mean_log2_size(&self) -> Option<f64> {
+    if self.estimates.is_empty() {
+      return None;
+    }
+    let sum = self.estimates.iter().fold(0.0, |acc, e| acc + e.log2_size);
+    Some(sum / self.estimates.len() as f64)
+  }
+
+  /// The variance of the base-2 logarithms of the estimates.
+  pub fn variance_log2_size(&self) -> Option<f64> {
+    let mean = match self.mean_log2_size() {
+      Some(m) => m,
+      None    => return None,
+    };
+    let sum = self.estimates.iter().fold(0.0, |acc, e| {
+      let d = e.log2_size - mean;
+      acc + d * d
+    });
+    Some(sum / self.estimates.len() as f64)
+  }
+
+  /// The network size corresponding to the mean of the estimates.
+  pub fn mean_size(&self) -> Option<f64> {
+    self.mean_log2_size().map(log2_to_size)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_estimate_history() {
+    let mut history = EstimateHistory::new(3);
+    assert_eq!(history.mean_size(), None);
+    for &log2_size in [1.0, 10.0, 11.0, 12.0].iter() {
+      history.push(Estimate {
+        timestamp: 0,
+        log2_size: log2_size,
+        std_deviation: 0.5,
+      });
+    }
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.mean_log2_size(), Some(11.0));
+    assert_eq!(history.mean_size(), Some(2048.0));
+    assert!((history.variance_log2_size().unwrap() - 2.0 / 3.0).abs() < 1e-9);
+    assert_eq!(history.latest().unwrap().size(), 4096.0);
+    assert_eq!(size_to_log2(1024.0), 10.0);
+  }
+}
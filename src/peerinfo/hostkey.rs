@@ -0,0 +1,66 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use Cfg;
+use crypto::EddsaPrivateKey;
+use peerinfo::PeerIdentity;
+
+/// The name of the hostkey file inside a peer's `SERVICEHOME`.
+pub const HOSTKEY_FILE_NAME: &'static str = "private_key.ecc";
+
+/// Write `key` to `path` in the format the peer reads its hostkey from. The file is only readable
+/// by its owner and must not exist yet.
+pub fn write_hostkey<P: AsRef<Path>>(path: P, key: &EddsaPrivateKey) -> Result<(), io::Error> {
+  let mut f = try!(OpenOptions::new().write(true).create_new(true).mode(0o600).open(path));
+  key.serialize(&mut f)
+}
+
+/// Read a hostkey written by `write_hostkey` or by the peer itself.
+pub fn read_hostkey<P: AsRef<Path>>(path: P) -> Result<EddsaPrivateKey, io::Error> {
+  let mut f = try!(File::open(path));
+  EddsaPrivateKey::deserialize(&mut f)
+}
+
+/// Set up `home` as the `SERVICEHOME` of a fresh peer whose hostkey is `key`, and point `cfg` at
+/// it. Returns the identity the peer will have.
+///
+/// `home` is created if it doesn't exist and the hostkey is written to `HOSTKEY_FILE_NAME` inside
+/// it. `[PATHS] SERVICEHOME` and `[PEER] PRIVATE_KEY` are set in `cfg`, which can then be saved
+/// and given to `gnunet-arm -c` to start the peer. Together with `EddsaPrivateKey::from_seed`
+/// this gives a test harness peers with known identities.
+pub fn setup_peer_home<P: AsRef<Path>>(cfg: &mut Cfg, home: P, key: &EddsaPrivateKey) -> Result<PeerIdentity, io::Error> {
+  let home = home.as_ref();
+  try!(fs::create_dir_all(home));
+  let mut keyfile = PathBuf::from(home);
+  keyfile.push(HOSTKEY_FILE_NAME);
+  try!(write_hostkey(&keyfile, key));
+  cfg.set_string("PATHS", "SERVICEHOME", home.to_string_lossy().into_owned());
+  cfg.set_string("PEER", "PRIVATE_KEY", keyfile.to_string_lossy().into_owned());
+  Ok(key.peer_identity())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use Cfg;
+  use crypto::EddsaPrivateKey;
+  use super::*;
+
+  #[test]
+  fn test_setup_peer_home() {
+    let mut home = env::temp_dir();
+    home.push(format!("gnunet-rs-hostkey-{}", ::rand::random::<u32>()));
+    let key = EddsaPrivateKey::from_seed(b"test peer");
+    let mut cfg = Cfg::empty();
+    let id = setup_peer_home(&mut cfg, &home, &key).unwrap();
+    let keyfile = cfg.get_filename("PEER", "PRIVATE_KEY").unwrap();
+    let read = read_hostkey(&keyfile).unwrap();
+    assert!(setup_peer_home(&mut cfg, &home, &key).is_err());
+    fs::remove_dir_all(&home).unwrap();
+    assert!(read.peer_identity() == id);
+    assert!(id == key.peer_identity());
+  }
+}
@@ -0,0 +1,265 @@
+use std::io::{self, Cursor, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use PeerIdentity;
+use service::{self, ReadMessageError};
+use time;
+
+/// What `import_hellos` did with one HELLO.
+#[derive(Debug)]
+pub enum ImportOutcome {
+  /// The HELLO was handed to the peerinfo service.
+  Imported(PeerIdentity),
+  /// Every address in the HELLO has expired, so it was not imported.
+  Expired(PeerIdentity),
+  /// The HELLO is malformed and was not imported.
+  Invalid(HelloValidationError),
+  /// The HELLO carries no signature, so it was not imported. See `import_unsigned_hellos`.
+  Unsigned(PeerIdentity),
+}
+
+/// The result of `import_hellos`: one outcome per HELLO, in the order they were given.
+#[derive(Debug)]
+pub struct ImportReport {
+  pub outcomes: Vec<ImportOutcome>,
+}
+
+impl ImportReport {
+  /// The number of HELLOs which were imported.
+  pub fn imported(&self) -> usize {
+    self.outcomes.iter().filter(|o| match **o { ImportOutcome::Imported(_) => true, _ => false }).count()
+  }
+
+  /// The number of HELLOs which were skipped because all their addresses had expired.
+  pub fn expired(&self) -> usize {
+    self.outcomes.iter().filter(|o| match **o { ImportOutcome::Expired(_) => true, _ => false }).count()
+  }
+
+  /// The number of HELLOs which were skipped because they were malformed.
+  pub fn invalid(&self) -> usize {
+    self.outcomes.iter().filter(|o| match **o { ImportOutcome::Invalid(_) => true, _ => false }).count()
+  }
+
+  /// The number of HELLOs which were skipped because they carry no signature.
+  pub fn unsigned(&self) -> usize {
+    self.outcomes.iter().filter(|o| match **o { ImportOutcome::Unsigned(_) => true, _ => false }).count()
+  }
+}
+
+/// Reasons a HELLO is rejected by `import_hellos`.
+error_def! HelloValidationError {
+  TooShort { len: usize }
+    => "The HELLO is too short" ("A HELLO of {} bytes is too short", len),
+  NotAHello { ty: u16 }
+    => "The message is not a HELLO" ("Message type {} is not HELLO", ty),
+  SizeMismatch { size: u16, len: usize }
+    => "The size in the HELLO's header is wrong" ("Header says {} bytes, got {}", size, len),
+  MalformedAddress
+    => "An address in the HELLO is malformed",
+}
+
+/// Errors returned by `import_hellos`.
+error_def! ImportHellosError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the peerinfo service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the peerinfo service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the peerinfo service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The peerinfo service sent an unexpected response message type" ("Message type {} was not expected", ty),
+}
+
+/// Read one address entry of a HELLO. Returns its expiration time.
+fn read_address(r: &mut Cursor<&[u8]>) -> Result<u64, io::Error> {
+  // The transport plugin name, 0-terminated.
+  loop {
+    if try!(r.read_u8()) == 0 {
+      break;
+    }
+  }
+  let addr_len = try!(r.read_u16::<BigEndian>());
+  let expiration = try!(r.read_u64::<BigEndian>());
+  let mut addr = vec![0u8; addr_len as usize];
+  try!(r.read_exact(&mut addr[..]));
+  Ok(expiration)
+}
+
+/// Check a complete HELLO message, including its header. `now` is the current time in
+/// microseconds since the epoch. HELLOs have no signature to check, so well-formed ones are only
+/// imported if `accept_unsigned` is set.
+fn check_hello(msg: &[u8], now: u64, accept_unsigned: bool) -> ImportOutcome {
+  // header, friend-only flag and peer identity
+  const HEADER_LEN: usize = 4 + 4 + 32;
+
+  if msg.len() < HEADER_LEN {
+    return ImportOutcome::Invalid(HelloValidationError::TooShort { len: msg.len() });
+  }
+  let mut r = Cursor::new(msg);
+  let size = r.read_u16::<BigEndian>().unwrap();
+  let ty = r.read_u16::<BigEndian>().unwrap();
+  if ty != ll::GNUNET_MESSAGE_TYPE_HELLO {
+    return ImportOutcome::Invalid(HelloValidationError::NotAHello { ty: ty });
+  }
+  if size as usize != msg.len() {
+    return ImportOutcome::Invalid(HelloValidationError::SizeMismatch { size: size, len: msg.len() });
+  }
+  let _friend_only = r.read_u32::<BigEndian>().unwrap();
+  let id = PeerIdentity::deserialize(&mut r).unwrap();
+
+  let mut addresses = 0;
+  let mut live = 0;
+  while (r.position() as usize) < msg.len() {
+    match read_address(&mut r) {
+      Ok(expiration) => {
+        addresses += 1;
+        if expiration > now {
+          live += 1;
+        }
+      },
+      Err(_) => return ImportOutcome::Invalid(HelloValidationError::MalformedAddress),
+    }
+  }
+  if !accept_unsigned {
+    return ImportOutcome::Unsigned(id);
+  }
+  // A HELLO without any addresses still tells peerinfo the peer exists.
+  match addresses > 0 && live == 0 {
+    true  => ImportOutcome::Expired(id),
+    false => ImportOutcome::Imported(id),
+  }
+}
+
+/// Add many HELLOs to the peerinfo service over a single connection, eg. when bootstrapping from
+/// a hostlist or restoring a dump of another peer's peerinfo.
+///
+/// Each item is a complete HELLO message, header included. HELLOs are checked before they are
+/// sent: malformed ones, ones whose addresses have all expired and unsigned ones are skipped.
+/// HELLOs in GNUnet 0.10 carry no signature, so this imports none of them and only reports what
+/// they are. Use `import_unsigned_hellos` for HELLOs from a source you trust.
+///
+/// Returns once the peerinfo service has processed every imported HELLO.
+pub fn import_hellos<I, B>(cfg: &Cfg, hellos: I) -> Result<ImportReport, ImportHellosError>
+    where I: IntoIterator<Item=B>,
+          B: AsRef<[u8]>
+{
+  import(cfg, hellos, false)
+}
+
+/// Like `import_hellos` but imports well-formed HELLOs without a signature too, which includes
+/// every HELLO of GNUnet 0.10.
+///
+/// Nothing vouches for the addresses in such a HELLO until the transport service contacts the
+/// peer and validates them, so only use this for HELLOs from a source you trust, eg. the local
+/// transport service or a dump of your own peer's peerinfo.
+pub fn import_unsigned_hellos<I, B>(cfg: &Cfg, hellos: I) -> Result<ImportReport, ImportHellosError>
+    where I: IntoIterator<Item=B>,
+          B: AsRef<[u8]>
+{
+  import(cfg, hellos, true)
+}
+
+fn import<I, B>(cfg: &Cfg, hellos: I, accept_unsigned: bool) -> Result<ImportReport, ImportHellosError>
+    where I: IntoIterator<Item=B>,
+          B: AsRef<[u8]>
+{
+  let now = time::now_micros();
+  let (mut sr, mut sw) = try!(service::connect(cfg, "peerinfo"));
+  let mut outcomes = Vec::new();
+  let mut last = None;
+  for hello in hellos {
+    let msg = hello.as_ref();
+    let outcome = check_hello(msg, now, accept_unsigned);
+    if let ImportOutcome::Imported(id) = outcome {
+      // The message is already framed, so it is sent as it is.
+      let mut mw = sw.write_message(msg.len() as u16, ll::GNUNET_MESSAGE_TYPE_HELLO);
+      mw.write_all(&msg[4..]).unwrap();
+      try!(mw.send());
+      last = Some(id);
+    }
+    outcomes.push(outcome);
+  }
+
+  // The service handles a client's messages in order, so once it has answered a request sent
+  // after the HELLOs it has stored all of them.
+  if let Some(id) = last {
+    {
+      let mut mw = sw.write_message(40, ll::GNUNET_MESSAGE_TYPE_PEERINFO_GET);
+      mw.write_u32::<BigEndian>(0).unwrap();
+      id.serialize(&mut mw).unwrap();
+      try!(mw.send());
+    }
+    loop {
+      let (tpe, _) = try!(sr.read_message());
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_PEERINFO_INFO     => (),
+        ll::GNUNET_MESSAGE_TYPE_PEERINFO_INFO_END => break,
+        x => return Err(ImportHellosError::UnexpectedMessageType { ty: x }),
+      }
+    }
+  }
+  Ok(ImportReport {
+    outcomes: outcomes,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use byteorder::{BigEndian, WriteBytesExt};
+  use ll;
+  use super::{check_hello, ImportOutcome};
+
+  fn hello(expirations: &[u64]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.write_u32::<BigEndian>(0).unwrap();
+    body.extend_from_slice(&[7u8; 32]);
+    for &expiration in expirations {
+      body.extend_from_slice(b"tcp\0");
+      body.write_u16::<BigEndian>(6).unwrap();
+      body.write_u64::<BigEndian>(expiration).unwrap();
+      body.extend_from_slice(&[127, 0, 0, 1, 8, 8]);
+    }
+    let mut msg = Vec::new();
+    msg.write_u16::<BigEndian>(body.len() as u16 + 4).unwrap();
+    msg.write_u16::<BigEndian>(ll::GNUNET_MESSAGE_TYPE_HELLO).unwrap();
+    msg.extend_from_slice(&body[..]);
+    msg
+  }
+
+  #[test]
+  fn test_check_hello() {
+    let now = 1000;
+    match check_hello(&hello(&[500, 2000])[..], now, true) {
+      ImportOutcome::Imported(_) => (),
+      o => panic!("unexpected outcome: {:?}", o),
+    };
+    match check_hello(&hello(&[])[..], now, true) {
+      ImportOutcome::Imported(_) => (),
+      o => panic!("unexpected outcome: {:?}", o),
+    };
+    match check_hello(&hello(&[500, 999])[..], now, true) {
+      ImportOutcome::Expired(_) => (),
+      o => panic!("unexpected outcome: {:?}", o),
+    };
+
+    let mut truncated = hello(&[2000]);
+    truncated.pop();
+    let len = truncated.len() as u16;
+    truncated[0] = (len >> 8) as u8;
+    truncated[1] = len as u8;
+    match check_hello(&truncated[..], now, true) {
+      ImportOutcome::Invalid(_) => (),
+      o => panic!("unexpected outcome: {:?}", o),
+    };
+    match check_hello(&hello(&[2000])[..10], now, false) {
+      ImportOutcome::Invalid(_) => (),
+      o => panic!("unexpected outcome: {:?}", o),
+    };
+    match check_hello(&hello(&[2000])[..], now, false) {
+      ImportOutcome::Unsigned(_) => (),
+      o => panic!("unexpected outcome: {:?}", o),
+    };
+  }
+}
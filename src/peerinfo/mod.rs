@@ -1,4 +1,7 @@
-pub use self::peerinfo::{iterate_peers, self_id, PeerIdentity};
+pub use self::peerinfo::{iterate_peers, get_peer, self_id, self_id_with_retry, PeerIdentity};
+pub use self::hostkey::{write_hostkey, read_hostkey, setup_peer_home, HOSTKEY_FILE_NAME};
+pub use self::import::{import_hellos, import_unsigned_hellos, ImportOutcome, ImportReport, HelloValidationError, ImportHellosError};
 
 pub mod peerinfo;
-
+mod hostkey;
+mod import;
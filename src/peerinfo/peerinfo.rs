@@ -1,21 +1,40 @@
-use std::mem::{uninitialized, size_of_val};
+use std::mem::uninitialized;
 use std::fmt;
-use std::str::{from_utf8, FromStr};
-use std::io::{self, Read, Write};
-use libc::{c_void, c_char, size_t};
+use std::hash;
+use std::str::FromStr;
+use std::io::{self, Read, Write, Cursor};
+use libc::{c_void, size_t};
 use byteorder::{self, BigEndian, ReadBytesExt, WriteBytesExt};
 
 use ll;
+use data;
 use Cfg;
-use service::{self, connect, ServiceReader, ReadMessageError};
+use service::{self, connect, ServiceReader, ReadMessageError, RetryPolicy};
 use Hello;
 use transport::{self, TransportServiceInitError};
 
 /// The identity of a GNUnet peer.
+#[derive(Copy, Clone)]
 pub struct PeerIdentity {
   data: ll::Struct_GNUNET_PeerIdentity,
 }
 
+impl PartialEq for PeerIdentity {
+  fn eq(&self, other: &PeerIdentity) -> bool {
+    self.data.public_key.q_y == other.data.public_key.q_y
+  }
+}
+
+impl Eq for PeerIdentity {}
+
+impl hash::Hash for PeerIdentity {
+  fn hash<H>(&self, state: &mut H)
+      where H: hash::Hasher
+  {
+    self.data.public_key.q_y.hash(state)
+  }
+}
+
 impl PeerIdentity {
   pub fn deserialize<R>(r: &mut R) -> Result<PeerIdentity, io::Error> where R: Read {
     let mut ret: PeerIdentity = unsafe { uninitialized() };
@@ -71,11 +90,33 @@ pub fn iterate_peers(cfg: &Cfg) -> Result<Peers, IteratePeersError> {
   })
 } 
 
+/// Get the information the peerinfo service has about a single peer.
+///
+/// The returned iterator yields at most one item.
+pub fn get_peer(cfg: &Cfg, peer: &PeerIdentity) -> Result<Peers, IteratePeersError> {
+  let (sr, mut sw) = try!(connect(cfg, "peerinfo"));
+
+  let msg_length = 40u16;
+  let mut mw = sw.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_PEERINFO_GET);
+  mw.write_u32::<BigEndian>(0).unwrap();
+  peer.serialize(&mut mw).unwrap();
+  try!(mw.send());
+  Ok(Peers {
+    service: sr,
+  })
+}
+
 pub fn self_id(cfg: &Cfg) -> Result<PeerIdentity, TransportServiceInitError> {
   let hello = try!(transport::self_hello(cfg));
   Ok(hello.id)
 }
 
+/// Like `self_id` but retries according to `policy` if the transport service can't be reached,
+/// eg. because it is being restarted.
+pub fn self_id_with_retry(cfg: &Cfg, policy: &RetryPolicy) -> Result<PeerIdentity, TransportServiceInitError> {
+  policy.run(|| self_id(cfg))
+}
+
 /// An iterator over all the currently connected peers.
 pub struct Peers {
   service: ServiceReader,
@@ -96,6 +137,26 @@ error_def! NextPeerError {
 }
 byteorder_error_chain! {NextPeerError}
 
+/// Read the HELLO message that may follow the peer identity in a `PEERINFO_INFO` message.
+fn read_attached_hello(mr: &mut Cursor<Vec<u8>>) -> Result<Option<Hello>, NextPeerError> {
+  if mr.position() as usize >= mr.get_ref().len() {
+    return Ok(None);
+  }
+  let len = try!(mr.read_u16::<BigEndian>());
+  let tpe = try!(mr.read_u16::<BigEndian>());
+  if tpe != ll::GNUNET_MESSAGE_TYPE_HELLO || len < 4 {
+    return Err(NextPeerError::InvalidResponse);
+  }
+  let hello = match Hello::deserialize(&mut mr.take(len as u64 - 4)) {
+    Ok(hello) => hello,
+    Err(_)    => return Err(NextPeerError::InvalidResponse),
+  };
+  match service::check_end(mr) {
+    Ok(())  => Ok(Some(hello)),
+    Err(_)  => Err(NextPeerError::InvalidResponse),
+  }
+}
+
 impl Iterator for Peers {
   type Item = Result<(PeerIdentity, Option<Hello>), NextPeerError>;
 
@@ -114,17 +175,9 @@ impl Iterator for Peers {
           false => Some(Err(NextPeerError::InvalidResponse)),
           true  => match PeerIdentity::deserialize(&mut mr) {
             Err(e)  => Some(Err(NextPeerError::Io { cause: e })),
-            Ok(pi)  => {
-              Some(Ok((pi, None)))
-              /*
-               * when we have hello parsing
-              match mr.eof() {
-                true  => Some(Ok(pi, None)),
-                false => {
-
-                },
-              }
-              */
+            Ok(pi)  => match read_attached_hello(&mut mr) {
+              Ok(hello) => Some(Ok((pi, hello))),
+              Err(e)    => Some(Err(e)),
             },
           },
         },
@@ -137,17 +190,7 @@ impl Iterator for Peers {
 
 impl fmt::Debug for PeerIdentity {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    unsafe {
-      const LEN: usize = 52usize;
-      assert!(LEN == (size_of_val(&self.data.public_key.q_y) * 8 + 4) / 5);
-      let mut enc: [u8; LEN] = uninitialized();
-      let res = ll::GNUNET_STRINGS_data_to_string(self.data.public_key.q_y.as_ptr() as *const c_void,
-                                                  self.data.public_key.q_y.len() as size_t,
-                                                  enc.as_mut_ptr() as *mut c_char,
-                                                  enc.len() as size_t);
-      assert!(!res.is_null());
-      fmt::Display::fmt(from_utf8(&enc).unwrap(), f)
-    }
+    data::crockford_encode_fmt(f, &self.data.public_key.q_y[..])
   }
 }
 
@@ -157,3 +200,27 @@ impl fmt::Display for PeerIdentity {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use byteorder::{BigEndian, ReadBytesExt};
+  use fixtures;
+  use ll;
+  use service;
+  use super::*;
+  use super::read_attached_hello;
+
+  #[test]
+  fn test_info_fixture() {
+    let (tpe, mut mr) = service::split_frame(fixtures::PEERINFO_INFO.to_vec());
+    assert_eq!(tpe, ll::GNUNET_MESSAGE_TYPE_PEERINFO_INFO);
+    assert_eq!(mr.read_u32::<BigEndian>().unwrap(), 0);
+    let id = PeerIdentity::deserialize(&mut mr).unwrap();
+    let hello = read_attached_hello(&mut mr).unwrap().unwrap();
+    assert!(hello.id == id);
+    assert!(!hello.friend_only);
+
+    let mut reencoded = Vec::new();
+    id.serialize(&mut reencoded).unwrap();
+    assert_eq!(&reencoded[..], &fixtures::PEER[..]);
+  }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, Read, Cursor};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+use reclaim::{Attribute, Ticket, TokenError};
+
+/// A handle to a locally-running instance of the reclaim service.
+pub struct Reclaim {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  next_request_id: u32,
+}
+
+/// Errors returned by the requests of `Reclaim`.
+error_def! ReclaimError {
+  TooLong
+    => "The request was too large to send to the service",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the reclaim service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the reclaim service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The reclaim service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  InvalidAttribute { #[from] cause: TokenError }
+    => "The reclaim service sent a malformed attribute" ("Reason: {}", cause),
+  InvalidResponse
+    => "The response from the reclaim service was incoherent",
+  Failed
+    => "The reclaim service failed to perform the request",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {ReclaimError}
+
+/// The number of bytes `attribute` takes up when serialized.
+fn attribute_len(attribute: &Attribute) -> usize {
+  20 + attribute.name.len() + attribute.data.len()
+}
+
+impl Reclaim {
+  /// Connect to the reclaim service.
+  pub fn connect(cfg: &Cfg) -> Result<Reclaim, service::ConnectError> {
+    let (service_reader, service_writer) = try!(service::connect(cfg, "reclaim"));
+    Ok(Reclaim {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      next_request_id: 0,
+    })
+  }
+
+  fn request_id(&mut self) -> u32 {
+    let id = self.next_request_id;
+    self.next_request_id = self.next_request_id.wrapping_add(1);
+    id
+  }
+
+  /// Read a response to request `id` of type `expected`, returning a reader positioned after the
+  /// request id.
+  fn read_response(&mut self, id: u32, expected: u16) -> Result<Cursor<Vec<u8>>, ReclaimError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != expected {
+      return Err(ReclaimError::UnexpectedMessageType { ty: tpe });
+    }
+    if try!(mr.read_u32::<BigEndian>()) != id {
+      return Err(ReclaimError::InvalidResponse);
+    }
+    Ok(mr)
+  }
+
+  fn read_success(&mut self, id: u32) -> Result<(), ReclaimError> {
+    let mut mr = try!(self.read_response(id, ll::GNUNET_MESSAGE_TYPE_RECLAIM_SUCCESS_RESPONSE));
+    match try!(mr.read_i32::<BigEndian>()) {
+      ll::GNUNET_OK => Ok(()),
+      _             => Err(ReclaimError::Failed),
+    }
+  }
+
+  /// Store `attribute` for the ego `identity`, replacing any attribute with the same id. The
+  /// attribute expires `expiration` microseconds after it is stored.
+  pub fn store_attribute(&mut self, identity: &EcdsaPrivateKey, attribute: &Attribute, expiration: u64) -> Result<(), ReclaimError> {
+    let attr_len = attribute_len(attribute);
+    if 52 + attr_len > 0xffff {
+      return Err(ReclaimError::TooLong);
+    }
+    let id = self.request_id();
+    {
+      let mut mw = self.service_writer.write_message((52 + attr_len) as u16, ll::GNUNET_MESSAGE_TYPE_RECLAIM_ATTRIBUTE_STORE);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      mw.write_u32::<BigEndian>(attr_len as u32).unwrap();
+      mw.write_u64::<BigEndian>(expiration).unwrap();
+      identity.serialize(&mut mw).unwrap();
+      attribute.serialize(&mut mw).unwrap();
+      try!(mw.send());
+    }
+    self.read_success(id)
+  }
+
+  /// Delete `attribute` from the ego `identity`. Tickets which granted access to it no longer
+  /// do.
+  pub fn delete_attribute(&mut self, identity: &EcdsaPrivateKey, attribute: &Attribute) -> Result<(), ReclaimError> {
+    let attr_len = attribute_len(attribute);
+    if 44 + attr_len > 0xffff {
+      return Err(ReclaimError::TooLong);
+    }
+    let id = self.request_id();
+    {
+      let mut mw = self.service_writer.write_message((44 + attr_len) as u16, ll::GNUNET_MESSAGE_TYPE_RECLAIM_ATTRIBUTE_DELETE);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      mw.write_u32::<BigEndian>(attr_len as u32).unwrap();
+      identity.serialize(&mut mw).unwrap();
+      attribute.serialize(&mut mw).unwrap();
+      try!(mw.send());
+    }
+    self.read_success(id)
+  }
+
+  /// Issue a ticket from the ego `identity` granting the relying party `audience` access to
+  /// `attributes`, which must already be stored for the ego.
+  ///
+  /// The relying party redeems the ticket with `consume_ticket`.
+  pub fn issue_ticket(&mut self, identity: &EcdsaPrivateKey, audience: &EcdsaPublicKey, attributes: &[Attribute]) -> Result<Ticket, ReclaimError> {
+    let attrs_len = attributes.iter().fold(0, |acc, a| acc + attribute_len(a));
+    if 76 + attrs_len > 0xffff {
+      return Err(ReclaimError::TooLong);
+    }
+    let id = self.request_id();
+    {
+      let mut mw = self.service_writer.write_message((76 + attrs_len) as u16, ll::GNUNET_MESSAGE_TYPE_RECLAIM_ISSUE_TICKET);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      identity.serialize(&mut mw).unwrap();
+      audience.serialize(&mut mw).unwrap();
+      mw.write_u32::<BigEndian>(attrs_len as u32).unwrap();
+      for attribute in attributes.iter() {
+        attribute.serialize(&mut mw).unwrap();
+      }
+      try!(mw.send());
+    }
+    let mut mr = try!(self.read_response(id, ll::GNUNET_MESSAGE_TYPE_RECLAIM_TICKET_RESULT));
+    Ok(try!(Ticket::deserialize(&mut mr)))
+  }
+
+  /// Redeem `ticket` as the relying party `audience`, returning the attributes it grants access
+  /// to. The attributes are fetched from GNS, so the ego which issued the ticket need not be on
+  /// this peer.
+  pub fn consume_ticket(&mut self, audience: &EcdsaPrivateKey, ticket: &Ticket) -> Result<Vec<Attribute>, ReclaimError> {
+    let id = self.request_id();
+    {
+      let mut mw = self.service_writer.write_message(112, ll::GNUNET_MESSAGE_TYPE_RECLAIM_CONSUME_TICKET);
+      mw.write_u32::<BigEndian>(id).unwrap();
+      audience.serialize(&mut mw).unwrap();
+      ticket.serialize(&mut mw).unwrap();
+      try!(mw.send());
+    }
+    let mut mr = try!(self.read_response(id, ll::GNUNET_MESSAGE_TYPE_RECLAIM_CONSUME_TICKET_RESULT));
+    if try!(mr.read_i32::<BigEndian>()) != ll::GNUNET_OK {
+      return Err(ReclaimError::Failed);
+    }
+    let attrs_len = try!(mr.read_u16::<BigEndian>()) as u64;
+    let _reserved = try!(mr.read_u16::<BigEndian>());
+    let _identity = try!(EcdsaPublicKey::deserialize(&mut mr));
+    let mut attrs = (&mut mr).take(attrs_len);
+    let mut ret = Vec::new();
+    while attrs.limit() > 0 {
+      ret.push(try!(Attribute::deserialize(&mut attrs)));
+    }
+    Ok(ret)
+  }
+}
@@ -0,0 +1,13 @@
+//! Module for interoperating with re:claimID, GNUnet's self-sovereign identity system.
+//!
+//! Identities publish attributes, such as an email address, and issue tickets granting relying
+//! parties access to them. `Reclaim` talks to the reclaim service to store attributes and to
+//! issue and consume tickets. The helpers in `token` produce and check the signed authorization
+//! codes and ID tokens used in reclaim's OpenID Connect flows, so that relying parties and issuers
+//! written in Rust can take part without going through the REST server.
+
+pub use self::client::*;
+pub use self::token::*;
+
+mod client;
+mod token;
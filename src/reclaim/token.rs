@@ -0,0 +1,371 @@
+use std::io::{self, Read, Write, Cursor};
+use std::str::from_utf8;
+use std::u16;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rcrypto::hmac::Hmac;
+use rcrypto::mac::Mac;
+use rcrypto::sha2::Sha512;
+use rcrypto::util::fixed_time_eq;
+
+use ll;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use EcdsaSignature;
+use time;
+use util::base64;
+use util::io::ReadUtil;
+
+/// The attribute type of a UTF-8 string attribute.
+pub const ATTRIBUTE_TYPE_STRING: u32 = 1;
+
+/// The header of ID tokens, as written by re:claimID's OpenID Connect plugin.
+const ID_TOKEN_HEADER: &'static str = "{\"alg\":\"HS512\",\"typ\":\"JWT\"}";
+
+/// The `iss` claim of ID tokens issued by re:claimID.
+pub const ID_TOKEN_ISSUER: &'static str = "https://api.reclaim";
+
+/// A reclaim ticket: a grant from an identity allowing an audience to read some of its
+/// attributes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Ticket {
+  /// The public key of the ego which issued the ticket.
+  pub identity: EcdsaPublicKey,
+  /// The public key of the relying party the ticket was issued to.
+  pub audience: EcdsaPublicKey,
+  /// A random number distinguishing tickets with the same identity and audience.
+  pub rnd: u64,
+}
+
+impl Ticket {
+  /// Create a ticket from `identity` to `audience` with a random `rnd`.
+  pub fn new(identity: &EcdsaPublicKey, audience: &EcdsaPublicKey) -> Ticket {
+    Ticket {
+      identity: *identity,
+      audience: *audience,
+      rnd: ::rand::random(),
+    }
+  }
+
+  /// Serialize a ticket to a byte stream.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    try!(self.identity.serialize(w));
+    try!(self.audience.serialize(w));
+    try!(w.write_u64::<BigEndian>(self.rnd));
+    Ok(())
+  }
+
+  /// Deserialize a ticket from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<Ticket, io::Error> where T: Read {
+    let identity = try!(EcdsaPublicKey::deserialize(r));
+    let audience = try!(EcdsaPublicKey::deserialize(r));
+    let rnd = try!(r.read_u64::<BigEndian>());
+    Ok(Ticket {
+      identity: identity,
+      audience: audience,
+      rnd: rnd,
+    })
+  }
+}
+
+/// An attribute of an identity, such as its email address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+  /// The name of the attribute.
+  pub name: String,
+  /// The type of the attribute's data. See `ATTRIBUTE_TYPE_STRING`.
+  pub attribute_type: u32,
+  /// The version of the attribute, incremented each time it changes.
+  pub version: u32,
+  /// An identifier for the attribute, unique within the identity.
+  pub id: u64,
+  /// The value of the attribute.
+  pub data: Vec<u8>,
+}
+
+impl Attribute {
+  /// Create a string attribute with a random id.
+  pub fn new_string(name: &str, value: &str) -> Attribute {
+    Attribute {
+      name: name.to_string(),
+      attribute_type: ATTRIBUTE_TYPE_STRING,
+      version: 0,
+      id: ::rand::random(),
+      data: value.as_bytes().to_vec(),
+    }
+  }
+
+  /// The value of a string attribute.
+  pub fn as_str(&self) -> Option<&str> {
+    match self.attribute_type {
+      ATTRIBUTE_TYPE_STRING => from_utf8(&self.data[..]).ok(),
+      _                     => None,
+    }
+  }
+
+  /// Serialize an attribute to a byte stream, in the format used by reclaim.
+  ///
+  /// Fails with `InvalidInput` if the name or data is longer than 65535 bytes.
+  pub fn serialize<T>(&self, w: &mut T) -> Result<(), io::Error> where T: Write {
+    if self.name.len() > u16::MAX as usize || self.data.len() > u16::MAX as usize {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "attribute name or data too long"));
+    }
+    try!(w.write_u32::<BigEndian>(self.attribute_type));
+    try!(w.write_u32::<BigEndian>(self.version));
+    try!(w.write_u64::<BigEndian>(self.id));
+    try!(w.write_u16::<BigEndian>(self.name.len() as u16));
+    try!(w.write_u16::<BigEndian>(self.data.len() as u16));
+    try!(w.write_all(self.name.as_bytes()));
+    w.write_all(&self.data[..])
+  }
+
+  /// Deserialize an attribute from a byte stream.
+  pub fn deserialize<T>(r: &mut T) -> Result<Attribute, TokenError> where T: Read {
+    let attribute_type = try!(r.read_u32::<BigEndian>());
+    let version = try!(r.read_u32::<BigEndian>());
+    let id = try!(r.read_u64::<BigEndian>());
+    let name_len = try!(r.read_u16::<BigEndian>());
+    let data_len = try!(r.read_u16::<BigEndian>());
+    let name = match String::from_utf8(try!(r.read_exact_alloc(name_len as usize))) {
+      Ok(name)  => name,
+      Err(_)    => return Err(TokenError::Malformed),
+    };
+    let data = try!(r.read_exact_alloc(data_len as usize));
+    Ok(Attribute {
+      name: name,
+      attribute_type: attribute_type,
+      version: version,
+      id: id,
+      data: data,
+    })
+  }
+}
+
+/// Errors returned when decoding or verifying a token.
+error_def! TokenError {
+  Malformed
+    => "The token is malformed",
+  InvalidSignature
+    => "The token's signature is not valid",
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error reading the token" ("Specifically: {}", cause),
+  Disconnected
+    => "The token ended unexpectedly",
+}
+byteorder_error_chain! {TokenError}
+
+/// The contents of an OpenID Connect authorization code, as issued by reclaim.
+///
+/// The code carries the ticket, the attributes it grants access to and the nonce from the
+/// authorization request, signed by the ego which issued the ticket. A relying party exchanges it
+/// for an ID token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthorizationCode {
+  /// The ticket granting access to the attributes.
+  pub ticket: Ticket,
+  /// The nonce from the authorization request, or 0 if there was none.
+  pub nonce: u32,
+  /// The attributes the ticket grants access to.
+  pub attributes: Vec<Attribute>,
+}
+
+impl AuthorizationCode {
+  /// Sign the code with `issuer`, which must be the private key of `ticket.identity`, and encode
+  /// it as base64url. Fails if an attribute is too long to serialize.
+  pub fn encode(&self, issuer: &EcdsaPrivateKey) -> Result<String, io::Error> {
+    let mut signed = Vec::new();
+    signed.write_u32::<BigEndian>(0).unwrap(); // size, filled in below
+    signed.write_u32::<BigEndian>(ll::GNUNET_SIGNATURE_PURPOSE_RECLAIM_CODE_SIGN).unwrap();
+    self.ticket.serialize(&mut signed).unwrap();
+    signed.write_u32::<BigEndian>(self.nonce).unwrap();
+    for attr in self.attributes.iter() {
+      try!(attr.serialize(&mut signed));
+    }
+    let len = signed.len() as u32;
+    (&mut signed[0..4]).write_u32::<BigEndian>(len).unwrap();
+    let signature = issuer.sign(&signed[..]);
+    signature.serialize(&mut signed).unwrap();
+    Ok(base64::encode_url(&signed[..]))
+  }
+
+  /// Decode a code and check that it was signed by the identity in its ticket.
+  pub fn decode(code: &str) -> Result<AuthorizationCode, TokenError> {
+    let data = match base64::decode_url(code) {
+      Some(d) => d,
+      None    => return Err(TokenError::Malformed),
+    };
+    if data.len() < 8 + 72 + 4 + 64 {
+      return Err(TokenError::Malformed);
+    }
+    let (signed, sig) = data.split_at(data.len() - 64);
+    let mut r = Cursor::new(signed);
+    let len = try!(r.read_u32::<BigEndian>());
+    let purpose = try!(r.read_u32::<BigEndian>());
+    if len as usize != signed.len() || purpose != ll::GNUNET_SIGNATURE_PURPOSE_RECLAIM_CODE_SIGN {
+      return Err(TokenError::Malformed);
+    }
+    let ticket = try!(Ticket::deserialize(&mut r));
+    let nonce = try!(r.read_u32::<BigEndian>());
+    let mut attributes = Vec::new();
+    while (r.position() as usize) < signed.len() {
+      attributes.push(try!(Attribute::deserialize(&mut r)));
+    }
+    let sig = try!(EcdsaSignature::deserialize(&mut Cursor::new(sig)));
+    if !ticket.identity.verify(ll::GNUNET_SIGNATURE_PURPOSE_RECLAIM_CODE_SIGN, signed, &sig) {
+      return Err(TokenError::InvalidSignature);
+    }
+    Ok(AuthorizationCode {
+      ticket: ticket,
+      nonce: nonce,
+      attributes: attributes,
+    })
+  }
+}
+
+/// Write `s` as a JSON string.
+fn json_string(out: &mut String, s: &str) {
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"'                   => out.push_str("\\\""),
+      '\\'                  => out.push_str("\\\\"),
+      '\n'                  => out.push_str("\\n"),
+      '\r'                  => out.push_str("\\r"),
+      '\t'                  => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c                     => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
+/// The HMAC-SHA512 of the encoded header and claims of an ID token under `secret`.
+fn id_token_mac(secret: &[u8], header_and_claims: &str) -> Vec<u8> {
+  let mut hmac = Hmac::new(Sha512::new(), secret);
+  hmac.input(header_and_claims.as_bytes());
+  hmac.result().code().to_vec()
+}
+
+/// Issue an OpenID Connect ID token for `ticket`, the way re:claimID's OpenID Connect plugin does.
+///
+/// The token is a JSON web token with the algorithm `HS512`, an HMAC-SHA512 over the encoded
+/// header and claims keyed with `secret`, which is the plugin's `JWT_SECRET`. The `iss` claim is
+/// `ID_TOKEN_ISSUER`, `sub` is the ego which issued the ticket and `aud` is the ticket's
+/// audience. String attributes are added as claims named after the attribute. The token expires
+/// `lifetime` seconds from now.
+pub fn issue_id_token(secret: &[u8],
+                      ticket: &Ticket,
+                      attributes: &[Attribute],
+                      nonce: Option<&str>,
+                      lifetime: u64) -> String {
+  let now = time::now_micros() / 1000000;
+  let mut claims = String::from("{\"iss\":");
+  json_string(&mut claims, ID_TOKEN_ISSUER);
+  claims.push_str(",\"sub\":");
+  json_string(&mut claims, &ticket.identity.to_string());
+  claims.push_str(",\"aud\":");
+  json_string(&mut claims, &ticket.audience.to_string());
+  claims.push_str(&format!(",\"iat\":{},\"nbf\":{},\"exp\":{}", now, now, now + lifetime));
+  if let Some(nonce) = nonce {
+    claims.push_str(",\"nonce\":");
+    json_string(&mut claims, nonce);
+  }
+  for attr in attributes.iter() {
+    if let Some(value) = attr.as_str() {
+      claims.push(',');
+      json_string(&mut claims, &attr.name);
+      claims.push(':');
+      json_string(&mut claims, value);
+    }
+  }
+  claims.push('}');
+
+  let mut token = base64::encode_url(ID_TOKEN_HEADER.as_bytes());
+  token.push('.');
+  token.push_str(&base64::encode_url(claims.as_bytes()));
+  let mac = id_token_mac(secret, &token);
+  token.push('.');
+  token.push_str(&base64::encode_url(&mac[..]));
+  token
+}
+
+/// Check that `token` is an `HS512` ID token made with `secret`. Returns the JSON claims.
+///
+/// Only the MAC is checked. The caller should check the claims, such as the expiry time and
+/// audience, itself.
+pub fn verify_id_token(token: &str, secret: &[u8]) -> Result<String, TokenError> {
+  let parts: Vec<&str> = token.split('.').collect();
+  if parts.len() != 3 {
+    return Err(TokenError::Malformed);
+  }
+  let header = base64::decode_url(parts[0]);
+  let claims = base64::decode_url(parts[1]).and_then(|c| String::from_utf8(c).ok());
+  let sig = base64::decode_url(parts[2]);
+  let (claims, sig) = match (header, claims, sig) {
+    (Some(ref h), Some(c), Some(s)) if &h[..] == ID_TOKEN_HEADER.as_bytes() => (c, s),
+    _ => return Err(TokenError::Malformed),
+  };
+  let mac = id_token_mac(secret, &token[..parts[0].len() + 1 + parts[1].len()]);
+  match sig.len() == mac.len() && fixed_time_eq(&sig[..], &mac[..]) {
+    true  => Ok(claims),
+    false => Err(TokenError::InvalidSignature),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use rand;
+  use EcdsaPrivateKey;
+  use super::*;
+
+  #[test]
+  fn test_reclaim_tokens() {
+    let issuer = EcdsaPrivateKey::anonymous();
+    let rp_key: [u8; 32] = rand::random();
+    let rp = EcdsaPrivateKey::deserialize(&mut Cursor::new(&rp_key[..])).unwrap();
+    let ticket = Ticket::new(&issuer.get_public(), &rp.get_public());
+    let attrs = vec![Attribute::new_string("email", "alice@example.gnu")];
+
+    let code = AuthorizationCode {
+      ticket: ticket,
+      nonce: 1234,
+      attributes: attrs.clone(),
+    };
+    let encoded = code.encode(&issuer).unwrap();
+    assert_eq!(AuthorizationCode::decode(&encoded).unwrap(), code);
+
+    let token = issue_id_token(b"secret", &ticket, &attrs[..], Some("n-0S6"), 3600);
+    let claims = verify_id_token(&token, b"secret").unwrap();
+    assert!(claims.starts_with("{\"iss\":\"https://api.reclaim\","));
+    assert!(claims.contains("\"email\":\"alice@example.gnu\""));
+    assert!(claims.contains("\"nonce\":\"n-0S6\""));
+    match verify_id_token(&token, b"other secret") {
+      Err(TokenError::InvalidSignature) => (),
+      _ => panic!("expected the signature check to fail"),
+    };
+
+    let long = Attribute::new_string("photo", &::std::iter::repeat('x').take(70000).collect::<String>());
+    let code = AuthorizationCode {
+      ticket: ticket,
+      nonce: 0,
+      attributes: vec![long],
+    };
+    assert!(code.encode(&issuer).is_err());
+  }
+
+  #[test]
+  fn test_id_token_vector() {
+    // An HS512 token as made by re:claimID and other JWT implementations, with the secret
+    // "secret".
+    let token = "eyJhbGciOiJIUzUxMiIsInR5cCI6IkpXVCJ9.\
+                 eyJpc3MiOiJodHRwczovL2FwaS5yZWNsYWltIiwic3ViIjoiYWxpY2UiLCJhdWQiOiJycCIsImlhdCI6MTQ2NDMw\
+                 MDAwMCwibmJmIjoxNDY0MzAwMDAwLCJleHAiOjE0NjQzMDM2MDAsIm5vbmNlIjoibi0wUzYiLCJlbWFpbCI6ImFs\
+                 aWNlQGV4YW1wbGUuZ251In0.\
+                 _r5LMsMNm6Z3h4DbGchnAF2dwEZa_0uT8RJfQcljV4LOp-xt7VHnhMHRIuTMi8lga-FeCMc_qbq-8Ww1opK1tw";
+    let claims = verify_id_token(token, b"secret").unwrap();
+    assert_eq!(claims, "{\"iss\":\"https://api.reclaim\",\"sub\":\"alice\",\"aud\":\"rp\",\
+                        \"iat\":1464300000,\"nbf\":1464300000,\"exp\":1464303600,\
+                        \"nonce\":\"n-0S6\",\"email\":\"alice@example.gnu\"}");
+    assert!(verify_id_token(token, b"secreT").is_err());
+  }
+}
@@ -0,0 +1,354 @@
+//! Periodically republishing records and our HELLO in the background.
+//!
+//! Blocks stored in the DHT expire and the addresses in a HELLO go stale, so both have to be
+//! published again every so often for the rest of the network to keep finding them. A
+//! `Republisher` does this on its own thread. Jobs can be added and removed while it runs, and
+//! their runs are spread out by a random amount so that many jobs with the same interval, or many
+//! peers started together, don't all publish at the same moment.
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use gnunet::{Cfg, EcdsaPrivateKey, Record, RecordType};
+//! use gnunet::gns::RF_RELATIVE_EXPIRATION;
+//! use gnunet::republish::{Job, Republisher};
+//!
+//! let config = Cfg::default().unwrap();
+//! let republisher = Republisher::start(&config, 16);
+//! republisher.add(Job::Hello, Duration::from_secs(12 * 60 * 60)).unwrap();
+//! let www = Record::from_value_str(RecordType::A, "10.0.0.1", 4 * 60 * 60 * 1000000, RF_RELATIVE_EXPIRATION).unwrap();
+//! republisher.add(Job::Records {
+//!   zone: EcdsaPrivateKey::generate(),
+//!   label: "www".to_string(),
+//!   records: vec![www],
+//! }, Duration::from_secs(60 * 60)).unwrap();
+//! ```
+
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use rand;
+
+use Cfg;
+use EcdsaPrivateKey;
+use block::BlockType;
+use dht::{DhtPublisher, PublishId, PublishStatus, RouteOptions};
+use gns::{self, Record};
+use peerinfo;
+use time::now_micros;
+use transport::TransportService;
+
+/// How far, as a fraction of its interval, a run may be moved from when it is due.
+const JITTER: f64 = 0.1;
+/// The replication level GNS uses for its blocks.
+const GNS_REPLICATION_LEVEL: u32 = 5;
+/// How long the worker sleeps at most while publications are outstanding.
+const POLL_INTERVAL_MS: u64 = 500;
+/// How long a `Hello` run waits for the transport service to send our HELLO.
+const HELLO_TIMEOUT_SECS: u64 = 30;
+/// How long dropping a `Republisher` waits for the worker to stop.
+const STOP_TIMEOUT_SECS: u64 = 5;
+
+/// Work done periodically by a `Republisher`.
+#[derive(Clone)]
+pub enum Job {
+  /// Publish `records`, stored under `label` in `zone`, to the DHT the way the GNS service does.
+  Records { zone: EcdsaPrivateKey, label: String, records: Vec<Record> },
+  /// Fetch our HELLO from the transport service and store it with the peerinfo service.
+  Hello,
+}
+
+/// Identifies a job added to a `Republisher`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(u64);
+
+/// How a job has fared so far.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct JobStatus {
+  /// The number of times the job has been run.
+  pub runs: u64,
+  /// The number of runs which failed. A `Records` run fails when the DHT service can't be made to
+  /// store the block, a `Hello` run when either service can't be talked to or peerinfo doesn't
+  /// take the HELLO.
+  pub failures: u64,
+}
+
+/// Errors returned by `Republisher::add`.
+error_def! AddJobError {
+  Full { max_jobs: usize }
+    => "The republisher already has as many jobs as it was started with room for" ("The limit is {} jobs.", max_jobs),
+}
+
+struct Scheduled {
+  job: Job,
+  interval: Duration,
+  next_run: Instant,
+  status: JobStatus,
+  publication: Option<PublishId>,
+}
+
+struct State {
+  jobs: BTreeMap<JobId, Scheduled>,
+  next_id: u64,
+  running: bool,
+}
+
+struct Shared {
+  state: Mutex<State>,
+  cond: Condvar,
+}
+
+/// Runs jobs periodically on a background thread. Created with `Republisher::start`.
+///
+/// The thread is stopped when the `Republisher` is dropped. Publications still waiting to be
+/// confirmed by the DHT service are abandoned. Dropping waits a few seconds at most: a worker
+/// still stuck talking to a service after that stops on its own once the service answers or
+/// the connection fails.
+pub struct Republisher {
+  shared: Arc<Shared>,
+  max_jobs: usize,
+  worker: Option<JoinHandle<()>>,
+  finished: Receiver<()>,
+}
+
+/// `interval` moved by a random amount of up to `JITTER` of itself in either direction. `r` is a
+/// random number in `[0, 1)`.
+fn jittered(interval: Duration, r: f64) -> Duration {
+  let micros = interval.as_secs() as f64 * 1000000.0 + (interval.subsec_nanos() / 1000) as f64;
+  from_micros(micros * (1.0 + JITTER * (2.0 * r - 1.0)))
+}
+
+/// The delay before the first run of a job with `interval`, between zero and `JITTER` of it.
+fn first_delay(interval: Duration, r: f64) -> Duration {
+  let micros = interval.as_secs() as f64 * 1000000.0 + (interval.subsec_nanos() / 1000) as f64;
+  from_micros(micros * JITTER * r)
+}
+
+fn from_micros(micros: f64) -> Duration {
+  let micros = micros as u64;
+  Duration::new(micros / 1000000, ((micros % 1000000) * 1000) as u32)
+}
+
+impl Republisher {
+  /// Start a republisher with room for `max_jobs` jobs. It talks to the services described by
+  /// `cfg`.
+  pub fn start(cfg: &Cfg, max_jobs: usize) -> Republisher {
+    let shared = Arc::new(Shared {
+      state: Mutex::new(State {
+        jobs: BTreeMap::new(),
+        next_id: 0,
+        running: true,
+      }),
+      cond: Condvar::new(),
+    });
+    let worker_shared = shared.clone();
+    let cfg = cfg.clone();
+    let (finished_tx, finished_rx) = channel::<()>();
+    let worker = thread::spawn(move || {
+      // Dropped when the worker returns, which tells `drop` it can join.
+      let _finished = finished_tx;
+      work(&cfg, &worker_shared)
+    });
+    Republisher {
+      shared: shared,
+      max_jobs: max_jobs,
+      worker: Some(worker),
+      finished: finished_rx,
+    }
+  }
+
+  /// Run `job` every `interval`, give or take a tenth of it. The first run happens within a tenth
+  /// of `interval` of the job being added.
+  pub fn add(&self, job: Job, interval: Duration) -> Result<JobId, AddJobError> {
+    let mut state = self.shared.state.lock().unwrap();
+    if state.jobs.len() >= self.max_jobs {
+      return Err(AddJobError::Full { max_jobs: self.max_jobs });
+    }
+    let id = JobId(state.next_id);
+    state.next_id += 1;
+    state.jobs.insert(id, Scheduled {
+      job: job,
+      interval: interval,
+      next_run: Instant::now() + first_delay(interval, rand::random()),
+      status: JobStatus::default(),
+      publication: None,
+    });
+    self.shared.cond.notify_all();
+    Ok(id)
+  }
+
+  /// Stop running the job `id`. Returns its status, or `None` if there is no such job.
+  pub fn remove(&self, id: JobId) -> Option<JobStatus> {
+    self.shared.state.lock().unwrap().jobs.remove(&id).map(|s| s.status)
+  }
+
+  /// The status of the job `id`, or `None` if there is no such job.
+  pub fn status(&self, id: JobId) -> Option<JobStatus> {
+    self.shared.state.lock().unwrap().jobs.get(&id).map(|s| s.status)
+  }
+
+  /// The jobs currently being run.
+  pub fn jobs(&self) -> Vec<JobId> {
+    self.shared.state.lock().unwrap().jobs.keys().cloned().collect()
+  }
+}
+
+impl Drop for Republisher {
+  fn drop(&mut self) {
+    self.shared.state.lock().unwrap().running = false;
+    self.shared.cond.notify_all();
+    // The worker checks `running` between runs, but may be blocked on a service in the middle
+    // of one.
+    match self.finished.recv_timeout(Duration::from_secs(STOP_TIMEOUT_SECS)) {
+      Err(RecvTimeoutError::Timeout) => (),
+      _ => if let Some(worker) = self.worker.take() {
+        let _ = worker.join();
+      },
+    }
+  }
+}
+
+/// Fetch our HELLO from the transport service and hand it to peerinfo. Returns `true` if
+/// peerinfo took it.
+fn refresh_hello(cfg: &Cfg) -> bool {
+  let transport = match TransportService::init_timeout(cfg, Duration::from_secs(HELLO_TIMEOUT_SECS)) {
+    Ok(t)   => t,
+    Err(_)  => return false,
+  };
+  match peerinfo::import_unsigned_hellos(cfg, Some(transport.our_hello_message())) {
+    Ok(report)  => report.imported() == 1,
+    Err(_)      => false,
+  }
+}
+
+/// The body of the worker thread.
+fn work(cfg: &Cfg, shared: &Shared) {
+  let mut publisher = DhtPublisher::new();
+  loop {
+    // Take the jobs which are due and schedule their next runs.
+    let now = Instant::now();
+    let due: Vec<(JobId, Job, Option<PublishId>)> = {
+      let mut state = shared.state.lock().unwrap();
+      if !state.running {
+        return;
+      }
+      let mut due = Vec::new();
+      for (id, scheduled) in state.jobs.iter_mut() {
+        if scheduled.next_run <= now {
+          scheduled.next_run = now + jittered(scheduled.interval, rand::random());
+          due.push((*id, scheduled.job.clone(), scheduled.publication.take()));
+        }
+      }
+      due
+    };
+
+    for (id, job, previous) in due {
+      // A publication still outstanding from the last run is superseded by this one.
+      if let Some(previous) = previous {
+        publisher.remove(previous);
+      }
+      let (publication, ok) = match job {
+        Job::Records { ref zone, ref label, ref records } => {
+          let expiration = gns::block_expiration(&records[..], now_micros());
+          let data = gns::create_block(zone, label, &records[..], expiration);
+          let options = RouteOptions {
+            demultiplex_everywhere: true,
+            .. RouteOptions::default()
+          };
+          let key = gns::query_from_private_key(zone, label);
+          (Some(publisher.enqueue(&key, BlockType::GnsNameRecord, &data[..], GNS_REPLICATION_LEVEL, options, expiration)), true)
+        },
+        Job::Hello => (None, refresh_hello(cfg)),
+      };
+      let mut state = shared.state.lock().unwrap();
+      match state.jobs.get_mut(&id) {
+        Some(scheduled) => {
+          scheduled.status.runs += 1;
+          if !ok {
+            scheduled.status.failures += 1;
+          }
+          scheduled.publication = publication;
+        },
+        // The job was removed while it ran.
+        None => if let Some(publication) = publication {
+          publisher.remove(publication);
+        },
+      }
+    }
+
+    // The connection is retried on the next pass if it can't be made now.
+    let _ = publisher.poll(cfg);
+
+    let mut state = shared.state.lock().unwrap();
+    for scheduled in state.jobs.values_mut() {
+      let finished = match scheduled.publication {
+        Some(publication) => match publisher.status(publication) {
+          Some(PublishStatus::Confirmed)        => true,
+          Some(PublishStatus::Failed { .. })    => {
+            scheduled.status.failures += 1;
+            true
+          },
+          Some(_)                               => false,
+          None                                  => true,
+        },
+        None => false,
+      };
+      if finished {
+        publisher.remove(scheduled.publication.take().unwrap());
+      }
+    }
+
+    // Sleep until the next job is due, or a job is added, waking regularly while publications
+    // are waiting to be confirmed or retried.
+    if !state.running {
+      return;
+    }
+    let now = Instant::now();
+    let mut timeout = match state.jobs.values().map(|s| s.next_run).min() {
+      Some(next) if next > now  => next - now,
+      Some(_)                   => Duration::from_millis(0),
+      None                      => Duration::from_secs(60 * 60),
+    };
+    if !publisher.is_idle() {
+      timeout = min(timeout, Duration::from_millis(POLL_INTERVAL_MS));
+    }
+    let _ = shared.cond.wait_timeout(state, timeout).unwrap();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use Cfg;
+  use super::*;
+  use super::{jittered, first_delay};
+
+  #[test]
+  fn test_jittered() {
+    let hour = Duration::from_secs(60 * 60);
+    assert_eq!(jittered(hour, 0.5), hour);
+    assert_eq!(jittered(hour, 0.0), Duration::from_secs(54 * 60));
+    assert!(jittered(hour, 0.999999) <= Duration::from_secs(66 * 60));
+    assert_eq!(first_delay(hour, 0.0), Duration::from_secs(0));
+    assert_eq!(first_delay(hour, 0.5), Duration::from_secs(3 * 60));
+  }
+
+  #[test]
+  fn test_republisher_jobs() {
+    let republisher = Republisher::start(&Cfg::empty(), 2);
+    let day = Duration::from_secs(24 * 60 * 60);
+    let a = republisher.add(Job::Hello, day).unwrap();
+    let b = republisher.add(Job::Hello, day).unwrap();
+    match republisher.add(Job::Hello, day) {
+      Err(AddJobError::Full { max_jobs: 2 }) => (),
+      _ => panic!("a third job was accepted"),
+    }
+    assert_eq!(republisher.jobs(), vec![a, b]);
+    assert!(republisher.remove(a).is_some());
+    assert!(republisher.status(a).is_none());
+    assert!(republisher.status(b).is_some());
+    republisher.add(Job::Hello, day).unwrap();
+  }
+}
@@ -0,0 +1,98 @@
+//! Module for querying the revocation service.
+//!
+//! The owner of an ego can revoke its key, for example because the private key was compromised.
+//! The revocation is flooded to every peer, and names in the ego's zone stop resolving. This
+//! module checks whether a key has been revoked and sends revocations whose proof of work has
+//! already been computed, eg. by `gnunet-revocation`.
+
+use std::io::{self, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use EcdsaPrivateKey;
+use EcdsaPublicKey;
+use activity::{self, Activity};
+use service::{self, ReadMessageError};
+
+/// Errors returned by `is_revoked`.
+error_def! QueryError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the revocation service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the revocation service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the revocation service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The revocation service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {QueryError}
+
+/// Ask the revocation service whether `key` has been revoked.
+pub fn is_revoked(cfg: &Cfg, key: &EcdsaPublicKey) -> Result<bool, QueryError> {
+  let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "revocation"));
+  {
+    let mut mw = service_writer.write_message(40, ll::GNUNET_MESSAGE_TYPE_REVOCATION_QUERY);
+    mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+    key.serialize(&mut mw).unwrap();
+    try!(mw.send());
+  }
+  let (tpe, mut mr) = try!(service_reader.read_message());
+  if tpe != ll::GNUNET_MESSAGE_TYPE_REVOCATION_QUERY_RESPONSE {
+    return Err(QueryError::UnexpectedMessageType { ty: tpe });
+  }
+  let is_valid = try!(mr.read_u32::<BigEndian>());
+  Ok(is_valid == 0)
+}
+
+/// Errors returned by `revoke`.
+error_def! RevokeError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the revocation service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the revocation service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the revocation service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The revocation service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  Rejected
+    => "The revocation service did not accept the revocation",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {RevokeError}
+
+/// Revoke `key`. `proof_of_work` is a nonce which makes the revocation expensive enough for the
+/// service to accept it.
+///
+/// Successful revocations are logged to the audit log configured in `cfg`. A revocation can't be
+/// undone.
+pub fn revoke(cfg: &Cfg, key: &EcdsaPrivateKey, proof_of_work: u64) -> Result<(), RevokeError> {
+  let public = key.get_public();
+  let mut signed = Vec::with_capacity(40);
+  signed.write_u32::<BigEndian>(40).unwrap();
+  signed.write_u32::<BigEndian>(ll::GNUNET_SIGNATURE_PURPOSE_REVOCATION).unwrap();
+  public.serialize(&mut signed).unwrap();
+  let signature = key.sign(&signed[..]);
+
+  let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "revocation"));
+  {
+    let mut mw = service_writer.write_message(120, ll::GNUNET_MESSAGE_TYPE_REVOCATION_REVOKE);
+    mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+    mw.write_u64::<BigEndian>(proof_of_work).unwrap();
+    signature.serialize(&mut mw).unwrap();
+    mw.write_all(&signed[..]).unwrap();
+    try!(mw.send());
+  }
+  let (tpe, mut mr) = try!(service_reader.read_message());
+  if tpe != ll::GNUNET_MESSAGE_TYPE_REVOCATION_REVOKE_RESPONSE {
+    return Err(RevokeError::UnexpectedMessageType { ty: tpe });
+  }
+  if try!(mr.read_u32::<BigEndian>()) == 0 {
+    return Err(RevokeError::Rejected);
+  }
+  activity::log(cfg, &Activity::KeyRevoked { key: public });
+  Ok(())
+}
@@ -0,0 +1,478 @@
+//! A small request/response layer for simple protocols between peers.
+//!
+//! Messages are sent as frames tagged with a request id so that responses can be matched to
+//! requests. Frames are carried by an `RpcTransport`, either a CADET `Channel` or any reliable
+//! byte stream wrapped in a `StreamTransport`. Requests and responses are serialized as JSON with
+//! rustc-serialize; `call_raw` and `serve_raw` work with the bytes directly.
+
+use std::io::{self, Cursor, Read, Write};
+use std::str::from_utf8;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rustc_serialize::{Decodable, Encodable};
+use rustc_serialize::json::{self, DecoderError, EncoderError, ErrorCode, ParserError};
+
+use cadet::{Channel, ChannelRecvError, ChannelSendError};
+use util::io::ReadUtil;
+
+/// The largest payload that will be accepted in a frame.
+pub const MAX_PAYLOAD_LEN: usize = 1 << 20;
+
+/// The message type used for frames sent over a CADET channel.
+pub const CADET_MESSAGE_TYPE: u16 = 60000;
+
+/// Whether a frame carries a request or a response.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+  /// A request sent by a client.
+  Request   = 0,
+  /// A response sent by a server.
+  Response  = 1,
+}
+
+/// A single RPC frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+  /// Whether this is a request or a response.
+  pub kind: FrameKind,
+  /// The id of the request. Responses carry the id of the request they answer.
+  pub request_id: u32,
+  /// The payload of the frame.
+  pub payload: Vec<u8>,
+}
+
+/// Errors returned by `read_frame` and `RpcTransport::recv_frame`.
+error_def! ReadFrameError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error reading the frame" ("Specifically: {}", cause),
+  TooShort { len: usize }
+    => "The frame was too short" ("A frame of {} bytes is too short to hold the frame header", len),
+  TooLong { len: usize }
+    => "The frame was too long" ("A payload of {} bytes exceeds the maximum length", len),
+  InvalidKind { kind: u8 }
+    => "The frame had an invalid kind" ("{} is not a valid frame kind", kind),
+  UnexpectedMessageType { ty: u16 }
+    => "A message of an unexpected type was received on the channel" ("Message type {} is not an RPC frame", ty),
+  Disconnected
+    => "The stream was closed unexpectedly",
+}
+byteorder_error_chain! {ReadFrameError}
+
+/// Errors returned by `RpcTransport::send_frame`.
+error_def! SendFrameError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error sending the frame" ("Specifically: {}", cause),
+  Channel { #[from] cause: ChannelSendError }
+    => "Failed to send the frame over the CADET channel" ("Reason: {}", cause),
+}
+
+/// Something frames can be sent and received over.
+pub trait RpcTransport {
+  /// Send a frame.
+  fn send_frame(&mut self, frame: &Frame) -> Result<(), SendFrameError>;
+
+  /// Receive the next frame, waiting at most `timeout` or forever if `timeout` is `None`.
+  /// Returns `Ok(None)` if the timeout expired.
+  fn recv_frame(&mut self, timeout: Option<Duration>) -> Result<Option<Frame>, ReadFrameError>;
+}
+
+/// Serialize the request id, kind and payload of `frame`.
+fn write_frame_body(buf: &mut Vec<u8>, frame: &Frame) {
+  buf.write_u32::<BigEndian>(frame.request_id).unwrap();
+  buf.write_u8(frame.kind as u8).unwrap();
+  buf.write_all(&frame.payload[..]).unwrap();
+}
+
+/// Parse the request id and kind of a frame, followed by `payload_len` bytes of payload.
+fn read_frame_body<R>(r: &mut R, payload_len: usize) -> Result<Frame, ReadFrameError> where R: Read {
+  let request_id = try!(r.read_u32::<BigEndian>());
+  let kind = match try!(r.read_u8()) {
+    0 => FrameKind::Request,
+    1 => FrameKind::Response,
+    k => return Err(ReadFrameError::InvalidKind { kind: k }),
+  };
+  let payload = try!(r.read_exact_alloc(payload_len));
+  Ok(Frame {
+    kind: kind,
+    request_id: request_id,
+    payload: payload,
+  })
+}
+
+/// Write a frame to a byte stream.
+///
+/// # Panics
+///
+/// Panics if the payload is longer than `MAX_PAYLOAD_LEN`.
+pub fn write_frame<W>(w: &mut W, frame: &Frame) -> Result<(), io::Error> where W: Write {
+  assert!(frame.payload.len() <= MAX_PAYLOAD_LEN);
+  let mut buf = Vec::with_capacity(9 + frame.payload.len());
+  buf.write_u32::<BigEndian>(5 + frame.payload.len() as u32).unwrap();
+  write_frame_body(&mut buf, frame);
+  try!(w.write_all(&buf[..]));
+  w.flush()
+}
+
+/// Read a frame from a byte stream.
+pub fn read_frame<R>(r: &mut R) -> Result<Frame, ReadFrameError> where R: Read {
+  let len = try!(r.read_u32::<BigEndian>()) as usize;
+  if len < 5 {
+    return Err(ReadFrameError::TooShort { len: len });
+  }
+  if len - 5 > MAX_PAYLOAD_LEN {
+    return Err(ReadFrameError::TooLong { len: len - 5 });
+  }
+  read_frame_body(r, len - 5)
+}
+
+/// An `RpcTransport` over a reliable byte stream.
+///
+/// A background thread reads frames from the stream so that `recv_frame` can time out.
+pub struct StreamTransport<W> {
+  writer: Option<W>,
+  frames: Receiver<Result<Frame, ReadFrameError>>,
+  reader_thread: Option<JoinHandle<()>>,
+}
+
+impl<W> StreamTransport<W> where W: Write {
+  /// Send frames over `writer` and receive them from `reader`.
+  pub fn new<R>(reader: R, writer: W) -> Result<StreamTransport<W>, io::Error>
+      where R: Read + Send + 'static
+  {
+    let (tx, rx) = channel();
+    let reader_thread = try!(thread::Builder::new().name("rpc reader".to_string()).spawn(move || {
+      let mut reader = reader;
+      loop {
+        let res = read_frame(&mut reader);
+        let stop = res.is_err();
+        if tx.send(res).is_err() || stop {
+          break;
+        }
+      }
+    }));
+    Ok(StreamTransport {
+      writer: Some(writer),
+      frames: rx,
+      reader_thread: Some(reader_thread),
+    })
+  }
+
+  /// Close the writing half of the stream and wait for the reader thread to exit. The thread
+  /// exits once the other end closes the stream or a malformed frame is read.
+  pub fn close(mut self) {
+    self.writer = None;
+    if let Some(t) = self.reader_thread.take() {
+      let _ = t.join();
+    }
+  }
+}
+
+impl<W> RpcTransport for StreamTransport<W> where W: Write {
+  fn send_frame(&mut self, frame: &Frame) -> Result<(), SendFrameError> {
+    match self.writer {
+      Some(ref mut w) => Ok(try!(write_frame(w, frame))),
+      None            => Err(SendFrameError::Io { cause: io::Error::new(io::ErrorKind::NotConnected, "the stream is closed") }),
+    }
+  }
+
+  fn recv_frame(&mut self, timeout: Option<Duration>) -> Result<Option<Frame>, ReadFrameError> {
+    let res = match timeout {
+      Some(t) => match self.frames.recv_timeout(t) {
+        Ok(res)                             => res,
+        Err(RecvTimeoutError::Timeout)      => return Ok(None),
+        Err(RecvTimeoutError::Disconnected) => return Err(ReadFrameError::Disconnected),
+      },
+      None    => match self.frames.recv() {
+        Ok(res) => res,
+        Err(_)  => return Err(ReadFrameError::Disconnected),
+      },
+    };
+    res.map(Some)
+  }
+}
+
+impl RpcTransport for Channel {
+  fn send_frame(&mut self, frame: &Frame) -> Result<(), SendFrameError> {
+    let mut buf = Vec::with_capacity(5 + frame.payload.len());
+    write_frame_body(&mut buf, frame);
+    Ok(try!(self.send(CADET_MESSAGE_TYPE, &buf[..])))
+  }
+
+  fn recv_frame(&mut self, timeout: Option<Duration>) -> Result<Option<Frame>, ReadFrameError> {
+    let res = match timeout {
+      Some(t) => self.recv_timeout(t),
+      None    => self.recv().map(Some),
+    };
+    let (tpe, payload) = match res {
+      Ok(Some(m))                             => m,
+      Ok(None)                                => return Ok(None),
+      Err(ChannelRecvError::Destroyed)        => return Err(ReadFrameError::Disconnected),
+      Err(ChannelRecvError::Disconnected)     => return Err(ReadFrameError::Disconnected),
+    };
+    if tpe != CADET_MESSAGE_TYPE {
+      return Err(ReadFrameError::UnexpectedMessageType { ty: tpe });
+    }
+    if payload.len() < 5 {
+      return Err(ReadFrameError::TooShort { len: payload.len() });
+    }
+    let payload_len = payload.len() - 5;
+    Ok(Some(try!(read_frame_body(&mut Cursor::new(payload), payload_len))))
+  }
+}
+
+/// The client side of an RPC connection.
+///
+/// Requests are issued with `call`, which sends a request and waits for its response.
+pub struct RpcClient<T> {
+  transport: T,
+  next_request_id: u32,
+  timeout: Duration,
+}
+
+/// Errors returned by `RpcClient::call`.
+error_def! CallError {
+  Send { #[from] cause: SendFrameError }
+    => "Failed to send the request" ("Reason: {}", cause),
+  Receive { #[from] cause: ReadFrameError }
+    => "Failed to receive the response" ("Reason: {}", cause),
+  Encode { #[from] cause: EncoderError }
+    => "Failed to encode the request" ("Reason: {}", cause),
+  Decode { #[from] cause: DecoderError }
+    => "Failed to decode the response" ("Reason: {}", cause),
+  Timeout
+    => "No response was received before the timeout expired",
+  Disconnected
+    => "The connection was closed before a response was received",
+}
+
+impl<T> RpcClient<T> where T: RpcTransport {
+  /// Create a client which sends requests and receives responses over `transport`.
+  ///
+  /// Calls time out after 30 seconds unless a different timeout is set with `set_timeout`.
+  pub fn new(transport: T) -> RpcClient<T> {
+    RpcClient {
+      transport: transport,
+      next_request_id: 0,
+      timeout: Duration::from_secs(30),
+    }
+  }
+
+  /// Set the time to wait for a response before `call` gives up.
+  pub fn set_timeout(&mut self, timeout: Duration) {
+    self.timeout = timeout;
+  }
+
+  /// Take back the transport.
+  pub fn into_transport(self) -> T {
+    self.transport
+  }
+
+  /// Send `request` and block until the response arrives or the timeout expires.
+  pub fn call<Q, S>(&mut self, request: &Q) -> Result<S, CallError>
+      where Q: Encodable,
+            S: Decodable
+  {
+    let payload = try!(json::encode(request));
+    let response = try!(self.call_raw(payload.as_bytes()));
+    Ok(try!(decode_payload(&response[..])))
+  }
+
+  /// Send a request with the payload `payload` and block until the response arrives or the
+  /// timeout expires. Returns the payload of the response.
+  pub fn call_raw(&mut self, payload: &[u8]) -> Result<Vec<u8>, CallError> {
+    let id = self.next_request_id;
+    self.next_request_id = self.next_request_id.wrapping_add(1);
+
+    try!(self.transport.send_frame(&Frame {
+      kind: FrameKind::Request,
+      request_id: id,
+      payload: payload.to_vec(),
+    }));
+    let deadline = Instant::now() + self.timeout;
+    loop {
+      let now = Instant::now();
+      if now >= deadline {
+        return Err(CallError::Timeout);
+      }
+      let frame = match self.transport.recv_frame(Some(deadline - now)) {
+        Ok(Some(f))                         => f,
+        Ok(None)                            => return Err(CallError::Timeout),
+        Err(ReadFrameError::Disconnected)   => return Err(CallError::Disconnected),
+        Err(e)                              => return Err(From::from(e)),
+      };
+      // responses to earlier calls which timed out are dropped
+      if frame.kind == FrameKind::Response && frame.request_id == id {
+        return Ok(frame.payload);
+      }
+    }
+  }
+}
+
+/// Errors returned by `serve` and `serve_raw`.
+error_def! ServeError {
+  Receive { #[from] cause: ReadFrameError }
+    => "Failed to receive a request" ("Reason: {}", cause),
+  Send { #[from] cause: SendFrameError }
+    => "Failed to send a response" ("Reason: {}", cause),
+  Encode { #[from] cause: EncoderError }
+    => "Failed to encode a response" ("Reason: {}", cause),
+}
+
+/// Decode a JSON payload.
+fn decode_payload<T>(payload: &[u8]) -> Result<T, DecoderError> where T: Decodable {
+  match from_utf8(payload) {
+    Ok(s)   => json::decode(s),
+    Err(_)  => Err(DecoderError::ParseError(ParserError::SyntaxError(ErrorCode::NotUtf8, 0, 0))),
+  }
+}
+
+/// Serve requests received over `transport`.
+///
+/// `handler` is called with each request and returns the response. Requests which can't be
+/// decoded are ignored. Returns when the transport is closed or an error occurs.
+pub fn serve<T, Q, S, F>(transport: &mut T, mut handler: F) -> Result<(), ServeError>
+    where T: RpcTransport,
+          Q: Decodable,
+          S: Encodable,
+          F: FnMut(Q) -> S
+{
+  let mut encode_error = None;
+  let res = serve_raw(transport, |payload| {
+    let request = match decode_payload(payload) {
+      Ok(r)   => r,
+      Err(_)  => return None,
+    };
+    match json::encode(&handler(request)) {
+      Ok(response)  => Some(response.into_bytes()),
+      Err(e)        => {
+        encode_error = Some(e);
+        None
+      },
+    }
+  });
+  match encode_error {
+    Some(e) => Err(From::from(e)),
+    None    => res,
+  }
+}
+
+/// Serve requests received over `transport`, handing the payloads to `handler` as bytes.
+///
+/// `handler` is called with the payload of each request and returns the payload of the response,
+/// or `None` to send no response. Returns when the transport is closed or an error occurs.
+pub fn serve_raw<T, F>(transport: &mut T, mut handler: F) -> Result<(), ServeError>
+    where T: RpcTransport,
+          F: FnMut(&[u8]) -> Option<Vec<u8>>
+{
+  loop {
+    let frame = match transport.recv_frame(None) {
+      Ok(Some(f))                         => f,
+      Ok(None)                            => continue,
+      Err(ReadFrameError::Disconnected)   => return Ok(()),
+      Err(e)                              => return Err(From::from(e)),
+    };
+    if frame.kind != FrameKind::Request {
+      continue;
+    }
+    if let Some(payload) = handler(&frame.payload[..]) {
+      try!(transport.send_frame(&Frame {
+        kind: FrameKind::Response,
+        request_id: frame.request_id,
+        payload: payload,
+      }));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use super::*;
+
+  /// A transport which replays `frames` and records what is sent.
+  struct Replay {
+    frames: Vec<Frame>,
+    sent: Vec<Frame>,
+  }
+
+  impl RpcTransport for Replay {
+    fn send_frame(&mut self, frame: &Frame) -> Result<(), SendFrameError> {
+      self.sent.push(frame.clone());
+      Ok(())
+    }
+
+    fn recv_frame(&mut self, _timeout: Option<Duration>) -> Result<Option<Frame>, ReadFrameError> {
+      match self.frames.is_empty() {
+        true  => Err(ReadFrameError::Disconnected),
+        false => Ok(Some(self.frames.remove(0))),
+      }
+    }
+  }
+
+  #[test]
+  fn test_frame_round_trip() {
+    let frame = Frame {
+      kind: FrameKind::Response,
+      request_id: 1234,
+      payload: b"hello".to_vec(),
+    };
+    let mut buf = Vec::new();
+    write_frame(&mut buf, &frame).unwrap();
+    assert_eq!(buf.len(), 14);
+    let decoded = read_frame(&mut Cursor::new(buf)).unwrap();
+    assert_eq!(decoded, frame);
+
+    match read_frame(&mut Cursor::new(vec![0, 0, 0, 4, 0, 0, 0, 0])) {
+      Err(ReadFrameError::TooShort { len: 4 }) => (),
+      r => panic!("unexpected result: {:?}", r),
+    };
+  }
+
+  #[test]
+  fn test_serve() {
+    let mut transport = Replay {
+      frames: vec![
+        Frame { kind: FrameKind::Request, request_id: 0, payload: b"\"ping\"".to_vec() },
+        Frame { kind: FrameKind::Request, request_id: 1, payload: b"not json".to_vec() },
+        Frame { kind: FrameKind::Request, request_id: 2, payload: b"\"pong\"".to_vec() },
+      ],
+      sent: Vec::new(),
+    };
+    serve(&mut transport, |p: String| p.chars().rev().collect::<String>()).unwrap();
+    assert_eq!(transport.sent.len(), 2);
+    assert_eq!((transport.sent[0].request_id, &transport.sent[0].payload[..]), (0, &b"\"gnip\""[..]));
+    assert_eq!((transport.sent[1].request_id, &transport.sent[1].payload[..]), (2, &b"\"gnop\""[..]));
+  }
+
+  #[test]
+  fn test_call_disconnected() {
+    let mut client = RpcClient::new(Replay {
+      frames: vec![Frame { kind: FrameKind::Response, request_id: 0, payload: b"[1,2]".to_vec() }],
+      sent: Vec::new(),
+    });
+    let r: Vec<u32> = client.call(&"hi").unwrap();
+    assert_eq!(r, vec![1, 2]);
+    match client.call::<_, Vec<u32>>(&"hi") {
+      Err(CallError::Disconnected) => (),
+      r => panic!("unexpected result: {:?}", r),
+    };
+  }
+
+  #[test]
+  fn test_stream_transport() {
+    let mut responses = Vec::new();
+    write_frame(&mut responses, &Frame { kind: FrameKind::Response, request_id: 0, payload: b"7".to_vec() }).unwrap();
+    let transport = StreamTransport::new(Cursor::new(responses), Vec::new()).unwrap();
+    let mut client = RpcClient::new(transport);
+    let r: u32 = client.call(&()).unwrap();
+    assert_eq!(r, 7);
+    // the reader thread has hit the end of the stream
+    match client.call::<_, u32>(&()) {
+      Err(CallError::Disconnected) => (),
+      r => panic!("unexpected result: {:?}", r),
+    };
+    client.into_transport().close();
+  }
+}
@@ -0,0 +1,199 @@
+//! Encoding and decoding of the messages exchanged with services, without doing any I/O.
+//!
+//! `ServiceReader` and `ServiceWriter` use these to talk to services over blocking sockets, and
+//! with the `async` feature `MessageStream` and `MessageSink` use them to do so through futures.
+//! Programs built around any other event loop can use them directly: read whatever bytes are
+//! available from the service's socket, `feed` them to a `MessageDecoder` and take out any
+//! complete messages, and `queue` messages on a `MessageEncoder`, writing out its `pending` bytes
+//! when the socket is writable. Nothing here depends on how the socket is driven.
+
+use std::io::Cursor;
+use std::mem;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use service::ReadMessageError;
+
+/// Splits a stream of bytes received from a service into messages.
+#[derive(Clone, Debug, Default)]
+pub struct MessageDecoder {
+  buf: Vec<u8>,
+}
+
+impl MessageDecoder {
+  /// Create a decoder which hasn't received anything yet.
+  pub fn new() -> MessageDecoder {
+    MessageDecoder {
+      buf: Vec::new(),
+    }
+  }
+
+  /// Add bytes received from the service.
+  pub fn feed(&mut self, data: &[u8]) {
+    self.buf.extend_from_slice(data);
+  }
+
+  /// The number of bytes received but not yet taken out as messages.
+  pub fn buffered(&self) -> usize {
+    self.buf.len()
+  }
+
+  /// The length of the message at the front of the buffer, if enough of it has been received to
+  /// know.
+  fn message_len(&self) -> Option<usize> {
+    match self.buf.len() >= 2 {
+      true  => Some(((self.buf[0] as usize) << 8) | self.buf[1] as usize),
+      false => None,
+    }
+  }
+
+  /// The number of bytes which must be fed in before the next message is complete. Feeding in
+  /// exactly this many bytes never reads past the end of the message, which lets a blocking
+  /// reader avoid buffering the start of the next one.
+  pub fn wanted(&self) -> usize {
+    match self.message_len() {
+      Some(len) => len.saturating_sub(self.buf.len()),
+      None      => 2 - self.buf.len(),
+    }
+  }
+
+  /// Take the next complete message out of the buffer, including its header. Returns `None` if
+  /// more bytes are needed.
+  ///
+  /// A message too short to hold its header means the stream can't be split into messages any
+  /// more, as there is no way to tell where the next one starts. Everything buffered is discarded
+  /// along with it, and the connection should be dropped.
+  pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, ReadMessageError> {
+    let len = match self.message_len() {
+      Some(len) => len,
+      None      => return Ok(None),
+    };
+    if len < 4 {
+      self.buf.clear();
+      return Err(ReadMessageError::ShortMessage { len: len as u16 });
+    }
+    if self.buf.len() < len {
+      return Ok(None);
+    }
+    let rest = self.buf.split_off(len);
+    Ok(Some(mem::replace(&mut self.buf, rest)))
+  }
+
+  /// Take the next complete message out of the buffer. Returns its type and a reader over its
+  /// body, as `ServiceReader::read_message` does, or `None` if more bytes are needed.
+  pub fn next_message(&mut self) -> Result<Option<(u16, Cursor<Vec<u8>>)>, ReadMessageError> {
+    match try!(self.next_frame()) {
+      Some(frame) => Ok(Some(split_frame(frame))),
+      None        => Ok(None),
+    }
+  }
+}
+
+/// Holds the messages to be sent to a service until the socket takes them.
+#[derive(Clone, Debug, Default)]
+pub struct MessageEncoder {
+  buf: Vec<u8>,
+}
+
+impl MessageEncoder {
+  /// Create an encoder with nothing to send.
+  pub fn new() -> MessageEncoder {
+    MessageEncoder {
+      buf: Vec::new(),
+    }
+  }
+
+  /// Add a message of type `tpe` with body `body` to the bytes to be sent. Returns `false`, and
+  /// adds nothing, if the message would be too long.
+  pub fn queue(&mut self, tpe: u16, body: &[u8]) -> bool {
+    match encode_message(tpe, body) {
+      Some(msg) => {
+        self.buf.extend_from_slice(&msg[..]);
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// The bytes waiting to be written to the socket.
+  pub fn pending(&self) -> &[u8] {
+    &self.buf[..]
+  }
+
+  /// Drop the first `n` pending bytes, once the socket has taken them.
+  pub fn consume(&mut self, n: usize) {
+    self.buf.drain(..n);
+  }
+}
+
+/// Split a complete message into its type and a reader over its body.
+pub fn split_frame(mut frame: Vec<u8>) -> (u16, Cursor<Vec<u8>>) {
+  frame.drain(..2);
+  let mut mr = Cursor::new(frame);
+  let tpe = mr.read_u16::<BigEndian>().unwrap();
+  (tpe, mr)
+}
+
+/// Build a message of type `tpe` with body `body`, ready to be sent to a service. Returns `None`
+/// if the message would be too long.
+pub fn encode_message(tpe: u16, body: &[u8]) -> Option<Vec<u8>> {
+  if body.len() > 0xffff - 4 {
+    return None;
+  }
+  let mut ret = Vec::with_capacity(4 + body.len());
+  ret.write_u16::<BigEndian>((4 + body.len()) as u16).unwrap();
+  ret.write_u16::<BigEndian>(tpe).unwrap();
+  ret.extend_from_slice(body);
+  Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+  use service::ReadMessageError;
+  use super::*;
+
+  #[test]
+  fn test_message_decoder() {
+    let mut stream = encode_message(1, b"abc").unwrap();
+    stream.extend_from_slice(&encode_message(2, b"").unwrap()[..]);
+
+    // Feed one byte at a time, as a non-blocking socket might hand them over.
+    let mut decoder = MessageDecoder::new();
+    let mut messages = Vec::new();
+    for b in stream.iter() {
+      decoder.feed(&[*b]);
+      while let Some((tpe, mut mr)) = decoder.next_message().unwrap() {
+        let mut body = Vec::new();
+        mr.read_to_end(&mut body).unwrap();
+        messages.push((tpe, body));
+      }
+    }
+    assert_eq!(messages, vec![(1, b"abc".to_vec()), (2, Vec::new())]);
+    assert_eq!(decoder.buffered(), 0);
+    assert_eq!(decoder.wanted(), 2);
+
+    decoder.feed(&[0, 7, 0]);
+    assert_eq!(decoder.wanted(), 4);
+
+    let mut decoder = MessageDecoder::new();
+    decoder.feed(&[0, 3, 0, 4, 0, 1]);
+    match decoder.next_message() {
+      Err(ReadMessageError::ShortMessage { len: 3 }) => (),
+      x => panic!("unexpected result: {:?}", x.map(|m| m.map(|(tpe, _)| tpe))),
+    };
+    assert_eq!(decoder.buffered(), 0);
+  }
+
+  #[test]
+  fn test_message_encoder() {
+    let mut encoder = MessageEncoder::new();
+    assert!(encoder.queue(1, b"abc"));
+    assert!(encoder.queue(2, b""));
+    assert!(!encoder.queue(3, &vec![0u8; 0x10000][..]));
+    assert_eq!(encoder.pending(), &[0, 7, 0, 1, b'a', b'b', b'c', 0, 4, 0, 2][..]);
+    encoder.consume(5);
+    assert_eq!(encoder.pending(), &[b'b', b'c', 0, 4, 0, 2][..]);
+    encoder.consume(6);
+    assert!(encoder.pending().is_empty());
+  }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+/// Counters describing how many requests a handle coalesced. Returned by `GNS::dedup_stats` and
+/// `DHT::dedup_stats`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DedupStats {
+  /// The number of requests made through the handle.
+  pub requests: u64,
+  /// The number of requests which joined an identical request already in flight rather than
+  /// being sent to the service.
+  pub coalesced: u64,
+  /// The number of requests currently in flight.
+  pub in_flight: u64,
+}
+
+/// A waiter's place in an `Inflight` table, returned by `Inflight::join`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Ticket {
+  request: u64,
+  waiter: u64,
+}
+
+struct InflightRequest<T> {
+  key: Vec<u8>,
+  waiters: Vec<(u64, Sender<T>)>,
+  delivered: Vec<T>,
+}
+
+/// The requests a handle has sent to a service which are still waiting for results, keyed by
+/// their contents so that identical requests can share one.
+///
+/// A handle registers each request with `add` before sending it, and its callback loop hands each
+/// result to `deliver`. Every waiter of a request receives every result of it, including those
+/// which arrived before the waiter joined.
+pub struct Inflight<T> {
+  ids: HashMap<Vec<u8>, u64>,
+  requests: HashMap<u64, InflightRequest<T>>,
+  next_waiter: u64,
+  stats: DedupStats,
+}
+
+impl<T: Clone> Inflight<T> {
+  /// Create an empty table.
+  pub fn new() -> Inflight<T> {
+    Inflight {
+      ids: HashMap::new(),
+      requests: HashMap::new(),
+      next_waiter: 0,
+      stats: DedupStats::default(),
+    }
+  }
+
+  /// Add `waiter` to the request whose contents, apart from its id, are `key`.
+  ///
+  /// If an identical request is in flight the waiter joins it and `false` is returned. Otherwise
+  /// the request is registered under `id` and `true` is returned, in which case the caller must
+  /// send it.
+  pub fn add(&mut self, id: u64, key: Vec<u8>, waiter: Sender<T>) -> bool {
+    self.join(id, key, waiter).1
+  }
+
+  /// Like `add`, but also returns a ticket with which the waiter can `leave` the request.
+  pub fn join(&mut self, id: u64, key: Vec<u8>, waiter: Sender<T>) -> (Ticket, bool) {
+    self.stats.requests += 1;
+    let waiter_id = self.next_waiter;
+    self.next_waiter += 1;
+    if let Some(existing) = self.ids.get(&key) {
+      let request = self.requests.get_mut(existing).unwrap();
+      for result in request.delivered.iter() {
+        let _ = waiter.send(result.clone());
+      }
+      request.waiters.push((waiter_id, waiter));
+      self.stats.coalesced += 1;
+      return (Ticket { request: *existing, waiter: waiter_id }, false);
+    }
+    self.ids.insert(key.clone(), id);
+    self.requests.insert(id, InflightRequest {
+      key: key,
+      waiters: vec![(waiter_id, waiter)],
+      delivered: Vec::new(),
+    });
+    (Ticket { request: id, waiter: waiter_id }, true)
+  }
+
+  /// Remove the waiter holding `ticket` from its request, eg. because it is no longer interested
+  /// in the results. If it was the last waiter the request is forgotten and its id is returned,
+  /// so that the service can be told to stop working on it. Callers should not ignore the id
+  /// unless the service has no way of stopping a request.
+  #[must_use]
+  pub fn leave(&mut self, ticket: Ticket) -> Option<u64> {
+    let done = match self.requests.get_mut(&ticket.request) {
+      Some(request) => {
+        request.waiters.retain(|&(w, _)| w != ticket.waiter);
+        request.waiters.is_empty()
+      },
+      None => false,
+    };
+    match done {
+      true  => {
+        self.finish(ticket.request);
+        Some(ticket.request)
+      },
+      false => None,
+    }
+  }
+
+  /// Send `result` to every waiter of the request `id`. The request is forgotten once all of its
+  /// waiters have hung up.
+  pub fn deliver(&mut self, id: u64, result: T) {
+    let done = match self.requests.get_mut(&id) {
+      Some(request) => {
+        request.waiters.retain(|&(_, ref w)| w.send(result.clone()).is_ok());
+        request.delivered.push(result);
+        request.waiters.is_empty()
+      },
+      None => false,
+    };
+    if done {
+      self.finish(id);
+    }
+  }
+
+  /// Forget the request `id`, eg. because the service has sent its last result or it could not be
+  /// sent, and return its waiters. Later identical requests are sent afresh.
+  pub fn finish(&mut self, id: u64) -> Vec<Sender<T>> {
+    match self.requests.remove(&id) {
+      Some(request) => {
+        self.ids.remove(&request.key);
+        request.waiters.into_iter().map(|(_, w)| w).collect()
+      },
+      None => Vec::new(),
+    }
+  }
+
+  /// Whether the request `id` is still waiting for results.
+  pub fn contains(&self, id: u64) -> bool {
+    self.requests.contains_key(&id)
+  }
+
+  /// Forget every request, eg. because the connection to the service was lost. Their waiters are
+  /// dropped so that they stop waiting.
+  pub fn clear(&mut self) {
+    self.requests.clear();
+    self.ids.clear();
+  }
+
+  /// The counters for the requests made so far.
+  pub fn stats(&self) -> DedupStats {
+    DedupStats {
+      in_flight: self.requests.len() as u64,
+      .. self.stats
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc::channel;
+  use super::*;
+
+  #[test]
+  fn test_inflight() {
+    let mut inflight = Inflight::new();
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    assert!(inflight.add(1, b"www.gnu".to_vec(), tx1));
+    inflight.deliver(1, 10);
+    // A late joiner is sent what it missed.
+    assert!(!inflight.add(2, b"www.gnu".to_vec(), tx2));
+    inflight.deliver(1, 11);
+    for rx in [rx1, rx2].iter() {
+      assert_eq!(rx.try_recv(), Ok(10));
+      assert_eq!(rx.try_recv(), Ok(11));
+      assert!(rx.try_recv().is_err());
+    }
+    assert_eq!(inflight.stats(), DedupStats { requests: 2, coalesced: 1, in_flight: 1 });
+
+    assert_eq!(inflight.finish(1).len(), 2);
+    let (tx3, rx3) = channel();
+    assert!(inflight.add(3, b"www.gnu".to_vec(), tx3));
+
+    // Requests nobody is waiting for any more are dropped.
+    drop(rx3);
+    inflight.deliver(3, 12);
+    assert_eq!(inflight.stats().in_flight, 0);
+  }
+
+  #[test]
+  fn test_inflight_leave() {
+    let mut inflight = Inflight::new();
+    let (tx1, rx1) = channel();
+    let (tx2, _rx2) = channel();
+    let (first, sent) = inflight.join(1, b"www.gnu".to_vec(), tx1);
+    assert!(sent);
+    let (second, sent) = inflight.join(2, b"www.gnu".to_vec(), tx2);
+    assert!(!sent);
+    // The request is kept while someone still waits for it.
+    assert_eq!(inflight.leave(second), None);
+    inflight.deliver(1, 10);
+    assert_eq!(rx1.try_recv(), Ok(10));
+    assert_eq!(inflight.leave(first), Some(1));
+    assert_eq!(inflight.stats().in_flight, 0);
+    assert_eq!(inflight.leave(first), None);
+
+    let (tx3, rx3) = channel();
+    assert!(inflight.add(3, b"www.gnu".to_vec(), tx3));
+    assert!(inflight.contains(3));
+    inflight.clear();
+    assert!(!inflight.contains(3));
+    assert!(rx3.recv().is_err());
+  }
+}
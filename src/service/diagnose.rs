@@ -0,0 +1,168 @@
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+
+use arm;
+use configuration::Cfg;
+use super::{connect, start_on_demand, ConnectError, ServiceReader, ServiceWriter};
+
+/// A problem which may explain why a service could not be connected to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectProblem {
+  /// The configuration has no section for the service.
+  NoSection,
+  /// The service's section has no `UNIXPATH` option.
+  NoSocketPath,
+  /// Nothing exists at the service's socket path.
+  SocketMissing,
+  /// Something other than a socket exists at the service's socket path.
+  NotASocket,
+  /// ARM is not running, so neither is the peer.
+  ArmNotRunning,
+  /// The service is not started on demand, so ARM won't start it when a client connects.
+  Disabled,
+}
+
+impl fmt::Display for ConnectProblem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let s = match *self {
+      ConnectProblem::NoSection     => "The configuration has no section for the service, check that it is installed and the right config file is loaded",
+      ConnectProblem::NoSocketPath  => "The service's config section has no UNIXPATH option, add one",
+      ConnectProblem::SocketMissing => "The service's socket does not exist, the service is not running",
+      ConnectProblem::NotASocket    => "The service's socket path is not a socket, remove the stale file",
+      ConnectProblem::ArmNotRunning => "ARM is not running, start the peer with `gnunet-arm -s`",
+      ConnectProblem::Disabled      => "The service has START_ON_DEMAND = NO, start it with `gnunet-arm -i`",
+    };
+    write!(f, "{}", s)
+  }
+}
+
+/// What was found while probing why a service could not be connected to. Created by
+/// `diagnose_connect`.
+#[derive(Clone, Debug)]
+pub struct ConnectDiagnostics {
+  /// The name of the service.
+  pub service: String,
+  /// Whether the configuration has a section for the service.
+  pub section_exists: bool,
+  /// The path of the service's socket, if it is configured.
+  pub socket_path: Option<PathBuf>,
+  /// Whether anything exists at the socket path.
+  pub socket_exists: bool,
+  /// Whether the socket path is a socket.
+  pub is_socket: bool,
+  /// Whether ARM is accepting connections.
+  pub arm_running: bool,
+  /// Whether ARM starts the service when a client connects to it.
+  pub start_on_demand: bool,
+}
+
+impl ConnectDiagnostics {
+  /// The problems found, most fundamental first. Empty if nothing stands in the way of
+  /// connecting.
+  pub fn problems(&self) -> Vec<ConnectProblem> {
+    let mut ret = Vec::new();
+    if !self.section_exists {
+      ret.push(ConnectProblem::NoSection);
+    }
+    if self.socket_path.is_none() {
+      ret.push(ConnectProblem::NoSocketPath);
+      return ret;
+    }
+    if self.socket_exists && !self.is_socket {
+      ret.push(ConnectProblem::NotASocket);
+    }
+    if !self.arm_running {
+      ret.push(ConnectProblem::ArmNotRunning);
+    }
+    else if !self.start_on_demand && !self.socket_exists {
+      ret.push(ConnectProblem::Disabled);
+    }
+    if !self.socket_exists && ret.is_empty() {
+      ret.push(ConnectProblem::SocketMissing);
+    }
+    ret
+  }
+}
+
+impl fmt::Display for ConnectDiagnostics {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(writeln!(f, "Service {}:", self.service));
+    try!(writeln!(f, "  config section:  {}", self.section_exists));
+    match self.socket_path {
+      Some(ref path)  => try!(writeln!(f, "  socket path:     {}", path.display())),
+      None            => try!(writeln!(f, "  socket path:     (not configured)")),
+    };
+    try!(writeln!(f, "  socket exists:   {}", self.socket_exists));
+    try!(writeln!(f, "  is a socket:     {}", self.is_socket));
+    try!(writeln!(f, "  ARM running:     {}", self.arm_running));
+    try!(writeln!(f, "  start on demand: {}", self.start_on_demand));
+    for problem in self.problems().iter() {
+      try!(writeln!(f, "  hint: {}", problem));
+    }
+    Ok(())
+  }
+}
+
+/// Probe the configuration, the filesystem and ARM to find out why the service `name` can't be
+/// connected to.
+pub fn diagnose_connect(cfg: &Cfg, name: &str) -> ConnectDiagnostics {
+  let socket_path = cfg.get_filename(name, "UNIXPATH").ok();
+  let file_type = socket_path.as_ref().and_then(|p| fs::symlink_metadata(p).ok()).map(|m| m.file_type());
+  ConnectDiagnostics {
+    service: name.to_string(),
+    section_exists: cfg.get_section(name).is_some(),
+    socket_path: socket_path,
+    socket_exists: file_type.is_some(),
+    is_socket: file_type.map(|t| t.is_socket()).unwrap_or(false),
+    arm_running: arm::is_running(cfg),
+    start_on_demand: start_on_demand(cfg, name),
+  }
+}
+
+/// Errors returned by `connect_diagnosed`.
+error_def! DiagnosedConnectError {
+  Failed { cause: ConnectError, diagnostics: ConnectDiagnostics }
+    => "Failed to connect to the service" ("Reason: {}\n{}", cause, diagnostics),
+}
+
+/// Like `connect`, but if the connection fails the error carries a `ConnectDiagnostics`
+/// explaining why, for command-line tools to print.
+pub fn connect_diagnosed(cfg: &Cfg, name: &str) -> Result<(ServiceReader, ServiceWriter), DiagnosedConnectError> {
+  match connect(cfg, name) {
+    Ok(x)   => Ok(x),
+    Err(e)  => Err(DiagnosedConnectError::Failed {
+      cause: e,
+      diagnostics: diagnose_connect(cfg, name),
+    }),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs::File;
+  use configuration::Cfg;
+  use super::*;
+
+  #[test]
+  fn test_diagnose_connect() {
+    let cfg = Cfg::empty();
+    let diagnostics = diagnose_connect(&cfg, "gns");
+    assert!(!diagnostics.section_exists);
+    assert_eq!(diagnostics.problems(), vec![ConnectProblem::NoSection, ConnectProblem::NoSocketPath]);
+
+    let mut path = env::temp_dir();
+    path.push(format!("gnunet-rs-diagnose-{}", ::rand::random::<u32>()));
+    File::create(&path).unwrap();
+    let mut cfg = Cfg::empty();
+    cfg.set_string("gns", "UNIXPATH", path.to_str().unwrap().to_string());
+    cfg.set_string("arm", "UNIXPATH", path.to_str().unwrap().to_string());
+    let diagnostics = diagnose_connect(&cfg, "gns");
+    ::std::fs::remove_file(&path).unwrap();
+    assert!(diagnostics.socket_exists);
+    assert!(!diagnostics.is_socket);
+    assert_eq!(diagnostics.problems(), vec![ConnectProblem::NotASocket, ConnectProblem::ArmNotRunning]);
+  }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use libc;
+use unix_socket::UnixStream;
+
+/// Shared flag recording whether a service connection has been found to be broken.
+///
+/// A connection is marked as broken when reading or writing a message fails, or when a keepalive
+/// check notices that the service has gone away. Once broken a connection stays broken; the only
+/// way to recover is to connect again.
+#[derive(Clone, Debug)]
+pub struct Liveness {
+  broken: Arc<AtomicBool>,
+}
+
+impl Liveness {
+  /// Create a flag for a connection which is not (yet) broken.
+  pub fn new() -> Liveness {
+    Liveness {
+      broken: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  /// Returns `true` if the connection has been found to be broken.
+  pub fn is_broken(&self) -> bool {
+    self.broken.load(Ordering::SeqCst)
+  }
+
+  /// Mark the connection as broken.
+  pub fn mark_broken(&self) {
+    self.broken.store(true, Ordering::SeqCst);
+  }
+}
+
+/// A connection checked by the keepalive thread.
+struct Watched {
+  connection: UnixStream,
+  liveness: Liveness,
+  interval: Duration,
+  next_check: Instant,
+}
+
+/// Requests sent to the keepalive thread.
+enum Command {
+  Watch(usize, Watched),
+  /// Stop checking a connection and close the thread's handle to it, then acknowledge.
+  Unwatch(usize, Sender<()>),
+}
+
+static NEXT_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+static POOL_INIT: Once = ONCE_INIT;
+static mut POOL: *const Mutex<Option<Sender<Command>>> = 0 as *const _;
+
+/// The channel to the thread which checks every connection with a keepalive.
+fn pool() -> &'static Mutex<Option<Sender<Command>>> {
+  POOL_INIT.call_once(|| unsafe {
+    POOL = Box::into_raw(Box::new(Mutex::new(None)));
+  });
+  unsafe { &*POOL }
+}
+
+/// Send `command` to the keepalive thread, starting the thread if it isn't running.
+fn send_command(command: Command) -> Result<(), io::Error> {
+  let mut pool = pool().lock().unwrap();
+  let command = match *pool {
+    Some(ref tx) => match tx.send(command) {
+      Ok(())  => return Ok(()),
+      Err(e)  => e.0,
+    },
+    None => command,
+  };
+  let (tx, rx) = channel();
+  try!(thread::Builder::new().name("gnunet keepalive".to_string()).spawn(move || run_pool(rx)));
+  let _ = tx.send(command);
+  *pool = Some(tx);
+  Ok(())
+}
+
+/// The body of the keepalive thread. Checks each watched connection when its interval has passed.
+/// If the service has hung up or the socket is in an error state, the connection is marked as
+/// broken and the socket shut down so that any read or write blocked on it fails immediately.
+fn run_pool(commands: Receiver<Command>) {
+  let mut watched: HashMap<usize, Watched> = HashMap::new();
+  loop {
+    let now = Instant::now();
+    let wait = watched.values().map(|w| w.next_check).min().map(|t| match t > now {
+      true  => t - now,
+      false => Duration::from_millis(0),
+    });
+    let command = match wait {
+      Some(wait)  => match commands.recv_timeout(wait) {
+        Ok(c)                               => Some(c),
+        Err(RecvTimeoutError::Timeout)      => None,
+        Err(RecvTimeoutError::Disconnected) => return,
+      },
+      None        => match commands.recv() {
+        Ok(c)   => Some(c),
+        Err(_)  => return,
+      },
+    };
+    match command {
+      Some(Command::Watch(id, w))       => { watched.insert(id, w); },
+      Some(Command::Unwatch(id, done))  => {
+        watched.remove(&id);
+        let _ = done.send(());
+      },
+      None                              => (),
+    };
+
+    let now = Instant::now();
+    let mut finished = Vec::new();
+    for (&id, w) in watched.iter_mut() {
+      if w.next_check > now {
+        continue;
+      }
+      if w.liveness.is_broken() {
+        finished.push(id);
+        continue;
+      }
+      let dead = match peer_hung_up(&w.connection) {
+        Ok(dead)  => dead,
+        Err(_)    => true,
+      };
+      if dead {
+        w.liveness.mark_broken();
+        let _ = w.connection.shutdown(Shutdown::Both);
+        finished.push(id);
+        continue;
+      }
+      w.next_check = now + w.interval;
+    }
+    for id in finished {
+      watched.remove(&id);
+    }
+  }
+}
+
+/// A periodic check that the service at the other end of a connection is still there. Created by
+/// `ServiceReader::keep_alive`.
+///
+/// All connections are checked by a single thread shared by the process. The checks stop when this
+/// is dropped, at which point the thread has closed its handle to the connection.
+pub struct KeepAlive {
+  id: usize,
+}
+
+impl KeepAlive {
+  /// Check `connection` every `interval`, marking `liveness` as broken if the service has gone
+  /// away.
+  pub fn start(connection: &UnixStream, liveness: Liveness, interval: Duration) -> Result<KeepAlive, io::Error> {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let watched = Watched {
+      connection: try!(connection.try_clone()),
+      liveness: liveness,
+      interval: interval,
+      next_check: Instant::now() + interval,
+    };
+    try!(send_command(Command::Watch(id, watched)));
+    Ok(KeepAlive {
+      id: id,
+    })
+  }
+}
+
+impl Drop for KeepAlive {
+  fn drop(&mut self) {
+    let (tx, rx) = channel();
+    if send_command(Command::Unwatch(self.id, tx)).is_ok() {
+      let _ = rx.recv();
+    }
+  }
+}
+
+/// Set in `revents` when the other end has shut down its side of the connection, even if it
+/// hasn't closed the socket yet.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const POLL_RDHUP: libc::c_short = libc::POLLRDHUP;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const POLL_RDHUP: libc::c_short = 0;
+
+/// Poll the socket without blocking to see whether the other end has closed it or it is in an
+/// error state.
+fn peer_hung_up(connection: &UnixStream) -> Result<bool, io::Error> {
+  let mut pfd = libc::pollfd {
+    fd: connection.as_raw_fd(),
+    events: POLL_RDHUP,
+    revents: 0,
+  };
+  let res = unsafe { libc::poll(&mut pfd, 1, 0) };
+  if res < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  if pfd.revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL | POLL_RDHUP) != 0 {
+    return Ok(true);
+  }
+  // A pending error on the socket, eg. a reset, may not show up in the poll events.
+  let mut err: libc::c_int = 0;
+  let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+  let res = unsafe {
+    libc::getsockopt(connection.as_raw_fd(), libc::SOL_SOCKET, libc::SO_ERROR,
+                     &mut err as *mut libc::c_int as *mut libc::c_void, &mut len)
+  };
+  if res < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(err != 0)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::thread;
+  use std::time::Duration;
+  use unix_socket::UnixStream;
+  use service::{CatchAll, Liveness, MessageDecoder, ParseMode, ServiceReader};
+
+  #[test]
+  fn test_keepalive_detects_hangup() {
+    let (ours, theirs) = UnixStream::pair().unwrap();
+    let mut reader = ServiceReader {
+      connection: ours,
+      recording: None,
+      liveness: Liveness::new(),
+      keepalive: None,
+      decoder: MessageDecoder::new(),
+      catch_all: CatchAll::new(),
+      parse_mode: ParseMode::Lenient,
+    };
+    reader.keep_alive(Duration::from_millis(10)).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert!(!reader.is_broken());
+
+    drop(theirs);
+    thread::sleep(Duration::from_millis(100));
+    assert!(reader.is_broken());
+  }
+
+  #[test]
+  fn test_keepalive_zero_is_disabled() {
+    let (ours, _theirs) = UnixStream::pair().unwrap();
+    let mut reader = ServiceReader {
+      connection: ours,
+      recording: None,
+      liveness: Liveness::new(),
+      keepalive: None,
+      decoder: MessageDecoder::new(),
+      catch_all: CatchAll::new(),
+      parse_mode: ParseMode::Lenient,
+    };
+    reader.keep_alive(Duration::from_millis(10)).unwrap();
+    assert!(reader.keepalive.is_some());
+    reader.keep_alive(Duration::from_millis(0)).unwrap();
+    assert!(reader.keepalive.is_none());
+  }
+}
@@ -1,14 +1,48 @@
 //! Module for communicating with GNUnet services. Implements the parts of the GNUnet IPC protocols
 //! that are common to all services.
 
-use std::io::{self, Write, Cursor};
+use std::ascii::AsciiExt;
+use std::io::{self, Read, Write, Cursor};
+use std::fs;
+use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use std::thread;
+use std::time::{Duration, Instant};
 use std::net::Shutdown;
+use libc;
 use unix_socket::UnixStream;
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, WriteBytesExt};
 
+use arm;
 use configuration::{self, Cfg};
+use shutdown::ReadLoop;
 use util::io::ReadUtil;
+pub use self::codec::*;
+pub use self::dedup::*;
+pub use self::diagnose::*;
+pub use self::keepalive::*;
+pub use self::multi::*;
+pub use self::parsing::*;
+pub use self::probe::*;
+pub use self::raw::*;
+pub use self::record::*;
+pub use self::retry::*;
+#[cfg(feature = "async")]
+pub use self::stream::*;
+
+mod codec;
+mod dedup;
+mod diagnose;
+mod keepalive;
+mod multi;
+mod parsing;
+mod probe;
+mod raw;
+mod record;
+mod retry;
+#[cfg(feature = "async")]
+mod stream;
 
 /*
 pub struct Service<'c> {
@@ -23,12 +57,29 @@ pub struct Service<'c> {
 pub struct ServiceReader {
     /// The underlying socket wrapped by `ServiceReader`. This is a read-only socket.
     pub connection: UnixStream, // TODO: should be UnixReader
+    recording: Option<Recording>,
+    liveness: Liveness,
+    keepalive: Option<KeepAlive>,
+    decoder: MessageDecoder,
+    catch_all: CatchAll,
+    /// The parse mode of the thread which connected, used by callback loops.
+    parse_mode: ParseMode,
 }
 
 /// Created by `service::connect`. Used to send messages to a GNUnet service.
 pub struct ServiceWriter {
     /// The underlying socket wrapped by `ServiceWriter`. This is a write-only socket.
     pub connection: UnixStream, // TODO: should be UnixWriter
+    recording: Option<Recording>,
+    liveness: Liveness,
+}
+
+/// A `Recorder` along with the id of the connection being recorded and the filter to apply.
+#[derive(Clone)]
+struct Recording {
+  recorder: Recorder,
+  connection: u32,
+  filter: RecordFilter,
 }
 
 /// Callbacks passed to `ServiceReader::spawn_callback_loop` return a `ProcessMessageResult` to
@@ -41,6 +92,9 @@ pub enum ProcessMessageResult {
   Reconnect,
   /// Exit the callback loop, shutting down it's thread.
   Shutdown,
+  /// The callback doesn't understand the message. It is passed to the connection's `CatchAll`
+  /// handler if one is set, otherwise this is treated like `Reconnect`.
+  Unhandled,
 }
 
 /// Error that can be generated when attempting to connect to a service.
@@ -50,6 +104,61 @@ error_def! ConnectError {
             ("Config does not contain an entry for UNIXPATH in the service's section: {}", cause),
     Io { #[from] cause: io::Error }
         => "There was an I/O error communicating with the service" ("Specifically {}", cause),
+    PermissionDenied { path: String, owner: u32, mode: u32 }
+        => "Permission to connect to the service's socket was denied"
+            ("Socket {} is owned by uid {} with mode {:o}", path, owner, mode),
+    WrongOwner { path: String, owner: u32, expected: u32 }
+        => "The service's socket is not owned by the expected user"
+            ("Socket {} is owned by uid {}, expected uid {}", path, owner, expected),
+    WorldWritable { path: String, mode: u32 }
+        => "The service's socket can be written to by any user"
+            ("Socket {} has mode {:o}", path, mode),
+    StartFailed { name: String, reason: String }
+        => "The service is not running and could not be started through ARM"
+            ("Could not start {}: {}", name, reason),
+}
+
+/// Expectations about the ownership and permissions of a service's socket. Used with
+/// `connect_checked` to avoid talking to a service started by another user on a shared system.
+#[derive(Copy, Clone, Debug)]
+pub struct SocketExpectations {
+  /// The uid which must own the socket, or `None` to accept any owner.
+  pub owner: Option<u32>,
+  /// Whether to accept a socket which any user can write to.
+  pub allow_world_writable: bool,
+}
+
+impl SocketExpectations {
+  /// Require the socket to be owned by the current user and not be world-writable.
+  pub fn current_user() -> SocketExpectations {
+    SocketExpectations {
+      owner: Some(unsafe { libc::getuid() } as u32),
+      allow_world_writable: false,
+    }
+  }
+
+  /// Check the socket at `path` against these expectations.
+  pub fn check(&self, path: &Path) -> Result<(), ConnectError> {
+    let metadata = try!(fs::metadata(path));
+    let owner = metadata.uid();
+    let mode = metadata.mode() & 0o7777;
+    if let Some(expected) = self.owner {
+      if owner != expected {
+        return Err(ConnectError::WrongOwner {
+          path: path.display().to_string(),
+          owner: owner,
+          expected: expected,
+        });
+      }
+    }
+    if !self.allow_world_writable && mode & 0o002 != 0 {
+      return Err(ConnectError::WorldWritable {
+        path: path.display().to_string(),
+        mode: mode,
+      });
+    }
+    Ok(())
+  }
 }
 
 /// Attempt to connect to the local GNUnet service named `name`.
@@ -57,19 +166,162 @@ error_def! ConnectError {
 /// eg. `connect(cfg, "arm")` will attempt to connect to the locally-running `gnunet-arm` service
 /// using the congfiguration details (eg. socket address, port etc.) in `cfg`.
 pub fn connect(cfg: &Cfg, name: &str) -> Result<(ServiceReader, ServiceWriter), ConnectError> {
+  connect_inner(cfg, name, None)
+}
+
+/// Like `connect`, but first check that the service's socket meets `expectations`.
+pub fn connect_checked(cfg: &Cfg, name: &str, expectations: &SocketExpectations) -> Result<(ServiceReader, ServiceWriter), ConnectError> {
+  connect_inner(cfg, name, Some(expectations))
+}
+
+/// How `connect_autostart` starts a service and waits for it to accept connections.
+#[derive(Copy, Clone, Debug)]
+pub struct AutoStart {
+  /// The number of connection attempts made after asking ARM to start the service.
+  pub max_attempts: u32,
+  /// The delay before the first of those attempts. It doubles after each failed attempt.
+  pub initial_delay: Duration,
+  /// The maximum delay between attempts.
+  pub max_delay: Duration,
+}
+
+impl Default for AutoStart {
+  fn default() -> AutoStart {
+    AutoStart {
+      max_attempts: 8,
+      initial_delay: Duration::from_millis(50),
+      max_delay: Duration::from_secs(2),
+    }
+  }
+}
+
+/// Whether the config allows the service `name` to be started on demand. Services are started on
+/// demand unless their `START_ON_DEMAND` (or, in older configs, `AUTOSTART`) option is `NO`.
+fn start_on_demand(cfg: &Cfg, name: &str) -> bool {
+  let section = match cfg.get_section(name) {
+    Some(s) => s,
+    None    => return true,
+  };
+  match section.get("START_ON_DEMAND").or_else(|| section.get("AUTOSTART")) {
+    Some(v) => !v.eq_ignore_ascii_case("NO"),
+    None    => true,
+  }
+}
+
+/// Returns `true` if a failure to connect means nothing is listening on the service's socket.
+fn is_not_running(e: &ConnectError) -> bool {
+  match *e {
+    ConnectError::Io { ref cause } => match cause.kind() {
+      io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => true,
+      _ => false,
+    },
+    _ => false,
+  }
+}
+
+/// Like `connect`, but if the service isn't running ask ARM to start it, then retry the
+/// connection according to `policy`.
+///
+/// This matches the behaviour of the C client library. Services whose config sets
+/// `START_ON_DEMAND = NO` are not started. Fails with `StartFailed` if ARM cannot be reached or
+/// refuses to start the service, or with the last connection error if the service doesn't start
+/// accepting connections in time.
+pub fn connect_autostart(cfg: &Cfg, name: &str, policy: &AutoStart) -> Result<(ServiceReader, ServiceWriter), ConnectError> {
+  let err = match connect_inner(cfg, name, None) {
+    Ok(x)   => return Ok(x),
+    Err(e)  => e,
+  };
+  if name == "arm" || !is_not_running(&err) || !start_on_demand(cfg, name) {
+    return Err(err);
+  }
+
+  match arm::start_service(cfg, name) {
+    Ok(result) => {
+      if !result.is_running() {
+        return Err(ConnectError::StartFailed { name: name.to_string(), reason: result.to_string() });
+      }
+    },
+    Err(e) => return Err(ConnectError::StartFailed { name: name.to_string(), reason: e.to_string() }),
+  };
+
+  let mut delay = policy.initial_delay;
+  let mut last_err = err;
+  for _ in 0..policy.max_attempts {
+    thread::sleep(delay);
+    match connect_inner(cfg, name, None) {
+      Ok(x)                         => return Ok(x),
+      Err(e) if is_not_running(&e)  => last_err = e,
+      Err(e)                        => return Err(e),
+    };
+    delay = delay * 2;
+    if delay > policy.max_delay {
+      delay = policy.max_delay;
+    }
+  }
+  Err(last_err)
+}
+
+fn connect_inner(cfg: &Cfg, name: &str, expectations: Option<&SocketExpectations>) -> Result<(ServiceReader, ServiceWriter), ConnectError> {
   let unixpath = try!(cfg.get_filename(name, "UNIXPATH"));
+  if let Some(expectations) = expectations {
+    try!(expectations.check(&unixpath));
+  }
 
   // TODO: use UnixStream::split() instead when it exists
   let path = unixpath.into_os_string().into_string().unwrap();
-  let in_stream = try!(UnixStream::connect(path));
+  let in_stream = match UnixStream::connect(&path) {
+    Ok(s)   => s,
+    Err(e)  => {
+      if e.kind() == io::ErrorKind::PermissionDenied {
+        if let Ok(metadata) = fs::metadata(&path) {
+          return Err(ConnectError::PermissionDenied {
+            path: path,
+            owner: metadata.uid(),
+            mode: metadata.mode() & 0o7777,
+          });
+        }
+      }
+      return Err(ConnectError::Io { cause: e });
+    },
+  };
   let out_stream = try!(in_stream.try_clone());
 
-  let r = ServiceReader {
+  let filter = RecordFilter::from_cfg(cfg, name);
+  let recording = match cfg.get_filename(name, "RECORD") {
+    // Recording is a debugging aid, so a recording which can't be opened doesn't stop the
+    // connection from being made.
+    Ok(record_path) if filter.matches_service(name) => match Recorder::open(&record_path) {
+      Ok(recorder) => Some(Recording {
+        recorder: recorder,
+        connection: ::rand::random::<u32>(),
+        filter: filter,
+      }),
+      Err(e) => {
+        let _ = writeln!(io::stderr(), "gnunet: not recording the {} connection, failed to open {}: {}",
+                         name, record_path.display(), e);
+        None
+      },
+    },
+    _ => None,
+  };
+  let liveness = Liveness::new();
+  let mut r = ServiceReader {
     connection: in_stream,
+    recording: recording.clone(),
+    liveness: liveness.clone(),
+    keepalive: None,
+    decoder: MessageDecoder::new(),
+    catch_all: CatchAll::new(),
+    parse_mode: parse_mode(),
   };
   let w = ServiceWriter {
     connection: out_stream,
+    recording: recording,
+    liveness: liveness,
   };
+  if let Ok(interval) = cfg.get_relative_time(name, "KEEPALIVE") {
+    try!(r.keep_alive(Duration::from(interval)));
+  }
   Ok((r, w))
 }
 
@@ -88,39 +340,168 @@ impl ServiceReader {
             F: 'static
   {
     let reader = try!(self.connection.try_clone());
+    let catch_all = self.catch_all.clone();
+    let (finished_tx, finished_rx) = channel::<()>();
     let callback_loop = thread::spawn(move || -> ServiceReader {
+      // Dropped when the thread exits, even by panicking, which tells `stop` it can join.
+      let _finished = finished_tx;
+      set_parse_mode(self.parse_mode);
       //TODO: implement reconnection (currently fails)
       loop {
         let (tpe, mr) = match self.read_message() {
           Ok(x)   => x,
           Err(_)  => return self, // TODO: reconnect
         };
+        // The callback consumes the message, so keep a copy in case it can't handle it.
+        let copy = match self.catch_all.is_set() {
+          true  => Some(mr.get_ref().clone()),
+          false => None,
+        };
         match cb(tpe, mr) {
           ProcessMessageResult::Continue  => (),
           ProcessMessageResult::Reconnect => return self, //TODO: auto reconnect
           ProcessMessageResult::Shutdown  => return self,
+          ProcessMessageResult::Unhandled => {
+            let handled = match copy {
+              Some(msg) => self.catch_all.handle(tpe, &msg[2..]),
+              None      => false,
+            };
+            if !handled {
+              return self; //TODO: auto reconnect
+            }
+          },
         };
       }
     });
     Ok(ServiceReadLoop {
       reader:        reader,
-      _callback_loop: callback_loop,
+      catch_all:     catch_all,
+      callback_loop: Some(callback_loop),
+      finished:      finished_rx,
     })
   }
 
+  /// The catch-all handler used by the callback loop spawned from this reader.
+  pub fn catch_all(&self) -> CatchAll {
+    self.catch_all.clone()
+  }
+
+  /// Read the next message from the service, blocking until it arrives. If this fails the
+  /// connection is marked as broken.
   pub fn read_message(&mut self) -> Result<(u16, Cursor<Vec<u8>>), ReadMessageError> {
-    let len = try!(self.connection.read_u16::<BigEndian>());
-    if len < 4 {
-      return Err(ReadMessageError::ShortMessage { len: len });
-    };
-    let v = try!(self.connection.read_exact_alloc(len as usize - 2));
-    let mut mr = Cursor::new(v);
-    let tpe = try!(mr.read_u16::<BigEndian>());
-    Ok((tpe, mr))
+    let res = self.read_message_inner();
+    if res.is_err() {
+      self.liveness.mark_broken();
+    }
+    res
+  }
+
+  /// Start checking every `interval` whether the service is still there, so that a dead service
+  /// is noticed while the connection is idle rather than when it is next used. The checks stop
+  /// when this `ServiceReader` is dropped.
+  ///
+  /// Connections are checked automatically if the service's section of the config sets the
+  /// `KEEPALIVE` option to an interval, eg. `KEEPALIVE = 30 s`. An interval of zero turns the
+  /// checks off.
+  pub fn keep_alive(&mut self, interval: Duration) -> Result<(), io::Error> {
+    // Stop any earlier checks first so they don't outlive the new ones.
+    self.keepalive = None;
+    if interval == Duration::from_millis(0) {
+      return Ok(());
+    }
+    let keepalive = try!(KeepAlive::start(&self.connection, self.liveness.clone(), interval));
+    self.keepalive = Some(keepalive);
+    Ok(())
+  }
+
+  /// Returns `true` if this connection has been found to be broken. A broken connection should be
+  /// dropped and a new one made.
+  pub fn is_broken(&self) -> bool {
+    self.liveness.is_broken()
+  }
+
+  /// The liveness flag shared by this connection's `ServiceReader` and `ServiceWriter`.
+  pub fn liveness(&self) -> Liveness {
+    self.liveness.clone()
+  }
+
+  /// Like `read_message`, but gives up and returns `None` if no complete message arrives within
+  /// `timeout`. Any part of a message received before the timeout is kept for the next read.
+  pub fn read_message_timeout(&mut self, timeout: Duration) -> Result<Option<(u16, Cursor<Vec<u8>>)>, ReadMessageError> {
+    let deadline = Instant::now() + timeout;
+    let res = self.read_message_until(deadline);
+    let _ = self.connection.set_read_timeout(None);
+    if res.is_err() {
+      self.liveness.mark_broken();
+    }
+    res
+  }
+
+  /// Take the next complete message out of the decoder, recording it if the connection is being
+  /// recorded.
+  fn take_frame(&mut self) -> Result<Option<(u16, Cursor<Vec<u8>>)>, ReadMessageError> {
+    match try!(self.decoder.next_frame()) {
+      Some(frame) => {
+        if let Some(ref recording) = self.recording {
+          // A failure to record should not break the connection.
+          let _ = recording.recorder.record_filtered(&recording.filter, recording.connection,
+                                                    Direction::FromService, &frame[..]);
+        }
+        Ok(Some(split_frame(frame)))
+      },
+      None        => Ok(None),
+    }
+  }
+
+  fn read_message_inner(&mut self) -> Result<(u16, Cursor<Vec<u8>>), ReadMessageError> {
+    loop {
+      if let Some(msg) = try!(self.take_frame()) {
+        return Ok(msg);
+      }
+      // Only read what the decoder asks for so nothing past the end of the message is consumed.
+      let wanted = self.decoder.wanted();
+      let data = try!(self.connection.read_exact_alloc(wanted));
+      self.decoder.feed(&data[..]);
+    }
+  }
+
+  fn read_message_until(&mut self, deadline: Instant) -> Result<Option<(u16, Cursor<Vec<u8>>)>, ReadMessageError> {
+    loop {
+      if let Some(msg) = try!(self.take_frame()) {
+        return Ok(Some(msg));
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        return Ok(None);
+      }
+      try!(self.connection.set_read_timeout(Some(deadline - now)));
+      // Whatever arrives is fed to the decoder straight away so nothing is lost on a timeout.
+      let mut buf = vec![0u8; self.decoder.wanted()];
+      match self.connection.read(&mut buf[..]) {
+        Ok(0)                                                 => return Err(ReadMessageError::Disconnected),
+        Ok(n)                                                 => self.decoder.feed(&buf[..n]),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                   || e.kind() == io::ErrorKind::TimedOut     => return Ok(None),
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted  => (),
+        Err(e)                                                => return Err(From::from(e)),
+      };
+    }
   }
 }
 
 impl ServiceWriter {
+  /// Stop sending to the service. The service sees the connection closed, which ends the
+  /// connection's read side as well.
+  pub fn close(&mut self) {
+    let _ = self.connection.shutdown(Shutdown::Write);
+  }
+
+  /// Returns `true` if this connection has been found to be broken. A broken connection should be
+  /// dropped and a new one made.
+  pub fn is_broken(&self) -> bool {
+    self.liveness.is_broken()
+  }
+
   pub fn write_message<'a>(&'a mut self, len: u16, tpe: u16) -> MessageWriter<'a> {
     assert!(len >= 4);
     let v = Vec::with_capacity(len as usize);
@@ -145,7 +526,15 @@ impl<'a> MessageWriter<'a> {
   pub fn send(self) -> Result<(), io::Error> {
     let v = self.mw.into_inner();
     assert!(v.len() == v.capacity());
-    self.service_writer.connection.write_all(&v[..])
+    if let Some(ref recording) = self.service_writer.recording {
+      let _ = recording.recorder.record_filtered(&recording.filter, recording.connection,
+                                                  Direction::ToService, &v[..]);
+    }
+    let res = self.service_writer.connection.write_all(&v[..]);
+    if res.is_err() {
+      self.service_writer.liveness.mark_broken();
+    }
+    res
   }
 }
 
@@ -163,10 +552,35 @@ impl<'a> Write for MessageWriter<'a> {
 /// Created with `ServiceReader::spawn_callback_loop`.
 pub struct ServiceReadLoop {
   reader: UnixStream,
-  _callback_loop: thread::JoinHandle<ServiceReader>,
+  catch_all: CatchAll,
+  callback_loop: Option<thread::JoinHandle<ServiceReader>>,
+  finished: Receiver<()>,
+}
+
+/// Errors returned by `ServiceReadLoop::stop`.
+error_def! StopLoopError {
+  TimedOut
+    => "The thread did not stop in time. It is joined when the loop is dropped",
+  Panicked
+    => "The callback panicked",
 }
 
 impl ServiceReadLoop {
+  /// The catch-all handler for messages the loop's callback doesn't understand.
+  pub fn catch_all(&self) -> CatchAll {
+    self.catch_all.clone()
+  }
+
+  /// Stop reading from the service and wait up to `timeout` for the thread to finish. The thread
+  /// only notices once it is done with the message it is handling, if any.
+  ///
+  /// Calling this again after it has succeeded does nothing. If it timed out, calling it again
+  /// waits for the thread again.
+  pub fn stop(&mut self, timeout: Duration) -> Result<(), StopLoopError> {
+    let _ = self.reader.shutdown(Shutdown::Read);
+    join_within(&mut self.callback_loop, &self.finished, timeout)
+  }
+
   /*
   fn join(mut self) -> ServiceReader {
     let _ = self.reader.shutdown(Shutdown::Read);
@@ -175,10 +589,39 @@ impl ServiceReadLoop {
   */
 }
 
+impl ReadLoop for ServiceReadLoop {
+  fn close(&mut self, timeout: Duration) -> Result<(), StopLoopError> {
+    let _ = self.reader.shutdown(Shutdown::Both);
+    self.stop(timeout)
+  }
+}
+
+/// Dropping the loop stops reading from the service and waits for the thread to finish, which
+/// happens as soon as the callback is done with the message it is handling, if any.
 impl Drop for ServiceReadLoop {
   fn drop(&mut self) {
     let _ = self.reader.shutdown(Shutdown::Read);
-    //let _ = self.callback_loop.join();
+    if let Some(callback_loop) = self.callback_loop.take() {
+      let _ = callback_loop.join();
+    }
+  }
+}
+
+/// Join the thread in `handle` if it finishes within `timeout`. `finished` is disconnected when
+/// the thread exits. If it doesn't finish in time `handle` is left as it is, so that it can be
+/// waited for again or joined later.
+fn join_within<T>(handle: &mut Option<thread::JoinHandle<T>>, finished: &Receiver<()>, timeout: Duration) -> Result<(), StopLoopError> {
+  if handle.is_none() {
+    return Ok(());
+  }
+  // Threads can't be joined with a timeout, so wait for the thread to hang up instead.
+  match finished.recv_timeout(timeout) {
+    Err(RecvTimeoutError::Timeout)  => return Err(StopLoopError::TimedOut),
+    _                               => (),
+  }
+  match handle.take().map(|h| h.join()) {
+    Some(Err(_))  => Err(StopLoopError::Panicked),
+    _             => Ok(()),
   }
 }
 
@@ -0,0 +1,197 @@
+use std::io::{self, Cursor};
+use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+use libc;
+use unix_socket::UnixStream;
+
+use service::{ServiceReader, ProcessMessageResult, StopLoopError, join_within, set_parse_mode};
+use shutdown::ReadLoop;
+
+/// A thread that reads from several service connections at once, passing each message to a
+/// callback along with the index of the connection it arrived on. Created with
+/// `spawn_shared_loop`.
+///
+/// The connections share a lifecycle: if reading from any of them fails, or the callback returns
+/// `Reconnect` or `Shutdown`, the thread stops reading from all of them. Dropping the loop shuts
+/// down every connection and waits for the thread to finish.
+pub struct SharedReadLoop {
+  readers: Vec<UnixStream>,
+  thread: Option<thread::JoinHandle<Vec<ServiceReader>>>,
+  finished: Receiver<()>,
+}
+
+/// Read from all of `readers` on a single thread, passing messages to `cb`. The first argument of
+/// `cb` is the index in `readers` of the connection the message came from.
+///
+/// Messages the callback returns `Unhandled` for are given to the catch-all handler of the
+/// connection they came from.
+pub fn spawn_shared_loop<F>(mut readers: Vec<ServiceReader>, mut cb: F) -> Result<SharedReadLoop, io::Error>
+    where F: FnMut(usize, u16, Cursor<Vec<u8>>) -> ProcessMessageResult,
+          F: Send,
+          F: 'static
+{
+  let mut sockets = Vec::with_capacity(readers.len());
+  for reader in readers.iter() {
+    sockets.push(try!(reader.connection.try_clone()));
+  }
+  let (finished_tx, finished_rx) = channel::<()>();
+  let thread = try!(thread::Builder::new().name("gnunet shared read loop".to_string()).spawn(move || -> Vec<ServiceReader> {
+    let _finished = finished_tx;
+    loop {
+      let ready = match wait_readable(&readers) {
+        Ok(ready) => ready,
+        Err(_)    => return readers,
+      };
+      for i in ready {
+        let (tpe, mr) = match readers[i].read_message() {
+          Ok(x)   => x,
+          Err(_)  => return readers,
+        };
+        let copy = match readers[i].catch_all.is_set() {
+          true  => Some(mr.get_ref().clone()),
+          false => None,
+        };
+        set_parse_mode(readers[i].parse_mode);
+        match cb(i, tpe, mr) {
+          ProcessMessageResult::Continue  => (),
+          ProcessMessageResult::Reconnect => return readers,
+          ProcessMessageResult::Shutdown  => return readers,
+          ProcessMessageResult::Unhandled => {
+            let handled = match copy {
+              Some(msg) => readers[i].catch_all.handle(tpe, &msg[2..]),
+              None      => false,
+            };
+            if !handled {
+              return readers;
+            }
+          },
+        };
+      }
+    }
+  }));
+  Ok(SharedReadLoop {
+    readers: sockets,
+    thread: Some(thread),
+    finished: finished_rx,
+  })
+}
+
+impl SharedReadLoop {
+  /// Stop reading from every connection and wait up to `timeout` for the thread to finish. As
+  /// with `ServiceReadLoop::stop`, calling this again after a timeout waits again.
+  pub fn stop(&mut self, timeout: Duration) -> Result<(), StopLoopError> {
+    for reader in self.readers.iter() {
+      let _ = reader.shutdown(Shutdown::Read);
+    }
+    join_within(&mut self.thread, &self.finished, timeout)
+  }
+}
+
+impl ReadLoop for SharedReadLoop {
+  fn close(&mut self, timeout: Duration) -> Result<(), StopLoopError> {
+    for reader in self.readers.iter() {
+      let _ = reader.shutdown(Shutdown::Both);
+    }
+    self.stop(timeout)
+  }
+}
+
+impl Drop for SharedReadLoop {
+  fn drop(&mut self) {
+    for reader in self.readers.iter() {
+      let _ = reader.shutdown(Shutdown::Read);
+    }
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Block until at least one of `readers` has something to read, or has hung up. Returns the
+/// indices of those readers.
+fn wait_readable(readers: &[ServiceReader]) -> Result<Vec<usize>, io::Error> {
+  let mut pfds: Vec<libc::pollfd> = readers.iter().map(|r| libc::pollfd {
+    fd: r.connection.as_raw_fd(),
+    events: libc::POLLIN,
+    revents: 0,
+  }).collect();
+  loop {
+    let res = unsafe { libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, -1) };
+    if res >= 0 {
+      break;
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() != io::ErrorKind::Interrupted {
+      return Err(err);
+    }
+  }
+  Ok(pfds.iter().enumerate().filter(|&(_, pfd)| pfd.revents != 0).map(|(i, _)| i).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+  use std::sync::mpsc::channel;
+  use std::time::Duration;
+  use unix_socket::UnixStream;
+  use service::{CatchAll, Liveness, MessageDecoder, ParseMode, ServiceReader, ProcessMessageResult, StopLoopError};
+  use super::*;
+
+  fn reader(connection: UnixStream) -> ServiceReader {
+    ServiceReader {
+      connection: connection,
+      recording: None,
+      liveness: Liveness::new(),
+      keepalive: None,
+      decoder: MessageDecoder::new(),
+      catch_all: CatchAll::new(),
+      parse_mode: ParseMode::Lenient,
+    }
+  }
+
+  #[test]
+  fn test_shared_loop() {
+    let (ours_a, mut theirs_a) = UnixStream::pair().unwrap();
+    let (ours_b, mut theirs_b) = UnixStream::pair().unwrap();
+    let (tx, rx) = channel();
+    let _read_loop = spawn_shared_loop(vec![reader(ours_a), reader(ours_b)], move |i, tpe, _| {
+      tx.send((i, tpe)).unwrap();
+      ProcessMessageResult::Continue
+    }).unwrap();
+
+    theirs_b.write_all(&[0, 4, 0, 7]).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), (1, 7));
+    theirs_a.write_all(&[0, 5, 0, 9, 42]).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), (0, 9));
+
+    // Either connection going away stops the loop.
+    drop(theirs_a);
+    assert!(rx.recv_timeout(Duration::from_secs(5)).is_err());
+  }
+
+  #[test]
+  fn test_shared_loop_stop() {
+    let (ours, _theirs) = UnixStream::pair().unwrap();
+    let mut read_loop = spawn_shared_loop(vec![reader(ours)], |_, _, _| ProcessMessageResult::Continue).unwrap();
+    read_loop.stop(Duration::from_secs(5)).unwrap();
+    read_loop.stop(Duration::from_secs(5)).unwrap();
+
+    // A callback which is slow to return makes `stop` time out, after which it can wait again.
+    let (ours, mut theirs) = UnixStream::pair().unwrap();
+    let (tx, rx) = channel::<()>();
+    let mut read_loop = spawn_shared_loop(vec![reader(ours)], move |_, _, _| {
+      let _ = rx.recv();
+      ProcessMessageResult::Continue
+    }).unwrap();
+    theirs.write_all(&[0, 4, 0, 7]).unwrap();
+    match read_loop.stop(Duration::from_millis(50)) {
+      Err(StopLoopError::TimedOut) => (),
+      _ => panic!("expected the stop to time out"),
+    }
+    tx.send(()).unwrap();
+    read_loop.stop(Duration::from_secs(5)).unwrap();
+  }
+}
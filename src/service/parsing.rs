@@ -0,0 +1,88 @@
+use std::cell::Cell;
+use std::io::{self, Cursor};
+
+/// How messages received from services are checked for things this library doesn't know about.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseMode {
+  /// Reject messages with bytes left over after their last field or with flags set that aren't
+  /// known. Useful in tests, to notice when the services' protocol has drifted from ours.
+  Strict,
+  /// Ignore leftover bytes and unknown flags. This is the default, as it lets the library keep
+  /// working with newer services.
+  Lenient,
+}
+
+thread_local!(static MODE: Cell<ParseMode> = Cell::new(ParseMode::Lenient));
+
+/// The mode used by this thread to parse incoming messages.
+pub fn parse_mode() -> ParseMode {
+  MODE.with(|m| m.get())
+}
+
+/// Set the mode used by this thread to parse incoming messages.
+///
+/// Connections remember the mode of the thread which opened them, and the callback loops they
+/// spawn parse in that mode. So set the mode before connecting to have it apply to a
+/// connection's callback loop as well.
+pub fn set_parse_mode(mode: ParseMode) {
+  MODE.with(|m| m.set(mode));
+}
+
+/// Check that `reader` has been read to the end. In lenient mode this always succeeds.
+pub fn check_end(reader: &Cursor<Vec<u8>>) -> Result<(), io::Error> {
+  let left = reader.get_ref().len().saturating_sub(reader.position() as usize);
+  match (parse_mode(), left) {
+    (ParseMode::Strict, n) if n > 0
+      => Err(io::Error::new(io::ErrorKind::InvalidData, "trailing bytes after the end of the message")),
+    _ => Ok(()),
+  }
+}
+
+/// Check that no bits are set in `flags` except those in `known`. In lenient mode this always
+/// succeeds.
+pub fn check_flags(flags: u32, known: u32) -> Result<(), io::Error> {
+  match parse_mode() {
+    ParseMode::Strict if flags & !known != 0
+      => Err(io::Error::new(io::ErrorKind::InvalidData, "unknown flags are set")),
+    _ => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Cursor, Read};
+  use super::*;
+
+  #[test]
+  fn test_parse_mode() {
+    let mut reader = Cursor::new(vec![1u8, 2, 3]);
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf).unwrap();
+
+    // Lenient is the default.
+    assert_eq!(parse_mode(), ParseMode::Lenient);
+    assert!(check_end(&reader).is_ok());
+    assert!(check_flags(0x80, 0x0f).is_ok());
+
+    set_parse_mode(ParseMode::Strict);
+    let strict_end = check_end(&reader).is_err();
+    let strict_flags = check_flags(0x80, 0x0f).is_err();
+    let known_flags = check_flags(0x0a, 0x0f).is_ok();
+    reader.read_exact(&mut buf[..1]).unwrap();
+    let at_end = check_end(&reader).is_ok();
+    set_parse_mode(ParseMode::Lenient);
+
+    assert!(strict_end);
+    assert!(strict_flags);
+    assert!(known_flags);
+    assert!(at_end);
+  }
+
+  #[test]
+  fn test_parse_mode_is_per_thread() {
+    set_parse_mode(ParseMode::Strict);
+    let other = ::std::thread::spawn(|| parse_mode()).join().unwrap();
+    assert_eq!(parse_mode(), ParseMode::Strict);
+    assert_eq!(other, ParseMode::Lenient);
+  }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::time::Duration;
+
+use configuration::Cfg;
+use super::{connect, ConnectError, ReadMessageError};
+
+/// How a service reacted to a message sent by `probe`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProbeOutcome {
+  /// The service replied with a message of type `tpe`.
+  Answered { tpe: u16 },
+  /// The service dropped the connection. GNUnet services do this when a client sends a message
+  /// type they don't handle or a message of the wrong size, so the message is not understood.
+  Rejected,
+  /// The service neither replied nor dropped the connection before the timeout. It accepted the
+  /// message but had nothing to say about it.
+  Silent,
+}
+
+impl ProbeOutcome {
+  /// Whether the service understood the message.
+  pub fn understood(&self) -> bool {
+    *self != ProbeOutcome::Rejected
+  }
+}
+
+/// Classify the result of waiting for the reply to a probe.
+fn classify<T>(res: Result<(u16, T), ReadMessageError>) -> ProbeOutcome {
+  match res {
+    Ok((tpe, _)) => ProbeOutcome::Answered { tpe: tpe },
+    Err(ReadMessageError::Io { ref cause })
+      if cause.kind() == io::ErrorKind::WouldBlock || cause.kind() == io::ErrorKind::TimedOut
+                 => ProbeOutcome::Silent,
+    Err(_)       => ProbeOutcome::Rejected,
+  }
+}
+
+/// Find out whether the service `name` understands messages of type `tpe` by sending one with
+/// `body` over a fresh connection and waiting up to `timeout` to see how the service reacts.
+///
+/// The connection is closed afterwards, so the message should not have lasting effects on the
+/// service, eg. a lookup rather than a store.
+pub fn probe(cfg: &Cfg, name: &str, tpe: u16, body: &[u8], timeout: Duration) -> Result<ProbeOutcome, ConnectError> {
+  let (mut sr, mut sw) = try!(connect(cfg, name));
+  if sw.send_raw(tpe, body).is_err() {
+    return Ok(ProbeOutcome::Rejected);
+  }
+  try!(sr.connection.set_read_timeout(Some(timeout)));
+  Ok(classify(sr.read_message()))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io;
+  use service::ReadMessageError;
+  use super::*;
+  use super::classify;
+
+  #[test]
+  fn test_classify() {
+    assert_eq!(classify(Ok((501, ()))), ProbeOutcome::Answered { tpe: 501 });
+    assert_eq!(classify::<()>(Err(ReadMessageError::Disconnected)), ProbeOutcome::Rejected);
+    let timed_out = io::Error::new(io::ErrorKind::WouldBlock, "timed out");
+    assert_eq!(classify::<()>(Err(ReadMessageError::Io { cause: timed_out })), ProbeOutcome::Silent);
+    let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+    assert_eq!(classify::<()>(Err(ReadMessageError::Io { cause: reset })), ProbeOutcome::Rejected);
+    assert!(ProbeOutcome::Silent.understood());
+    assert!(!ProbeOutcome::Rejected.understood());
+  }
+}
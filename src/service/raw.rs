@@ -0,0 +1,94 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use service::ServiceWriter;
+
+/// A handler for messages which the typed API reading a connection doesn't understand. Called
+/// with the message type and body.
+pub type RawHandler = Box<FnMut(u16, &[u8]) + Send>;
+
+/// The catch-all handler of a connection's callback loop. Clones refer to the same handler, so it
+/// can be set or replaced while the loop is running.
+///
+/// Callbacks return `ProcessMessageResult::Unhandled` for messages they don't understand. These
+/// are given to the catch-all handler if one is set. Otherwise the loop reconnects, as it would
+/// for `ProcessMessageResult::Reconnect`.
+#[derive(Clone)]
+pub struct CatchAll {
+  handler: Arc<Mutex<Option<RawHandler>>>,
+}
+
+impl CatchAll {
+  /// Create a slot with no handler set.
+  pub fn new() -> CatchAll {
+    CatchAll {
+      handler: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  /// Set the handler, replacing any previous one.
+  pub fn set<F>(&self, handler: F)
+      where F: FnMut(u16, &[u8]) + Send + 'static
+  {
+    *self.handler.lock().unwrap() = Some(Box::new(handler));
+  }
+
+  /// Remove the handler.
+  pub fn clear(&self) {
+    *self.handler.lock().unwrap() = None;
+  }
+
+  /// Returns `true` if a handler is set.
+  pub fn is_set(&self) -> bool {
+    self.handler.lock().unwrap().is_some()
+  }
+
+  /// Pass a message to the handler. Returns `false` if no handler is set.
+  pub fn handle(&self, tpe: u16, body: &[u8]) -> bool {
+    match *self.handler.lock().unwrap() {
+      Some(ref mut handler) => {
+        handler(tpe, body);
+        true
+      },
+      None => false,
+    }
+  }
+}
+
+impl ServiceWriter {
+  /// Send a message of type `tpe` with body `body`, bypassing the typed APIs.
+  ///
+  /// This is an escape hatch for experimenting with messages this crate doesn't cover yet. The
+  /// typed handle sharing the connection may be confused by any replies, which should be received
+  /// through a `CatchAll` handler. Fails with `InvalidInput` if the message would be too long.
+  pub fn send_raw(&mut self, tpe: u16, body: &[u8]) -> Result<(), io::Error> {
+    if body.len() > 0xffff - 4 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "message too long"));
+    }
+    let mut mw = self.write_message((4 + body.len()) as u16, tpe);
+    mw.write_all(body).unwrap();
+    mw.send()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use super::*;
+
+  #[test]
+  fn test_catch_all() {
+    let catch_all = CatchAll::new();
+    assert!(!catch_all.handle(1, b"x"));
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_cloned = seen.clone();
+    catch_all.clone().set(move |tpe, body| seen_cloned.lock().unwrap().push((tpe, body.to_vec())));
+    assert!(catch_all.is_set());
+    assert!(catch_all.handle(7, b"abc"));
+    assert_eq!(*seen.lock().unwrap(), vec![(7, b"abc".to_vec())]);
+
+    catch_all.clear();
+    assert!(!catch_all.handle(7, b"abc"));
+  }
+}
@@ -0,0 +1,545 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use unix_socket::UnixListener;
+
+use configuration::Cfg;
+use time::now_micros;
+
+const MAGIC: &'static [u8] = b"GNRSREC\0";
+const VERSION: u32 = 2;
+/// Set in the direction byte of a message whose payload was cut short by a `RecordFilter`.
+const TRUNCATED_FLAG: u8 = 0x80;
+
+/// The direction a recorded message travelled in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+  /// The message was sent by the client to the service.
+  ToService,
+  /// The message was received by the client from the service.
+  FromService,
+}
+
+/// A message captured by a `Recorder`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedMessage {
+  /// When the message was sent or received, in microseconds since the epoch.
+  pub timestamp: u64,
+  /// Identifies the connection the message was sent over. Messages from several connections may
+  /// be interleaved in one recording.
+  pub connection: u32,
+  /// Which way the message went.
+  pub direction: Direction,
+  /// The whole message, including its size and type header. If `truncated` is set the size in
+  /// the header is that of the recorded part, not of the message that was sent.
+  pub message: Vec<u8>,
+  /// The payload was cut short because the recording's `RecordFilter` redacts large payloads.
+  pub truncated: bool,
+}
+
+impl RecordedMessage {
+  /// The type of the message, read from its header.
+  pub fn message_type(&self) -> u16 {
+    if self.message.len() < 4 {
+      return 0;
+    }
+    ((self.message[2] as u16) << 8) | self.message[3] as u16
+  }
+
+  fn serialize<W: Write>(&self, w: &mut W) -> Result<(), io::Error> {
+    try!(w.write_u64::<BigEndian>(self.timestamp));
+    try!(w.write_u32::<BigEndian>(self.connection));
+    let direction = match self.direction {
+      Direction::ToService    => 0,
+      Direction::FromService  => 1,
+    };
+    try!(w.write_u8(match self.truncated {
+      true  => direction | TRUNCATED_FLAG,
+      false => direction,
+    }));
+    w.write_all(&self.message[..])
+  }
+}
+
+/// Decides which messages a recording keeps and how much of each.
+///
+/// Filters are read from the config by `service::connect`. The `[record]` section sets defaults
+/// for every service:
+///
+/// ```text
+/// [record]
+/// SERVICES = gns identity
+/// TYPES = 500-599 636
+/// MAX_PAYLOAD = 64
+/// ```
+///
+/// `SERVICES` limits recording to the services listed, so `RECORD` can be left set for every
+/// service and only switched on where needed. `TYPES` is a list of message types and inclusive
+/// ranges of them to record. `MAX_PAYLOAD` cuts each recorded payload down to that many bytes. A
+/// service's own section can override the last two with `RECORD_TYPES` and `RECORD_MAX_PAYLOAD`.
+/// Unset options record everything and malformed entries are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecordFilter {
+  /// The services to record. `None` records every service which has `RECORD` set.
+  pub services: Option<Vec<String>>,
+  /// Inclusive ranges of message types to record. If empty, every type is recorded.
+  pub types: Vec<(u16, u16)>,
+  /// The most payload bytes to record of each message. Anything past this is dropped.
+  pub max_payload: Option<usize>,
+}
+
+impl RecordFilter {
+  /// Read the filter for the service `name` from `cfg`.
+  pub fn from_cfg(cfg: &Cfg, name: &str) -> RecordFilter {
+    let mut ret = RecordFilter::default();
+    if let Some(section) = cfg.get_section("record") {
+      ret.services = section.get("SERVICES").map(|v| {
+        v.split(|c: char| c == ',' || c.is_whitespace())
+         .filter(|s| !s.is_empty())
+         .map(|s| s.to_string())
+         .collect()
+      });
+      if let Some(v) = section.get("TYPES") {
+        ret.types = parse_type_ranges(v);
+      }
+      ret.max_payload = section.get("MAX_PAYLOAD").and_then(|v| v.parse().ok());
+    }
+    if let Some(section) = cfg.get_section(name) {
+      if let Some(v) = section.get("RECORD_TYPES") {
+        ret.types = parse_type_ranges(v);
+      }
+      if let Some(max) = section.get("RECORD_MAX_PAYLOAD").and_then(|v| v.parse().ok()) {
+        ret.max_payload = Some(max);
+      }
+    }
+    ret
+  }
+
+  /// Whether connections to the service `name` should be recorded.
+  pub fn matches_service(&self, name: &str) -> bool {
+    match self.services {
+      Some(ref services) => services.iter().any(|s| s == name),
+      None               => true,
+    }
+  }
+
+  /// Whether messages of type `tpe` should be recorded.
+  pub fn matches_type(&self, tpe: u16) -> bool {
+    self.types.is_empty() || self.types.iter().any(|&(lo, hi)| lo <= tpe && tpe <= hi)
+  }
+}
+
+fn parse_type_ranges(s: &str) -> Vec<(u16, u16)> {
+  let mut ret = Vec::new();
+  for entry in s.split(|c: char| c == ',' || c.is_whitespace()).filter(|e| !e.is_empty()) {
+    let mut it = entry.splitn(2, '-');
+    let lo = it.next().and_then(|v| v.parse::<u16>().ok());
+    let hi = match it.next() {
+      Some(v) => v.parse::<u16>().ok(),
+      None    => lo,
+    };
+    if let (Some(lo), Some(hi)) = (lo, hi) {
+      if lo <= hi {
+        ret.push((lo, hi));
+      }
+    }
+  }
+  ret
+}
+
+/// Captures the IPC messages exchanged with services to a file or other writer, for debugging.
+///
+/// A recording starts with the magic bytes `GNRSREC\0` and a format version (u32). Each message
+/// follows as a timestamp (u64, microseconds since the epoch), a connection id (u32), a direction
+/// byte (0 for client to service, 1 for service to client, with the high bit set if the payload
+/// was truncated) and the message itself, which carries its own length in its header. All
+/// integers are big-endian.
+///
+/// Recording is enabled for a service by setting the `RECORD` option in the service's config
+/// section to a file name, eg.
+///
+/// ```text
+/// [gns]
+/// RECORD = /tmp/gns.rec
+/// ```
+///
+/// Every connection made to that service with `service::connect` then appends to the file,
+/// subject to the service's `RecordFilter`. Recordings can be inspected with `RecordingReader`
+/// and replayed with `Replayer`.
+#[derive(Clone)]
+pub struct Recorder {
+  out: Arc<Mutex<Box<Write + Send>>>,
+}
+
+impl Recorder {
+  /// Start a recording which writes to `out`.
+  pub fn new<W>(mut out: W) -> Result<Recorder, io::Error>
+      where W: Write + Send + 'static
+  {
+    try!(out.write_all(MAGIC));
+    try!(out.write_u32::<BigEndian>(VERSION));
+    Ok(Recorder {
+      out: Arc::new(Mutex::new(Box::new(out))),
+    })
+  }
+
+  /// Append to the recording at `path`, creating it if it doesn't exist.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Recorder, io::Error> {
+    let file = try!(OpenOptions::new().create(true).append(true).open(path));
+    if try!(file.metadata()).len() == 0 {
+      return Recorder::new(file);
+    }
+    Ok(Recorder {
+      out: Arc::new(Mutex::new(Box::new(file))),
+    })
+  }
+
+  /// Record a message sent over the connection `connection`.
+  ///
+  /// Each message is written with a single call so that several recorders appending to the same
+  /// file don't interleave partial messages.
+  pub fn record(&self, connection: u32, direction: Direction, message: &[u8]) -> Result<(), io::Error> {
+    self.write(RecordedMessage {
+      timestamp: now_micros(),
+      connection: connection,
+      direction: direction,
+      message: message.to_vec(),
+      truncated: false,
+    })
+  }
+
+  /// Record a message if `filter` lets it through, truncating its payload if `filter` asks for
+  /// that. The filter's service list is not checked here.
+  pub fn record_filtered(&self, filter: &RecordFilter, connection: u32, direction: Direction, message: &[u8])
+      -> Result<(), io::Error>
+  {
+    if message.len() < 4 {
+      return self.record(connection, direction, message);
+    }
+    let tpe = ((message[2] as u16) << 8) | message[3] as u16;
+    if !filter.matches_type(tpe) {
+      return Ok(());
+    }
+    let keep = match filter.max_payload {
+      Some(max) if message.len() > 4 + max => 4 + max,
+      _                                    => return self.record(connection, direction, message),
+    };
+    let mut truncated = message[..keep].to_vec();
+    truncated[0] = (keep >> 8) as u8;
+    truncated[1] = keep as u8;
+    self.write(RecordedMessage {
+      timestamp: now_micros(),
+      connection: connection,
+      direction: direction,
+      message: truncated,
+      truncated: true,
+    })
+  }
+
+  fn write(&self, rm: RecordedMessage) -> Result<(), io::Error> {
+    let mut buf = Vec::with_capacity(13 + rm.message.len());
+    try!(rm.serialize(&mut buf));
+    let mut out = self.out.lock().unwrap();
+    try!(out.write_all(&buf[..]));
+    out.flush()
+  }
+}
+
+/// Errors returned when reading a recording.
+error_def! ReadRecordingError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error reading the recording" ("Specifically: {}", cause),
+  BadMagic
+    => "The file is not a message recording",
+  UnsupportedVersion { version: u32 }
+    => "The recording has an unsupported version" ("Version: {}", version),
+  InvalidDirection { direction: u8 }
+    => "A recorded message has an invalid direction" ("Direction byte: {}", direction),
+  ShortMessage { len: u16 }
+    => "A recorded message is too short" ("Length was {} bytes.", len),
+  Disconnected
+    => "The recording ends part way through a message",
+}
+byteorder_error_chain! {ReadRecordingError}
+
+/// Iterates over the messages in a recording.
+pub struct RecordingReader<R> {
+  reader: R,
+}
+
+impl<R: Read> RecordingReader<R> {
+  /// Read a recording from `reader`, checking its header.
+  pub fn new(mut reader: R) -> Result<RecordingReader<R>, ReadRecordingError> {
+    let mut magic = [0u8; 8];
+    try!(reader.read_exact(&mut magic[..]));
+    if &magic[..] != MAGIC {
+      return Err(ReadRecordingError::BadMagic);
+    }
+    let version = try!(reader.read_u32::<BigEndian>());
+    if version != 1 && version != VERSION {
+      return Err(ReadRecordingError::UnsupportedVersion { version: version });
+    }
+    Ok(RecordingReader {
+      reader: reader,
+    })
+  }
+
+  fn read_rest(&mut self, first: u8) -> Result<RecordedMessage, ReadRecordingError> {
+    let mut ts = [0u8; 7];
+    try!(self.reader.read_exact(&mut ts[..]));
+    let timestamp = ts.iter().fold(first as u64, |acc, &b| (acc << 8) | b as u64);
+    let connection = try!(self.reader.read_u32::<BigEndian>());
+    let d = try!(self.reader.read_u8());
+    let direction = match d & !TRUNCATED_FLAG {
+      0 => Direction::ToService,
+      1 => Direction::FromService,
+      _ => return Err(ReadRecordingError::InvalidDirection { direction: d }),
+    };
+    let len = try!(self.reader.read_u16::<BigEndian>());
+    if len < 4 {
+      return Err(ReadRecordingError::ShortMessage { len: len });
+    }
+    let mut message = vec![0u8; len as usize];
+    message[0] = (len >> 8) as u8;
+    message[1] = len as u8;
+    try!(self.reader.read_exact(&mut message[2..]));
+    Ok(RecordedMessage {
+      timestamp: timestamp,
+      connection: connection,
+      direction: direction,
+      message: message,
+      truncated: d & TRUNCATED_FLAG != 0,
+    })
+  }
+}
+
+impl RecordingReader<BufReader<File>> {
+  /// Open the recording at `path`.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<RecordingReader<BufReader<File>>, ReadRecordingError> {
+    let file = try!(File::open(path));
+    RecordingReader::new(BufReader::new(file))
+  }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+  type Item = Result<RecordedMessage, ReadRecordingError>;
+
+  fn next(&mut self) -> Option<Result<RecordedMessage, ReadRecordingError>> {
+    let mut first = [0u8; 1];
+    loop {
+      match self.reader.read(&mut first[..]) {
+        Ok(0)   => return None,
+        Ok(_)   => return Some(self.read_rest(first[0])),
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+        Err(e)  => return Some(Err(ReadRecordingError::Io { cause: e })),
+      }
+    }
+  }
+}
+
+/// Errors returned by `Replayer::serve` and `Replayer::serve_socket`.
+error_def! ReplayError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error talking to the client" ("Specifically: {}", cause),
+  Mismatch { index: usize, expected_type: u16, got_type: u16 }
+    => "The client sent a message which differs from the recording"
+       ("Recorded message {} has type {}, the client sent a message of type {}", index, expected_type, got_type),
+  ShortMessage { len: u16 }
+    => "The client sent a message which is too short" ("Length was {} bytes.", len),
+  Truncated { index: usize }
+    => "The recording does not hold the whole of a message the service sent"
+       ("Recorded message {} was truncated by the recording's filter", index),
+  Disconnected
+    => "The client disconnected before the end of the recording",
+}
+byteorder_error_chain! {ReplayError}
+
+/// Plays a recording back to a client, standing in for the service the recording was made with.
+///
+/// Messages the service sent are sent to the client in their recorded order, and each message the
+/// client sent is expected again and compared byte-for-byte. Timestamps are ignored so a replay
+/// goes as fast as the client allows and does not depend on timing.
+///
+/// Messages truncated by a `RecordFilter` can't be replayed faithfully. Only the recorded part
+/// of a truncated client message is compared, and a truncated service message ends the replay
+/// with an error.
+pub struct Replayer {
+  messages: Vec<RecordedMessage>,
+}
+
+impl Replayer {
+  /// Create a replayer from recorded messages.
+  pub fn new(messages: Vec<RecordedMessage>) -> Replayer {
+    Replayer {
+      messages: messages,
+    }
+  }
+
+  /// Load the recording at `path`.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Replayer, ReadRecordingError> {
+    let mut messages = Vec::new();
+    for rm in try!(RecordingReader::open(path)) {
+      messages.push(try!(rm));
+    }
+    Ok(Replayer::new(messages))
+  }
+
+  /// The recorded messages.
+  pub fn messages(&self) -> &[RecordedMessage] {
+    &self.messages[..]
+  }
+
+  /// The ids of the connections in the recording, in the order they were first used.
+  pub fn connections(&self) -> Vec<u32> {
+    let mut ret: Vec<u32> = Vec::new();
+    for rm in self.messages.iter() {
+      if !ret.contains(&rm.connection) {
+        ret.push(rm.connection);
+      }
+    }
+    ret
+  }
+
+  /// Replay the recorded connection `connection` to a client over `stream`.
+  pub fn serve<S: Read + Write>(&self, connection: u32, stream: &mut S) -> Result<(), ReplayError> {
+    for (index, rm) in self.messages.iter().enumerate() {
+      if rm.connection != connection {
+        continue;
+      }
+      match rm.direction {
+        Direction::FromService => {
+          if rm.truncated {
+            return Err(ReplayError::Truncated { index: index });
+          }
+          try!(stream.write_all(&rm.message[..]));
+        },
+        Direction::ToService   => {
+          let len = try!(stream.read_u16::<BigEndian>());
+          if len < 4 {
+            return Err(ReplayError::ShortMessage { len: len });
+          }
+          let mut got = vec![0u8; len as usize];
+          got[0] = (len >> 8) as u8;
+          got[1] = len as u8;
+          try!(stream.read_exact(&mut got[2..]));
+          let matches = match rm.truncated {
+            true  => got.len() >= rm.message.len() && got[2..rm.message.len()] == rm.message[2..],
+            false => got == rm.message,
+          };
+          if !matches {
+            return Err(ReplayError::Mismatch {
+              index: index,
+              expected_type: rm.message_type(),
+              got_type: ((got[2] as u16) << 8) | got[3] as u16,
+            });
+          }
+        },
+      }
+    }
+    Ok(())
+  }
+
+  /// Act as a mock service listening on the unix socket `path`.
+  ///
+  /// Accepts one client for each connection in the recording, in order, and replays that
+  /// connection to it. Point the `UNIXPATH` of the service in the client's config at `path` to
+  /// have the client talk to the replayer.
+  pub fn serve_socket<P: AsRef<Path>>(&self, path: P) -> Result<(), ReplayError> {
+    let listener = try!(UnixListener::bind(path));
+    for connection in self.connections() {
+      let (mut stream, _) = try!(listener.accept());
+      try!(self.serve(connection, &mut stream));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use std::io::{Read, Write};
+  use std::thread;
+  use unix_socket::UnixStream;
+  use Cfg;
+  use super::*;
+
+  #[test]
+  fn test_record_and_replay() {
+    let hello = vec![0, 8, 0, 42, 1, 2, 3, 4];
+    let reply = vec![0, 5, 0, 43, 9];
+
+    let mut path = env::temp_dir();
+    path.push(format!("gnunet-rs-test-{}.rec", ::rand::random::<u32>()));
+    {
+      let rec = Recorder::open(&path).unwrap();
+      rec.record(7, Direction::ToService, &hello[..]).unwrap();
+      rec.record(7, Direction::FromService, &reply[..]).unwrap();
+    }
+    let messages: Vec<RecordedMessage> = RecordingReader::open(&path).unwrap()
+                                                                     .map(|r| r.unwrap())
+                                                                     .collect();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].message, hello);
+    assert_eq!(messages[1].direction, Direction::FromService);
+    assert_eq!(messages[1].message_type(), 43);
+
+    let replayer = Replayer::new(messages);
+    assert_eq!(replayer.connections(), vec![7]);
+    let (mut client, mut server) = UnixStream::pair().unwrap();
+    let t = thread::spawn(move || replayer.serve(7, &mut server).is_ok());
+    client.write_all(&hello[..]).unwrap();
+    let mut got = vec![0u8; reply.len()];
+    client.read_exact(&mut got[..]).unwrap();
+    assert_eq!(got, reply);
+    assert!(t.join().unwrap());
+  }
+
+  #[test]
+  fn test_record_filter() {
+    let mut cfg = Cfg::empty();
+    assert_eq!(RecordFilter::from_cfg(&cfg, "gns"), RecordFilter::default());
+
+    cfg.set_string("record", "SERVICES", "gns, identity".to_string());
+    cfg.set_string("record", "TYPES", "500-599 636 x 9-3".to_string());
+    cfg.set_string("record", "MAX_PAYLOAD", "2".to_string());
+    cfg.set_string("identity", "RECORD_TYPES", "42".to_string());
+    let filter = RecordFilter::from_cfg(&cfg, "gns");
+    assert!(filter.matches_service("identity"));
+    assert!(!filter.matches_service("dht"));
+    assert_eq!(filter.types, vec![(500, 599), (636, 636)]);
+    assert!(filter.matches_type(550) && !filter.matches_type(600));
+    assert_eq!(RecordFilter::from_cfg(&cfg, "identity").types, vec![(42, 42)]);
+
+    let mut path = env::temp_dir();
+    path.push(format!("gnunet-rs-test-{}.rec", ::rand::random::<u32>()));
+    {
+      let rec = Recorder::open(&path).unwrap();
+      rec.record_filtered(&filter, 1, Direction::ToService, &[0, 8, 2, 38, 1, 2, 3, 4]).unwrap();
+      rec.record_filtered(&filter, 1, Direction::ToService, &[0, 5, 0, 42, 1]).unwrap();
+      rec.record_filtered(&filter, 1, Direction::FromService, &[0, 6, 2, 124, 1, 2]).unwrap();
+    }
+    let messages: Vec<RecordedMessage> = RecordingReader::open(&path).unwrap()
+                                                                     .map(|r| r.unwrap())
+                                                                     .collect();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].message, vec![0, 6, 2, 38, 1, 2]);
+    assert!(messages[0].truncated);
+    assert_eq!(messages[1].message, vec![0, 6, 2, 124, 1, 2]);
+    assert!(!messages[1].truncated);
+
+    // Only the recorded prefix of a truncated client message is compared.
+    let replayer = Replayer::new(messages);
+    let (mut client, mut server) = UnixStream::pair().unwrap();
+    let t = thread::spawn(move || replayer.serve(1, &mut server).is_ok());
+    client.write_all(&[0, 8, 2, 38, 1, 2, 9, 9]).unwrap();
+    let mut got = vec![0u8; 6];
+    client.read_exact(&mut got[..]).unwrap();
+    assert_eq!(got, vec![0, 6, 2, 124, 1, 2]);
+    assert!(t.join().unwrap());
+  }
+}
@@ -0,0 +1,148 @@
+use std::thread;
+use std::time::Duration;
+
+use service::{ConnectError, ReadMessageError};
+
+/// Errors which may go away if the operation that caused them is tried again.
+///
+/// Losing the connection to a service, or failing to connect because its socket has not been
+/// created yet, is what happens while a daemon is being restarted. Errors like these are
+/// transient. Errors caused by bad arguments or a malformed response are not.
+pub trait Transient {
+  /// Returns `true` if retrying the operation may succeed.
+  fn is_transient(&self) -> bool;
+}
+
+impl Transient for ConnectError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      ConnectError::Io { .. } => true,
+      _                       => false,
+    }
+  }
+}
+
+impl Transient for ReadMessageError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      ReadMessageError::Io { .. }     => true,
+      ReadMessageError::Disconnected  => true,
+      _                               => false,
+    }
+  }
+}
+
+/// How often, and how quickly, to retry an operation which failed.
+///
+/// The first retry happens after `initial_delay`. Each retry after that waits `backoff_factor`
+/// times longer than the last, up to `max_delay`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+  /// The maximum number of times to try the operation, including the first try.
+  pub attempts: u32,
+  /// How long to wait before the first retry.
+  pub initial_delay: Duration,
+  /// The longest to wait between two tries.
+  pub max_delay: Duration,
+  /// How much longer to wait before each retry than before the last one.
+  pub backoff_factor: u32,
+}
+
+impl Default for RetryPolicy {
+  /// Five tries, waiting 100ms, 200ms, 400ms then 800ms in between.
+  fn default() -> RetryPolicy {
+    RetryPolicy {
+      attempts: 5,
+      initial_delay: Duration::from_millis(100),
+      max_delay: Duration::from_secs(5),
+      backoff_factor: 2,
+    }
+  }
+}
+
+impl RetryPolicy {
+  /// A policy which tries once and never retries.
+  pub fn never() -> RetryPolicy {
+    RetryPolicy {
+      attempts: 1,
+      ..RetryPolicy::default()
+    }
+  }
+
+  /// How long to wait before retry number `retry`, counting from 1.
+  pub fn delay_before(&self, retry: u32) -> Duration {
+    let mut delay = self.initial_delay;
+    for _ in 1..retry {
+      delay = delay * self.backoff_factor;
+      if delay >= self.max_delay {
+        return self.max_delay;
+      }
+    }
+    if delay > self.max_delay {
+      self.max_delay
+    }
+    else {
+      delay
+    }
+  }
+
+  /// Call `f` until it succeeds, fails with an error which isn't transient, or the policy runs out
+  /// of attempts. Returns the result of the last call.
+  pub fn run<T, E, F>(&self, f: F) -> Result<T, E>
+      where F: FnMut() -> Result<T, E>,
+            E: Transient
+  {
+    self.run_if(f, |e| e.is_transient())
+  }
+
+  /// Like `run` but uses `retryable` to decide which errors are worth retrying.
+  pub fn run_if<T, E, F, P>(&self, mut f: F, retryable: P) -> Result<T, E>
+      where F: FnMut() -> Result<T, E>,
+            P: Fn(&E) -> bool
+  {
+    let mut attempt = 1;
+    loop {
+      match f() {
+        Ok(x)   => return Ok(x),
+        Err(e)  => {
+          if attempt >= self.attempts || !retryable(&e) {
+            return Err(e);
+          }
+        },
+      };
+      thread::sleep(self.delay_before(attempt));
+      attempt += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use super::*;
+
+  #[test]
+  fn test_retry_policy() {
+    let policy = RetryPolicy {
+      attempts: 4,
+      initial_delay: Duration::from_millis(1),
+      max_delay: Duration::from_millis(3),
+      backoff_factor: 2,
+    };
+    assert_eq!(policy.delay_before(1), Duration::from_millis(1));
+    assert_eq!(policy.delay_before(2), Duration::from_millis(2));
+    assert_eq!(policy.delay_before(3), Duration::from_millis(3));
+
+    let mut calls = 0;
+    let res: Result<(), u32> = policy.run_if(|| { calls += 1; Err(calls) }, |_| true);
+    assert_eq!(res, Err(4));
+
+    let mut calls = 0;
+    let res: Result<u32, u32> = policy.run_if(|| { calls += 1; if calls < 3 { Err(calls) } else { Ok(calls) } }, |_| true);
+    assert_eq!(res, Ok(3));
+
+    let mut calls = 0;
+    let res: Result<(), u32> = policy.run_if(|| { calls += 1; Err(calls) }, |&e| e != 2);
+    assert_eq!(res, Err(2));
+  }
+}
@@ -0,0 +1,162 @@
+use std::io::{self, Cursor, Read, Write};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+use service::{MessageDecoder, MessageEncoder, ReadMessageError};
+
+/// The number of bytes a `MessageSink` holds before it stops taking more messages.
+const SINK_HIGH_WATER_MARK: usize = 0x10000;
+
+/// The messages received from a service over a non-blocking socket, as a `Stream`.
+///
+/// `io` is read from whenever the stream is polled, and a read failing with `WouldBlock` makes
+/// the poll return `NotReady`. Sockets registered with an event loop in the usual way, eg. a
+/// `tokio_core` `PollEvented`, arrange for the task to be woken when there is more to read.
+/// The stream ends when the service closes the connection between messages.
+pub struct MessageStream<T> {
+  io: T,
+  decoder: MessageDecoder,
+}
+
+impl<T: Read> MessageStream<T> {
+  /// Read messages from `io`.
+  pub fn new(io: T) -> MessageStream<T> {
+    MessageStream {
+      io: io,
+      decoder: MessageDecoder::new(),
+    }
+  }
+
+  /// Take back the socket. Any part of a message read but not yet returned is lost.
+  pub fn into_inner(self) -> T {
+    self.io
+  }
+}
+
+impl<T: Read> Stream for MessageStream<T> {
+  type Item = (u16, Cursor<Vec<u8>>);
+  type Error = ReadMessageError;
+
+  fn poll(&mut self) -> Poll<Option<(u16, Cursor<Vec<u8>>)>, ReadMessageError> {
+    let mut buf = [0u8; 4096];
+    loop {
+      if let Some(msg) = try!(self.decoder.next_message()) {
+        return Ok(Async::Ready(Some(msg)));
+      }
+      match self.io.read(&mut buf[..]) {
+        Ok(0) => return match self.decoder.buffered() {
+          0 => Ok(Async::Ready(None)),
+          _ => Err(ReadMessageError::Disconnected),
+        },
+        Ok(n)                                                 => self.decoder.feed(&buf[..n]),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock   => return Ok(Async::NotReady),
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted  => (),
+        Err(e)                                                => return Err(From::from(e)),
+      };
+    }
+  }
+}
+
+/// Sends messages to a service over a non-blocking socket, as a `Sink` of message types and
+/// bodies.
+///
+/// Messages are written to `io` as it takes them, and a write failing with `WouldBlock` leaves the
+/// rest for the next poll. Sending a message too long to encode fails with `InvalidInput`.
+pub struct MessageSink<T> {
+  io: T,
+  encoder: MessageEncoder,
+}
+
+impl<T: Write> MessageSink<T> {
+  /// Write messages to `io`.
+  pub fn new(io: T) -> MessageSink<T> {
+    MessageSink {
+      io: io,
+      encoder: MessageEncoder::new(),
+    }
+  }
+
+  /// Take back the socket. Any messages not yet written are lost.
+  pub fn into_inner(self) -> T {
+    self.io
+  }
+}
+
+impl<T: Write> Sink for MessageSink<T> {
+  type SinkItem = (u16, Vec<u8>);
+  type SinkError = io::Error;
+
+  fn start_send(&mut self, item: (u16, Vec<u8>)) -> StartSend<(u16, Vec<u8>), io::Error> {
+    if self.encoder.pending().len() >= SINK_HIGH_WATER_MARK {
+      try!(self.poll_complete());
+      if self.encoder.pending().len() >= SINK_HIGH_WATER_MARK {
+        return Ok(AsyncSink::NotReady(item));
+      }
+    }
+    match self.encoder.queue(item.0, &item.1[..]) {
+      true  => Ok(AsyncSink::Ready),
+      false => Err(io::Error::new(io::ErrorKind::InvalidInput, "The message is too long to send")),
+    }
+  }
+
+  fn poll_complete(&mut self) -> Poll<(), io::Error> {
+    while !self.encoder.pending().is_empty() {
+      match self.io.write(self.encoder.pending()) {
+        Ok(0)                                                 => return Err(io::Error::new(io::ErrorKind::WriteZero, "The service stopped taking messages")),
+        Ok(n)                                                 => self.encoder.consume(n),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock   => return Ok(Async::NotReady),
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted  => (),
+        Err(e)                                                => return Err(e),
+      };
+    }
+    Ok(Async::Ready(()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+  use futures::{Async, AsyncSink, Sink, Stream};
+  use unix_socket::UnixStream;
+  use service::encode_message;
+  use super::*;
+
+  #[test]
+  fn test_message_stream() {
+    let (ours, mut theirs) = UnixStream::pair().unwrap();
+    ours.set_nonblocking(true).unwrap();
+    let mut stream = MessageStream::new(ours);
+    match stream.poll() {
+      Ok(Async::NotReady) => (),
+      _ => panic!("expected nothing to be ready"),
+    }
+
+    let msg = encode_message(7, b"abc").unwrap();
+    theirs.write_all(&msg[..3]).unwrap();
+    match stream.poll() {
+      Ok(Async::NotReady) => (),
+      _ => panic!("expected half a message not to be ready"),
+    }
+    theirs.write_all(&msg[3..]).unwrap();
+    match stream.poll() {
+      Ok(Async::Ready(Some((7, _)))) => (),
+      _ => panic!("expected the message"),
+    }
+    drop(theirs);
+    match stream.poll() {
+      Ok(Async::Ready(None)) => (),
+      _ => panic!("expected the stream to end"),
+    }
+  }
+
+  #[test]
+  fn test_message_sink() {
+    let mut sink = MessageSink::new(Vec::new());
+    match sink.start_send((7, b"abc".to_vec())).unwrap() {
+      AsyncSink::Ready => (),
+      AsyncSink::NotReady(_) => panic!("expected the message to be taken"),
+    }
+    assert!(sink.start_send((8, vec![0u8; 0x10000])).is_err());
+    assert_eq!(sink.poll_complete().unwrap(), Async::Ready(()));
+    assert_eq!(sink.into_inner(), encode_message(7, b"abc").unwrap());
+  }
+}
@@ -0,0 +1,428 @@
+//! Module for GNUnet's set reconciliation services. These compute the union or intersection of a
+//! local set with a set held by a remote peer, without either peer sending its whole set.
+//!
+//! One peer listens for operations with `listen` and accepts them with `Set::accept`, the other
+//! starts them with `Set::evaluate`. Both then receive the result as a stream of `SetEvent`s.
+//!
+//! Older versions of GNUnet provide both operations through the `set` service. Newer versions
+//! split them into the `setu` (union) and `seti` (intersection) services. `Backend::from_cfg`
+//! picks whichever the peer's config describes.
+
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use HashCode;
+use PeerIdentity;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+
+/// The operations supported by the set services.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OperationType {
+  /// Compute the elements both peers have.
+  Intersection = 1,
+  /// Compute the elements either peer has.
+  Union        = 2,
+}
+
+/// Which of GNUnet's set services to talk to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+  /// The `set` service, which provides both operations.
+  Combined,
+  /// The `setu` and `seti` services, one for each operation.
+  Split,
+}
+
+/// The message types used to talk to a set service.
+#[derive(Copy, Clone)]
+struct MessageTypes {
+  create: u16,
+  add: u16,
+  evaluate: u16,
+  listen: u16,
+  request: u16,
+  accept: u16,
+  reject: u16,
+  result: u16,
+  cancel: u16,
+}
+
+impl Backend {
+  /// Use the split services if the config describes them, otherwise the combined `set` service.
+  pub fn from_cfg(cfg: &Cfg) -> Backend {
+    match cfg.get_filename("setu", "UNIXPATH").is_ok() && cfg.get_filename("seti", "UNIXPATH").is_ok() {
+      true  => Backend::Split,
+      false => Backend::Combined,
+    }
+  }
+
+  /// The name of the service which performs operations of type `op`.
+  fn service_name(&self, op: OperationType) -> &'static str {
+    match (*self, op) {
+      (Backend::Combined, _)                        => "set",
+      (Backend::Split, OperationType::Union)        => "setu",
+      (Backend::Split, OperationType::Intersection) => "seti",
+    }
+  }
+
+  fn message_types(&self, op: OperationType) -> MessageTypes {
+    match (*self, op) {
+      (Backend::Combined, _) => MessageTypes {
+        create:   ll::GNUNET_MESSAGE_TYPE_SET_CREATE,
+        add:      ll::GNUNET_MESSAGE_TYPE_SET_ADD,
+        evaluate: ll::GNUNET_MESSAGE_TYPE_SET_EVALUATE,
+        listen:   ll::GNUNET_MESSAGE_TYPE_SET_LISTEN,
+        request:  ll::GNUNET_MESSAGE_TYPE_SET_REQUEST,
+        accept:   ll::GNUNET_MESSAGE_TYPE_SET_ACCEPT,
+        reject:   ll::GNUNET_MESSAGE_TYPE_SET_REJECT,
+        result:   ll::GNUNET_MESSAGE_TYPE_SET_RESULT,
+        cancel:   ll::GNUNET_MESSAGE_TYPE_SET_CANCEL,
+      },
+      (Backend::Split, OperationType::Union) => MessageTypes {
+        create:   ll::GNUNET_MESSAGE_TYPE_SETU_CREATE,
+        add:      ll::GNUNET_MESSAGE_TYPE_SETU_ADD,
+        evaluate: ll::GNUNET_MESSAGE_TYPE_SETU_EVALUATE,
+        listen:   ll::GNUNET_MESSAGE_TYPE_SETU_LISTEN,
+        request:  ll::GNUNET_MESSAGE_TYPE_SETU_REQUEST,
+        accept:   ll::GNUNET_MESSAGE_TYPE_SETU_ACCEPT,
+        reject:   ll::GNUNET_MESSAGE_TYPE_SETU_REJECT,
+        result:   ll::GNUNET_MESSAGE_TYPE_SETU_RESULT,
+        cancel:   ll::GNUNET_MESSAGE_TYPE_SETU_CANCEL,
+      },
+      (Backend::Split, OperationType::Intersection) => MessageTypes {
+        create:   ll::GNUNET_MESSAGE_TYPE_SETI_CREATE,
+        add:      ll::GNUNET_MESSAGE_TYPE_SETI_ADD,
+        evaluate: ll::GNUNET_MESSAGE_TYPE_SETI_EVALUATE,
+        listen:   ll::GNUNET_MESSAGE_TYPE_SETI_LISTEN,
+        request:  ll::GNUNET_MESSAGE_TYPE_SETI_REQUEST,
+        accept:   ll::GNUNET_MESSAGE_TYPE_SETI_ACCEPT,
+        reject:   ll::GNUNET_MESSAGE_TYPE_SETI_REJECT,
+        result:   ll::GNUNET_MESSAGE_TYPE_SETI_RESULT,
+        cancel:   ll::GNUNET_MESSAGE_TYPE_SETI_CANCEL,
+      },
+    }
+  }
+}
+
+/// Which elements the result of an operation consists of.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResultMode {
+  /// Every element of the resulting set.
+  Full      = 0,
+  /// The elements each peer is missing, as `SetEvent::AddLocal` and `SetEvent::AddRemote`.
+  Symmetric = 1,
+  /// The elements removed from the local set by the operation.
+  Removed   = 2,
+  /// The elements added to the local set by the operation.
+  Added     = 3,
+}
+
+/// An element of a set. The element type is chosen by the application.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Element {
+  pub element_type: u16,
+  pub data: Vec<u8>,
+}
+
+/// The result of a set operation, as a stream of events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetEvent {
+  /// An element of the result.
+  Element(Element),
+  /// An element the remote peer has but the local set lacked.
+  AddLocal(Element),
+  /// An element the local set has but the remote peer lacked.
+  AddRemote(Element),
+  /// The operation is complete. No more events follow.
+  Done,
+}
+
+/// The status field of a result message.
+const STATUS_OK: u16 = 0;
+const STATUS_ADD_LOCAL: u16 = 1;
+const STATUS_ADD_REMOTE: u16 = 2;
+const STATUS_FAILURE: u16 = 3;
+const STATUS_HALF_DONE: u16 = 4;
+const STATUS_DONE: u16 = 5;
+
+/// Errors returned by the functions in this module.
+error_def! SetError {
+  TooLong
+    => "The element or context message was too large to send to the service",
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the set service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the set service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the set service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The set service sent an unexpected message type" ("Message type {} was not expected", ty),
+  InvalidResponse
+    => "The response from the set service was incoherent",
+  Failed
+    => "The set operation failed",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {SetError}
+
+/// A set held by the local set service.
+///
+/// Each `Set` has its own connection to the service. Performing an operation on the set with
+/// `evaluate` or `accept` hands the connection over to the operation.
+pub struct Set {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  op: OperationType,
+  types: MessageTypes,
+}
+
+impl Set {
+  /// Create an empty set to be used for operations of type `op`, using the service described by
+  /// `cfg`.
+  pub fn create(cfg: &Cfg, op: OperationType) -> Result<Set, SetError> {
+    Set::create_with_backend(cfg, op, Backend::from_cfg(cfg))
+  }
+
+  /// Create an empty set to be used for operations of type `op` with the given backend.
+  pub fn create_with_backend(cfg: &Cfg, op: OperationType, backend: Backend) -> Result<Set, SetError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, backend.service_name(op)));
+    let types = backend.message_types(op);
+    match backend {
+      Backend::Combined => {
+        let mut mw = service_writer.write_message(8, types.create);
+        mw.write_u32::<BigEndian>(op as u32).unwrap();
+        try!(mw.send());
+      },
+      // The split services only perform one kind of operation.
+      Backend::Split => try!(service_writer.write_message(4, types.create).send()),
+    };
+    Ok(Set {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      op: op,
+      types: types,
+    })
+  }
+
+  /// The type of operation this set was created for.
+  pub fn operation_type(&self) -> OperationType {
+    self.op
+  }
+
+  /// Add `element` to the set.
+  pub fn add_element(&mut self, element: &Element) -> Result<(), SetError> {
+    let msg_length = 8 + element.data.len();
+    if msg_length > 0xffff {
+      return Err(SetError::TooLong);
+    }
+    let mut mw = self.service_writer.write_message(msg_length as u16, self.types.add);
+    mw.write_u16::<BigEndian>(element.element_type).unwrap();
+    mw.write_u16::<BigEndian>(0).unwrap();
+    mw.write_all(&element.data[..]).unwrap();
+    Ok(try!(mw.send()))
+  }
+
+  /// Start an operation between this set and a set of the peer `peer`, which must be listening
+  /// for operations with `app_id`. `context` is given to the remote peer along with the request.
+  pub fn evaluate(mut self, peer: &PeerIdentity, app_id: &HashCode, context: &[u8], mode: ResultMode) -> Result<SetOperation, SetError> {
+    let msg_length = 4 + 4 + 32 + 64 + 4 + 4 + context.len();
+    if msg_length > 0xffff {
+      return Err(SetError::TooLong);
+    }
+    {
+      let mut mw = self.service_writer.write_message(msg_length as u16, self.types.evaluate);
+      mw.write_u32::<BigEndian>(mode as u32).unwrap();
+      peer.serialize(&mut mw).unwrap();
+      app_id.serialize(&mut mw).unwrap();
+      mw.write_u32::<BigEndian>(OPERATION_REQUEST_ID).unwrap();
+      mw.write_u32::<BigEndian>(0).unwrap(); // option flags
+      mw.write_all(context).unwrap();
+      try!(mw.send());
+    }
+    Ok(SetOperation::new(self))
+  }
+
+  /// Accept an operation requested by a remote peer, performing it on this set.
+  pub fn accept(mut self, request: OperationRequest, mode: ResultMode) -> Result<SetOperation, SetError> {
+    {
+      let mut mw = self.service_writer.write_message(20, self.types.accept);
+      mw.write_u32::<BigEndian>(request.accept_id).unwrap();
+      mw.write_u32::<BigEndian>(OPERATION_REQUEST_ID).unwrap();
+      mw.write_u32::<BigEndian>(mode as u32).unwrap();
+      mw.write_u32::<BigEndian>(0).unwrap(); // option flags
+      try!(mw.send());
+    }
+    Ok(SetOperation::new(self))
+  }
+}
+
+/// The id of the operation on a set's connection. Each connection only carries one operation.
+const OPERATION_REQUEST_ID: u32 = 1;
+
+/// A request from a remote peer to perform an operation, received by a `Listener`.
+pub struct OperationRequest {
+  /// The peer requesting the operation.
+  pub peer: PeerIdentity,
+  /// The context message given by the peer to `Set::evaluate`.
+  pub context: Vec<u8>,
+  accept_id: u32,
+}
+
+/// Receives requests from remote peers to perform operations. Created with `listen`.
+pub struct Listener {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  types: MessageTypes,
+}
+
+/// Listen for requests from remote peers to perform operations of type `op` with `app_id`, using
+/// the service described by `cfg`.
+pub fn listen(cfg: &Cfg, op: OperationType, app_id: &HashCode) -> Result<Listener, SetError> {
+  listen_with_backend(cfg, op, app_id, Backend::from_cfg(cfg))
+}
+
+/// Like `listen` but with the given backend.
+pub fn listen_with_backend(cfg: &Cfg, op: OperationType, app_id: &HashCode, backend: Backend) -> Result<Listener, SetError> {
+  let (service_reader, mut service_writer) = try!(service::connect(cfg, backend.service_name(op)));
+  let types = backend.message_types(op);
+  {
+    let mut mw = service_writer.write_message(72, types.listen);
+    mw.write_u32::<BigEndian>(op as u32).unwrap();
+    app_id.serialize(&mut mw).unwrap();
+    try!(mw.send());
+  }
+  Ok(Listener {
+    service_reader: service_reader,
+    service_writer: service_writer,
+    types: types,
+  })
+}
+
+impl Listener {
+  /// Wait for the next request. The request should be passed to `Set::accept` or `reject`.
+  pub fn next_request(&mut self) -> Result<OperationRequest, SetError> {
+    let (tpe, mut mr) = try!(self.service_reader.read_message());
+    if tpe != self.types.request {
+      return Err(SetError::UnexpectedMessageType { ty: tpe });
+    }
+    let accept_id = try!(mr.read_u32::<BigEndian>());
+    let peer = try!(PeerIdentity::deserialize(&mut mr));
+    let mut context = Vec::new();
+    try!(mr.read_to_end(&mut context));
+    Ok(OperationRequest {
+      peer: peer,
+      context: context,
+      accept_id: accept_id,
+    })
+  }
+
+  /// Refuse a request.
+  pub fn reject(&mut self, request: OperationRequest) -> Result<(), SetError> {
+    let mut mw = self.service_writer.write_message(8, self.types.reject);
+    mw.write_u32::<BigEndian>(request.accept_id).unwrap();
+    Ok(try!(mw.send()))
+  }
+}
+
+/// An operation in progress, created by `Set::evaluate` or `Set::accept`. Its results are read
+/// with `next_event`, or by iterating over it.
+pub struct SetOperation {
+  set: Set,
+  done: bool,
+}
+
+impl SetOperation {
+  fn new(set: Set) -> SetOperation {
+    SetOperation {
+      set: set,
+      done: false,
+    }
+  }
+
+  /// Wait for the next event of the operation. After `SetEvent::Done` or an error no more events
+  /// are received.
+  pub fn next_event(&mut self) -> Result<SetEvent, SetError> {
+    if self.done {
+      return Ok(SetEvent::Done);
+    }
+    let res = self.read_event();
+    match res {
+      Ok(SetEvent::Done) | Err(_) => self.done = true,
+      _                           => (),
+    };
+    res
+  }
+
+  fn read_event(&mut self) -> Result<SetEvent, SetError> {
+    loop {
+      let (tpe, mut mr) = try!(self.set.service_reader.read_message());
+      if tpe != self.set.types.result {
+        return Err(SetError::UnexpectedMessageType { ty: tpe });
+      }
+      let _current_size = try!(mr.read_u64::<BigEndian>());
+      if try!(mr.read_u32::<BigEndian>()) != OPERATION_REQUEST_ID {
+        return Err(SetError::InvalidResponse);
+      }
+      let status = try!(mr.read_u16::<BigEndian>());
+      let element_type = try!(mr.read_u16::<BigEndian>());
+      let mut data = Vec::new();
+      try!(mr.read_to_end(&mut data));
+      let element = Element {
+        element_type: element_type,
+        data: data,
+      };
+      return match status {
+        STATUS_OK         => Ok(SetEvent::Element(element)),
+        STATUS_ADD_LOCAL  => Ok(SetEvent::AddLocal(element)),
+        STATUS_ADD_REMOTE => Ok(SetEvent::AddRemote(element)),
+        STATUS_FAILURE    => Err(SetError::Failed),
+        STATUS_HALF_DONE  => continue,
+        STATUS_DONE       => Ok(SetEvent::Done),
+        _                 => Err(SetError::InvalidResponse),
+      };
+    }
+  }
+
+  /// Stop the operation before it completes.
+  pub fn cancel(mut self) -> Result<(), SetError> {
+    let mut mw = self.set.service_writer.write_message(8, self.set.types.cancel);
+    mw.write_u32::<BigEndian>(OPERATION_REQUEST_ID).unwrap();
+    Ok(try!(mw.send()))
+  }
+}
+
+impl Iterator for SetOperation {
+  type Item = Result<SetEvent, SetError>;
+
+  /// Yields the events of the operation up to, but not including, `SetEvent::Done`.
+  fn next(&mut self) -> Option<Result<SetEvent, SetError>> {
+    if self.done {
+      return None;
+    }
+    match self.next_event() {
+      Ok(SetEvent::Done)  => None,
+      res                 => Some(res),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Backend, OperationType};
+
+  #[test]
+  fn test_backend_services() {
+    assert_eq!(Backend::Combined.service_name(OperationType::Union), "set");
+    assert_eq!(Backend::Combined.service_name(OperationType::Intersection), "set");
+    assert_eq!(Backend::Split.service_name(OperationType::Union), "setu");
+    assert_eq!(Backend::Split.service_name(OperationType::Intersection), "seti");
+
+    let union = Backend::Split.message_types(OperationType::Union);
+    let intersection = Backend::Split.message_types(OperationType::Intersection);
+    assert!(union.result != intersection.result);
+    assert!(union.add != intersection.add);
+  }
+}
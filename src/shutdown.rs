@@ -0,0 +1,207 @@
+//! Shutting down several service handles together.
+//!
+//! Dropping a handle closes its connection and waits for the thread reading from it without a
+//! time limit, and handles which depend on each other have to be dropped in the right order by
+//! hand. A
+//! `ShutdownGroup` takes the handles an application uses and shuts them all down in steps, with a
+//! time limit, reporting any which didn't stop cleanly.
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use gnunet::{Cfg, DHT, GNS, IdentityService};
+//! use gnunet::shutdown::ShutdownGroup;
+//!
+//! let config = Cfg::default().unwrap();
+//! let identity = IdentityService::connect(&config).unwrap();
+//! let gns = GNS::connect(&config).unwrap();
+//! let dht = DHT::connect(&config).unwrap();
+//!
+//! let mut group = ShutdownGroup::new();
+//! group.join("identity", identity);
+//! group.join("gns", gns);
+//! group.join("dht", dht);
+//! let report = group.shutdown(Duration::from_secs(2));
+//! for &(ref name, ref error) in report.failures.iter() {
+//!   println!("{} did not stop cleanly: {}", name, error);
+//! }
+//! ```
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use service::StopLoopError;
+
+/// Errors encountered while shutting down a `Component`.
+error_def! ShutdownError {
+  Flush { cause: io::Error }
+    => "Failed to send the messages waiting to be sent" ("Specifically: {}", cause),
+  StopLoop { #[from] cause: StopLoopError }
+    => "The thread reading from the service did not stop" ("Reason: {}", cause),
+}
+
+/// A thread reading from one or more service connections.
+pub trait ReadLoop {
+  /// Close the connections in both directions and wait up to `timeout` for the thread to finish.
+  fn close(&mut self, timeout: Duration) -> Result<(), StopLoopError>;
+}
+
+/// A handle which can be shut down by a `ShutdownGroup`.
+///
+/// Shutting down happens in three steps, each of which is taken by every component in the group
+/// before any component moves on to the next. Handles built around a read loop only need to
+/// provide `read_loop`.
+pub trait Component {
+  /// Stop taking on new work, eg. accepting incoming channels.
+  fn stop_accepting(&mut self) {
+  }
+
+  /// Make sure the messages given to the component have been sent. Messages are written to the
+  /// connection as they are sent, so by default there is nothing to do.
+  fn flush(&mut self) -> Result<(), io::Error> {
+    Ok(())
+  }
+
+  /// The thread reading the component's connections, if it has one.
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    None
+  }
+
+  /// Close the component's connections and wait up to `timeout` for its threads to finish. The
+  /// component is unusable afterwards. By default this closes the `read_loop`.
+  fn close(&mut self, timeout: Duration) -> Result<(), ShutdownError> {
+    match self.read_loop() {
+      Some(read_loop) => Ok(try!(read_loop.close(timeout))),
+      None            => Ok(()),
+    }
+  }
+}
+
+/// The outcome of `ShutdownGroup::shutdown`.
+pub struct ShutdownReport {
+  /// The components which failed a step, by the name they joined the group with, with the
+  /// reason. A component may appear more than once.
+  pub failures: Vec<(String, ShutdownError)>,
+}
+
+impl ShutdownReport {
+  /// Whether every component stopped cleanly.
+  pub fn is_clean(&self) -> bool {
+    self.failures.is_empty()
+  }
+}
+
+/// Components to be shut down together, in order. Created with `ShutdownGroup::new`.
+///
+/// Components are shut down in the reverse of the order they joined the group in, so a component
+/// should join after those it depends on. If the group is dropped without `shutdown` being
+/// called, the components are just dropped.
+pub struct ShutdownGroup {
+  members: Vec<(String, Box<Component>)>,
+}
+
+impl ShutdownGroup {
+  /// Create an empty group.
+  pub fn new() -> ShutdownGroup {
+    ShutdownGroup {
+      members: Vec::new(),
+    }
+  }
+
+  /// Add `component` to the group. `name` identifies it in the `ShutdownReport`.
+  pub fn join<C>(&mut self, name: &str, component: C)
+      where C: Component + 'static
+  {
+    self.members.push((name.to_string(), Box::new(component)));
+  }
+
+  /// The number of components in the group.
+  pub fn len(&self) -> usize {
+    self.members.len()
+  }
+
+  /// Shut down every component, taking at most about `timeout` altogether. Each component is
+  /// closed even if an earlier step failed for it or the time is up, but once the time is up
+  /// their threads are no longer waited for.
+  pub fn shutdown(mut self, timeout: Duration) -> ShutdownReport {
+    let deadline = Instant::now() + timeout;
+    let mut failures = Vec::new();
+    self.members.reverse();
+    for &mut (_, ref mut component) in self.members.iter_mut() {
+      component.stop_accepting();
+    }
+    for &mut (ref name, ref mut component) in self.members.iter_mut() {
+      if let Err(e) = component.flush() {
+        failures.push((name.clone(), ShutdownError::Flush { cause: e }));
+      }
+    }
+    for &mut (ref name, ref mut component) in self.members.iter_mut() {
+      let now = Instant::now();
+      let left = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+      if let Err(e) = component.close(left) {
+        failures.push((name.clone(), e));
+      }
+    }
+    ShutdownReport {
+      failures: failures,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io;
+  use std::sync::{Arc, Mutex};
+  use std::time::Duration;
+  use service::StopLoopError;
+  use super::*;
+
+  struct Fake {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+    fail_flush: bool,
+    fail_close: bool,
+  }
+
+  impl Component for Fake {
+    fn stop_accepting(&mut self) {
+      self.log.lock().unwrap().push(format!("stop {}", self.name));
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+      self.log.lock().unwrap().push(format!("flush {}", self.name));
+      match self.fail_flush {
+        true  => Err(io::Error::new(io::ErrorKind::BrokenPipe, "gone")),
+        false => Ok(()),
+      }
+    }
+
+    fn close(&mut self, _timeout: Duration) -> Result<(), ShutdownError> {
+      self.log.lock().unwrap().push(format!("close {}", self.name));
+      match self.fail_close {
+        true  => Err(ShutdownError::StopLoop { cause: StopLoopError::TimedOut }),
+        false => Ok(()),
+      }
+    }
+  }
+
+  #[test]
+  fn test_shutdown_group() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut group = ShutdownGroup::new();
+    group.join("identity", Fake { name: "identity", log: log.clone(), fail_flush: false, fail_close: true });
+    group.join("gns", Fake { name: "gns", log: log.clone(), fail_flush: true, fail_close: false });
+    assert_eq!(group.len(), 2);
+    let report = group.shutdown(Duration::from_secs(1));
+
+    let expected: Vec<String> = ["stop gns", "stop identity", "flush gns", "flush identity", "close gns", "close identity"]
+                                  .iter().map(|s| s.to_string()).collect();
+    assert_eq!(*log.lock().unwrap(), expected);
+    assert!(!report.is_clean());
+    let failed: Vec<&str> = report.failures.iter().map(|&(ref name, _)| &name[..]).collect();
+    assert_eq!(failed, vec!["gns", "identity"]);
+    match report.failures[0].1 {
+      ShutdownError::Flush { .. } => (),
+      _ => panic!("expected the flush to fail"),
+    }
+  }
+}
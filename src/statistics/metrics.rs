@@ -0,0 +1,78 @@
+use statistics::{Statistics, GetError};
+
+/// The name of the value counting the bytes received by the transport service.
+pub const TRANSPORT_BYTES_RECEIVED: &'static str = "# bytes total received";
+/// The name of the value counting the bytes sent by the transport service.
+pub const TRANSPORT_BYTES_SENT: &'static str = "# bytes transmitted";
+/// The name of the value counting the PUT requests made by clients of the DHT service.
+pub const DHT_PUTS: &'static str = "# PUT requests received from clients";
+/// The name of the value counting the GET requests made by clients of the DHT service.
+pub const DHT_GETS: &'static str = "# GET requests received from clients";
+/// The name of the value counting the peers the core service is connected to.
+pub const CORE_CONNECTED_PEERS: &'static str = "# peers connected";
+
+/// A snapshot of commonly monitored values, read with `Statistics::peer_metrics`.
+///
+/// Each field is `None` if the service hasn't set the value, eg. because it isn't running or
+/// hasn't done anything yet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerMetrics {
+  /// Bytes received by the transport service.
+  pub transport_bytes_received: Option<u64>,
+  /// Bytes sent by the transport service.
+  pub transport_bytes_sent: Option<u64>,
+  /// PUT requests made by clients of the DHT service.
+  pub dht_puts: Option<u64>,
+  /// GET requests made by clients of the DHT service.
+  pub dht_gets: Option<u64>,
+  /// Peers the core service is connected to.
+  pub core_connected_peers: Option<u64>,
+}
+
+fn find(values: &[(String, u64, bool)], name: &str) -> Option<u64> {
+  values.iter()
+        .find(|&&(ref n, _, _)| n == name)
+        .map(|&(_, value, _)| value)
+}
+
+impl PeerMetrics {
+  fn from_values(transport: &[(String, u64, bool)],
+                 dht: &[(String, u64, bool)],
+                 core: &[(String, u64, bool)]) -> PeerMetrics {
+    PeerMetrics {
+      transport_bytes_received: find(transport, TRANSPORT_BYTES_RECEIVED),
+      transport_bytes_sent: find(transport, TRANSPORT_BYTES_SENT),
+      dht_puts: find(dht, DHT_PUTS),
+      dht_gets: find(dht, DHT_GETS),
+      core_connected_peers: find(core, CORE_CONNECTED_PEERS),
+    }
+  }
+}
+
+impl Statistics {
+  /// Read a snapshot of the values in `PeerMetrics` from the transport, dht and core subsystems.
+  pub fn peer_metrics(&mut self) -> Result<PeerMetrics, GetError> {
+    let transport = try!(self.get_all("transport"));
+    let dht = try!(self.get_all("dht"));
+    let core = try!(self.get_all("core"));
+    Ok(PeerMetrics::from_values(&transport[..], &dht[..], &core[..]))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_peer_metrics_from_values() {
+    let transport = vec![(TRANSPORT_BYTES_RECEIVED.to_string(), 1024, false),
+                         ("# something else".to_string(), 7, false)];
+    let core = vec![(CORE_CONNECTED_PEERS.to_string(), 3, false)];
+    let metrics = PeerMetrics::from_values(&transport[..], &[], &core[..]);
+    assert_eq!(metrics, PeerMetrics {
+      transport_bytes_received: Some(1024),
+      core_connected_peers: Some(3),
+      ..PeerMetrics::default()
+    });
+  }
+}
@@ -0,0 +1,160 @@
+//! Module for reading and publishing the values kept by the statistics service.
+//!
+//! Every GNUnet service records counters, such as the number of connected peers or bytes
+//! transmitted, with the statistics service. Each value is identified by the name of the subsystem
+//! which set it and the value's own name.
+//!
+//! # Example
+//!
+//! Print every value recorded by the core service.
+//!
+//! ```rust
+//! use gnunet::{Cfg, statistics};
+//!
+//! let config = Cfg::default().unwrap();
+//! let mut stats = statistics::Statistics::connect(&config).unwrap();
+//! for (name, value, _persistent) in stats.get_all("core").unwrap() {
+//!   println!("{}: {}", name, value);
+//! }
+//! ```
+
+use std::io::{self, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use num::ToPrimitive;
+
+use ll;
+use Cfg;
+use service::{self, ServiceReader, ServiceWriter};
+use util::{ReadCString, ReadCStringError};
+pub use self::metrics::*;
+
+mod metrics;
+
+/// Set in the uid of a value if the value is persistent, ie. kept across restarts of the peer.
+const PERSIST_BIT: u32 = 0x80000000;
+
+/// Flags sent with a SET message.
+const SETFLAG_RELATIVE: u32 = 1;
+const SETFLAG_PERSISTENT: u32 = 2;
+
+/// A handle to the statistics service.
+pub struct Statistics {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+}
+
+/// Errors returned by `Statistics::get` and `Statistics::get_all`.
+error_def! GetError {
+  NameTooLong { subsystem: String, name: String }
+    => "The subsystem and name were too long" ("\"{}\" and \"{}\" are too long to be sent to the service.", subsystem, name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the statistics service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: service::ReadMessageError }
+    => "Failed to read a message from the service" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  InvalidName { #[from] cause: ReadCStringError }
+    => "Failed to read the name of a value" ("Reason: {}", cause),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {GetError}
+
+/// Errors returned by `Statistics::set` and `Statistics::update`.
+error_def! SetError {
+  NameTooLong { subsystem: String, name: String }
+    => "The subsystem and name were too long" ("\"{}\" and \"{}\" are too long to be sent to the service.", subsystem, name),
+  Io { #[from] cause: io::Error }
+    => "An I/O error occured while communicating with the statistics service" ("Specifically: {}", cause),
+}
+
+impl Statistics {
+  /// Connect to the statistics service.
+  pub fn connect(cfg: &Cfg) -> Result<Statistics, service::ConnectError> {
+    let (service_reader, service_writer) = try!(service::connect(cfg, "statistics"));
+    Ok(Statistics {
+      service_reader: service_reader,
+      service_writer: service_writer,
+    })
+  }
+
+  /// Get the value `name` of the subsystem `subsystem`. Returns the value and whether it is
+  /// persistent, or `None` if the value has not been set.
+  pub fn get(&mut self, subsystem: &str, name: &str) -> Result<Option<(u64, bool)>, GetError> {
+    let values = try!(self.request(subsystem, name));
+    Ok(values.into_iter()
+             .find(|&(ref n, _, _)| n == name)
+             .map(|(_, value, persistent)| (value, persistent)))
+  }
+
+  /// Get every value of the subsystem `subsystem` as `(name, value, persistent)` tuples.
+  pub fn get_all(&mut self, subsystem: &str) -> Result<Vec<(String, u64, bool)>, GetError> {
+    self.request(subsystem, "")
+  }
+
+  /// Set the value `name` of the subsystem `subsystem` to `value`.
+  ///
+  /// If `persistent` is `true` the service keeps the value across restarts of the peer.
+  pub fn set(&mut self, subsystem: &str, name: &str, value: u64, persistent: bool) -> Result<(), SetError> {
+    let flags = if persistent { SETFLAG_PERSISTENT } else { 0 };
+    self.send_set(subsystem, name, flags, value)
+  }
+
+  /// Add `delta` to the value `name` of the subsystem `subsystem`. A value which has not been set
+  /// starts at zero, and the service won't let a value go below zero.
+  pub fn update(&mut self, subsystem: &str, name: &str, delta: i64, persistent: bool) -> Result<(), SetError> {
+    let mut flags = SETFLAG_RELATIVE;
+    if persistent {
+      flags |= SETFLAG_PERSISTENT;
+    }
+    self.send_set(subsystem, name, flags, delta as u64)
+  }
+
+  fn send_set(&mut self, subsystem: &str, name: &str, flags: u32, value: u64) -> Result<(), SetError> {
+    let msg_length = match (16 + subsystem.len() + 1 + name.len() + 1).to_u16() {
+      Some(l) => l,
+      None    => return Err(SetError::NameTooLong { subsystem: subsystem.to_string(), name: name.to_string() }),
+    };
+    let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_STATISTICS_SET);
+    mw.write_u32::<BigEndian>(flags).unwrap();
+    mw.write_u64::<BigEndian>(value).unwrap();
+    mw.write_all(subsystem.as_bytes()).unwrap();
+    mw.write_u8(0u8).unwrap();
+    mw.write_all(name.as_bytes()).unwrap();
+    mw.write_u8(0u8).unwrap();
+    Ok(try!(mw.send()))
+  }
+
+  /// Send a GET request and collect the values until the service sends END. An empty subsystem or
+  /// name matches everything.
+  fn request(&mut self, subsystem: &str, name: &str) -> Result<Vec<(String, u64, bool)>, GetError> {
+    let msg_length = match (4 + subsystem.len() + 1 + name.len() + 1).to_u16() {
+      Some(l) => l,
+      None    => return Err(GetError::NameTooLong { subsystem: subsystem.to_string(), name: name.to_string() }),
+    };
+    {
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_STATISTICS_GET);
+      mw.write_all(subsystem.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      mw.write_all(name.as_bytes()).unwrap();
+      mw.write_u8(0u8).unwrap();
+      try!(mw.send());
+    };
+
+    let mut ret = Vec::new();
+    loop {
+      let (tpe, mut mr) = try!(self.service_reader.read_message());
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_STATISTICS_VALUE => {
+          let uid = try!(mr.read_u32::<BigEndian>());
+          let value = try!(mr.read_u64::<BigEndian>());
+          let _subsystem = try!(mr.read_c_string());
+          let name = try!(mr.read_c_string());
+          ret.push((name, value, uid & PERSIST_BIT != 0));
+        },
+        ll::GNUNET_MESSAGE_TYPE_STATISTICS_END => return Ok(ret),
+        x => return Err(GetError::UnexpectedMessageType { ty: x }),
+      }
+    }
+  }
+}
@@ -0,0 +1,264 @@
+//! Module for controlling test peers through the testbed service.
+//!
+//! A testbed controller runs GNUnet peers for experiments and tests. Through a `Controller`, peers
+//! can be created from a config, started and stopped, and connected to each other, so networks of
+//! peers can be set up from Rust test code. Only peers on the controller's own host are supported.
+//!
+//! # Example
+//!
+//! ```rust
+//! use gnunet::{Cfg, testbed};
+//!
+//! let config = Cfg::default().unwrap();
+//! let mut controller = testbed::Controller::connect(&config).unwrap();
+//! let a = controller.create_peer(&config).unwrap();
+//! let b = controller.create_peer(&config).unwrap();
+//! controller.start_peer(a).unwrap();
+//! controller.start_peer(b).unwrap();
+//! controller.connect_peers(a, b).unwrap();
+//! ```
+
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use num::ToPrimitive;
+
+use ll;
+use Cfg;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+
+/// The id of the host the controller runs on.
+const MASTER_HOST_ID: u32 = 0;
+/// Ask for every kind of event: operation finished, peer started and stopped, peers connected
+/// and disconnected.
+const EVENT_MASK: u64 = 0x1f;
+
+const ET_PEER_START: i32 = 1;
+const ET_PEER_STOP: i32 = 2;
+const ET_CONNECT: i32 = 3;
+
+/// A peer created by a `Controller`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TestPeer {
+  /// The id the peer is known by to the controller.
+  pub id: u32,
+}
+
+/// Errors returned by `Controller::connect`.
+error_def! ConnectError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the testbed controller" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the testbed controller" ("Specifically: {}", cause),
+}
+
+/// Errors returned by the operations of a `Controller`.
+error_def! OperationError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the testbed controller" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the testbed controller" ("Reason: {}", cause),
+  ConfigTooLarge
+    => "The peer's config is too large to send to the controller",
+  Failed { message: String }
+    => "The testbed controller failed to carry out the operation" ("Reason: {}", message),
+  UnexpectedResponse { ty: u16 }
+    => "The testbed controller sent an unexpected response" ("Message type {} was not expected", ty),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {OperationError}
+
+/// A connection to a testbed controller.
+///
+/// Each operation waits for the controller to report that it has finished. Peers are not
+/// destroyed when the connection is dropped; use `destroy_peer`.
+pub struct Controller {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  next_operation: u32,
+  next_peer: u32,
+}
+
+impl Controller {
+  /// Connect to the testbed controller.
+  pub fn connect(cfg: &Cfg) -> Result<Controller, ConnectError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "testbed"));
+    {
+      let hostname = b"127.0.0.1\0";
+      let msg_length = 16 + hostname.len() as u16;
+      let mut mw = service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_TESTBED_INIT);
+      mw.write_u32::<BigEndian>(MASTER_HOST_ID).unwrap();
+      mw.write_u64::<BigEndian>(EVENT_MASK).unwrap();
+      mw.write_all(hostname).unwrap();
+      try!(mw.send());
+    }
+    Ok(Controller {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      next_operation: 0,
+      next_peer: 0,
+    })
+  }
+
+  /// Create a peer with the config `peer_cfg`. The peer is not started.
+  pub fn create_peer(&mut self, peer_cfg: &Cfg) -> Result<TestPeer, OperationError> {
+    let peer = TestPeer { id: self.next_peer };
+    let op_id = self.new_operation_id();
+    let body = try!(peer_create_body(MASTER_HOST_ID, op_id, peer.id, peer_cfg));
+    try!(self.service_writer.send_raw(ll::GNUNET_MESSAGE_TYPE_TESTBED_CREATE_PEER, &body[..]));
+    try!(self.wait_for(op_id, ll::GNUNET_MESSAGE_TYPE_TESTBED_CREATE_PEER_SUCCESS, None));
+    self.next_peer += 1;
+    Ok(peer)
+  }
+
+  /// Start the peer `peer`.
+  pub fn start_peer(&mut self, peer: TestPeer) -> Result<(), OperationError> {
+    let op_id = try!(self.send_peer_message(ll::GNUNET_MESSAGE_TYPE_TESTBED_START_PEER, peer));
+    self.wait_for(op_id, ll::GNUNET_MESSAGE_TYPE_TESTBED_PEER_EVENT, Some(ET_PEER_START))
+  }
+
+  /// Stop the peer `peer`.
+  pub fn stop_peer(&mut self, peer: TestPeer) -> Result<(), OperationError> {
+    let op_id = try!(self.send_peer_message(ll::GNUNET_MESSAGE_TYPE_TESTBED_STOP_PEER, peer));
+    self.wait_for(op_id, ll::GNUNET_MESSAGE_TYPE_TESTBED_PEER_EVENT, Some(ET_PEER_STOP))
+  }
+
+  /// Destroy the peer `peer`, which must be stopped.
+  pub fn destroy_peer(&mut self, peer: TestPeer) -> Result<(), OperationError> {
+    let op_id = try!(self.send_peer_message(ll::GNUNET_MESSAGE_TYPE_TESTBED_DESTROY_PEER, peer));
+    self.wait_for(op_id, ll::GNUNET_MESSAGE_TYPE_TESTBED_GENERIC_OPERATION_SUCCESS, None)
+  }
+
+  /// Connect the running peers `a` and `b` to each other.
+  pub fn connect_peers(&mut self, a: TestPeer, b: TestPeer) -> Result<(), OperationError> {
+    let op_id = self.new_operation_id();
+    {
+      let mut mw = self.service_writer.write_message(24, ll::GNUNET_MESSAGE_TYPE_TESTBED_OVERLAY_CONNECT);
+      mw.write_u32::<BigEndian>(a.id).unwrap();
+      mw.write_u64::<BigEndian>(op_id).unwrap();
+      mw.write_u32::<BigEndian>(b.id).unwrap();
+      mw.write_u32::<BigEndian>(MASTER_HOST_ID).unwrap();
+      try!(mw.send());
+    }
+    self.wait_for(op_id, ll::GNUNET_MESSAGE_TYPE_TESTBED_PEER_CONNECT_EVENT, Some(ET_CONNECT))
+  }
+
+  fn new_operation_id(&mut self) -> u64 {
+    let ret = operation_id(MASTER_HOST_ID, self.next_operation);
+    self.next_operation = self.next_operation.wrapping_add(1);
+    ret
+  }
+
+  fn send_peer_message(&mut self, tpe: u16, peer: TestPeer) -> Result<u64, OperationError> {
+    let op_id = self.new_operation_id();
+    let mut mw = self.service_writer.write_message(16, tpe);
+    mw.write_u32::<BigEndian>(peer.id).unwrap();
+    mw.write_u64::<BigEndian>(op_id).unwrap();
+    try!(mw.send());
+    Ok(op_id)
+  }
+
+  /// Wait for the controller to report the result of the operation `op_id`. Success is reported
+  /// by a message of type `success`, carrying the event type `event` if it is an event.
+  fn wait_for(&mut self, op_id: u64, success: u16, event: Option<i32>) -> Result<(), OperationError> {
+    loop {
+      let (tpe, mut mr) = try!(self.service_reader.read_message());
+      let (id, event_type) = match tpe {
+        ll::GNUNET_MESSAGE_TYPE_TESTBED_OPERATION_FAIL_EVENT => {
+          let _event_type = try!(mr.read_i32::<BigEndian>());
+          let id = try!(mr.read_u64::<BigEndian>());
+          if id != op_id {
+            continue;
+          }
+          let mut msg = Vec::new();
+          try!(mr.read_to_end(&mut msg));
+          while msg.last() == Some(&0) {
+            msg.pop();
+          }
+          return Err(OperationError::Failed { message: String::from_utf8_lossy(&msg[..]).into_owned() });
+        },
+        ll::GNUNET_MESSAGE_TYPE_TESTBED_CREATE_PEER_SUCCESS => {
+          let _peer_id = try!(mr.read_u32::<BigEndian>());
+          (try!(mr.read_u64::<BigEndian>()), None)
+        },
+        ll::GNUNET_MESSAGE_TYPE_TESTBED_GENERIC_OPERATION_SUCCESS => {
+          let event_type = try!(mr.read_i32::<BigEndian>());
+          (try!(mr.read_u64::<BigEndian>()), Some(event_type))
+        },
+        ll::GNUNET_MESSAGE_TYPE_TESTBED_PEER_EVENT => {
+          let event_type = try!(mr.read_i32::<BigEndian>());
+          let _host_id = try!(mr.read_u32::<BigEndian>());
+          let _peer_id = try!(mr.read_u32::<BigEndian>());
+          (try!(mr.read_u64::<BigEndian>()), Some(event_type))
+        },
+        ll::GNUNET_MESSAGE_TYPE_TESTBED_PEER_CONNECT_EVENT => {
+          let event_type = try!(mr.read_i32::<BigEndian>());
+          let _peer1 = try!(mr.read_u32::<BigEndian>());
+          let _peer2 = try!(mr.read_u32::<BigEndian>());
+          (try!(mr.read_u64::<BigEndian>()), Some(event_type))
+        },
+        x => return Err(OperationError::UnexpectedResponse { ty: x }),
+      };
+      // Events for other operations, eg. ones started by other clients, are skipped.
+      if id != op_id {
+        continue;
+      }
+      if tpe != success || (event.is_some() && event_type != event) {
+        return Err(OperationError::UnexpectedResponse { ty: tpe });
+      }
+      return Ok(());
+    }
+  }
+}
+
+/// Operation ids carry the id of the host they were started from in their upper half.
+fn operation_id(host_id: u32, counter: u32) -> u64 {
+  ((host_id as u64) << 32) | counter as u64
+}
+
+/// The body of a `TESTBED_CREATE_PEER` message. The peer's config is sent zlib-compressed,
+/// preceded by its uncompressed size.
+fn peer_create_body(host_id: u32, op_id: u64, peer_id: u32, peer_cfg: &Cfg) -> Result<Vec<u8>, OperationError> {
+  let mut serialized = Vec::new();
+  try!(peer_cfg.serialize(&mut serialized));
+  let config_size = match serialized.len().to_u16() {
+    Some(s) => s,
+    None    => return Err(OperationError::ConfigTooLarge),
+  };
+  let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Best);
+  try!(encoder.write_all(&serialized[..]));
+  let compressed = try!(encoder.finish());
+  if 4 + 18 + compressed.len() > 0xffff {
+    return Err(OperationError::ConfigTooLarge);
+  }
+  let mut body = Vec::with_capacity(18 + compressed.len());
+  body.write_u32::<BigEndian>(host_id).unwrap();
+  body.write_u64::<BigEndian>(op_id).unwrap();
+  body.write_u32::<BigEndian>(peer_id).unwrap();
+  body.write_u16::<BigEndian>(config_size).unwrap();
+  body.extend_from_slice(&compressed[..]);
+  Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Read;
+  use flate2::read::ZlibDecoder;
+  use Cfg;
+  use super::{operation_id, peer_create_body};
+
+  #[test]
+  fn test_peer_create_body() {
+    assert_eq!(operation_id(2, 7), 0x0000000200000007);
+
+    let mut cfg = Cfg::empty();
+    cfg.set_string("PATHS", "GNUNET_HOME", "/tmp/peer0".to_string());
+    let body = peer_create_body(0, 5, 3, &cfg).unwrap();
+    assert_eq!(&body[..18], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 3, 0, 34][..]);
+    let mut config = String::new();
+    ZlibDecoder::new(&body[18..]).read_to_string(&mut config).unwrap();
+    assert_eq!(config, "[PATHS]\nGNUNET_HOME = /tmp/peer0\n\n");
+  }
+}
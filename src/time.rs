@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::str::FromStr;
 use std::{u32, u64};
 use util;
@@ -27,6 +27,20 @@ static RELATIVE_UNITS: [(&'static str, u64); 17] = [
     ("a", 31536000000000 /* year */ ),
 ];
 
+impl Relative {
+    /// Create a relative time of `micros` microseconds.
+    pub fn from_micros(micros: u64) -> Relative {
+        Relative {
+            micros: micros,
+        }
+    }
+
+    /// The length of this relative time in microseconds.
+    pub fn as_micros(&self) -> u64 {
+        self.micros
+    }
+}
+
 impl FromStr for Relative {
     type Err = util::strings::ParseQuantityWithUnitsError;
     fn from_str(s: &str) -> Result<Relative, util::strings::ParseQuantityWithUnitsError> {
@@ -58,6 +72,15 @@ impl From<Relative> for Duration {
     }
 }
 
+/// The current time in microseconds since the epoch, the form GNUnet gives absolute times in.
+/// Zero if the system clock is set before the epoch.
+pub fn now_micros() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d)   => d.as_secs() * 1000000 + (d.subsec_nanos() / 1000) as u64,
+        Err(_)  => 0,
+    }
+}
+
 #[cfg(tests)]
 mod test {
     #[test]
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use PeerIdentity;
+use peerinfo::peerinfo::PeerIdentityFromStrError;
+use service::{self, CatchAll, ServiceReadLoop, ProcessMessageResult};
+use shutdown::{Component, ReadLoop};
+
+/// What is blocked for a blacklisted peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Blocked {
+  /// The peer may not connect over any transport.
+  AllTransports,
+  /// The peer may not connect over the named transport plugins, eg. `"tcp"`.
+  Transports(Vec<String>),
+}
+
+/// A set of peers which the transport service should not talk to.
+///
+/// The transport service reads its blacklist from the config section
+/// `transport-blacklist-<our peer id>`. Each key is the identity of a blocked peer and each value
+/// is a space-separated list of transport plugins, or empty to block every transport. A
+/// `Blacklist` can be read from and written to that section, and enforced on a running service
+/// with a `BlacklistClient`.
+#[derive(Clone, Debug, Default)]
+pub struct Blacklist {
+  entries: HashMap<PeerIdentity, Blocked>,
+}
+
+/// Errors returned by `Blacklist::from_cfg`.
+error_def! BlacklistFromCfgError {
+  InvalidPeer { key: String, cause: PeerIdentityFromStrError }
+    => "The blacklist contains an invalid peer identity" ("\"{}\" is not a peer identity: {}", key, cause),
+}
+
+impl Blacklist {
+  /// Create an empty blacklist.
+  pub fn new() -> Blacklist {
+    Blacklist {
+      entries: HashMap::new(),
+    }
+  }
+
+  /// The name of the config section holding the blacklist of the peer `our_id`.
+  pub fn section_name(our_id: &PeerIdentity) -> String {
+    format!("transport-blacklist-{}", our_id)
+  }
+
+  /// Read the blacklist of the peer `our_id` from a config. Returns an empty blacklist if the
+  /// config has no blacklist section.
+  pub fn from_cfg(cfg: &Cfg, our_id: &PeerIdentity) -> Result<Blacklist, BlacklistFromCfgError> {
+    let mut ret = Blacklist::new();
+    if let Some(section) = cfg.get_section(&Blacklist::section_name(our_id)[..]) {
+      for (key, value) in section.iter() {
+        let peer = match PeerIdentity::from_str(key) {
+          Ok(peer)  => peer,
+          Err(e)    => return Err(BlacklistFromCfgError::InvalidPeer { key: key.clone(), cause: e }),
+        };
+        let transports: Vec<String> = value.split_whitespace().map(|t| t.to_string()).collect();
+        let blocked = match transports.is_empty() {
+          true  => Blocked::AllTransports,
+          false => Blocked::Transports(transports),
+        };
+        ret.entries.insert(peer, blocked);
+      }
+    }
+    Ok(ret)
+  }
+
+  /// Write this blacklist to a config as the blacklist of the peer `our_id`, replacing any
+  /// blacklist already in the config. Save the config with `Cfg::save` to persist it.
+  pub fn write_to_cfg(&self, cfg: &mut Cfg, our_id: &PeerIdentity) {
+    let section = Blacklist::section_name(our_id);
+    cfg.remove_section(&section[..]);
+    for (peer, blocked) in self.entries.iter() {
+      let value = match *blocked {
+        Blocked::AllTransports              => String::new(),
+        Blocked::Transports(ref transports) => transports.join(" "),
+      };
+      cfg.set_string(&section[..], format!("{}", peer), value);
+    }
+  }
+
+  /// Block `peer` on every transport.
+  pub fn block_peer(&mut self, peer: &PeerIdentity) {
+    self.entries.insert(*peer, Blocked::AllTransports);
+  }
+
+  /// Block `peer` on the transport plugin `transport`.
+  pub fn block_transport(&mut self, peer: &PeerIdentity, transport: &str) {
+    let entry = self.entries.entry(*peer).or_insert(Blocked::Transports(Vec::new()));
+    if let Blocked::Transports(ref mut transports) = *entry {
+      if !transports.iter().any(|t| t == transport) {
+        transports.push(transport.to_string());
+      }
+    }
+  }
+
+  /// Remove `peer` from the blacklist. Returns `true` if it was blacklisted.
+  pub fn unblock(&mut self, peer: &PeerIdentity) -> bool {
+    self.entries.remove(peer).is_some()
+  }
+
+  /// What is blocked for `peer`, if anything.
+  pub fn get(&self, peer: &PeerIdentity) -> Option<&Blocked> {
+    self.entries.get(peer)
+  }
+
+  /// Returns `true` if `peer` may not connect over `transport`. If `transport` is `None`, returns
+  /// `true` only if the peer is blocked on every transport.
+  pub fn is_blocked(&self, peer: &PeerIdentity, transport: Option<&str>) -> bool {
+    match (self.entries.get(peer), transport) {
+      (Some(&Blocked::AllTransports), _)                    => true,
+      (Some(&Blocked::Transports(ref ts)), Some(transport)) => ts.iter().any(|t| t == transport),
+      _                                                     => false,
+    }
+  }
+
+  /// Iterate over the blacklisted peers.
+  pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(&'a PeerIdentity, &'a Blocked)> + 'a> {
+    Box::new(self.entries.iter())
+  }
+}
+
+/// Enforces a `Blacklist` on the running transport service.
+///
+/// While the client is alive the transport service asks it before connecting to any peer. The
+/// blacklist is shared, so changes made through the `Arc` take effect for later connections.
+/// The service can only be told to refuse a peer entirely, so only peers blocked on all
+/// transports are refused. Transport-specific entries only take effect through the config.
+pub struct BlacklistClient {
+  callback_loop: ServiceReadLoop,
+}
+
+/// Read the peer out of a `BLACKLIST_QUERY` message.
+fn read_query(reader: &mut Cursor<Vec<u8>>) -> Result<PeerIdentity, io::Error> {
+  let _is_allowed = try!(reader.read_u32::<BigEndian>());
+  let peer = try!(PeerIdentity::deserialize(reader));
+  try!(service::check_end(reader));
+  Ok(peer)
+}
+
+impl BlacklistClient {
+  /// Register as the transport service's blacklister and start answering its queries with
+  /// `blacklist`.
+  pub fn start(cfg: &Cfg, blacklist: Arc<Mutex<Blacklist>>) -> Result<BlacklistClient, service::ConnectError> {
+    let (service_reader, mut service_writer) = try!(service::connect(cfg, "transport"));
+    {
+      let mw = service_writer.write_message(4, ll::GNUNET_MESSAGE_TYPE_TRANSPORT_BLACKLIST_INIT);
+      try!(mw.send());
+    };
+    let callback_loop = try!(service_reader.spawn_callback_loop(move |tpe: u16, mut reader: Cursor<Vec<u8>>| -> ProcessMessageResult {
+      match tpe {
+        ll::GNUNET_MESSAGE_TYPE_TRANSPORT_BLACKLIST_QUERY => {
+          let peer = match read_query(&mut reader) {
+            Ok(peer)  => peer,
+            Err(_)    => return ProcessMessageResult::Reconnect,
+          };
+          let allowed = !blacklist.lock().unwrap().is_blocked(&peer, None);
+          let mut mw = service_writer.write_message(40, ll::GNUNET_MESSAGE_TYPE_TRANSPORT_BLACKLIST_REPLY);
+          mw.write_u32::<BigEndian>(allowed as u32).unwrap();
+          peer.serialize(&mut mw).unwrap();
+          if mw.send().is_err() {
+            return ProcessMessageResult::Reconnect;
+          }
+        },
+        _ => return ProcessMessageResult::Unhandled,
+      };
+      ProcessMessageResult::Continue
+    }));
+    Ok(BlacklistClient {
+      callback_loop: callback_loop,
+    })
+  }
+
+  /// The handler for messages from the transport service which this client doesn't understand.
+  /// Without a handler such messages make the client stop answering queries.
+  pub fn catch_all(&self) -> CatchAll {
+    self.callback_loop.catch_all()
+  }
+}
+
+impl Component for BlacklistClient {
+  fn read_loop(&mut self) -> Option<&mut ReadLoop> {
+    Some(&mut self.callback_loop)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+  use Cfg;
+  use PeerIdentity;
+  use super::*;
+
+  #[test]
+  fn test_blacklist_cfg_round_trip() {
+    let us = PeerIdentity::from_str("JK55QA8J1A164MB08VM209KE93M9JBB07M2VB8M3M03FKRFSV0MG").unwrap();
+    let p0 = PeerIdentity::from_str("AK55QA8J1A164MB08VM209KE93M9JBB07M2VB8M3M03FKRFSV0MG").unwrap();
+    let p1 = PeerIdentity::from_str("BK55QA8J1A164MB08VM209KE93M9JBB07M2VB8M3M03FKRFSV0MG").unwrap();
+
+    let mut blacklist = Blacklist::new();
+    blacklist.block_peer(&p0);
+    blacklist.block_transport(&p1, "tcp");
+    blacklist.block_transport(&p1, "udp");
+    assert!(blacklist.is_blocked(&p0, None));
+    assert!(blacklist.is_blocked(&p1, Some("tcp")));
+    assert!(!blacklist.is_blocked(&p1, None));
+
+    let mut cfg = Cfg::empty();
+    blacklist.write_to_cfg(&mut cfg, &us);
+    let mut buf = Vec::new();
+    cfg.serialize(&mut buf).unwrap();
+    let cfg = Cfg::deserialize(&buf[..], false).unwrap();
+    let loaded = Blacklist::from_cfg(&cfg, &us).unwrap();
+    assert_eq!(loaded.get(&p0), Some(&Blocked::AllTransports));
+    assert_eq!(loaded.get(&p1), Some(&Blocked::Transports(vec!["tcp".to_string(), "udp".to_string()])));
+  }
+}
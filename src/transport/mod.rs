@@ -0,0 +1,200 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use byteorder::{WriteBytesExt, BigEndian};
+
+use capabilities::{Capabilities, PROBE_TIMEOUT_MILLIS};
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError, Transient};
+use hello::HelloDeserializeError;
+use Hello;
+use Cfg;
+use PeerIdentity;
+use ll;
+pub use self::blacklist::*;
+pub use self::validation::*;
+
+mod blacklist;
+mod validation;
+
+pub struct TransportService {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  our_hello:      Hello,
+  hello_message:  Vec<u8>,
+  cfg:            Cfg,
+  capabilities:   Option<Capabilities>,
+}
+
+error_def! TransportServiceInitError {
+  NonHelloMessage { ty: u16 }
+    => "Expected a HELLO message from the service but received a different message type" ("Received message type {} instead.", ty),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the service" ("Error: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the service" ("Reason: {}", cause),
+  Connect { #[from] cause: service::ConnectError } 
+    => "Failed to connect to the transport service" ("Reason: {}", cause),
+  HelloDeserialize { #[from] cause: HelloDeserializeError }
+    => "Failed to serialize the hello message from the service" ("Reason {}", cause),
+  TimedOut
+    => "The service did not send our HELLO in time",
+}
+
+impl Transient for TransportServiceInitError {
+  fn is_transient(&self) -> bool {
+    match *self {
+      TransportServiceInitError::Connect { ref cause }      => cause.is_transient(),
+      TransportServiceInitError::ReadMessage { ref cause }  => cause.is_transient(),
+      TransportServiceInitError::Io { .. }                  => true,
+      TransportServiceInitError::TimedOut                   => true,
+      _                                                     => false,
+    }
+  }
+}
+
+/// Errors returned by `TransportService::wait_for_connection`.
+error_def! WaitForConnectionError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the service" ("Error: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive a message from the service" ("Reason: {}", cause),
+}
+
+impl TransportService {
+  pub fn init(cfg: &Cfg) -> Result<TransportService, TransportServiceInitError> {
+    TransportService::init_inner(cfg, None)
+  }
+
+  /// Like `init` but gives up if the service hasn't sent our HELLO within `timeout`.
+  pub fn init_timeout(cfg: &Cfg, timeout: Duration) -> Result<TransportService, TransportServiceInitError> {
+    TransportService::init_inner(cfg, Some(timeout))
+  }
+
+  fn init_inner(cfg: &Cfg, timeout: Option<Duration>) -> Result<TransportService, TransportServiceInitError> {
+    let (mut sr, mut sw) = try!(service::connect(cfg, "transport"));
+    let msg_length = 4 + 4 + 32;
+    {
+      let mut mw = sw.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_TRANSPORT_START);
+      mw.write_u32::<BigEndian>(0).unwrap();
+      let null_peer_id = [0; 32];
+      mw.write(&null_peer_id[..]).unwrap();
+      try!(mw.send());
+    };
+    let (ty, mut mr) = match timeout {
+      Some(timeout) => match try!(sr.read_message_timeout(timeout)) {
+        Some(msg) => msg,
+        None      => return Err(TransportServiceInitError::TimedOut),
+      },
+      None => try!(sr.read_message()),
+    };
+    if ty != ll::GNUNET_MESSAGE_TYPE_HELLO {
+      return Err(TransportServiceInitError::NonHelloMessage { ty: ty });
+    };
+    let mut hello_message = Vec::with_capacity(4 + mr.get_ref().len());
+    hello_message.write_u16::<BigEndian>((4 + mr.get_ref().len()) as u16).unwrap();
+    hello_message.write_u16::<BigEndian>(ty).unwrap();
+    hello_message.extend_from_slice(&mr.get_ref()[..]);
+    let hello = try!(Hello::deserialize(&mut mr));
+    Ok(TransportService {
+      service_reader: sr,
+      service_writer: sw,
+      our_hello:      hello,
+      hello_message:  hello_message,
+      cfg:            cfg.clone(),
+      capabilities:   None,
+    })
+  }
+
+  /// Our own HELLO, as sent by the service when we connected.
+  pub fn our_hello(&self) -> &Hello {
+    &self.our_hello
+  }
+
+  /// Our own HELLO as the complete message sent by the service, header and addresses included.
+  /// This is the form taken by `peerinfo::import_unsigned_hellos`.
+  pub fn our_hello_message(&self) -> &[u8] {
+    &self.hello_message[..]
+  }
+
+  /// Send requests the way a daemon with `capabilities` expects.
+  pub fn set_capabilities(&mut self, capabilities: &Capabilities) {
+    self.capabilities = Some(*capabilities);
+  }
+
+  /// Ask the transport service to try to establish a connection to `peer`.
+  ///
+  /// This only sends the request. Use `wait_for_connection` to find out whether it succeeded.
+  /// Fails without sending anything if the service doesn't take connection requests, since it
+  /// would drop the connection. Unless `set_capabilities` was called, the first call probes the
+  /// service to find out, which can take up to `capabilities::PROBE_TIMEOUT_MILLIS`.
+  pub fn try_connect(&mut self, peer: &PeerIdentity) -> Result<(), io::Error> {
+    let capabilities = match self.capabilities {
+      Some(c) => c,
+      None    => {
+        let mut c = Capabilities::default();
+        let timeout = Duration::from_millis(PROBE_TIMEOUT_MILLIS);
+        if let Ok(supported) = probe_request_connect(&self.cfg, timeout) {
+          c.transport_request_connect = supported;
+        }
+        self.capabilities = Some(c);
+        c
+      },
+    };
+    if !capabilities.transport_request_connect {
+      return Err(io::Error::new(io::ErrorKind::Other, "the transport service does not accept connection requests"));
+    }
+    let mut mw = self.service_writer.write_message(40, ll::GNUNET_MESSAGE_TYPE_TRANSPORT_REQUEST_CONNECT);
+    mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+    peer.serialize(&mut mw).unwrap();
+    mw.send()
+  }
+
+  /// Wait until the service reports that we are connected to `peer`.
+  ///
+  /// Returns `Ok(true)` if the connection was reported and `Ok(false)` if `timeout` expired first.
+  /// The service reports every peer we are already connected to when we first connect to it, so
+  /// this also succeeds if the connection existed beforehand.
+  pub fn wait_for_connection(&mut self, peer: &PeerIdentity, timeout: Duration) -> Result<bool, WaitForConnectionError> {
+    let res = self.read_connections_until(peer, timeout);
+    try!(self.service_reader.connection.set_read_timeout(None));
+    res
+  }
+
+  fn read_connections_until(&mut self, peer: &PeerIdentity, timeout: Duration) -> Result<bool, WaitForConnectionError> {
+    let start = Instant::now();
+    loop {
+      let elapsed = start.elapsed();
+      if elapsed >= timeout {
+        return Ok(false);
+      }
+      try!(self.service_reader.connection.set_read_timeout(Some(timeout - elapsed)));
+      let (ty, mut mr) = match self.service_reader.read_message() {
+        Ok(x)   => x,
+        Err(ReadMessageError::Io { ref cause })
+          if cause.kind() == io::ErrorKind::WouldBlock || cause.kind() == io::ErrorKind::TimedOut
+                => return Ok(false),
+        Err(e)  => return Err(WaitForConnectionError::ReadMessage { cause: e }),
+      };
+      if ty == ll::GNUNET_MESSAGE_TYPE_TRANSPORT_CONNECT {
+        // the peer identity follows the outbound quota
+        let mut quota = [0u8; 4];
+        try!(io::Read::read_exact(&mut mr, &mut quota[..]));
+        if try!(PeerIdentity::deserialize(&mut mr)) == *peer {
+          return Ok(true);
+        }
+      }
+    }
+  }
+}
+
+/// Find out whether the transport daemon accepts connection requests, waiting up to `timeout` for
+/// it to react to one. The request is for the all-zero peer identity, which no peer has.
+pub fn probe_request_connect(cfg: &Cfg, timeout: Duration) -> Result<bool, service::ConnectError> {
+  let body = [0u8; 4 + 32];
+  let outcome = try!(service::probe(cfg, "transport", ll::GNUNET_MESSAGE_TYPE_TRANSPORT_REQUEST_CONNECT, &body[..], timeout));
+  Ok(outcome.understood())
+}
+
+pub fn self_hello(cfg: &Cfg) -> Result<Hello, TransportServiceInitError> {
+  let ts = try!(TransportService::init(cfg));
+  Ok(ts.our_hello)
+}
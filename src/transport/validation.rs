@@ -0,0 +1,195 @@
+use std::io::{self, Read};
+use std::str::from_utf8;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use ll;
+use Cfg;
+use PeerIdentity;
+use service::{self, ReadMessageError};
+
+/// The stage the transport service's validation of an address has reached.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValidationState {
+  /// No validation has been started.
+  None,
+  /// The address has just been learned of and will be validated.
+  New,
+  /// The address was validated again, or its validation was scheduled.
+  Update,
+  /// The address could not be validated in time.
+  Timeout,
+  /// The address has been dropped.
+  Remove,
+}
+
+impl ValidationState {
+  /// Create a `ValidationState` from the code sent by the service.
+  pub fn from_u32(x: u32) -> Option<ValidationState> {
+    Some(match x {
+      0 => ValidationState::None,
+      1 => ValidationState::New,
+      2 => ValidationState::Update,
+      3 => ValidationState::Timeout,
+      4 => ValidationState::Remove,
+      _ => return None,
+    })
+  }
+}
+
+/// What the transport service knows about the validation of one address of a peer.
+#[derive(Clone, Debug)]
+pub struct ValidationInfo {
+  /// The peer the address belongs to.
+  pub peer: PeerIdentity,
+  /// The name of the transport plugin the address belongs to, eg. `"tcp"`.
+  pub plugin: String,
+  /// The address, in the plugin's own format.
+  pub address: Vec<u8>,
+  /// When the address was last validated, in microseconds since the epoch. Zero if never.
+  pub last_validation: u64,
+  /// Until when the address counts as valid, in microseconds since the epoch.
+  pub valid_until: u64,
+  /// When the address will next be validated, in microseconds since the epoch.
+  pub next_validation: u64,
+  /// The state of the validation.
+  pub state: ValidationState,
+}
+
+impl ValidationInfo {
+  /// Whether the address is valid at `now`, in microseconds since the epoch.
+  pub fn is_valid_at(&self, now: u64) -> bool {
+    self.valid_until > now
+  }
+}
+
+/// Errors returned by `validations`.
+error_def! ValidationsError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the transport service" ("Reason: {}", cause),
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the transport service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to read a message from the service" ("Specifically: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "Received an unexpected message from the service" ("Message type {} was not expected.", ty),
+  InvalidPluginName
+    => "The service sent a plugin name which is not valid utf-8",
+  Malformed
+    => "The service sent a message whose lengths don't match its size",
+  InvalidState { state: u32 }
+    => "The service sent an unknown validation state" ("State: {}", state),
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {ValidationsError}
+
+/// Read the body of a `TRANSPORT_MONITOR_VALIDATION_RESPONSE` message. Returns `None` for the
+/// empty response which ends the list.
+fn read_validation_info<R: Read>(mr: &mut R, len: usize) -> Result<Option<ValidationInfo>, ValidationsError> {
+  if len == 0 {
+    return Ok(None);
+  }
+  let _reserved = try!(mr.read_u32::<BigEndian>());
+  let peer = try!(PeerIdentity::deserialize(mr));
+  let last_validation = try!(mr.read_u64::<BigEndian>());
+  let valid_until = try!(mr.read_u64::<BigEndian>());
+  let next_validation = try!(mr.read_u64::<BigEndian>());
+  let _local_address_info = try!(mr.read_u32::<BigEndian>());
+  let address_length = try!(mr.read_u32::<BigEndian>()) as usize;
+  let plugin_length = try!(mr.read_u32::<BigEndian>()) as usize;
+  let state = try!(mr.read_u32::<BigEndian>());
+  // The fixed part is 4 + 32 + 3 * 8 + 4 * 4 bytes, the rest holds the address and plugin name.
+  if address_length + plugin_length > len.saturating_sub(76) {
+    return Err(ValidationsError::Malformed);
+  }
+  let mut address = vec![0u8; address_length];
+  try!(mr.read_exact(&mut address[..]));
+  let mut plugin = vec![0u8; plugin_length];
+  try!(mr.read_exact(&mut plugin[..]));
+  if plugin.last() == Some(&0) {
+    plugin.pop();
+  }
+  let plugin = match from_utf8(&plugin[..]) {
+    Ok(s)   => s.to_string(),
+    Err(_)  => return Err(ValidationsError::InvalidPluginName),
+  };
+  let state = match ValidationState::from_u32(state) {
+    Some(s) => s,
+    None    => return Err(ValidationsError::InvalidState { state: state }),
+  };
+  Ok(Some(ValidationInfo {
+    peer: peer,
+    plugin: plugin,
+    address: address,
+    last_validation: last_validation,
+    valid_until: valid_until,
+    next_validation: next_validation,
+    state: state,
+  }))
+}
+
+/// Get what the transport service knows about the validation of the addresses of `peer`, or of
+/// every peer if `peer` is `None`.
+pub fn validations(cfg: &Cfg, peer: Option<&PeerIdentity>) -> Result<Vec<ValidationInfo>, ValidationsError> {
+  let (mut service_reader, mut service_writer) = try!(service::connect(cfg, "transport"));
+  {
+    let mut mw = service_writer.write_message(4 + 4 + 32, ll::GNUNET_MESSAGE_TYPE_TRANSPORT_MONITOR_VALIDATION_REQUEST);
+    mw.write_u32::<BigEndian>(1).unwrap(); // one shot
+    match peer {
+      Some(peer)  => peer.serialize(&mut mw).unwrap(),
+      None        => io::Write::write_all(&mut mw, &[0u8; 32][..]).unwrap(),
+    };
+    try!(mw.send());
+  };
+  let mut ret = Vec::new();
+  loop {
+    let (tpe, mut mr) = try!(service_reader.read_message());
+    if tpe != ll::GNUNET_MESSAGE_TYPE_TRANSPORT_MONITOR_VALIDATION_RESPONSE {
+      return Err(ValidationsError::UnexpectedMessageType { ty: tpe });
+    }
+    let len = mr.get_ref().len();
+    match try!(read_validation_info(&mut mr, len)) {
+      Some(info)  => ret.push(info),
+      None        => return Ok(ret),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use byteorder::{BigEndian, WriteBytesExt};
+  use super::*;
+  use super::read_validation_info;
+
+  #[test]
+  fn test_read_validation_info() {
+    let mut buf = Vec::new();
+    buf.write_u32::<BigEndian>(0).unwrap();
+    buf.extend_from_slice(&[7u8; 32][..]);
+    buf.write_u64::<BigEndian>(100).unwrap();
+    buf.write_u64::<BigEndian>(200).unwrap();
+    buf.write_u64::<BigEndian>(150).unwrap();
+    buf.write_u32::<BigEndian>(0).unwrap();
+    buf.write_u32::<BigEndian>(3).unwrap();
+    buf.write_u32::<BigEndian>(4).unwrap();
+    buf.write_u32::<BigEndian>(2).unwrap();
+    buf.extend_from_slice(b"abctcp\0");
+    let len = buf.len();
+
+    let info = read_validation_info(&mut Cursor::new(buf.clone()), len).unwrap().unwrap();
+    assert_eq!(info.plugin, "tcp");
+    assert_eq!(info.address, b"abc".to_vec());
+    assert_eq!(info.state, ValidationState::Update);
+    assert!(info.is_valid_at(199));
+    assert!(!info.is_valid_at(200));
+    assert!(read_validation_info(&mut Cursor::new(Vec::new()), 0).unwrap().is_none());
+
+    // Lengths claiming more than the message holds.
+    buf[64] = 0xff;
+    match read_validation_info(&mut Cursor::new(buf), len) {
+      Err(ValidationsError::Malformed) => (),
+      _ => panic!("a too long address was accepted"),
+    }
+  }
+}
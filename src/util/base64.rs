@@ -0,0 +1,62 @@
+const ALPHABET: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encode `data` as unpadded base64url, as used in JSON web tokens.
+pub fn encode_url(data: &[u8]) -> String {
+  let mut ret = String::with_capacity((data.len() * 4 + 2) / 3);
+  for chunk in data.chunks(3) {
+    let b0 = chunk[0] as u32;
+    let b1 = if chunk.len() > 1 { chunk[1] as u32 } else { 0 };
+    let b2 = if chunk.len() > 2 { chunk[2] as u32 } else { 0 };
+    let n = (b0 << 16) | (b1 << 8) | b2;
+    for i in 0..(chunk.len() + 1) {
+      ret.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+    }
+  }
+  ret
+}
+
+/// Decode unpadded base64url. Returns `None` if `s` is not valid base64url.
+pub fn decode_url(s: &str) -> Option<Vec<u8>> {
+  let s = s.trim_right_matches('=');
+  if s.len() % 4 == 1 {
+    return None;
+  }
+  let mut ret = Vec::with_capacity(s.len() * 3 / 4);
+  for chunk in s.as_bytes().chunks(4) {
+    let mut n = 0u32;
+    for (i, &c) in chunk.iter().enumerate() {
+      let v = match c {
+        b'A'...b'Z' => c - b'A',
+        b'a'...b'z' => c - b'a' + 26,
+        b'0'...b'9' => c - b'0' + 52,
+        b'-'        => 62,
+        b'_'        => 63,
+        _           => return None,
+      };
+      n |= (v as u32) << (18 - 6 * i);
+    }
+    for i in 0..(chunk.len() - 1) {
+      ret.push((n >> (16 - 8 * i)) as u8);
+    }
+  }
+  Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_base64_url() {
+    assert_eq!(encode_url(b""), "");
+    assert_eq!(encode_url(b"f"), "Zg");
+    assert_eq!(encode_url(b"foob"), "Zm9vYg");
+    assert_eq!(encode_url(&[0xfb, 0xff]), "-_8");
+    for len in 0..10 {
+      let data: Vec<u8> = (0..len).map(|i| (i * 37) as u8).collect();
+      assert_eq!(decode_url(&encode_url(&data[..])).unwrap(), data);
+    }
+    assert!(decode_url("a").is_none());
+    assert!(decode_url("ab+c").is_none());
+  }
+}
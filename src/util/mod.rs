@@ -1,5 +1,6 @@
 pub use self::c_strings::*;
 
+pub mod base64;
 pub mod c_strings;
 pub mod io;
 pub mod strings;
@@ -1,5 +1,6 @@
 use std::num::ParseIntError;
 use std::str::FromStr;
+use num::ToPrimitive;
 
 error_def! ParseQuantityWithUnitsError {
     ParseInt { #[from] cause: ParseIntError }
@@ -62,3 +63,33 @@ pub fn parse_quantity_with_units<'a>(s: &'a str, units: &[(&str, u64)]) -> Resul
     }
 }
 
+/// The length of a message made up of `fixed` bytes followed by `s` and a terminating NUL. Returns
+/// `None` if the message would be too long for the 16-bit length field of its header.
+pub fn c_string_message_len(fixed: usize, s: &str) -> Option<u16> {
+    fixed.checked_add(s.len())
+         .and_then(|len| len.checked_add(1))
+         .and_then(|len| len.to_u16())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_c_string_message_len_boundaries() {
+        for &fixed in [4usize, 8, 40, 0xfff0].iter() {
+            let longest = 0xffff - fixed - 1;
+            for len in (longest - 3)..(longest + 4) {
+                let s: String = ::std::iter::repeat('x').take(len).collect();
+                match c_string_message_len(fixed, &s) {
+                    Some(l) => {
+                        assert!(len <= longest);
+                        assert_eq!(l as usize, fixed + len + 1);
+                    },
+                    None => assert!(len > longest),
+                }
+            }
+        }
+        assert_eq!(c_string_message_len(::std::usize::MAX, "x"), None);
+    }
+}
@@ -0,0 +1,229 @@
+//! Module for setting up tunnels through the VPN service.
+//!
+//! The VPN service gives programs on this machine a local IP address which stands for a remote
+//! destination. Traffic sent to the address is carried over GNUnet, either to an exit node which
+//! forwards it to an IP address on the internet or to a service offered by another peer. Once a
+//! redirection has been set up, ordinary TCP or UDP sockets can be used to talk through it.
+//!
+//! # Example
+//!
+//! ```rust
+//! use gnunet::{Cfg, vpn};
+//!
+//! let config = Cfg::default().unwrap();
+//! let mut vpn = vpn::Vpn::connect(&config).unwrap();
+//! let peer = "JK55QA8J1A164MB08VM209KE93M9JBB07M2VB8M3M03FKRFSV0MG".parse().unwrap();
+//! let addr = vpn.redirect_to_service(vpn::AddressFamily::Ipv4, vpn::Protocol::Tcp, &peer,
+//!                                    "www", 0).unwrap();
+//! println!("Connect to {} to reach the service", addr);
+//! ```
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libc;
+
+use ll;
+use Cfg;
+use HashCode;
+use PeerIdentity;
+use service::{self, ServiceReader, ServiceWriter, ReadMessageError};
+
+/// The kind of local address wanted for a redirection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressFamily {
+  /// Let the service choose.
+  Any,
+  /// An IPv4 address.
+  Ipv4,
+  /// An IPv6 address.
+  Ipv6,
+}
+
+impl AddressFamily {
+  fn to_af(&self) -> i32 {
+    match *self {
+      AddressFamily::Any  => libc::AF_UNSPEC as i32,
+      AddressFamily::Ipv4 => libc::AF_INET as i32,
+      AddressFamily::Ipv6 => libc::AF_INET6 as i32,
+    }
+  }
+}
+
+/// The transport protocol of a service reached through the VPN.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+  /// TCP.
+  Tcp,
+  /// UDP.
+  Udp,
+}
+
+impl Protocol {
+  fn to_ipproto(&self) -> i32 {
+    match *self {
+      Protocol::Tcp => ll::IPPROTO_TCP as i32,
+      Protocol::Udp => ll::IPPROTO_UDP as i32,
+    }
+  }
+}
+
+/// Errors returned by `Vpn::connect`.
+error_def! ConnectError {
+  Connect { #[from] cause: service::ConnectError }
+    => "Failed to connect to the VPN service" ("Reason: {}", cause),
+}
+
+/// Errors returned by `Vpn::redirect_to_ip` and `Vpn::redirect_to_service`.
+error_def! RedirectError {
+  Io { #[from] cause: io::Error }
+    => "There was an I/O error communicating with the VPN service" ("Specifically: {}", cause),
+  ReadMessage { #[from] cause: ReadMessageError }
+    => "Failed to receive the response from the VPN service" ("Reason: {}", cause),
+  UnexpectedMessageType { ty: u16 }
+    => "The VPN service sent an unexpected response message type" ("Message type {} was not expected", ty),
+  InvalidAddressFamily { af: i32 }
+    => "The VPN service allocated an address of an unknown family" ("Address family: {}", af),
+  NoAddress
+    => "The VPN service could not allocate an address for the redirection",
+  Disconnected
+    => "The service disconnected unexpectedly",
+}
+byteorder_error_chain! {RedirectError}
+
+/// A handle to the VPN service.
+///
+/// Redirections last until their expiration time or until the handle is dropped, whichever comes
+/// first.
+pub struct Vpn {
+  service_reader: ServiceReader,
+  service_writer: ServiceWriter,
+  next_request_id: u64,
+}
+
+impl Vpn {
+  /// Connect to the VPN service.
+  pub fn connect(cfg: &Cfg) -> Result<Vpn, ConnectError> {
+    let (service_reader, service_writer) = try!(service::connect(cfg, "vpn"));
+    Ok(Vpn {
+      service_reader: service_reader,
+      service_writer: service_writer,
+      next_request_id: 1,
+    })
+  }
+
+  /// Redirect traffic for `target` through an exit node, returning the local address which stands
+  /// for it. `expiration` is when the redirection may be dropped, in microseconds since the
+  /// epoch.
+  pub fn redirect_to_ip(&mut self, result_af: AddressFamily, target: IpAddr, expiration: u64)
+      -> Result<IpAddr, RedirectError>
+  {
+    let request_id = self.new_request_id();
+    let (addr_af, addr_len) = match target {
+      IpAddr::V4(_) => (libc::AF_INET as i32, 4),
+      IpAddr::V6(_) => (libc::AF_INET6 as i32, 16),
+    };
+    {
+      let msg_length = 32 + addr_len;
+      let mut mw = self.service_writer.write_message(msg_length, ll::GNUNET_MESSAGE_TYPE_VPN_CLIENT_REDIRECT_TO_IP);
+      mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+      mw.write_u64::<BigEndian>(expiration).unwrap();
+      mw.write_i32::<BigEndian>(result_af.to_af()).unwrap();
+      mw.write_i32::<BigEndian>(addr_af).unwrap();
+      mw.write_u64::<BigEndian>(request_id).unwrap();
+      match target {
+        IpAddr::V4(a) => mw.write_all(&a.octets()[..]).unwrap(),
+        IpAddr::V6(a) => mw.write_all(&a.octets()[..]).unwrap(),
+      };
+      try!(mw.send());
+    }
+    self.read_response(request_id)
+  }
+
+  /// Redirect traffic to the service `service` offered by `peer`, returning the local address
+  /// which stands for it. `expiration` is when the redirection may be dropped, in microseconds
+  /// since the epoch.
+  pub fn redirect_to_service(&mut self,
+                             result_af: AddressFamily,
+                             protocol: Protocol,
+                             peer: &PeerIdentity,
+                             service: &str,
+                             expiration: u64) -> Result<IpAddr, RedirectError>
+  {
+    let request_id = self.new_request_id();
+    {
+      let mut mw = self.service_writer.write_message(128, ll::GNUNET_MESSAGE_TYPE_VPN_CLIENT_REDIRECT_TO_SERVICE);
+      mw.write_u32::<BigEndian>(0).unwrap(); // reserved
+      mw.write_u64::<BigEndian>(expiration).unwrap();
+      mw.write_i32::<BigEndian>(protocol.to_ipproto()).unwrap();
+      mw.write_i32::<BigEndian>(result_af.to_af()).unwrap();
+      peer.serialize(&mut mw).unwrap();
+      service_descriptor(service).serialize(&mut mw).unwrap();
+      mw.write_u64::<BigEndian>(request_id).unwrap();
+      try!(mw.send());
+    }
+    self.read_response(request_id)
+  }
+
+  fn new_request_id(&mut self) -> u64 {
+    let ret = self.next_request_id;
+    self.next_request_id += 1;
+    ret
+  }
+
+  fn read_response(&mut self, request_id: u64) -> Result<IpAddr, RedirectError> {
+    loop {
+      let (tpe, mut mr) = try!(self.service_reader.read_message());
+      if tpe != ll::GNUNET_MESSAGE_TYPE_VPN_CLIENT_USE_IP {
+        return Err(RedirectError::UnexpectedMessageType { ty: tpe });
+      }
+      let result_af = try!(mr.read_i32::<BigEndian>());
+      let id = try!(mr.read_u64::<BigEndian>());
+      // Responses to requests we gave up on are skipped.
+      if id != request_id {
+        continue;
+      }
+      return read_address(result_af, &mut mr);
+    }
+  }
+}
+
+/// The hash the VPN and exit services identify a service by.
+pub fn service_descriptor(service: &str) -> HashCode {
+  HashCode::from_buffer(service.as_bytes())
+}
+
+fn read_address<R: Read>(af: i32, r: &mut R) -> Result<IpAddr, RedirectError> {
+  if af == libc::AF_UNSPEC as i32 {
+    return Err(RedirectError::NoAddress);
+  }
+  if af == libc::AF_INET as i32 {
+    let mut octets = [0u8; 4];
+    try!(r.read_exact(&mut octets[..]));
+    return Ok(IpAddr::V4(Ipv4Addr::from(octets)));
+  }
+  if af == libc::AF_INET6 as i32 {
+    let mut octets = [0u8; 16];
+    try!(r.read_exact(&mut octets[..]));
+    return Ok(IpAddr::V6(Ipv6Addr::from(octets)));
+  }
+  Err(RedirectError::InvalidAddressFamily { af: af })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use std::net::{IpAddr, Ipv4Addr};
+  use libc;
+  use super::read_address;
+
+  #[test]
+  fn test_read_address() {
+    let mut mr = Cursor::new(vec![10, 0, 0, 7]);
+    let addr = read_address(libc::AF_INET as i32, &mut mr).unwrap();
+    assert_eq!(addr, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)));
+    assert!(read_address(libc::AF_UNSPEC as i32, &mut Cursor::new(vec![])).is_err());
+    assert!(read_address(libc::AF_INET6 as i32, &mut Cursor::new(vec![1, 2])).is_err());
+    assert!(read_address(99, &mut Cursor::new(vec![])).is_err());
+  }
+}